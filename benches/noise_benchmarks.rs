@@ -2,11 +2,11 @@ use criterion::{black_box, criterion_group, criterion_main, Criterion, Benchmark
 use noise_mobile::core::{
     session::NoiseSession,
     error::NoiseError,
+    keys::SecretKey,
 };
-use noise_mobile::mobile::{
-    battery::BatchedCrypto,
-    network::ResilientSession,
-};
+#[cfg(feature = "batching")]
+use noise_mobile::mobile::battery::BatchedCrypto;
+use noise_mobile::mobile::network::ResilientSession;
 use noise_mobile::ffi::c_api;
 use std::time::Duration;
 
@@ -151,7 +151,12 @@ fn benchmark_decryption_throughput(c: &mut Criterion) {
     group.finish();
 }
 
+/// Batching is compiled out without the `batching` feature
+#[cfg(not(feature = "batching"))]
+fn benchmark_batch_vs_individual(_c: &mut Criterion) {}
+
 /// Benchmark batch vs individual crypto operations
+#[cfg(feature = "batching")]
 fn benchmark_batch_vs_individual(c: &mut Criterion) {
     let mut group = c.benchmark_group("batch_vs_individual");
     
@@ -193,7 +198,7 @@ fn benchmark_batch_vs_individual(c: &mut Criterion) {
                         batched.queue_encrypt(msg.clone());
                     }
                     
-                    let results = batched.flush_encrypts().unwrap();
+                    let results = batched.flush_encrypts();
                     black_box(results)
                 })
             }
@@ -256,7 +261,7 @@ fn benchmark_session_creation(c: &mut Criterion) {
     });
     
     // Benchmark session creation with custom key
-    let private_key = [0x42u8; 32]; // Dummy key for benchmarking
+    let private_key = SecretKey::from_bytes(&[0x42u8; 32]).unwrap(); // Dummy key for benchmarking
     
     group.bench_function("with_private_key_initiator", |b| {
         b.iter(|| {
@@ -78,7 +78,7 @@ cp target/android-libs/x86_64/libnoise_mobile.so android/jniLibs/x86_64/
 # Generate C header
 echo "Generating C header..."
 mkdir -p include
-cbindgen --config cbindgen.toml --crate noise-mobile-rust --output include/noise_mobile.h
+cargo check --features generate-header
 
 # Create a sample JNI wrapper header
 echo "Creating JNI wrapper template..."
@@ -56,7 +56,7 @@ lipo -create \
 # Generate C header
 echo "Generating C header..."
 mkdir -p include
-cbindgen --config cbindgen.toml --crate noise-mobile-rust --output include/noise_mobile.h
+cargo check --features generate-header
 
 # Create module map for Swift
 echo "Creating module map..."
@@ -0,0 +1,27 @@
+//! Build script for noise-mobile-rust
+//!
+//! With the `generate-header` feature, regenerates `include/noise_mobile.h`
+//! with cbindgen so the header shipped to iOS and Android builds always
+//! matches the exported functions. Without it the build does nothing.
+
+fn main() {
+    #[cfg(feature = "generate-header")]
+    generate_header();
+}
+
+#[cfg(feature = "generate-header")]
+fn generate_header() {
+    use std::path::PathBuf;
+    
+    let crate_dir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
+    println!("cargo:rerun-if-changed=src/ffi");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml")).expect("invalid cbindgen.toml");
+    cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(config)
+        .generate()
+        .expect("failed to generate C header")
+        .write_to_file(crate_dir.join("include/noise_mobile.h"));
+}
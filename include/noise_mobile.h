@@ -15,24 +15,371 @@
 
 #pragma once
 
-/* Generated with cbindgen:0.29.0 */
+/* Generated with cbindgen:0.29.4 */
 
 #include <stdarg.h>
 #include <stdbool.h>
 #include <stdint.h>
 #include <stdlib.h>
 
+/**
+ * Length of a [`SessionId`]; short enough that log scrubbing keeps its hex
+ */
+#define SESSION_ID_LEN 8
+
 /**
  * Maximum message length supported by Noise
  */
 #define NoiseSession_MAX_MESSAGE_LEN 65535
 
+/**
+ * Length of the `generation || nonce` header on explicit-nonce frames
+ */
+#define NoiseSession_EXPLICIT_HEADER_LEN 12
+
+/**
+ * Length of the `count || index` header on each message of [`NoiseSession::encrypt_large`]
+ */
+#define NoiseSession_LARGE_HEADER_LEN 8
+
+
+
+/**
+ * Most rekeys a receiver will catch up on from a single explicit-nonce frame
+ */
+#define NoiseSession_MAX_GENERATION_SKIP 16
+
+/**
+ * Default number of buffers a pool keeps
+ */
+#define DEFAULT_POOL_BUFFERS 8
+
 #define NOISE_MAX_MESSAGE_LEN 65535
 
 #define NOISE_MAX_PAYLOAD_LEN (65535 - 16)
 
 #define NOISE_TAG_LEN 16
 
+/**
+ * Transport nonce count at which a session refuses to send or receive more
+ * messages. Far below the 2^64 hard limit so apps have ample warning to
+ * schedule a fresh handshake.
+ */
+#define NOISE_NONCE_LIMIT (1 << 60)
+
+/**
+ * Length of Curve25519 private and public keys
+ */
+#define KEY_LEN 32
+
+/**
+ * Length of an XEdDSA signature
+ */
+#define SIGNATURE_LEN 64
+
+/**
+ * Handshake payload format version
+ */
+#define PAYLOAD_VERSION 1
+
+/**
+ * Longest value a single field can hold
+ */
+#define MAX_FIELD_LEN (uintptr_t)UINT16_MAX
+
+/**
+ * Default smallest payload worth compressing
+ */
+#define DEFAULT_MIN_COMPRESS_LEN 128
+
+/**
+ * Default cap on a decompressed payload
+ */
+#define DEFAULT_MAX_DECOMPRESSED_LEN (1 << 20)
+
+/**
+ * Default plaintext bytes per chunk
+ */
+#define DEFAULT_FILE_CHUNK_LEN (64 * 1024)
+
+/**
+ * Largest plaintext chunk accepted when decrypting
+ */
+#define MAX_FILE_CHUNK_LEN (1024 * 1024)
+
+/**
+ * Length of Ed25519 public and secret keys
+ */
+#define IDENTITY_KEY_LEN 32
+
+/**
+ * Length of an Ed25519 signature
+ */
+#define IDENTITY_SIGNATURE_LEN 64
+
+/**
+ * Encoded length of a [`StaticKeyCertificate`]
+ */
+#define CERTIFICATE_LEN (((1 + IDENTITY_KEY_LEN) + KEY_LEN) + IDENTITY_SIGNATURE_LEN)
+
+/**
+ * Number of emoji in an [`EmojiSas`]
+ */
+#define SAS_EMOJI_COUNT 7
+
+/**
+ * Most names an offer can carry
+ */
+#define MAX_OFFERED (uintptr_t)UINT8_MAX
+
+/**
+ * Longest name in an offer, in bytes
+ */
+#define MAX_NAME_LEN (uintptr_t)UINT8_MAX
+
+/**
+ * Length of the timestamp that precedes early data
+ */
+#define EARLY_DATA_HEADER_LEN 8
+
+/**
+ * Default number of ephemeral keys a [`ReplayCache`] remembers
+ */
+#define DEFAULT_REPLAY_CACHE_CAPACITY 10000
+
+/**
+ * Default number of resumptions a [`TicketIssuer`] remembers
+ */
+#define DEFAULT_TICKET_REPLAY_CAPACITY 10000
+
+/**
+ * Largest plaintext an anonymous sealed message can carry
+ */
+#define MAX_SEALED_PLAINTEXT_LEN ((NOISE_MAX_MESSAGE_LEN - KEY_LEN) - NOISE_TAG_LEN)
+
+/**
+ * Largest plaintext a sender-authenticated sealed message can carry
+ */
+#define MAX_SIGNED_SEALED_PLAINTEXT_LEN ((MAX_SEALED_PLAINTEXT_LEN - KEY_LEN) - NOISE_TAG_LEN)
+
+/**
+ * Default most messages a [`SealedReplayCache`] remembers
+ */
+#define DEFAULT_SEALED_REPLAY_CAPACITY 4096
+
+/**
+ * Most recipients an envelope can address
+ */
+#define MAX_RECIPIENTS (uintptr_t)UINT16_MAX
+
+#define NOISE_MODE_INITIATOR 0
+
+#define NOISE_MODE_RESPONDER 1
+
+#define NOISE_ERROR_SUCCESS 0
+
+#define NOISE_ERROR_INVALID_PARAMETER 1
+
+#define NOISE_ERROR_OUT_OF_MEMORY 2
+
+#define NOISE_ERROR_HANDSHAKE_FAILED 3
+
+#define NOISE_ERROR_ENCRYPTION_FAILED 4
+
+#define NOISE_ERROR_DECRYPTION_FAILED 5
+
+#define NOISE_ERROR_BUFFER_TOO_SMALL 6
+
+#define NOISE_ERROR_INVALID_STATE 7
+
+#define NOISE_ERROR_PROTOCOL_ERROR 8
+
+#define NOISE_ERROR_NONCE_EXHAUSTED 9
+
+#define NOISE_ERROR_INTERNAL 10
+
+#define NOISE_ERROR_PEER_KEY_MISMATCH 11
+
+#define NOISE_ERROR_INVALID_KEY 12
+
+#define NOISE_FINGERPRINT_HEX 0
+
+#define NOISE_FINGERPRINT_BASE32 1
+
+#define NOISE_FINGERPRINT_SHA256 2
+
+#define NOISE_LOG_OFF 0
+
+#define NOISE_LOG_ERROR 1
+
+#define NOISE_LOG_WARN 2
+
+#define NOISE_LOG_INFO 3
+
+#define NOISE_LOG_DEBUG 4
+
+#define NOISE_LOG_TRACE 5
+
+#define NOISE_BATCH_ENCRYPT 0
+
+#define NOISE_BATCH_DECRYPT 1
+
+/**
+ * Major version of the C ABI, bumped when a function changes or goes away
+ */
+#define NOISE_FFI_VERSION_MAJOR 1
+
+/**
+ * Minor version of the C ABI, bumped when functions are added
+ */
+#define NOISE_FFI_VERSION_MINOR 1
+
+/**
+ * Handle value that never refers to an object
+ */
+#define NOISE_INVALID_HANDLE 0
+
+/**
+ * `kind` passed to callbacks for identity keys
+ */
+#define NOISE_STORAGE_IDENTITY 0
+
+/**
+ * `kind` passed to callbacks for session data
+ */
+#define NOISE_STORAGE_SESSION 1
+
+/**
+ * `direction` passed to `rekeyed` when sending keys changed
+ */
+#define NOISE_REKEY_OUTGOING 0
+
+/**
+ * `direction` passed to `rekeyed` when receiving keys changed
+ */
+#define NOISE_REKEY_INCOMING 1
+
+/**
+ * Largest replay window a session will allocate (8 KiB of bitmap)
+ */
+#define MAX_REPLAY_WINDOW_SIZE (1 << 16)
+
+/**
+ * Bit set in the type byte of a data frame whose body is LZ4-compressed
+ */
+#define FRAME_COMPRESSED 128
+
+/**
+ * Default cap on live sessions kept per peer
+ */
+#define DEFAULT_MAX_SESSIONS_PER_PEER 4
+
+/**
+ * Length of the frame header
+ */
+#define FRAME_HEADER_LEN 2
+
+/**
+ * ATT MTU every BLE link supports before negotiation
+ */
+#define DEFAULT_ATT_MTU 23
+
+/**
+ * Bytes of each ATT packet taken by the opcode and attribute handle
+ */
+#define ATT_HEADER_LEN 3
+
+/**
+ * Longest characteristic value a single write may carry
+ */
+#define MAX_ATTRIBUTE_LEN 512
+
+/**
+ * Messages sent in one key generation before [`ConnectionAction::NeedsRekey`]
+ */
+#define DEFAULT_REKEY_INTERVAL (1 << 20)
+
+/**
+ * Largest `BYTES` payload Nearby Connections accepts
+ */
+#define NEARBY_MAX_BYTES_LEN (32 * 1024)
+
+/**
+ * Packet size that fits any framed Noise message whole
+ *
+ * Multipeer's reliable mode has no small limit, so every message goes out
+ * as a single packet.
+ */
+#define MULTIPEER_MAX_PACKET_LEN (FRAME_HEADER_LEN + NOISE_MAX_MESSAGE_LEN)
+
+
+
+/**
+ * Length of a cookie
+ */
+#define COOKIE_LEN 16
+
+/**
+ * Length of the MAC an initiator appends to its opening
+ */
+#define COOKIE_MAC_LEN 16
+
+/**
+ * Length of a cookie reply
+ */
+#define COOKIE_REPLY_LEN (1 + COOKIE_LEN)
+
+/**
+ * Default number of handshakes a source may open back to back
+ */
+#define DEFAULT_HANDSHAKE_BURST 5
+
+/**
+ * Default cap on sources tracked at once
+ */
+#define DEFAULT_MAX_TRACKED_SOURCES 4096
+
+/**
+ * Length of a Noise pre-shared key
+ */
+#define PSK_LEN 32
+
+/**
+ * Most endpoint hints a payload can carry
+ */
+#define MAX_ENDPOINT_HINTS (uintptr_t)UINT8_MAX
+
+/**
+ * Longest endpoint hint, in bytes
+ */
+#define MAX_ENDPOINT_HINT_LEN (uintptr_t)UINT8_MAX
+
+/**
+ * Encoded length of a [`RotationLink`]
+ */
+#define ROTATION_LINK_LEN ((1 + (2 * KEY_LEN)) + SIGNATURE_LEN)
+
+/**
+ * Most message keys kept per member for out-of-order delivery
+ */
+#define MAX_SKIPPED_MESSAGE_KEYS 1000
+
+/**
+ * Most message keys kept for messages that have not arrived yet
+ */
+#define MAX_SKIPPED_MESSAGE_KEYS 1000
+
+/**
+ * Header bytes before the ciphertext: version, ratchet key, previous and
+ * current chain lengths
+ */
+#define RATCHET_HEADER_LEN (((1 + KEY_LEN) + 4) + 4)
+
+/**
+ * Default cap on messages queued per peer
+ */
+#define DEFAULT_MAX_QUEUED_MESSAGES 1000
+
 /**
  * FFI-safe error codes returned by C API functions
  */
@@ -73,6 +420,22 @@ typedef enum NoiseErrorCode {
    * General protocol error
    */
   PROTOCOL_ERROR = 8,
+  /**
+   * Transport nonces exhausted, a new handshake is required
+   */
+  NONCE_EXHAUSTED = 9,
+  /**
+   * A Rust panic was caught at the FFI boundary
+   */
+  INTERNAL = 10,
+  /**
+   * The peer presented a different static key than the pinned one
+   */
+  PEER_KEY_MISMATCH = 11,
+  /**
+   * A key was all zeros, a low-order point or non-canonically encoded
+   */
+  INVALID_KEY = 12,
 } NoiseErrorCode;
 
 /**
@@ -89,43 +452,459 @@ typedef enum NoiseMode {
   RESPONDER = 1,
 } NoiseMode;
 
-typedef struct NoiseError NoiseError;
-
 /**
- * Opaque pointer type for Noise sessions
+ * FFI-safe handshake pattern
+ *
+ * Sessions use the default suite, 25519 with ChaChaPoly and BLAKE2s.
+ *
  */
-typedef struct NoiseSessionFFI {
-  uint8_t _private[0];
-} NoiseSessionFFI;
+typedef enum NoisePattern {
+  /**
+   * Static keys exchanged during the handshake (the default)
+   */
+  NOISE_PATTERN_XX = 0,
+  /**
+   * The initiator knows the responder's static key in advance
+   */
+  NOISE_PATTERN_IK = 1,
+} NoisePattern;
 
 /**
- * Create a new Noise session
+ * FFI-safe priority for batched messages
+ *
  */
- struct NoiseSessionFFI *noise_session_new(int mode, int *error);
+typedef enum NoiseBatchPriority {
+  /**
+   * Processed at once and reported before the call returns
+   */
+  NOISE_BATCH_PRIORITY_IMMEDIATE = 0,
+  /**
+   * Flushed at the batch's threshold or interval
+   */
+  NOISE_BATCH_PRIORITY_NORMAL = 1,
+  /**
+   * Flushed with the next normal flush or after the background interval
+   */
+  NOISE_BATCH_PRIORITY_BACKGROUND = 2,
+} NoiseBatchPriority;
 
 /**
- * Create a new Noise session with a specific private key
+ * FFI-safe device power state for batching
+ *
  */
+typedef enum NoisePowerMode {
+  /**
+   * Low battery or thermal pressure: larger, rarer batches
+   */
+  NOISE_POWER_MODE_LOW = 0,
+  /**
+   * The default settings
+   */
+  NOISE_POWER_MODE_BALANCED = 1,
+  /**
+   * Charging or latency-sensitive: small batches, flushed quickly
+   */
+  NOISE_POWER_MODE_PERFORMANCE = 2,
+} NoisePowerMode;
 
-struct NoiseSessionFFI *noise_session_new_with_key(const unsigned char *private_key,
-                                                   size_t private_key_len,
-                                                   int mode,
-                                                   int *error);
+typedef struct NoiseError NoiseError;
 
 /**
- * Free a Noise session
+ * Represents a Noise Protocol session that can be either in handshake or transport mode
  */
- void noise_session_free(struct NoiseSessionFFI *session);
+typedef struct NoiseSession NoiseSession;
 
 /**
- * Write a handshake message
+ * Opaque handle type for Noise sessions
+ *
+ * Pointers of this type are registry handles, not addresses; they must not
+ * be dereferenced.
  */
-
-int noise_write_message(struct NoiseSessionFFI *session,
-                        const unsigned char *payload,
-                        size_t payload_len,
-                        unsigned char *output,
-                        size_t *output_len);
+typedef struct NoiseSessionFFI {
+  uint8_t _private[0];
+} NoiseSessionFFI;
+
+/**
+ * Opaque handle type for a session configuration
+ */
+typedef struct NoiseConfigFFI {
+  uint8_t _private[0];
+} NoiseConfigFFI;
+
+/**
+ * Platform callbacks backing `KeyStorage`
+ *
+ * `kind` is `NOISE_STORAGE_IDENTITY` or `NOISE_STORAGE_SESSION`, and `id` a
+ * NUL-terminated UTF-8 string. Callbacks return a `NOISE_ERROR_*` code:
+ * `load` reports a missing entry as `NOISE_ERROR_INVALID_PARAMETER` and
+ * follows the buffer sizing rules of the rest of the API. They may be
+ * called from any thread.
+ */
+typedef struct NoiseKeyStorageVTable {
+  /**
+   * Passed back as the first argument of every callback
+   */
+  void *context;
+  /**
+   * Store `data` under `id`, replacing any existing entry
+   */
+  int (*store)(void *context, int kind, const char *id, const unsigned char *data, size_t data_len);
+  /**
+   * Copy the entry stored under `id` into `output`
+   */
+  int (*load)(void *context, int kind, const char *id, unsigned char *output, size_t *output_len);
+  /**
+   * Delete the entry stored under `id`; deleting a missing entry succeeds
+   */
+  int (*delete_)(void *context, int kind, const char *id);
+  /**
+   * Optional: copy all ids of `kind` into `output`, each NUL-terminated
+   */
+  int (*list)(void *context, int kind, unsigned char *output, size_t *output_len);
+  /**
+   * Optional: called once when the library lets go of `context`
+   */
+  void (*release)(void *context);
+} NoiseKeyStorageVTable;
+
+/**
+ * Session event callbacks for event-driven runtimes
+ *
+ * Every callback is optional and receives `context` unchanged. Callbacks
+ * run on the thread that made the call raising the event, after the
+ * library has let go of the session, so they may call back in on the same
+ * session.
+ */
+typedef struct NoiseSessionCallbacks {
+  /**
+   * Passed back as the first argument of every callback
+   */
+  void *context;
+  /**
+   * The handshake finished; the session is ready for `noise_encrypt`
+   */
+  void (*handshake_complete)(void *context);
+  /**
+   * A message was decrypted; `plaintext` is valid only during the call
+   */
+  void (*message_decrypted)(void *context, const unsigned char *plaintext, size_t plaintext_len);
+  /**
+   * A call on the session failed with the given `NOISE_ERROR_*` code
+   */
+  void (*error)(void *context, int error);
+  /**
+   * One direction was rekeyed: `NOISE_REKEY_OUTGOING` or `NOISE_REKEY_INCOMING`
+   */
+  void (*rekeyed)(void *context, int direction);
+  /**
+   * The session handle was freed or split; no further callbacks follow
+   */
+  void (*closed)(void *context);
+} NoiseSessionCallbacks;
+
+/**
+ * FFI-safe buffer structure for data exchange
+ */
+typedef struct NoiseBuffer {
+  /**
+   * Pointer to data
+   */
+  uint8_t *data;
+  /**
+   * Length of data
+   */
+  uintptr_t len;
+  /**
+   * Capacity of buffer
+   */
+  uintptr_t capacity;
+} NoiseBuffer;
+
+/**
+ * Receives the library's log records, e.g. to forward to os_log or logcat
+ *
+ * `level` is a `NOISE_LOG_*` value, `target` the Rust module that logged
+ * and `message` the scrubbed text; both strings are NUL-terminated and
+ * valid only during the call. Called from whichever thread logged.
+ */
+typedef void (*NoiseLogCallback)(void *context, int level, const char *target, const char *message);
+
+/**
+ * Inspects a raw handshake payload from the peer
+ *
+ * `remote_static` is null with length 0 until the peer's static key has
+ * arrived. Return 0 to accept the payload; any other value aborts the
+ * handshake. Called on the thread calling `noise_read_message`.
+ */
+typedef int (*NoisePayloadVerifier)(void *context,
+                                    const unsigned char *payload,
+                                    size_t payload_len,
+                                    const unsigned char *remote_static,
+                                    size_t remote_static_len);
+
+/**
+ * Traffic counters for a session or a manager's live sessions
+ */
+typedef struct NoiseSessionStats {
+  /**
+   * Transport messages encrypted
+   */
+  uint64_t messages_sent;
+  /**
+   * Transport messages decrypted
+   */
+  uint64_t messages_received;
+  /**
+   * Ciphertext bytes produced
+   */
+  uint64_t bytes_sent;
+  /**
+   * Ciphertext bytes accepted
+   */
+  uint64_t bytes_received;
+  /**
+   * Transport messages that failed to decrypt
+   */
+  uint64_t decrypt_failures;
+  /**
+   * Rekeys applied in either direction
+   */
+  uint64_t rekeys;
+  /**
+   * Handshake duration in milliseconds, 0 until it completes
+   */
+  uint64_t handshake_duration_ms;
+} NoiseSessionStats;
+
+/**
+ * Opaque handle type for the sending half of a split session
+ */
+typedef struct NoiseSendHalfFFI {
+  uint8_t _private[0];
+} NoiseSendHalfFFI;
+
+/**
+ * Opaque handle type for the receiving half of a split session
+ */
+typedef struct NoiseRecvHalfFFI {
+  uint8_t _private[0];
+} NoiseRecvHalfFFI;
+
+/**
+ * Opaque handle type for a Noise Pipes handshake
+ */
+typedef struct NoisePipeFFI {
+  uint8_t _private[0];
+} NoisePipeFFI;
+
+/**
+ * Opaque handle type for a batch of queued encryptions and decryptions
+ */
+typedef struct NoiseBatchFFI {
+  uint8_t _private[0];
+} NoiseBatchFFI;
+
+/**
+ * Receives one result of a batch flush
+ *
+ * `operation` is `NOISE_BATCH_ENCRYPT` or `NOISE_BATCH_DECRYPT` and `id`
+ * the value the message was queued with. With `status`
+ * `NOISE_ERROR_SUCCESS`, `data` holds the ciphertext or plaintext, valid
+ * only during the call; otherwise it is null with length 0. Called on the
+ * thread that flushed, which for a background flusher is a library thread.
+ */
+typedef void (*NoiseBatchFlushCallback)(void *context,
+                                        uint64_t id,
+                                        int operation,
+                                        int status,
+                                        const unsigned char *data,
+                                        size_t data_len);
+
+/**
+ * Bucket sizes used by [`PaddingScheme::default_buckets`]
+ */
+#define DEFAULT_BUCKETS { 64, 256, 1024, 4096, 16384, }
+
+/**
+ * Create a new Noise session
+ */
+ struct NoiseSessionFFI *noise_session_new(int mode, int *error);
+
+/**
+ * Create a new Noise session for `pattern`
+ *
+ * `mode` is a `NOISE_MODE_*` value and `pattern` a `NOISE_PATTERN_*` one;
+ * anything else fails with `NOISE_ERROR_INVALID_PARAMETER`.
+ * `private_key` is 32 bytes, or NULL to
+ * generate one. `remote_static` is the responder's 32-byte static key,
+ * required for `NOISE_PATTERN_IK` initiators and NULL otherwise.
+ */
+
+struct NoiseSessionFFI *noise_session_new_with_pattern(int mode,
+                                                       int pattern,
+                                                       const unsigned char *private_key,
+                                                       size_t private_key_len,
+                                                       const unsigned char *remote_static,
+                                                       size_t remote_static_len,
+                                                       int *error);
+
+/**
+ * Create a session configuration
+ *
+ * Starts as an XX handshake with a generated static key, no PSK and an
+ * empty prologue. Adjust it with the `noise_config_set_*` functions, create
+ * any number of sessions with `noise_session_from_config` and release it
+ * with `noise_config_free`.
+ */
+ struct NoiseConfigFFI *noise_config_new(void);
+
+/**
+ * Free a session configuration
+ *
+ * Sessions already created from it are unaffected.
+ */
+ void noise_config_free(struct NoiseConfigFFI *config);
+
+/**
+ * Set the handshake pattern to a `NOISE_PATTERN_*` value
+ */
+ int noise_config_set_pattern(struct NoiseConfigFFI *config, int pattern);
+
+/**
+ * Set the 32-byte static private key, or generate one per session with NULL
+ */
+
+int noise_config_set_local_key(struct NoiseConfigFFI *config,
+                               const unsigned char *private_key,
+                               size_t private_key_len);
+
+/**
+ * Set the peer's 32-byte static key, or clear it with NULL
+ *
+ * Required for `NOISE_PATTERN_IK` initiators. With other patterns the key
+ * is pinned: the handshake fails with `NOISE_ERROR_PEER_KEY_MISMATCH` if
+ * the peer presents another one.
+ */
+
+int noise_config_set_remote_key(struct NoiseConfigFFI *config,
+                                const unsigned char *remote_static,
+                                size_t remote_static_len);
+
+/**
+ * Set a 32-byte pre-shared key, or clear it with NULL
+ *
+ * Switches the pattern to its PSK variant, `XXpsk3` or `IKpsk2`. Both peers
+ * must set the same PSK.
+ */
+ int noise_config_set_psk(struct NoiseConfigFFI *config, const unsigned char *psk, size_t psk_len);
+
+/**
+ * Set the handshake prologue, e.g. a channel binding; NULL clears it
+ *
+ * Both peers must set the same prologue or the handshake fails.
+ */
+
+int noise_config_set_prologue(struct NoiseConfigFFI *config,
+                              const unsigned char *prologue,
+                              size_t prologue_len);
+
+/**
+ * Create a new Noise session from a configuration
+ *
+ * `mode` is a `NOISE_MODE_*` value.
+ */
+
+struct NoiseSessionFFI *noise_session_from_config(struct NoiseConfigFFI *config,
+                                                  int mode,
+                                                  int *error);
+
+/**
+ * Create a new Noise session with a specific private key
+ */
+
+struct NoiseSessionFFI *noise_session_new_with_key(const unsigned char *private_key,
+                                                   size_t private_key_len,
+                                                   int mode,
+                                                   int *error);
+
+/**
+ * Create a new Noise session bound to transport identifiers via the prologue
+ *
+ * Both peers must pass identical `binding` bytes (e.g. a hash of the sorted
+ * BLE addresses of the link) or the handshake fails.
+ */
+
+struct NoiseSessionFFI *noise_session_new_with_binding(const unsigned char *private_key,
+                                                       size_t private_key_len,
+                                                       int mode,
+                                                       const unsigned char *binding,
+                                                       size_t binding_len,
+                                                       int *error);
+
+/**
+ * Create a new Noise session that only completes with a known peer
+ *
+ * The handshake fails with `NOISE_ERROR_PEER_KEY_MISMATCH` if the peer
+ * presents any static key other than the 32-byte `remote_static`.
+ */
+
+struct NoiseSessionFFI *noise_session_new_with_expected_remote(const unsigned char *private_key,
+                                                               size_t private_key_len,
+                                                               int mode,
+                                                               const unsigned char *remote_static,
+                                                               size_t remote_static_len,
+                                                               int *error);
+
+/**
+ * Install platform callbacks as the library's key storage
+ *
+ * The vtable is copied. Any previously installed storage has its `release`
+ * callback run once no call is using it. Pass NULL to uninstall. On failure
+ * nothing is installed and `release` is not called.
+ */
+ int noise_set_key_storage(const struct NoiseKeyStorageVTable *vtable);
+
+/**
+ * Create a new Noise session with the identity key stored under `identity_id`
+ *
+ * Loads the key through the storage set with `noise_set_key_storage`.
+ */
+
+struct NoiseSessionFFI *noise_session_new_with_identity(const char *identity_id,
+                                                        int mode,
+                                                        int *error);
+
+/**
+ * Free a Noise session
+ *
+ * The handle is invalid afterwards; freeing it again is a no-op.
+ */
+ void noise_session_free(struct NoiseSessionFFI *session);
+
+/**
+ * Set callbacks for the session's events, replacing any set before
+ *
+ * Saves polling `noise_is_handshake_complete`: `handshake_complete` fires
+ * from the `noise_read_message` or `noise_write_message` call that
+ * finishes the handshake, `message_decrypted` from each successful
+ * `noise_decrypt`, `rekeyed` after either side of a rekey and `error`
+ * whenever a call on the session fails. `closed` fires once when the
+ * handle is freed or split. `context` must stay valid until then or until
+ * the callbacks are replaced; a null `callbacks` removes them.
+ */
+
+int noise_session_set_callbacks(struct NoiseSessionFFI *session,
+                                const struct NoiseSessionCallbacks *callbacks);
+
+/**
+ * Write a handshake message
+ */
+
+int noise_write_message(struct NoiseSessionFFI *session,
+                        const unsigned char *payload,
+                        size_t payload_len,
+                        unsigned char *output,
+                        size_t *output_len);
 
 /**
  * Read a handshake message
@@ -142,8 +921,20 @@ int noise_read_message(struct NoiseSessionFFI *session,
  */
  int noise_is_handshake_complete(struct NoiseSessionFFI *session);
 
+/**
+ * Check whether this side should write the next message
+ *
+ * Follows the handshake pattern until the handshake completes, then is
+ * always 1. Returns 0 for an invalid session.
+ */
+ int noise_is_my_turn_to_send(struct NoiseSessionFFI *session);
+
 /**
  * Encrypt a message
+ *
+ * Encrypts straight into `ciphertext`. If it is too small,
+ * `*ciphertext_len` is set to the length needed and no nonce is used, so
+ * the call can be retried with a larger buffer.
  */
 
 int noise_encrypt(struct NoiseSessionFFI *session,
@@ -154,6 +945,10 @@ int noise_encrypt(struct NoiseSessionFFI *session,
 
 /**
  * Decrypt a message
+ *
+ * Decrypts straight into `plaintext`, which must hold `ciphertext_len`
+ * less the 16-byte tag. If it is too small, `*plaintext_len` is set to
+ * that length and the message is left undecrypted.
  */
 
 int noise_decrypt(struct NoiseSessionFFI *session,
@@ -162,6 +957,82 @@ int noise_decrypt(struct NoiseSessionFFI *session,
                   unsigned char *plaintext,
                   size_t *plaintext_len);
 
+/**
+ * Write a handshake message into a buffer allocated by the library
+ *
+ * On success `*output` owns the message and must be released with
+ * `noise_buffer_free`. On failure it is left empty.
+ */
+
+int noise_write_message_alloc(struct NoiseSessionFFI *session,
+                              const unsigned char *payload,
+                              size_t payload_len,
+                              struct NoiseBuffer *output);
+
+/**
+ * Read a handshake message, returning its payload in a buffer allocated
+ * by the library
+ *
+ * On success `*payload` owns the payload, possibly empty, and must be
+ * released with `noise_buffer_free`.
+ */
+
+int noise_read_message_alloc(struct NoiseSessionFFI *session,
+                             const unsigned char *input,
+                             size_t input_len,
+                             struct NoiseBuffer *payload);
+
+/**
+ * Encrypt a message into a buffer allocated by the library
+ *
+ * Saves sizing the output first with `noise_encrypt_size_hint`. On
+ * success `*ciphertext` must be released with `noise_buffer_free`.
+ */
+
+int noise_encrypt_alloc(struct NoiseSessionFFI *session,
+                        const unsigned char *plaintext,
+                        size_t plaintext_len,
+                        struct NoiseBuffer *ciphertext);
+
+/**
+ * Decrypt a message into a buffer allocated by the library
+ *
+ * On success `*plaintext` must be released with `noise_buffer_free`,
+ * which wipes it first.
+ */
+
+int noise_decrypt_alloc(struct NoiseSessionFFI *session,
+                        const unsigned char *ciphertext,
+                        size_t ciphertext_len,
+                        struct NoiseBuffer *plaintext);
+
+/**
+ * Release a buffer returned by a `_alloc` function
+ *
+ * The contents are wiped and `*buffer` is left empty, so freeing it again
+ * is harmless. Null and empty buffers are ignored. `_alloc` functions
+ * overwrite their output without reading it, so free a buffer before
+ * passing it to another one.
+ */
+ void noise_buffer_free(struct NoiseBuffer *buffer);
+
+/**
+ * Allocate a zeroed buffer of `len` bytes, e.g. to build input in place
+ *
+ * Like any other library buffer it must be released with
+ * `noise_buffer_free`.
+ */
+ int noise_buffer_alloc(size_t len, struct NoiseBuffer *buffer);
+
+/**
+ * Resize a library buffer to `len` bytes, keeping its contents
+ *
+ * New bytes are zero. Bytes cut off, and the old allocation if the buffer
+ * has to move, are wiped. `data` may change, so pointers into the buffer
+ * must be refreshed afterwards.
+ */
+ int noise_buffer_resize(struct NoiseBuffer *buffer, size_t len);
+
 /**
  * Get the remote peer's static public key
  */
@@ -171,18 +1042,752 @@ int noise_get_remote_static(struct NoiseSessionFFI *session,
                             size_t *output_len);
 
 /**
- * Get the maximum message length
+ * Get this side's static public key
  */
- size_t noise_max_message_len(void);
+
+int noise_get_local_static(struct NoiseSessionFFI *session,
+                           unsigned char *output,
+                           size_t *output_len);
 
 /**
- * Get the maximum payload length
+ * Get the session's short public identifier, the same on both peers
+ *
+ * Writes `SESSION_ID_LEN` bytes once the handshake completes, for use
+ * as a correlation key in logs and metrics. Fails with
+ * `NOISE_ERROR_INVALID_STATE` before then.
  */
- size_t noise_max_payload_len(void);
+ int noise_session_id(struct NoiseSessionFFI *session, unsigned char *output, size_t *output_len);
 
 /**
- * Get error string for an error code
+ * Route log records up to `level` to `callback`
+ *
+ * `level` is one of `NOISE_LOG_ERROR` through `NOISE_LOG_TRACE`;
+ * `NOISE_LOG_OFF` or a null `callback` stops logging. Messages are scrubbed
+ * of anything resembling key material. `context` is passed back unchanged
+ * and must stay valid until the callback is replaced.
  */
- const char *noise_error_string(int error);
+ int noise_set_log_callback(int level, NoiseLogCallback callback, void *context);
+
+/**
+ * Inspect every handshake payload from the peer with `callback`
+ *
+ * A non-zero return from `callback` aborts the handshake: the pending
+ * `noise_read_message` fails with `NOISE_ERROR_HANDSHAKE_FAILED` and the
+ * session cannot continue. `context` is passed back unchanged and must
+ * stay valid until the session is freed or the verifier is replaced. A
+ * null `callback` removes the verifier.
+ */
+
+int noise_set_payload_verifier(struct NoiseSessionFFI *session,
+                               NoisePayloadVerifier callback,
+                               void *context);
+
+/**
+ * Write the session's safety number as a NUL-terminated string
+ *
+ * `local_id` and `remote_id` are optional stable identifiers for this
+ * side and the peer, e.g. user IDs. Each peer passes its own identifier as
+ * `local_id` and the other's as `remote_id`; the two halves of the number
+ * are ordered by value rather than by side, so both compute the same
+ * digits. Needs both static keys, so call it once the handshake has
+ * completed. `output_len` includes the NUL.
+ */
+
+int noise_safety_number(struct NoiseSessionFFI *session,
+                        const char *local_id,
+                        const char *remote_id,
+                        char *output,
+                        size_t *output_len);
+
+/**
+ * Write the session's emoji SAS as a NUL-terminated UTF-8 string
+ *
+ * The emoji are separated by spaces. Available once the handshake has
+ * completed; `output_len` includes the NUL.
+ */
+ int noise_emoji_sas(struct NoiseSessionFFI *session, char *output, size_t *output_len);
+
+/**
+ * Derive `output_len` bytes of keying material bound to the session
+ *
+ * `label` is a NUL-terminated UTF-8 name for the key's purpose; `context`
+ * may be null when `context_len` is 0. Available once the handshake has
+ * completed.
+ */
+
+int noise_export_key(struct NoiseSessionFFI *session,
+                     const char *label,
+                     const unsigned char *context,
+                     size_t context_len,
+                     unsigned char *output,
+                     size_t output_len);
+
+/**
+ * Get the estimated number of handshake round trips remaining (0 once complete)
+ */
+ int noise_round_trips_remaining(struct NoiseSessionFFI *session, size_t *remaining);
+
+/**
+ * Get the number of transport messages left before nonce exhaustion
+ */
+ int noise_remaining_nonce_budget(struct NoiseSessionFFI *session, uint64_t *remaining);
+
+/**
+ * Rekey the sending direction; the peer must rekey its receiving direction
+ */
+ int noise_rekey_outgoing(struct NoiseSessionFFI *session);
+
+/**
+ * Rekey the receiving direction to follow a peer's `noise_rekey_outgoing`
+ */
+ int noise_rekey_incoming(struct NoiseSessionFFI *session);
+
+/**
+ * Get the number of rekeys applied to each direction
+ */
+ int noise_key_generations(struct NoiseSessionFFI *session, uint32_t *sending, uint32_t *receiving);
+
+/**
+ * Get the session's traffic counters
+ *
+ * Counters start at zero for each session handle, including sessions
+ * restored from a snapshot.
+ */
+ int noise_session_stats(struct NoiseSessionFFI *session, struct NoiseSessionStats *stats);
+
+/**
+ * Get the number of protocols this build supports
+ */
+ size_t noise_supported_protocol_count(void);
+
+/**
+ * Copy the NUL-terminated name of the supported protocol at `index`
+ *
+ * Names have the form `Noise_<pattern>_<dh>_<cipher>_<hash>`.
+ */
+ int noise_supported_protocol_name(size_t index, char *output, size_t *output_len);
+
+/**
+ * Get the version of the C ABI this library implements
+ *
+ * The major version is in the upper 16 bits and the minor in the lower 16.
+ * An app built against major `M`, minor `m` works with any library of
+ * major `M` and minor at least `m`; check this at startup rather than
+ * failing later on a missing symbol.
+ */
+ uint32_t noise_ffi_version(void);
+
+/**
+ * Get the library's release version, e.g. `"0.1.0"`
+ *
+ * The string is static and owned by the library.
+ */
+ const char *noise_library_version(void);
+
+/**
+ * Check whether this build has an optional capability: 1 if it does,
+ * 0 if not or if `feature` is unknown
+ *
+ * Protocol capabilities (`"xx"`, `"ik"`, `"psk"`, `"pq"`) reflect the
+ * crypto compiled in; `"fd"` the file-descriptor functions; the rest name
+ * Cargo features the library was built with.
+ */
+ int noise_has_feature(const char *feature);
+
+/**
+ * Create a session manager, returning its handle or `NOISE_INVALID_HANDLE`
+ */
+ uint64_t noise_manager_new(const unsigned char *private_key, size_t private_key_len, int *error);
+
+/**
+ * Rebuild a manager from `noise_manager_serialize` output
+ */
+
+uint64_t noise_manager_restore(const unsigned char *private_key,
+                               size_t private_key_len,
+                               const unsigned char *data,
+                               size_t data_len,
+                               int *error);
+
+/**
+ * Free a session manager and every session it owns
+ *
+ * The handle is invalid afterwards; freeing it again is a no-op.
+ */
+ void noise_manager_free(uint64_t manager);
+
+/**
+ * Start a handshake with a peer, writing the first message to `output`
+ */
+
+int noise_manager_connect(uint64_t manager,
+                          const char *peer_id,
+                          unsigned char *output,
+                          size_t *output_len);
+
+/**
+ * Prepare a handshake with a peer before it is reachable
+ *
+ * The next `noise_manager_connect` to the peer writes the prepared first
+ * message without generating keys.
+ */
+ int noise_manager_prewarm(uint64_t manager, const char *peer_id);
+
+/**
+ * Process a handshake message from a peer
+ *
+ * The reply to send back is written to `output`; `*output_len` is set to 0
+ * when there is nothing to send.
+ */
+
+int noise_manager_handle_handshake(uint64_t manager,
+                                   const char *peer_id,
+                                   const unsigned char *input,
+                                   size_t input_len,
+                                   unsigned char *output,
+                                   size_t *output_len);
+
+/**
+ * Encrypt a message for a peer with its newest live session
+ */
+
+int noise_manager_encrypt(uint64_t manager,
+                          const char *peer_id,
+                          const unsigned char *plaintext,
+                          size_t plaintext_len,
+                          unsigned char *ciphertext,
+                          size_t *ciphertext_len);
+
+/**
+ * Decrypt a message from a peer with its newest live session
+ */
+
+int noise_manager_decrypt(uint64_t manager,
+                          const char *peer_id,
+                          const unsigned char *ciphertext,
+                          size_t ciphertext_len,
+                          unsigned char *plaintext,
+                          size_t *plaintext_len);
+
+/**
+ * Encrypt a message to a peer into a buffer allocated by the library
+ *
+ * On success `*ciphertext` must be released with `noise_buffer_free`.
+ */
+
+int noise_manager_encrypt_alloc(uint64_t manager,
+                                const char *peer_id,
+                                const unsigned char *plaintext,
+                                size_t plaintext_len,
+                                struct NoiseBuffer *ciphertext);
+
+/**
+ * Decrypt a message from a peer into a buffer allocated by the library
+ *
+ * On success `*plaintext` must be released with `noise_buffer_free`.
+ */
+
+int noise_manager_decrypt_alloc(uint64_t manager,
+                                const char *peer_id,
+                                const unsigned char *ciphertext,
+                                size_t ciphertext_len,
+                                struct NoiseBuffer *plaintext);
+
+/**
+ * Check whether a session with a peer is live (1) or not (0)
+ */
+ int noise_manager_is_established(uint64_t manager, const char *peer_id);
+
+/**
+ * Drop every session with a peer
+ */
+ int noise_manager_remove(uint64_t manager, const char *peer_id);
+
+/**
+ * Number of peers with a live session or handshake in progress
+ */
+ size_t noise_manager_peer_count(uint64_t manager);
+
+/**
+ * Get traffic counters summed over all of a manager's live sessions
+ *
+ * `handshake_duration_ms` is the longest handshake among them.
+ */
+ int noise_manager_stats(uint64_t manager, struct NoiseSessionStats *stats);
+
+/**
+ * Get traffic counters summed over a peer's live sessions
+ *
+ * Fails with `NOISE_ERROR_INVALID_PARAMETER` if the peer has none.
+ */
+
+int noise_manager_peer_stats(uint64_t manager,
+                             const char *peer_id,
+                             struct NoiseSessionStats *stats);
+
+/**
+ * Serialize every live session, including raw keys, into `output`
+ *
+ * Wrap the output before persisting it, and free the manager afterwards:
+ * continuing to use it alongside a restored copy reuses nonces.
+ */
+ int noise_manager_serialize(uint64_t manager, unsigned char *output, size_t *output_len);
+
+/**
+ * Serialize a manager's live sessions into a buffer allocated by the library
+ *
+ * On success `*output` must be released with `noise_buffer_free`, which
+ * wipes the key material it holds.
+ */
+ int noise_manager_serialize_alloc(uint64_t manager, struct NoiseBuffer *output);
+
+/**
+ * Split an established session into send and receive halves
+ *
+ * On success the session handle is freed and each half gets its own handle,
+ * so a reader and a writer thread never wait on each other.
+ */
+
+int noise_session_split(struct NoiseSessionFFI *session,
+                        struct NoiseSendHalfFFI **send_half,
+                        struct NoiseRecvHalfFFI **recv_half);
+
+/**
+ * Encrypt a message with the sending half of a split session
+ */
+
+int noise_send_half_encrypt(struct NoiseSendHalfFFI *half,
+                            const unsigned char *plaintext,
+                            size_t plaintext_len,
+                            unsigned char *ciphertext,
+                            size_t *ciphertext_len);
+
+/**
+ * Decrypt a message with the receiving half of a split session
+ */
+
+int noise_recv_half_decrypt(struct NoiseRecvHalfFFI *half,
+                            const unsigned char *ciphertext,
+                            size_t ciphertext_len,
+                            unsigned char *plaintext,
+                            size_t *plaintext_len);
+
+/**
+ * Rekey the sending half; the peer must rekey its receiving direction
+ */
+ int noise_send_half_rekey(struct NoiseSendHalfFFI *half);
+
+/**
+ * Rekey the receiving half to follow the peer's outgoing rekey
+ */
+ int noise_recv_half_rekey(struct NoiseRecvHalfFFI *half);
+
+/**
+ * Free the sending half of a split session
+ */
+ void noise_send_half_free(struct NoiseSendHalfFFI *half);
+
+/**
+ * Free the receiving half of a split session
+ */
+ void noise_recv_half_free(struct NoiseRecvHalfFFI *half);
+
+/**
+ * Start a Noise Pipes handshake
+ *
+ * An initiator passing the responder's cached 32-byte static key tries IK
+ * and falls back to XX if the key has changed; with `remote_static` null
+ * it runs XX. Responders pass null.
+ */
+
+struct NoisePipeFFI *noise_pipe_new(const unsigned char *private_key,
+                                    size_t private_key_len,
+                                    int mode,
+                                    const unsigned char *remote_static,
+                                    size_t remote_static_len,
+                                    int *error);
+
+/**
+ * Write the next Noise Pipes handshake message
+ */
+
+int noise_pipe_write_message(struct NoisePipeFFI *pipe,
+                             const unsigned char *payload,
+                             size_t payload_len,
+                             unsigned char *output,
+                             size_t *output_len);
+
+/**
+ * Read the peer's next Noise Pipes handshake message
+ */
+
+int noise_pipe_read_message(struct NoisePipeFFI *pipe,
+                            const unsigned char *input,
+                            size_t input_len,
+                            unsigned char *payload,
+                            size_t *payload_len);
+
+/**
+ * Check if a Noise Pipes handshake is complete
+ */
+ int noise_pipe_is_handshake_complete(struct NoisePipeFFI *pipe);
+
+/**
+ * Check whether the responder rejected IK and the handshake fell back to XX
+ */
+ int noise_pipe_fell_back(struct NoisePipeFFI *pipe);
+
+/**
+ * Turn a completed Noise Pipes handshake into a session
+ *
+ * On success the pipe handle is freed and `*session` can be used with the
+ * rest of the API.
+ */
+ int noise_pipe_into_session(struct NoisePipeFFI *pipe, struct NoiseSessionFFI **session);
+
+/**
+ * Free a Noise Pipes handshake
+ */
+ void noise_pipe_free(struct NoisePipeFFI *pipe);
+
+/**
+ * Move a session into a batch that encrypts and decrypts in bulk
+ *
+ * On success the session handle is freed and `*batch` owns the session.
+ * Once a callback is set with `noise_batch_set_flush_callback`, messages
+ * are flushed to it when the batch's threshold is reached, when
+ * `noise_batch_poll` finds the interval has passed, or by a background
+ * flusher. Until then messages stay queued.
+ */
+ int noise_batch_new(struct NoiseSessionFFI *session, struct NoiseBatchFFI **batch);
+
+/**
+ * Queue a plaintext for encryption, reported under `id`
+ */
+
+int noise_batch_queue_encrypt(struct NoiseBatchFFI *batch,
+                              uint64_t id,
+                              const unsigned char *plaintext,
+                              size_t plaintext_len);
+
+/**
+ * Queue a ciphertext for decryption, reported under `id`
+ */
+
+int noise_batch_queue_decrypt(struct NoiseBatchFFI *batch,
+                              uint64_t id,
+                              const unsigned char *ciphertext,
+                              size_t ciphertext_len);
+
+/**
+ * Queue a message at `priority`, reported under `id`
+ *
+ * `operation` is `NOISE_BATCH_ENCRYPT` or `NOISE_BATCH_DECRYPT` and
+ * `priority` a `NOISE_BATCH_PRIORITY_*` value.
+ * `NOISE_BATCH_PRIORITY_IMMEDIATE` messages bypass the batch and reach the
+ * flush callback before this returns, so they need one set; background
+ * messages wait for the next normal flush or the background interval.
+ * Ciphertexts must be decrypted in order, so an immediate one waits behind
+ * queued ciphertexts and background ones are queued as normal.
+ */
+
+int noise_batch_queue_with_priority(struct NoiseBatchFFI *batch,
+                                    uint64_t id,
+                                    int operation,
+                                    int priority,
+                                    const unsigned char *data,
+                                    size_t data_len);
+
+/**
+ * Send the batch's flushed results to `callback`, once per message
+ *
+ * With `background` non-zero a library thread flushes the batch once its
+ * interval passes, so queued messages are never stranded; otherwise the
+ * app drives time-based flushes with `noise_batch_poll`. `callback` must
+ * not call back into the same batch. `context` is passed back unchanged
+ * and must stay valid until the batch is freed or the callback replaced.
+ * A null `callback` removes it and stops any background flusher.
+ */
+
+int noise_batch_set_flush_callback(struct NoiseBatchFFI *batch,
+                                   NoiseBatchFlushCallback callback,
+                                   void *context,
+                                   int background);
+
+/**
+ * Adapt every batch to the device's power state
+ *
+ * Call it from battery and thermal notifications. Applies to live batches
+ * and those created later, replacing their flush threshold and intervals;
+ * background flushers pick up the new intervals at once. `mode` is a
+ * `NOISE_POWER_MODE_*` value.
+ */
+ int noise_set_power_mode(int mode);
+
+/**
+ * Flush whatever is due to the flush callback
+ *
+ * `*next_flush_ms` receives the milliseconds until the next flush falls
+ * due, for scheduling a timer, or -1 with nothing queued.
+ */
+ int noise_batch_poll(struct NoiseBatchFFI *batch, int64_t *next_flush_ms);
+
+/**
+ * Flush everything queued to the flush callback now
+ *
+ * Fails with `NOISE_ERROR_INVALID_STATE` if no callback is set. Messages
+ * that failed to encrypt are reported and stay queued; automatic flushes
+ * report each failure once, and only this call retries them.
+ */
+ int noise_batch_flush(struct NoiseBatchFFI *batch);
+
+/**
+ * Free a batch, its session and anything still queued
+ */
+ void noise_batch_free(struct NoiseBatchFFI *batch);
+
+/**
+ * Generate a static keypair without creating a session
+ *
+ * Both buffers must hold 32 bytes. Persist the private key in secure
+ * storage and pass it to `noise_session_new_with_key`.
+ */
+
+int noise_generate_keypair(unsigned char *private_key,
+                           size_t *private_key_len,
+                           unsigned char *public_key,
+                           size_t *public_key_len);
+
+/**
+ * Derive the public key for a 32-byte private key
+ */
+
+int noise_public_key_from_private(const unsigned char *private_key,
+                                  size_t private_key_len,
+                                  unsigned char *public_key,
+                                  size_t *public_key_len);
+
+/**
+ * Render a public key fingerprint as a NUL-terminated string
+ *
+ * `format` is one of the `NOISE_FINGERPRINT_*` constants; `output_len`
+ * includes the NUL.
+ */
+
+int noise_key_fingerprint(const unsigned char *public_key,
+                          size_t public_key_len,
+                          int format,
+                          char *output,
+                          size_t *output_len);
+
+/**
+ * Encode a pairing payload for a QR code
+ *
+ * `psk` may be null; otherwise it must be 32 bytes. `endpoints` points to
+ * `endpoint_count` NUL-terminated hint strings and may be null when the
+ * count is 0.
+ */
+
+int noise_pairing_payload_encode(const unsigned char *static_key,
+                                 size_t static_key_len,
+                                 const unsigned char *psk,
+                                 size_t psk_len,
+                                 const char *const *endpoints,
+                                 size_t endpoint_count,
+                                 unsigned char *output,
+                                 size_t *output_len);
+
+/**
+ * Decode a scanned pairing payload
+ *
+ * Writes the 32-byte static key, the PSK (`*psk_len` is set to 0 if the
+ * payload has none) and the endpoint hints, each followed by a NUL. All
+ * buffers are checked before any is written; on `NOISE_ERROR_BUFFER_TOO_SMALL`
+ * every length holds the size needed.
+ */
+
+int noise_pairing_payload_decode(const unsigned char *payload,
+                                 size_t payload_len,
+                                 unsigned char *static_key,
+                                 size_t *static_key_len,
+                                 unsigned char *psk,
+                                 size_t *psk_len,
+                                 char *endpoints,
+                                 size_t *endpoints_len);
+
+/**
+ * Seal a one-way message to a recipient's 32-byte static key
+ *
+ * With `sender_private_key` null the message is anonymous; otherwise the
+ * recipient learns and can trust the sender's static key.
+ */
+
+int noise_seal(const unsigned char *recipient_static,
+               size_t recipient_static_len,
+               const unsigned char *sender_private_key,
+               size_t sender_private_key_len,
+               const unsigned char *plaintext,
+               size_t plaintext_len,
+               unsigned char *output,
+               size_t *output_len);
+
+/**
+ * Seal a one-way message into a buffer allocated by the library
+ *
+ * As `noise_seal`; on success `*output` must be released with
+ * `noise_buffer_free`.
+ */
+
+int noise_seal_alloc(const unsigned char *recipient_static,
+                     size_t recipient_static_len,
+                     const unsigned char *sender_private_key,
+                     size_t sender_private_key_len,
+                     const unsigned char *plaintext,
+                     size_t plaintext_len,
+                     struct NoiseBuffer *output);
+
+/**
+ * Open a sealed message with the recipient's private key
+ *
+ * If `sender` is not null it receives the sender's 32-byte static key,
+ * with `*sender_len` set to 0 for anonymous messages.
+ */
+
+int noise_open(const unsigned char *private_key,
+               size_t private_key_len,
+               const unsigned char *blob,
+               size_t blob_len,
+               unsigned char *plaintext,
+               size_t *plaintext_len,
+               unsigned char *sender,
+               size_t *sender_len);
+
+/**
+ * Encrypt the file at `input_path` into `output_path` under the session's
+ * file key
+ *
+ * Both ends of a session derive the same key, so the peer can decrypt the
+ * file with [`noise_file_decrypt`]; files never use transport nonces.
+ * If `plaintext_len` is not null it receives the bytes encrypted. The
+ * output only appears at `output_path` once complete; on failure any file
+ * already there is left as it was.
+ */
+
+int noise_file_encrypt(struct NoiseSessionFFI *session,
+                       const char *input_path,
+                       const char *output_path,
+                       uint64_t *plaintext_len);
+
+/**
+ * Decrypt a file from [`noise_file_encrypt`]
+ *
+ * Fails with `NOISE_ERROR_DECRYPTION_FAILED` or
+ * `NOISE_ERROR_PROTOCOL_ERROR` if the file was altered or cut short, in
+ * which case no output is written.
+ */
+
+int noise_file_decrypt(struct NoiseSessionFFI *session,
+                       const char *input_path,
+                       const char *output_path,
+                       uint64_t *plaintext_len);
+
+/**
+ * Like [`noise_file_encrypt`] between open file descriptors
+ *
+ * Reads `input_fd` from its current offset to the end and writes to
+ * `output_fd`. The descriptors stay open and owned by the caller.
+ */
+
+int noise_file_encrypt_fd(struct NoiseSessionFFI *session,
+                          int input_fd,
+                          int output_fd,
+                          uint64_t *plaintext_len);
+
+/**
+ * Like [`noise_file_decrypt`] between open file descriptors
+ *
+ * On failure `output_fd` may hold part of the file, which must be
+ * discarded. The descriptors stay open and owned by the caller.
+ */
+
+int noise_file_decrypt_fd(struct NoiseSessionFFI *session,
+                          int input_fd,
+                          int output_fd,
+                          uint64_t *plaintext_len);
+
+/**
+ * Get the maximum message length
+ */
+ size_t noise_max_message_len(void);
+
+/**
+ * Get the ciphertext length `noise_encrypt` will produce for a plaintext
+ * of `plaintext_len` bytes, accounting for the session's padding
+ */
+
+int noise_encrypt_size_hint(struct NoiseSessionFFI *session,
+                            size_t plaintext_len,
+                            size_t *ciphertext_len);
+
+/**
+ * Get the plaintext buffer length `noise_decrypt` needs for a ciphertext
+ * of `ciphertext_len` bytes
+ */
+
+int noise_decrypt_size_hint(struct NoiseSessionFFI *session,
+                            size_t ciphertext_len,
+                            size_t *plaintext_len);
+
+/**
+ * Get the maximum payload length
+ */
+ size_t noise_max_payload_len(void);
+
+/**
+ * Get the length of the authentication tag on every transport message
+ */
+ size_t noise_tag_len(void);
+
+/**
+ * Get the number of handshake messages `pattern` takes
+ *
+ * `pattern` is a handshake pattern such as `"XX"` or `"IKpsk2"`, run with
+ * this library's default suite, or a full protocol name such as
+ * `"Noise_XX_25519_AESGCM_SHA256"`. Returns 0 if it is not understood.
+ */
+ size_t noise_handshake_message_count(const char *pattern);
+
+/**
+ * Get the length of handshake message `index` (from 0) of `pattern`
+ * carrying an empty payload
+ *
+ * A payload adds exactly its own length, so a buffer of this size plus the
+ * payload length always fits the message. `pattern` is read as by
+ * `noise_handshake_message_count`. Returns 0 if it is not understood or
+ * `index` is past the last message.
+ */
+ size_t noise_handshake_message_max_len(const char *pattern, size_t index);
+
+/**
+ * Get error string for an error code
+ */
+ const char *noise_error_string(int error);
+
+/**
+ * Get the code of the last error on the calling thread
+ *
+ * Every function that reports an error code resets this on entry, so it
+ * describes the most recent such call. `NOISE_ERROR_SUCCESS` if it succeeded.
+ */
+ int noise_last_error_code(void);
+
+/**
+ * Get a description of the last error on the calling thread
+ *
+ * Unlike `noise_error_string` this carries the underlying detail, e.g. which
+ * Noise check failed. Returns NULL if the last call succeeded. The string is
+ * owned by the library and valid until the next call on this thread.
+ */
+ const char *noise_last_error_message(void);
 
 #endif  /* NOISE_MOBILE_H */
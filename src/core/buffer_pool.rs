@@ -0,0 +1,176 @@
+//! Scratch buffers shared between sessions
+//!
+//! A session needs scratch space for padded, gathered and in-place
+//! messages. Each session grows its own buffer by default, so a mesh with
+//! hundreds of peers can hold hundreds of them. With a [`BufferPool`] set via
+//! [`NoiseSession::set_buffer_pool`], sessions borrow a buffer for the
+//! length of one call instead, and the pool keeps at most `max_buffers`
+//! of them however many sessions there are.
+//!
+//! When every buffer is lent out, a call gets a temporary one that is freed
+//! afterwards. [`BufferPool::stats`] counts these so the pool can be sized.
+//!
+//! [`NoiseSession::set_buffer_pool`]: crate::core::session::NoiseSession::set_buffer_pool
+
+use crate::core::session::NoiseSession;
+use std::sync::{Arc, Mutex};
+
+/// Default number of buffers a pool keeps
+pub const DEFAULT_POOL_BUFFERS: usize = 8;
+
+/// How hard a pool is being used
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BufferPoolStats {
+    /// Buffers lent out in total
+    pub acquired: u64,
+    /// Loans served by an idle pooled buffer rather than a new allocation
+    pub reused: u64,
+    /// Loans that found the pool exhausted and got a temporary buffer
+    pub overflowed: u64,
+    /// Pooled buffers lent out right now
+    pub in_use: usize,
+    /// Most pooled buffers lent out at once
+    pub peak_in_use: usize,
+    /// Idle buffers held by the pool
+    pub idle: usize,
+}
+
+/// A bounded set of scratch buffers that sessions borrow from
+///
+/// Cloning gives another handle to the same pool.
+#[derive(Clone)]
+pub struct BufferPool {
+    inner: Arc<PoolInner>,
+}
+
+struct PoolInner {
+    buffer_len: usize,
+    max_buffers: usize,
+    state: Mutex<PoolState>,
+}
+
+#[derive(Default)]
+struct PoolState {
+    free: Vec<Vec<u8>>,
+    stats: BufferPoolStats,
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self::new(DEFAULT_POOL_BUFFERS)
+    }
+}
+
+impl BufferPool {
+    /// Create a pool of up to `max_buffers` buffers, each large enough for
+    /// any Noise message
+    pub fn new(max_buffers: usize) -> Self {
+        Self::with_buffer_len(max_buffers, NoiseSession::MAX_MESSAGE_LEN)
+    }
+    
+    /// Create a pool of up to `max_buffers` buffers of `buffer_len` bytes
+    ///
+    /// Longer requests get a temporary buffer, so keep `buffer_len` at
+    /// least the sessions' maximum message length.
+    pub fn with_buffer_len(max_buffers: usize, buffer_len: usize) -> Self {
+        Self {
+            inner: Arc::new(PoolInner {
+                buffer_len,
+                max_buffers,
+                state: Mutex::new(PoolState::default()),
+            }),
+        }
+    }
+    
+    /// Most memory the pool's own buffers take
+    pub fn memory_bound(&self) -> usize {
+        self.inner.max_buffers * self.inner.buffer_len
+    }
+    
+    /// Current usage counters
+    pub fn stats(&self) -> BufferPoolStats {
+        let state = self.lock();
+        BufferPoolStats { idle: state.free.len(), ..state.stats }
+    }
+    
+    /// Free the idle buffers, e.g. when the app moves to the background
+    pub fn trim(&self) {
+        self.lock().free.clear();
+    }
+    
+    /// Borrow a buffer of at least `len` bytes until the loan is dropped
+    pub(crate) fn acquire(&self, len: usize) -> PooledBuffer {
+        let mut state = self.lock();
+        state.stats.acquired += 1;
+        if len > self.inner.buffer_len || state.stats.in_use >= self.inner.max_buffers {
+            state.stats.overflowed += 1;
+            return PooledBuffer { buffer: vec![0u8; len], pool: None };
+        }
+        
+        let buffer = match state.free.pop() {
+            Some(buffer) => {
+                state.stats.reused += 1;
+                buffer
+            }
+            None => vec![0u8; self.inner.buffer_len],
+        };
+        state.stats.in_use += 1;
+        state.stats.peak_in_use = state.stats.peak_in_use.max(state.stats.in_use);
+        PooledBuffer { buffer, pool: Some(self.clone()) }
+    }
+    
+    fn lock(&self) -> std::sync::MutexGuard<'_, PoolState> {
+        self.inner.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// A buffer on loan from a [`BufferPool`], returned when dropped
+///
+/// The borrower wipes what it used; the pool does not.
+pub(crate) struct PooledBuffer {
+    buffer: Vec<u8>,
+    /// `None` for temporary buffers that are not returned
+    pool: Option<BufferPool>,
+}
+
+impl PooledBuffer {
+    pub(crate) fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.buffer
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            let mut state = pool.lock();
+            state.stats.in_use -= 1;
+            state.free.push(std::mem::take(&mut self.buffer));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_pool_bounds_buffers_and_counts_pressure() {
+        let pool = BufferPool::with_buffer_len(2, 1024);
+        assert_eq!(pool.memory_bound(), 2048);
+        
+        let first = pool.acquire(100);
+        let second = pool.acquire(1024);
+        let overflow = pool.acquire(10);
+        let too_long = pool.acquire(2000);
+        assert!(first.pool.is_some() && second.pool.is_some());
+        assert!(overflow.pool.is_none() && too_long.pool.is_none());
+        drop((first, second, overflow, too_long));
+        
+        let _reused = pool.acquire(10);
+        let stats = pool.stats();
+        assert_eq!((stats.acquired, stats.reused, stats.overflowed), (5, 1, 2));
+        assert_eq!((stats.in_use, stats.peak_in_use, stats.idle), (1, 2, 1));
+        pool.trim();
+        assert_eq!(pool.stats().idle, 0);
+    }
+}
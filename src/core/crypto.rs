@@ -1,3 +1,11 @@
+use crate::core::error::{NoiseError, Result};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::{ChaCha20Poly1305, XChaCha20Poly1305};
+use snow::params::{HandshakeModifier, HandshakePattern, HashChoice, NoiseParams};
+use snow::resolvers::{CryptoResolver, DefaultResolver};
+use snow::Builder;
+use subtle::ConstantTimeEq;
 use zeroize::Zeroize;
 
 #[derive(Zeroize)]
@@ -24,4 +32,293 @@ impl SecureBuffer {
 
 pub const NOISE_MAX_MESSAGE_LEN: usize = 65535;
 pub const NOISE_MAX_PAYLOAD_LEN: usize = 65535 - 16; // Subtract AEAD tag
-pub const NOISE_TAG_LEN: usize = 16;
\ No newline at end of file
+pub const NOISE_TAG_LEN: usize = 16;
+
+/// Transport nonce count at which a session refuses to send or receive more
+/// messages. Far below the 2^64 hard limit so apps have ample warning to
+/// schedule a fresh handshake.
+pub const NOISE_NONCE_LIMIT: u64 = 1 << 60;
+
+/// Number of messages a handshake pattern needs before transport mode
+///
+/// Counts follow the pattern definitions in sections 7.4 to 7.6 of the
+/// Noise specification. Deferring the initiator's authentication to a
+/// message of its own (`X1*`) adds a fourth.
+pub fn handshake_message_count(pattern: HandshakePattern) -> usize {
+    use HandshakePattern::*;
+    
+    match pattern {
+        N | K | X => 1,
+        NN | NK | NX | KN | KK | KX | IN | IK | IX => 2,
+        NK1 | KK1 | IK1 => 2,
+        XN | XK | XX => 3,
+        NX1 | XK1 | XX1 => 3,
+        K1N | K1K | K1K1 | K1X | KX1 | K1X1 => 3,
+        I1N | I1K | I1K1 | I1X | IX1 | I1X1 => 3,
+        X1N | X1K | X1K1 | X1X | X1X1 => 4,
+    }
+}
+
+/// Length of each handshake message of `params` when it carries no payload
+///
+/// Measured by running the handshake between throwaway keys, so key
+/// sizes, tags and PSK or KEM modifiers all match what a session writes. A
+/// payload adds exactly its own length, its tag being counted already.
+pub fn handshake_message_lens(params: &NoiseParams) -> Result<Vec<usize>> {
+    let pattern = params.handshake.pattern;
+    let initiator_static = Builder::new(params.clone()).generate_keypair()?;
+    let responder_static = Builder::new(params.clone()).generate_keypair()?;
+    let psk = [0u8; 32];
+    let build = |local: &snow::Keypair, remote: &snow::Keypair, initiator: bool| {
+        let mut builder = Builder::new(params.clone());
+        if pattern.needs_local_static_key(initiator) {
+            builder = builder.local_private_key(&local.private)?;
+        }
+        if pattern.need_known_remote_pubkey(initiator) {
+            builder = builder.remote_public_key(&remote.public)?;
+        }
+        for modifier in &params.handshake.modifiers.list {
+            if let HandshakeModifier::Psk(location) = modifier {
+                builder = builder.psk(*location, &psk)?;
+            }
+        }
+        if initiator {
+            builder.build_initiator()
+        } else {
+            builder.build_responder()
+        }
+    };
+    let mut initiator = build(&initiator_static, &responder_static, true)?;
+    let mut responder = build(&responder_static, &initiator_static, false)?;
+    
+    let mut message = vec![0u8; NOISE_MAX_MESSAGE_LEN];
+    let mut payload = vec![0u8; NOISE_MAX_MESSAGE_LEN];
+    let mut lens = Vec::new();
+    for index in 0..handshake_message_count(pattern) {
+        let (writer, reader) = if index % 2 == 0 {
+            (&mut initiator, &mut responder)
+        } else {
+            (&mut responder, &mut initiator)
+        };
+        let len = writer.write_message(&[], &mut message)?;
+        reader.read_message(&message[..len], &mut payload)?;
+        lens.push(len);
+    }
+    Ok(lens)
+}
+
+/// Length of the random nonce prepended by [`aead_seal`]
+pub(crate) const AEAD_NONCE_LEN: usize = 24;
+
+/// Fill `out` with bytes from the operating system CSPRNG
+pub(crate) fn random_bytes(out: &mut [u8]) {
+    OsRng.fill_bytes(out);
+}
+
+/// Encrypt under a 32-byte key with XChaCha20-Poly1305 and a random nonce
+///
+/// Output layout is `nonce || ciphertext || tag`; random 24-byte nonces make
+/// it safe to reuse one key for many blobs (e.g. a key-encryption key).
+pub(crate) fn aead_seal(key: &[u8], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let cipher = XChaCha20Poly1305::new_from_slice(key).map_err(|_| NoiseError::InvalidParameter)?;
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, Payload { msg: plaintext, aad })
+        .map_err(|_| NoiseError::EncryptionFailed)?;
+    
+    let mut output = Vec::with_capacity(AEAD_NONCE_LEN + ciphertext.len());
+    output.extend_from_slice(&nonce);
+    output.extend_from_slice(&ciphertext);
+    Ok(output)
+}
+
+/// Decrypt a blob produced by [`aead_seal`]
+pub(crate) fn aead_open(key: &[u8], aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>> {
+    if sealed.len() < AEAD_NONCE_LEN + NOISE_TAG_LEN {
+        return Err(NoiseError::InvalidMessage);
+    }
+    let cipher = XChaCha20Poly1305::new_from_slice(key).map_err(|_| NoiseError::InvalidParameter)?;
+    let (nonce, ciphertext) = sealed.split_at(AEAD_NONCE_LEN);
+    cipher
+        .decrypt(nonce.into(), Payload { msg: ciphertext, aad })
+        .map_err(|_| NoiseError::DecryptionFailed)
+}
+
+/// Noise `REKEY(k)` for ChaChaPoly: the first 32 bytes of
+/// `ENCRYPT(k, 2^64 - 1, "", zeros(32))`
+pub(crate) fn rekey_chachapoly(key: &[u8; 32]) -> Result<[u8; 32]> {
+    let cipher = ChaCha20Poly1305::new_from_slice(key).map_err(|_| NoiseError::InvalidParameter)?;
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&u64::MAX.to_le_bytes());
+    let mut ciphertext = cipher
+        .encrypt((&nonce).into(), &[0u8; 32][..])
+        .map_err(|_| NoiseError::EncryptionFailed)?;
+    
+    let mut rekeyed = [0u8; 32];
+    rekeyed.copy_from_slice(&ciphertext[..32]);
+    ciphertext.zeroize();
+    Ok(rekeyed)
+}
+
+/// Advance a symmetric chain key, returning `(next chain key, message key)`
+///
+/// Both come from the ChaCha20 keystream of the chain key at nonce zero, so
+/// earlier chain keys cannot be recovered from later ones.
+#[cfg(any(feature = "group", feature = "ratchet"))]
+pub(crate) fn chain_step(chain_key: &[u8; 32]) -> Result<([u8; 32], [u8; 32])> {
+    let cipher = ChaCha20Poly1305::new_from_slice(chain_key).map_err(|_| NoiseError::InvalidParameter)?;
+    let mut keystream = cipher
+        .encrypt((&[0u8; 12]).into(), &[0u8; 64][..])
+        .map_err(|_| NoiseError::EncryptionFailed)?;
+    
+    let mut next = [0u8; 32];
+    let mut message_key = [0u8; 32];
+    next.copy_from_slice(&keystream[..32]);
+    message_key.copy_from_slice(&keystream[32..64]);
+    keystream.zeroize();
+    Ok((next, message_key))
+}
+
+/// HKDF (RFC 5869) over one of the Noise hash functions
+///
+/// `salt` must be no longer than the hash's block size. Fails if `len` is
+/// zero or more than 255 hash outputs.
+pub(crate) fn hkdf(hash: HashChoice, salt: &[u8], ikm: &[u8], info: &[u8], len: usize) -> Result<Vec<u8>> {
+    let mut hash = DefaultResolver
+        .resolve_hash(&hash)
+        .ok_or_else(|| NoiseError::InvalidState("Hash function unavailable".to_string()))?;
+    let hash_len = hash.hash_len();
+    if len == 0 || len > 255 * hash_len || salt.len() > hash.block_len() {
+        return Err(NoiseError::InvalidParameter);
+    }
+    
+    let mut prk = [0u8; 64];
+    hash.hmac(salt, ikm, &mut prk[..hash_len]);
+    
+    let mut output = Vec::with_capacity(len + hash_len);
+    let mut block = [0u8; 64];
+    let mut input = Vec::with_capacity(hash_len + info.len() + 1);
+    for counter in 1..=len.div_ceil(hash_len) as u8 {
+        input.clear();
+        if counter > 1 {
+            input.extend_from_slice(&block[..hash_len]);
+        }
+        input.extend_from_slice(info);
+        input.push(counter);
+        hash.hmac(&prk[..hash_len], &input, &mut block[..hash_len]);
+        output.extend_from_slice(&block[..hash_len]);
+    }
+    output.truncate(len);
+    prk.zeroize();
+    block.zeroize();
+    input.zeroize();
+    Ok(output)
+}
+
+/// Compare two byte strings without leaking where they differ through timing
+///
+/// Use this for keys, MACs, fingerprints and anything else an attacker may
+/// try to guess byte by byte. The lengths are not secret and are compared
+/// first.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+/// Domain separator for channel binding prologues
+const CHANNEL_BINDING_LABEL: &[u8] = b"noise-mobile/channel-binding/v1";
+
+/// Build a handshake prologue binding the session to transport identifiers
+///
+/// Identifiers are sorted before encoding, so each side can pass its own
+/// view (e.g. local and remote BLE addresses) in any order. A relay that
+/// forwards handshake messages between two different links produces
+/// mismatched prologues and the handshake fails.
+pub fn channel_binding(context: &str, identifiers: &[&[u8]]) -> Vec<u8> {
+    let mut sorted = identifiers.to_vec();
+    sorted.sort();
+    
+    let mut binding = CHANNEL_BINDING_LABEL.to_vec();
+    for field in std::iter::once(context.as_bytes()).chain(sorted) {
+        binding.extend_from_slice(&(field.len() as u32).to_be_bytes());
+        binding.extend_from_slice(field);
+    }
+    binding
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_handshake_message_count() {
+        assert_eq!(handshake_message_count(HandshakePattern::N), 1);
+        assert_eq!(handshake_message_count(HandshakePattern::NN), 2);
+        assert_eq!(handshake_message_count(HandshakePattern::IK), 2);
+        assert_eq!(handshake_message_count(HandshakePattern::NK1), 2);
+        assert_eq!(handshake_message_count(HandshakePattern::XX), 3);
+        assert_eq!(handshake_message_count(HandshakePattern::NX1), 3);
+        assert_eq!(handshake_message_count(HandshakePattern::K1N), 3);
+        assert_eq!(handshake_message_count(HandshakePattern::I1X), 3);
+        assert_eq!(handshake_message_count(HandshakePattern::X1N), 4);
+        assert_eq!(handshake_message_count(HandshakePattern::X1X1), 4);
+    }
+    
+    #[test]
+    fn test_handshake_message_lens() {
+        let lens = |name: &str| handshake_message_lens(&name.parse().unwrap()).unwrap();
+        // e; e, ee, s, es; s, se
+        assert_eq!(lens("Noise_XX_25519_ChaChaPoly_BLAKE2s"), vec![32, 32 + 48 + 16, 48 + 16]);
+        assert_eq!(lens("Noise_IK_25519_ChaChaPoly_BLAKE2s"), vec![32 + 48 + 16, 32 + 16]);
+        assert_eq!(lens("Noise_N_25519_ChaChaPoly_BLAKE2s"), vec![32 + 16]);
+        assert_eq!(lens("Noise_NNpsk0_25519_ChaChaPoly_BLAKE2s"), vec![32 + 16, 32 + 16]);
+    }
+    
+    #[test]
+    fn test_aead_roundtrip() {
+        let mut key = [0u8; 32];
+        random_bytes(&mut key);
+        
+        let sealed = aead_seal(&key, b"aad", b"secret").unwrap();
+        assert_eq!(sealed.len(), AEAD_NONCE_LEN + 6 + NOISE_TAG_LEN);
+        assert_eq!(aead_open(&key, b"aad", &sealed).unwrap(), b"secret");
+        
+        // Wrong associated data or truncated input must fail
+        assert!(aead_open(&key, b"other", &sealed).is_err());
+        assert!(aead_open(&key, b"aad", &sealed[..10]).is_err());
+    }
+    
+    #[test]
+    fn test_hkdf_sha256_vector() {
+        // RFC 5869 test case 1
+        let ikm = [0x0bu8; 22];
+        let salt: Vec<u8> = (0x00..=0x0c).collect();
+        let info: Vec<u8> = (0xf0..=0xf9).collect();
+        let okm = hkdf(HashChoice::SHA256, &salt, &ikm, &info, 42).unwrap();
+        assert_eq!(okm[..8], [0x3c, 0xb2, 0x5f, 0x25, 0xfa, 0xac, 0xd5, 0x7a]);
+        assert_eq!(okm[32..], [0x34, 0x00, 0x72, 0x08, 0xd5, 0xb8, 0x87, 0x18, 0x58, 0x65]);
+        assert!(hkdf(HashChoice::SHA256, &salt, &ikm, &info, 0).is_err());
+        assert!(hkdf(HashChoice::SHA256, &salt, &ikm, &info, 255 * 32 + 1).is_err());
+    }
+    
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"", b""));
+        assert!(constant_time_eq(&[7u8; 32], &[7u8; 32]));
+        assert!(!constant_time_eq(&[7u8; 32], &[7u8; 31]));
+        let mut last_differs = [7u8; 32];
+        last_differs[31] = 8;
+        assert!(!constant_time_eq(&[7u8; 32], &last_differs));
+    }
+    
+    #[test]
+    fn test_channel_binding_is_order_independent() {
+        let a = channel_binding("ble", &[b"AA:BB", b"CC:DD"]);
+        let b = channel_binding("ble", &[b"CC:DD", b"AA:BB"]);
+        assert_eq!(a, b);
+        
+        assert_ne!(a, channel_binding("relay", &[b"AA:BB", b"CC:DD"]));
+        assert_ne!(a, channel_binding("ble", &[b"AA:BB", b"CC:DE"]));
+        // Length prefixes keep field boundaries unambiguous
+        assert_ne!(channel_binding("ab", &[b"c"]), channel_binding("a", &[b"bc"]));
+    }
+}
@@ -0,0 +1,154 @@
+//! 0-RTT early data for IK reconnects
+//!
+//! An initiator that already knows the responder's static key can put
+//! application data in the first IK handshake message and save a round
+//! trip. That data is weaker than transport traffic:
+//!
+//! - it is encrypted to the responder's static key alone, so a later leak
+//!   of that key exposes it (no forward secrecy);
+//! - an attacker can capture the message and replay it to the responder.
+//!
+//! Only send early data that is safe to act on twice, such as a read-only
+//! request. Responders pass every early payload through a [`ReplayCache`],
+//! which rejects payloads timestamped outside a window around the local
+//! clock and any handshake ephemeral key already seen within it.
+
+use crate::core::error::{NoiseError, Result};
+use crate::core::now_ms;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Length of the timestamp that precedes early data
+pub const EARLY_DATA_HEADER_LEN: usize = 8;
+
+/// Default clock difference tolerated between initiator and responder
+pub const DEFAULT_REPLAY_WINDOW: Duration = Duration::from_secs(60);
+
+/// Default number of ephemeral keys a [`ReplayCache`] remembers
+pub const DEFAULT_REPLAY_CACHE_CAPACITY: usize = 10_000;
+
+/// Length of the ephemeral key that identifies a handshake
+const EPHEMERAL_LEN: usize = 32;
+
+/// Prefix early data with the current time, for the first IK message
+pub fn encode_early_data(data: &[u8]) -> Vec<u8> {
+    let mut payload = now_ms().to_be_bytes().to_vec();
+    payload.extend_from_slice(data);
+    payload
+}
+
+/// Remembers recent 0-RTT handshakes so each is accepted only once
+///
+/// Payloads timestamped before the cache was created are rejected too: a
+/// new cache cannot know what an earlier one (e.g. before an app restart)
+/// accepted. Rejection is always safe; the initiator retries with a full
+/// handshake.
+#[derive(Debug)]
+pub struct ReplayCache {
+    window_ms: u64,
+    capacity: usize,
+    created_ms: u64,
+    /// Ephemeral key -> early data timestamp
+    seen: HashMap<[u8; EPHEMERAL_LEN], u64>,
+}
+
+impl Default for ReplayCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_REPLAY_WINDOW, DEFAULT_REPLAY_CACHE_CAPACITY)
+    }
+}
+
+impl ReplayCache {
+    /// Create a cache accepting timestamps within `window` of the local clock
+    ///
+    /// Once `capacity` handshakes inside the window are remembered, further
+    /// early data is rejected until older entries expire.
+    pub fn new(window: Duration, capacity: usize) -> Self {
+        Self {
+            window_ms: window.as_millis().try_into().unwrap_or(u64::MAX),
+            capacity,
+            created_ms: now_ms(),
+            seen: HashMap::new(),
+        }
+    }
+    
+    /// Check an early payload, returning the application data
+    ///
+    /// `message` is the handshake message the payload arrived in; its
+    /// leading ephemeral key identifies the handshake. Fails with
+    /// [`NoiseError::ReplayDetected`] for stale, future or repeated payloads.
+    pub fn accept(&mut self, message: &[u8], payload: &[u8]) -> Result<Vec<u8>> {
+        self.accept_at(message, payload, now_ms())
+    }
+    
+    fn accept_at(&mut self, message: &[u8], payload: &[u8], now_ms: u64) -> Result<Vec<u8>> {
+        let ephemeral: [u8; EPHEMERAL_LEN] = message
+            .get(..EPHEMERAL_LEN)
+            .and_then(|key| key.try_into().ok())
+            .ok_or(NoiseError::InvalidMessage)?;
+        let (timestamp, data) = payload.split_first_chunk::<EARLY_DATA_HEADER_LEN>().ok_or(NoiseError::InvalidMessage)?;
+        let timestamp = u64::from_be_bytes(*timestamp);
+        
+        if timestamp < self.created_ms || timestamp.abs_diff(now_ms) > self.window_ms {
+            return Err(NoiseError::ReplayDetected);
+        }
+        let window_ms = self.window_ms;
+        self.seen.retain(|_, seen| seen.abs_diff(now_ms) <= window_ms);
+        if self.seen.len() >= self.capacity || self.seen.contains_key(&ephemeral) {
+            return Err(NoiseError::ReplayDetected);
+        }
+        self.seen.insert(ephemeral, timestamp);
+        Ok(data.to_vec())
+    }
+    
+    /// Number of handshakes currently remembered
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+    
+    /// Check whether no handshakes are remembered
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_rejects_replays() {
+        let mut cache = ReplayCache::default();
+        let payload = encode_early_data(b"GET /inbox");
+        let message = [7u8; 96];
+        assert_eq!(cache.accept(&message, &payload).unwrap(), b"GET /inbox");
+        assert!(matches!(cache.accept(&message, &payload), Err(NoiseError::ReplayDetected)));
+        
+        // A new handshake with the same data is fine
+        let other = [8u8; 96];
+        assert!(cache.accept(&other, &payload).is_ok());
+        assert_eq!(cache.len(), 2);
+        assert!(cache.accept(&other[..16], &payload).is_err());
+        assert!(cache.accept(&[9u8; 96], &payload[..4]).is_err());
+    }
+    
+    #[test]
+    fn test_time_window_and_capacity() {
+        let mut cache = ReplayCache::new(Duration::from_secs(10), 2);
+        let now = cache.created_ms + 1_000;
+        let at = |ms: u64| ms.to_be_bytes().to_vec();
+        
+        assert!(cache.accept_at(&[1u8; 32], &at(now - 500), now).is_ok());
+        // Before the cache existed, or too far from the clock
+        assert!(cache.accept_at(&[2u8; 32], &at(cache.created_ms - 1), now).is_err());
+        assert!(cache.accept_at(&[2u8; 32], &at(now + 20_000), now).is_err());
+        
+        assert!(cache.accept_at(&[2u8; 32], &at(now), now).is_ok());
+        assert!(cache.accept_at(&[3u8; 32], &at(now), now).is_err());
+        
+        // Entries expire with the window, freeing space
+        let later = now + 30_000;
+        assert!(cache.accept_at(&[3u8; 32], &at(later), later).is_ok());
+        assert_eq!(cache.len(), 1);
+    }
+}
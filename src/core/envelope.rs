@@ -0,0 +1,139 @@
+//! Multi-recipient envelopes
+//!
+//! Encrypts a payload once under a random content key and wraps that key
+//! to each recipient's static key as a [`SealedMessage`], so large media in
+//! a small group is encrypted once rather than once per member.
+//!
+//! Layout:
+//!
+//! ```text
+//! version: u8 | count: u16 | count * (len: u16 | sealed content key)
+//!      | XChaCha20-Poly1305(content key, header as AAD, payload)
+//! ```
+//!
+//! Wrapped keys carry no recipient identifiers, so opening tries each in
+//! turn; keep recipient lists to small groups.
+
+use crate::core::crypto::{aead_open, aead_seal, random_bytes};
+use crate::core::error::{NoiseError, Result};
+use crate::core::keys::Keypair;
+use crate::core::sealed::{OpenedMessage, SealedMessage};
+use zeroize::Zeroizing;
+
+/// Envelope format version
+const ENVELOPE_VERSION: u8 = 1;
+
+/// Length of the random content key
+const CONTENT_KEY_LEN: usize = 32;
+
+/// Most recipients an envelope can address
+pub const MAX_RECIPIENTS: usize = u16::MAX as usize;
+
+/// Encrypts one payload for many recipients
+pub struct Envelope;
+
+impl Envelope {
+    /// Encrypt for every key in `recipients` without revealing the sender
+    pub fn seal(recipients: &[&[u8]], plaintext: &[u8]) -> Result<Vec<u8>> {
+        Self::build(recipients, plaintext, SealedMessage::seal)
+    }
+    
+    /// Encrypt for every key in `recipients`, authenticated by the sender's
+    ///
+    /// Authentication covers the content key, which every recipient learns:
+    /// one recipient can forge a payload to the others that appears to come
+    /// from the sender. Sign the payload where that matters.
+    pub fn seal_from(sender: &Keypair, recipients: &[&[u8]], plaintext: &[u8]) -> Result<Vec<u8>> {
+        Self::build(recipients, plaintext, |recipient, key| SealedMessage::seal_from(sender, recipient, key))
+    }
+    
+    fn build(
+        recipients: &[&[u8]],
+        plaintext: &[u8],
+        wrap: impl Fn(&[u8], &[u8]) -> Result<Vec<u8>>,
+    ) -> Result<Vec<u8>> {
+        if recipients.is_empty() || recipients.len() > MAX_RECIPIENTS {
+            return Err(NoiseError::InvalidParameter);
+        }
+        let mut content_key = Zeroizing::new([0u8; CONTENT_KEY_LEN]);
+        random_bytes(&mut content_key[..]);
+        
+        let mut envelope = vec![ENVELOPE_VERSION];
+        envelope.extend_from_slice(&(recipients.len() as u16).to_be_bytes());
+        for recipient in recipients {
+            let wrapped = wrap(recipient, &content_key[..])?;
+            envelope.extend_from_slice(&(wrapped.len() as u16).to_be_bytes());
+            envelope.extend_from_slice(&wrapped);
+        }
+        let ciphertext = aead_seal(&content_key[..], &envelope, plaintext)?;
+        envelope.extend_from_slice(&ciphertext);
+        Ok(envelope)
+    }
+    
+    /// Decrypt an envelope addressed to `keypair`
+    ///
+    /// Fails with [`NoiseError::DecryptionFailed`] if no wrapped key is for
+    /// this recipient or the payload was altered.
+    pub fn open(keypair: &Keypair, envelope: &[u8]) -> Result<OpenedMessage> {
+        let (&version, rest) = envelope.split_first().ok_or(NoiseError::InvalidMessage)?;
+        let (count, mut rest) = rest.split_first_chunk::<2>().ok_or(NoiseError::InvalidMessage)?;
+        if version != ENVELOPE_VERSION {
+            return Err(NoiseError::InvalidMessage);
+        }
+        
+        let mut opened = None;
+        for _ in 0..u16::from_be_bytes(*count) {
+            let (len, after) = rest.split_first_chunk::<2>().ok_or(NoiseError::InvalidMessage)?;
+            let len = u16::from_be_bytes(*len) as usize;
+            let wrapped = after.get(..len).ok_or(NoiseError::InvalidMessage)?;
+            rest = &after[len..];
+            if opened.is_none() {
+                opened = SealedMessage::open(keypair, wrapped).ok();
+            }
+        }
+        let OpenedMessage { plaintext: content_key, sender, .. } = opened.ok_or(NoiseError::DecryptionFailed)?;
+        let content_key = Zeroizing::new(content_key);
+        
+        let header = &envelope[..envelope.len() - rest.len()];
+        let plaintext = aead_open(&content_key, header, rest).map_err(|_| NoiseError::DecryptionFailed)?;
+        Ok(OpenedMessage { plaintext, sender, sent_at_ms: None })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_every_recipient_opens() {
+        let members: Vec<Keypair> = (0..3).map(|_| Keypair::generate().unwrap()).collect();
+        let keys: Vec<&[u8]> = members.iter().map(|m| &m.public_key()[..]).collect();
+        let media = vec![0x42u8; 100_000];
+        
+        let envelope = Envelope::seal(&keys, &media).unwrap();
+        assert!(envelope.len() < media.len() + 3 * 200);
+        for member in &members {
+            let opened = Envelope::open(member, &envelope).unwrap();
+            assert_eq!(opened.plaintext, media);
+            assert_eq!(opened.sender, None);
+        }
+        
+        let outsider = Keypair::generate().unwrap();
+        assert!(matches!(Envelope::open(&outsider, &envelope), Err(NoiseError::DecryptionFailed)));
+        assert!(Envelope::seal(&[], b"x").is_err());
+    }
+    
+    #[test]
+    fn test_sender_and_tampering() {
+        let sender = Keypair::generate().unwrap();
+        let member = Keypair::generate().unwrap();
+        let envelope = Envelope::seal_from(&sender, &[member.public_key()], b"photo").unwrap();
+        let opened = Envelope::open(&member, &envelope).unwrap();
+        assert_eq!(opened.sender, Some(*sender.public_key()));
+        
+        let mut tampered = envelope.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(Envelope::open(&member, &tampered).is_err());
+        assert!(Envelope::open(&member, &envelope[..10]).is_err());
+    }
+}
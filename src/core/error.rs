@@ -29,8 +29,79 @@ pub enum NoiseError {
     #[error("Invalid message")]
     InvalidMessage,
     
+    #[error("Nonce space exhausted, a new handshake is required")]
+    NonceExhausted,
+    
+    #[error("Remote static key does not match the expected key")]
+    PeerKeyMismatch,
+    
+    #[error("Invalid key: {0}")]
+    InvalidKey(#[from] KeyError),
+    
+    #[error("Handshake timed out waiting for the peer")]
+    HandshakeTimeout,
+    
+    #[error("Invalid serialized state: {0}")]
+    Deserialize(#[from] DeserializeError),
+    
     #[error("Snow error: {0}")]
     Snow(#[from] snow::Error),
 }
 
+/// Reasons a key was refused by [`crate::core::keys::validate_private_key`]
+/// or [`crate::core::keys::validate_public_key`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum KeyError {
+    /// Every byte is zero, typically an uninitialized buffer
+    #[error("key is all zeros")]
+    AllZero,
+    
+    /// The point has order dividing 8, so Diffie-Hellman with it yields a
+    /// secret an attacker can predict
+    #[error("key is a low-order point")]
+    LowOrder,
+    
+    /// The encoding has the top bit set or is not reduced modulo 2^255 - 19
+    #[error("key encoding is not canonical")]
+    NonCanonical,
+}
+
+/// Reasons a serialized session state was rejected
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum DeserializeError {
+    /// The format version byte is not one this build understands
+    #[error("unsupported format version {0}")]
+    UnsupportedVersion(u8),
+    
+    /// The data ended before the named field was complete
+    #[error("truncated while reading {field}")]
+    Truncated {
+        /// Field being read when the data ran out
+        field: &'static str,
+    },
+    
+    /// The replay window is larger than this build allows
+    #[error("replay window size {size} exceeds maximum {max}")]
+    WindowTooLarge {
+        /// Size found in the data
+        size: usize,
+        /// Largest accepted size
+        max: usize,
+    },
+    
+    /// A record marked as required to understand has an unknown tag
+    #[error("unknown required record {0:#04x}")]
+    UnknownRecord(u8),
+    
+    /// The same record appears twice
+    #[error("duplicate record {0:#04x}")]
+    DuplicateRecord(u8),
+}
+
+impl From<NoiseError> for std::io::Error {
+    fn from(err: NoiseError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, err)
+    }
+}
+
 pub type Result<T> = std::result::Result<T, NoiseError>;
\ No newline at end of file
@@ -0,0 +1,259 @@
+//! Streaming file encryption under a session's keys
+//!
+//! Attachments can be far larger than a phone should hold in memory, and
+//! too large to send as [`NoiseSession::encrypt_large`] messages.
+//! [`SessionFileCipher`] derives a file key from the session with
+//! [`NoiseSession::export_key`], so both peers can encrypt and decrypt
+//! files without using up transport nonces, and works on any `Read` and
+//! `Write` a chunk at a time.
+//!
+//! # Format
+//!
+//! `magic (4) || salt (16)`, then chunks of
+//! `len (4 bytes BE) || XChaCha20-Poly1305 ciphertext`, then a 32-byte
+//! MAC chained over every chunk's ciphertext. Each chunk's associated data
+//! holds its index and whether it is the last, so chunks cannot be
+//! reordered, dropped or the stream cut short without detection. The salt
+//! gives every file its own key.
+
+use crate::core::crypto::{aead_open, aead_seal, constant_time_eq, hkdf, random_bytes};
+use crate::core::error::{NoiseError, Result};
+use crate::core::session::NoiseSession;
+use snow::params::HashChoice;
+use std::io::{self, Read, Write};
+use zeroize::Zeroizing;
+
+/// Default plaintext bytes per chunk
+pub const DEFAULT_FILE_CHUNK_LEN: usize = 64 * 1024;
+
+/// Largest plaintext chunk accepted when decrypting
+pub const MAX_FILE_CHUNK_LEN: usize = 1024 * 1024;
+
+/// Identifies the format and its version
+const FILE_MAGIC: &[u8; 4] = b"NMF1";
+
+const SALT_LEN: usize = 16;
+
+const MAC_LEN: usize = 32;
+
+/// XChaCha20-Poly1305 nonce and tag around each chunk
+const CHUNK_OVERHEAD: usize = 24 + 16;
+
+/// Exporter label for the secret behind every file key
+const FILE_EXPORT_LABEL: &str = "noise-mobile file cipher";
+
+const FILE_KEY_LABEL: &[u8] = b"noise-mobile/file/key/v1";
+
+const FILE_MAC_LABEL: &[u8] = b"noise-mobile/file/mac/v1";
+
+/// Encrypts and decrypts files for the two ends of a session
+pub struct SessionFileCipher {
+    secret: Zeroizing<Vec<u8>>,
+    chunk_len: usize,
+}
+
+/// Keys for a single file
+struct FileKeys {
+    key: Zeroizing<Vec<u8>>,
+    mac_key: Zeroizing<Vec<u8>>,
+}
+
+impl SessionFileCipher {
+    /// Derive a file cipher from a session that has finished its handshake
+    ///
+    /// Both peers derive the same cipher, so a file encrypted by one can be
+    /// decrypted by the other.
+    pub fn new(session: &NoiseSession) -> Result<Self> {
+        Ok(Self {
+            secret: Zeroizing::new(session.export_key(FILE_EXPORT_LABEL, &[], 32)?),
+            chunk_len: DEFAULT_FILE_CHUNK_LEN,
+        })
+    }
+    
+    /// Set the plaintext bytes per chunk, capped at [`MAX_FILE_CHUNK_LEN`]
+    pub fn with_chunk_len(mut self, chunk_len: usize) -> Self {
+        self.chunk_len = chunk_len.clamp(1, MAX_FILE_CHUNK_LEN);
+        self
+    }
+    
+    /// Encrypt everything `reader` yields into `writer`
+    ///
+    /// Returns the number of plaintext bytes encrypted.
+    pub fn encrypt(&self, mut reader: impl Read, mut writer: impl Write) -> io::Result<u64> {
+        let mut salt = [0u8; SALT_LEN];
+        random_bytes(&mut salt);
+        let keys = self.file_keys(&salt)?;
+        writer.write_all(FILE_MAGIC)?;
+        writer.write_all(&salt)?;
+        
+        // Read one chunk ahead to know which chunk is the last
+        let mut mac = Zeroizing::new(keys.mac_key.to_vec());
+        let mut current = Zeroizing::new(vec![0u8; self.chunk_len]);
+        let mut next = Zeroizing::new(vec![0u8; self.chunk_len]);
+        let mut current_len = read_full(&mut reader, &mut current)?;
+        let mut total = 0u64;
+        let mut index = 0u64;
+        loop {
+            let next_len = if current_len == self.chunk_len { read_full(&mut reader, &mut next)? } else { 0 };
+            let last = next_len == 0;
+            let sealed = aead_seal(&keys.key, &chunk_aad(index, last), &current[..current_len])?;
+            writer.write_all(&(sealed.len() as u32).to_be_bytes())?;
+            writer.write_all(&sealed)?;
+            chain_mac(&mut mac, &sealed)?;
+            total += current_len as u64;
+            if last {
+                break;
+            }
+            std::mem::swap(&mut current, &mut next);
+            current_len = next_len;
+            index += 1;
+        }
+        writer.write_all(&mac)?;
+        writer.flush()?;
+        Ok(total)
+    }
+    
+    /// Decrypt a stream from [`encrypt`](Self::encrypt) into `writer`
+    ///
+    /// Chunks are written as they authenticate, so on error the output
+    /// holds a prefix of the file and must be discarded. Returns the
+    /// number of plaintext bytes written.
+    pub fn decrypt(&self, mut reader: impl Read, mut writer: impl Write) -> io::Result<u64> {
+        let mut header = [0u8; FILE_MAGIC.len() + SALT_LEN];
+        reader.read_exact(&mut header).map_err(truncated)?;
+        let (magic, salt) = header.split_at(FILE_MAGIC.len());
+        if magic != FILE_MAGIC {
+            return Err(NoiseError::InvalidMessage.into());
+        }
+        let keys = self.file_keys(salt)?;
+        
+        let mut mac = Zeroizing::new(keys.mac_key.to_vec());
+        let mut total = 0u64;
+        let mut index = 0u64;
+        loop {
+            let mut len = [0u8; 4];
+            reader.read_exact(&mut len).map_err(truncated)?;
+            let len = u32::from_be_bytes(len) as usize;
+            if !(CHUNK_OVERHEAD..=MAX_FILE_CHUNK_LEN + CHUNK_OVERHEAD).contains(&len) {
+                return Err(NoiseError::InvalidMessage.into());
+            }
+            let mut sealed = vec![0u8; len];
+            reader.read_exact(&mut sealed).map_err(truncated)?;
+            
+            // The last flag is authenticated, so try the likelier one first
+            let (chunk, last) = match aead_open(&keys.key, &chunk_aad(index, false), &sealed) {
+                Ok(chunk) => (chunk, false),
+                Err(_) => (aead_open(&keys.key, &chunk_aad(index, true), &sealed)?, true),
+            };
+            let chunk = Zeroizing::new(chunk);
+            chain_mac(&mut mac, &sealed)?;
+            writer.write_all(&chunk)?;
+            total += chunk.len() as u64;
+            if last {
+                break;
+            }
+            index += 1;
+        }
+        
+        let mut expected = [0u8; MAC_LEN];
+        reader.read_exact(&mut expected).map_err(truncated)?;
+        if !constant_time_eq(&mac, &expected) {
+            return Err(NoiseError::DecryptionFailed.into());
+        }
+        writer.flush()?;
+        Ok(total)
+    }
+    
+    fn file_keys(&self, salt: &[u8]) -> Result<FileKeys> {
+        Ok(FileKeys {
+            key: Zeroizing::new(hkdf(HashChoice::SHA256, salt, &self.secret, FILE_KEY_LABEL, 32)?),
+            mac_key: Zeroizing::new(hkdf(HashChoice::SHA256, salt, &self.secret, FILE_MAC_LABEL, MAC_LEN)?),
+        })
+    }
+}
+
+/// `index (8 bytes BE) || last (1 byte)`
+fn chunk_aad(index: u64, last: bool) -> [u8; 9] {
+    let mut aad = [0u8; 9];
+    aad[..8].copy_from_slice(&index.to_be_bytes());
+    aad[8] = last as u8;
+    aad
+}
+
+/// Fold a chunk into the running stream MAC
+fn chain_mac(mac: &mut Zeroizing<Vec<u8>>, sealed: &[u8]) -> Result<()> {
+    *mac = Zeroizing::new(hkdf(HashChoice::SHA256, mac, sealed, FILE_MAC_LABEL, MAC_LEN)?);
+    Ok(())
+}
+
+/// Fill `buf` unless the reader runs out first, returning the bytes read
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// A stream that ends early is a bad message rather than an IO failure
+fn truncated(error: io::Error) -> io::Error {
+    if error.kind() == io::ErrorKind::UnexpectedEof {
+        NoiseError::InvalidMessage.into()
+    } else {
+        error
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::perform_handshake;
+    
+    fn ciphers() -> (SessionFileCipher, SessionFileCipher) {
+        let (initiator, responder) = perform_handshake().unwrap();
+        (
+            SessionFileCipher::new(&initiator).unwrap().with_chunk_len(1000),
+            SessionFileCipher::new(&responder).unwrap(),
+        )
+    }
+    
+    #[test]
+    fn test_roundtrip_between_peers() {
+        let (alice, bob) = ciphers();
+        for len in [0, 1, 999, 1000, 1001, 5000] {
+            let file: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let mut encrypted = Vec::new();
+            assert_eq!(alice.encrypt(&file[..], &mut encrypted).unwrap(), len as u64);
+            let mut decrypted = Vec::new();
+            assert_eq!(bob.decrypt(&encrypted[..], &mut decrypted).unwrap(), len as u64);
+            assert_eq!(decrypted, file);
+        }
+    }
+    
+    #[test]
+    fn test_tampering_and_truncation_detected() {
+        let (alice, bob) = ciphers();
+        let mut encrypted = Vec::new();
+        alice.encrypt(&[7u8; 2500][..], &mut encrypted).unwrap();
+        let chunk = 4 + 1000 + CHUNK_OVERHEAD;
+        let header = FILE_MAGIC.len() + SALT_LEN;
+        
+        let mut flipped = encrypted.clone();
+        flipped[header + 10] ^= 1;
+        let mut trailer = encrypted.clone();
+        *trailer.last_mut().unwrap() ^= 1;
+        let cut = &encrypted[..header + 2 * chunk];
+        for bad in [&flipped[..], &trailer[..], cut, &encrypted[..encrypted.len() - 1]] {
+            assert!(bob.decrypt(bad, &mut Vec::new()).is_err());
+        }
+        
+        // Another session's cipher cannot read it
+        let (other, _) = ciphers();
+        assert!(other.decrypt(&encrypted[..], &mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,582 @@
+//! Static identity keypairs
+//!
+//! Lets apps create and persist a Curve25519 identity before any session
+//! exists, then pass the private key to [`NoiseSession::with_private_key`].
+//!
+//! [`NoiseSession::with_private_key`]: crate::core::session::NoiseSession::with_private_key
+
+use crate::core::crypto::{constant_time_eq, random_bytes};
+use crate::core::error::{KeyError, NoiseError, Result};
+use crate::core::secret::SecretBytes;
+use curve25519_dalek::edwards::{CompressedEdwardsY, EdwardsPoint};
+use curve25519_dalek::montgomery::MontgomeryPoint;
+use curve25519_dalek::scalar::{clamp_integer, Scalar};
+use snow::params::{DHChoice, HashChoice};
+use snow::resolvers::{CryptoResolver, DefaultResolver};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// Length of Curve25519 private and public keys
+pub const KEY_LEN: usize = 32;
+
+/// Length of an XEdDSA signature
+pub const SIGNATURE_LEN: usize = 64;
+
+/// Characters per fingerprint group
+const FINGERPRINT_GROUP_LEN: usize = 4;
+
+/// Bytes of the SHA-256 digest kept by [`FingerprintFormat::Sha256`]
+const FINGERPRINT_DIGEST_LEN: usize = 16;
+
+/// RFC 4648 base32 alphabet
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// How [`fingerprint`] renders a public key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FingerprintFormat {
+    /// Lowercase hex of the whole key
+    Hex,
+    /// Unpadded RFC 4648 base32 of the whole key
+    Base32,
+    /// Lowercase hex of the first 16 bytes of the key's SHA-256 digest
+    Sha256,
+}
+
+/// A Curve25519 private key
+///
+/// Wiped when dropped, kept out of swap with the `mlock` feature, and
+/// redacted by `Debug`, so it cannot end up in a log by accident.
+#[derive(Clone, Zeroize)]
+pub struct SecretKey(SecretBytes);
+
+impl SecretKey {
+    /// Generate a key from the operating system CSPRNG
+    pub fn generate() -> Self {
+        let mut key = SecretBytes::zeroed(KEY_LEN);
+        random_bytes(&mut key);
+        Self(key)
+    }
+    
+    /// Copy a stored key, which must be [`KEY_LEN`] bytes
+    ///
+    /// The caller remains responsible for wiping its own copy.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != KEY_LEN {
+            return Err(NoiseError::InvalidParameter);
+        }
+        Ok(Self(SecretBytes::new(bytes)))
+    }
+    
+    /// The raw key, to hand to secure storage
+    pub fn as_bytes(&self) -> &[u8; KEY_LEN] {
+        // Every constructor checks the length
+        self.0[..].try_into().expect("secret key is KEY_LEN bytes")
+    }
+    
+    /// Derive the matching public key
+    pub fn public_key(&self) -> Result<PublicKey> {
+        public_from_private(&self.0[..]).map(PublicKey)
+    }
+}
+
+impl TryFrom<&[u8]> for SecretKey {
+    type Error = NoiseError;
+    
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        Self::from_bytes(bytes)
+    }
+}
+
+impl std::fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SecretKey([redacted])")
+    }
+}
+
+/// A Curve25519 public key
+///
+/// Compares in constant time, like [`keys_equal`].
+#[derive(Clone, Copy, Eq)]
+pub struct PublicKey([u8; KEY_LEN]);
+
+impl PublicKey {
+    /// Wrap a key received from a peer, which must be [`KEY_LEN`] bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        bytes.try_into().map(Self).map_err(|_| NoiseError::InvalidParameter)
+    }
+    
+    /// The raw key, to send to peers
+    pub fn as_bytes(&self) -> &[u8; KEY_LEN] {
+        &self.0
+    }
+    
+    /// Render the key for display, see [`fingerprint`]
+    pub fn fingerprint(&self, format: FingerprintFormat) -> Result<String> {
+        fingerprint(&self.0, format)
+    }
+}
+
+impl From<[u8; KEY_LEN]> for PublicKey {
+    fn from(bytes: [u8; KEY_LEN]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl TryFrom<&[u8]> for PublicKey {
+    type Error = NoiseError;
+    
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        Self::from_bytes(bytes)
+    }
+}
+
+impl AsRef<[u8]> for PublicKey {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl PartialEq for PublicKey {
+    fn eq(&self, other: &Self) -> bool {
+        keys_equal(&self.0, &other.0)
+    }
+}
+
+impl std::fmt::Debug for PublicKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PublicKey({})", hex(&self.0))
+    }
+}
+
+/// A Curve25519 static keypair
+///
+/// The private key is wiped when the keypair is dropped, and kept out of
+/// swap with the `mlock` feature.
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct Keypair {
+    private: SecretKey,
+    public: [u8; KEY_LEN],
+}
+
+impl Keypair {
+    /// Generate a keypair from the operating system CSPRNG
+    pub fn generate() -> Result<Self> {
+        Self::from_secret_key(SecretKey::generate())
+    }
+    
+    /// Rebuild a keypair from a stored private key
+    pub fn from_private(private_key: &[u8]) -> Result<Self> {
+        Self::from_secret_key(SecretKey::from_bytes(private_key)?)
+    }
+    
+    /// Rebuild a keypair around a private key
+    pub fn from_secret_key(private: SecretKey) -> Result<Self> {
+        let public = public_from_private(private.as_bytes())?;
+        Ok(Self { private, public })
+    }
+    
+    /// The private key, to pass to sessions
+    pub fn secret_key(&self) -> &SecretKey {
+        &self.private
+    }
+    
+    /// The private key, to persist in secure storage
+    pub fn private_key(&self) -> &[u8; KEY_LEN] {
+        self.private.as_bytes()
+    }
+    
+    /// The public key as a [`PublicKey`]
+    pub fn public(&self) -> PublicKey {
+        PublicKey(self.public)
+    }
+    
+    /// The public key, to share with peers
+    pub fn public_key(&self) -> &[u8; KEY_LEN] {
+        &self.public
+    }
+    
+    /// Sign `message` with the private key using XEdDSA
+    ///
+    /// Lets a Curve25519 identity vouch for data, e.g. its replacement key,
+    /// without a separate signing key. Check with [`verify_signature`].
+    pub fn sign(&self, message: &[u8]) -> Result<[u8; SIGNATURE_LEN]> {
+        // Force the Edwards form of the public key to a zero sign bit
+        let mut k = Scalar::from_bytes_mod_order(clamp_integer(*self.private_key()));
+        let public = EdwardsPoint::mul_base(&k).compress();
+        let mut a = if public.as_bytes()[31] & 0x80 != 0 { -k } else { k };
+        k.zeroize();
+        let mut public = public.to_bytes();
+        public[31] &= 0x7f;
+        
+        let mut nonce = [0u8; 64];
+        random_bytes(&mut nonce);
+        let mut prefix = [0xffu8; 32];
+        prefix[0] = 0xfe;
+        let mut r = Scalar::from_bytes_mod_order_wide(&sha512(&[&prefix, a.as_bytes(), message, &nonce])?);
+        nonce.zeroize();
+        
+        let r_point = EdwardsPoint::mul_base(&r).compress();
+        let h = Scalar::from_bytes_mod_order_wide(&sha512(&[r_point.as_bytes(), &public, message])?);
+        let s = r + h * a;
+        r.zeroize();
+        a.zeroize();
+        
+        let mut signature = [0u8; SIGNATURE_LEN];
+        signature[..32].copy_from_slice(r_point.as_bytes());
+        signature[32..].copy_from_slice(s.as_bytes());
+        Ok(signature)
+    }
+    
+    /// X25519 shared secret with a peer's public key
+    ///
+    /// Low-order public keys, which give an all-zero secret, are rejected.
+    #[cfg(feature = "ratchet")]
+    pub(crate) fn dh(&self, public_key: &[u8]) -> Result<[u8; KEY_LEN]> {
+        if public_key.len() != KEY_LEN {
+            return Err(NoiseError::InvalidParameter);
+        }
+        let mut dh = DefaultResolver
+            .resolve_dh(&DHChoice::Curve25519)
+            .ok_or_else(|| NoiseError::InvalidState("Curve25519 unavailable".to_string()))?;
+        dh.set(self.private.as_bytes());
+        let mut shared = [0u8; KEY_LEN];
+        dh.dh(public_key, &mut shared).map_err(|_| NoiseError::InvalidParameter)?;
+        if keys_equal(&shared, &[0u8; KEY_LEN]) {
+            return Err(NoiseError::InvalidParameter);
+        }
+        Ok(shared)
+    }
+}
+
+impl std::fmt::Debug for Keypair {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Keypair").field("public", &self.public).finish_non_exhaustive()
+    }
+}
+
+/// Derive the Curve25519 public key for a private key
+pub fn public_from_private(private_key: &[u8]) -> Result<[u8; KEY_LEN]> {
+    if private_key.len() != KEY_LEN {
+        return Err(NoiseError::InvalidParameter);
+    }
+    let mut dh = DefaultResolver
+        .resolve_dh(&DHChoice::Curve25519)
+        .ok_or_else(|| NoiseError::InvalidState("Curve25519 unavailable".to_string()))?;
+    dh.set(private_key);
+    dh.pubkey().try_into().map_err(|_| NoiseError::InvalidParameter)
+}
+
+/// Refuse private keys that would give a weak session
+///
+/// Rejects the all-zero key, which is what an unfilled buffer looks like,
+/// and keys whose public key is a low-order point. Every 32-byte key is
+/// clamped before use, so no other encoding is invalid.
+pub fn validate_private_key(private_key: &[u8]) -> Result<()> {
+    if private_key.len() != KEY_LEN {
+        return Err(NoiseError::InvalidParameter);
+    }
+    if keys_equal(private_key, &[0u8; KEY_LEN]) {
+        return Err(KeyError::AllZero.into());
+    }
+    let public = public_from_private(private_key)?;
+    if is_low_order(&public) {
+        return Err(KeyError::LowOrder.into());
+    }
+    Ok(())
+}
+
+/// Refuse public keys that are not canonical or have low order
+///
+/// A low-order key forces the Diffie-Hellman result into a handful of
+/// values, and a non-canonical one is a second encoding of some other key,
+/// which defeats pinning by byte comparison.
+pub fn validate_public_key(public_key: &[u8]) -> Result<()> {
+    let public: [u8; KEY_LEN] = public_key.try_into().map_err(|_| NoiseError::InvalidParameter)?;
+    if !is_canonical(&public) {
+        return Err(KeyError::NonCanonical.into());
+    }
+    if is_low_order(&public) {
+        return Err(KeyError::LowOrder.into());
+    }
+    Ok(())
+}
+
+/// Whether the u-coordinate is below 2^255 - 19 with the top bit clear
+fn is_canonical(public: &[u8; KEY_LEN]) -> bool {
+    // Little-endian 2^255 - 19 is 0xed, then thirty 0xff, then 0x7f
+    let at_least_p = public[31] == 0x7f && public[1..31].iter().all(|&b| b == 0xff) && public[0] >= 0xed;
+    public[31] & 0x80 == 0 && !at_least_p
+}
+
+/// Whether multiplying by the cofactor sends the point to the identity
+fn is_low_order(public: &[u8; KEY_LEN]) -> bool {
+    (MontgomeryPoint(*public) * Scalar::from(8u8)).to_bytes() == [0u8; KEY_LEN]
+}
+
+/// Check an XEdDSA signature made with [`Keypair::sign`]
+pub fn verify_signature(public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    let (Ok(public_key), Some((r_bytes, s_bytes))) = (
+        <[u8; KEY_LEN]>::try_from(public_key),
+        signature.split_first_chunk::<32>().filter(|(_, rest)| rest.len() == 32),
+    ) else {
+        return false;
+    };
+    // The Edwards conversion ignores the top bit and reduces mod p, so an
+    // alias of the signer's key would otherwise verify too
+    if !is_canonical(&public_key) {
+        return false;
+    }
+    let Some(a) = MontgomeryPoint(public_key).to_edwards(0) else {
+        return false;
+    };
+    let Ok(s_bytes) = <[u8; 32]>::try_from(s_bytes) else {
+        return false;
+    };
+    let Some(s) = Option::<Scalar>::from(Scalar::from_canonical_bytes(s_bytes)) else {
+        return false;
+    };
+    if CompressedEdwardsY(*r_bytes).decompress().is_none() {
+        return false;
+    }
+    
+    let Ok(digest) = sha512(&[r_bytes, a.compress().as_bytes(), message]) else {
+        return false;
+    };
+    let h = Scalar::from_bytes_mod_order_wide(&digest);
+    let expected = EdwardsPoint::vartime_double_scalar_mul_basepoint(&-h, &a, &s).compress();
+    keys_equal(expected.as_bytes(), r_bytes)
+}
+
+fn sha512(parts: &[&[u8]]) -> Result<[u8; 64]> {
+    let mut hash = DefaultResolver
+        .resolve_hash(&HashChoice::SHA512)
+        .ok_or_else(|| NoiseError::InvalidState("SHA-512 unavailable".to_string()))?;
+    for part in parts {
+        hash.input(part);
+    }
+    let mut digest = [0u8; 64];
+    hash.result(&mut digest);
+    Ok(digest)
+}
+
+/// Render a public key for display, in groups of four characters
+///
+/// Every app using this library shows the same string for the same key, so
+/// users can compare fingerprints across platforms.
+pub fn fingerprint(public_key: &[u8], format: FingerprintFormat) -> Result<String> {
+    if public_key.is_empty() {
+        return Err(NoiseError::InvalidParameter);
+    }
+    let ungrouped = match format {
+        FingerprintFormat::Hex => hex(public_key),
+        FingerprintFormat::Base32 => base32(public_key),
+        FingerprintFormat::Sha256 => {
+            let mut hash = DefaultResolver
+                .resolve_hash(&HashChoice::SHA256)
+                .ok_or_else(|| NoiseError::InvalidState("SHA-256 unavailable".to_string()))?;
+            let mut digest = [0u8; 32];
+            hash.input(public_key);
+            hash.result(&mut digest);
+            hex(&digest[..FINGERPRINT_DIGEST_LEN])
+        }
+    };
+    Ok(group(&ungrouped))
+}
+
+/// Compare two keys without leaking where they differ through timing
+///
+/// Same as [`constant_time_eq`], under the name key-handling code reads best with.
+pub fn keys_equal(a: &[u8], b: &[u8]) -> bool {
+    constant_time_eq(a, b)
+}
+
+/// Compare two rendered fingerprints in constant time
+///
+/// Ignores case, whitespace and `:`/`-` separators, so a fingerprint typed
+/// back by a user still matches.
+pub fn fingerprints_equal(a: &str, b: &str) -> bool {
+    keys_equal(&normalize_fingerprint(a), &normalize_fingerprint(b))
+}
+
+fn normalize_fingerprint(fingerprint: &str) -> Vec<u8> {
+    fingerprint
+        .bytes()
+        .filter(|b| !b.is_ascii_whitespace() && *b != b':' && *b != b'-')
+        .map(|b| b.to_ascii_lowercase())
+        .collect()
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn base32(data: &[u8]) -> String {
+    let mut output = String::with_capacity(data.len().div_ceil(5) * 8);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for &byte in data {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            output.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        output.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    output
+}
+
+fn group(ungrouped: &str) -> String {
+    // Both encodings are ASCII, so every group boundary is a char boundary
+    ungrouped
+        .as_bytes()
+        .chunks(FINGERPRINT_GROUP_LEN)
+        .map(|chunk| std::str::from_utf8(chunk).unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::session::NoiseSession;
+    
+    #[test]
+    fn test_generate_and_rebuild() {
+        let keypair = Keypair::generate().unwrap();
+        assert_ne!(keypair.private_key(), &[0u8; KEY_LEN]);
+        
+        let rebuilt = Keypair::from_private(keypair.private_key()).unwrap();
+        assert_eq!(rebuilt.public_key(), keypair.public_key());
+        assert!(Keypair::from_private(&[0u8; 16]).is_err());
+        assert!(!format!("{:?}", keypair).contains("private"));
+    }
+    
+    #[test]
+    fn test_key_newtypes() {
+        let secret = SecretKey::from_bytes(&[0x42u8; KEY_LEN]).unwrap();
+        assert_eq!(format!("{:?}", secret), "SecretKey([redacted])");
+        assert!(SecretKey::from_bytes(&[0x42u8; 16]).is_err());
+        
+        let keypair = Keypair::from_secret_key(secret.clone()).unwrap();
+        assert_eq!(keypair.secret_key().as_bytes(), secret.as_bytes());
+        assert_eq!(secret.public_key().unwrap(), keypair.public());
+        
+        let public = PublicKey::try_from(&keypair.public_key()[..]).unwrap();
+        assert_eq!(public.as_bytes(), keypair.public_key());
+        assert_eq!(format!("{:?}", public), format!("PublicKey({})", hex(keypair.public_key())));
+        assert!(PublicKey::from_bytes(&[1u8; 31]).is_err());
+    }
+    
+    #[test]
+    fn test_public_key_matches_handshake() {
+        let keypair = Keypair::generate().unwrap();
+        let mut initiator = NoiseSession::with_private_key(keypair.secret_key(), true).unwrap();
+        let mut responder = NoiseSession::new_responder().unwrap();
+        
+        let msg1 = initiator.write_message(&[]).unwrap();
+        responder.read_message(&msg1).unwrap();
+        let msg2 = responder.write_message(&[]).unwrap();
+        initiator.read_message(&msg2).unwrap();
+        let msg3 = initiator.write_message(&[]).unwrap();
+        responder.read_message(&msg3).unwrap();
+        
+        assert_eq!(responder.get_remote_static(), Some(&keypair.public_key()[..]));
+        assert_eq!(responder.remote_public_key(), Some(keypair.public()));
+    }
+    
+    #[test]
+    fn test_fingerprint_formats() {
+        let key: Vec<u8> = (0u8..32).collect();
+        assert_eq!(
+            fingerprint(&key, FingerprintFormat::Hex).unwrap(),
+            "0001 0203 0405 0607 0809 0a0b 0c0d 0e0f 1011 1213 1415 1617 1819 1a1b 1c1d 1e1f"
+        );
+        // RFC 4648 test vector
+        assert_eq!(fingerprint(b"foobar", FingerprintFormat::Base32).unwrap(), "MZXW 6YTB OI");
+        // SHA-256("abc") starts ba7816bf8f01cfea414140de5dae2223
+        assert_eq!(
+            fingerprint(b"abc", FingerprintFormat::Sha256).unwrap(),
+            "ba78 16bf 8f01 cfea 4141 40de 5dae 2223"
+        );
+        assert!(fingerprint(&[], FingerprintFormat::Hex).is_err());
+    }
+    
+    #[test]
+    fn test_key_validation() {
+        let keypair = Keypair::generate().unwrap();
+        assert!(validate_private_key(keypair.private_key()).is_ok());
+        assert!(validate_public_key(keypair.public_key()).is_ok());
+        assert!(matches!(validate_private_key(&[0u8; KEY_LEN]), Err(NoiseError::InvalidKey(KeyError::AllZero))));
+        assert!(matches!(validate_private_key(&[1u8; 16]), Err(NoiseError::InvalidParameter)));
+        
+        // Zero, one and the order-8 points from the Curve25519 paper
+        let mut order_eight = [0u8; KEY_LEN];
+        order_eight[..8].copy_from_slice(&[0xe0, 0xeb, 0x7a, 0x7c, 0x3b, 0x41, 0xb8, 0xae]);
+        order_eight[8..].copy_from_slice(&[
+            0x16, 0x56, 0xe3, 0xfa, 0xf1, 0x9f, 0xc4, 0x6a, 0xda, 0x09, 0x8d, 0xeb, 0x9c, 0x32, 0xb1, 0xfd,
+            0x86, 0x62, 0x05, 0x16, 0x5f, 0x49, 0xb8, 0x00,
+        ]);
+        let mut one = [0u8; KEY_LEN];
+        one[0] = 1;
+        for key in [[0u8; KEY_LEN], one, order_eight] {
+            assert!(matches!(validate_public_key(&key), Err(NoiseError::InvalidKey(KeyError::LowOrder))));
+        }
+        
+        // p + 1 and a set top bit encode keys that already have a shorter form
+        let mut p_plus_one = [0xffu8; KEY_LEN];
+        p_plus_one[0] = 0xee;
+        p_plus_one[31] = 0x7f;
+        let mut high_bit = *keypair.public_key();
+        high_bit[31] |= 0x80;
+        for key in [p_plus_one, high_bit] {
+            assert!(matches!(validate_public_key(&key), Err(NoiseError::InvalidKey(KeyError::NonCanonical))));
+        }
+    }
+    
+    #[test]
+    fn test_constant_time_comparison() {
+        assert!(keys_equal(&[1, 2, 3], &[1, 2, 3]));
+        assert!(!keys_equal(&[1, 2, 3], &[1, 2, 4]));
+        assert!(!keys_equal(&[1, 2, 3], &[1, 2]));
+        
+        let key = [9u8; 32];
+        let rendered = fingerprint(&key, FingerprintFormat::Sha256).unwrap();
+        assert!(fingerprints_equal(&rendered, &rendered.to_uppercase().replace(' ', ":")));
+        assert!(!fingerprints_equal(&rendered, &fingerprint(&[8u8; 32], FingerprintFormat::Sha256).unwrap()));
+    }
+    
+    #[test]
+    fn test_xeddsa_signatures() {
+        let keypair = Keypair::generate().unwrap();
+        let signature = keypair.sign(b"new key").unwrap();
+        assert!(verify_signature(keypair.public_key(), b"new key", &signature));
+        assert!(!verify_signature(keypair.public_key(), b"other key", &signature));
+        assert!(!verify_signature(Keypair::generate().unwrap().public_key(), b"new key", &signature));
+        
+        let mut tampered = signature;
+        tampered[40] ^= 1;
+        assert!(!verify_signature(keypair.public_key(), b"new key", &tampered));
+        assert!(!verify_signature(keypair.public_key(), b"new key", &signature[..63]));
+        
+        // Signatures are randomized but all verify
+        assert_ne!(keypair.sign(b"new key").unwrap(), signature);
+    }
+    
+    #[test]
+    fn test_signatures_reject_non_canonical_keys() {
+        let keypair = Keypair::generate().unwrap();
+        let signature = keypair.sign(b"new key").unwrap();
+        
+        let mut high_bit = *keypair.public_key();
+        high_bit[31] |= 0x80;
+        assert!(!verify_signature(&high_bit, b"new key", &signature));
+        
+        // p itself, a second encoding of zero
+        let mut p = [0xffu8; KEY_LEN];
+        p[0] = 0xed;
+        p[31] = 0x7f;
+        assert!(!verify_signature(&p, b"new key", &signature));
+    }
+}
@@ -0,0 +1,291 @@
+//! Handshakes compatible with libp2p's `/noise` security protocol
+//!
+//! [`Libp2pHandshake`] runs `Noise_XX_25519_ChaChaPoly_SHA256` the way
+//! rust-libp2p and go-libp2p do, so the resulting [`NoiseSession`] can talk
+//! to their peers directly:
+//!
+//! - The second and third handshake messages carry a protobuf
+//!   `NoiseHandshakePayload` with the sender's Ed25519 identity key and its
+//!   signature over `"noise-libp2p-static-key:" || static key`. The first
+//!   message carries no payload.
+//! - Every message, handshake and transport alike, is sent with a 2-byte
+//!   big-endian length prefix. That is the framing of
+//!   [`FrameCodec`](crate::mobile::framing::FrameCodec), so run the stream
+//!   through it and pass the messages it yields here.
+//!
+//! The identity is a [`SigningIdentity`]; the peer ID derived from it with
+//! [`peer_id`] is the one libp2p shows for the same key. Only Ed25519
+//! identities are supported. Payload extensions (WebTransport certificate
+//! hashes, stream muxers) are skipped when received and never sent.
+
+use crate::core::error::{NoiseError, Result};
+use crate::core::keys::SecretKey;
+use crate::core::session::NoiseSession;
+use crate::core::signing::{SigningIdentity, IDENTITY_KEY_LEN, IDENTITY_SIGNATURE_LEN};
+use ed25519_dalek::{Signature, VerifyingKey};
+
+/// Protocol libp2p runs under `/noise`
+pub const LIBP2P_NOISE_PARAMS: &str = "Noise_XX_25519_ChaChaPoly_SHA256";
+
+/// Protocol ID negotiated by multistream-select
+pub const LIBP2P_PROTOCOL_ID: &str = "/noise";
+
+/// Prefix of the message identity keys sign
+const STATIC_KEY_PREFIX: &[u8] = b"noise-libp2p-static-key:";
+
+/// `KeyType.Ed25519` in libp2p's `PublicKey` protobuf
+const KEY_TYPE_ED25519: u64 = 1;
+
+/// Multihash code of the identity hash used for short keys
+const MULTIHASH_IDENTITY: u8 = 0x00;
+
+/// An XX handshake exchanging libp2p identities
+pub struct Libp2pHandshake {
+    session: NoiseSession,
+    is_initiator: bool,
+    /// Our signed identity, sent in the second or third message
+    payload: Vec<u8>,
+    /// Handshake messages written and read so far
+    messages: usize,
+    remote_identity: Option<[u8; IDENTITY_KEY_LEN]>,
+}
+
+impl Libp2pHandshake {
+    /// Start as the dialer
+    pub fn new_initiator(static_key: &SecretKey, identity: &SigningIdentity) -> Result<Self> {
+        Self::new(static_key, identity, true)
+    }
+    
+    /// Start as the listener
+    pub fn new_responder(static_key: &SecretKey, identity: &SigningIdentity) -> Result<Self> {
+        Self::new(static_key, identity, false)
+    }
+    
+    fn new(static_key: &SecretKey, identity: &SigningIdentity, is_initiator: bool) -> Result<Self> {
+        let session = NoiseSession::with_protocol(LIBP2P_NOISE_PARAMS, static_key.as_bytes(), None, is_initiator, &[])?;
+        let local_static = session.get_local_static().ok_or(NoiseError::InvalidParameter)?;
+        let signature = identity.sign(&[STATIC_KEY_PREFIX, local_static].concat());
+        Ok(Self {
+            payload: encode_payload(&identity.public_key(), &signature),
+            session,
+            is_initiator,
+            messages: 0,
+            remote_identity: None,
+        })
+    }
+    
+    /// Check if the handshake is complete
+    pub fn is_handshake_finished(&self) -> bool {
+        self.session.is_transport_state()
+    }
+    
+    /// The peer's Ed25519 identity key, once its signature has been checked
+    pub fn remote_identity(&self) -> Option<[u8; IDENTITY_KEY_LEN]> {
+        self.remote_identity
+    }
+    
+    /// The peer's libp2p peer ID, once its identity has been checked
+    pub fn remote_peer_id(&self) -> Option<Vec<u8>> {
+        self.remote_identity.as_ref().map(peer_id)
+    }
+    
+    /// Write the next handshake message, without its length prefix
+    pub fn write_message(&mut self) -> Result<Vec<u8>> {
+        let payload = if self.messages == 0 { &[][..] } else { &self.payload[..] };
+        let message = self.session.write_message(payload)?;
+        self.messages += 1;
+        Ok(message)
+    }
+    
+    /// Read the peer's next handshake message, without its length prefix
+    ///
+    /// Fails if the peer's identity payload is missing or its signature
+    /// does not cover the static key the peer used in the handshake.
+    pub fn read_message(&mut self, message: &[u8]) -> Result<()> {
+        let payload = self.session.read_message(message)?;
+        self.messages += 1;
+        // The responder's first read is the dialer's bare opening
+        if self.is_initiator || self.messages > 1 {
+            let remote_static = self.session.revealed_remote_static().ok_or(NoiseError::HandshakeFailed)?;
+            self.remote_identity = Some(verify_payload(&payload, remote_static)?);
+        }
+        Ok(())
+    }
+    
+    /// Take the established session
+    pub fn into_session(self) -> Result<NoiseSession> {
+        match self.remote_identity {
+            Some(_) if self.session.is_transport_state() => Ok(self.session),
+            _ => Err(NoiseError::InvalidState("Handshake not complete".to_string())),
+        }
+    }
+}
+
+/// The binary libp2p peer ID of an Ed25519 identity key
+///
+/// Short keys are embedded whole under the identity multihash, so this is
+/// the protobuf-encoded public key behind a two-byte multihash header.
+/// Base58 encoding gives the familiar `12D3KooW...` form.
+pub fn peer_id(identity_key: &[u8; IDENTITY_KEY_LEN]) -> Vec<u8> {
+    let public_key = encode_public_key(identity_key);
+    let mut id = vec![MULTIHASH_IDENTITY, public_key.len() as u8];
+    id.extend_from_slice(&public_key);
+    id
+}
+
+/// `PublicKey { Type = Ed25519, Data = key }`
+fn encode_public_key(identity_key: &[u8; IDENTITY_KEY_LEN]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(4 + IDENTITY_KEY_LEN);
+    write_varint(&mut out, 1 << 3);
+    write_varint(&mut out, KEY_TYPE_ED25519);
+    write_bytes_field(&mut out, 2, identity_key);
+    out
+}
+
+/// `NoiseHandshakePayload { identity_key = 1, identity_sig = 2 }`
+fn encode_payload(identity_key: &[u8; IDENTITY_KEY_LEN], signature: &[u8; IDENTITY_SIGNATURE_LEN]) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_bytes_field(&mut out, 1, &encode_public_key(identity_key));
+    write_bytes_field(&mut out, 2, signature);
+    out
+}
+
+/// Check a peer's handshake payload against the static key it used
+fn verify_payload(payload: &[u8], remote_static: &[u8]) -> Result<[u8; IDENTITY_KEY_LEN]> {
+    let (mut public_key, mut signature) = (None, None);
+    for (field, value) in protobuf_fields(payload)? {
+        match (field, value) {
+            (1, Some(value)) => public_key = Some(value),
+            (2, Some(value)) => signature = Some(value),
+            _ => {}
+        }
+    }
+    let (Some(public_key), Some(signature)) = (public_key, signature) else {
+        return Err(NoiseError::HandshakeFailed);
+    };
+    
+    let key_data = protobuf_fields(public_key)?
+        .into_iter()
+        .find_map(|(field, value)| if field == 2 { value } else { None });
+    // Only an Ed25519 key re-encodes to exactly the bytes that were sent
+    let identity_key: [u8; IDENTITY_KEY_LEN] = key_data
+        .and_then(|data| data.try_into().ok())
+        .filter(|key| encode_public_key(key) == public_key)
+        .ok_or(NoiseError::HandshakeFailed)?;
+    let signature: [u8; IDENTITY_SIGNATURE_LEN] = signature.try_into().map_err(|_| NoiseError::HandshakeFailed)?;
+    
+    let verifying_key = VerifyingKey::from_bytes(&identity_key).map_err(|_| NoiseError::HandshakeFailed)?;
+    verifying_key
+        .verify_strict(&[STATIC_KEY_PREFIX, remote_static].concat(), &Signature::from_bytes(&signature))
+        .map_err(|_| NoiseError::HandshakeFailed)?;
+    Ok(identity_key)
+}
+
+/// Split a protobuf message into `(field, bytes)` pairs
+///
+/// Length-delimited fields come with their bytes, every other wire type
+/// with `None`.
+fn protobuf_fields(mut data: &[u8]) -> Result<Vec<(u64, Option<&[u8]>)>> {
+    let mut fields = Vec::new();
+    while !data.is_empty() {
+        let key = read_varint(&mut data)?;
+        let value = match key & 7 {
+            0 => {
+                read_varint(&mut data)?;
+                None
+            }
+            1 | 5 => {
+                let len = if key & 7 == 1 { 8 } else { 4 };
+                data = data.get(len..).ok_or(NoiseError::InvalidMessage)?;
+                None
+            }
+            2 => {
+                let len = usize::try_from(read_varint(&mut data)?).map_err(|_| NoiseError::InvalidMessage)?;
+                let value = data.get(..len).ok_or(NoiseError::InvalidMessage)?;
+                data = &data[len..];
+                Some(value)
+            }
+            _ => return Err(NoiseError::InvalidMessage),
+        };
+        fields.push((key >> 3, value));
+    }
+    Ok(fields)
+}
+
+fn write_bytes_field(out: &mut Vec<u8>, field: u64, value: &[u8]) {
+    write_varint(out, (field << 3) | 2);
+    write_varint(out, value.len() as u64);
+    out.extend_from_slice(value);
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = data.split_first().ok_or(NoiseError::InvalidMessage)?;
+        *data = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(NoiseError::InvalidMessage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_handshake_exchanges_identities() {
+        let (dialer_id, listener_id) = (SigningIdentity::generate(), SigningIdentity::generate());
+        let mut dialer = Libp2pHandshake::new_initiator(&SecretKey::generate(), &dialer_id).unwrap();
+        let mut listener = Libp2pHandshake::new_responder(&SecretKey::generate(), &listener_id).unwrap();
+        
+        let msg1 = dialer.write_message().unwrap();
+        assert_eq!(msg1.len(), 32);
+        listener.read_message(&msg1).unwrap();
+        assert!(listener.remote_identity().is_none());
+        dialer.read_message(&listener.write_message().unwrap()).unwrap();
+        assert_eq!(dialer.remote_identity(), Some(listener_id.public_key()));
+        listener.read_message(&dialer.write_message().unwrap()).unwrap();
+        assert_eq!(listener.remote_peer_id(), Some(peer_id(&dialer_id.public_key())));
+        
+        let mut dialer = dialer.into_session().unwrap();
+        let mut listener = listener.into_session().unwrap();
+        assert_eq!(listener.decrypt(&dialer.encrypt(b"/yamux/1.0.0").unwrap()).unwrap(), b"/yamux/1.0.0");
+    }
+    
+    #[test]
+    fn test_payload_must_sign_the_static_key() {
+        let identity = SigningIdentity::generate();
+        let static_key = [9u8; 32];
+        let signature = identity.sign(&[STATIC_KEY_PREFIX, &static_key[..]].concat());
+        let payload = encode_payload(&identity.public_key(), &signature);
+        assert_eq!(verify_payload(&payload, &static_key).unwrap(), identity.public_key());
+        
+        // Unknown fields such as extensions are skipped
+        let mut extended = payload.clone();
+        write_bytes_field(&mut extended, 4, &[0x0a, 0x00]);
+        assert!(verify_payload(&extended, &static_key).is_ok());
+        
+        assert!(verify_payload(&payload, &[8u8; 32]).is_err());
+        assert!(verify_payload(&payload[..payload.len() - 1], &static_key).is_err());
+        assert!(verify_payload(&[], &static_key).is_err());
+    }
+    
+    #[test]
+    fn test_peer_id_encoding() {
+        let id = peer_id(&[7u8; IDENTITY_KEY_LEN]);
+        // Identity multihash of PublicKey { Type: Ed25519, Data: key }
+        assert_eq!(&id[..6], &[0x00, 0x24, 0x08, 0x01, 0x12, 0x20]);
+        assert_eq!(&id[6..], &[7u8; IDENTITY_KEY_LEN]);
+    }
+}
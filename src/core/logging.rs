@@ -0,0 +1,191 @@
+//! Diagnostic logging routed to the host app
+//!
+//! The library logs handshake outcomes, decryption failures, rekeys and
+//! session management decisions. Records go to the handler installed with
+//! [`set_log_handler`], which on mobile is usually `noise_set_log_callback`
+//! forwarding to os_log or logcat, and with the `tracing` feature also to
+//! the `tracing` subscriber under the `noise_mobile` target.
+//!
+//! Messages never carry keys, plaintext or handshake hashes. As a second
+//! line of defence every message is passed through [`scrub`] before it
+//! leaves the library, so a long hex or base64 run that slipped in is
+//! redacted.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, RwLock};
+
+/// Shortest hex run treated as key material: 16 bytes
+const MIN_HEX_SECRET_LEN: usize = 32;
+
+/// Shortest base64 run treated as key material: 24 bytes
+const MIN_BASE64_SECRET_LEN: usize = 32;
+
+/// Replacement for scrubbed runs
+const REDACTED: &str = "[redacted]";
+
+/// Severity of a log record, most severe first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LogLevel {
+    /// An operation failed in a way the app should know about
+    Error = 1,
+    /// Something unexpected that the library recovered from
+    Warn = 2,
+    /// Session lifecycle: handshakes, rekeys, expiry
+    Info = 3,
+    /// Per-message detail such as rejected ciphertexts
+    Debug = 4,
+    /// Everything else
+    Trace = 5,
+}
+
+impl LogLevel {
+    /// The level for a numeric value from 1 (error) to 5 (trace)
+    pub fn from_u8(level: u8) -> Option<Self> {
+        match level {
+            1 => Some(Self::Error),
+            2 => Some(Self::Warn),
+            3 => Some(Self::Info),
+            4 => Some(Self::Debug),
+            5 => Some(Self::Trace),
+            _ => None,
+        }
+    }
+}
+
+type LogHandler = Arc<dyn Fn(LogLevel, &str, &str) + Send + Sync>;
+
+/// Most verbose level the handler wants, 0 with no handler
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(0);
+
+static HANDLER: RwLock<Option<LogHandler>> = RwLock::new(None);
+
+/// Send records up to `max_level` to `handler` as `(level, target, message)`
+///
+/// Replaces any handler installed before. The handler may be called from
+/// any thread and must not log back into the library.
+pub fn set_log_handler(max_level: LogLevel, handler: impl Fn(LogLevel, &str, &str) + Send + Sync + 'static) {
+    *HANDLER.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(Arc::new(handler));
+    MAX_LEVEL.store(max_level as u8, Ordering::Release);
+}
+
+/// Stop sending records to the installed handler
+pub fn clear_log_handler() {
+    MAX_LEVEL.store(0, Ordering::Release);
+    *HANDLER.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = None;
+}
+
+/// Whether a record at `level` would go anywhere
+pub(crate) fn enabled(level: LogLevel) -> bool {
+    level as u8 <= MAX_LEVEL.load(Ordering::Acquire) || tracing_enabled(level)
+}
+
+/// Scrub and deliver a record; use [`log_event!`] rather than calling this
+pub(crate) fn log(level: LogLevel, target: &str, args: fmt::Arguments<'_>) {
+    let message = args.to_string();
+    let message = scrub(&message);
+    if level as u8 <= MAX_LEVEL.load(Ordering::Acquire) {
+        let handler = HANDLER.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+        if let Some(handler) = handler {
+            handler(level, target, &message);
+        }
+    }
+    emit_tracing(level, target, &message);
+}
+
+/// Redact runs of hex or base64 long enough to be key material
+pub fn scrub(message: &str) -> Cow<'_, str> {
+    let mut scrubbed = String::new();
+    let mut copied = 0;
+    let mut start = None;
+    // A trailing space ends a run at the end of the message
+    for (i, c) in message.char_indices().chain([(message.len(), ' ')]) {
+        let in_run = c.is_ascii_alphanumeric() || matches!(c, '+' | '/' | '=' | '-' | '_');
+        match (start, in_run) {
+            (None, true) => start = Some(i),
+            (Some(run_start), false) => {
+                if is_secret_like(&message[run_start..i]) {
+                    scrubbed.push_str(&message[copied..run_start]);
+                    scrubbed.push_str(REDACTED);
+                    copied = i;
+                }
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if copied == 0 {
+        return Cow::Borrowed(message);
+    }
+    scrubbed.push_str(&message[copied..]);
+    Cow::Owned(scrubbed)
+}
+
+fn is_secret_like(run: &str) -> bool {
+    let hex = run.len() >= MIN_HEX_SECRET_LEN && run.bytes().all(|b| b.is_ascii_hexdigit());
+    // Words and identifiers rarely mix cases and digits over this length
+    let base64 = run.len() >= MIN_BASE64_SECRET_LEN
+        && run.bytes().any(|b| b.is_ascii_digit())
+        && run.bytes().any(|b| b.is_ascii_uppercase())
+        && run.bytes().any(|b| b.is_ascii_lowercase());
+    hex || base64
+}
+
+#[cfg(feature = "tracing")]
+fn tracing_enabled(level: LogLevel) -> bool {
+    match level {
+        LogLevel::Error => tracing::enabled!(target: "noise_mobile", tracing::Level::ERROR),
+        LogLevel::Warn => tracing::enabled!(target: "noise_mobile", tracing::Level::WARN),
+        LogLevel::Info => tracing::enabled!(target: "noise_mobile", tracing::Level::INFO),
+        LogLevel::Debug => tracing::enabled!(target: "noise_mobile", tracing::Level::DEBUG),
+        LogLevel::Trace => tracing::enabled!(target: "noise_mobile", tracing::Level::TRACE),
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+fn tracing_enabled(_level: LogLevel) -> bool {
+    false
+}
+
+#[cfg(feature = "tracing")]
+fn emit_tracing(level: LogLevel, module: &str, message: &str) {
+    match level {
+        LogLevel::Error => tracing::error!(target: "noise_mobile", module, "{message}"),
+        LogLevel::Warn => tracing::warn!(target: "noise_mobile", module, "{message}"),
+        LogLevel::Info => tracing::info!(target: "noise_mobile", module, "{message}"),
+        LogLevel::Debug => tracing::debug!(target: "noise_mobile", module, "{message}"),
+        LogLevel::Trace => tracing::trace!(target: "noise_mobile", module, "{message}"),
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+fn emit_tracing(_level: LogLevel, _module: &str, _message: &str) {}
+
+/// Log a record if anything listens at its level, formatting lazily
+macro_rules! log_event {
+    ($level:expr, $($arg:tt)+) => {
+        if $crate::core::logging::enabled($level) {
+            $crate::core::logging::log($level, module_path!(), format_args!($($arg)+));
+        }
+    };
+}
+
+pub(crate) use log_event;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_scrub_redacts_key_material() {
+        let key = "3b6a27bcceb6a42d62a3a8d02a6f0d73653215771de243a63ac048a18b59da29";
+        assert_eq!(scrub(&format!("static key {key} rejected")), "static key [redacted] rejected");
+        assert_eq!(scrub("key: q83vEjRWeJCrze8SNFZ4kKvN7xI0VniQq83vEjRWeJA="), "key: [redacted]");
+        
+        // Ordinary messages pass through untouched
+        let plain = "Handshake with peer alice-phone completed after 3 messages (nonce 18446744073709551615)";
+        assert!(matches!(scrub(plain), Cow::Borrowed(_)));
+        assert_eq!(scrub(""), "");
+    }
+}
@@ -1,3 +1,35 @@
 pub mod error;
+pub mod logging;
 pub mod session;
-pub mod crypto;
\ No newline at end of file
+pub mod buffer_pool;
+pub mod crypto;
+pub mod keys;
+pub mod secret;
+pub mod payload;
+pub mod compression;
+#[cfg(feature = "file-cipher")]
+pub mod file_cipher;
+pub mod padding;
+pub mod signing;
+pub mod protocols;
+pub mod provider;
+pub mod verification;
+pub mod pipes;
+pub mod libp2p;
+pub mod negotiation;
+pub mod early_data;
+#[cfg(feature = "tickets")]
+pub mod tickets;
+#[cfg(feature = "sealed")]
+pub mod sealed;
+#[cfg(feature = "sealed")]
+pub mod envelope;
+#[cfg(feature = "test-utils")]
+pub mod test_vectors;
+
+/// Milliseconds since the Unix epoch, 0 if the clock is set before it
+pub(crate) fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis().try_into().unwrap_or(u64::MAX))
+}
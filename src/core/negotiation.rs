@@ -0,0 +1,192 @@
+//! Protocol version negotiation bound into the handshake
+//!
+//! Before the handshake the initiator sends a [`NegotiationOffer`] naming
+//! the versions (or patterns) it supports, most preferred first, and the
+//! responder answers with the first one it also supports. Neither message
+//! is protected, so on its own an active attacker could strip the newer
+//! versions from the offer and force both sides onto an older one.
+//!
+//! To stop that, both sides use the whole exchange as the handshake
+//! prologue ([`Agreement::prologue`]). The prologue is mixed into the
+//! handshake hash, so if either message was altered in transit the two
+//! sides disagree on it and the handshake fails instead of completing on a
+//! downgraded version.
+//!
+//! Layout:
+//!
+//! ```text
+//! offer: version: u8 | count: u8 | count * (len: u8 | utf-8 name)
+//! reply: version: u8 | index of the chosen name in the offer: u8
+//! ```
+
+use crate::core::error::{NoiseError, Result};
+
+/// Negotiation message format version
+const NEGOTIATION_VERSION: u8 = 1;
+
+/// Domain separator at the front of the prologue
+const PROLOGUE_LABEL: &[u8] = b"noise-mobile/negotiation/v1";
+
+/// Most names an offer can carry
+pub const MAX_OFFERED: usize = u8::MAX as usize;
+
+/// Longest name in an offer, in bytes
+pub const MAX_NAME_LEN: usize = u8::MAX as usize;
+
+/// The initiator's side of a negotiation, waiting for the reply
+pub struct NegotiationOffer {
+    names: Vec<String>,
+    message: Vec<u8>,
+}
+
+impl NegotiationOffer {
+    /// Offer `supported`, most preferred first, returning the message to send
+    pub fn new(supported: &[&str]) -> Result<(Self, Vec<u8>)> {
+        if supported.is_empty()
+            || supported.len() > MAX_OFFERED
+            || supported.iter().any(|name| name.is_empty() || name.len() > MAX_NAME_LEN)
+        {
+            return Err(NoiseError::InvalidParameter);
+        }
+        let mut message = vec![NEGOTIATION_VERSION, supported.len() as u8];
+        for name in supported {
+            message.push(name.len() as u8);
+            message.extend_from_slice(name.as_bytes());
+        }
+        let offer = Self {
+            names: supported.iter().map(|name| name.to_string()).collect(),
+            message: message.clone(),
+        };
+        Ok((offer, message))
+    }
+    
+    /// Read the responder's reply
+    pub fn complete(self, reply: &[u8]) -> Result<Agreement> {
+        let &[NEGOTIATION_VERSION, index] = reply else {
+            return Err(NoiseError::InvalidMessage);
+        };
+        let chosen = self.names.get(index as usize).ok_or(NoiseError::InvalidMessage)?.clone();
+        Ok(Agreement::new(chosen, &self.message, reply))
+    }
+}
+
+/// Answer an offer with the first offered name found in `supported`
+///
+/// Returns the agreement and the reply to send. Fails with
+/// [`NoiseError::HandshakeFailed`] if nothing offered is supported.
+pub fn select(offer: &[u8], supported: &[&str]) -> Result<(Agreement, Vec<u8>)> {
+    let names = parse_offer(offer)?;
+    let index = names
+        .iter()
+        .position(|name| supported.contains(name))
+        .ok_or(NoiseError::HandshakeFailed)?;
+    let reply = vec![NEGOTIATION_VERSION, index as u8];
+    Ok((Agreement::new(names[index].to_string(), offer, &reply), reply))
+}
+
+fn parse_offer(offer: &[u8]) -> Result<Vec<&str>> {
+    let [NEGOTIATION_VERSION, count, rest @ ..] = offer else {
+        return Err(NoiseError::InvalidMessage);
+    };
+    let mut rest = rest;
+    let mut names = Vec::with_capacity(*count as usize);
+    for _ in 0..*count {
+        let (&len, tail) = rest.split_first().ok_or(NoiseError::InvalidMessage)?;
+        let name = tail.get(..len as usize).ok_or(NoiseError::InvalidMessage)?;
+        names.push(std::str::from_utf8(name).map_err(|_| NoiseError::InvalidMessage)?);
+        rest = &tail[len as usize..];
+    }
+    if names.is_empty() || !rest.is_empty() {
+        return Err(NoiseError::InvalidMessage);
+    }
+    Ok(names)
+}
+
+/// The outcome of a negotiation, as both sides see it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Agreement {
+    chosen: String,
+    prologue: Vec<u8>,
+}
+
+impl Agreement {
+    fn new(chosen: String, offer: &[u8], reply: &[u8]) -> Self {
+        let mut prologue = PROLOGUE_LABEL.to_vec();
+        for message in [offer, reply] {
+            prologue.extend_from_slice(&(message.len() as u16).to_be_bytes());
+            prologue.extend_from_slice(message);
+        }
+        Self { chosen, prologue }
+    }
+    
+    /// The agreed version or pattern
+    pub fn chosen(&self) -> &str {
+        &self.chosen
+    }
+    
+    /// Prologue binding the handshake to the negotiation transcript
+    ///
+    /// Pass it as the binding to
+    /// [`NoiseSession::with_channel_binding`](crate::core::session::NoiseSession::with_channel_binding)
+    /// or [`NoiseSessionBuilder::channel_binding`](crate::core::session::NoiseSessionBuilder::channel_binding).
+    /// To bind a transport channel as well, append its binding after this
+    /// one on both sides.
+    pub fn prologue(&self) -> &[u8] {
+        &self.prologue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::keys::SecretKey;
+    use crate::core::session::NoiseSession;
+    
+    /// Run an XX handshake with the given prologues, returning whether it completed
+    fn handshake(initiator_prologue: &[u8], responder_prologue: &[u8]) -> bool {
+        let mut initiator = NoiseSession::with_channel_binding(&SecretKey::generate(), true, initiator_prologue).unwrap();
+        let mut responder = NoiseSession::with_channel_binding(&SecretKey::generate(), false, responder_prologue).unwrap();
+        let msg1 = initiator.write_message(&[]).unwrap();
+        responder.read_message(&msg1).unwrap();
+        let msg2 = responder.write_message(&[]).unwrap();
+        initiator.read_message(&msg2).is_ok()
+    }
+    
+    #[test]
+    fn test_negotiates_first_common_choice() {
+        let (offer, message) = NegotiationOffer::new(&["v3", "v2", "v1"]).unwrap();
+        let (responder, reply) = select(&message, &["v1", "v2"]).unwrap();
+        let initiator = offer.complete(&reply).unwrap();
+        assert_eq!(initiator.chosen(), "v2");
+        assert_eq!(initiator, responder);
+        assert!(handshake(initiator.prologue(), responder.prologue()));
+        
+        let (_, message) = NegotiationOffer::new(&["v3"]).unwrap();
+        assert!(matches!(select(&message, &["v1"]), Err(NoiseError::HandshakeFailed)));
+    }
+    
+    #[test]
+    fn test_stripped_offer_fails_handshake() {
+        let (offer, _) = NegotiationOffer::new(&["v3", "v2"]).unwrap();
+        
+        // An attacker replaces the offer so the responder settles for v2
+        let (_, stripped) = NegotiationOffer::new(&["v2"]).unwrap();
+        let (responder, reply) = select(&stripped, &["v2", "v3"]).unwrap();
+        let initiator = offer.complete(&reply).unwrap();
+        assert_eq!((initiator.chosen(), responder.chosen()), ("v3", "v2"));
+        assert!(!handshake(initiator.prologue(), responder.prologue()));
+    }
+    
+    #[test]
+    fn test_rejects_malformed() {
+        assert!(NegotiationOffer::new(&[]).is_err());
+        assert!(NegotiationOffer::new(&[""]).is_err());
+        assert!(NegotiationOffer::new(&[&"x".repeat(MAX_NAME_LEN + 1)]).is_err());
+        
+        let (offer, message) = NegotiationOffer::new(&["v1"]).unwrap();
+        assert!(select(&message[..message.len() - 1], &["v1"]).is_err());
+        assert!(select(&[&message[..], &[0]].concat(), &["v1"]).is_err());
+        assert!(select(&[NEGOTIATION_VERSION, 0], &["v1"]).is_err());
+        assert!(offer.complete(&[NEGOTIATION_VERSION, 1]).is_err());
+    }
+}
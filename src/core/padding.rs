@@ -0,0 +1,207 @@
+//! Length padding for traffic-analysis resistance
+//!
+//! Ciphertext length gives away a lot about content: a read receipt, a
+//! text message and a photo are easy to tell apart. With a
+//! [`PaddingScheme`] set via [`NoiseSession::set_padding`], plaintext is
+//! padded before encryption and the padding stripped after decryption.
+//!
+//! Padding is a `0x80` byte followed by zeros, so the receiver can strip
+//! it without knowing which scheme the sender used. Both sides must agree
+//! that padding is on: advertise the schemes you support in the handshake
+//! payload with [`advertise`] and pick one with [`negotiate`]. The result
+//! is symmetric, since padding is on for both peers exactly when they have
+//! a scheme in common.
+//!
+//! [`NoiseSession::set_padding`]: crate::core::session::NoiseSession::set_padding
+
+use crate::core::crypto::NOISE_MAX_PAYLOAD_LEN;
+use crate::core::error::{NoiseError, Result};
+use crate::core::payload::HandshakePayload;
+
+/// Bucket sizes used by [`PaddingScheme::default_buckets`]
+pub const DEFAULT_BUCKETS: [usize; 5] = [64, 256, 1024, 4096, 16384];
+
+/// Byte that starts the padding
+const PADDING_MARKER: u8 = 0x80;
+
+/// How plaintext lengths are rounded up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PaddingScheme {
+    /// Pad to the smallest bucket that fits; past the largest bucket, pad
+    /// to a multiple of it
+    Buckets(Vec<usize>),
+    /// PADMÉ: leaks at most `O(log log n)` bits of the length with at most
+    /// 12% overhead
+    Padme,
+}
+
+impl PaddingScheme {
+    /// Buckets of [`DEFAULT_BUCKETS`]
+    pub fn default_buckets() -> Self {
+        PaddingScheme::Buckets(DEFAULT_BUCKETS.to_vec())
+    }
+    
+    /// Capability string advertising this scheme in a handshake payload
+    pub fn capability(&self) -> &'static str {
+        match self {
+            PaddingScheme::Buckets(_) => "padding/buckets",
+            PaddingScheme::Padme => "padding/padme",
+        }
+    }
+    
+    /// Length a plaintext of `len` bytes is padded to
+    pub fn padded_len(&self, len: usize) -> usize {
+        // Room for the marker byte
+        let len = len + 1;
+        match self {
+            PaddingScheme::Buckets(buckets) => match buckets.iter().copied().filter(|&b| b >= len).min() {
+                Some(bucket) => bucket,
+                None => match buckets.iter().copied().max() {
+                    Some(largest) if largest > 0 => len.div_ceil(largest) * largest,
+                    _ => len,
+                },
+            },
+            PaddingScheme::Padme => padme(len),
+        }
+    }
+    
+    /// Pad a plaintext
+    ///
+    /// Padding is capped so the result still fits a Noise message. Fails
+    /// with [`NoiseError::InvalidParameter`] if the plaintext leaves no room
+    /// for the marker byte.
+    pub fn pad(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        if plaintext.len() >= NOISE_MAX_PAYLOAD_LEN {
+            return Err(NoiseError::InvalidParameter);
+        }
+        let mut padded = vec![0u8; self.padded_len(plaintext.len()).min(NOISE_MAX_PAYLOAD_LEN)];
+        padded[..plaintext.len()].copy_from_slice(plaintext);
+        let len = self.pad_in_place(&mut padded, plaintext.len())?;
+        padded.truncate(len);
+        Ok(padded)
+    }
+    
+    /// Pad the plaintext in `buf[..len]` within `buf`, returning the padded length
+    ///
+    /// Like [`pad`](Self::pad) without allocating. Fails with
+    /// [`NoiseError::BufferTooSmall`] if `buf` cannot hold the padding.
+    pub fn pad_in_place(&self, buf: &mut [u8], len: usize) -> Result<usize> {
+        if len >= NOISE_MAX_PAYLOAD_LEN {
+            return Err(NoiseError::InvalidParameter);
+        }
+        let padded_len = self.padded_len(len).min(NOISE_MAX_PAYLOAD_LEN);
+        if buf.len() < padded_len {
+            return Err(NoiseError::BufferTooSmall { needed: padded_len, got: buf.len() });
+        }
+        buf[len] = PADDING_MARKER;
+        buf[len + 1..padded_len].fill(0);
+        Ok(padded_len)
+    }
+}
+
+/// Strip the padding from a decrypted message
+pub fn unpad(mut padded: Vec<u8>) -> Result<Vec<u8>> {
+    padded.truncate(unpadded_len(&padded)?);
+    Ok(padded)
+}
+
+/// Length of a decrypted message without its padding
+pub fn unpadded_len(padded: &[u8]) -> Result<usize> {
+    let marker = padded.iter().rposition(|&b| b != 0).ok_or(NoiseError::InvalidMessage)?;
+    if padded[marker] != PADDING_MARKER {
+        return Err(NoiseError::InvalidMessage);
+    }
+    Ok(marker)
+}
+
+/// List `schemes` in a handshake payload's capabilities
+pub fn advertise(payload: &mut HandshakePayload, schemes: &[PaddingScheme]) {
+    for scheme in schemes {
+        payload.capabilities.push(scheme.capability().to_string());
+    }
+}
+
+/// The first of our `schemes`, in preference order, that the peer advertised
+pub fn negotiate(schemes: &[PaddingScheme], peer: &HandshakePayload) -> Option<PaddingScheme> {
+    schemes.iter().find(|scheme| peer.has_capability(scheme.capability())).cloned()
+}
+
+/// PADMÉ length: keep the top `floor(log2(e)) + 1` bits of `len`, where
+/// `e = floor(log2(len))`, rounding the rest up
+fn padme(len: usize) -> usize {
+    if len < 2 {
+        return len;
+    }
+    let exponent = usize::BITS - 1 - len.leading_zeros();
+    let significant = u32::BITS - exponent.leading_zeros();
+    let mask = (1usize << (exponent - significant)) - 1;
+    (len + mask) & !mask
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::session::NoiseSession;
+    
+    #[test]
+    fn test_schemes_and_roundtrip() {
+        let buckets = PaddingScheme::default_buckets();
+        assert_eq!(buckets.padded_len(0), 64);
+        assert_eq!(buckets.padded_len(64), 256);
+        assert_eq!(buckets.padded_len(20000), 32768);
+        
+        // PADMÉ keeps fewer significant bits as lengths grow
+        assert_eq!(padme(9), 10);
+        assert_eq!(padme(1000), 1024);
+        assert_eq!(padme(1_000_000), 1_015_808);
+        assert_eq!(PaddingScheme::Padme.padded_len(99), 104);
+        
+        for scheme in [buckets, PaddingScheme::Padme] {
+            for message in [&b""[..], b"\0\0", b"\x80", &[1u8; 5000]] {
+                let padded = scheme.pad(message).unwrap();
+                assert_eq!(padded.len(), scheme.padded_len(message.len()));
+                assert_eq!(unpad(padded).unwrap(), message);
+            }
+        }
+        assert_eq!(PaddingScheme::Padme.pad(&[0u8; NOISE_MAX_PAYLOAD_LEN - 1]).unwrap().len(), NOISE_MAX_PAYLOAD_LEN);
+        assert!(PaddingScheme::Padme.pad(&[0u8; NOISE_MAX_PAYLOAD_LEN]).is_err());
+        assert!(unpad(vec![1, 2, 0, 0]).is_err());
+        assert!(unpad(vec![0; 4]).is_err());
+    }
+    
+    #[test]
+    fn test_negotiated_padding_on_session() {
+        let mut offer = HandshakePayload::default();
+        advertise(&mut offer, &[PaddingScheme::Padme, PaddingScheme::default_buckets()]);
+        let mut answer = HandshakePayload::default();
+        advertise(&mut answer, &[PaddingScheme::default_buckets()]);
+        
+        // Each side sends with its own choice; both agree padding is on
+        let alice_scheme = negotiate(&[PaddingScheme::Padme, PaddingScheme::default_buckets()], &answer);
+        let bob_scheme = negotiate(&[PaddingScheme::default_buckets()], &offer);
+        assert_eq!(alice_scheme, Some(PaddingScheme::default_buckets()));
+        assert_eq!(bob_scheme, Some(PaddingScheme::default_buckets()));
+        assert_eq!(negotiate(&[PaddingScheme::Padme], &answer), None);
+        
+        let mut alice = NoiseSession::new_initiator().unwrap();
+        let mut bob = NoiseSession::new_responder().unwrap();
+        let msg1 = alice.write_message(&[]).unwrap();
+        bob.read_message(&msg1).unwrap();
+        let msg2 = bob.write_message(&[]).unwrap();
+        alice.read_message(&msg2).unwrap();
+        let msg3 = alice.write_message(&[]).unwrap();
+        bob.read_message(&msg3).unwrap();
+        alice.set_padding(alice_scheme);
+        bob.set_padding(Some(PaddingScheme::Padme));
+        
+        let short = alice.encrypt(b"ok").unwrap();
+        let longer = alice.encrypt(b"a somewhat longer message").unwrap();
+        assert_eq!(short.len(), longer.len());
+        assert_eq!(bob.decrypt(&short).unwrap(), b"ok");
+        assert_eq!(bob.decrypt(&longer).unwrap(), b"a somewhat longer message");
+        
+        let frame = bob.encrypt_with_explicit_nonce(&[3u8; 99]).unwrap();
+        assert_eq!(frame.len(), NoiseSession::EXPLICIT_HEADER_LEN + 104 + 16);
+        assert_eq!(alice.decrypt_with_explicit_nonce(&frame).unwrap().1, vec![3u8; 99]);
+    }
+}
@@ -0,0 +1,164 @@
+//! Structured handshake payloads
+//!
+//! [`HandshakePayload`] gives handshake messages a common, versioned format
+//! instead of app-specific byte layouts. Fields are encoded as
+//! `type: u8 | len: u16 | value` records after a version byte; records of
+//! unknown types are skipped, so newer peers can add fields.
+
+use crate::core::error::{NoiseError, Result};
+use std::sync::Arc;
+
+/// Handshake payload format version
+pub const PAYLOAD_VERSION: u8 = 1;
+
+/// Longest value a single field can hold
+pub const MAX_FIELD_LEN: usize = u16::MAX as usize;
+
+const FIELD_IDENTITY: u8 = 1;
+const FIELD_CAPABILITY: u8 = 2;
+const FIELD_SIGNATURE: u8 = 3;
+const FIELD_DATA: u8 = 4;
+
+/// Decides whether a peer's handshake payload is acceptable
+///
+/// Called from [`NoiseSession::read_message`] for every handshake message
+/// from the peer, with the peer's static key if it is known yet. Return
+/// `false` to abort the handshake.
+///
+/// [`NoiseSession::read_message`]: crate::core::session::NoiseSession::read_message
+pub type PayloadValidator = Arc<dyn Fn(&HandshakePayload, Option<&[u8]>) -> bool + Send + Sync>;
+
+/// Like [`PayloadValidator`], for payloads in an app's own format
+pub type RawPayloadValidator = Arc<dyn Fn(&[u8], Option<&[u8]>) -> bool + Send + Sync>;
+
+/// A versioned handshake payload
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HandshakePayload {
+    /// Who the sender claims to be, e.g. a user ID or certificate
+    pub identity: Option<Vec<u8>>,
+    /// Features the sender supports, e.g. `"compression"`
+    pub capabilities: Vec<String>,
+    /// Signature vouching for the sender, e.g. a `StaticKeyCertificate`
+    pub signature: Option<Vec<u8>>,
+    /// Application data
+    pub data: Vec<u8>,
+}
+
+impl HandshakePayload {
+    /// Whether the sender listed a capability
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+    
+    /// Encode for [`NoiseSession::write_message`]
+    ///
+    /// Fails if any field is longer than [`MAX_FIELD_LEN`].
+    ///
+    /// [`NoiseSession::write_message`]: crate::core::session::NoiseSession::write_message
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut data = vec![PAYLOAD_VERSION];
+        if let Some(identity) = &self.identity {
+            put_field(&mut data, FIELD_IDENTITY, identity)?;
+        }
+        for capability in &self.capabilities {
+            put_field(&mut data, FIELD_CAPABILITY, capability.as_bytes())?;
+        }
+        if let Some(signature) = &self.signature {
+            put_field(&mut data, FIELD_SIGNATURE, signature)?;
+        }
+        if !self.data.is_empty() {
+            put_field(&mut data, FIELD_DATA, &self.data)?;
+        }
+        Ok(data)
+    }
+    
+    /// Decode a received payload
+    ///
+    /// An empty input decodes to an empty payload, since handshake messages
+    /// often carry nothing (e.g. the first XX message).
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let mut payload = Self::default();
+        let Some((&version, mut rest)) = data.split_first() else {
+            return Ok(payload);
+        };
+        if version != PAYLOAD_VERSION {
+            return Err(NoiseError::InvalidMessage);
+        }
+        
+        while !rest.is_empty() {
+            if rest.len() < 3 {
+                return Err(NoiseError::InvalidMessage);
+            }
+            let field = rest[0];
+            let len = u16::from_be_bytes([rest[1], rest[2]]) as usize;
+            let value = rest.get(3..3 + len).ok_or(NoiseError::InvalidMessage)?;
+            rest = &rest[3 + len..];
+            
+            match field {
+                FIELD_IDENTITY => set_once(&mut payload.identity, value)?,
+                FIELD_CAPABILITY => payload.capabilities.push(
+                    String::from_utf8(value.to_vec()).map_err(|_| NoiseError::InvalidMessage)?,
+                ),
+                FIELD_SIGNATURE => set_once(&mut payload.signature, value)?,
+                FIELD_DATA => payload.data.extend_from_slice(value),
+                // Fields from newer versions
+                _ => {}
+            }
+        }
+        Ok(payload)
+    }
+}
+
+fn put_field(data: &mut Vec<u8>, field: u8, value: &[u8]) -> Result<()> {
+    let len = u16::try_from(value.len()).map_err(|_| NoiseError::InvalidParameter)?;
+    data.push(field);
+    data.extend_from_slice(&len.to_be_bytes());
+    data.extend_from_slice(value);
+    Ok(())
+}
+
+fn set_once(slot: &mut Option<Vec<u8>>, value: &[u8]) -> Result<()> {
+    if slot.is_some() {
+        return Err(NoiseError::InvalidMessage);
+    }
+    *slot = Some(value.to_vec());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_roundtrip() {
+        let payload = HandshakePayload {
+            identity: Some(b"alice@example".to_vec()),
+            capabilities: vec!["compression".to_string(), "padding".to_string()],
+            signature: Some(vec![7u8; 64]),
+            data: b"hello".to_vec(),
+        };
+        let decoded = HandshakePayload::decode(&payload.encode().unwrap()).unwrap();
+        assert_eq!(decoded, payload);
+        assert!(decoded.has_capability("padding"));
+        assert!(!decoded.has_capability("sealed"));
+        
+        assert_eq!(HandshakePayload::decode(&[]).unwrap(), HandshakePayload::default());
+        assert_eq!(HandshakePayload::default().encode().unwrap(), vec![PAYLOAD_VERSION]);
+    }
+    
+    #[test]
+    fn test_unknown_and_malformed_fields() {
+        // An unknown field is skipped
+        let mut data = HandshakePayload { data: b"x".to_vec(), ..Default::default() }.encode().unwrap();
+        data.extend_from_slice(&[99, 0, 2, 1, 2]);
+        assert_eq!(HandshakePayload::decode(&data).unwrap().data, b"x");
+        
+        assert!(HandshakePayload::decode(&data[..data.len() - 1]).is_err());
+        assert!(HandshakePayload::decode(&[2]).is_err());
+        // Identity may appear only once
+        assert!(HandshakePayload::decode(&[1, 1, 0, 1, b'a', 1, 0, 1, b'b']).is_err());
+        
+        let oversized = HandshakePayload { data: vec![0u8; MAX_FIELD_LEN + 1], ..Default::default() };
+        assert!(oversized.encode().is_err());
+    }
+}
@@ -0,0 +1,351 @@
+//! Noise Pipes: IK with fallback to XX
+//!
+//! A [`PipeSession`] initiator that has the responder's static key cached
+//! from an earlier session opens with a one-round-trip IK handshake. If the
+//! responder's key has changed it cannot decrypt the IK message and answers
+//! by starting an XX handshake with the roles swapped, so the initiator
+//! never needs to find out about the new key out of band. Initiators
+//! without a cached key use XX from the start.
+//!
+//! snow cannot seed a handshake with the peer's ephemeral key, so the
+//! fallback is a fresh XX handshake rather than the spec's `XXfallback`;
+//! the rejected IK message is mixed into its prologue to tie the two
+//! together. Every handshake message starts with a byte naming its pattern.
+
+use crate::core::error::{NoiseError, Result};
+use crate::core::keys::{SecretKey, KEY_LEN};
+use crate::core::session::NoiseSession;
+use zeroize::Zeroizing;
+
+/// Protocol used when the initiator has the responder's key cached
+pub const PIPE_IK_PARAMS: &str = "Noise_IK_25519_ChaChaPoly_BLAKE2s";
+
+const MESSAGE_XX: u8 = 0;
+const MESSAGE_IK: u8 = 1;
+const MESSAGE_FALLBACK: u8 = 2;
+
+/// Handshake a [`PipeSession`] is running
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipeMode {
+    /// Abbreviated handshake with the cached key
+    Ik,
+    /// Full handshake, no key was cached
+    Xx,
+    /// Full handshake after the responder rejected IK
+    Fallback,
+}
+
+impl PipeMode {
+    fn tag(self) -> u8 {
+        match self {
+            PipeMode::Ik => MESSAGE_IK,
+            PipeMode::Xx => MESSAGE_XX,
+            PipeMode::Fallback => MESSAGE_FALLBACK,
+        }
+    }
+}
+
+/// A handshake that picks IK or XX, then hands over a [`NoiseSession`]
+///
+/// Drive it like a `NoiseSession` handshake, alternating
+/// [`write_message`](Self::write_message) and
+/// [`read_message`](Self::read_message) until
+/// [`is_handshake_finished`](Self::is_handshake_finished), then call
+/// [`into_session`](Self::into_session).
+///
+/// The payload of an IK first message is encrypted to the cached key but
+/// is not forward secret and can be replayed. It is lost if the responder
+/// falls back, so check [`fell_back`](Self::fell_back) and resend it. After
+/// a fallback the responder presents a different static key: check it
+/// (e.g. against a rotation chain) before updating the cache.
+pub struct PipeSession {
+    is_initiator: bool,
+    private_key: SecretKey,
+    binding: Vec<u8>,
+    mode: Option<PipeMode>,
+    session: Option<NoiseSession>,
+    /// The initiator's IK message, needed to follow a fallback
+    ik_message: Option<Vec<u8>>,
+    /// Set on a responder that has yet to send its fallback message
+    fallback_pending: bool,
+    /// Responder payload held back from the unencrypted fallback message
+    deferred_payload: Zeroizing<Vec<u8>>,
+}
+
+impl PipeSession {
+    /// Start as initiator, using IK if the responder's key is cached
+    pub fn new_initiator(private_key: &SecretKey, cached_remote_static: Option<&[u8]>) -> Result<Self> {
+        Self::new_initiator_with_binding(private_key, cached_remote_static, &[])
+    }
+    
+    /// Start as initiator with a channel binding, as in [`NoiseSession::with_channel_binding`]
+    pub fn new_initiator_with_binding(
+        private_key: &SecretKey,
+        cached_remote_static: Option<&[u8]>,
+        binding: &[u8],
+    ) -> Result<Self> {
+        let (mode, session) = match cached_remote_static {
+            Some(remote_static) if remote_static.len() != KEY_LEN => return Err(NoiseError::InvalidParameter),
+            Some(remote_static) => (
+                PipeMode::Ik,
+                NoiseSession::with_protocol(PIPE_IK_PARAMS, private_key.as_bytes(), Some(remote_static), true, binding)?,
+            ),
+            None => (PipeMode::Xx, NoiseSession::with_channel_binding(private_key, true, binding)?),
+        };
+        let mut pipe = Self::new(private_key, true, binding);
+        pipe.mode = Some(mode);
+        pipe.session = Some(session);
+        Ok(pipe)
+    }
+    
+    /// Start as responder; the initiator's first message picks the handshake
+    pub fn new_responder(private_key: &SecretKey) -> Result<Self> {
+        Self::new_responder_with_binding(private_key, &[])
+    }
+    
+    /// Start as responder with a channel binding
+    pub fn new_responder_with_binding(private_key: &SecretKey, binding: &[u8]) -> Result<Self> {
+        Ok(Self::new(private_key, false, binding))
+    }
+    
+    fn new(private_key: &SecretKey, is_initiator: bool, binding: &[u8]) -> Self {
+        Self {
+            is_initiator,
+            private_key: private_key.clone(),
+            binding: binding.to_vec(),
+            mode: None,
+            session: None,
+            ik_message: None,
+            fallback_pending: false,
+            deferred_payload: Zeroizing::new(Vec::new()),
+        }
+    }
+    
+    /// The handshake in use; `None` on a responder before the first message
+    pub fn mode(&self) -> Option<PipeMode> {
+        self.mode
+    }
+    
+    /// Whether the responder rejected IK and the handshake fell back to XX
+    pub fn fell_back(&self) -> bool {
+        self.mode == Some(PipeMode::Fallback)
+    }
+    
+    /// Check if the handshake is complete
+    pub fn is_handshake_finished(&self) -> bool {
+        self.session.as_ref().is_some_and(NoiseSession::is_transport_state)
+    }
+    
+    /// The peer's static key, once the handshake has revealed it
+    pub fn get_remote_static(&self) -> Option<&[u8]> {
+        self.session.as_ref()?.get_remote_static()
+    }
+    
+    /// Write the next handshake message
+    ///
+    /// A responder's first message after falling back is sent in the clear,
+    /// so its payload is held back and sent with the responder's next one.
+    pub fn write_message(&mut self, payload: &[u8]) -> Result<Vec<u8>> {
+        let (Some(mode), Some(session)) = (self.mode, self.session.as_mut()) else {
+            return Err(NoiseError::InvalidState("Responder must read the first message".to_string()));
+        };
+        let message = if self.fallback_pending {
+            let message = session.write_message(&[])?;
+            self.deferred_payload = Zeroizing::new(payload.to_vec());
+            self.fallback_pending = false;
+            message
+        } else if !self.deferred_payload.is_empty() {
+            let combined = Zeroizing::new([&self.deferred_payload[..], payload].concat());
+            let message = session.write_message(&combined)?;
+            self.deferred_payload = Zeroizing::new(Vec::new());
+            message
+        } else {
+            session.write_message(payload)?
+        };
+        if mode == PipeMode::Ik && self.is_initiator {
+            self.ik_message = Some(message.clone());
+        }
+        Ok([&[mode.tag()], &message[..]].concat())
+    }
+    
+    /// Read the peer's next handshake message, returning its payload
+    ///
+    /// A responder that cannot decrypt an IK message falls back instead of
+    /// failing, and returns an empty payload.
+    pub fn read_message(&mut self, message: &[u8]) -> Result<Vec<u8>> {
+        let (&tag, body) = message.split_first().ok_or(NoiseError::InvalidMessage)?;
+        match (self.is_initiator, self.mode) {
+            (false, None) => return self.accept(tag, body),
+            (true, Some(PipeMode::Ik)) if tag == MESSAGE_FALLBACK => return self.follow_fallback(body),
+            _ => {}
+        }
+        
+        let (Some(mode), Some(session)) = (self.mode, self.session.as_mut()) else {
+            return Err(NoiseError::InvalidState("Handshake has not started".to_string()));
+        };
+        if tag != mode.tag() {
+            return Err(NoiseError::InvalidMessage);
+        }
+        let payload = session.read_message(body)?;
+        self.ik_message = None;
+        Ok(payload)
+    }
+    
+    /// Take the established session
+    pub fn into_session(self) -> Result<NoiseSession> {
+        match self.session {
+            Some(session) if session.is_transport_state() => Ok(session),
+            _ => Err(NoiseError::InvalidState("Handshake not complete".to_string())),
+        }
+    }
+    
+    /// Responder: start whichever handshake the first message asks for
+    fn accept(&mut self, tag: u8, body: &[u8]) -> Result<Vec<u8>> {
+        match tag {
+            MESSAGE_XX => {
+                let mut session = NoiseSession::with_channel_binding(&self.private_key, false, &self.binding)?;
+                let payload = session.read_message(body)?;
+                self.start(PipeMode::Xx, session);
+                Ok(payload)
+            }
+            MESSAGE_IK => {
+                let mut session =
+                    NoiseSession::with_protocol(PIPE_IK_PARAMS, self.private_key.as_bytes(), None, false, &self.binding)?;
+                if let Ok(payload) = session.read_message(body) {
+                    self.start(PipeMode::Ik, session);
+                    return Ok(payload);
+                }
+                // Encrypted to a key we no longer hold: run XX as initiator
+                let prologue = fallback_prologue(&self.binding, body);
+                let session = NoiseSession::with_channel_binding(&self.private_key, true, &prologue)?;
+                self.start(PipeMode::Fallback, session);
+                self.fallback_pending = true;
+                Ok(Vec::new())
+            }
+            _ => Err(NoiseError::InvalidMessage),
+        }
+    }
+    
+    /// Initiator: switch to XX as responder after our IK message was rejected
+    fn follow_fallback(&mut self, body: &[u8]) -> Result<Vec<u8>> {
+        let ik_message = self.ik_message.as_ref()
+            .ok_or_else(|| NoiseError::InvalidState("No IK message was sent".to_string()))?;
+        let prologue = fallback_prologue(&self.binding, ik_message);
+        let mut session = NoiseSession::with_channel_binding(&self.private_key, false, &prologue)?;
+        let payload = session.read_message(body)?;
+        self.start(PipeMode::Fallback, session);
+        self.ik_message = None;
+        Ok(payload)
+    }
+    
+    fn start(&mut self, mode: PipeMode, session: NoiseSession) {
+        self.mode = Some(mode);
+        self.session = Some(session);
+    }
+}
+
+fn fallback_prologue(binding: &[u8], ik_message: &[u8]) -> Vec<u8> {
+    [binding, ik_message].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::keys::Keypair;
+    
+    /// Run a handshake to completion, initiator first
+    fn handshake(initiator: &mut PipeSession, responder: &mut PipeSession) {
+        let mut sender_is_initiator = true;
+        while !initiator.is_handshake_finished() || !responder.is_handshake_finished() {
+            let (from, to) = if sender_is_initiator {
+                (&mut *initiator, &mut *responder)
+            } else {
+                (&mut *responder, &mut *initiator)
+            };
+            let message = from.write_message(&[]).unwrap();
+            to.read_message(&message).unwrap();
+            sender_is_initiator = !sender_is_initiator;
+        }
+    }
+    
+    #[test]
+    fn test_ik_with_cached_key() {
+        let server = Keypair::generate().unwrap();
+        let client = Keypair::generate().unwrap();
+        let mut initiator = PipeSession::new_initiator(client.secret_key(), Some(server.public_key())).unwrap();
+        let mut responder = PipeSession::new_responder(server.secret_key()).unwrap();
+        assert!(responder.write_message(&[]).is_err());
+        
+        let message = initiator.write_message(b"early").unwrap();
+        assert_eq!(message[0], MESSAGE_IK);
+        assert_eq!(responder.read_message(&message).unwrap(), b"early");
+        let message = responder.write_message(b"reply").unwrap();
+        assert_eq!(initiator.read_message(&message).unwrap(), b"reply");
+        
+        assert!(initiator.is_handshake_finished() && responder.is_handshake_finished());
+        assert_eq!(initiator.mode(), Some(PipeMode::Ik));
+        assert_eq!(responder.get_remote_static(), Some(&client.public_key()[..]));
+        
+        let mut a = initiator.into_session().unwrap();
+        let mut b = responder.into_session().unwrap();
+        let ciphertext = a.encrypt(b"hello").unwrap();
+        assert_eq!(b.decrypt(&ciphertext).unwrap(), b"hello");
+    }
+    
+    #[test]
+    fn test_xx_without_cached_key() {
+        let server = Keypair::generate().unwrap();
+        let mut initiator = PipeSession::new_initiator(Keypair::generate().unwrap().secret_key(), None).unwrap();
+        let mut responder = PipeSession::new_responder(server.secret_key()).unwrap();
+        assert!(PipeSession::new_initiator(server.secret_key(), Some(&[1u8; 16])).is_err());
+        
+        handshake(&mut initiator, &mut responder);
+        assert_eq!(responder.mode(), Some(PipeMode::Xx));
+        assert_eq!(initiator.get_remote_static(), Some(&server.public_key()[..]));
+    }
+    
+    #[test]
+    fn test_fallback_after_key_change() {
+        let stale = Keypair::generate().unwrap();
+        let server = Keypair::generate().unwrap();
+        let client = Keypair::generate().unwrap();
+        let mut initiator = PipeSession::new_initiator(client.secret_key(), Some(stale.public_key())).unwrap();
+        let mut responder = PipeSession::new_responder(server.secret_key()).unwrap();
+        
+        let message = initiator.write_message(b"early").unwrap();
+        assert!(responder.read_message(&message).unwrap().is_empty());
+        assert!(responder.fell_back());
+        
+        // The responder's payload waits for an encrypted message
+        let message = responder.write_message(b"welcome").unwrap();
+        assert_eq!(message[0], MESSAGE_FALLBACK);
+        assert!(initiator.read_message(&message).unwrap().is_empty());
+        assert!(initiator.fell_back());
+        
+        let message = initiator.write_message(b"early again").unwrap();
+        assert_eq!(responder.read_message(&message).unwrap(), b"early again");
+        let message = responder.write_message(&[]).unwrap();
+        assert_eq!(initiator.read_message(&message).unwrap(), b"welcome");
+        
+        assert_eq!(initiator.get_remote_static(), Some(&server.public_key()[..]));
+        assert_eq!(responder.get_remote_static(), Some(&client.public_key()[..]));
+        let mut a = initiator.into_session().unwrap();
+        let mut b = responder.into_session().unwrap();
+        let ciphertext = b.encrypt(b"hello").unwrap();
+        assert_eq!(a.decrypt(&ciphertext).unwrap(), b"hello");
+    }
+    
+    #[test]
+    fn test_rejects_unexpected_messages() {
+        let server = Keypair::generate().unwrap();
+        let mut responder = PipeSession::new_responder(server.secret_key()).unwrap();
+        assert!(responder.read_message(&[]).is_err());
+        assert!(responder.read_message(&[MESSAGE_FALLBACK, 0]).is_err());
+        
+        // A fallback message is only valid in reply to IK
+        let mut initiator = PipeSession::new_initiator(Keypair::generate().unwrap().secret_key(), None).unwrap();
+        initiator.write_message(&[]).unwrap();
+        assert!(initiator.read_message(&[MESSAGE_FALLBACK, 0]).is_err());
+        assert!(initiator.into_session().is_err());
+    }
+}
@@ -0,0 +1,117 @@
+//! Runtime discovery of the Noise protocols this build can run
+
+use crate::core::libp2p::LIBP2P_NOISE_PARAMS;
+use crate::core::pipes::PIPE_IK_PARAMS;
+use crate::core::session::NoiseSession;
+#[cfg(feature = "tickets")]
+use crate::core::tickets::TICKET_PARAMS;
+use snow::params::NoiseParams;
+use snow::resolvers::{CryptoResolver, DefaultResolver};
+
+/// Protocol names `NoiseSession`, `PipeSession`, ticket resumption and
+/// libp2p handshakes can be built with
+const SESSION_PROTOCOLS: &[&str] = &[
+    NoiseSession::NOISE_PARAMS,
+    PIPE_IK_PARAMS,
+    #[cfg(feature = "tickets")]
+    TICKET_PARAMS,
+    LIBP2P_NOISE_PARAMS,
+];
+
+/// One Noise protocol combination, split into its components
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtocolDescriptor {
+    /// Handshake pattern including modifiers (e.g. `XX`, `XXpsk3`)
+    pub pattern: String,
+    /// Diffie-Hellman function (e.g. `25519`)
+    pub dh: String,
+    /// AEAD cipher (e.g. `ChaChaPoly`)
+    pub cipher: String,
+    /// Hash function (e.g. `BLAKE2s`)
+    pub hash: String,
+    /// Whether the pattern mixes in a pre-shared key
+    pub psk: bool,
+    /// Whether the handshake includes a post-quantum KEM
+    pub pq: bool,
+}
+
+impl ProtocolDescriptor {
+    /// Split a full protocol name such as `Noise_XX_25519_ChaChaPoly_BLAKE2s`
+    pub fn parse(name: &str) -> Option<Self> {
+        let mut parts = name.split('_');
+        if parts.next()? != "Noise" {
+            return None;
+        }
+        let pattern = parts.next()?.to_string();
+        let dh = parts.next()?.to_string();
+        let cipher = parts.next()?.to_string();
+        let hash = parts.next()?.to_string();
+        if parts.next().is_some() {
+            return None;
+        }
+        
+        Some(Self {
+            psk: pattern.contains("psk"),
+            pq: pattern.contains("hfs") || dh.contains('+'),
+            pattern,
+            dh,
+            cipher,
+            hash,
+        })
+    }
+    
+    /// Full protocol name, as used in the handshake hash
+    pub fn name(&self) -> String {
+        format!("Noise_{}_{}_{}_{}", self.pattern, self.dh, self.cipher, self.hash)
+    }
+}
+
+/// List the protocol combinations sessions in this build can use
+///
+/// Each entry is checked against the crypto backends compiled into snow, so
+/// the list tracks the build's actual features.
+pub fn supported_protocols() -> Vec<ProtocolDescriptor> {
+    let resolver = DefaultResolver;
+    SESSION_PROTOCOLS
+        .iter()
+        .filter(|name| {
+            name.parse::<NoiseParams>().is_ok_and(|params| {
+                resolver.resolve_dh(&params.dh).is_some()
+                    && resolver.resolve_cipher(&params.cipher).is_some()
+                    && resolver.resolve_hash(&params.hash).is_some()
+            })
+        })
+        .filter_map(|name| ProtocolDescriptor::parse(name))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_default_protocol_supported() {
+        let protocols = supported_protocols();
+        assert!(protocols.iter().any(|p| p.name() == NoiseSession::NOISE_PARAMS));
+        
+        let xx = &protocols[0];
+        assert_eq!(xx.pattern, "XX");
+        assert_eq!(xx.dh, "25519");
+        assert_eq!(xx.cipher, "ChaChaPoly");
+        assert_eq!(xx.hash, "BLAKE2s");
+        assert!(!xx.psk && !xx.pq);
+    }
+    
+    #[test]
+    fn test_descriptor_parse() {
+        let psk = ProtocolDescriptor::parse("Noise_XXpsk3_25519_AESGCM_SHA256").unwrap();
+        assert!(psk.psk);
+        assert_eq!(psk.name(), "Noise_XXpsk3_25519_AESGCM_SHA256");
+        
+        let pq = ProtocolDescriptor::parse("Noise_XXhfs_25519+Kyber1024_ChaChaPoly_BLAKE2s").unwrap();
+        assert!(pq.pq);
+        
+        assert!(ProtocolDescriptor::parse("Noise_XX_25519").is_none());
+        assert!(ProtocolDescriptor::parse("Foo_XX_25519_ChaChaPoly_BLAKE2s").is_none());
+    }
+}
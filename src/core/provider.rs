@@ -0,0 +1,95 @@
+//! Pluggable crypto providers
+//!
+//! Sessions run on snow's pure-Rust primitives by default. Integrators can
+//! substitute platform-accelerated or certified implementations (CryptoKit,
+//! ring, BoringSSL) by implementing snow's [`CryptoResolver`] and passing it
+//! to [`NoiseSessionBuilder::resolver`]. Wrap a partial provider in a
+//! [`FallbackResolver`] to keep the built-in primitives for the rest.
+//!
+//! The resolver covers the handshake and transport encryption of the
+//! session it is given to. Rekeying, key export, snapshot restore and the
+//! library's other constructions (sealed messages, tickets, ratchets) keep
+//! using the built-in primitives.
+//!
+//! [`NoiseSessionBuilder::resolver`]: crate::core::session::NoiseSessionBuilder::resolver
+
+pub use snow::params::{CipherChoice, DHChoice, HashChoice};
+pub use snow::resolvers::{BoxedCryptoResolver, CryptoResolver, DefaultResolver, FallbackResolver};
+pub use snow::types::{Cipher, Dh, Hash, Random};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::session::NoiseSessionBuilder;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    
+    /// Counts cipher lookups and defers everything to the built-in provider
+    struct CountingResolver(Arc<AtomicUsize>);
+    
+    impl CryptoResolver for CountingResolver {
+        fn resolve_rng(&self) -> Option<Box<dyn Random>> {
+            DefaultResolver.resolve_rng()
+        }
+        
+        fn resolve_dh(&self, choice: &DHChoice) -> Option<Box<dyn Dh>> {
+            DefaultResolver.resolve_dh(choice)
+        }
+        
+        fn resolve_hash(&self, choice: &HashChoice) -> Option<Box<dyn Hash>> {
+            DefaultResolver.resolve_hash(choice)
+        }
+        
+        fn resolve_cipher(&self, choice: &CipherChoice) -> Option<Box<dyn Cipher>> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            DefaultResolver.resolve_cipher(choice)
+        }
+    }
+    
+    #[test]
+    fn test_custom_resolver_interoperates() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut alice = NoiseSessionBuilder::new()
+            .resolver(Box::new(CountingResolver(calls.clone())))
+            .build_initiator()
+            .unwrap();
+        let mut bob = NoiseSessionBuilder::new().build_responder().unwrap();
+        assert!(calls.load(Ordering::SeqCst) > 0);
+        
+        let msg1 = alice.write_message(&[]).unwrap();
+        bob.read_message(&msg1).unwrap();
+        let msg2 = bob.write_message(&[]).unwrap();
+        alice.read_message(&msg2).unwrap();
+        let msg3 = alice.write_message(&[]).unwrap();
+        bob.read_message(&msg3).unwrap();
+        
+        let ciphertext = alice.encrypt(b"via custom provider").unwrap();
+        assert_eq!(bob.decrypt(&ciphertext).unwrap(), b"via custom provider");
+    }
+    
+    #[test]
+    fn test_missing_primitive_fails_to_build() {
+        struct NoCipher;
+        impl CryptoResolver for NoCipher {
+            fn resolve_rng(&self) -> Option<Box<dyn Random>> {
+                DefaultResolver.resolve_rng()
+            }
+            
+            fn resolve_dh(&self, choice: &DHChoice) -> Option<Box<dyn Dh>> {
+                DefaultResolver.resolve_dh(choice)
+            }
+            
+            fn resolve_hash(&self, choice: &HashChoice) -> Option<Box<dyn Hash>> {
+                DefaultResolver.resolve_hash(choice)
+            }
+            
+            fn resolve_cipher(&self, _choice: &CipherChoice) -> Option<Box<dyn Cipher>> {
+                None
+            }
+        }
+        
+        assert!(NoiseSessionBuilder::new().resolver(Box::new(NoCipher)).build_initiator().is_err());
+        let fallback = FallbackResolver::new(Box::new(NoCipher), Box::new(DefaultResolver));
+        assert!(NoiseSessionBuilder::new().resolver(Box::new(fallback)).build_initiator().is_ok());
+    }
+}
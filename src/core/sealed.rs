@@ -0,0 +1,446 @@
+//! One-way sealed messages
+//!
+//! For asynchronous delivery (push notifications, offline mailboxes) where
+//! the recipient cannot take part in a handshake. A sealed message is a
+//! single Noise handshake message to the recipient's static key: the `N`
+//! pattern for anonymous senders, `X` when the sender's static key travels
+//! with it and is authenticated.
+//!
+//! Sealed messages are not forward secret with respect to the recipient's
+//! static key, and on their own have no replay protection. Messages sealed
+//! with [`SealedMessage::seal_timestamped`] or
+//! [`SealedMessage::seal_from_timestamped`] carry an authenticated send time,
+//! which lets a receiver-side [`SealedReplayCache`] reject copies it has seen
+//! within a time window.
+
+use crate::core::crypto::{NOISE_MAX_MESSAGE_LEN, NOISE_TAG_LEN};
+use crate::core::error::{NoiseError, Result};
+use crate::core::keys::{Keypair, KEY_LEN};
+use crate::core::now_ms;
+use snow::params::HashChoice;
+use snow::resolvers::{CryptoResolver, DefaultResolver};
+use snow::{Builder, HandshakeState};
+use std::collections::{BTreeSet, HashSet};
+use std::time::Duration;
+use zeroize::Zeroizing;
+
+/// Sealed message format version
+const SEALED_VERSION: u8 = 1;
+
+/// Mixed into every handshake so sealed messages cannot pass for other uses
+const SEALED_PROLOGUE: &[u8] = b"noise-mobile/sealed/v1";
+
+const PATTERN_N: u8 = 0;
+const PATTERN_X: u8 = 1;
+
+/// Set on the pattern byte when the payload starts with the send time
+const FLAG_TIMESTAMPED: u8 = 0x80;
+
+/// Length of the send time, in milliseconds since the Unix epoch
+const TIMESTAMP_LEN: usize = 8;
+
+const N_PARAMS: &str = "Noise_N_25519_ChaChaPoly_BLAKE2s";
+const X_PARAMS: &str = "Noise_X_25519_ChaChaPoly_BLAKE2s";
+
+/// Header bytes before the Noise message
+const HEADER_LEN: usize = 2;
+
+/// Largest plaintext an anonymous sealed message can carry
+pub const MAX_SEALED_PLAINTEXT_LEN: usize = NOISE_MAX_MESSAGE_LEN - KEY_LEN - NOISE_TAG_LEN;
+
+/// Largest plaintext a sender-authenticated sealed message can carry
+pub const MAX_SIGNED_SEALED_PLAINTEXT_LEN: usize = MAX_SEALED_PLAINTEXT_LEN - KEY_LEN - NOISE_TAG_LEN;
+
+/// Default [`SealedReplayCache`] window: how far a send time may be from the
+/// receiver's clock
+pub const DEFAULT_SEALED_REPLAY_WINDOW: Duration = Duration::from_secs(300);
+
+/// Default most messages a [`SealedReplayCache`] remembers
+pub const DEFAULT_SEALED_REPLAY_CAPACITY: usize = 4096;
+
+/// Seals and opens one-way messages
+pub struct SealedMessage;
+
+/// Contents of an opened sealed message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenedMessage {
+    /// The decrypted message
+    pub plaintext: Vec<u8>,
+    /// The sender's authenticated static key; `None` for anonymous messages
+    pub sender: Option<[u8; KEY_LEN]>,
+    /// Authenticated send time in milliseconds since the Unix epoch; `None`
+    /// unless the message was sealed with a timestamp
+    pub sent_at_ms: Option<u64>,
+}
+
+impl SealedMessage {
+    /// Encrypt to a recipient's static key without revealing the sender
+    pub fn seal(recipient_static: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+        seal_with(None, recipient_static, None, plaintext)
+    }
+    
+    /// Encrypt to a recipient's static key, authenticated by the sender's
+    pub fn seal_from(sender: &Keypair, recipient_static: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+        seal_with(Some(sender), recipient_static, None, plaintext)
+    }
+    
+    /// As [`SealedMessage::seal`], carrying the current time for replay checks
+    ///
+    /// The time takes 8 bytes of the plaintext limit.
+    pub fn seal_timestamped(recipient_static: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+        seal_with(None, recipient_static, Some(now_ms()), plaintext)
+    }
+    
+    /// As [`SealedMessage::seal_from`], carrying the current time for replay checks
+    ///
+    /// The time takes 8 bytes of the plaintext limit.
+    pub fn seal_from_timestamped(sender: &Keypair, recipient_static: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+        seal_with(Some(sender), recipient_static, Some(now_ms()), plaintext)
+    }
+    
+    /// Decrypt a message sealed to `keypair`
+    ///
+    /// Fails with [`NoiseError::DecryptionFailed`] if the message was sealed
+    /// to another key or altered.
+    pub fn open(keypair: &Keypair, blob: &[u8]) -> Result<OpenedMessage> {
+        let (header, message) = blob.split_first_chunk::<HEADER_LEN>().ok_or(NoiseError::InvalidMessage)?;
+        let [SEALED_VERSION, flags] = *header else {
+            return Err(NoiseError::InvalidMessage);
+        };
+        let params = match flags & !FLAG_TIMESTAMPED {
+            PATTERN_N => N_PARAMS,
+            PATTERN_X => X_PARAMS,
+            _ => return Err(NoiseError::InvalidMessage),
+        };
+        let mut handshake = Builder::new(params.parse()?)
+            .local_private_key(keypair.private_key())?
+            .prologue(&prologue(*header))?
+            .build_responder()?;
+        
+        let mut buffer = Zeroizing::new(vec![0u8; NOISE_MAX_MESSAGE_LEN]);
+        let len = handshake.read_message(message, &mut buffer).map_err(|_| NoiseError::DecryptionFailed)?;
+        let sender = match handshake.get_remote_static() {
+            Some(key) => Some(key.try_into().map_err(|_| NoiseError::InvalidMessage)?),
+            None => None,
+        };
+        let mut payload = &buffer[..len];
+        let sent_at_ms = if flags & FLAG_TIMESTAMPED != 0 {
+            let (timestamp, rest) = payload.split_first_chunk::<TIMESTAMP_LEN>().ok_or(NoiseError::InvalidMessage)?;
+            payload = rest;
+            Some(u64::from_be_bytes(*timestamp))
+        } else {
+            None
+        };
+        Ok(OpenedMessage {
+            plaintext: payload.to_vec(),
+            sender,
+            sent_at_ms,
+        })
+    }
+}
+
+/// Receiver-side replay protection for timestamped sealed messages
+///
+/// Remembers each accepted message by its sender's key and a hash of the
+/// sealed blob until its send time falls out of the window, and rejects
+/// messages sent further than the window from the receiver's clock. Memory
+/// is bounded by the capacity (about 100 bytes per message): when full, the
+/// oldest entry is dropped and every message sent at or before it is
+/// rejected from then on, so a flood costs legitimate late messages rather
+/// than letting replays through.
+///
+/// A fresh cache rejects messages sent before it was created, since it
+/// cannot know which of them were already accepted. A receiver that
+/// restarts loses messages that were in flight rather than accepting
+/// replays of them.
+pub struct SealedReplayCache {
+    window_ms: u64,
+    capacity: usize,
+    seen: HashSet<ReplayKey>,
+    /// The same entries ordered by send time, oldest first
+    by_time: BTreeSet<(u64, ReplayKey)>,
+    /// Latest send time rejected outright: just before the cache was
+    /// created, or the newest entry evicted to stay within capacity
+    floor_ms: u64,
+}
+
+/// Sender's static key (zero for anonymous messages) and blob hash
+type ReplayKey = ([u8; KEY_LEN], [u8; 32]);
+
+impl Default for SealedReplayCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_SEALED_REPLAY_WINDOW, DEFAULT_SEALED_REPLAY_CAPACITY)
+    }
+}
+
+impl SealedReplayCache {
+    /// Create a cache accepting send times within `window` of the local
+    /// clock and remembering at most `capacity` messages
+    pub fn new(window: Duration, capacity: usize) -> Self {
+        Self::new_at(window, capacity, now_ms())
+    }
+    
+    fn new_at(window: Duration, capacity: usize, now_ms: u64) -> Self {
+        Self {
+            window_ms: window.as_millis().try_into().unwrap_or(u64::MAX),
+            capacity: capacity.max(1),
+            seen: HashSet::new(),
+            by_time: BTreeSet::new(),
+            floor_ms: now_ms.saturating_sub(1),
+        }
+    }
+    
+    /// Open a sealed message and check it is not a replay
+    pub fn open(&mut self, keypair: &Keypair, blob: &[u8]) -> Result<OpenedMessage> {
+        let opened = SealedMessage::open(keypair, blob)?;
+        self.check(blob, &opened)?;
+        Ok(opened)
+    }
+    
+    /// Record an opened message, failing if it should not be accepted
+    ///
+    /// Fails with [`NoiseError::ReplayDetected`] if the message was already
+    /// seen or its send time is outside the window, and with
+    /// [`NoiseError::InvalidMessage`] if it was sealed without a timestamp.
+    pub fn check(&mut self, blob: &[u8], opened: &OpenedMessage) -> Result<()> {
+        self.check_at(blob, opened, now_ms())
+    }
+    
+    fn check_at(&mut self, blob: &[u8], opened: &OpenedMessage, now_ms: u64) -> Result<()> {
+        let sent_at = opened.sent_at_ms.ok_or(NoiseError::InvalidMessage)?;
+        if sent_at.abs_diff(now_ms) > self.window_ms {
+            return Err(NoiseError::ReplayDetected);
+        }
+        self.expire(now_ms);
+        if sent_at <= self.floor_ms {
+            return Err(NoiseError::ReplayDetected);
+        }
+        
+        let key = (opened.sender.unwrap_or([0; KEY_LEN]), blob_hash(blob)?);
+        if !self.seen.insert(key) {
+            return Err(NoiseError::ReplayDetected);
+        }
+        self.by_time.insert((sent_at, key));
+        if self.seen.len() > self.capacity {
+            if let Some((evicted_at, evicted)) = self.by_time.pop_first() {
+                self.seen.remove(&evicted);
+                self.floor_ms = self.floor_ms.max(evicted_at);
+            }
+        }
+        Ok(())
+    }
+    
+    /// Forget messages whose send time has left the window
+    fn expire(&mut self, now_ms: u64) {
+        while let Some(&(sent_at, key)) = self.by_time.first() {
+            if now_ms.saturating_sub(sent_at) <= self.window_ms {
+                break;
+            }
+            self.by_time.pop_first();
+            self.seen.remove(&key);
+        }
+    }
+    
+    /// Number of messages currently remembered
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+    
+    /// Whether no messages are remembered
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+}
+
+fn seal_with(sender: Option<&Keypair>, recipient_static: &[u8], sent_at_ms: Option<u64>, plaintext: &[u8]) -> Result<Vec<u8>> {
+    let (max_len, params, mut pattern) = match sender {
+        None => (MAX_SEALED_PLAINTEXT_LEN, N_PARAMS, PATTERN_N),
+        Some(_) => (MAX_SIGNED_SEALED_PLAINTEXT_LEN, X_PARAMS, PATTERN_X),
+    };
+    let mut payload = Zeroizing::new(Vec::with_capacity(TIMESTAMP_LEN + plaintext.len()));
+    if let Some(sent_at_ms) = sent_at_ms {
+        pattern |= FLAG_TIMESTAMPED;
+        payload.extend_from_slice(&sent_at_ms.to_be_bytes());
+    }
+    if payload.len() + plaintext.len() > max_len {
+        return Err(NoiseError::InvalidParameter);
+    }
+    payload.extend_from_slice(plaintext);
+    
+    let mut builder = Builder::new(params.parse()?);
+    if let Some(sender) = sender {
+        builder = builder.local_private_key(sender.private_key())?;
+    }
+    let handshake = builder
+        .remote_public_key(check_key(recipient_static)?)?
+        .prologue(&prologue([SEALED_VERSION, pattern]))?
+        .build_initiator()?;
+    write(handshake, pattern, &payload)
+}
+
+/// Prologue for a message with `header`
+///
+/// Timestamped messages also bind the header, so the flag cannot be
+/// stripped to pass the send time off as plaintext.
+fn prologue(header: [u8; HEADER_LEN]) -> Vec<u8> {
+    let mut prologue = SEALED_PROLOGUE.to_vec();
+    if header[1] & FLAG_TIMESTAMPED != 0 {
+        prologue.extend_from_slice(&header);
+    }
+    prologue
+}
+
+fn blob_hash(blob: &[u8]) -> Result<[u8; 32]> {
+    let mut hash = DefaultResolver
+        .resolve_hash(&HashChoice::Blake2s)
+        .ok_or_else(|| NoiseError::InvalidState("BLAKE2s unavailable".to_string()))?;
+    let mut digest = [0u8; 32];
+    hash.input(blob);
+    hash.result(&mut digest);
+    Ok(digest)
+}
+
+fn check_key(key: &[u8]) -> Result<&[u8]> {
+    if key.len() != KEY_LEN {
+        return Err(NoiseError::InvalidParameter);
+    }
+    Ok(key)
+}
+
+fn write(mut handshake: HandshakeState, pattern: u8, plaintext: &[u8]) -> Result<Vec<u8>> {
+    let mut blob = vec![0u8; HEADER_LEN + NOISE_MAX_MESSAGE_LEN];
+    blob[..HEADER_LEN].copy_from_slice(&[SEALED_VERSION, pattern]);
+    let len = handshake.write_message(plaintext, &mut blob[HEADER_LEN..])?;
+    blob.truncate(HEADER_LEN + len);
+    Ok(blob)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_anonymous_roundtrip() {
+        let recipient = Keypair::generate().unwrap();
+        let blob = SealedMessage::seal(recipient.public_key(), b"you have mail").unwrap();
+        assert_eq!(blob.len(), HEADER_LEN + KEY_LEN + 13 + NOISE_TAG_LEN);
+        
+        let opened = SealedMessage::open(&recipient, &blob).unwrap();
+        assert_eq!(opened.plaintext, b"you have mail");
+        assert_eq!(opened.sender, None);
+        
+        let other = Keypair::generate().unwrap();
+        assert!(matches!(SealedMessage::open(&other, &blob), Err(NoiseError::DecryptionFailed)));
+        let mut tampered = blob.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(SealedMessage::open(&recipient, &tampered).is_err());
+    }
+    
+    #[test]
+    fn test_sender_authenticated() {
+        let sender = Keypair::generate().unwrap();
+        let recipient = Keypair::generate().unwrap();
+        let blob = SealedMessage::seal_from(&sender, recipient.public_key(), b"from alice").unwrap();
+        
+        let opened = SealedMessage::open(&recipient, &blob).unwrap();
+        assert_eq!(opened.plaintext, b"from alice");
+        assert_eq!(opened.sender, Some(*sender.public_key()));
+        
+        assert!(SealedMessage::seal(&[1u8; 16], b"x").is_err());
+        assert!(SealedMessage::seal(recipient.public_key(), &vec![0u8; MAX_SEALED_PLAINTEXT_LEN + 1]).is_err());
+        assert!(SealedMessage::seal(recipient.public_key(), &vec![0u8; MAX_SEALED_PLAINTEXT_LEN]).is_ok());
+        assert!(SealedMessage::open(&recipient, &[SEALED_VERSION, 9, 0]).is_err());
+    }
+    
+    #[test]
+    fn test_timestamped_roundtrip() {
+        let sender = Keypair::generate().unwrap();
+        let recipient = Keypair::generate().unwrap();
+        let blob = SealedMessage::seal_from_timestamped(&sender, recipient.public_key(), b"from alice").unwrap();
+        let opened = SealedMessage::open(&recipient, &blob).unwrap();
+        assert_eq!(opened.plaintext, b"from alice");
+        assert_eq!(opened.sender, Some(*sender.public_key()));
+        assert!(opened.sent_at_ms.unwrap().abs_diff(now_ms()) < 60_000);
+        
+        assert!(SealedMessage::seal_timestamped(recipient.public_key(), &vec![0u8; MAX_SEALED_PLAINTEXT_LEN]).is_err());
+        assert!(SealedMessage::seal_timestamped(recipient.public_key(), &vec![0u8; MAX_SEALED_PLAINTEXT_LEN - TIMESTAMP_LEN]).is_ok());
+        
+        // The timestamp flag is authenticated along with the payload
+        let mut stripped = blob.clone();
+        stripped[1] &= !FLAG_TIMESTAMPED;
+        assert!(matches!(SealedMessage::open(&recipient, &stripped), Err(NoiseError::DecryptionFailed)));
+        
+        let plain = SealedMessage::seal(recipient.public_key(), b"x").unwrap();
+        let mut flagged = plain.clone();
+        flagged[1] |= FLAG_TIMESTAMPED;
+        assert!(SealedMessage::open(&recipient, &flagged).is_err());
+        assert_eq!(SealedMessage::open(&recipient, &plain).unwrap().sent_at_ms, None);
+        assert!(matches!(SealedReplayCache::default().open(&recipient, &plain), Err(NoiseError::InvalidMessage)));
+    }
+    
+    #[test]
+    fn test_replay_cache() {
+        let recipient = Keypair::generate().unwrap();
+        let mut cache = SealedReplayCache::default();
+        let blob = SealedMessage::seal_timestamped(recipient.public_key(), b"ping").unwrap();
+        assert_eq!(cache.open(&recipient, &blob).unwrap().plaintext, b"ping");
+        assert!(matches!(cache.open(&recipient, &blob), Err(NoiseError::ReplayDetected)));
+        
+        // The same plaintext sealed again is a different message
+        let again = SealedMessage::seal_timestamped(recipient.public_key(), b"ping").unwrap();
+        assert!(cache.open(&recipient, &again).is_ok());
+        assert_eq!(cache.len(), 2);
+    }
+    
+    #[test]
+    fn test_replay_cache_window_and_capacity() {
+        let recipient = Keypair::generate().unwrap();
+        let now = 1_000_000;
+        let mut cache = SealedReplayCache::new_at(Duration::from_secs(10), 2, now - 10_000);
+        let sealed_at = |sent_at_ms| {
+            let blob = seal_with(None, recipient.public_key(), Some(sent_at_ms), b"m").unwrap();
+            let opened = SealedMessage::open(&recipient, &blob).unwrap();
+            (blob, opened)
+        };
+        
+        let (old, old_opened) = sealed_at(now - 10_001);
+        assert!(matches!(cache.check_at(&old, &old_opened, now), Err(NoiseError::ReplayDetected)));
+        let (future, future_opened) = sealed_at(now + 10_001);
+        assert!(matches!(cache.check_at(&future, &future_opened, now), Err(NoiseError::ReplayDetected)));
+        
+        let (a, a_opened) = sealed_at(now - 5_000);
+        let (b, b_opened) = sealed_at(now - 4_000);
+        let (c, c_opened) = sealed_at(now - 3_000);
+        cache.check_at(&a, &a_opened, now).unwrap();
+        cache.check_at(&b, &b_opened, now).unwrap();
+        cache.check_at(&c, &c_opened, now).unwrap();
+        assert_eq!(cache.len(), 2);
+        
+        // Evicting `a` raised the floor, so it stays rejected
+        assert!(matches!(cache.check_at(&a, &a_opened, now), Err(NoiseError::ReplayDetected)));
+        let (late, late_opened) = sealed_at(now - 6_000);
+        assert!(cache.check_at(&late, &late_opened, now).is_err());
+        
+        // Entries expire with the window
+        assert!(cache.check_at(&c, &c_opened, now + 6_000).is_err());
+        let (d, d_opened) = sealed_at(now + 8_000);
+        cache.check_at(&d, &d_opened, now + 8_000).unwrap();
+        assert!(cache.len() <= 2);
+    }
+    
+    #[test]
+    fn test_fresh_cache_rejects_earlier_messages() {
+        let recipient = Keypair::generate().unwrap();
+        let now = 1_000_000;
+        let mut cache = SealedReplayCache::new_at(DEFAULT_SEALED_REPLAY_WINDOW, 16, now);
+        
+        // Sent before a restart: possibly accepted by the previous cache
+        let before = seal_with(None, recipient.public_key(), Some(now - 1), b"m").unwrap();
+        let opened = SealedMessage::open(&recipient, &before).unwrap();
+        assert!(matches!(cache.check_at(&before, &opened, now + 1), Err(NoiseError::ReplayDetected)));
+        
+        let after = seal_with(None, recipient.public_key(), Some(now), b"m").unwrap();
+        let opened = SealedMessage::open(&recipient, &after).unwrap();
+        cache.check_at(&after, &opened, now + 1).unwrap();
+    }
+}
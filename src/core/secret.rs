@@ -0,0 +1,242 @@
+//! Secret bytes kept out of swap
+//!
+//! [`SecretBytes`] holds this crate's copies of private keys and PSKs. The
+//! bytes are wiped when dropped, and with the `mlock` feature the pages
+//! holding them are locked into RAM with `mlock` (or `VirtualLock` on
+//! Windows), so a rooted or jailbroken device cannot recover them from swap.
+//!
+//! This does not cover key material held by snow: each handshake and
+//! transport state keeps its own copy of the static key and cipher keys in
+//! ordinary memory, which this crate can neither lock nor wipe.
+//!
+//! Locking is best effort: the OS may refuse once the process reaches its
+//! locked-memory limit, in which case the secret is still usable and
+//! [`SecretBytes::is_locked`] reports `false`.
+
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use zeroize::Zeroize;
+
+/// A heap buffer of secret bytes, wiped on drop and locked into RAM where
+/// supported
+pub struct SecretBytes {
+    data: Box<[u8]>,
+    locked: bool,
+}
+
+impl SecretBytes {
+    /// Copy `secret` into locked memory
+    ///
+    /// The caller remains responsible for wiping its own copy.
+    pub fn new(secret: &[u8]) -> Self {
+        let mut bytes = Self::zeroed(secret.len());
+        bytes.data.copy_from_slice(secret);
+        bytes
+    }
+    
+    /// `len` zero bytes, locked before anything secret is written to them
+    pub fn zeroed(len: usize) -> Self {
+        let data = vec![0u8; len].into_boxed_slice();
+        let locked = memlock::lock(&data);
+        Self { data, locked }
+    }
+    
+    /// Move a secret out of a vector, wiping the vector's buffer
+    pub fn from_vec(mut secret: Vec<u8>) -> Self {
+        let bytes = Self::new(&secret);
+        secret.zeroize();
+        bytes
+    }
+    
+    /// Whether the OS agreed to keep these bytes out of swap
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+}
+
+impl Deref for SecretBytes {
+    type Target = [u8];
+    
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl DerefMut for SecretBytes {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+}
+
+impl AsRef<[u8]> for SecretBytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl From<&[u8]> for SecretBytes {
+    fn from(secret: &[u8]) -> Self {
+        Self::new(secret)
+    }
+}
+
+impl Clone for SecretBytes {
+    fn clone(&self) -> Self {
+        Self::new(&self.data)
+    }
+}
+
+impl Zeroize for SecretBytes {
+    fn zeroize(&mut self) {
+        self.data.zeroize();
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        self.data.zeroize();
+        if self.locked {
+            memlock::unlock(&self.data);
+        }
+    }
+}
+
+impl fmt::Debug for SecretBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecretBytes").field("len", &self.data.len()).finish_non_exhaustive()
+    }
+}
+
+#[cfg(all(feature = "mlock", any(unix, windows)))]
+mod memlock {
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+    
+    /// Secrets on each locked page; small secrets share pages, and a page
+    /// is unlocked only when the last of them is dropped
+    static PAGES: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+    
+    pub(super) fn lock(data: &[u8]) -> bool {
+        if data.is_empty() {
+            return false;
+        }
+        let mut pages = PAGES.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        // Locking is idempotent, so pages already locked may be locked again
+        if !os::lock(data.as_ptr(), data.len()) {
+            return false;
+        }
+        for page in pages_of(data) {
+            *pages.entry(page).or_default() += 1;
+        }
+        true
+    }
+    
+    pub(super) fn unlock(data: &[u8]) {
+        let mut pages = PAGES.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for page in pages_of(data) {
+            match pages.get_mut(&page) {
+                Some(count) if *count > 1 => *count -= 1,
+                _ => {
+                    pages.remove(&page);
+                    os::unlock(page as *const u8, os::page_size());
+                }
+            }
+        }
+    }
+    
+    fn pages_of(data: &[u8]) -> impl Iterator<Item = usize> {
+        let page_size = os::page_size();
+        let start = data.as_ptr() as usize / page_size * page_size;
+        let end = data.as_ptr() as usize + data.len();
+        (start..end).step_by(page_size)
+    }
+    
+    #[cfg(test)]
+    pub(super) fn lockers(data: &[u8]) -> usize {
+        let pages = PAGES.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        pages_of(data).filter_map(|page| pages.get(&page)).copied().min().unwrap_or(0)
+    }
+    
+    #[cfg(unix)]
+    mod os {
+        pub(super) fn lock(ptr: *const u8, len: usize) -> bool {
+            unsafe { libc::mlock(ptr.cast(), len) == 0 }
+        }
+        
+        pub(super) fn unlock(ptr: *const u8, len: usize) {
+            unsafe { libc::munlock(ptr.cast(), len) };
+        }
+        
+        pub(super) fn page_size() -> usize {
+            match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+                size if size > 0 => size as usize,
+                _ => 4096,
+            }
+        }
+    }
+    
+    #[cfg(windows)]
+    mod os {
+        use std::ffi::c_void;
+        
+        #[link(name = "kernel32")]
+        extern "system" {
+            fn VirtualLock(address: *const c_void, size: usize) -> i32;
+            fn VirtualUnlock(address: *const c_void, size: usize) -> i32;
+        }
+        
+        pub(super) fn lock(ptr: *const u8, len: usize) -> bool {
+            unsafe { VirtualLock(ptr.cast(), len) != 0 }
+        }
+        
+        pub(super) fn unlock(ptr: *const u8, len: usize) {
+            unsafe { VirtualUnlock(ptr.cast(), len) };
+        }
+        
+        pub(super) fn page_size() -> usize {
+            // Every Windows target Rust supports uses 4 KiB pages
+            4096
+        }
+    }
+}
+
+#[cfg(not(all(feature = "mlock", any(unix, windows))))]
+mod memlock {
+    pub(super) fn lock(_data: &[u8]) -> bool {
+        false
+    }
+    
+    pub(super) fn unlock(_data: &[u8]) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_secret_bytes() {
+        let secret = SecretBytes::new(&[7u8; 32]);
+        assert_eq!(&secret[..], &[7u8; 32]);
+        assert_eq!(secret.clone().as_ref(), &[7u8; 32]);
+        assert_eq!(SecretBytes::from_vec(vec![1, 2, 3]).len(), 3);
+        assert!(!format!("{:?}", secret).contains('7'));
+        assert!(!SecretBytes::zeroed(0).is_locked());
+        #[cfg(not(feature = "mlock"))]
+        assert!(!secret.is_locked());
+    }
+    
+    #[cfg(all(feature = "mlock", any(unix, windows)))]
+    #[test]
+    fn test_shared_pages_stay_locked() {
+        let first = SecretBytes::new(&[1u8; 32]);
+        if !first.is_locked() {
+            // The sandbox's locked-memory limit is exhausted
+            return;
+        }
+        let second = SecretBytes::new(&[2u8; 32]);
+        let before = memlock::lockers(&first);
+        drop(second);
+        assert!(memlock::lockers(&first) >= 1);
+        assert!(memlock::lockers(&first) <= before);
+    }
+}
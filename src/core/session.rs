@@ -1,12 +1,84 @@
+use crate::core::buffer_pool::BufferPool;
+use crate::core::crypto::{handshake_message_count, hkdf, rekey_chachapoly, NOISE_MAX_PAYLOAD_LEN, NOISE_NONCE_LIMIT, NOISE_TAG_LEN};
 use crate::core::error::{NoiseError, Result};
-use snow::{Builder, HandshakeState, TransportState};
-use zeroize::Zeroize;
+use crate::core::keys::{keys_equal, public_from_private, validate_private_key, validate_public_key, PublicKey, SecretKey, KEY_LEN};
+use crate::core::logging::{log_event, LogLevel};
+use crate::core::padding::{unpadded_len, PaddingScheme};
+use crate::core::secret::SecretBytes;
+use crate::core::payload::{HandshakePayload, PayloadValidator, RawPayloadValidator};
+use crate::core::signing::{IdentityVerifier, SigningIdentity, StaticKeyCertificate, CERTIFICATE_LEN, IDENTITY_KEY_LEN};
+use snow::params::{CipherChoice, HandshakeModifier, NoiseParams};
+use snow::resolvers::BoxedCryptoResolver;
+use snow::{Builder, HandshakeState, StatelessTransportState, TransportState};
+use std::io::IoSlice;
+use std::time::{Duration, Instant};
+use zeroize::{Zeroize, Zeroizing};
+
+/// Room for keys and tags around a handshake payload; hybrid Kyber1024
+/// ciphertexts are the largest
+const HANDSHAKE_OVERHEAD: usize = 4096;
+
+/// Scratch buffers larger than this are released once only small messages use them
+const BUFFER_SHRINK_THRESHOLD: usize = 4096;
+
+/// Consecutive small uses before an oversized scratch buffer is released
+const BUFFER_SHRINK_AFTER: u32 = 16;
+
+/// Domain separator for the secret behind [`NoiseSession::export_key`]
+const EXPORTER_LABEL: &[u8] = b"noise-mobile/exporter/v1";
+
+/// Length of the exporter secret
+const EXPORTER_SECRET_LEN: usize = 32;
+
+/// Domain separator for [`NoiseSession::session_id`]
+const SESSION_ID_LABEL: &[u8] = b"noise-mobile/session-id/v1";
+
+/// Length of a [`SessionId`]; short enough that log scrubbing keeps its hex
+pub const SESSION_ID_LEN: usize = 8;
+
+/// Domain separator for keys from [`NoiseSessionBuilder::deterministic`]
+#[cfg(any(test, feature = "test-utils"))]
+const DETERMINISTIC_SALT: &[u8] = b"noise-mobile/deterministic/v1";
 
 /// Represents a Noise Protocol session that can be either in handshake or transport mode
 pub struct NoiseSession {
     state: NoiseState,
+    params: NoiseParams,
+    /// Scratch space for handshake messages and padding, grown on demand
     buffer: Vec<u8>,
+    /// Longest message this session will write or accept
+    max_message_len: usize,
+    /// Consecutive scratch uses far below the buffer's size
+    small_buffer_uses: u32,
+    /// Shared scratch space used instead of `buffer` in transport mode
+    buffer_pool: Option<BufferPool>,
     remote_static: Option<Vec<u8>>,
+    local_static: Option<Vec<u8>>,
+    /// Key the peer must present, checked as soon as it arrives
+    expected_remote_static: Option<Vec<u8>>,
+    handshake_hash: Option<Vec<u8>>,
+    session_id: Option<SessionId>,
+    /// Secret behind [`NoiseSession::export_key`], fixed when the handshake completes
+    exporter_secret: Option<Zeroizing<Vec<u8>>>,
+    /// Certificate for our static key, sent with our last handshake message
+    identity_certificate: Option<StaticKeyCertificate>,
+    /// Checks the certificate expected with the peer's last handshake message
+    identity_verifier: Option<IdentityVerifier>,
+    remote_identity: Option<[u8; IDENTITY_KEY_LEN]>,
+    payload_validator: Option<RawPayloadValidator>,
+    nonce_limit: u64,
+    /// Padding applied to transport messages in both directions
+    padding: Option<PaddingScheme>,
+    handshake_messages: usize,
+    handshake_index: usize,
+    sending_nonce: u64,
+    receiving_nonce: u64,
+    sending_generation: u32,
+    receiving_generation: u32,
+    transport_keys: Option<TransportKeys>,
+    stats: SessionStats,
+    /// When the first handshake message was written or read
+    handshake_started: Option<Instant>,
 }
 
 /// The current state of a Noise session
@@ -14,11 +86,340 @@ pub enum NoiseState {
     /// Session is in handshake phase
     Handshake(Box<HandshakeState>),
     /// Session is in transport phase (handshake complete)
-    Transport(Box<TransportState>),
+    ///
+    /// Nonces are tracked by the session rather than snow so they can be
+    /// persisted and restored.
+    Transport(Box<StatelessTransportState>),
+    /// Transport phase adopted from a stateful snow session
+    ///
+    /// Raw keys are unavailable, so snapshots and explicit-nonce generation
+    /// resync are not supported.
+    StatefulTransport(Box<TransportState>),
     /// Temporary state during transition
     Transitioning,
 }
 
+/// Raw transport cipher keys, retained so a session can be snapshotted
+#[derive(Clone, Zeroize)]
+#[zeroize(drop)]
+pub(crate) struct TransportKeys {
+    /// Key for initiator -> responder traffic
+    pub(crate) initiator: [u8; 32],
+    /// Key for responder -> initiator traffic
+    pub(crate) responder: [u8; 32],
+}
+
+/// Everything needed to rebuild a transport-mode session
+pub(crate) struct TransportSnapshot {
+    pub(crate) protocol_name: String,
+    pub(crate) is_initiator: bool,
+    pub(crate) keys: TransportKeys,
+    pub(crate) sending_nonce: u64,
+    pub(crate) receiving_nonce: u64,
+    pub(crate) sending_generation: u32,
+    pub(crate) receiving_generation: u32,
+    pub(crate) remote_static: Option<Vec<u8>>,
+    pub(crate) local_static: Option<Vec<u8>>,
+    pub(crate) exporter_secret: Option<Zeroizing<Vec<u8>>>,
+}
+
+/// Traffic counters for one session, see [`NoiseSession::stats`]
+///
+/// Counters start at zero for every session object, including sessions
+/// restored from a snapshot or ticket; they are not persisted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SessionStats {
+    /// Transport messages encrypted
+    pub messages_sent: u64,
+    /// Transport messages decrypted
+    pub messages_received: u64,
+    /// Ciphertext bytes produced, tags and padding included
+    pub bytes_sent: u64,
+    /// Ciphertext bytes accepted, tags and padding included
+    pub bytes_received: u64,
+    /// Transport messages that failed authentication or had bad padding
+    pub decrypt_failures: u64,
+    /// Rekeys applied in either direction
+    pub rekeys: u64,
+    /// Time from the first handshake message to the last, once complete
+    pub handshake_duration: Option<Duration>,
+}
+
+impl SessionStats {
+    /// Add another session's counters to these, keeping the longer handshake
+    pub fn merge(&mut self, other: &SessionStats) {
+        self.messages_sent += other.messages_sent;
+        self.messages_received += other.messages_received;
+        self.bytes_sent += other.bytes_sent;
+        self.bytes_received += other.bytes_received;
+        self.decrypt_failures += other.decrypt_failures;
+        self.rekeys += other.rekeys;
+        self.handshake_duration = self.handshake_duration.max(other.handshake_duration);
+    }
+}
+
+/// Short public identifier of a session, see [`NoiseSession::session_id`]
+///
+/// Displays as lowercase hex.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SessionId([u8; SESSION_ID_LEN]);
+
+impl SessionId {
+    /// The raw identifier
+    pub fn as_bytes(&self) -> &[u8; SESSION_ID_LEN] {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for SessionId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{:02x}", b))
+    }
+}
+
+/// Configures a [`NoiseSession`] before its handshake starts
+#[derive(Default)]
+pub struct NoiseSessionBuilder {
+    private_key: Option<SecretKey>,
+    binding: Vec<u8>,
+    expected_remote_static: Option<Vec<u8>>,
+    identity: Option<SigningIdentity>,
+    identity_verifier: Option<IdentityVerifier>,
+    payload_validator: Option<PayloadValidator>,
+    resolver: Option<BoxedCryptoResolver>,
+    ephemeral_key: Option<SecretBytes>,
+    max_message_len: Option<usize>,
+    protocol: Option<String>,
+    remote_static: Option<Vec<u8>>,
+    psks: Vec<(u8, SecretBytes)>,
+}
+
+impl NoiseSessionBuilder {
+    /// Start with a fresh identity and no binding
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Use this static private key instead of generating one
+    pub fn private_key(mut self, private_key: &SecretKey) -> Self {
+        self.private_key = Some(private_key.clone());
+        self
+    }
+    
+    /// Bind the handshake to the transport, as in [`NoiseSession::with_channel_binding`]
+    pub fn channel_binding(mut self, binding: &[u8]) -> Self {
+        self.binding = binding.to_vec();
+        self
+    }
+    
+    /// Run this protocol instead of [`NoiseSession::NOISE_PARAMS`]
+    ///
+    /// Building fails if the name does not parse, or if the pattern needs a
+    /// key or PSK that was not supplied.
+    pub fn protocol(mut self, protocol: &str) -> Self {
+        self.protocol = Some(protocol.to_string());
+        self
+    }
+    
+    /// The peer's static key, known in advance
+    ///
+    /// Patterns such as IK send to this key from the first message; for
+    /// patterns that learn the key during the handshake it is pinned as
+    /// with [`expect_remote_static`](Self::expect_remote_static).
+    pub fn remote_static(mut self, remote_static: &[u8]) -> Self {
+        self.remote_static = Some(remote_static.to_vec());
+        self
+    }
+    
+    /// Mix a 32-byte pre-shared key in at `location`, for `psk` patterns
+    ///
+    /// Both peers must supply the same PSK or the handshake fails.
+    pub fn psk(mut self, location: u8, psk: &[u8]) -> Self {
+        self.psks.push((location, SecretBytes::new(psk)));
+        self
+    }
+    
+    /// Cap the length of messages, as in [`NoiseSession::set_max_message_len`]
+    pub fn max_message_len(mut self, len: usize) -> Self {
+        self.max_message_len = Some(len);
+        self
+    }
+    
+    /// Pin the peer's static key
+    ///
+    /// If the peer presents any other key the handshake fails with
+    /// [`NoiseError::PeerKeyMismatch`] and the session becomes unusable.
+    /// Building fails with [`NoiseError::InvalidKey`] if `remote_static` is
+    /// a low-order or non-canonical key, see [`validate_public_key`].
+    pub fn expect_remote_static(mut self, remote_static: &[u8]) -> Self {
+        self.expected_remote_static = Some(remote_static.to_vec());
+        self
+    }
+    
+    /// Vouch for the static key with a long-term Ed25519 identity
+    ///
+    /// The certificate is prepended to the payload of the last handshake
+    /// message this side sends, so the peer must use
+    /// [`verify_identity`](Self::verify_identity).
+    pub fn identity(mut self, identity: SigningIdentity) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+    
+    /// Require a certificate from the peer and pass it to `verifier`
+    ///
+    /// The certificate is taken off the front of the payload of the peer's
+    /// last handshake message. The handshake fails with
+    /// [`NoiseError::HandshakeFailed`] if it is missing, badly signed or for
+    /// another static key, and with [`NoiseError::PeerKeyMismatch`] if
+    /// `verifier` returns `false`.
+    pub fn verify_identity(mut self, verifier: impl Fn(&StaticKeyCertificate) -> bool + Send + Sync + 'static) -> Self {
+        self.identity_verifier = Some(std::sync::Arc::new(verifier));
+        self
+    }
+    
+    /// Check every handshake payload from the peer, as in
+    /// [`NoiseSession::set_payload_validator`]
+    pub fn validate_payload(
+        mut self,
+        validator: impl Fn(&HandshakePayload, Option<&[u8]>) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.payload_validator = Some(std::sync::Arc::new(validator));
+        self
+    }
+    
+    /// Use `resolver` for the handshake and transport primitives
+    ///
+    /// See [`crate::core::provider`] for what it covers.
+    pub fn resolver(mut self, resolver: BoxedCryptoResolver) -> Self {
+        self.resolver = Some(resolver);
+        self
+    }
+    
+    /// Use this ephemeral private key instead of a random one
+    ///
+    /// For test vectors and reproducible handshakes only: reusing an
+    /// ephemeral key across handshakes forfeits forward secrecy. Only built
+    /// with the `test-utils` feature.
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn fixed_ephemeral(mut self, private_key: &[u8]) -> Self {
+        self.ephemeral_key = Some(SecretBytes::new(private_key));
+        self
+    }
+    
+    /// Derive the static and ephemeral keys from `seed`
+    ///
+    /// Two builds with the same seed produce byte-identical handshakes, so
+    /// give each side its own seed. A key set with
+    /// [`private_key`](Self::private_key) is kept. For tests only, as with
+    /// [`fixed_ephemeral`](Self::fixed_ephemeral), and likewise only built
+    /// with the `test-utils` feature.
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn deterministic(mut self, seed: &[u8]) -> Result<Self> {
+        let derive = |info: &[u8]| {
+            hkdf(snow::params::HashChoice::SHA256, DETERMINISTIC_SALT, seed, info, KEY_LEN).map(SecretBytes::from_vec)
+        };
+        if self.private_key.is_none() {
+            self.private_key = Some(SecretKey::from_bytes(&derive(b"static")?)?);
+        }
+        self.ephemeral_key = Some(derive(b"ephemeral")?);
+        Ok(self)
+    }
+    
+    /// Build the session as initiator
+    pub fn build_initiator(self) -> Result<NoiseSession> {
+        self.build(true)
+    }
+    
+    /// Build the session as responder
+    pub fn build_responder(self) -> Result<NoiseSession> {
+        self.build(false)
+    }
+    
+    fn build(self, is_initiator: bool) -> Result<NoiseSession> {
+        for remote in self.expected_remote_static.iter().chain(&self.remote_static) {
+            validate_public_key(remote)?;
+        }
+        let private_key = self.private_key.unwrap_or_else(SecretKey::generate);
+        let protocol = self.protocol.as_deref().unwrap_or(NoiseSession::NOISE_PARAMS);
+        let params: NoiseParams = protocol.parse()?;
+        // snow would only notice a missing PSK at its position in the handshake
+        let missing_psk = params.handshake.modifiers.list.iter().any(|modifier| match modifier {
+            HandshakeModifier::Psk(location) => !self.psks.iter().any(|(supplied, _)| supplied == location),
+            _ => false,
+        });
+        if missing_psk {
+            return Err(NoiseError::InvalidParameter);
+        }
+        let known_remote = self.remote_static.as_deref()
+            .filter(|_| params.handshake.pattern.need_known_remote_pubkey(is_initiator));
+        let extra = ExtraKeys {
+            ephemeral: self.ephemeral_key.as_deref(),
+            psks: &self.psks,
+        };
+        
+        let mut session = NoiseSession::with_resolver(
+            self.resolver,
+            protocol,
+            private_key.as_bytes(),
+            known_remote,
+            is_initiator,
+            &self.binding,
+            extra,
+        )?;
+        session.expected_remote_static = self.expected_remote_static.or(self.remote_static);
+        if let (Some(identity), Some(local_static)) = (&self.identity, &session.local_static) {
+            session.identity_certificate = Some(identity.certify(local_static)?);
+        }
+        session.identity_verifier = self.identity_verifier;
+        if let Some(len) = self.max_message_len {
+            session.set_max_message_len(len);
+        }
+        if let Some(validator) = self.payload_validator {
+            session.payload_validator = Some(structured_validator(validator));
+        }
+        Ok(session)
+    }
+}
+
+/// Keys for [`NoiseSession::with_resolver`] beyond the static ones
+#[derive(Default)]
+pub(crate) struct ExtraKeys<'a> {
+    /// Fixed ephemeral private key, for tests only
+    pub(crate) ephemeral: Option<&'a [u8]>,
+    /// Pre-shared keys by handshake location
+    pub(crate) psks: &'a [(u8, SecretBytes)],
+}
+
+/// Adapt a [`HandshakePayload`] validator to raw payload bytes
+///
+/// Payloads that fail to decode are rejected.
+fn structured_validator(validator: PayloadValidator) -> RawPayloadValidator {
+    std::sync::Arc::new(move |payload, remote_static| {
+        HandshakePayload::decode(payload).is_ok_and(|payload| validator(&payload, remote_static))
+    })
+}
+
+/// Check the certificate at the front of the peer's last handshake payload
+fn check_certificate(
+    payload: &[u8],
+    remote_static: Option<&[u8]>,
+    verifier: &IdentityVerifier,
+) -> Result<[u8; IDENTITY_KEY_LEN]> {
+    let certificate = payload
+        .get(..CERTIFICATE_LEN)
+        .and_then(|data| StaticKeyCertificate::from_bytes(data).ok())
+        .ok_or(NoiseError::HandshakeFailed)?;
+    let for_remote_static = remote_static.is_some_and(|key| keys_equal(key, &certificate.static_key));
+    if !for_remote_static || !certificate.verify() {
+        return Err(NoiseError::HandshakeFailed);
+    }
+    if !verifier(&certificate) {
+        return Err(NoiseError::PeerKeyMismatch);
+    }
+    Ok(certificate.identity_key)
+}
+
 impl Drop for NoiseSession {
     fn drop(&mut self) {
         self.buffer.zeroize();
@@ -35,60 +436,331 @@ impl NoiseSession {
     /// Noise protocol pattern (XX provides mutual authentication)
     pub const NOISE_PARAMS: &'static str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
     
+    /// Length of the `generation || nonce` header on explicit-nonce frames
+    pub const EXPLICIT_HEADER_LEN: usize = 12;
+    
+    /// Length of the `count || index` header on each message of [`NoiseSession::encrypt_large`]
+    pub const LARGE_HEADER_LEN: usize = 8;
+    
+    /// Bytes of a large payload carried per message, leaving room for padding's marker byte
+    pub const LARGE_CHUNK_LEN: usize = NOISE_MAX_PAYLOAD_LEN - Self::LARGE_HEADER_LEN - 1;
+    
+    /// Most rekeys a receiver will catch up on from a single explicit-nonce frame
+    pub const MAX_GENERATION_SKIP: u32 = 16;
+    
     /// Create a new Noise session as initiator
     pub fn new_initiator() -> Result<Self> {
-        let params = Self::NOISE_PARAMS.parse()?;
-        let builder = Builder::new(params);
+        let params: NoiseParams = Self::NOISE_PARAMS.parse()?;
+        let builder = Builder::new(params.clone());
         let keypair = builder.generate_keypair()?;
         
         let handshake = builder
             .local_private_key(&keypair.private)?
             .build_initiator()?;
-            
-        Ok(NoiseSession {
-            state: NoiseState::Handshake(Box::new(handshake)),
-            buffer: vec![0u8; Self::MAX_MESSAGE_LEN],
-            remote_static: None,
-        })
+        
+        let mut session = Self::from_handshake(handshake, &params);
+        session.local_static = Some(keypair.public);
+        Ok(session)
     }
     
     /// Create a new Noise session as responder
     pub fn new_responder() -> Result<Self> {
-        let params = Self::NOISE_PARAMS.parse()?;
-        let builder = Builder::new(params);
+        let params: NoiseParams = Self::NOISE_PARAMS.parse()?;
+        let builder = Builder::new(params.clone());
         let keypair = builder.generate_keypair()?;
         
         let handshake = builder
             .local_private_key(&keypair.private)?
             .build_responder()?;
-            
-        Ok(NoiseSession {
-            state: NoiseState::Handshake(Box::new(handshake)),
-            buffer: vec![0u8; Self::MAX_MESSAGE_LEN],
-            remote_static: None,
-        })
+        
+        let mut session = Self::from_handshake(handshake, &params);
+        session.local_static = Some(keypair.public);
+        Ok(session)
     }
     
     /// Create a new Noise session with a specific private key
-    pub fn with_private_key(private_key: &[u8], is_initiator: bool) -> Result<Self> {
-        let params = Self::NOISE_PARAMS.parse()?;
-        let builder = Builder::new(params);
+    ///
+    /// Fails with [`NoiseError::InvalidKey`] for the all-zero key, see
+    /// [`validate_private_key`].
+    pub fn with_private_key(private_key: &SecretKey, is_initiator: bool) -> Result<Self> {
+        Self::with_channel_binding(private_key, is_initiator, &[])
+    }
+    
+    /// Create a new Noise session bound to the underlying transport
+    ///
+    /// `binding` is used as the handshake prologue, typically built with
+    /// [`crate::core::crypto::channel_binding`]. Both peers must supply the
+    /// same binding or the handshake fails.
+    pub fn with_channel_binding(private_key: &SecretKey, is_initiator: bool, binding: &[u8]) -> Result<Self> {
+        Self::with_protocol(Self::NOISE_PARAMS, private_key.as_bytes(), None, is_initiator, binding)
+    }
+    
+    /// Create a session for another handshake pattern, e.g. IK for Noise Pipes
+    pub(crate) fn with_protocol(
+        protocol: &str,
+        private_key: &[u8],
+        remote_static: Option<&[u8]>,
+        is_initiator: bool,
+        prologue: &[u8],
+    ) -> Result<Self> {
+        Self::with_resolver(None, protocol, private_key, remote_static, is_initiator, prologue, ExtraKeys::default())
+    }
+    
+    /// [`with_protocol`](Self::with_protocol) on an integrator's crypto
+    /// provider, or snow's built-in one for `None`, optionally with a fixed
+    /// ephemeral key and PSKs
+    pub(crate) fn with_resolver(
+        resolver: Option<BoxedCryptoResolver>,
+        protocol: &str,
+        private_key: &[u8],
+        remote_static: Option<&[u8]>,
+        is_initiator: bool,
+        prologue: &[u8],
+        extra: ExtraKeys<'_>,
+    ) -> Result<Self> {
+        validate_private_key(private_key)?;
+        if let Some(remote_static) = remote_static {
+            validate_public_key(remote_static)?;
+        }
+        let params: NoiseParams = protocol.parse()?;
+        let builder = match resolver {
+            Some(resolver) => Builder::with_resolver(params.clone(), resolver),
+            None => Builder::new(params.clone()),
+        };
+        let mut builder = builder
+            .local_private_key(private_key)?
+            .prologue(prologue)?;
+        if let Some(remote_static) = remote_static {
+            builder = builder.remote_public_key(remote_static)?;
+        }
+        if let Some(ephemeral_key) = extra.ephemeral {
+            if ephemeral_key.len() != KEY_LEN {
+                return Err(NoiseError::InvalidParameter);
+            }
+            builder = builder.fixed_ephemeral_key_for_testing_only(ephemeral_key)?;
+        }
+        for (location, psk) in extra.psks {
+            if psk.len() != KEY_LEN {
+                return Err(NoiseError::InvalidParameter);
+            }
+            builder = builder.psk(*location, psk)?;
+        }
         
         let handshake = if is_initiator {
-            builder
-                .local_private_key(private_key)?
-                .build_initiator()?
+            builder.build_initiator()?
         } else {
-            builder
-                .local_private_key(private_key)?
-                .build_responder()?
+            builder.build_responder()?
         };
-            
-        Ok(NoiseSession {
+        
+        let mut session = Self::from_handshake(handshake, &params);
+        session.local_static = Some(public_from_private(private_key)?.to_vec());
+        Ok(session)
+    }
+    
+    /// Create a handshake keyed by a PSK alone, for ticket resumption
+    ///
+    /// `remote_static` is the key the peer authenticated with in the session
+    /// the PSK came from, reported by [`get_remote_static`](Self::get_remote_static).
+    #[cfg(feature = "tickets")]
+    pub(crate) fn with_resumption_psk(
+        protocol: &str,
+        psk: &[u8],
+        is_initiator: bool,
+        remote_static: Option<&[u8]>,
+    ) -> Result<Self> {
+        let params: NoiseParams = protocol.parse()?;
+        let builder = Builder::new(params.clone()).psk(0, psk)?;
+        let handshake = if is_initiator {
+            builder.build_initiator()?
+        } else {
+            builder.build_responder()?
+        };
+        
+        let mut session = Self::from_handshake(handshake, &params);
+        session.remote_static = remote_static.map(<[u8]>::to_vec);
+        Ok(session)
+    }
+    
+    /// Adopt a handshake already set up with snow
+    ///
+    /// `params` must be the parameters the handshake was built with. The
+    /// handshake is assumed not to have started for round-trip estimates.
+    pub fn from_snow_handshake(handshake: HandshakeState, params: &NoiseParams) -> Result<Self> {
+        let finished = handshake.is_handshake_finished();
+        let mut session = Self::from_handshake(handshake, params);
+        if finished {
+            session.finish_handshake()?;
+        }
+        Ok(session)
+    }
+    
+    /// Adopt a transport-mode session established with snow
+    ///
+    /// Nonces carry over from the snow state. `params` must be the
+    /// parameters the session was built with.
+    pub fn from_snow_transport(transport: TransportState, params: &NoiseParams) -> Self {
+        let mut session = Self::from_handshake_params(params);
+        session.remote_static = transport.get_remote_static().map(|k| k.to_vec());
+        session.sending_nonce = transport.sending_nonce();
+        session.receiving_nonce = transport.receiving_nonce();
+        session.state = NoiseState::StatefulTransport(Box::new(transport));
+        session
+    }
+    
+    /// Wrap a freshly built handshake state
+    fn from_handshake(handshake: HandshakeState, params: &NoiseParams) -> Self {
+        NoiseSession {
             state: NoiseState::Handshake(Box::new(handshake)),
-            buffer: vec![0u8; Self::MAX_MESSAGE_LEN],
+            params: params.clone(),
+            buffer: Vec::new(),
+            max_message_len: Self::MAX_MESSAGE_LEN,
+            small_buffer_uses: 0,
+            buffer_pool: None,
             remote_static: None,
-        })
+            local_static: None,
+            expected_remote_static: None,
+            handshake_hash: None,
+            session_id: None,
+            exporter_secret: None,
+            identity_certificate: None,
+            identity_verifier: None,
+            remote_identity: None,
+            payload_validator: None,
+            nonce_limit: NOISE_NONCE_LIMIT,
+            padding: None,
+            handshake_messages: handshake_message_count(params.handshake.pattern),
+            handshake_index: 0,
+            sending_nonce: 0,
+            receiving_nonce: 0,
+            sending_generation: 0,
+            receiving_generation: 0,
+            transport_keys: None,
+            stats: SessionStats::default(),
+            handshake_started: None,
+        }
+    }
+    
+    /// Rebuild a transport-mode session from a snapshot
+    ///
+    /// snow cannot construct a transport state from raw keys, so a throwaway
+    /// NN handshake with the same primitives is run locally and the saved
+    /// keys are installed over its split.
+    pub(crate) fn from_transport_snapshot(snapshot: TransportSnapshot) -> Result<Self> {
+        let params: NoiseParams = snapshot.protocol_name.parse()?;
+        let mut parts: Vec<&str> = snapshot.protocol_name.split('_').collect();
+        if parts.len() != 5 {
+            return Err(NoiseError::InvalidMessage);
+        }
+        parts[1] = "NN";
+        let nn_params: NoiseParams = parts.join("_").parse()?;
+        
+        let mut initiator = Builder::new(nn_params.clone()).build_initiator()?;
+        let mut responder = Builder::new(nn_params).build_responder()?;
+        let mut message = [0u8; 128];
+        let len = initiator.write_message(&[], &mut message)?;
+        responder.read_message(&message[..len], &mut [])?;
+        let len = responder.write_message(&[], &mut message)?;
+        initiator.read_message(&message[..len], &mut [])?;
+        
+        let local = if snapshot.is_initiator { initiator } else { responder };
+        let mut transport = local.into_stateless_transport_mode()?;
+        transport.rekey_manually(Some(&snapshot.keys.initiator), Some(&snapshot.keys.responder));
+        
+        let mut session = Self::from_handshake_params(&params);
+        session.state = NoiseState::Transport(Box::new(transport));
+        session.sending_nonce = snapshot.sending_nonce;
+        session.receiving_nonce = snapshot.receiving_nonce;
+        session.sending_generation = snapshot.sending_generation;
+        session.receiving_generation = snapshot.receiving_generation;
+        session.remote_static = snapshot.remote_static;
+        session.local_static = snapshot.local_static;
+        session.exporter_secret = snapshot.exporter_secret;
+        session.transport_keys = Some(snapshot.keys);
+        Ok(session)
+    }
+    
+    /// Capture the transport state for a snapshot (transport mode only)
+    pub(crate) fn transport_snapshot(&self) -> Result<TransportSnapshot> {
+        match (&self.state, &self.transport_keys) {
+            (NoiseState::Transport(transport), Some(keys)) => Ok(TransportSnapshot {
+                protocol_name: self.params.name.clone(),
+                is_initiator: transport.is_initiator(),
+                keys: keys.clone(),
+                sending_nonce: self.sending_nonce,
+                receiving_nonce: self.receiving_nonce,
+                sending_generation: self.sending_generation,
+                receiving_generation: self.receiving_generation,
+                remote_static: self.remote_static.clone(),
+                local_static: self.local_static.clone(),
+                exporter_secret: self.exporter_secret.clone(),
+            }),
+            _ => Err(NoiseError::InvalidState("Only transport-mode sessions can be snapshotted".to_string())),
+        }
+    }
+    
+    /// Session shell with no live cipher state, filled in by the caller
+    fn from_handshake_params(params: &NoiseParams) -> Self {
+        NoiseSession {
+            state: NoiseState::Transitioning,
+            params: params.clone(),
+            buffer: Vec::new(),
+            max_message_len: Self::MAX_MESSAGE_LEN,
+            small_buffer_uses: 0,
+            buffer_pool: None,
+            remote_static: None,
+            local_static: None,
+            expected_remote_static: None,
+            handshake_hash: None,
+            session_id: None,
+            exporter_secret: None,
+            identity_certificate: None,
+            identity_verifier: None,
+            remote_identity: None,
+            payload_validator: None,
+            nonce_limit: NOISE_NONCE_LIMIT,
+            padding: None,
+            handshake_messages: handshake_message_count(params.handshake.pattern),
+            handshake_index: handshake_message_count(params.handshake.pattern),
+            sending_nonce: 0,
+            receiving_nonce: 0,
+            sending_generation: 0,
+            receiving_generation: 0,
+            transport_keys: None,
+            stats: SessionStats::default(),
+            handshake_started: None,
+        }
+    }
+    
+    /// Move from handshake to transport mode once the final message is processed
+    fn finish_handshake(&mut self) -> Result<()> {
+        let old_state = std::mem::replace(&mut self.state, NoiseState::Transitioning);
+        if let NoiseState::Handshake(mut handshake) = old_state {
+            // Store remote static key before transitioning; resumed sessions
+            // keep the one recorded in their ticket
+            if let Some(remote_static) = handshake.get_remote_static() {
+                self.remote_static = Some(remote_static.to_vec());
+            }
+            self.handshake_hash = Some(handshake.get_handshake_hash().to_vec());
+            let mut id = SessionId([0u8; SESSION_ID_LEN]);
+            id.0.copy_from_slice(&hkdf(self.params.hash, &[], handshake.get_handshake_hash(), SESSION_ID_LABEL, SESSION_ID_LEN)?);
+            self.session_id = Some(id);
+            
+            let (initiator, responder) = handshake.dangerously_get_raw_split();
+            let mut split = Zeroizing::new([0u8; 64]);
+            split[..32].copy_from_slice(&initiator);
+            split[32..].copy_from_slice(&responder);
+            let secret = hkdf(self.params.hash, handshake.get_handshake_hash(), &split[..], EXPORTER_LABEL, EXPORTER_SECRET_LEN)?;
+            self.exporter_secret = Some(Zeroizing::new(secret));
+            self.transport_keys = Some(TransportKeys { initiator, responder });
+            
+            let transport = handshake.into_stateless_transport_mode()?;
+            self.state = NoiseState::Transport(Box::new(transport));
+            // Transport messages are written straight into caller buffers
+            self.release_buffer();
+            self.stats.handshake_duration = self.handshake_started.map(|started| started.elapsed());
+            log_event!(LogLevel::Info, "Handshake complete after {} messages, session {id}", self.handshake_index);
+        }
+        Ok(())
     }
     
     /// Check if the session is still in handshake state
@@ -98,7 +770,33 @@ impl NoiseSession {
     
     /// Check if the session is in transport state (handshake complete)
     pub fn is_transport_state(&self) -> bool {
-        matches!(self.state, NoiseState::Transport(_))
+        matches!(self.state, NoiseState::Transport(_) | NoiseState::StatefulTransport(_))
+    }
+    
+    /// Whether this side should write the next message
+    ///
+    /// During the handshake this follows the pattern, so transport glue can
+    /// alternate `write_message` and `read_message` for any pattern, e.g.
+    /// one-way `N` or `IK` as well as `XX`. Once the handshake completes
+    /// either side may send, so this is always true.
+    pub fn is_my_turn_to_send(&self) -> bool {
+        match &self.state {
+            NoiseState::Handshake(handshake) => handshake.is_my_turn(),
+            NoiseState::Transport(_) | NoiseState::StatefulTransport(_) => true,
+            NoiseState::Transitioning => false,
+        }
+    }
+    
+    /// Estimate of handshake round trips still needed (0 once complete)
+    ///
+    /// A round trip is a pair of messages, so the final odd message of XX
+    /// still counts as one.
+    pub fn round_trips_remaining(&self) -> usize {
+        if !self.is_handshake_state() {
+            return 0;
+        }
+        let remaining = self.handshake_messages.saturating_sub(self.handshake_index);
+        remaining.div_ceil(2)
     }
     
     /// Get the remote peer's static public key (only available after handshake)
@@ -106,24 +804,222 @@ impl NoiseSession {
         self.remote_static.as_deref()
     }
     
+    /// The remote peer's static key as a [`PublicKey`], once the handshake completes
+    pub fn remote_public_key(&self) -> Option<PublicKey> {
+        self.remote_static.as_deref().and_then(|key| PublicKey::from_bytes(key).ok())
+    }
+    
+    /// The remote static key, as soon as the handshake has revealed it
+    pub(crate) fn revealed_remote_static(&self) -> Option<&[u8]> {
+        match &self.state {
+            NoiseState::Handshake(handshake) => handshake.get_remote_static(),
+            _ => self.remote_static.as_deref(),
+        }
+    }
+    
+    /// Require the peer to present `remote_static`, as with
+    /// [`NoiseSessionBuilder::expect_remote_static`]
+    ///
+    /// Fails with [`NoiseError::PeerKeyMismatch`] if a different key was
+    /// already revealed or expected.
+    pub(crate) fn pin_remote_static(&mut self, remote_static: &[u8]) -> Result<()> {
+        let known = self.revealed_remote_static().or(self.expected_remote_static.as_deref());
+        if known.is_some_and(|key| !keys_equal(key, remote_static)) {
+            return Err(NoiseError::PeerKeyMismatch);
+        }
+        self.expected_remote_static = Some(remote_static.to_vec());
+        Ok(())
+    }
+    
+    /// Get this side's static public key, e.g. to show in a pairing QR code
+    ///
+    /// `None` for sessions adopted from snow, whose keys were never seen by
+    /// this library.
+    pub fn get_local_static(&self) -> Option<&[u8]> {
+        self.local_static.as_deref()
+    }
+    
+    /// Get the handshake hash, which uniquely identifies this session
+    ///
+    /// Available once the handshake completes. Not carried over by snapshots
+    /// or by sessions adopted from snow.
+    pub fn get_handshake_hash(&self) -> Option<&[u8]> {
+        self.handshake_hash.as_deref()
+    }
+    
+    /// Short identifier of this session, the same on both peers
+    ///
+    /// Derived one-way from the handshake hash, so it reveals nothing about
+    /// keys and can go into logs and metrics to correlate both ends of a
+    /// session. Available once the handshake completes; like the handshake
+    /// hash, not carried over by snapshots or by sessions adopted from snow.
+    pub fn session_id(&self) -> Option<SessionId> {
+        self.session_id
+    }
+    
+    /// Derive `len` bytes of keying material bound to this session
+    ///
+    /// Both peers get the same output for the same `label` and `context`,
+    /// and different labels give independent keys, e.g. one for a file
+    /// cache and one for a database. Output is unchanged by rekeying and
+    /// carried over by snapshots. Available once the handshake completes;
+    /// `len` may be up to 255 hash outputs.
+    pub fn export_key(&self, label: &str, context: &[u8], len: usize) -> Result<Vec<u8>> {
+        let secret = self.exporter_secret.as_ref()
+            .ok_or_else(|| NoiseError::InvalidState("Key export requires a completed handshake".to_string()))?;
+        if len > u16::MAX as usize || label.len() > u16::MAX as usize || context.len() > u32::MAX as usize {
+            return Err(NoiseError::InvalidParameter);
+        }
+        
+        let mut info = Vec::with_capacity(8 + label.len() + context.len());
+        info.extend_from_slice(&(label.len() as u16).to_be_bytes());
+        info.extend_from_slice(label.as_bytes());
+        info.extend_from_slice(&(context.len() as u32).to_be_bytes());
+        info.extend_from_slice(context);
+        info.extend_from_slice(&(len as u16).to_be_bytes());
+        hkdf(self.params.hash, &[], secret, &info, len)
+    }
+    
+    /// Get the peer's verified Ed25519 identity key
+    ///
+    /// Set once the certificate required by
+    /// [`NoiseSessionBuilder::verify_identity`] has been accepted.
+    pub fn get_remote_identity(&self) -> Option<&[u8]> {
+        self.remote_identity.as_ref().map(|key| &key[..])
+    }
+    
+    /// Check every handshake payload from the peer before accepting it
+    ///
+    /// Each payload is decoded as a [`HandshakePayload`] and passed to
+    /// `validator` with the peer's static key, if known yet. A payload that
+    /// fails to decode or is rejected aborts the handshake with
+    /// [`NoiseError::HandshakeFailed`]. `read_message` still returns the raw
+    /// payload bytes.
+    pub fn set_payload_validator(
+        &mut self,
+        validator: impl Fn(&HandshakePayload, Option<&[u8]>) -> bool + Send + Sync + 'static,
+    ) {
+        self.payload_validator = Some(structured_validator(std::sync::Arc::new(validator)));
+    }
+    
+    /// Check every raw handshake payload from the peer before accepting it
+    ///
+    /// Like [`NoiseSession::set_payload_validator`], for payloads in an app's
+    /// own format. Replaces any validator set before.
+    pub fn set_raw_payload_validator(
+        &mut self,
+        validator: impl Fn(&[u8], Option<&[u8]>) -> bool + Send + Sync + 'static,
+    ) {
+        self.payload_validator = Some(std::sync::Arc::new(validator));
+    }
+    
+    /// Stop checking handshake payloads
+    pub fn clear_payload_validator(&mut self) {
+        self.payload_validator = None;
+    }
+    
+    /// Index of the last handshake message sent by the initiator or responder
+    fn last_message_index(&self, from_initiator: bool) -> Option<usize> {
+        let last = self.handshake_messages.checked_sub(1)?;
+        // Messages alternate, starting with the initiator
+        if (last % 2 == 0) == from_initiator {
+            Some(last)
+        } else {
+            last.checked_sub(1)
+        }
+    }
+    
+    /// Number of transport messages that can still be sent or received before
+    /// the session returns `NonceExhausted`.
+    ///
+    /// Apps should schedule a new handshake well before this reaches zero.
+    pub fn remaining_nonce_budget(&self) -> u64 {
+        match &self.state {
+            NoiseState::Transport(_) | NoiseState::StatefulTransport(_) => {
+                let used = self.sending_nonce.max(self.receiving_nonce);
+                self.nonce_limit.saturating_sub(used)
+            }
+            _ => self.nonce_limit,
+        }
+    }
+    
+    /// Lower the nonce limit (mainly useful for testing exhaustion handling)
+    pub fn set_nonce_limit(&mut self, limit: u64) {
+        self.nonce_limit = limit.min(NOISE_NONCE_LIMIT);
+    }
+    
+    /// Pad transport messages with `scheme`, `None` to stop padding
+    ///
+    /// Incoming messages are expected to be padded too, so both peers must
+    /// switch at the same point; see [`crate::core::padding::negotiate`].
+    /// Like the nonce limit, this is not part of snapshots.
+    pub fn set_padding(&mut self, scheme: Option<PaddingScheme>) {
+        self.padding = scheme;
+    }
+    
+    /// The padding scheme in use, if any
+    pub fn padding(&self) -> Option<&PaddingScheme> {
+        self.padding.as_ref()
+    }
+    
+    /// Cap the length of messages written and accepted, at most
+    /// [`MAX_MESSAGE_LEN`](Self::MAX_MESSAGE_LEN)
+    ///
+    /// Sessions hold no message buffer once the handshake is done, and
+    /// grow one only as large as padded or in-place messages need, so this
+    /// mostly bounds memory on constrained links and meshes with many
+    /// peers. Handshake messages must fit too.
+    pub fn set_max_message_len(&mut self, len: usize) {
+        self.max_message_len = len.clamp(NOISE_TAG_LEN + 1, Self::MAX_MESSAGE_LEN);
+    }
+    
+    /// Longest message this session writes or accepts
+    pub fn max_message_len(&self) -> usize {
+        self.max_message_len
+    }
+    
+    /// Borrow scratch space from a shared pool instead of growing a buffer
+    /// of this session's own
+    ///
+    /// Handshake messages still use the session's buffer, which is freed
+    /// when the handshake completes.
+    pub fn set_buffer_pool(&mut self, pool: Option<BufferPool>) {
+        if pool.is_some() && self.is_transport_state() {
+            self.release_buffer();
+        }
+        self.buffer_pool = pool;
+    }
+    
+    /// The shared pool scratch space comes from, if any
+    pub fn buffer_pool(&self) -> Option<&BufferPool> {
+        self.buffer_pool.as_ref()
+    }
+    
     /// Write a handshake message
     pub fn write_message(&mut self, payload: &[u8]) -> Result<Vec<u8>> {
+        let is_initiator = match &self.state {
+            NoiseState::Handshake(handshake) => handshake.is_initiator(),
+            _ => false,
+        };
+        let certified;
+        let payload = match &self.identity_certificate {
+            Some(certificate) if self.last_message_index(is_initiator) == Some(self.handshake_index) => {
+                certified = [&certificate.to_bytes()[..], payload].concat();
+                &certified[..]
+            }
+            _ => payload,
+        };
+        
+        self.reserve_buffer(payload.len().saturating_add(HANDSHAKE_OVERHEAD));
         if let NoiseState::Handshake(ref mut handshake) = &mut self.state {
+            self.handshake_started.get_or_insert_with(Instant::now);
             let len = handshake.write_message(payload, &mut self.buffer)?;
             let result = self.buffer[..len].to_vec();
+            self.handshake_index += 1;
             
             // Check if handshake is complete after writing
             if handshake.is_handshake_finished() {
-                // Store remote static key before transitioning
-                self.remote_static = handshake.get_remote_static()
-                    .map(|k| k.to_vec());
-                    
-                // Take ownership of the handshake state to transition
-                let old_state = std::mem::replace(&mut self.state, NoiseState::Transitioning);
-                if let NoiseState::Handshake(handshake) = old_state {
-                    let transport = handshake.into_transport_mode()?;
-                    self.state = NoiseState::Transport(Box::new(transport));
-                }
+                self.finish_handshake()?;
             }
             
             Ok(result)
@@ -134,22 +1030,54 @@ impl NoiseSession {
     
     /// Read a handshake message
     pub fn read_message(&mut self, message: &[u8]) -> Result<Vec<u8>> {
+        let reads_certificate = match (&self.state, &self.identity_verifier) {
+            (NoiseState::Handshake(handshake), Some(_)) => {
+                self.last_message_index(!handshake.is_initiator()) == Some(self.handshake_index)
+            }
+            _ => false,
+        };
+        
+        if message.len() > self.max_message_len {
+            return Err(NoiseError::InvalidMessage);
+        }
+        self.reserve_buffer(message.len());
         if let NoiseState::Handshake(ref mut handshake) = &mut self.state {
-            let len = handshake.read_message(message, &mut self.buffer)?;
-            let result = self.buffer[..len].to_vec();
+            self.handshake_started.get_or_insert_with(Instant::now);
+            let len = handshake.read_message(message, &mut self.buffer).inspect_err(|e| {
+                log_event!(LogLevel::Warn, "Handshake message {} rejected: {e}", self.handshake_index);
+            })?;
+            let mut check = match (&self.expected_remote_static, handshake.get_remote_static()) {
+                (Some(expected), Some(actual)) if !keys_equal(expected, actual) => Err(NoiseError::PeerKeyMismatch),
+                _ => Ok(()),
+            };
+            let mut start = 0;
+            if let (Ok(()), true, Some(verifier)) = (&check, reads_certificate, &self.identity_verifier) {
+                match check_certificate(&self.buffer[..len], handshake.get_remote_static(), verifier) {
+                    Ok(identity) => {
+                        self.remote_identity = Some(identity);
+                        start = CERTIFICATE_LEN;
+                    }
+                    Err(e) => check = Err(e),
+                }
+            }
+            if let (Ok(()), Some(validator)) = (&check, &self.payload_validator) {
+                if !validator(&self.buffer[start..len], handshake.get_remote_static()) {
+                    check = Err(NoiseError::HandshakeFailed);
+                }
+            }
+            if let Err(e) = check {
+                // Never let a session with the wrong peer reach transport mode
+                self.buffer[..len].zeroize();
+                self.state = NoiseState::Transitioning;
+                log_event!(LogLevel::Warn, "Handshake aborted: {e}");
+                return Err(e);
+            }
+            let result = self.buffer[start..len].to_vec();
+            self.handshake_index += 1;
             
             // Check if handshake is complete after reading
             if handshake.is_handshake_finished() {
-                // Store remote static key before transitioning
-                self.remote_static = handshake.get_remote_static()
-                    .map(|k| k.to_vec());
-                    
-                // Take ownership of the handshake state to transition
-                let old_state = std::mem::replace(&mut self.state, NoiseState::Transitioning);
-                if let NoiseState::Handshake(handshake) = old_state {
-                    let transport = handshake.into_transport_mode()?;
-                    self.state = NoiseState::Transport(Box::new(transport));
-                }
+                self.finish_handshake()?;
             }
             
             Ok(result)
@@ -160,76 +1088,599 @@ impl NoiseSession {
     
     /// Encrypt a message (only available after handshake completion)
     pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<Vec<u8>> {
-        match &mut self.state {
-            NoiseState::Handshake(_) => {
-                Err(NoiseError::InvalidState("Cannot encrypt before handshake completion".to_string()))
-            }
-            NoiseState::Transport(ref mut transport) => {
-                let len = transport.write_message(plaintext, &mut self.buffer)?;
-                Ok(self.buffer[..len].to_vec())
-            }
-            NoiseState::Transitioning => {
-                Err(NoiseError::InvalidState("Session is in transition".to_string()))
-            }
+        let mut ciphertext = vec![0u8; self.encrypt_size(plaintext.len())];
+        let len = self.encrypt_into(plaintext, &mut ciphertext)?;
+        ciphertext.truncate(len);
+        Ok(ciphertext)
+    }
+    
+    /// Encrypt into `out`, returning the ciphertext length
+    ///
+    /// Does not allocate. Fails with [`NoiseError::BufferTooSmall`] before
+    /// using a nonce if `out` cannot hold the ciphertext.
+    pub fn encrypt_into(&mut self, plaintext: &[u8], out: &mut [u8]) -> Result<usize> {
+        self.check_transport("encrypt")?;
+        let needed = self.encrypt_size(plaintext.len());
+        if out.len() < needed {
+            return Err(NoiseError::BufferTooSmall { needed, got: out.len() });
+        }
+        if self.padding.is_none() {
+            return self.seal(plaintext, out);
+        }
+        self.gather_and_seal(&[IoSlice::new(plaintext)], out)
+    }
+    
+    /// Encrypt the concatenation of `parts`, e.g. a header and a body
+    ///
+    /// The parts are gathered into the session's own buffer, so callers
+    /// need not concatenate them first.
+    pub fn encrypt_vectored(&mut self, parts: &[IoSlice<'_>]) -> Result<Vec<u8>> {
+        let len = parts.iter().map(|part| part.len()).sum();
+        let mut ciphertext = vec![0u8; self.encrypt_size(len)];
+        let len = self.encrypt_vectored_into(parts, &mut ciphertext)?;
+        ciphertext.truncate(len);
+        Ok(ciphertext)
+    }
+    
+    /// Like [`encrypt_vectored`](Self::encrypt_vectored) into `out`,
+    /// returning the ciphertext length
+    ///
+    /// Does not allocate. Fails with [`NoiseError::BufferTooSmall`] before
+    /// using a nonce if `out` cannot hold the ciphertext.
+    pub fn encrypt_vectored_into(&mut self, parts: &[IoSlice<'_>], out: &mut [u8]) -> Result<usize> {
+        self.check_transport("encrypt")?;
+        let needed = self.encrypt_size(parts.iter().map(|part| part.len()).sum());
+        if out.len() < needed {
+            return Err(NoiseError::BufferTooSmall { needed, got: out.len() });
         }
+        self.gather_and_seal(parts, out)
+    }
+    
+    /// Replace the plaintext in `buf` with its ciphertext
+    ///
+    /// Does not allocate if `buf` has capacity for the tag (and padding,
+    /// if on).
+    pub fn encrypt_in_place(&mut self, buf: &mut Vec<u8>) -> Result<()> {
+        self.check_transport("encrypt")?;
+        let needed = self.encrypt_size(buf.len());
+        self.with_scratch(needed - NOISE_TAG_LEN, |session, scratch| {
+            let len = scratch.len().min(buf.len());
+            scratch[..len].copy_from_slice(&buf[..len]);
+            let padded_len = session.apply_padding(scratch, buf.len())?;
+            buf.resize(needed, 0);
+            let sealed = session.seal(&scratch[..padded_len], buf)?;
+            buf.truncate(sealed);
+            Ok(())
+        })
     }
     
     /// Decrypt a message (only available after handshake completion)
     pub fn decrypt(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>> {
-        match &mut self.state {
-            NoiseState::Handshake(_) => {
-                Err(NoiseError::InvalidState("Cannot decrypt before handshake completion".to_string()))
-            }
-            NoiseState::Transport(ref mut transport) => {
-                let len = transport.read_message(ciphertext, &mut self.buffer)?;
-                Ok(self.buffer[..len].to_vec())
+        self.read_message_with_nonce(self.receiving_nonce, ciphertext)
+    }
+    
+    /// Decrypt into `out`, returning the plaintext length
+    ///
+    /// Does not allocate. `out` must hold the ciphertext length less the
+    /// 16-byte tag, including any padding that is then stripped.
+    pub fn decrypt_into(&mut self, ciphertext: &[u8], out: &mut [u8]) -> Result<usize> {
+        self.open_into(self.receiving_nonce, ciphertext, out)
+    }
+    
+    /// Replace the ciphertext in `buf` with its plaintext, without allocating
+    pub fn decrypt_in_place(&mut self, buf: &mut Vec<u8>) -> Result<()> {
+        let nonce = self.receiving_nonce;
+        self.with_scratch(self.decrypt_size(buf.len()), |session, scratch| {
+            let len = session.open_into(nonce, buf, scratch)?;
+            buf.clear();
+            buf.extend_from_slice(&scratch[..len]);
+            Ok(())
+        })
+    }
+    
+    /// Encrypt a payload of any size as a run of Noise messages
+    ///
+    /// Each message holds `count (4 bytes BE) || index (4 bytes BE)` and up
+    /// to [`LARGE_CHUNK_LEN`](Self::LARGE_CHUNK_LEN) bytes of the payload.
+    /// Send them in order and pass them all to
+    /// [`decrypt_large`](Self::decrypt_large). Each message uses one nonce.
+    pub fn encrypt_large(&mut self, plaintext: &[u8]) -> Result<Vec<Vec<u8>>> {
+        let count = plaintext.len().div_ceil(Self::LARGE_CHUNK_LEN).max(1);
+        let count = u32::try_from(count).map_err(|_| NoiseError::InvalidParameter)?;
+        if self.remaining_nonce_budget() < count as u64 {
+            return Err(NoiseError::NonceExhausted);
+        }
+        
+        let mut chunks = plaintext.chunks(Self::LARGE_CHUNK_LEN);
+        let mut messages = Vec::with_capacity(count as usize);
+        let mut message = Zeroizing::new(Vec::with_capacity(Self::LARGE_HEADER_LEN + Self::LARGE_CHUNK_LEN));
+        for index in 0..count {
+            message.clear();
+            message.extend_from_slice(&count.to_be_bytes());
+            message.extend_from_slice(&index.to_be_bytes());
+            message.extend_from_slice(chunks.next().unwrap_or_default());
+            messages.push(self.encrypt(&message)?);
+        }
+        Ok(messages)
+    }
+    
+    /// Decrypt and reassemble the messages of [`encrypt_large`](Self::encrypt_large)
+    ///
+    /// Fails with [`NoiseError::InvalidMessage`] if messages are missing,
+    /// duplicated or belong to different payloads.
+    pub fn decrypt_large<M: AsRef<[u8]>>(&mut self, messages: &[M]) -> Result<Vec<u8>> {
+        if messages.is_empty() {
+            return Err(NoiseError::InvalidMessage);
+        }
+        let mut plaintext = Vec::with_capacity(messages.len() * Self::LARGE_CHUNK_LEN);
+        for (expected, message) in messages.iter().enumerate() {
+            let chunk = Zeroizing::new(self.decrypt(message.as_ref())?);
+            if chunk.len() < Self::LARGE_HEADER_LEN {
+                return Err(NoiseError::InvalidMessage);
             }
-            NoiseState::Transitioning => {
-                Err(NoiseError::InvalidState("Session is in transition".to_string()))
+            let (header, body) = chunk.split_at(Self::LARGE_HEADER_LEN);
+            let count = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+            let index = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+            if count as usize != messages.len() || index as usize != expected {
+                return Err(NoiseError::InvalidMessage);
             }
+            plaintext.extend_from_slice(body);
         }
+        Ok(plaintext)
     }
     
-    /// Process a message - automatically handles handshake or transport mode
-    pub fn process_message(&mut self, input: &[u8]) -> Result<Vec<u8>> {
-        match &self.state {
-            NoiseState::Handshake(_) => self.read_message(input),
-            NoiseState::Transport(_) => self.decrypt(input),
-            NoiseState::Transitioning => Err(NoiseError::InvalidState("Session is in transition".to_string())),
-        }
+    /// Encrypt into an explicit-nonce frame:
+    /// `generation (4 bytes BE) || nonce (8 bytes BE) || ciphertext`
+    ///
+    /// Frames carry their own nonce and key generation so the receiver can
+    /// decrypt them in any order with [`NoiseSession::decrypt_with_explicit_nonce`]
+    /// and catch up on rekeys it missed.
+    pub fn encrypt_with_explicit_nonce(&mut self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let generation = self.sending_generation;
+        let nonce = self.sending_nonce;
+        let ciphertext = self.encrypt(plaintext)?;
+        
+        let mut frame = Vec::with_capacity(Self::EXPLICIT_HEADER_LEN + ciphertext.len());
+        frame.extend_from_slice(&generation.to_be_bytes());
+        frame.extend_from_slice(&nonce.to_be_bytes());
+        frame.extend_from_slice(&ciphertext);
+        Ok(frame)
     }
     
-    /// Generate the next message - automatically handles handshake or transport mode
-    pub fn generate_message(&mut self, payload: &[u8]) -> Result<Vec<u8>> {
-        match &self.state {
-            NoiseState::Handshake(_) => self.write_message(payload),
-            NoiseState::Transport(_) => self.encrypt(payload),
-            NoiseState::Transitioning => Err(NoiseError::InvalidState("Session is in transition".to_string())),
+    /// Decrypt an explicit-nonce frame, returning its nonce and plaintext
+    ///
+    /// Frames are accepted in any order and replays are not rejected here;
+    /// pair this with a replay window such as `ResilientSession`'s. A frame
+    /// from a newer generation rekeys the receiving direction to match, but
+    /// only once it authenticates. Frames from older generations are rejected.
+    pub fn decrypt_with_explicit_nonce(&mut self, frame: &[u8]) -> Result<(u64, Vec<u8>)> {
+        if frame.len() < Self::EXPLICIT_HEADER_LEN {
+            return Err(NoiseError::InvalidMessage);
+        }
+        let (header, ciphertext) = frame.split_at(Self::EXPLICIT_HEADER_LEN);
+        let generation = u32::from_be_bytes(header[..4].try_into().map_err(|_| NoiseError::InvalidMessage)?);
+        let nonce = u64::from_be_bytes(header[4..].try_into().map_err(|_| NoiseError::InvalidMessage)?);
+        
+        let skip = generation.checked_sub(self.receiving_generation).ok_or(NoiseError::InvalidMessage)?;
+        if skip > Self::MAX_GENERATION_SKIP {
+            return Err(NoiseError::InvalidMessage);
+        }
+        if skip == 0 {
+            return Ok((nonce, self.read_message_with_nonce(nonce, ciphertext)?));
+        }
+        if self.transport_keys.is_none() {
+            return Err(NoiseError::InvalidState("Generation resync requires tracked transport keys".to_string()));
+        }
+        
+        // The peer rekeyed and we missed the signal; roll back unless the frame authenticates
+        let saved_keys = self.transport_keys.clone();
+        let (saved_generation, saved_stats) = (self.receiving_generation, self.stats);
+        let result = (0..skip)
+            .try_for_each(|_| self.rekey_incoming())
+            .and_then(|()| self.read_message_with_nonce(nonce, ciphertext));
+        match result {
+            Ok(plaintext) => Ok((nonce, plaintext)),
+            Err(e) => {
+                self.transport_keys = saved_keys;
+                self.receiving_generation = saved_generation;
+                self.install_transport_keys();
+                // The trial rekeys never happened, but the rejected frame counts
+                self.stats = SessionStats { decrypt_failures: self.stats.decrypt_failures, ..saved_stats };
+                Err(e)
+            }
+        }
+    }
+    
+    /// Decrypt a transport message under an explicit nonce
+    pub fn read_message_with_nonce(&mut self, nonce: u64, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let mut plaintext = vec![0u8; self.decrypt_size(ciphertext.len())];
+        let len = self.open_into(nonce, ciphertext, &mut plaintext)?;
+        plaintext.truncate(len);
+        Ok(plaintext)
+    }
+    
+    /// Ciphertext length [`encrypt`](Self::encrypt) produces for a
+    /// plaintext of `plaintext_len` bytes, padding included
+    pub fn encrypt_size(&self, plaintext_len: usize) -> usize {
+        let padded = match &self.padding {
+            Some(scheme) if plaintext_len < NOISE_MAX_PAYLOAD_LEN => scheme.padded_len(plaintext_len).min(NOISE_MAX_PAYLOAD_LEN),
+            _ => plaintext_len,
+        };
+        padded.saturating_add(NOISE_TAG_LEN)
+    }
+    
+    /// Buffer length [`decrypt_into`](Self::decrypt_into) needs for a
+    /// ciphertext of `ciphertext_len` bytes
+    ///
+    /// With padding on, the plaintext itself may turn out shorter.
+    pub fn decrypt_size(&self, ciphertext_len: usize) -> usize {
+        ciphertext_len.saturating_sub(NOISE_TAG_LEN)
+    }
+    
+    fn check_transport(&self, action: &str) -> Result<()> {
+        match self.state {
+            NoiseState::Handshake(_) => Err(NoiseError::InvalidState(format!("Cannot {action} before handshake completion"))),
+            NoiseState::Transitioning => Err(NoiseError::InvalidState("Session is in transition".to_string())),
+            _ => Ok(()),
         }
     }
+    
+    /// Lend `len` bytes of the buffer out as scratch space, wiping what `f` left in it
+    fn with_scratch<R>(&mut self, len: usize, f: impl FnOnce(&mut Self, &mut [u8]) -> Result<R>) -> Result<R> {
+        if let Some(pool) = self.buffer_pool.clone() {
+            let len = len.min(self.max_message_len);
+            let mut pooled = pool.acquire(len);
+            let scratch = &mut pooled.as_mut_slice()[..len];
+            let result = f(self, scratch);
+            scratch.zeroize();
+            return result;
+        }
+        
+        self.reserve_buffer(len);
+        let mut scratch = std::mem::take(&mut self.buffer);
+        let len = len.min(scratch.len());
+        let result = f(self, &mut scratch[..len]);
+        scratch[..len].zeroize();
+        self.buffer = scratch;
+        
+        // Give back memory grown for a burst of large messages
+        if self.buffer.len() > BUFFER_SHRINK_THRESHOLD && len <= self.buffer.len() / 4 {
+            self.small_buffer_uses += 1;
+            if self.small_buffer_uses >= BUFFER_SHRINK_AFTER {
+                self.release_buffer();
+            }
+        } else {
+            self.small_buffer_uses = 0;
+        }
+        result
+    }
+    
+    /// Grow the buffer to `len` bytes, up to the maximum message length
+    fn reserve_buffer(&mut self, len: usize) {
+        let len = len.min(self.max_message_len);
+        if self.buffer.len() < len {
+            self.buffer.resize(len, 0);
+        }
+    }
+    
+    fn release_buffer(&mut self) {
+        self.buffer.zeroize();
+        self.buffer = Vec::new();
+        self.small_buffer_uses = 0;
+    }
+    
+    /// Copy `parts` into the scratch buffer, pad them and encrypt into `out`
+    fn gather_and_seal(&mut self, parts: &[IoSlice<'_>], out: &mut [u8]) -> Result<usize> {
+        let len = self.encrypt_size(parts.iter().map(|part| part.len()).sum()) - NOISE_TAG_LEN;
+        self.with_scratch(len, |session, scratch| {
+            let mut len = 0;
+            for part in parts {
+                let end = len + part.len();
+                if end > scratch.len() {
+                    return Err(NoiseError::InvalidParameter);
+                }
+                scratch[len..end].copy_from_slice(part);
+                len = end;
+            }
+            let padded_len = session.apply_padding(scratch, len)?;
+            session.seal(&scratch[..padded_len], out)
+        })
+    }
+    
+    fn apply_padding(&self, buf: &mut [u8], len: usize) -> Result<usize> {
+        match &self.padding {
+            Some(scheme) => scheme.pad_in_place(buf, len),
+            None if len <= buf.len() => Ok(len),
+            None => Err(NoiseError::InvalidParameter),
+        }
+    }
+    
+    /// Encrypt under the next sending nonce
+    fn seal(&mut self, plaintext: &[u8], out: &mut [u8]) -> Result<usize> {
+        if self.sending_nonce >= self.nonce_limit {
+            log_event!(LogLevel::Warn, "Sending nonces exhausted at {}; a new handshake is needed", self.sending_nonce);
+            return Err(NoiseError::NonceExhausted);
+        }
+        if plaintext.len() + NOISE_TAG_LEN > self.max_message_len {
+            return Err(NoiseError::InvalidParameter);
+        }
+        let len = match &mut self.state {
+            NoiseState::Transport(ref transport) => transport.write_message(self.sending_nonce, plaintext, out)?,
+            NoiseState::StatefulTransport(ref mut transport) => transport.write_message(plaintext, out)?,
+            _ => return Err(NoiseError::InvalidState("Session is not in transport mode".to_string())),
+        };
+        self.sending_nonce += 1;
+        self.stats.messages_sent += 1;
+        self.stats.bytes_sent += len as u64;
+        Ok(len)
+    }
+    
+    /// Decrypt under `nonce` into `out` and strip any padding
+    fn open_into(&mut self, nonce: u64, ciphertext: &[u8], out: &mut [u8]) -> Result<usize> {
+        self.check_transport("decrypt")?;
+        if nonce >= self.nonce_limit {
+            return Err(NoiseError::NonceExhausted);
+        }
+        if ciphertext.len() > self.max_message_len {
+            return Err(NoiseError::InvalidMessage);
+        }
+        let needed = self.decrypt_size(ciphertext.len());
+        if out.len() < needed {
+            return Err(NoiseError::BufferTooSmall { needed, got: out.len() });
+        }
+        let len = match &mut self.state {
+            NoiseState::Transport(ref transport) => transport.read_message(nonce, ciphertext, out),
+            NoiseState::StatefulTransport(ref mut transport) => {
+                transport.set_receiving_nonce(nonce);
+                transport.read_message(ciphertext, out)
+            }
+            _ => return Err(NoiseError::InvalidState("Session is not in transport mode".to_string())),
+        }
+        .inspect_err(|e| {
+            self.stats.decrypt_failures += 1;
+            log_event!(LogLevel::Debug, "Message {nonce} of {} bytes rejected: {e}", ciphertext.len());
+        })?;
+        self.receiving_nonce = self.receiving_nonce.max(nonce + 1);
+        let len = match self.padding {
+            None => len,
+            Some(_) => unpadded_len(&out[..len]).inspect_err(|_| {
+                self.stats.decrypt_failures += 1;
+                out[..len].zeroize();
+            })?,
+        };
+        self.stats.messages_received += 1;
+        self.stats.bytes_received += ciphertext.len() as u64;
+        Ok(len)
+    }
+    
+    /// Rekey the sending direction (Noise `REKEY`) and advance its generation
+    ///
+    /// The peer must call [`NoiseSession::rekey_incoming`] at the same point
+    /// in the stream. Explicit-nonce frames carry the generation, so a peer
+    /// using them catches up automatically if the signal is lost.
+    pub fn rekey_outgoing(&mut self) -> Result<()> {
+        if let NoiseState::StatefulTransport(transport) = &mut self.state {
+            transport.rekey_outgoing();
+        } else {
+            let initiator = self.is_initiator_transport()?;
+            self.rekey_direction(initiator)?;
+        }
+        self.sending_generation += 1;
+        self.stats.rekeys += 1;
+        log_event!(LogLevel::Info, "Rekeyed sending direction to generation {}", self.sending_generation);
+        Ok(())
+    }
+    
+    /// Rekey the receiving direction and advance its generation
+    pub fn rekey_incoming(&mut self) -> Result<()> {
+        if let NoiseState::StatefulTransport(transport) = &mut self.state {
+            transport.rekey_incoming();
+        } else {
+            let initiator = self.is_initiator_transport()?;
+            self.rekey_direction(!initiator)?;
+        }
+        self.receiving_generation += 1;
+        self.stats.rekeys += 1;
+        log_event!(LogLevel::Info, "Rekeyed receiving direction to generation {}", self.receiving_generation);
+        Ok(())
+    }
+    
+    /// Traffic counters since this session object was created
+    pub fn stats(&self) -> SessionStats {
+        self.stats
+    }
+    
+    /// Number of rekeys applied to the sending direction
+    pub fn sending_generation(&self) -> u32 {
+        self.sending_generation
+    }
+    
+    /// Number of rekeys applied to the receiving direction
+    pub fn receiving_generation(&self) -> u32 {
+        self.receiving_generation
+    }
+    
+    /// Nonce the next outgoing transport message will use
+    pub(crate) fn sending_nonce(&self) -> u64 {
+        self.sending_nonce
+    }
+    
+    /// Never send with a nonce below `nonce`
+    ///
+    /// For restoring state older than a record of the nonces already spent.
+    /// Only the stateless transport can skip nonces; other states fail
+    /// unless they are already at or past `nonce`.
+    pub(crate) fn skip_sending_nonces_to(&mut self, nonce: u64) -> Result<()> {
+        if nonce <= self.sending_nonce {
+            return Ok(());
+        }
+        match self.state {
+            NoiseState::Transport(_) => {
+                self.sending_nonce = nonce;
+                Ok(())
+            }
+            _ => Err(NoiseError::InvalidState("Sending nonces can only be skipped in stateless transport mode".to_string())),
+        }
+    }
+    
+    /// Nonce the next incoming transport message is expected to use
+    #[cfg(feature = "manager")]
+    pub(crate) fn receiving_nonce(&self) -> u64 {
+        self.receiving_nonce
+    }
+    
+    pub(crate) fn is_initiator_transport(&self) -> Result<bool> {
+        match &self.state {
+            NoiseState::Transport(transport) => Ok(transport.is_initiator()),
+            _ => Err(NoiseError::InvalidState("Rekey requires transport mode".to_string())),
+        }
+    }
+    
+    /// Replace the initiator (`true`) or responder cipher key with `REKEY(k)`
+    fn rekey_direction(&mut self, initiator: bool) -> Result<()> {
+        if self.params.cipher != CipherChoice::ChaChaPoly {
+            return Err(NoiseError::InvalidState("Rekey is only supported with ChaChaPoly".to_string()));
+        }
+        let keys = self.transport_keys.as_mut()
+            .ok_or_else(|| NoiseError::InvalidState("Rekey requires transport mode".to_string()))?;
+        
+        if initiator {
+            keys.initiator = rekey_chachapoly(&keys.initiator)?;
+        } else {
+            keys.responder = rekey_chachapoly(&keys.responder)?;
+        }
+        self.install_transport_keys();
+        Ok(())
+    }
+    
+    /// Load the tracked keys into the live transport state
+    fn install_transport_keys(&mut self) {
+        if let (NoiseState::Transport(transport), Some(keys)) = (&mut self.state, &self.transport_keys) {
+            transport.rekey_manually(Some(&keys.initiator), Some(&keys.responder));
+        }
+    }
+    
+    /// Process a message - automatically handles handshake or transport mode
+    pub fn process_message(&mut self, input: &[u8]) -> Result<Vec<u8>> {
+        match &self.state {
+            NoiseState::Handshake(_) => self.read_message(input),
+            NoiseState::Transport(_) | NoiseState::StatefulTransport(_) => self.decrypt(input),
+            NoiseState::Transitioning => Err(NoiseError::InvalidState("Session is in transition".to_string())),
+        }
+    }
+    
+    /// Generate the next message - automatically handles handshake or transport mode
+    pub fn generate_message(&mut self, payload: &[u8]) -> Result<Vec<u8>> {
+        match &self.state {
+            NoiseState::Handshake(_) => self.write_message(payload),
+            NoiseState::Transport(_) | NoiseState::StatefulTransport(_) => self.encrypt(payload),
+            NoiseState::Transitioning => Err(NoiseError::InvalidState("Session is in transition".to_string())),
+        }
+    }
+    
+    /// Split an established session into halves that can move to separate threads
+    ///
+    /// Each half holds only the key for its own direction.
+    pub fn split(mut self) -> Result<(SendHalf, RecvHalf)> {
+        self.split_off()
+    }
+    
+    /// Move the transport into send and receive halves, leaving this session unusable
+    pub(crate) fn split_off(&mut self) -> Result<(SendHalf, RecvHalf)> {
+        let mut send = self.transport_snapshot()?;
+        let mut recv = self.transport_snapshot()?;
+        let (send_unused, recv_unused) = if send.is_initiator {
+            (&mut send.keys.responder, &mut recv.keys.initiator)
+        } else {
+            (&mut send.keys.initiator, &mut recv.keys.responder)
+        };
+        send_unused.zeroize();
+        recv_unused.zeroize();
+        
+        let mut send = Self::from_transport_snapshot(send)?;
+        let mut recv = Self::from_transport_snapshot(recv)?;
+        send.nonce_limit = self.nonce_limit;
+        recv.nonce_limit = self.nonce_limit;
+        
+        self.state = NoiseState::Transitioning;
+        self.transport_keys = None;
+        Ok((SendHalf { session: send }, RecvHalf { session: recv }))
+    }
+}
+
+/// Sending direction of a split transport session
+pub struct SendHalf {
+    session: NoiseSession,
+}
+
+impl SendHalf {
+    /// Encrypt a message
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        self.session.encrypt(plaintext)
+    }
+    
+    /// Encrypt a message into an explicit-nonce frame
+    pub fn encrypt_with_explicit_nonce(&mut self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        self.session.encrypt_with_explicit_nonce(plaintext)
+    }
+    
+    /// Move to a fresh sending key
+    pub fn rekey(&mut self) -> Result<()> {
+        self.session.rekey_outgoing()
+    }
+    
+    /// Number of rekeys applied
+    pub fn generation(&self) -> u32 {
+        self.session.sending_generation()
+    }
+    
+    /// Messages left before the nonce space is exhausted
+    pub fn remaining_nonce_budget(&self) -> u64 {
+        self.session.nonce_limit.saturating_sub(self.session.sending_nonce)
+    }
+}
+
+/// Receiving direction of a split transport session
+pub struct RecvHalf {
+    session: NoiseSession,
+}
+
+impl RecvHalf {
+    /// Decrypt a message
+    pub fn decrypt(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        self.session.decrypt(ciphertext)
+    }
+    
+    /// Decrypt an explicit-nonce frame, returning its nonce and plaintext
+    pub fn decrypt_with_explicit_nonce(&mut self, frame: &[u8]) -> Result<(u64, Vec<u8>)> {
+        self.session.decrypt_with_explicit_nonce(frame)
+    }
+    
+    /// Move to a fresh receiving key
+    pub fn rekey(&mut self) -> Result<()> {
+        self.session.rekey_incoming()
+    }
+    
+    /// Number of rekeys applied
+    pub fn generation(&self) -> u32 {
+        self.session.receiving_generation()
+    }
+    
+    /// The remote peer's static public key
+    pub fn get_remote_static(&self) -> Option<&[u8]> {
+        self.session.get_remote_static()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::core::keys::Keypair;
+    use crate::test_utils::perform_handshake;
     
-    fn perform_handshake() -> Result<(NoiseSession, NoiseSession)> {
-        let mut initiator = NoiseSession::new_initiator()?;
-        let mut responder = NoiseSession::new_responder()?;
-        
-        // Message 1: initiator -> responder (e)
-        let msg1 = initiator.write_message(&[])?;
-        responder.read_message(&msg1)?;
-        
-        // Message 2: responder -> initiator (e, ee, s, es)
-        let msg2 = responder.write_message(&[])?;
-        initiator.read_message(&msg2)?;
-        
-        // Message 3: initiator -> responder (s, se)
-        let msg3 = initiator.write_message(&[])?;
-        responder.read_message(&msg3)?;
-        
-        Ok((initiator, responder))
+    /// A static key filled with `byte`
+    fn static_key(byte: u8) -> SecretKey {
+        SecretKey::from_bytes(&[byte; 32]).unwrap()
     }
     
     #[test]
@@ -242,6 +1693,161 @@ mod tests {
         assert!(!responder.is_handshake_state());
     }
     
+    #[test]
+    fn test_local_static() {
+        let (initiator, responder) = perform_handshake().unwrap();
+        assert_eq!(initiator.get_local_static(), responder.get_remote_static());
+        assert_eq!(responder.get_local_static(), initiator.get_remote_static());
+        
+        let session = NoiseSession::with_private_key(&static_key(3), false).unwrap();
+        let expected = public_from_private(&[3u8; 32]).unwrap();
+        assert_eq!(session.get_local_static(), Some(&expected[..]));
+    }
+    
+    #[test]
+    fn test_expected_remote_static() {
+        let bob_key = Keypair::generate().unwrap();
+        let pinned = |key: &[u8]| NoiseSessionBuilder::new().expect_remote_static(key).build_initiator().unwrap();
+        
+        // The pinned key is accepted
+        let mut alice = pinned(bob_key.public_key());
+        let mut bob = NoiseSessionBuilder::new().private_key(bob_key.secret_key()).build_responder().unwrap();
+        let msg1 = alice.write_message(&[]).unwrap();
+        bob.read_message(&msg1).unwrap();
+        let msg2 = bob.write_message(&[]).unwrap();
+        alice.read_message(&msg2).unwrap();
+        
+        // Any other key fails as soon as it arrives in message 2
+        let mut alice = pinned(&[7u8; 32]);
+        let mut bob = NoiseSessionBuilder::new().private_key(bob_key.secret_key()).build_responder().unwrap();
+        let msg1 = alice.write_message(&[]).unwrap();
+        bob.read_message(&msg1).unwrap();
+        let msg2 = bob.write_message(&[]).unwrap();
+        assert!(matches!(alice.read_message(&msg2), Err(NoiseError::PeerKeyMismatch)));
+        assert!(alice.write_message(&[]).is_err());
+        assert!(!alice.is_transport_state());
+        
+        assert!(NoiseSessionBuilder::new().expect_remote_static(&[1u8; 16]).build_initiator().is_err());
+    }
+    
+    #[test]
+    fn test_builder_protocol_and_psk() {
+        const IK_PSK: &str = "Noise_IKpsk2_25519_ChaChaPoly_BLAKE2s";
+        let bob_key = Keypair::generate().unwrap();
+        let handshake = |alice_psk: &[u8]| {
+            let mut alice = NoiseSessionBuilder::new()
+                .protocol(IK_PSK)
+                .remote_static(bob_key.public_key())
+                .psk(2, alice_psk)
+                .build_initiator()
+                .unwrap();
+            let mut bob = NoiseSessionBuilder::new()
+                .protocol(IK_PSK)
+                .private_key(bob_key.secret_key())
+                .psk(2, &[9u8; 32])
+                .build_responder()
+                .unwrap();
+            let msg1 = alice.write_message(&[])?;
+            bob.read_message(&msg1)?;
+            let msg2 = bob.write_message(&[])?;
+            alice.read_message(&msg2)?;
+            Ok::<_, NoiseError>((alice, bob))
+        };
+        
+        let (alice, bob) = handshake(&[9u8; 32]).unwrap();
+        assert!(alice.is_transport_state() && bob.is_transport_state());
+        assert_eq!(alice.get_remote_static(), Some(&bob_key.public_key()[..]));
+        assert!(handshake(&[8u8; 32]).is_err());
+        
+        // Missing and malformed PSKs are caught when building
+        assert!(NoiseSessionBuilder::new().protocol("Noise_XXpsk3_25519_ChaChaPoly_BLAKE2s").build_initiator().is_err());
+        assert!(NoiseSessionBuilder::new().protocol(IK_PSK).psk(0, &[9u8; 32]).build_responder().is_err());
+        assert!(NoiseSessionBuilder::new().protocol(IK_PSK).psk(2, &[9u8; 16]).build_responder().is_err());
+        assert!(NoiseSessionBuilder::new().protocol("Noise_ZZ_25519_ChaChaPoly_BLAKE2s").build_initiator().is_err());
+    }
+    
+    #[test]
+    fn test_identity_certificates() {
+        let alice_identity = SigningIdentity::generate();
+        let bob_identity = SigningIdentity::generate();
+        let alice_public = alice_identity.public_key();
+        let bob_public = bob_identity.public_key();
+        
+        let mut alice = NoiseSessionBuilder::new()
+            .identity(alice_identity)
+            .verify_identity(move |certificate| certificate.identity_key == bob_public)
+            .build_initiator()
+            .unwrap();
+        let mut bob = NoiseSessionBuilder::new()
+            .identity(bob_identity)
+            .verify_identity(|_| true)
+            .build_responder()
+            .unwrap();
+        
+        // Certificates ride in front of the app payload and are stripped on receipt
+        let msg1 = alice.write_message(b"one").unwrap();
+        assert_eq!(bob.read_message(&msg1).unwrap(), b"one");
+        let msg2 = bob.write_message(b"two").unwrap();
+        assert_eq!(alice.read_message(&msg2).unwrap(), b"two");
+        let msg3 = alice.write_message(b"three").unwrap();
+        assert_eq!(bob.read_message(&msg3).unwrap(), b"three");
+        
+        assert_eq!(alice.get_remote_identity(), Some(&bob_public[..]));
+        assert_eq!(bob.get_remote_identity(), Some(&alice_public[..]));
+        
+        // A rejected identity aborts the handshake
+        let mut alice = NoiseSessionBuilder::new().verify_identity(|_| false).build_initiator().unwrap();
+        let mut bob = NoiseSessionBuilder::new().identity(SigningIdentity::generate()).build_responder().unwrap();
+        bob.read_message(&alice.write_message(&[]).unwrap()).unwrap();
+        let msg2 = bob.write_message(&[]).unwrap();
+        assert!(matches!(alice.read_message(&msg2), Err(NoiseError::PeerKeyMismatch)));
+        
+        // So does a missing certificate
+        let mut alice = NoiseSessionBuilder::new().verify_identity(|_| true).build_initiator().unwrap();
+        let mut bob = NoiseSession::new_responder().unwrap();
+        bob.read_message(&alice.write_message(&[]).unwrap()).unwrap();
+        let msg2 = bob.write_message(&[]).unwrap();
+        assert!(matches!(alice.read_message(&msg2), Err(NoiseError::HandshakeFailed)));
+        assert!(!alice.is_transport_state());
+    }
+    
+    #[test]
+    fn test_payload_validator() {
+        let offer = HandshakePayload {
+            identity: Some(b"bob".to_vec()),
+            capabilities: vec!["padding".to_string()],
+            ..Default::default()
+        };
+        let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = seen.clone();
+        let mut alice = NoiseSessionBuilder::new()
+            .validate_payload(move |payload, remote_static| {
+                log.lock().unwrap().push(remote_static.is_some());
+                payload.identity.as_deref() == Some(&b"bob"[..])
+            })
+            .build_initiator()
+            .unwrap();
+        let mut bob = NoiseSession::new_responder().unwrap();
+        
+        bob.read_message(&alice.write_message(&[]).unwrap()).unwrap();
+        let msg2 = bob.write_message(&offer.encode().unwrap()).unwrap();
+        let raw = alice.read_message(&msg2).unwrap();
+        assert_eq!(HandshakePayload::decode(&raw).unwrap(), offer);
+        // The validator saw the static key that arrived with message 2
+        assert_eq!(*seen.lock().unwrap(), vec![true]);
+        
+        // A rejected or undecodable payload aborts the handshake
+        for payload in [HandshakePayload::default().encode().unwrap(), vec![0xff]] {
+            let mut alice = NoiseSession::new_initiator().unwrap();
+            alice.set_payload_validator(|payload, _| payload.identity.is_some());
+            let mut bob = NoiseSession::new_responder().unwrap();
+            bob.read_message(&alice.write_message(&[]).unwrap()).unwrap();
+            let msg2 = bob.write_message(&payload).unwrap();
+            assert!(matches!(alice.read_message(&msg2), Err(NoiseError::HandshakeFailed)));
+            assert!(alice.write_message(&[]).is_err());
+        }
+    }
+    
     #[test]
     fn test_encryption_decryption() {
         let (mut alice, mut bob) = perform_handshake().unwrap();
@@ -270,6 +1876,454 @@ mod tests {
         assert_eq!(msg2, &pt2[..]);
     }
     
+    #[test]
+    fn test_round_trips_remaining() {
+        let mut initiator = NoiseSession::new_initiator().unwrap();
+        let mut responder = NoiseSession::new_responder().unwrap();
+        assert_eq!(initiator.round_trips_remaining(), 2);
+        
+        let msg1 = initiator.write_message(&[]).unwrap();
+        responder.read_message(&msg1).unwrap();
+        assert_eq!(initiator.round_trips_remaining(), 1);
+        assert_eq!(responder.round_trips_remaining(), 1);
+        
+        let msg2 = responder.write_message(&[]).unwrap();
+        initiator.read_message(&msg2).unwrap();
+        assert_eq!(initiator.round_trips_remaining(), 1);
+        
+        let msg3 = initiator.write_message(&[]).unwrap();
+        responder.read_message(&msg3).unwrap();
+        assert_eq!(initiator.round_trips_remaining(), 0);
+        assert_eq!(responder.round_trips_remaining(), 0);
+    }
+    
+    #[test]
+    fn test_is_my_turn_to_send() {
+        let mut initiator = NoiseSession::new_initiator().unwrap();
+        let mut responder = NoiseSession::new_responder().unwrap();
+        
+        // Drive the handshake by turn alone
+        while initiator.is_handshake_state() || responder.is_handshake_state() {
+            let (from, to) = if initiator.is_my_turn_to_send() {
+                (&mut initiator, &mut responder)
+            } else {
+                (&mut responder, &mut initiator)
+            };
+            assert!(!to.is_my_turn_to_send());
+            let message = from.write_message(&[]).unwrap();
+            to.read_message(&message).unwrap();
+        }
+        
+        assert!(initiator.is_my_turn_to_send() && responder.is_my_turn_to_send());
+        let ct = responder.encrypt(b"either side").unwrap();
+        assert_eq!(initiator.decrypt(&ct).unwrap(), b"either side");
+    }
+    
+    #[test]
+    fn test_nonce_exhaustion() {
+        let (mut alice, mut bob) = perform_handshake().unwrap();
+        alice.set_nonce_limit(2);
+        
+        assert_eq!(alice.remaining_nonce_budget(), 2);
+        let ct1 = alice.encrypt(b"one").unwrap();
+        let _ct2 = alice.encrypt(b"two").unwrap();
+        assert_eq!(alice.remaining_nonce_budget(), 0);
+        
+        assert!(matches!(alice.encrypt(b"three"), Err(NoiseError::NonceExhausted)));
+        
+        // Receiving side is bounded the same way
+        bob.set_nonce_limit(1);
+        bob.decrypt(&ct1).unwrap();
+        assert!(matches!(bob.decrypt(&ct1), Err(NoiseError::NonceExhausted)));
+    }
+    
+    #[test]
+    fn test_explicit_nonce_out_of_order() {
+        let (mut alice, mut bob) = perform_handshake().unwrap();
+        
+        let frame1 = alice.encrypt_with_explicit_nonce(b"first").unwrap();
+        let frame2 = alice.encrypt_with_explicit_nonce(b"second").unwrap();
+        let frame3 = alice.encrypt_with_explicit_nonce(b"third").unwrap();
+        
+        assert_eq!(bob.decrypt_with_explicit_nonce(&frame3).unwrap(), (2, b"third".to_vec()));
+        assert_eq!(bob.decrypt_with_explicit_nonce(&frame1).unwrap(), (0, b"first".to_vec()));
+        assert_eq!(bob.decrypt_with_explicit_nonce(&frame2).unwrap(), (1, b"second".to_vec()));
+        
+        // Tampering with the carried nonce breaks authentication
+        let mut forged = frame1.clone();
+        forged[11] = 5;
+        assert!(bob.decrypt_with_explicit_nonce(&forged).is_err());
+        assert!(bob.decrypt_with_explicit_nonce(&frame1[..4]).is_err());
+    }
+    
+    #[test]
+    fn test_channel_binding_mismatch_fails() {
+        use crate::core::crypto::channel_binding;
+        
+        let link_a = channel_binding("ble", &[b"alice-addr", b"relay-addr"]);
+        let link_b = channel_binding("ble", &[b"relay-addr", b"bob-addr"]);
+        
+        // Same link on both sides completes normally
+        let mut initiator = NoiseSession::with_channel_binding(&static_key(1), true, &link_a).unwrap();
+        let mut responder = NoiseSession::with_channel_binding(&static_key(2), false, &link_a).unwrap();
+        let msg1 = initiator.write_message(&[]).unwrap();
+        responder.read_message(&msg1).unwrap();
+        let msg2 = responder.write_message(&[]).unwrap();
+        initiator.read_message(&msg2).unwrap();
+        
+        // A relay splicing two links together is detected at the first encrypted message
+        let mut initiator = NoiseSession::with_channel_binding(&static_key(1), true, &link_a).unwrap();
+        let mut responder = NoiseSession::with_channel_binding(&static_key(2), false, &link_b).unwrap();
+        let msg1 = initiator.write_message(&[]).unwrap();
+        responder.read_message(&msg1).unwrap();
+        let msg2 = responder.write_message(&[]).unwrap();
+        assert!(initiator.read_message(&msg2).is_err());
+    }
+    
+    #[test]
+    fn test_rekey_matches_snow() {
+        let (mut alice, mut bob) = perform_handshake().unwrap();
+        
+        alice.rekey_outgoing().unwrap();
+        if let NoiseState::Transport(ref mut transport) = bob.state {
+            transport.rekey_incoming();
+        }
+        
+        let ct = alice.encrypt(b"after rekey").unwrap();
+        assert_eq!(bob.decrypt(&ct).unwrap(), b"after rekey");
+        assert_eq!(alice.sending_generation(), 1);
+        assert_eq!(alice.receiving_generation(), 0);
+    }
+    
+    #[test]
+    fn test_stats_count_traffic() {
+        let (mut alice, mut bob) = perform_handshake().unwrap();
+        assert!(alice.stats().handshake_duration.is_some());
+        assert_eq!(alice.stats().messages_sent, 0);
+        
+        let first = alice.encrypt(&[1u8; 100]).unwrap();
+        let second = alice.encrypt(&[2u8; 10]).unwrap();
+        bob.decrypt(&first).unwrap();
+        let mut forged = second.clone();
+        forged[0] ^= 1;
+        assert!(bob.decrypt(&forged).is_err());
+        bob.decrypt(&second).unwrap();
+        alice.rekey_outgoing().unwrap();
+        bob.rekey_incoming().unwrap();
+        
+        let sent = alice.stats();
+        assert_eq!((sent.messages_sent, sent.bytes_sent, sent.rekeys), (2, 142, 1));
+        let received = bob.stats();
+        assert_eq!((received.messages_received, received.bytes_received), (2, 142));
+        assert_eq!((received.decrypt_failures, received.rekeys), (1, 1));
+        
+        let mut total = sent;
+        total.merge(&received);
+        assert_eq!((total.messages_sent, total.messages_received, total.rekeys), (2, 2, 2));
+    }
+    
+    #[test]
+    fn test_session_id() {
+        assert!(NoiseSession::new_initiator().unwrap().session_id().is_none());
+        let (alice, bob) = perform_handshake().unwrap();
+        let (other, _) = perform_handshake().unwrap();
+        
+        let id = alice.session_id().unwrap();
+        assert_eq!(Some(id), bob.session_id());
+        assert_ne!(Some(id), other.session_id());
+        assert_eq!(id.to_string().len(), 2 * SESSION_ID_LEN);
+        assert!(!alice.get_handshake_hash().unwrap().starts_with(id.as_bytes()));
+        // Short enough to pass through log scrubbing intact
+        assert_eq!(crate::core::logging::scrub(&id.to_string()), id.to_string());
+    }
+    
+    #[test]
+    fn test_export_key() {
+        assert!(NoiseSession::new_initiator().unwrap().export_key("db", &[], 32).is_err());
+        let (mut alice, bob) = perform_handshake().unwrap();
+        
+        let key = alice.export_key("db", b"user-1", 32).unwrap();
+        assert_eq!(key.len(), 32);
+        assert_eq!(key, bob.export_key("db", b"user-1", 32).unwrap());
+        assert_ne!(key, alice.export_key("files", b"user-1", 32).unwrap());
+        assert_ne!(key, alice.export_key("db", b"user-2", 32).unwrap());
+        assert_ne!(key[..16], alice.export_key("db", b"user-1", 16).unwrap()[..]);
+        
+        // Stable across rekeys and snapshots, distinct between sessions
+        alice.rekey_outgoing().unwrap();
+        let restored = NoiseSession::from_transport_snapshot(alice.transport_snapshot().unwrap()).unwrap();
+        assert_eq!(key, restored.export_key("db", b"user-1", 32).unwrap());
+        let (other, _) = perform_handshake().unwrap();
+        assert_ne!(key, other.export_key("db", b"user-1", 32).unwrap());
+        assert!(alice.export_key("db", &[], 0).is_err());
+    }
+    
+    #[test]
+    fn test_deterministic_handshake_is_reproducible() {
+        let run = || {
+            let mut alice = NoiseSessionBuilder::new().deterministic(b"alice").unwrap().build_initiator().unwrap();
+            let mut bob = NoiseSessionBuilder::new().deterministic(b"bob").unwrap().build_responder().unwrap();
+            let msg1 = alice.write_message(b"one").unwrap();
+            bob.read_message(&msg1).unwrap();
+            let msg2 = bob.write_message(b"two").unwrap();
+            alice.read_message(&msg2).unwrap();
+            let msg3 = alice.write_message(b"three").unwrap();
+            bob.read_message(&msg3).unwrap();
+            (msg1, msg2, msg3, alice.encrypt(b"four").unwrap())
+        };
+        assert_eq!(run(), run());
+        
+        let fixed = NoiseSessionBuilder::new().private_key(&static_key(1)).fixed_ephemeral(&[2u8; 32]);
+        let mut session = fixed.build_initiator().unwrap();
+        assert_eq!(session.write_message(&[]).unwrap(), public_from_private(&[2u8; 32]).unwrap());
+        assert!(NoiseSessionBuilder::new().fixed_ephemeral(&[2u8; 31]).build_initiator().is_err());
+    }
+    
+    #[test]
+    fn test_explicit_frames_resync_generation() {
+        let (mut alice, mut bob) = perform_handshake().unwrap();
+        
+        let old = alice.encrypt_with_explicit_nonce(b"gen 0").unwrap();
+        alice.rekey_outgoing().unwrap();
+        alice.rekey_outgoing().unwrap();
+        let new = alice.encrypt_with_explicit_nonce(b"gen 2").unwrap();
+        
+        // Bob never saw the rekey signals but catches up from the frame header
+        assert_eq!(bob.decrypt_with_explicit_nonce(&new).unwrap().1, b"gen 2");
+        assert_eq!(bob.receiving_generation(), 2);
+        assert!(bob.decrypt_with_explicit_nonce(&old).is_err());
+        
+        // A forged generation that fails to authenticate leaves the keys alone
+        let mut forged = alice.encrypt_with_explicit_nonce(b"gen 2 again").unwrap();
+        forged[3] = 5;
+        let rekeys = bob.stats().rekeys;
+        assert!(bob.decrypt_with_explicit_nonce(&forged).is_err());
+        assert_eq!(bob.receiving_generation(), 2);
+        assert_eq!(bob.stats().rekeys, rekeys);
+        forged[3] = 2;
+        assert_eq!(bob.decrypt_with_explicit_nonce(&forged).unwrap().1, b"gen 2 again");
+    }
+    
+    #[test]
+    fn test_in_place_and_into_buffers() {
+        let (mut initiator, mut responder) = perform_handshake().unwrap();
+        
+        let mut out = [0u8; 128];
+        assert!(matches!(
+            initiator.encrypt_into(b"hello", &mut out[..20]),
+            Err(NoiseError::BufferTooSmall { needed: 21, got: 20 })
+        ));
+        let len = initiator.encrypt_into(b"hello", &mut out).unwrap();
+        let mut plain = [0u8; 64];
+        let plain_len = responder.decrypt_into(&out[..len], &mut plain).unwrap();
+        assert_eq!(&plain[..plain_len], b"hello");
+        
+        let mut buf = Vec::with_capacity(256);
+        buf.extend_from_slice(b"in place");
+        let capacity = buf.capacity();
+        initiator.encrypt_in_place(&mut buf).unwrap();
+        assert_eq!(buf.len(), 8 + 16);
+        responder.decrypt_in_place(&mut buf).unwrap();
+        assert_eq!(buf, b"in place");
+        assert_eq!(buf.capacity(), capacity);
+        
+        // Padding is applied and stripped the same way
+        initiator.set_padding(Some(PaddingScheme::Padme));
+        responder.set_padding(Some(PaddingScheme::default_buckets()));
+        initiator.encrypt_in_place(&mut buf).unwrap();
+        assert_eq!(buf.len(), PaddingScheme::Padme.padded_len(8) + 16);
+        responder.decrypt_in_place(&mut buf).unwrap();
+        assert_eq!(buf, b"in place");
+        let len = responder.encrypt_into(b"padded", &mut out).unwrap();
+        assert_eq!(len, 64 + 16);
+        assert_eq!(initiator.decrypt(&out[..len]).unwrap(), b"padded");
+    }
+    
+    #[test]
+    fn test_buffer_grows_and_shrinks_on_demand() {
+        let (mut initiator, mut responder) = perform_handshake().unwrap();
+        assert_eq!(initiator.buffer.len(), 0);
+        assert_eq!(responder.buffer.len(), 0);
+        
+        initiator.set_padding(Some(PaddingScheme::Padme));
+        responder.set_padding(Some(PaddingScheme::Padme));
+        let ciphertext = initiator.encrypt(&[1u8; 20000]).unwrap();
+        assert!(initiator.buffer.len() >= 20000 && initiator.buffer.len() < NoiseSession::MAX_MESSAGE_LEN);
+        responder.decrypt(&ciphertext).unwrap();
+        
+        for _ in 0..BUFFER_SHRINK_AFTER {
+            let ciphertext = initiator.encrypt(b"small").unwrap();
+            assert_eq!(responder.decrypt(&ciphertext).unwrap(), b"small");
+        }
+        assert_eq!(initiator.buffer.len(), 0);
+    }
+    
+    #[test]
+    fn test_scratch_from_shared_pool() {
+        let (mut initiator, mut responder) = perform_handshake().unwrap();
+        let pool = BufferPool::new(1);
+        initiator.set_buffer_pool(Some(pool.clone()));
+        initiator.set_padding(Some(PaddingScheme::Padme));
+        responder.set_padding(Some(PaddingScheme::Padme));
+        
+        for _ in 0..3 {
+            let ciphertext = initiator.encrypt(&[1u8; 20000]).unwrap();
+            assert_eq!(responder.decrypt(&ciphertext).unwrap(), vec![1u8; 20000]);
+        }
+        assert_eq!(initiator.buffer.len(), 0);
+        let stats = pool.stats();
+        assert_eq!((stats.acquired, stats.reused, stats.in_use, stats.idle), (3, 2, 0, 1));
+    }
+    
+    #[test]
+    fn test_max_message_len() {
+        let mut initiator = NoiseSessionBuilder::new().max_message_len(512).build_initiator().unwrap();
+        let mut responder = NoiseSession::new_responder().unwrap();
+        assert_eq!(initiator.max_message_len(), 512);
+        assert!(initiator.write_message(&[0u8; 600]).is_err());
+        
+        let msg1 = initiator.write_message(&[]).unwrap();
+        responder.read_message(&msg1).unwrap();
+        let msg2 = responder.write_message(&[]).unwrap();
+        initiator.read_message(&msg2).unwrap();
+        let msg3 = initiator.write_message(&[]).unwrap();
+        responder.read_message(&msg3).unwrap();
+        
+        assert!(matches!(initiator.encrypt(&[0u8; 497]), Err(NoiseError::InvalidParameter)));
+        let ciphertext = initiator.encrypt(&[0u8; 496]).unwrap();
+        assert_eq!(responder.decrypt(&ciphertext).unwrap().len(), 496);
+        
+        let ciphertext = responder.encrypt(&[0u8; 1000]).unwrap();
+        assert!(matches!(initiator.decrypt(&ciphertext), Err(NoiseError::InvalidMessage)));
+    }
+    
+    #[test]
+    fn test_size_hints_are_exact() {
+        let (mut initiator, mut responder) = perform_handshake().unwrap();
+        for padding in [None, Some(PaddingScheme::Padme), Some(PaddingScheme::default_buckets())] {
+            initiator.set_padding(padding.clone());
+            responder.set_padding(padding);
+            for len in [0, 1, 100, 5000] {
+                let ciphertext = initiator.encrypt(&vec![7u8; len]).unwrap();
+                assert_eq!(initiator.encrypt_size(len), ciphertext.len());
+                let mut out = vec![0u8; responder.decrypt_size(ciphertext.len())];
+                assert_eq!(responder.decrypt_into(&ciphertext, &mut out).unwrap(), len);
+            }
+        }
+    }
+    
+    #[test]
+    fn test_vectored_encrypt() {
+        let (mut initiator, mut responder) = perform_handshake().unwrap();
+        let header = [1u8, 2, 3];
+        let body = b"body bytes";
+        
+        let ciphertext = initiator.encrypt_vectored(&[IoSlice::new(&header), IoSlice::new(body)]).unwrap();
+        assert_eq!(responder.decrypt(&ciphertext).unwrap(), b"\x01\x02\x03body bytes");
+        
+        let mut out = [0u8; 64];
+        assert!(initiator.encrypt_vectored_into(&[IoSlice::new(body)], &mut out[..10]).is_err());
+        let len = initiator.encrypt_vectored_into(&[IoSlice::new(&header), IoSlice::new(&[])], &mut out).unwrap();
+        assert_eq!(responder.decrypt(&out[..len]).unwrap(), header);
+        
+        let large = vec![0u8; NOISE_MAX_PAYLOAD_LEN];
+        assert!(initiator.encrypt_vectored(&[IoSlice::new(&large), IoSlice::new(&large)]).is_err());
+    }
+    
+    #[test]
+    fn test_large_payloads() {
+        let (mut initiator, mut responder) = perform_handshake().unwrap();
+        let photo: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        
+        let messages = initiator.encrypt_large(&photo).unwrap();
+        assert_eq!(messages.len(), 4);
+        assert!(messages.iter().all(|m| m.len() <= NOISE_MAX_PAYLOAD_LEN + 16));
+        assert_eq!(responder.decrypt_large(&messages).unwrap(), photo);
+        
+        let empty = initiator.encrypt_large(&[]).unwrap();
+        assert_eq!(responder.decrypt_large(&empty).unwrap(), b"");
+        
+        // A truncated run is rejected even though each message decrypts
+        initiator.set_padding(Some(PaddingScheme::Padme));
+        responder.set_padding(Some(PaddingScheme::Padme));
+        let messages = initiator.encrypt_large(&photo).unwrap();
+        assert!(responder.decrypt_large(&messages[..3]).is_err());
+    }
+    
+    #[test]
+    fn test_adopt_snow_sessions() {
+        let params: NoiseParams = NoiseSession::NOISE_PARAMS.parse().unwrap();
+        let mut initiator = Builder::new(params.clone())
+            .local_private_key(&[1u8; 32]).unwrap()
+            .build_initiator().unwrap();
+        let mut responder = Builder::new(params.clone())
+            .local_private_key(&[2u8; 32]).unwrap()
+            .build_responder().unwrap();
+        
+        // The app ran the first message with snow directly, then hands over
+        let mut buf = [0u8; 1024];
+        let len = initiator.write_message(&[], &mut buf).unwrap();
+        responder.read_message(&buf[..len], &mut []).unwrap();
+        
+        let mut alice = NoiseSession::from_snow_handshake(initiator, &params).unwrap();
+        let len = responder.write_message(&[], &mut buf).unwrap();
+        alice.read_message(&buf[..len]).unwrap();
+        let msg3 = alice.write_message(&[]).unwrap();
+        responder.read_message(&msg3, &mut []).unwrap();
+        
+        // The responder side was already in snow's stateful transport mode
+        let mut snow_bob = responder.into_transport_mode().unwrap();
+        let len = snow_bob.write_message(b"from snow", &mut buf).unwrap();
+        assert_eq!(alice.decrypt(&buf[..len]).unwrap(), b"from snow");
+        
+        let mut bob = NoiseSession::from_snow_transport(snow_bob, &params);
+        assert!(bob.is_transport_state());
+        assert!(bob.get_remote_static().is_some());
+        
+        let ct = alice.encrypt_with_explicit_nonce(b"one").unwrap();
+        let ct2 = alice.encrypt_with_explicit_nonce(b"two").unwrap();
+        assert_eq!(bob.decrypt_with_explicit_nonce(&ct2).unwrap().1, b"two");
+        assert_eq!(bob.decrypt_with_explicit_nonce(&ct).unwrap().1, b"one");
+        
+        let ct = bob.encrypt(b"adopted").unwrap();
+        assert_eq!(alice.decrypt(&ct).unwrap(), b"adopted");
+        
+        // Rekeys interoperate with sessions that track raw keys
+        bob.rekey_outgoing().unwrap();
+        alice.rekey_incoming().unwrap();
+        let ct = bob.encrypt(b"rekeyed").unwrap();
+        assert_eq!(alice.decrypt(&ct).unwrap(), b"rekeyed");
+        assert!(bob.transport_snapshot().is_err());
+    }
+    
+    #[test]
+    fn test_split_halves_on_threads() {
+        let (alice, mut bob) = perform_handshake().unwrap();
+        let (mut send, recv) = alice.split().unwrap();
+        
+        let ct = bob.encrypt(b"to alice").unwrap();
+        let reader = std::thread::spawn(move || {
+            let mut recv = recv;
+            recv.decrypt(&ct).unwrap()
+        });
+        let ct = send.encrypt(b"to bob").unwrap();
+        
+        assert_eq!(reader.join().unwrap(), b"to alice");
+        assert_eq!(bob.decrypt(&ct).unwrap(), b"to bob");
+        
+        send.rekey().unwrap();
+        bob.rekey_incoming().unwrap();
+        let ct = send.encrypt(b"rekeyed").unwrap();
+        assert_eq!(bob.decrypt(&ct).unwrap(), b"rekeyed");
+        assert_eq!(send.generation(), 1);
+    }
+    
+    #[test]
+    fn test_split_requires_transport() {
+        let session = NoiseSession::new_initiator().unwrap();
+        assert!(session.split().is_err());
+    }
+    
     #[test]
     fn test_invalid_state_errors() {
         let mut session = NoiseSession::new_initiator().unwrap();
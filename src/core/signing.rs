@@ -0,0 +1,176 @@
+//! Long-term Ed25519 identities that vouch for Noise static keys
+//!
+//! A [`SigningIdentity`] signs each X25519 static key it uses, producing a
+//! [`StaticKeyCertificate`] sent in the handshake payload. Peers pin the
+//! Ed25519 identity instead of the static key, so static keys can rotate
+//! without the peer looking like a stranger.
+
+use crate::core::crypto::random_bytes;
+use crate::core::error::{NoiseError, Result};
+use crate::core::keys::KEY_LEN;
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use std::sync::Arc;
+use zeroize::Zeroize;
+
+/// Length of Ed25519 public and secret keys
+pub const IDENTITY_KEY_LEN: usize = 32;
+
+/// Length of an Ed25519 signature
+pub const IDENTITY_SIGNATURE_LEN: usize = 64;
+
+/// Encoded length of a [`StaticKeyCertificate`]
+pub const CERTIFICATE_LEN: usize = 1 + IDENTITY_KEY_LEN + KEY_LEN + IDENTITY_SIGNATURE_LEN;
+
+/// Certificate format version
+const CERTIFICATE_VERSION: u8 = 1;
+
+/// Domain separator for static key signatures
+const CERTIFICATE_LABEL: &[u8] = b"noise-mobile/static-key/v1";
+
+/// Decides whether a peer's verified identity is acceptable
+///
+/// Called during the handshake with a certificate whose signature and
+/// static key have already been checked; return `false` to abort.
+pub type IdentityVerifier = Arc<dyn Fn(&StaticKeyCertificate) -> bool + Send + Sync>;
+
+/// A long-term Ed25519 signing key
+///
+/// The secret key is wiped when the identity is dropped.
+pub struct SigningIdentity {
+    key: SigningKey,
+}
+
+impl SigningIdentity {
+    /// Generate an identity from the operating system CSPRNG
+    pub fn generate() -> Self {
+        let mut secret = [0u8; IDENTITY_KEY_LEN];
+        random_bytes(&mut secret);
+        let key = SigningKey::from_bytes(&secret);
+        secret.zeroize();
+        Self { key }
+    }
+    
+    /// Rebuild an identity from a stored secret key
+    pub fn from_secret(secret: &[u8]) -> Result<Self> {
+        let secret: &[u8; IDENTITY_KEY_LEN] = secret.try_into().map_err(|_| NoiseError::InvalidParameter)?;
+        Ok(Self { key: SigningKey::from_bytes(secret) })
+    }
+    
+    /// The secret key, to persist in secure storage
+    pub fn secret_key(&self) -> &[u8; IDENTITY_KEY_LEN] {
+        self.key.as_bytes()
+    }
+    
+    /// The public identity key peers pin
+    pub fn public_key(&self) -> [u8; IDENTITY_KEY_LEN] {
+        self.key.verifying_key().to_bytes()
+    }
+    
+    /// Sign an arbitrary message, e.g. a protocol's own static key binding
+    pub(crate) fn sign(&self, message: &[u8]) -> [u8; IDENTITY_SIGNATURE_LEN] {
+        self.key.sign(message).to_bytes()
+    }
+    
+    /// Sign a Noise static public key
+    pub fn certify(&self, static_key: &[u8]) -> Result<StaticKeyCertificate> {
+        let static_key: [u8; KEY_LEN] = static_key.try_into().map_err(|_| NoiseError::InvalidParameter)?;
+        let signature = self.key.sign(&certificate_message(&static_key));
+        Ok(StaticKeyCertificate {
+            identity_key: self.public_key(),
+            static_key,
+            signature: signature.to_bytes(),
+        })
+    }
+}
+
+impl std::fmt::Debug for SigningIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SigningIdentity").field("public", &self.public_key()).finish_non_exhaustive()
+    }
+}
+
+/// An identity key's signature over a Noise static key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaticKeyCertificate {
+    /// Ed25519 public key of the signer
+    pub identity_key: [u8; IDENTITY_KEY_LEN],
+    /// The X25519 static key being vouched for
+    pub static_key: [u8; KEY_LEN],
+    /// Ed25519 signature over the static key
+    pub signature: [u8; IDENTITY_SIGNATURE_LEN],
+}
+
+impl StaticKeyCertificate {
+    /// Check the signature
+    pub fn verify(&self) -> bool {
+        let Ok(identity) = VerifyingKey::from_bytes(&self.identity_key) else {
+            return false;
+        };
+        let signature = Signature::from_bytes(&self.signature);
+        identity.verify_strict(&certificate_message(&self.static_key), &signature).is_ok()
+    }
+    
+    /// Encode for a handshake payload
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(CERTIFICATE_LEN);
+        data.push(CERTIFICATE_VERSION);
+        data.extend_from_slice(&self.identity_key);
+        data.extend_from_slice(&self.static_key);
+        data.extend_from_slice(&self.signature);
+        data
+    }
+    
+    /// Decode a certificate; the signature is not checked
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        if data.len() != CERTIFICATE_LEN || data[0] != CERTIFICATE_VERSION {
+            return Err(NoiseError::InvalidMessage);
+        }
+        let (identity_key, rest) = data[1..].split_at(IDENTITY_KEY_LEN);
+        let (static_key, signature) = rest.split_at(KEY_LEN);
+        Ok(Self {
+            identity_key: identity_key.try_into().map_err(|_| NoiseError::InvalidMessage)?,
+            static_key: static_key.try_into().map_err(|_| NoiseError::InvalidMessage)?,
+            signature: signature.try_into().map_err(|_| NoiseError::InvalidMessage)?,
+        })
+    }
+}
+
+fn certificate_message(static_key: &[u8; KEY_LEN]) -> Vec<u8> {
+    [CERTIFICATE_LABEL, static_key].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::keys::Keypair;
+    
+    #[test]
+    fn test_certificate_roundtrip() {
+        let identity = SigningIdentity::generate();
+        let static_key = Keypair::generate().unwrap();
+        let certificate = identity.certify(static_key.public_key()).unwrap();
+        assert!(certificate.verify());
+        assert_eq!(certificate.identity_key, identity.public_key());
+        
+        let decoded = StaticKeyCertificate::from_bytes(&certificate.to_bytes()).unwrap();
+        assert_eq!(decoded, certificate);
+        assert_eq!(certificate.to_bytes().len(), CERTIFICATE_LEN);
+        
+        let restored = SigningIdentity::from_secret(identity.secret_key()).unwrap();
+        assert_eq!(restored.public_key(), identity.public_key());
+        assert!(!format!("{:?}", identity).contains("key:"));
+    }
+    
+    #[test]
+    fn test_tampered_certificate() {
+        let identity = SigningIdentity::generate();
+        let mut certificate = identity.certify(&[5u8; KEY_LEN]).unwrap();
+        certificate.static_key[0] ^= 1;
+        assert!(!certificate.verify());
+        
+        let mut certificate = identity.certify(&[5u8; KEY_LEN]).unwrap();
+        certificate.identity_key = SigningIdentity::generate().public_key();
+        assert!(!certificate.verify());
+        assert!(identity.certify(&[5u8; 16]).is_err());
+    }
+}
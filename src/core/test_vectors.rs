@@ -0,0 +1,356 @@
+//! Noise test vectors
+//!
+//! Loads vector files in the cacophony / snow JSON format and replays them
+//! through [`NoiseSession`], with the fixed ephemeral keys each vector
+//! specifies. Only built with the `test-utils` feature.
+//!
+//! ```ignore
+//! for vector in load_vectors("vectors/cacophony.txt")? {
+//!     if vector.is_supported() {
+//!         vector.run()?;
+//!     }
+//! }
+//! ```
+
+use crate::core::error::{NoiseError, Result};
+use crate::core::session::NoiseSession;
+use snow::params::{HandshakeModifier, NoiseParams};
+use snow::resolvers::{CryptoResolver, DefaultResolver};
+use serde_json::Value;
+use snow::Builder;
+use std::path::Path;
+
+/// One side's keys and settings in a [`TestVector`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VectorParty {
+    /// Prologue mixed into the handshake
+    pub prologue: Vec<u8>,
+    /// PSKs in the order of the `psk` modifiers in the protocol name
+    pub psks: Vec<Vec<u8>>,
+    /// Static private key
+    pub static_key: Option<Vec<u8>>,
+    /// Fixed ephemeral private key
+    pub ephemeral: Option<Vec<u8>>,
+    /// Peer's static public key, for patterns where it is known up front
+    pub remote_static: Option<Vec<u8>>,
+}
+
+/// A message in a [`TestVector`], in the order it is sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VectorMessage {
+    /// Plaintext the sender passes in
+    pub payload: Vec<u8>,
+    /// Expected bytes on the wire
+    pub ciphertext: Vec<u8>,
+}
+
+/// A single handshake and transport exchange with its expected output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestVector {
+    /// Full Noise protocol name, e.g. `Noise_XX_25519_ChaChaPoly_BLAKE2s`
+    pub protocol_name: String,
+    /// Initiator's keys and settings
+    pub initiator: VectorParty,
+    /// Responder's keys and settings
+    pub responder: VectorParty,
+    /// Expected handshake hash, if the vector gives one
+    pub handshake_hash: Option<Vec<u8>>,
+    /// Handshake then transport messages, alternating sides unless the
+    /// pattern is one-way
+    pub messages: Vec<VectorMessage>,
+    /// Fallback and other compound protocols, which [`run`](Self::run) skips
+    pub fallback: bool,
+}
+
+impl TestVector {
+    /// Whether this build can replay the vector
+    ///
+    /// False for fallback vectors and protocols whose primitives are not
+    /// compiled in.
+    pub fn is_supported(&self) -> bool {
+        let resolver = DefaultResolver;
+        !self.fallback
+            && self.protocol_name.parse::<NoiseParams>().is_ok_and(|params| {
+                resolver.resolve_dh(&params.dh).is_some()
+                    && resolver.resolve_cipher(&params.cipher).is_some()
+                    && resolver.resolve_hash(&params.hash).is_some()
+            })
+    }
+    
+    /// Replay the vector, checking every ciphertext, payload and the
+    /// handshake hash
+    ///
+    /// Fails with [`NoiseError::InvalidState`] naming the first mismatch.
+    pub fn run(&self) -> Result<()> {
+        if !self.is_supported() {
+            return Err(NoiseError::InvalidParameter);
+        }
+        let params: NoiseParams = self.protocol_name.parse()?;
+        let mut initiator = party_session(&params, &self.initiator, true)?;
+        let mut responder = party_session(&params, &self.responder, false)?;
+        let one_way = params.handshake.pattern.is_oneway();
+        
+        for (index, message) in self.messages.iter().enumerate() {
+            let initiator_sends = one_way || index.is_multiple_of(2);
+            let (sender, receiver) = if initiator_sends {
+                (&mut initiator, &mut responder)
+            } else {
+                (&mut responder, &mut initiator)
+            };
+            
+            let (ciphertext, payload) = if sender.is_transport_state() {
+                let ciphertext = sender.encrypt(&message.payload)?;
+                let payload = receiver.decrypt(&ciphertext)?;
+                (ciphertext, payload)
+            } else {
+                let ciphertext = sender.write_message(&message.payload)?;
+                let payload = receiver.read_message(&ciphertext)?;
+                (ciphertext, payload)
+            };
+            if ciphertext != message.ciphertext {
+                return Err(NoiseError::InvalidState(format!("message {index}: ciphertext mismatch")));
+            }
+            if payload != message.payload {
+                return Err(NoiseError::InvalidState(format!("message {index}: payload mismatch")));
+            }
+        }
+        
+        if let Some(expected) = &self.handshake_hash {
+            for (role, session) in [("initiator", &initiator), ("responder", &responder)] {
+                if session.get_handshake_hash() != Some(&expected[..]) {
+                    return Err(NoiseError::InvalidState(format!("{role}: handshake hash mismatch")));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Build one side of a vector with its fixed keys
+fn party_session(params: &NoiseParams, party: &VectorParty, is_initiator: bool) -> Result<NoiseSession> {
+    let mut builder = Builder::new(params.clone()).prologue(&party.prologue)?;
+    if let Some(static_key) = &party.static_key {
+        builder = builder.local_private_key(static_key)?;
+    }
+    if let Some(ephemeral) = &party.ephemeral {
+        builder = builder.fixed_ephemeral_key_for_testing_only(ephemeral)?;
+    }
+    if let Some(remote_static) = &party.remote_static {
+        builder = builder.remote_public_key(remote_static)?;
+    }
+    
+    // PSKs are listed in the order of the psk modifiers in the name
+    let locations = params.handshake.modifiers.list.iter().filter_map(|modifier| match modifier {
+        HandshakeModifier::Psk(location) => Some(*location),
+        _ => None,
+    });
+    for (location, psk) in locations.zip(&party.psks) {
+        builder = builder.psk(location, psk)?;
+    }
+    
+    let handshake = if is_initiator {
+        builder.build_initiator()?
+    } else {
+        builder.build_responder()?
+    };
+    NoiseSession::from_snow_handshake(handshake, params)
+}
+
+/// Read a vector file from disk
+pub fn load_vectors(path: impl AsRef<Path>) -> Result<Vec<TestVector>> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| NoiseError::InvalidState(format!("cannot read vector file: {e}")))?;
+    parse_vectors(&text)
+}
+
+/// Parse the `{"vectors": [...]}` document of a vector file
+pub fn parse_vectors(text: &str) -> Result<Vec<TestVector>> {
+    let document: Value = serde_json::from_str(text).map_err(|_| NoiseError::InvalidMessage)?;
+    let vectors = document.get("vectors").and_then(Value::as_array).ok_or(NoiseError::InvalidMessage)?;
+    vectors.iter().map(parse_vector).collect()
+}
+
+fn parse_vector(value: &Value) -> Result<TestVector> {
+    let protocol_name = value
+        .get("protocol_name")
+        .and_then(Value::as_str)
+        .ok_or(NoiseError::InvalidMessage)?
+        .to_string();
+    let messages = value
+        .get("messages")
+        .and_then(Value::as_array)
+        .ok_or(NoiseError::InvalidMessage)?
+        .iter()
+        .map(|message| {
+            Ok(VectorMessage {
+                payload: hex_field(message, "payload")?.ok_or(NoiseError::InvalidMessage)?,
+                ciphertext: hex_field(message, "ciphertext")?.ok_or(NoiseError::InvalidMessage)?,
+            })
+        })
+        .collect::<Result<_>>()?;
+    
+    Ok(TestVector {
+        protocol_name,
+        initiator: parse_party(value, "init")?,
+        responder: parse_party(value, "resp")?,
+        handshake_hash: hex_field(value, "handshake_hash")?,
+        messages,
+        fallback: value.get("fallback").is_some_and(|fallback| fallback.as_bool() == Some(true))
+            || value.get("fallback_pattern").is_some(),
+    })
+}
+
+fn parse_party(value: &Value, prefix: &str) -> Result<VectorParty> {
+    let psks = match value.get(format!("{prefix}_psks")) {
+        Some(psks) => psks
+            .as_array()
+            .ok_or(NoiseError::InvalidMessage)?
+            .iter()
+            .map(|psk| psk.as_str().ok_or(NoiseError::InvalidMessage).and_then(decode_hex))
+            .collect::<Result<_>>()?,
+        None => Vec::new(),
+    };
+    Ok(VectorParty {
+        prologue: hex_field(value, &format!("{prefix}_prologue"))?.unwrap_or_default(),
+        psks,
+        static_key: hex_field(value, &format!("{prefix}_static"))?,
+        ephemeral: hex_field(value, &format!("{prefix}_ephemeral"))?,
+        remote_static: hex_field(value, &format!("{prefix}_remote_static"))?,
+    })
+}
+
+/// Decode an optional hex string field
+fn hex_field(value: &Value, key: &str) -> Result<Option<Vec<u8>>> {
+    match value.get(key) {
+        Some(field) => field.as_str().ok_or(NoiseError::InvalidMessage).and_then(decode_hex).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn decode_hex(text: &str) -> Result<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return Err(NoiseError::InvalidMessage);
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2).ok_or(NoiseError::InvalidMessage)?, 16).map_err(|_| NoiseError::InvalidMessage))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const PROTOCOL: &str = "Noise_XXpsk3_25519_ChaChaPoly_BLAKE2s";
+    
+    fn hex(data: &[u8]) -> String {
+        data.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+    
+    /// Produce a vector file with snow alone, independent of [`NoiseSession`]
+    fn reference_vector() -> String {
+        let params: NoiseParams = PROTOCOL.parse().unwrap();
+        let psk = [9u8; 32];
+        let mut initiator = Builder::new(params.clone())
+            .prologue(b"vectors")
+            .unwrap()
+            .local_private_key(&[1u8; 32])
+            .unwrap()
+            .fixed_ephemeral_key_for_testing_only(&[2u8; 32])
+            .unwrap()
+            .psk(3, &psk)
+            .unwrap()
+            .build_initiator()
+            .unwrap();
+        let mut responder = Builder::new(params)
+            .prologue(b"vectors")
+            .unwrap()
+            .local_private_key(&[3u8; 32])
+            .unwrap()
+            .fixed_ephemeral_key_for_testing_only(&[4u8; 32])
+            .unwrap()
+            .psk(3, &psk)
+            .unwrap()
+            .build_responder()
+            .unwrap();
+        
+        let mut messages = Vec::new();
+        let mut buffer = [0u8; 1024];
+        let mut scratch = [0u8; 1024];
+        for (index, payload) in [&b"one"[..], b"two", b"three"].into_iter().enumerate() {
+            let (sender, receiver) = if index % 2 == 0 {
+                (&mut initiator, &mut responder)
+            } else {
+                (&mut responder, &mut initiator)
+            };
+            let len = sender.write_message(payload, &mut buffer).unwrap();
+            receiver.read_message(&buffer[..len], &mut scratch).unwrap();
+            messages.push(format!(r#"{{"payload": "{}", "ciphertext": "{}"}}"#, hex(payload), hex(&buffer[..len])));
+        }
+        // Transport messages keep alternating, so the responder sends next
+        let hash = hex(initiator.get_handshake_hash());
+        let mut transport = responder.into_transport_mode().unwrap();
+        let len = transport.write_message(b"four", &mut buffer).unwrap();
+        messages.push(format!(r#"{{"payload": "{}", "ciphertext": "{}"}}"#, hex(b"four"), hex(&buffer[..len])));
+        
+        format!(
+            r#"{{"vectors": [{{
+                "protocol_name": "{PROTOCOL}",
+                "init_prologue": "{prologue}", "init_psks": ["{psk}"],
+                "init_static": "{}", "init_ephemeral": "{}",
+                "resp_prologue": "{prologue}", "resp_psks": ["{psk}"],
+                "resp_static": "{}", "resp_ephemeral": "{}",
+                "handshake_hash": "{hash}",
+                "messages": [{}]
+            }}]}}"#,
+            hex(&[1u8; 32]),
+            hex(&[2u8; 32]),
+            hex(&[3u8; 32]),
+            hex(&[4u8; 32]),
+            messages.join(", "),
+            prologue = hex(b"vectors"),
+            psk = hex(&psk),
+        )
+    }
+    
+    #[test]
+    fn test_reference_vector_replays() {
+        let vectors = parse_vectors(&reference_vector()).unwrap();
+        assert_eq!(vectors.len(), 1);
+        assert!(vectors[0].is_supported());
+        assert_eq!(vectors[0].initiator.psks, vec![vec![9u8; 32]]);
+        vectors[0].run().unwrap();
+        
+        let mut tampered = vectors[0].clone();
+        tampered.messages[1].ciphertext[0] ^= 1;
+        assert!(matches!(tampered.run(), Err(NoiseError::InvalidState(_))));
+        let mut fallback = vectors[0].clone();
+        fallback.fallback = true;
+        assert!(!fallback.is_supported());
+    }
+    
+    #[test]
+    fn test_cacophony_vectors() {
+        // Generated by cacophony, an independent Haskell implementation
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/vectors/cacophony.txt");
+        let vectors = load_vectors(path).unwrap();
+        let supported: Vec<_> = vectors.iter().filter(|vector| vector.is_supported()).collect();
+        assert!(supported.len() >= 50);
+        for vector in supported {
+            vector.run().unwrap_or_else(|e| panic!("{}: {e}", vector.protocol_name));
+        }
+    }
+    
+    #[test]
+    fn test_malformed_files_rejected() {
+        assert!(parse_vectors("").is_err());
+        assert!(parse_vectors(r#"{"vectors": [{"protocol_name": "x"}]}"#).is_err());
+        assert!(parse_vectors(r#"{"vectors": [{"protocol_name": "x", "messages": [{"payload": "0", "ciphertext": ""}]}]}"#).is_err());
+        assert!(parse_vectors(r#"{"vectors": []} trailing"#).is_err());
+        assert!(parse_vectors(r#"{"vectors": [], "version": 1.5, "ok": null}"#).unwrap().is_empty());
+        
+        // Deep nesting fails instead of overflowing the stack
+        let deep = format!(r#"{{"vectors": [], "x": {}{}}}"#, "[".repeat(100_000), "]".repeat(100_000));
+        assert!(parse_vectors(&deep).is_err());
+    }
+}
@@ -0,0 +1,341 @@
+//! Session tickets for fast resumption
+//!
+//! After a full handshake the responder can issue a [`SessionTicket`]: a
+//! fresh resumption secret plus an opaque blob holding that secret
+//! encrypted under the responder's ticket key, much like a TLS session
+//! ticket. The initiator stores it and later resumes with a one-round-trip
+//! `NNpsk0` handshake keyed by the secret, skipping every static-key DH.
+//! The responder keeps no per-client state: the blob, sent ahead of the
+//! first handshake message, gives the secret back.
+//!
+//! The ephemeral DH in `NNpsk0` keeps resumed sessions forward secret, but
+//! the payload of the first message is only protected by the ticket and
+//! can be replayed, like 0-RTT data. The issuer remembers each ticket and
+//! ephemeral key it accepted until the ticket expires and refuses them
+//! again, but that memory does not survive a restart. Use each ticket once
+//! and issue a new one on every resumed session.
+
+use crate::core::crypto::{aead_open, aead_seal, random_bytes};
+use crate::core::error::{NoiseError, Result};
+use crate::core::keys::KEY_LEN;
+use crate::core::secret::SecretBytes;
+use crate::core::session::NoiseSession;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+/// Protocol of resumed handshakes
+pub const TICKET_PARAMS: &str = "Noise_NNpsk0_25519_ChaChaPoly_BLAKE2s";
+
+/// Default time a ticket stays redeemable
+pub const DEFAULT_TICKET_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Default number of resumptions a [`TicketIssuer`] remembers
+pub const DEFAULT_TICKET_REPLAY_CAPACITY: usize = 10_000;
+
+/// Length of the ephemeral key that opens a resumed handshake
+const EPHEMERAL_LEN: usize = 32;
+
+/// Length of a resumption secret
+const SECRET_LEN: usize = 32;
+
+/// Format version of tickets and of [`SessionTicket::to_bytes`]
+const TICKET_VERSION: u8 = 1;
+
+/// Associated data binding sealed tickets to their purpose
+const TICKET_LABEL: &[u8] = b"noise-mobile/ticket/v1";
+
+/// Issues tickets and resumes sessions from them (responder side)
+///
+/// The ticket key must survive restarts for tickets to stay valid; keep it
+/// in secure storage. Replacing the key revokes every outstanding ticket.
+pub struct TicketIssuer {
+    key: Zeroizing<[u8; KEY_LEN]>,
+    lifetime: Duration,
+    replay_capacity: usize,
+    /// (ticket blob, ephemeral key) -> ticket expiry
+    accepted: HashMap<(Vec<u8>, [u8; EPHEMERAL_LEN]), u64>,
+}
+
+impl Default for TicketIssuer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TicketIssuer {
+    /// Create an issuer with a random ticket key
+    pub fn new() -> Self {
+        let mut key = Zeroizing::new([0u8; KEY_LEN]);
+        random_bytes(&mut key[..]);
+        Self::with_key(key)
+    }
+    
+    /// Create an issuer with a stored ticket key
+    pub fn from_key(key: &[u8]) -> Result<Self> {
+        let key: [u8; KEY_LEN] = key.try_into().map_err(|_| NoiseError::InvalidParameter)?;
+        Ok(Self::with_key(Zeroizing::new(key)))
+    }
+    
+    fn with_key(key: Zeroizing<[u8; KEY_LEN]>) -> Self {
+        Self {
+            key,
+            lifetime: DEFAULT_TICKET_LIFETIME,
+            replay_capacity: DEFAULT_TICKET_REPLAY_CAPACITY,
+            accepted: HashMap::new(),
+        }
+    }
+    
+    /// The ticket key, to persist in secure storage
+    pub fn key(&self) -> &[u8; KEY_LEN] {
+        &self.key
+    }
+    
+    /// Set how long newly issued tickets stay redeemable
+    pub fn set_lifetime(&mut self, lifetime: Duration) {
+        self.lifetime = lifetime;
+    }
+    
+    /// Set how many unexpired resumptions are remembered for replay checks
+    ///
+    /// Once that many are remembered, further resumptions are refused until
+    /// older tickets expire.
+    pub fn set_replay_capacity(&mut self, capacity: usize) {
+        self.replay_capacity = capacity;
+    }
+    
+    /// Issue a ticket for the peer of an established session
+    ///
+    /// The result holds the resumption secret: send it to the peer as a
+    /// transport message on `session`, never in the clear.
+    pub fn issue(&self, session: &NoiseSession) -> Result<Vec<u8>> {
+        if !session.is_transport_state() {
+            return Err(NoiseError::InvalidState("Tickets need an established session".to_string()));
+        }
+        let mut secret = Zeroizing::new([0u8; SECRET_LEN]);
+        random_bytes(&mut secret[..]);
+        let remote_static = session.get_remote_static();
+        
+        let expiry = unix_time().saturating_add(self.lifetime.as_secs());
+        let mut plaintext = Zeroizing::new(vec![TICKET_VERSION]);
+        plaintext.extend_from_slice(&expiry.to_be_bytes());
+        plaintext.extend_from_slice(&secret[..]);
+        put_remote_static(&mut plaintext, remote_static)?;
+        let blob = aead_seal(&self.key[..], TICKET_LABEL, &plaintext)?;
+        
+        let ticket = SessionTicket {
+            secret: SecretBytes::new(&secret[..]),
+            blob,
+            remote_static: session.get_local_static().map(<[u8]>::to_vec),
+        };
+        Ok(ticket.to_bytes())
+    }
+    
+    /// Resume from an initiator's first message
+    ///
+    /// Returns the session, which must still write its reply, and the
+    /// initiator's payload. Fails with [`NoiseError::HandshakeFailed`] for
+    /// tickets this issuer did not make or that have expired, and with
+    /// [`NoiseError::ReplayDetected`] for a message already accepted or when
+    /// the replay cache is full; the initiator should then run a full
+    /// handshake.
+    pub fn accept(&mut self, message: &[u8]) -> Result<(NoiseSession, Vec<u8>)> {
+        let (blob, handshake) = split_resume_message(message)?;
+        let ephemeral: [u8; EPHEMERAL_LEN] = handshake
+            .get(..EPHEMERAL_LEN)
+            .and_then(|key| key.try_into().ok())
+            .ok_or(NoiseError::InvalidMessage)?;
+        let plaintext = Zeroizing::new(aead_open(&self.key[..], TICKET_LABEL, blob).map_err(|_| NoiseError::HandshakeFailed)?);
+        
+        let (&version, rest) = plaintext.split_first().ok_or(NoiseError::HandshakeFailed)?;
+        let (expiry, rest) = rest.split_first_chunk::<8>().ok_or(NoiseError::HandshakeFailed)?;
+        let (secret, rest) = rest.split_first_chunk::<SECRET_LEN>().ok_or(NoiseError::HandshakeFailed)?;
+        let (remote_static, rest) = take_remote_static(rest)?;
+        if version != TICKET_VERSION || !rest.is_empty() {
+            return Err(NoiseError::HandshakeFailed);
+        }
+        let (now, expiry) = (unix_time(), u64::from_be_bytes(*expiry));
+        if now >= expiry {
+            return Err(NoiseError::HandshakeFailed);
+        }
+        
+        self.accepted.retain(|_, expiry| *expiry > now);
+        let replay_key = (blob.to_vec(), ephemeral);
+        if self.accepted.contains_key(&replay_key) {
+            return Err(NoiseError::ReplayDetected);
+        }
+        let mut session = NoiseSession::with_resumption_psk(TICKET_PARAMS, secret, false, remote_static)?;
+        let payload = session.read_message(handshake)?;
+        // Only authenticated messages take up room in the cache
+        if self.accepted.len() >= self.replay_capacity {
+            return Err(NoiseError::ReplayDetected);
+        }
+        self.accepted.insert(replay_key, expiry);
+        Ok((session, payload))
+    }
+}
+
+/// A ticket held by the initiator
+///
+/// The resumption secret is wiped when the ticket is dropped. The encoded
+/// form holds the secret too and must be stored like a key.
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct SessionTicket {
+    secret: SecretBytes,
+    blob: Vec<u8>,
+    remote_static: Option<Vec<u8>>,
+}
+
+impl SessionTicket {
+    /// Decode a ticket received from [`TicketIssuer::issue`] or loaded from storage
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let (&version, rest) = data.split_first().ok_or(NoiseError::InvalidMessage)?;
+        let (secret, rest) = rest.split_first_chunk::<SECRET_LEN>().ok_or(NoiseError::InvalidMessage)?;
+        let (remote_static, rest) = take_remote_static(rest).map_err(|_| NoiseError::InvalidMessage)?;
+        if version != TICKET_VERSION || rest.is_empty() {
+            return Err(NoiseError::InvalidMessage);
+        }
+        Ok(Self {
+            secret: SecretBytes::new(secret),
+            blob: rest.to_vec(),
+            remote_static: remote_static.map(<[u8]>::to_vec),
+        })
+    }
+    
+    /// Encode for storage
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = vec![TICKET_VERSION];
+        data.extend_from_slice(&self.secret);
+        // Keys are always KEY_LEN here, so this cannot fail
+        let _ = put_remote_static(&mut data, self.remote_static.as_deref());
+        data.extend_from_slice(&self.blob);
+        data
+    }
+    
+    /// Static key of the responder that issued the ticket
+    pub fn remote_static(&self) -> Option<&[u8]> {
+        self.remote_static.as_deref()
+    }
+    
+    /// Start a resumed handshake, returning the session and its first message
+    ///
+    /// `payload` rides in the first message; see the module docs for its
+    /// weaker guarantees.
+    pub fn resume(&self, payload: &[u8]) -> Result<(NoiseSession, Vec<u8>)> {
+        let mut session =
+            NoiseSession::with_resumption_psk(TICKET_PARAMS, &self.secret, true, self.remote_static.as_deref())?;
+        let handshake = session.write_message(payload)?;
+        let blob_len = u16::try_from(self.blob.len()).map_err(|_| NoiseError::InvalidMessage)?;
+        let mut message = blob_len.to_be_bytes().to_vec();
+        message.extend_from_slice(&self.blob);
+        message.extend_from_slice(&handshake);
+        Ok((session, message))
+    }
+}
+
+impl std::fmt::Debug for SessionTicket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionTicket").field("remote_static", &self.remote_static).finish_non_exhaustive()
+    }
+}
+
+/// Split `blob_len: u16 | blob | handshake message`
+fn split_resume_message(message: &[u8]) -> Result<(&[u8], &[u8])> {
+    let (len, rest) = message.split_first_chunk::<2>().ok_or(NoiseError::InvalidMessage)?;
+    let len = u16::from_be_bytes(*len) as usize;
+    if rest.len() < len {
+        return Err(NoiseError::InvalidMessage);
+    }
+    Ok(rest.split_at(len))
+}
+
+/// Append `present: u8 | [key]`
+fn put_remote_static(data: &mut Vec<u8>, remote_static: Option<&[u8]>) -> Result<()> {
+    match remote_static {
+        Some(key) if key.len() == KEY_LEN => {
+            data.push(1);
+            data.extend_from_slice(key);
+        }
+        Some(_) => return Err(NoiseError::InvalidParameter),
+        None => data.push(0),
+    }
+    Ok(())
+}
+
+fn take_remote_static(data: &[u8]) -> Result<(Option<&[u8]>, &[u8])> {
+    match data.split_first() {
+        Some((0, rest)) => Ok((None, rest)),
+        Some((1, rest)) if rest.len() >= KEY_LEN => {
+            let (key, rest) = rest.split_at(KEY_LEN);
+            Ok((Some(key), rest))
+        }
+        _ => Err(NoiseError::HandshakeFailed),
+    }
+}
+
+/// Seconds since the Unix epoch
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::perform_handshake;
+    
+    #[test]
+    fn test_resume_with_ticket() {
+        let (mut initiator, mut responder) = perform_handshake().unwrap();
+        let mut issuer = TicketIssuer::new();
+        
+        // The ticket travels over the established session
+        let sealed = responder.encrypt(&issuer.issue(&responder).unwrap()).unwrap();
+        let ticket = SessionTicket::from_bytes(&initiator.decrypt(&sealed).unwrap()).unwrap();
+        assert_eq!(ticket.remote_static(), responder.get_local_static());
+        let ticket = SessionTicket::from_bytes(&ticket.to_bytes()).unwrap();
+        
+        let (mut client, message) = ticket.resume(b"resume").unwrap();
+        let (mut server, payload) = issuer.accept(&message).unwrap();
+        assert_eq!(payload, b"resume");
+        let reply = server.write_message(&[]).unwrap();
+        client.read_message(&reply).unwrap();
+        
+        assert!(client.is_transport_state() && server.is_transport_state());
+        assert_eq!(server.get_remote_static(), initiator.get_local_static());
+        assert_eq!(client.get_remote_static(), responder.get_local_static());
+        let ciphertext = client.encrypt(b"fast").unwrap();
+        assert_eq!(server.decrypt(&ciphertext).unwrap(), b"fast");
+        assert!(!format!("{:?}", ticket).contains("secret"));
+        
+        // A captured first message cannot be replayed, but the ticket can be
+        // retried with a fresh ephemeral key
+        assert!(matches!(issuer.accept(&message), Err(NoiseError::ReplayDetected)));
+        let (_, retry) = ticket.resume(b"resume").unwrap();
+        assert!(issuer.accept(&retry).is_ok());
+        
+        issuer.set_replay_capacity(2);
+        let (_, third) = ticket.resume(&[]).unwrap();
+        assert!(matches!(issuer.accept(&third), Err(NoiseError::ReplayDetected)));
+    }
+    
+    #[test]
+    fn test_rejects_foreign_and_expired_tickets() {
+        let (_, responder) = perform_handshake().unwrap();
+        let mut issuer = TicketIssuer::new();
+        let ticket = SessionTicket::from_bytes(&issuer.issue(&responder).unwrap()).unwrap();
+        let (_, message) = ticket.resume(&[]).unwrap();
+        
+        assert!(matches!(TicketIssuer::new().accept(&message), Err(NoiseError::HandshakeFailed)));
+        let mut restored = TicketIssuer::from_key(issuer.key()).unwrap();
+        assert!(restored.accept(&message).is_ok());
+        
+        let mut expired = TicketIssuer::from_key(issuer.key()).unwrap();
+        expired.set_lifetime(Duration::ZERO);
+        let ticket = SessionTicket::from_bytes(&expired.issue(&responder).unwrap()).unwrap();
+        let (_, message) = ticket.resume(&[]).unwrap();
+        assert!(expired.accept(&message).is_err());
+        
+        assert!(issuer.accept(&[0, 200, 1]).is_err());
+        assert!(issuer.issue(&NoiseSession::new_initiator().unwrap()).is_err());
+    }
+}
@@ -0,0 +1,247 @@
+//! Out-of-band session verification
+//!
+//! [`SafetyNumber`] is a Signal-style 60-digit number derived from both
+//! parties' static keys; it stays the same across sessions until a key
+//! changes. [`EmojiSas`] is a short authentication string derived from one
+//! session's handshake hash, for comparing a single session in person.
+
+use crate::core::crypto::constant_time_eq;
+use crate::core::error::{NoiseError, Result};
+use snow::params::HashChoice;
+use snow::resolvers::{CryptoResolver, DefaultResolver};
+use snow::types::Hash;
+use std::fmt;
+
+/// Safety number format version, mixed into every fingerprint
+const SAFETY_NUMBER_VERSION: u16 = 0;
+
+/// Hash iterations per fingerprint, as in Signal
+const SAFETY_NUMBER_ITERATIONS: usize = 5200;
+
+/// Digits contributed by each party
+const FINGERPRINT_DIGITS: usize = 30;
+
+/// Digits per display group
+const GROUP_DIGITS: usize = 5;
+
+/// Domain separator for emoji SAS derivation
+const SAS_LABEL: &[u8] = b"noise-mobile/sas/v1";
+
+/// Number of emoji in an [`EmojiSas`]
+pub const SAS_EMOJI_COUNT: usize = 7;
+
+/// The 64 SAS emoji with their English names, indexed by 6-bit values
+///
+/// Same table as Matrix's SAS verification, so the symbols are easy to
+/// tell apart and name aloud.
+const SAS_EMOJI: [(&str, &str); 64] = [
+    ("🐶", "Dog"), ("🐱", "Cat"), ("🦁", "Lion"), ("🐎", "Horse"),
+    ("🦄", "Unicorn"), ("🐷", "Pig"), ("🐘", "Elephant"), ("🐰", "Rabbit"),
+    ("🐼", "Panda"), ("🐓", "Rooster"), ("🐧", "Penguin"), ("🐢", "Turtle"),
+    ("🐟", "Fish"), ("🐙", "Octopus"), ("🦋", "Butterfly"), ("🌷", "Flower"),
+    ("🌳", "Tree"), ("🌵", "Cactus"), ("🍄", "Mushroom"), ("🌏", "Globe"),
+    ("🌙", "Moon"), ("☁️", "Cloud"), ("🔥", "Fire"), ("🍌", "Banana"),
+    ("🍎", "Apple"), ("🍓", "Strawberry"), ("🌽", "Corn"), ("🍕", "Pizza"),
+    ("🎂", "Cake"), ("❤️", "Heart"), ("😀", "Smiley"), ("🤖", "Robot"),
+    ("🎩", "Hat"), ("👓", "Glasses"), ("🔧", "Spanner"), ("🎅", "Santa"),
+    ("👍", "Thumbs Up"), ("☂️", "Umbrella"), ("⌛", "Hourglass"), ("⏰", "Clock"),
+    ("🎁", "Gift"), ("💡", "Light Bulb"), ("📕", "Book"), ("✏️", "Pencil"),
+    ("📎", "Paperclip"), ("✂️", "Scissors"), ("🔒", "Lock"), ("🔑", "Key"),
+    ("🔨", "Hammer"), ("☎️", "Telephone"), ("🏁", "Flag"), ("🚂", "Train"),
+    ("🚲", "Bicycle"), ("✈️", "Aeroplane"), ("🚀", "Rocket"), ("🏆", "Trophy"),
+    ("⚽", "Ball"), ("🎸", "Guitar"), ("🎺", "Trumpet"), ("🔔", "Bell"),
+    ("⚓", "Anchor"), ("🎧", "Headphones"), ("📁", "Folder"), ("📌", "Pin"),
+];
+
+/// A 60-digit safety number for a pair of static keys
+///
+/// Both sides compute the same number: the two halves are ordered by value,
+/// not by which side is local.
+#[derive(Debug, Clone)]
+pub struct SafetyNumber {
+    digits: String,
+}
+
+impl SafetyNumber {
+    /// Compute the safety number for two parties
+    ///
+    /// Each identifier is a stable name for its party (e.g. a user ID) that
+    /// both sides agree on; pass an empty slice if there is none.
+    pub fn new(local_id: &[u8], local_static: &[u8], remote_id: &[u8], remote_static: &[u8]) -> Result<Self> {
+        if local_static.is_empty() || remote_static.is_empty() {
+            return Err(NoiseError::InvalidParameter);
+        }
+        let local = fingerprint_digits(local_id, local_static)?;
+        let remote = fingerprint_digits(remote_id, remote_static)?;
+        let digits = if local <= remote { local + &remote } else { remote + &local };
+        Ok(Self { digits })
+    }
+    
+    /// The 60 digits without separators
+    pub fn digits(&self) -> &str {
+        &self.digits
+    }
+    
+    /// The digits in groups of five separated by spaces
+    pub fn grouped(&self) -> String {
+        self.groups().collect::<Vec<_>>().join(" ")
+    }
+    
+    /// The digits as three lines of four groups, as Signal displays them
+    pub fn lines(&self) -> String {
+        let groups: Vec<&str> = self.groups().collect();
+        groups.chunks(4).map(|line| line.join(" ")).collect::<Vec<_>>().join("\n")
+    }
+    
+    fn groups(&self) -> impl Iterator<Item = &str> {
+        // The digits are ASCII, so every group boundary is a char boundary
+        (0..self.digits.len()).step_by(GROUP_DIGITS).map(move |i| &self.digits[i..i + GROUP_DIGITS])
+    }
+}
+
+/// Compares in constant time, e.g. against a number scanned from the peer
+impl PartialEq for SafetyNumber {
+    fn eq(&self, other: &Self) -> bool {
+        constant_time_eq(self.digits.as_bytes(), other.digits.as_bytes())
+    }
+}
+
+impl Eq for SafetyNumber {}
+
+impl fmt::Display for SafetyNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.grouped())
+    }
+}
+
+/// Iterated hash of one party's key, rendered as 30 digits
+fn fingerprint_digits(id: &[u8], static_key: &[u8]) -> Result<String> {
+    let mut hash = sha512()?;
+    let mut digest = [0u8; 64];
+    
+    hash.input(&SAFETY_NUMBER_VERSION.to_be_bytes());
+    hash.input(static_key);
+    hash.input(id);
+    hash.result(&mut digest);
+    for _ in 1..SAFETY_NUMBER_ITERATIONS {
+        hash.reset();
+        hash.input(&digest);
+        hash.input(static_key);
+        hash.result(&mut digest);
+    }
+    
+    // Six 40-bit chunks, each reduced to five digits
+    Ok(digest[..FINGERPRINT_DIGITS]
+        .chunks(5)
+        .map(|chunk| {
+            let value = chunk.iter().fold(0u64, |acc, &b| (acc << 8) | b as u64);
+            format!("{:05}", value % 100_000)
+        })
+        .collect())
+}
+
+/// A short authentication string for one session
+///
+/// Derived from the handshake hash, so it differs for every session and
+/// only matches if no one sat in the middle of the handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EmojiSas {
+    indices: [u8; SAS_EMOJI_COUNT],
+}
+
+impl EmojiSas {
+    /// Derive the SAS from a completed session's handshake hash
+    pub fn from_handshake_hash(handshake_hash: &[u8]) -> Result<Self> {
+        if handshake_hash.is_empty() {
+            return Err(NoiseError::InvalidParameter);
+        }
+        let mut hash = sha512()?;
+        let mut digest = [0u8; 64];
+        hash.input(SAS_LABEL);
+        hash.input(handshake_hash);
+        hash.result(&mut digest);
+        
+        // 42 bits, six per emoji
+        let bits = digest[..6].iter().fold(0u64, |acc, &b| (acc << 8) | b as u64);
+        let mut indices = [0u8; SAS_EMOJI_COUNT];
+        for (i, index) in indices.iter_mut().enumerate() {
+            *index = ((bits >> (42 - 6 * i)) & 0x3f) as u8;
+        }
+        Ok(Self { indices })
+    }
+    
+    /// Positions of the emoji in the SAS table, each below 64
+    pub fn indices(&self) -> [u8; SAS_EMOJI_COUNT] {
+        self.indices
+    }
+    
+    /// The emoji symbols
+    pub fn emoji(&self) -> [&'static str; SAS_EMOJI_COUNT] {
+        self.indices.map(|i| SAS_EMOJI[i as usize].0)
+    }
+    
+    /// English names for the emoji, for reading them aloud
+    pub fn names(&self) -> [&'static str; SAS_EMOJI_COUNT] {
+        self.indices.map(|i| SAS_EMOJI[i as usize].1)
+    }
+}
+
+impl fmt::Display for EmojiSas {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.emoji().join(" "))
+    }
+}
+
+fn sha512() -> Result<Box<dyn Hash>> {
+    DefaultResolver
+        .resolve_hash(&HashChoice::SHA512)
+        .ok_or_else(|| NoiseError::InvalidState("SHA-512 unavailable".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::session::NoiseSession;
+    
+    #[test]
+    fn test_safety_number_is_symmetric() {
+        let alice = SafetyNumber::new(b"alice", &[1u8; 32], b"bob", &[2u8; 32]).unwrap();
+        let bob = SafetyNumber::new(b"bob", &[2u8; 32], b"alice", &[1u8; 32]).unwrap();
+        assert_eq!(alice, bob);
+        assert_eq!(alice.digits().len(), 60);
+        assert!(alice.digits().bytes().all(|b| b.is_ascii_digit()));
+        
+        let grouped = alice.grouped();
+        assert_eq!(grouped.split(' ').count(), 12);
+        assert_eq!(alice.to_string(), grouped);
+        assert_eq!(alice.lines().lines().count(), 3);
+        
+        let changed = SafetyNumber::new(b"alice", &[1u8; 32], b"bob", &[3u8; 32]).unwrap();
+        assert_ne!(alice, changed);
+        assert!(SafetyNumber::new(b"alice", &[], b"bob", &[2u8; 32]).is_err());
+    }
+    
+    #[test]
+    fn test_emoji_sas_matches_across_session() {
+        let mut initiator = NoiseSession::new_initiator().unwrap();
+        let mut responder = NoiseSession::new_responder().unwrap();
+        assert!(initiator.get_handshake_hash().is_none());
+        
+        let msg1 = initiator.write_message(&[]).unwrap();
+        responder.read_message(&msg1).unwrap();
+        let msg2 = responder.write_message(&[]).unwrap();
+        initiator.read_message(&msg2).unwrap();
+        let msg3 = initiator.write_message(&[]).unwrap();
+        responder.read_message(&msg3).unwrap();
+        
+        let a = EmojiSas::from_handshake_hash(initiator.get_handshake_hash().unwrap()).unwrap();
+        let b = EmojiSas::from_handshake_hash(responder.get_handshake_hash().unwrap()).unwrap();
+        assert_eq!(a, b);
+        assert!(a.indices().iter().all(|&i| i < 64));
+        assert_eq!(a.to_string().split(' ').count(), SAS_EMOJI_COUNT);
+        assert_eq!(a.names()[0], SAS_EMOJI[a.indices()[0] as usize].1);
+        
+        assert_ne!(a, EmojiSas::from_handshake_hash(&[0u8; 64]).unwrap());
+        assert!(EmojiSas::from_handshake_hash(&[]).is_err());
+    }
+}
@@ -1,10 +1,36 @@
 //! C-compatible API for the noise-mobile-rust library
 
-use crate::core::session::NoiseSession;
-use crate::ffi::types::{NoiseErrorCode, NoiseSessionFFI};
-use libc::{c_char, c_int, c_uchar, size_t};
+#[cfg(feature = "file-cipher")]
+use crate::core::file_cipher::SessionFileCipher;
+use crate::core::keys::{FingerprintFormat, SecretKey, KEY_LEN};
+#[cfg(feature = "sealed")]
+use crate::core::keys::Keypair;
+use crate::core::logging::LogLevel;
+use crate::core::pipes::PipeSession;
+use crate::core::secret::SecretBytes;
+#[cfg(feature = "sealed")]
+use crate::core::sealed::SealedMessage;
+use crate::core::session::{NoiseSession, NoiseSessionBuilder, RecvHalf, SendHalf};
+use crate::core::verification::{EmojiSas, SafetyNumber};
+use crate::ffi::callbacks::{self, SessionMarks};
+use crate::ffi::handles::HandleTable;
+#[cfg(feature = "manager")]
+use crate::ffi::handles::NOISE_INVALID_HANDLE;
+use crate::ffi::key_storage::{install_key_storage, installed_key_storage, CallbackKeyStorage};
+use crate::ffi::types::{NoiseBuffer, NoiseConfigFFI, NoiseErrorCode, NoiseKeyStorageVTable, NoiseLogCallback, NoiseMode, NoisePattern, NoisePayloadVerifier, NoisePipeFFI, NoiseRecvHalfFFI, NoiseSendHalfFFI, NoiseSessionCallbacks, NoiseSessionFFI, NoiseSessionStats};
+#[cfg(feature = "batching")]
+use crate::ffi::types::{NoiseBatchFFI, NoiseBatchFlushCallback, NoiseBatchPriority, NoisePowerMode};
+#[cfg(feature = "batching")]
+use crate::mobile::battery::{BatchFlusher, BatchOperation, BatchPriority, BatchResult, BatchedCrypto, PowerMode};
+#[cfg(feature = "manager")]
+use crate::mobile::manager::SessionManager;
+#[cfg(feature = "identity")]
+use crate::mobile::pairing::PairingPayload;
+use libc::{c_char, c_int, c_uchar, c_void, size_t};
+use std::ffi::CString;
 use std::ptr;
 use std::slice;
+use zeroize::Zeroize;
 
 // Constants for C API
 pub const NOISE_MODE_INITIATOR: c_int = 0;
@@ -19,6 +45,157 @@ pub const NOISE_ERROR_DECRYPTION_FAILED: c_int = 5;
 pub const NOISE_ERROR_BUFFER_TOO_SMALL: c_int = 6;
 pub const NOISE_ERROR_INVALID_STATE: c_int = 7;
 pub const NOISE_ERROR_PROTOCOL_ERROR: c_int = 8;
+pub const NOISE_ERROR_NONCE_EXHAUSTED: c_int = 9;
+pub const NOISE_ERROR_INTERNAL: c_int = 10;
+pub const NOISE_ERROR_PEER_KEY_MISMATCH: c_int = 11;
+pub const NOISE_ERROR_INVALID_KEY: c_int = 12;
+
+pub const NOISE_FINGERPRINT_HEX: c_int = 0;
+pub const NOISE_FINGERPRINT_BASE32: c_int = 1;
+pub const NOISE_FINGERPRINT_SHA256: c_int = 2;
+
+pub use crate::ffi::callbacks::{NOISE_REKEY_INCOMING, NOISE_REKEY_OUTGOING};
+pub use crate::ffi::key_storage::{NOISE_STORAGE_IDENTITY, NOISE_STORAGE_SESSION};
+
+pub const NOISE_LOG_OFF: c_int = 0;
+pub const NOISE_LOG_ERROR: c_int = 1;
+pub const NOISE_LOG_WARN: c_int = 2;
+pub const NOISE_LOG_INFO: c_int = 3;
+pub const NOISE_LOG_DEBUG: c_int = 4;
+pub const NOISE_LOG_TRACE: c_int = 5;
+
+pub const NOISE_BATCH_ENCRYPT: c_int = 0;
+pub const NOISE_BATCH_DECRYPT: c_int = 1;
+
+/// Major version of the C ABI, bumped when a function changes or goes away
+pub const NOISE_FFI_VERSION_MAJOR: u32 = 1;
+/// Minor version of the C ABI, bumped when functions are added
+pub const NOISE_FFI_VERSION_MINOR: u32 = 1;
+
+/// Live sessions, keyed by the handle value handed out as `NoiseSessionFFI*`
+static SESSIONS: HandleTable<NoiseSession> = HandleTable::new();
+
+/// Halves of split sessions, keyed like `SESSIONS`
+static SEND_HALVES: HandleTable<SendHalf> = HandleTable::new();
+static RECV_HALVES: HandleTable<RecvHalf> = HandleTable::new();
+
+/// Noise Pipes handshakes, keyed like `SESSIONS`
+static PIPES: HandleTable<PipeSession> = HandleTable::new();
+
+/// Session configurations, keyed like `SESSIONS`
+static CONFIGS: HandleTable<SessionConfig> = HandleTable::new();
+
+/// Batches, keyed like `SESSIONS`
+#[cfg(feature = "batching")]
+static BATCHES: HandleTable<BatchHandle> = HandleTable::new();
+
+/// Power mode for new batches, set with `noise_set_power_mode`
+#[cfg(feature = "batching")]
+static POWER_MODE: std::sync::Mutex<PowerMode> = std::sync::Mutex::new(PowerMode::Balanced);
+
+/// Session managers, keyed by `u64` handle
+#[cfg(feature = "manager")]
+static MANAGERS: HandleTable<SessionManager> = HandleTable::new();
+
+/// Register a session and return its handle
+fn session_to_ffi(session: NoiseSession) -> *mut NoiseSessionFFI {
+    SESSIONS.insert_ptr(session)
+}
+
+/// `NOISE_ERROR_OUT_OF_MEMORY` for a null handle, meaning the table ran
+/// out of handles, and success otherwise
+fn handle_status<P>(handle: *mut P) -> c_int {
+    if handle.is_null() {
+        NoiseErrorCode::OutOfMemory as c_int
+    } else {
+        NoiseErrorCode::Success as c_int
+    }
+}
+
+/// Run `f` on the session behind a handle
+///
+/// Null, freed and never-issued handles return `NOISE_ERROR_INVALID_PARAMETER`.
+fn with_session(session: *mut NoiseSessionFFI, f: impl FnOnce(&mut NoiseSession) -> c_int) -> c_int {
+    SESSIONS.with(session as u64, f).unwrap_or(NoiseErrorCode::InvalidParameter as c_int)
+}
+
+/// Run `f` on the session behind a handle, then raise the events it caused
+/// with the session's callbacks
+fn with_session_events(session: *mut NoiseSessionFFI, f: impl FnOnce(&mut NoiseSession) -> c_int) -> c_int {
+    let mut marks = None;
+    let status = with_session(session, |inner| {
+        let before = SessionMarks::of(inner);
+        let status = f(inner);
+        marks = Some((before, SessionMarks::of(inner)));
+        status
+    });
+    if let Some((before, after)) = marks {
+        callbacks::dispatch(session as u64, before, after, status);
+    }
+    status
+}
+
+/// Copy the result of a handle call into a caller buffer
+fn write_output(result: Option<crate::core::error::Result<Vec<u8>>>, output: *mut c_uchar, output_len: *mut size_t) -> c_int {
+    match result {
+        None => NoiseErrorCode::InvalidParameter as c_int,
+        Some(Err(e)) => crate::ffi::helpers::report_error(e),
+        Some(Ok(data)) => {
+            if unsafe { crate::ffi::helpers::copy_to_c_buffer(&data, output, output_len) } {
+                NoiseErrorCode::Success as c_int
+            } else {
+                NoiseErrorCode::BufferTooSmall as c_int
+            }
+        }
+    }
+}
+
+/// Report the outcome of a call that wrote straight into a caller buffer
+///
+/// On success or `BufferTooSmall`, `*output_len` is set to the length
+/// written or needed.
+unsafe fn write_output_len(result: crate::core::error::Result<usize>, output_len: *mut size_t) -> c_int {
+    match result {
+        Ok(len) => {
+            *output_len = len;
+            NoiseErrorCode::Success as c_int
+        }
+        Err(crate::core::error::NoiseError::BufferTooSmall { needed, .. }) => {
+            *output_len = needed;
+            NoiseErrorCode::BufferTooSmall as c_int
+        }
+        Err(e) => crate::ffi::helpers::report_error(e),
+    }
+}
+
+/// Hand the result of a handle call to the caller in a library-owned buffer
+///
+/// `*output` is left empty on failure.
+unsafe fn write_buffer(result: Option<crate::core::error::Result<Vec<u8>>>, output: *mut NoiseBuffer) -> c_int {
+    match result {
+        None => NoiseErrorCode::InvalidParameter as c_int,
+        Some(Err(e)) => crate::ffi::helpers::report_error(e),
+        Some(Ok(data)) => {
+            *output = NoiseBuffer::from_vec(data);
+            NoiseErrorCode::Success as c_int
+        }
+    }
+}
+
+/// Validate an output buffer and empty it, then run an `_alloc` call
+///
+/// The output is write-only: C callers commonly pass an uninitialized
+/// `struct NoiseBuffer`, so whatever it holds is overwritten without being
+/// read. A library buffer left in it leaks rather than being freed.
+fn alloc_call(output: *mut NoiseBuffer, f: impl FnOnce(*mut NoiseBuffer) -> c_int) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        if output.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        unsafe { output.write(NoiseBuffer::new()) };
+        f(output)
+    })
+}
 
 /// Create a new Noise session
 #[no_mangle]
@@ -26,31 +203,284 @@ pub extern "C" fn noise_session_new(
     mode: c_int,
     error: *mut c_int,
 ) -> *mut NoiseSessionFFI {
-    if error.is_null() {
-        return ptr::null_mut();
-    }
-    
-    let session = match mode {
-        0 => NoiseSession::new_initiator(),
-        1 => NoiseSession::new_responder(),
-        _ => {
-            unsafe { *error = NoiseErrorCode::InvalidParameter as c_int; }
+    crate::ffi::helpers::catch_panic_with_error(error, ptr::null_mut(), || {
+        if error.is_null() {
             return ptr::null_mut();
         }
-    };
-    
-    match session {
-        Ok(s) => {
-            unsafe { *error = NoiseErrorCode::Success as c_int; }
-            Box::into_raw(Box::new(s)) as *mut NoiseSessionFFI
+        
+        let session = match NoiseMode::from_c_int(mode) {
+            Some(NoiseMode::Initiator) => NoiseSession::new_initiator(),
+            Some(NoiseMode::Responder) => NoiseSession::new_responder(),
+            None => {
+                unsafe { *error = NoiseErrorCode::InvalidParameter as c_int; }
+                return ptr::null_mut();
+            }
+        };
+        
+        match session {
+            Ok(s) => {
+                let handle = session_to_ffi(s);
+                unsafe { *error = handle_status(handle); }
+                handle
+            }
+            Err(e) => {
+                unsafe { *error = crate::ffi::helpers::report_error(e); }
+                ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Create a new Noise session for `pattern`
+///
+/// `mode` is a `NOISE_MODE_*` value and `pattern` a `NOISE_PATTERN_*` one;
+/// anything else fails with `NOISE_ERROR_INVALID_PARAMETER`.
+/// `private_key` is 32 bytes, or NULL to
+/// generate one. `remote_static` is the responder's 32-byte static key,
+/// required for `NOISE_PATTERN_IK` initiators and NULL otherwise.
+#[no_mangle]
+pub extern "C" fn noise_session_new_with_pattern(
+    mode: c_int,
+    pattern: c_int,
+    private_key: *const c_uchar,
+    private_key_len: size_t,
+    remote_static: *const c_uchar,
+    remote_static_len: size_t,
+    error: *mut c_int,
+) -> *mut NoiseSessionFFI {
+    crate::ffi::helpers::catch_panic_with_error(error, ptr::null_mut(), || {
+        let (Some(mode), Some(pattern)) = (NoiseMode::from_c_int(mode), NoisePattern::from_c_int(pattern)) else {
+            if !error.is_null() {
+                unsafe { *error = NoiseErrorCode::InvalidParameter as c_int; }
+            }
+            return ptr::null_mut();
+        };
+        let needs_remote = pattern == NoisePattern::Ik && mode.is_initiator();
+        if error.is_null() || needs_remote == remote_static.is_null() {
+            if !error.is_null() {
+                unsafe { *error = NoiseErrorCode::InvalidParameter as c_int; }
+            }
+            return ptr::null_mut();
+        }
+        
+        let private_key = if private_key.is_null() {
+            Ok(SecretKey::generate())
+        } else {
+            SecretKey::from_bytes(unsafe { slice::from_raw_parts(private_key, private_key_len) })
+        };
+        let remote_static = unsafe { crate::ffi::helpers::c_to_slice(remote_static, remote_static_len) };
+        let session = private_key.and_then(|key| {
+            NoiseSession::with_protocol(pattern.protocol_name(), key.as_bytes(), remote_static, mode.is_initiator(), &[])
+        });
+        
+        match session {
+            Ok(s) => {
+                let handle = session_to_ffi(s);
+                unsafe { *error = handle_status(handle); }
+                handle
+            }
+            Err(e) => {
+                unsafe { *error = crate::ffi::helpers::report_error(e); }
+                ptr::null_mut()
+            }
         }
-        Err(e) => {
-            unsafe { *error = NoiseErrorCode::from(e) as c_int; }
-            ptr::null_mut()
+    })
+}
+
+/// Settings collected by the `noise_config_*` functions
+struct SessionConfig {
+    pattern: NoisePattern,
+    private_key: Option<SecretKey>,
+    remote_static: Option<Vec<u8>>,
+    psk: Option<SecretBytes>,
+    prologue: Vec<u8>,
+}
+
+impl SessionConfig {
+    fn builder(&self) -> NoiseSessionBuilder {
+        let mut builder = NoiseSessionBuilder::new()
+            .protocol(self.pattern.protocol_name())
+            .channel_binding(&self.prologue);
+        if let Some(psk) = &self.psk {
+            let (protocol, location) = self.pattern.psk_protocol();
+            builder = builder.protocol(protocol).psk(location, psk);
         }
+        if let Some(private_key) = &self.private_key {
+            builder = builder.private_key(private_key);
+        }
+        if let Some(remote_static) = &self.remote_static {
+            builder = builder.remote_static(remote_static);
+        }
+        builder
+    }
+}
+
+/// Apply `f` to the configuration behind `config`
+fn update_config(config: *mut NoiseConfigFFI, f: impl FnOnce(&mut SessionConfig)) -> c_int {
+    match CONFIGS.with(config as u64, f) {
+        Some(()) => NoiseErrorCode::Success as c_int,
+        None => NoiseErrorCode::InvalidParameter as c_int,
     }
 }
 
+/// Create a session configuration
+///
+/// Starts as an XX handshake with a generated static key, no PSK and an
+/// empty prologue. Adjust it with the `noise_config_set_*` functions, create
+/// any number of sessions with `noise_session_from_config` and release it
+/// with `noise_config_free`.
+#[no_mangle]
+pub extern "C" fn noise_config_new() -> *mut NoiseConfigFFI {
+    crate::ffi::helpers::catch_panic(ptr::null_mut(), || {
+        let config = SessionConfig {
+            pattern: NoisePattern::Xx,
+            private_key: None,
+            remote_static: None,
+            psk: None,
+            prologue: Vec::new(),
+        };
+        CONFIGS.insert_ptr(config)
+    })
+}
+
+/// Free a session configuration
+///
+/// Sessions already created from it are unaffected.
+#[no_mangle]
+pub extern "C" fn noise_config_free(config: *mut NoiseConfigFFI) {
+    crate::ffi::helpers::catch_panic((), || {
+        CONFIGS.remove(config as u64);
+    })
+}
+
+/// Set the handshake pattern to a `NOISE_PATTERN_*` value
+#[no_mangle]
+pub extern "C" fn noise_config_set_pattern(config: *mut NoiseConfigFFI, pattern: c_int) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        let Some(pattern) = NoisePattern::from_c_int(pattern) else {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        };
+        update_config(config, |config| config.pattern = pattern)
+    })
+}
+
+/// Set the 32-byte static private key, or generate one per session with NULL
+#[no_mangle]
+pub extern "C" fn noise_config_set_local_key(
+    config: *mut NoiseConfigFFI,
+    private_key: *const c_uchar,
+    private_key_len: size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        let private_key = if private_key.is_null() {
+            None
+        } else {
+            match SecretKey::from_bytes(unsafe { slice::from_raw_parts(private_key, private_key_len) }) {
+                Ok(private_key) => Some(private_key),
+                Err(e) => return crate::ffi::helpers::report_error(e),
+            }
+        };
+        update_config(config, |config| config.private_key = private_key)
+    })
+}
+
+/// Set the peer's 32-byte static key, or clear it with NULL
+///
+/// Required for `NOISE_PATTERN_IK` initiators. With other patterns the key
+/// is pinned: the handshake fails with `NOISE_ERROR_PEER_KEY_MISMATCH` if
+/// the peer presents another one.
+#[no_mangle]
+pub extern "C" fn noise_config_set_remote_key(
+    config: *mut NoiseConfigFFI,
+    remote_static: *const c_uchar,
+    remote_static_len: size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        let remote_static = if remote_static.is_null() {
+            None
+        } else if remote_static_len != KEY_LEN {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        } else {
+            Some(unsafe { slice::from_raw_parts(remote_static, remote_static_len) }.to_vec())
+        };
+        update_config(config, |config| config.remote_static = remote_static)
+    })
+}
+
+/// Set a 32-byte pre-shared key, or clear it with NULL
+///
+/// Switches the pattern to its PSK variant, `XXpsk3` or `IKpsk2`. Both peers
+/// must set the same PSK.
+#[no_mangle]
+pub extern "C" fn noise_config_set_psk(config: *mut NoiseConfigFFI, psk: *const c_uchar, psk_len: size_t) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        let psk = if psk.is_null() {
+            None
+        } else if psk_len != KEY_LEN {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        } else {
+            Some(SecretBytes::new(unsafe { slice::from_raw_parts(psk, psk_len) }))
+        };
+        update_config(config, |config| config.psk = psk)
+    })
+}
+
+/// Set the handshake prologue, e.g. a channel binding; NULL clears it
+///
+/// Both peers must set the same prologue or the handshake fails.
+#[no_mangle]
+pub extern "C" fn noise_config_set_prologue(
+    config: *mut NoiseConfigFFI,
+    prologue: *const c_uchar,
+    prologue_len: size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        let prologue = unsafe { crate::ffi::helpers::c_to_slice(prologue, prologue_len) }.unwrap_or(&[]).to_vec();
+        update_config(config, |config| config.prologue = prologue)
+    })
+}
+
+/// Create a new Noise session from a configuration
+///
+/// `mode` is a `NOISE_MODE_*` value.
+#[no_mangle]
+pub extern "C" fn noise_session_from_config(
+    config: *mut NoiseConfigFFI,
+    mode: c_int,
+    error: *mut c_int,
+) -> *mut NoiseSessionFFI {
+    crate::ffi::helpers::catch_panic_with_error(error, ptr::null_mut(), || {
+        if error.is_null() {
+            return ptr::null_mut();
+        }
+        let Some(mode) = NoiseMode::from_c_int(mode) else {
+            unsafe { *error = NoiseErrorCode::InvalidParameter as c_int; }
+            return ptr::null_mut();
+        };
+        let Some(builder) = CONFIGS.with(config as u64, |config| config.builder()) else {
+            unsafe { *error = NoiseErrorCode::InvalidParameter as c_int; }
+            return ptr::null_mut();
+        };
+        let session = if mode.is_initiator() {
+            builder.build_initiator()
+        } else {
+            builder.build_responder()
+        };
+        
+        match session {
+            Ok(s) => {
+                let handle = session_to_ffi(s);
+                unsafe { *error = handle_status(handle); }
+                handle
+            }
+            Err(e) => {
+                unsafe { *error = crate::ffi::helpers::report_error(e); }
+                ptr::null_mut()
+            }
+        }
+    })
+}
+
 /// Create a new Noise session with a specific private key
 #[no_mangle]
 pub extern "C" fn noise_session_new_with_key(
@@ -59,44 +489,233 @@ pub extern "C" fn noise_session_new_with_key(
     mode: c_int,
     error: *mut c_int,
 ) -> *mut NoiseSessionFFI {
-    if error.is_null() || private_key.is_null() || private_key_len != 32 {
-        if !error.is_null() {
+    crate::ffi::helpers::catch_panic_with_error(error, ptr::null_mut(), || {
+        if error.is_null() || private_key.is_null() || private_key_len != 32 {
+            if !error.is_null() {
+                unsafe { *error = NoiseErrorCode::InvalidParameter as c_int; }
+            }
+            return ptr::null_mut();
+        }
+        
+        let private_key_slice = unsafe { slice::from_raw_parts(private_key, private_key_len) };
+        
+        let Some(mode) = NoiseMode::from_c_int(mode) else {
             unsafe { *error = NoiseErrorCode::InvalidParameter as c_int; }
+            return ptr::null_mut();
+        };
+        
+        let session = SecretKey::from_bytes(private_key_slice)
+            .and_then(|key| NoiseSession::with_private_key(&key, mode.is_initiator()));
+        match session {
+            Ok(s) => {
+                let handle = session_to_ffi(s);
+                unsafe { *error = handle_status(handle); }
+                handle
+            }
+            Err(e) => {
+                unsafe { *error = crate::ffi::helpers::report_error(e); }
+                ptr::null_mut()
+            }
         }
-        return ptr::null_mut();
-    }
-    
-    let private_key_slice = unsafe { slice::from_raw_parts(private_key, private_key_len) };
-    
-    let is_initiator = match mode {
-        0 => true,
-        1 => false,
-        _ => {
+    })
+}
+
+/// Create a new Noise session bound to transport identifiers via the prologue
+///
+/// Both peers must pass identical `binding` bytes (e.g. a hash of the sorted
+/// BLE addresses of the link) or the handshake fails.
+#[no_mangle]
+pub extern "C" fn noise_session_new_with_binding(
+    private_key: *const c_uchar,
+    private_key_len: size_t,
+    mode: c_int,
+    binding: *const c_uchar,
+    binding_len: size_t,
+    error: *mut c_int,
+) -> *mut NoiseSessionFFI {
+    crate::ffi::helpers::catch_panic_with_error(error, ptr::null_mut(), || {
+        if error.is_null() || private_key.is_null() || private_key_len != 32 || (binding.is_null() && binding_len > 0) {
+            if !error.is_null() {
+                unsafe { *error = NoiseErrorCode::InvalidParameter as c_int; }
+            }
+            return ptr::null_mut();
+        }
+        
+        let private_key_slice = unsafe { slice::from_raw_parts(private_key, private_key_len) };
+        let binding_slice = unsafe {
+            crate::ffi::helpers::c_to_slice(binding, binding_len).unwrap_or(&[])
+        };
+        
+        let Some(mode) = NoiseMode::from_c_int(mode) else {
             unsafe { *error = NoiseErrorCode::InvalidParameter as c_int; }
             return ptr::null_mut();
+        };
+        
+        let session = SecretKey::from_bytes(private_key_slice)
+            .and_then(|key| NoiseSession::with_channel_binding(&key, mode.is_initiator(), binding_slice));
+        match session {
+            Ok(s) => {
+                let handle = session_to_ffi(s);
+                unsafe { *error = handle_status(handle); }
+                handle
+            }
+            Err(e) => {
+                unsafe { *error = crate::ffi::helpers::report_error(e); }
+                ptr::null_mut()
+            }
         }
-    };
-    
-    match NoiseSession::with_private_key(private_key_slice, is_initiator) {
-        Ok(s) => {
-            unsafe { *error = NoiseErrorCode::Success as c_int; }
-            Box::into_raw(Box::new(s)) as *mut NoiseSessionFFI
+    })
+}
+
+/// Create a new Noise session that only completes with a known peer
+///
+/// The handshake fails with `NOISE_ERROR_PEER_KEY_MISMATCH` if the peer
+/// presents any static key other than the 32-byte `remote_static`.
+#[no_mangle]
+pub extern "C" fn noise_session_new_with_expected_remote(
+    private_key: *const c_uchar,
+    private_key_len: size_t,
+    mode: c_int,
+    remote_static: *const c_uchar,
+    remote_static_len: size_t,
+    error: *mut c_int,
+) -> *mut NoiseSessionFFI {
+    crate::ffi::helpers::catch_panic_with_error(error, ptr::null_mut(), || {
+        if error.is_null() || private_key.is_null() || private_key_len != KEY_LEN
+            || remote_static.is_null() || remote_static_len != KEY_LEN
+        {
+            if !error.is_null() {
+                unsafe { *error = NoiseErrorCode::InvalidParameter as c_int; }
+            }
+            return ptr::null_mut();
         }
-        Err(e) => {
-            unsafe { *error = NoiseErrorCode::from(e) as c_int; }
-            ptr::null_mut()
+        
+        let private_key = match SecretKey::from_bytes(unsafe { slice::from_raw_parts(private_key, private_key_len) }) {
+            Ok(key) => key,
+            Err(e) => {
+                unsafe { *error = crate::ffi::helpers::report_error(e); }
+                return ptr::null_mut();
+            }
+        };
+        let builder = NoiseSessionBuilder::new()
+            .private_key(&private_key)
+            .expect_remote_static(unsafe { slice::from_raw_parts(remote_static, remote_static_len) });
+        let session = match NoiseMode::from_c_int(mode) {
+            Some(NoiseMode::Initiator) => builder.build_initiator(),
+            Some(NoiseMode::Responder) => builder.build_responder(),
+            None => {
+                unsafe { *error = NoiseErrorCode::InvalidParameter as c_int; }
+                return ptr::null_mut();
+            }
+        };
+        
+        match session {
+            Ok(s) => {
+                let handle = session_to_ffi(s);
+                unsafe { *error = handle_status(handle); }
+                handle
+            }
+            Err(e) => {
+                unsafe { *error = crate::ffi::helpers::report_error(e); }
+                ptr::null_mut()
+            }
         }
-    }
+    })
+}
+
+/// Install platform callbacks as the library's key storage
+///
+/// The vtable is copied. Any previously installed storage has its `release`
+/// callback run once no call is using it. Pass NULL to uninstall. On failure
+/// nothing is installed and `release` is not called.
+#[no_mangle]
+pub extern "C" fn noise_set_key_storage(vtable: *const NoiseKeyStorageVTable) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        if vtable.is_null() {
+            install_key_storage(None);
+            return NoiseErrorCode::Success as c_int;
+        }
+        
+        match unsafe { CallbackKeyStorage::new(*vtable) } {
+            Ok(storage) => {
+                install_key_storage(Some(storage));
+                NoiseErrorCode::Success as c_int
+            }
+            Err(e) => crate::ffi::helpers::report_error(e),
+        }
+    })
+}
+
+/// Create a new Noise session with the identity key stored under `identity_id`
+///
+/// Loads the key through the storage set with `noise_set_key_storage`.
+#[no_mangle]
+pub extern "C" fn noise_session_new_with_identity(
+    identity_id: *const c_char,
+    mode: c_int,
+    error: *mut c_int,
+) -> *mut NoiseSessionFFI {
+    crate::ffi::helpers::catch_panic_with_error(error, ptr::null_mut(), || {
+        if error.is_null() {
+            return ptr::null_mut();
+        }
+        
+        let (Some(id), Some(mode)) = (unsafe { crate::ffi::helpers::c_to_str(identity_id) }, NoiseMode::from_c_int(mode)) else {
+            unsafe { *error = NoiseErrorCode::InvalidParameter as c_int; }
+            return ptr::null_mut();
+        };
+        
+        let session = installed_key_storage()
+            .ok_or_else(|| crate::core::error::NoiseError::InvalidState("No key storage installed".to_string()))
+            .and_then(|storage| storage.load_identity(id))
+            .and_then(|key| NoiseSession::with_private_key(&key, mode.is_initiator()));
+        
+        match session {
+            Ok(s) => {
+                let handle = session_to_ffi(s);
+                unsafe { *error = handle_status(handle); }
+                handle
+            }
+            Err(e) => {
+                unsafe { *error = crate::ffi::helpers::report_error(e); }
+                ptr::null_mut()
+            }
+        }
+    })
 }
 
 /// Free a Noise session
+///
+/// The handle is invalid afterwards; freeing it again is a no-op.
 #[no_mangle]
 pub extern "C" fn noise_session_free(session: *mut NoiseSessionFFI) {
-    if !session.is_null() {
-        unsafe {
-            let _ = Box::from_raw(session as *mut NoiseSession);
+    crate::ffi::helpers::catch_panic((), || {
+        if SESSIONS.remove(session as u64) {
+            callbacks::closed(session as u64);
         }
-    }
+    })
+}
+
+/// Set callbacks for the session's events, replacing any set before
+///
+/// Saves polling `noise_is_handshake_complete`: `handshake_complete` fires
+/// from the `noise_read_message` or `noise_write_message` call that
+/// finishes the handshake, `message_decrypted` from each successful
+/// `noise_decrypt`, `rekeyed` after either side of a rekey and `error`
+/// whenever a call on the session fails. `closed` fires once when the
+/// handle is freed or split. `context` must stay valid until then or until
+/// the callbacks are replaced; a null `callbacks` removes them.
+#[no_mangle]
+pub extern "C" fn noise_session_set_callbacks(
+    session: *mut NoiseSessionFFI,
+    callbacks: *const NoiseSessionCallbacks,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        with_session(session, |_| {
+            callbacks::set_callbacks(session as u64, unsafe { callbacks.as_ref() }.copied());
+            NoiseErrorCode::Success as c_int
+        })
+    })
 }
 
 /// Write a handshake message
@@ -108,25 +727,28 @@ pub extern "C" fn noise_write_message(
     output: *mut c_uchar,
     output_len: *mut size_t,
 ) -> c_int {
-    if !crate::ffi::helpers::validate_session_ptr(session) || output_len.is_null() {
-        return NoiseErrorCode::InvalidParameter as c_int;
-    }
-    
-    let session = unsafe { &mut *(session as *mut NoiseSession) };
-    let payload_slice = unsafe { 
-        crate::ffi::helpers::c_to_slice(payload, payload_len).unwrap_or(&[])
-    };
-    
-    match session.write_message(payload_slice) {
-        Ok(msg) => {
-            if unsafe { crate::ffi::helpers::copy_to_c_buffer(&msg, output, output_len) } {
-                NoiseErrorCode::Success as c_int
-            } else {
-                NoiseErrorCode::BufferTooSmall as c_int
-            }
+    crate::ffi::helpers::catch_status(|| {
+        if output_len.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
         }
-        Err(e) => NoiseErrorCode::from(e) as c_int,
-    }
+        
+        with_session_events(session, |session| {
+            let payload_slice = unsafe { 
+                crate::ffi::helpers::c_to_slice(payload, payload_len).unwrap_or(&[])
+            };
+            
+            match session.write_message(payload_slice) {
+                Ok(msg) => {
+                    if unsafe { crate::ffi::helpers::copy_to_c_buffer(&msg, output, output_len) } {
+                        NoiseErrorCode::Success as c_int
+                    } else {
+                        NoiseErrorCode::BufferTooSmall as c_int
+                    }
+                }
+                Err(e) => crate::ffi::helpers::report_error(e),
+            }
+        })
+    })
 }
 
 /// Read a handshake message
@@ -138,43 +760,62 @@ pub extern "C" fn noise_read_message(
     payload: *mut c_uchar,
     payload_len: *mut size_t,
 ) -> c_int {
-    if !crate::ffi::helpers::validate_session_ptr(session) || input.is_null() || payload_len.is_null() {
-        return NoiseErrorCode::InvalidParameter as c_int;
-    }
-    
-    let session = unsafe { &mut *(session as *mut NoiseSession) };
-    let input_slice = match unsafe { crate::ffi::helpers::c_to_slice(input, input_len) } {
-        Some(slice) => slice,
-        None => return NoiseErrorCode::InvalidParameter as c_int,
-    };
-    
-    match session.read_message(input_slice) {
-        Ok(msg) => {
-            if msg.is_empty() {
-                unsafe { *payload_len = 0; }
-                NoiseErrorCode::Success as c_int
-            } else if unsafe { crate::ffi::helpers::copy_to_c_buffer(&msg, payload, payload_len) } {
-                NoiseErrorCode::Success as c_int
-            } else {
-                NoiseErrorCode::BufferTooSmall as c_int
-            }
+    crate::ffi::helpers::catch_status(|| {
+        if input.is_null() || payload_len.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
         }
-        Err(e) => NoiseErrorCode::from(e) as c_int,
-    }
+        
+        with_session_events(session, |session| {
+            let input_slice = match unsafe { crate::ffi::helpers::c_to_slice(input, input_len) } {
+                Some(slice) => slice,
+                None => return NoiseErrorCode::InvalidParameter as c_int,
+            };
+            
+            match session.read_message(input_slice) {
+                Ok(msg) => {
+                    if msg.is_empty() {
+                        unsafe { *payload_len = 0; }
+                        NoiseErrorCode::Success as c_int
+                    } else if unsafe { crate::ffi::helpers::copy_to_c_buffer(&msg, payload, payload_len) } {
+                        NoiseErrorCode::Success as c_int
+                    } else {
+                        NoiseErrorCode::BufferTooSmall as c_int
+                    }
+                }
+                Err(e) => crate::ffi::helpers::report_error(e),
+            }
+        })
+    })
 }
 
 /// Check if handshake is complete
 #[no_mangle]
 pub extern "C" fn noise_is_handshake_complete(session: *mut NoiseSessionFFI) -> c_int {
-    if !crate::ffi::helpers::validate_session_ptr(session) {
-        return 0;
-    }
-    
-    let session = unsafe { &*(session as *mut NoiseSession) };
-    if session.is_transport_state() { 1 } else { 0 }
+    crate::ffi::helpers::catch_panic(0, || {
+        SESSIONS
+            .with(session as u64, |session| if session.is_transport_state() { 1 } else { 0 })
+            .unwrap_or(0)
+    })
+}
+
+/// Check whether this side should write the next message
+///
+/// Follows the handshake pattern until the handshake completes, then is
+/// always 1. Returns 0 for an invalid session.
+#[no_mangle]
+pub extern "C" fn noise_is_my_turn_to_send(session: *mut NoiseSessionFFI) -> c_int {
+    crate::ffi::helpers::catch_panic(0, || {
+        SESSIONS
+            .with(session as u64, |session| if session.is_my_turn_to_send() { 1 } else { 0 })
+            .unwrap_or(0)
+    })
 }
 
 /// Encrypt a message
+///
+/// Encrypts straight into `ciphertext`. If it is too small,
+/// `*ciphertext_len` is set to the length needed and no nonce is used, so
+/// the call can be retried with a larger buffer.
 #[no_mangle]
 pub extern "C" fn noise_encrypt(
     session: *mut NoiseSessionFFI,
@@ -183,29 +824,29 @@ pub extern "C" fn noise_encrypt(
     ciphertext: *mut c_uchar,
     ciphertext_len: *mut size_t,
 ) -> c_int {
-    if !crate::ffi::helpers::validate_session_ptr(session) || ciphertext_len.is_null() {
-        return NoiseErrorCode::InvalidParameter as c_int;
-    }
-    
-    let session = unsafe { &mut *(session as *mut NoiseSession) };
-    let plaintext_slice = match unsafe { crate::ffi::helpers::c_to_slice(plaintext, plaintext_len) } {
-        Some(slice) => slice,
-        None => return NoiseErrorCode::InvalidParameter as c_int,
-    };
-    
-    match session.encrypt(plaintext_slice) {
-        Ok(ct) => {
-            if unsafe { crate::ffi::helpers::copy_to_c_buffer(&ct, ciphertext, ciphertext_len) } {
-                NoiseErrorCode::Success as c_int
-            } else {
-                NoiseErrorCode::BufferTooSmall as c_int
-            }
+    crate::ffi::helpers::catch_status(|| {
+        if ciphertext_len.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
         }
-        Err(e) => NoiseErrorCode::from(e) as c_int,
-    }
+        
+        with_session_events(session, |session| {
+            let plaintext_slice = match unsafe { crate::ffi::helpers::c_to_slice(plaintext, plaintext_len) } {
+                Some(slice) => slice,
+                None => return NoiseErrorCode::InvalidParameter as c_int,
+            };
+            
+            let output = unsafe { crate::ffi::helpers::c_to_slice_mut(ciphertext, *ciphertext_len) }.unwrap_or_default();
+            let result = session.encrypt_into(plaintext_slice, output);
+            unsafe { write_output_len(result, ciphertext_len) }
+        })
+    })
 }
 
 /// Decrypt a message
+///
+/// Decrypts straight into `plaintext`, which must hold `ciphertext_len`
+/// less the 16-byte tag. If it is too small, `*plaintext_len` is set to
+/// that length and the message is left undecrypted.
 #[no_mangle]
 pub extern "C" fn noise_decrypt(
     session: *mut NoiseSessionFFI,
@@ -214,81 +855,2183 @@ pub extern "C" fn noise_decrypt(
     plaintext: *mut c_uchar,
     plaintext_len: *mut size_t,
 ) -> c_int {
-    if !crate::ffi::helpers::validate_session_ptr(session) || plaintext_len.is_null() {
-        return NoiseErrorCode::InvalidParameter as c_int;
-    }
-    
-    let session = unsafe { &mut *(session as *mut NoiseSession) };
-    let ciphertext_slice = match unsafe { crate::ffi::helpers::c_to_slice(ciphertext, ciphertext_len) } {
-        Some(slice) => slice,
-        None => return NoiseErrorCode::InvalidParameter as c_int,
-    };
-    
-    match session.decrypt(ciphertext_slice) {
-        Ok(pt) => {
-            if unsafe { crate::ffi::helpers::copy_to_c_buffer(&pt, plaintext, plaintext_len) } {
-                NoiseErrorCode::Success as c_int
-            } else {
-                NoiseErrorCode::BufferTooSmall as c_int
-            }
+    crate::ffi::helpers::catch_status(|| {
+        if plaintext_len.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
         }
-        Err(e) => NoiseErrorCode::from(e) as c_int,
-    }
+        
+        let status = with_session_events(session, |session| {
+            let ciphertext_slice = match unsafe { crate::ffi::helpers::c_to_slice(ciphertext, ciphertext_len) } {
+                Some(slice) => slice,
+                None => return NoiseErrorCode::InvalidParameter as c_int,
+            };
+            
+            let output = unsafe { crate::ffi::helpers::c_to_slice_mut(plaintext, *plaintext_len) }.unwrap_or_default();
+            let result = session.decrypt_into(ciphertext_slice, output);
+            unsafe { write_output_len(result, plaintext_len) }
+        });
+        if status == NoiseErrorCode::Success as c_int {
+            let output = unsafe { crate::ffi::helpers::c_to_slice(plaintext, *plaintext_len) }.unwrap_or_default();
+            callbacks::message_decrypted(session as u64, output);
+        }
+        status
+    })
 }
 
-/// Get the remote peer's static public key
+/// Write a handshake message into a buffer allocated by the library
+///
+/// On success `*output` owns the message and must be released with
+/// `noise_buffer_free`. On failure it is left empty.
 #[no_mangle]
-pub extern "C" fn noise_get_remote_static(
+pub extern "C" fn noise_write_message_alloc(
     session: *mut NoiseSessionFFI,
-    output: *mut c_uchar,
-    output_len: *mut size_t,
+    payload: *const c_uchar,
+    payload_len: size_t,
+    output: *mut NoiseBuffer,
 ) -> c_int {
-    if !crate::ffi::helpers::validate_session_ptr(session) || output_len.is_null() {
-        return NoiseErrorCode::InvalidParameter as c_int;
-    }
-    
-    let session = unsafe { &*(session as *mut NoiseSession) };
-    
-    match session.get_remote_static() {
-        Some(key) => {
-            if unsafe { crate::ffi::helpers::copy_to_c_buffer(key, output, output_len) } {
-                NoiseErrorCode::Success as c_int
-            } else {
-                NoiseErrorCode::BufferTooSmall as c_int
-            }
-        }
-        None => {
-            unsafe { *output_len = 0; }
-            NoiseErrorCode::InvalidState as c_int
-        }
-    }
+    alloc_call(output, |output| {
+        with_session_events(session, |session| {
+            let payload_slice = unsafe { crate::ffi::helpers::c_to_slice(payload, payload_len).unwrap_or(&[]) };
+            unsafe { write_buffer(Some(session.write_message(payload_slice)), output) }
+        })
+    })
 }
 
-/// Get the maximum message length
+/// Read a handshake message, returning its payload in a buffer allocated
+/// by the library
+///
+/// On success `*payload` owns the payload, possibly empty, and must be
+/// released with `noise_buffer_free`.
 #[no_mangle]
-pub extern "C" fn noise_max_message_len() -> size_t {
-    crate::core::crypto::NOISE_MAX_MESSAGE_LEN
+pub extern "C" fn noise_read_message_alloc(
+    session: *mut NoiseSessionFFI,
+    input: *const c_uchar,
+    input_len: size_t,
+    payload: *mut NoiseBuffer,
+) -> c_int {
+    alloc_call(payload, |payload| {
+        with_session_events(session, |session| {
+            let input_slice = match unsafe { crate::ffi::helpers::c_to_slice(input, input_len) } {
+                Some(slice) => slice,
+                None => return NoiseErrorCode::InvalidParameter as c_int,
+            };
+            unsafe { write_buffer(Some(session.read_message(input_slice)), payload) }
+        })
+    })
 }
 
-/// Get the maximum payload length
+/// Encrypt a message into a buffer allocated by the library
+///
+/// Saves sizing the output first with `noise_encrypt_size_hint`. On
+/// success `*ciphertext` must be released with `noise_buffer_free`.
 #[no_mangle]
-pub extern "C" fn noise_max_payload_len() -> size_t {
-    crate::core::crypto::NOISE_MAX_PAYLOAD_LEN
+pub extern "C" fn noise_encrypt_alloc(
+    session: *mut NoiseSessionFFI,
+    plaintext: *const c_uchar,
+    plaintext_len: size_t,
+    ciphertext: *mut NoiseBuffer,
+) -> c_int {
+    alloc_call(ciphertext, |ciphertext| {
+        with_session_events(session, |session| {
+            let plaintext_slice = match unsafe { crate::ffi::helpers::c_to_slice(plaintext, plaintext_len) } {
+                Some(slice) => slice,
+                None => return NoiseErrorCode::InvalidParameter as c_int,
+            };
+            unsafe { write_buffer(Some(session.encrypt(plaintext_slice)), ciphertext) }
+        })
+    })
 }
 
-/// Get error string for an error code
+/// Decrypt a message into a buffer allocated by the library
+///
+/// On success `*plaintext` must be released with `noise_buffer_free`,
+/// which wipes it first.
 #[no_mangle]
-pub extern "C" fn noise_error_string(error: c_int) -> *const c_char {
-    match error {
-        0 => b"Success\0".as_ptr() as *const c_char,
-        1 => b"Invalid parameter\0".as_ptr() as *const c_char,
-        2 => b"Out of memory\0".as_ptr() as *const c_char,
-        3 => b"Handshake failed\0".as_ptr() as *const c_char,
-        4 => b"Encryption failed\0".as_ptr() as *const c_char,
-        5 => b"Decryption failed\0".as_ptr() as *const c_char,
-        6 => b"Buffer too small\0".as_ptr() as *const c_char,
-        7 => b"Invalid state\0".as_ptr() as *const c_char,
-        8 => b"Protocol error\0".as_ptr() as *const c_char,
-        _ => b"Unknown error\0".as_ptr() as *const c_char,
-    }
-}
\ No newline at end of file
+pub extern "C" fn noise_decrypt_alloc(
+    session: *mut NoiseSessionFFI,
+    ciphertext: *const c_uchar,
+    ciphertext_len: size_t,
+    plaintext: *mut NoiseBuffer,
+) -> c_int {
+    alloc_call(plaintext, |plaintext| {
+        let status = with_session_events(session, |session| {
+            let ciphertext_slice = match unsafe { crate::ffi::helpers::c_to_slice(ciphertext, ciphertext_len) } {
+                Some(slice) => slice,
+                None => return NoiseErrorCode::InvalidParameter as c_int,
+            };
+            unsafe { write_buffer(Some(session.decrypt(ciphertext_slice)), plaintext) }
+        });
+        if status == NoiseErrorCode::Success as c_int {
+            let output = unsafe { crate::ffi::helpers::c_to_slice((*plaintext).data, (*plaintext).len) }.unwrap_or_default();
+            callbacks::message_decrypted(session as u64, output);
+        }
+        status
+    })
+}
+
+/// Release a buffer returned by a `_alloc` function
+///
+/// The contents are wiped and `*buffer` is left empty, so freeing it again
+/// is harmless. Null and empty buffers are ignored. `_alloc` functions
+/// overwrite their output without reading it, so free a buffer before
+/// passing it to another one.
+#[no_mangle]
+pub extern "C" fn noise_buffer_free(buffer: *mut NoiseBuffer) {
+    crate::ffi::helpers::catch_panic((), || {
+        if buffer.is_null() {
+            return;
+        }
+        let mut data = unsafe { (*buffer).into_vec() };
+        data.zeroize();
+    })
+}
+
+/// Allocate a zeroed buffer of `len` bytes, e.g. to build input in place
+///
+/// Like any other library buffer it must be released with
+/// `noise_buffer_free`.
+#[no_mangle]
+pub extern "C" fn noise_buffer_alloc(len: size_t, buffer: *mut NoiseBuffer) -> c_int {
+    alloc_call(buffer, |buffer| {
+        unsafe { *buffer = NoiseBuffer::from_vec(vec![0u8; len]) };
+        NoiseErrorCode::Success as c_int
+    })
+}
+
+/// Resize a library buffer to `len` bytes, keeping its contents
+///
+/// New bytes are zero. Bytes cut off, and the old allocation if the buffer
+/// has to move, are wiped. `data` may change, so pointers into the buffer
+/// must be refreshed afterwards.
+#[no_mangle]
+pub extern "C" fn noise_buffer_resize(buffer: *mut NoiseBuffer, len: size_t) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        if buffer.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        let mut data = unsafe { (*buffer).into_vec() };
+        if len > data.capacity() {
+            let mut grown = Vec::with_capacity(len);
+            grown.extend_from_slice(&data);
+            data.zeroize();
+            data = grown;
+        } else if len < data.len() {
+            data[len..].zeroize();
+        }
+        data.resize(len, 0);
+        unsafe { *buffer = NoiseBuffer::from_vec(data) };
+        NoiseErrorCode::Success as c_int
+    })
+}
+
+/// Get the remote peer's static public key
+#[no_mangle]
+pub extern "C" fn noise_get_remote_static(
+    session: *mut NoiseSessionFFI,
+    output: *mut c_uchar,
+    output_len: *mut size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        if output_len.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        
+        with_session(session, |session| {
+            match session.get_remote_static() {
+                Some(key) => {
+                    if unsafe { crate::ffi::helpers::copy_to_c_buffer(key, output, output_len) } {
+                        NoiseErrorCode::Success as c_int
+                    } else {
+                        NoiseErrorCode::BufferTooSmall as c_int
+                    }
+                }
+                None => {
+                    unsafe { *output_len = 0; }
+                    NoiseErrorCode::InvalidState as c_int
+                }
+            }
+        })
+    })
+}
+
+/// Get this side's static public key
+#[no_mangle]
+pub extern "C" fn noise_get_local_static(
+    session: *mut NoiseSessionFFI,
+    output: *mut c_uchar,
+    output_len: *mut size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        if output_len.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        
+        with_session(session, |session| {
+            match session.get_local_static() {
+                Some(key) => {
+                    if unsafe { crate::ffi::helpers::copy_to_c_buffer(key, output, output_len) } {
+                        NoiseErrorCode::Success as c_int
+                    } else {
+                        NoiseErrorCode::BufferTooSmall as c_int
+                    }
+                }
+                None => {
+                    unsafe { *output_len = 0; }
+                    NoiseErrorCode::InvalidState as c_int
+                }
+            }
+        })
+    })
+}
+
+/// Get the session's short public identifier, the same on both peers
+///
+/// Writes `SESSION_ID_LEN` bytes once the handshake completes, for use
+/// as a correlation key in logs and metrics. Fails with
+/// `NOISE_ERROR_INVALID_STATE` before then.
+#[no_mangle]
+pub extern "C" fn noise_session_id(
+    session: *mut NoiseSessionFFI,
+    output: *mut c_uchar,
+    output_len: *mut size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        if output_len.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        
+        with_session(session, |session| {
+            match session.session_id() {
+                Some(id) => {
+                    if unsafe { crate::ffi::helpers::copy_to_c_buffer(id.as_bytes(), output, output_len) } {
+                        NoiseErrorCode::Success as c_int
+                    } else {
+                        NoiseErrorCode::BufferTooSmall as c_int
+                    }
+                }
+                None => {
+                    unsafe { *output_len = 0; }
+                    NoiseErrorCode::InvalidState as c_int
+                }
+            }
+        })
+    })
+}
+
+/// Route log records up to `level` to `callback`
+///
+/// `level` is one of `NOISE_LOG_ERROR` through `NOISE_LOG_TRACE`;
+/// `NOISE_LOG_OFF` or a null `callback` stops logging. Messages are scrubbed
+/// of anything resembling key material. `context` is passed back unchanged
+/// and must stay valid until the callback is replaced.
+#[no_mangle]
+pub extern "C" fn noise_set_log_callback(level: c_int, callback: NoiseLogCallback, context: *mut c_void) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        let level = match u8::try_from(level) {
+            Ok(0) => None,
+            Ok(level) => match LogLevel::from_u8(level) {
+                Some(level) => Some(level),
+                None => return NoiseErrorCode::InvalidParameter as c_int,
+            },
+            Err(_) => return NoiseErrorCode::InvalidParameter as c_int,
+        };
+        let (Some(level), Some(callback)) = (level, callback) else {
+            crate::core::logging::clear_log_handler();
+            return NoiseErrorCode::Success as c_int;
+        };
+        
+        let context = CallbackContext(context);
+        crate::core::logging::set_log_handler(level, move |level, target, message| {
+            let target = CString::new(target).unwrap_or_default();
+            let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+            unsafe { callback(context.get(), level as c_int, target.as_ptr(), message.as_ptr()) };
+        });
+        NoiseErrorCode::Success as c_int
+    })
+}
+
+/// Inspect every handshake payload from the peer with `callback`
+///
+/// A non-zero return from `callback` aborts the handshake: the pending
+/// `noise_read_message` fails with `NOISE_ERROR_HANDSHAKE_FAILED` and the
+/// session cannot continue. `context` is passed back unchanged and must
+/// stay valid until the session is freed or the verifier is replaced. A
+/// null `callback` removes the verifier.
+#[no_mangle]
+pub extern "C" fn noise_set_payload_verifier(
+    session: *mut NoiseSessionFFI,
+    callback: NoisePayloadVerifier,
+    context: *mut c_void,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        with_session(session, |session| {
+            match callback {
+                Some(callback) => {
+                    let context = CallbackContext(context);
+                    session.set_raw_payload_validator(move |payload, remote_static| {
+                        let (key, key_len) = remote_static.map_or((ptr::null(), 0), |key| (key.as_ptr(), key.len()));
+                        unsafe { callback(context.get(), payload.as_ptr(), payload.len(), key, key_len) == 0 }
+                    });
+                }
+                None => session.clear_payload_validator(),
+            }
+            NoiseErrorCode::Success as c_int
+        })
+    })
+}
+
+/// Caller-owned context handed back to a C callback
+struct CallbackContext(*mut c_void);
+
+// The caller guarantees the context is usable from the threads that call in
+unsafe impl Send for CallbackContext {}
+unsafe impl Sync for CallbackContext {}
+
+impl CallbackContext {
+    fn get(&self) -> *mut c_void {
+        self.0
+    }
+}
+
+/// Write the session's safety number as a NUL-terminated string
+///
+/// `local_id` and `remote_id` are optional stable identifiers for this
+/// side and the peer, e.g. user IDs. Each peer passes its own identifier as
+/// `local_id` and the other's as `remote_id`; the two halves of the number
+/// are ordered by value rather than by side, so both compute the same
+/// digits. Needs both static keys, so call it once the handshake has
+/// completed. `output_len` includes the NUL.
+#[no_mangle]
+pub extern "C" fn noise_safety_number(
+    session: *mut NoiseSessionFFI,
+    local_id: *const c_char,
+    remote_id: *const c_char,
+    output: *mut c_char,
+    output_len: *mut size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        if output_len.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        let local_id = unsafe { optional_c_bytes(local_id) };
+        let remote_id = unsafe { optional_c_bytes(remote_id) };
+        
+        let result = SESSIONS.with(session as u64, |session| {
+            let (Some(local), Some(remote)) = (session.get_local_static(), session.get_remote_static()) else {
+                return Err(crate::core::error::NoiseError::InvalidState("Static keys are not known yet".to_string()));
+            };
+            SafetyNumber::new(local_id, local, remote_id, remote).map(|number| nul_terminated(&number.grouped()))
+        });
+        write_output(result, output as *mut c_uchar, output_len)
+    })
+}
+
+/// Write the session's emoji SAS as a NUL-terminated UTF-8 string
+///
+/// The emoji are separated by spaces. Available once the handshake has
+/// completed; `output_len` includes the NUL.
+#[no_mangle]
+pub extern "C" fn noise_emoji_sas(
+    session: *mut NoiseSessionFFI,
+    output: *mut c_char,
+    output_len: *mut size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        if output_len.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        
+        let result = SESSIONS.with(session as u64, |session| {
+            let hash = session.get_handshake_hash()
+                .ok_or_else(|| crate::core::error::NoiseError::InvalidState("Handshake not complete".to_string()))?;
+            EmojiSas::from_handshake_hash(hash).map(|sas| nul_terminated(&sas.to_string()))
+        });
+        write_output(result, output as *mut c_uchar, output_len)
+    })
+}
+
+/// Derive `output_len` bytes of keying material bound to the session
+///
+/// `label` is a NUL-terminated UTF-8 name for the key's purpose; `context`
+/// may be null when `context_len` is 0. Available once the handshake has
+/// completed.
+#[no_mangle]
+pub extern "C" fn noise_export_key(
+    session: *mut NoiseSessionFFI,
+    label: *const c_char,
+    context: *const c_uchar,
+    context_len: size_t,
+    output: *mut c_uchar,
+    output_len: size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        if label.is_null() || output.is_null() || (context.is_null() && context_len != 0) {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        let Ok(label) = unsafe { std::ffi::CStr::from_ptr(label) }.to_str() else {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        };
+        let context = unsafe { crate::ffi::helpers::c_to_slice(context, context_len) }.unwrap_or(&[]);
+        
+        with_session(session, |session| match session.export_key(label, context, output_len) {
+            Ok(key) => {
+                let key = zeroize::Zeroizing::new(key);
+                unsafe { ptr::copy_nonoverlapping(key.as_ptr(), output, output_len) };
+                NoiseErrorCode::Success as c_int
+            }
+            Err(e) => crate::ffi::helpers::report_error(e),
+        })
+    })
+}
+
+/// Bytes of an optional C string; null reads as empty
+unsafe fn optional_c_bytes<'a>(s: *const c_char) -> &'a [u8] {
+    if s.is_null() {
+        &[]
+    } else {
+        std::ffi::CStr::from_ptr(s).to_bytes()
+    }
+}
+
+fn nul_terminated(s: &str) -> Vec<u8> {
+    let mut bytes = s.as_bytes().to_vec();
+    bytes.push(0);
+    bytes
+}
+
+/// Get the estimated number of handshake round trips remaining (0 once complete)
+#[no_mangle]
+pub extern "C" fn noise_round_trips_remaining(
+    session: *mut NoiseSessionFFI,
+    remaining: *mut size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        if remaining.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        
+        with_session(session, |session| {
+            unsafe { *remaining = session.round_trips_remaining(); }
+            NoiseErrorCode::Success as c_int
+        })
+    })
+}
+
+/// Get the number of transport messages left before nonce exhaustion
+#[no_mangle]
+pub extern "C" fn noise_remaining_nonce_budget(
+    session: *mut NoiseSessionFFI,
+    remaining: *mut u64,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        if remaining.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        
+        with_session(session, |session| {
+            unsafe { *remaining = session.remaining_nonce_budget(); }
+            NoiseErrorCode::Success as c_int
+        })
+    })
+}
+
+/// Rekey the sending direction; the peer must rekey its receiving direction
+#[no_mangle]
+pub extern "C" fn noise_rekey_outgoing(session: *mut NoiseSessionFFI) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        with_session_events(session, |session| {
+            match session.rekey_outgoing() {
+                Ok(()) => NoiseErrorCode::Success as c_int,
+                Err(e) => crate::ffi::helpers::report_error(e),
+            }
+        })
+    })
+}
+
+/// Rekey the receiving direction to follow a peer's `noise_rekey_outgoing`
+#[no_mangle]
+pub extern "C" fn noise_rekey_incoming(session: *mut NoiseSessionFFI) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        with_session_events(session, |session| {
+            match session.rekey_incoming() {
+                Ok(()) => NoiseErrorCode::Success as c_int,
+                Err(e) => crate::ffi::helpers::report_error(e),
+            }
+        })
+    })
+}
+
+/// Get the number of rekeys applied to each direction
+#[no_mangle]
+pub extern "C" fn noise_key_generations(
+    session: *mut NoiseSessionFFI,
+    sending: *mut u32,
+    receiving: *mut u32,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        if sending.is_null() || receiving.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        
+        with_session(session, |session| {
+            unsafe {
+                *sending = session.sending_generation();
+                *receiving = session.receiving_generation();
+            }
+            NoiseErrorCode::Success as c_int
+        })
+    })
+}
+
+/// Get the session's traffic counters
+///
+/// Counters start at zero for each session handle, including sessions
+/// restored from a snapshot.
+#[no_mangle]
+pub extern "C" fn noise_session_stats(session: *mut NoiseSessionFFI, stats: *mut NoiseSessionStats) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        if stats.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        
+        with_session(session, |session| {
+            unsafe { *stats = session.stats().into() };
+            NoiseErrorCode::Success as c_int
+        })
+    })
+}
+
+/// Get the number of protocols this build supports
+#[no_mangle]
+pub extern "C" fn noise_supported_protocol_count() -> size_t {
+    crate::ffi::helpers::catch_panic(0, || {
+        crate::core::protocols::supported_protocols().len()
+    })
+}
+
+/// Copy the NUL-terminated name of the supported protocol at `index`
+///
+/// Names have the form `Noise_<pattern>_<dh>_<cipher>_<hash>`.
+#[no_mangle]
+pub extern "C" fn noise_supported_protocol_name(
+    index: size_t,
+    output: *mut c_char,
+    output_len: *mut size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        if output_len.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        
+        let protocols = crate::core::protocols::supported_protocols();
+        let Some(protocol) = protocols.get(index) else {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        };
+        
+        let mut name = protocol.name().into_bytes();
+        name.push(0);
+        if unsafe { crate::ffi::helpers::copy_to_c_buffer(&name, output as *mut c_uchar, output_len) } {
+            NoiseErrorCode::Success as c_int
+        } else {
+            NoiseErrorCode::BufferTooSmall as c_int
+        }
+    })
+}
+
+/// Get the version of the C ABI this library implements
+///
+/// The major version is in the upper 16 bits and the minor in the lower 16.
+/// An app built against major `M`, minor `m` works with any library of
+/// major `M` and minor at least `m`; check this at startup rather than
+/// failing later on a missing symbol.
+#[no_mangle]
+pub extern "C" fn noise_ffi_version() -> u32 {
+    (NOISE_FFI_VERSION_MAJOR << 16) | NOISE_FFI_VERSION_MINOR
+}
+
+/// Get the library's release version, e.g. `"0.1.0"`
+///
+/// The string is static and owned by the library.
+#[no_mangle]
+pub extern "C" fn noise_library_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char
+}
+
+/// Check whether this build has an optional capability: 1 if it does,
+/// 0 if not or if `feature` is unknown
+///
+/// Protocol capabilities (`"xx"`, `"ik"`, `"psk"`, `"pq"`) reflect the
+/// crypto compiled in; `"fd"` the file-descriptor functions; the rest name
+/// Cargo features the library was built with.
+#[no_mangle]
+pub extern "C" fn noise_has_feature(feature: *const c_char) -> c_int {
+    crate::ffi::helpers::catch_panic(0, || {
+        let Some(feature) = (unsafe { crate::ffi::helpers::c_to_str(feature) }) else {
+            return 0;
+        };
+        has_feature(feature) as c_int
+    })
+}
+
+fn has_feature(feature: &str) -> bool {
+    let protocols = crate::core::protocols::supported_protocols;
+    match feature {
+        "xx" => protocols().iter().any(|p| p.pattern.starts_with("XX")),
+        "ik" => protocols().iter().any(|p| p.pattern.starts_with("IK")),
+        "psk" => protocols().iter().any(|p| p.psk),
+        "pq" => protocols().iter().any(|p| p.pq),
+        "fd" => cfg!(all(unix, feature = "file-cipher")),
+        "batching" => cfg!(feature = "batching"),
+        "file-cipher" => cfg!(feature = "file-cipher"),
+        "manager" => cfg!(feature = "manager"),
+        "sealed" => cfg!(feature = "sealed"),
+        "identity" => cfg!(feature = "identity"),
+        "hardware-crypto" => cfg!(feature = "hardware-crypto"),
+        "sqlite" => cfg!(feature = "sqlite"),
+        "sqlcipher" => cfg!(feature = "sqlcipher"),
+        "tracing" => cfg!(feature = "tracing"),
+        "mlock" => cfg!(all(feature = "mlock", any(unix, windows))),
+        _ => false,
+    }
+}
+
+/// Create a session manager, returning its handle or `NOISE_INVALID_HANDLE`
+#[cfg(feature = "manager")]
+#[no_mangle]
+pub extern "C" fn noise_manager_new(
+    private_key: *const c_uchar,
+    private_key_len: size_t,
+    error: *mut c_int,
+) -> u64 {
+    crate::ffi::helpers::catch_panic_with_error(error, NOISE_INVALID_HANDLE, || {
+        if error.is_null() || private_key.is_null() || private_key_len != 32 {
+            if !error.is_null() {
+                unsafe { *error = NoiseErrorCode::InvalidParameter as c_int; }
+            }
+            return NOISE_INVALID_HANDLE;
+        }
+        
+        match SecretKey::from_bytes(unsafe { slice::from_raw_parts(private_key, private_key_len) }) {
+            Ok(key) => {
+                unsafe { *error = NoiseErrorCode::Success as c_int; }
+                MANAGERS.insert(SessionManager::new(&key))
+            }
+            Err(e) => {
+                unsafe { *error = crate::ffi::helpers::report_error(e); }
+                NOISE_INVALID_HANDLE
+            }
+        }
+    })
+}
+
+/// Rebuild a manager from `noise_manager_serialize` output
+#[cfg(feature = "manager")]
+#[no_mangle]
+pub extern "C" fn noise_manager_restore(
+    private_key: *const c_uchar,
+    private_key_len: size_t,
+    data: *const c_uchar,
+    data_len: size_t,
+    error: *mut c_int,
+) -> u64 {
+    crate::ffi::helpers::catch_panic_with_error(error, NOISE_INVALID_HANDLE, || {
+        if error.is_null() || private_key.is_null() || private_key_len != 32 {
+            if !error.is_null() {
+                unsafe { *error = NoiseErrorCode::InvalidParameter as c_int; }
+            }
+            return NOISE_INVALID_HANDLE;
+        }
+        
+        let private_key_slice = unsafe { slice::from_raw_parts(private_key, private_key_len) };
+        let Some(data_slice) = (unsafe { crate::ffi::helpers::c_to_slice(data, data_len) }) else {
+            unsafe { *error = NoiseErrorCode::InvalidParameter as c_int; }
+            return NOISE_INVALID_HANDLE;
+        };
+        
+        let manager = SecretKey::from_bytes(private_key_slice)
+            .and_then(|key| SessionManager::restore(&key, data_slice));
+        match manager {
+            Ok(manager) => {
+                unsafe { *error = NoiseErrorCode::Success as c_int; }
+                MANAGERS.insert(manager)
+            }
+            Err(e) => {
+                unsafe { *error = crate::ffi::helpers::report_error(e); }
+                NOISE_INVALID_HANDLE
+            }
+        }
+    })
+}
+
+/// Free a session manager and every session it owns
+///
+/// The handle is invalid afterwards; freeing it again is a no-op.
+#[cfg(feature = "manager")]
+#[no_mangle]
+pub extern "C" fn noise_manager_free(manager: u64) {
+    crate::ffi::helpers::catch_panic((), || {
+        MANAGERS.remove(manager);
+    })
+}
+
+/// Start a handshake with a peer, writing the first message to `output`
+#[cfg(feature = "manager")]
+#[no_mangle]
+pub extern "C" fn noise_manager_connect(
+    manager: u64,
+    peer_id: *const c_char,
+    output: *mut c_uchar,
+    output_len: *mut size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        let Some(peer_id) = (unsafe { crate::ffi::helpers::c_to_str(peer_id) }) else {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        };
+        if output_len.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        
+        write_output(MANAGERS.with(manager, |m| m.connect(peer_id)), output, output_len)
+    })
+}
+
+/// Prepare a handshake with a peer before it is reachable
+///
+/// The next `noise_manager_connect` to the peer writes the prepared first
+/// message without generating keys.
+#[cfg(feature = "manager")]
+#[no_mangle]
+pub extern "C" fn noise_manager_prewarm(manager: u64, peer_id: *const c_char) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        let Some(peer_id) = (unsafe { crate::ffi::helpers::c_to_str(peer_id) }) else {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        };
+        
+        match MANAGERS.with(manager, |m| m.prewarm(peer_id)) {
+            None => NoiseErrorCode::InvalidParameter as c_int,
+            Some(Err(e)) => crate::ffi::helpers::report_error(e),
+            Some(Ok(())) => NoiseErrorCode::Success as c_int,
+        }
+    })
+}
+
+/// Process a handshake message from a peer
+///
+/// The reply to send back is written to `output`; `*output_len` is set to 0
+/// when there is nothing to send.
+#[cfg(feature = "manager")]
+#[no_mangle]
+pub extern "C" fn noise_manager_handle_handshake(
+    manager: u64,
+    peer_id: *const c_char,
+    input: *const c_uchar,
+    input_len: size_t,
+    output: *mut c_uchar,
+    output_len: *mut size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        let Some(peer_id) = (unsafe { crate::ffi::helpers::c_to_str(peer_id) }) else {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        };
+        let Some(input_slice) = (unsafe { crate::ffi::helpers::c_to_slice(input, input_len) }) else {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        };
+        if output_len.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        
+        let result = MANAGERS.with(manager, |m| m.handle_handshake(peer_id, input_slice));
+        write_output(result.map(|r| r.map(Option::unwrap_or_default)), output, output_len)
+    })
+}
+
+/// Encrypt a message for a peer with its newest live session
+#[cfg(feature = "manager")]
+#[no_mangle]
+pub extern "C" fn noise_manager_encrypt(
+    manager: u64,
+    peer_id: *const c_char,
+    plaintext: *const c_uchar,
+    plaintext_len: size_t,
+    ciphertext: *mut c_uchar,
+    ciphertext_len: *mut size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        let Some(peer_id) = (unsafe { crate::ffi::helpers::c_to_str(peer_id) }) else {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        };
+        let Some(plaintext_slice) = (unsafe { crate::ffi::helpers::c_to_slice(plaintext, plaintext_len) }) else {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        };
+        if ciphertext_len.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        
+        let result = MANAGERS.with(manager, |m| match m.session_mut(peer_id) {
+            Some(session) => session.encrypt(plaintext_slice),
+            None => Err(crate::core::error::NoiseError::InvalidState("No session with peer".to_string())),
+        });
+        write_output(result, ciphertext, ciphertext_len)
+    })
+}
+
+/// Decrypt a message from a peer with its newest live session
+#[cfg(feature = "manager")]
+#[no_mangle]
+pub extern "C" fn noise_manager_decrypt(
+    manager: u64,
+    peer_id: *const c_char,
+    ciphertext: *const c_uchar,
+    ciphertext_len: size_t,
+    plaintext: *mut c_uchar,
+    plaintext_len: *mut size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        let Some(peer_id) = (unsafe { crate::ffi::helpers::c_to_str(peer_id) }) else {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        };
+        let Some(ciphertext_slice) = (unsafe { crate::ffi::helpers::c_to_slice(ciphertext, ciphertext_len) }) else {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        };
+        if plaintext_len.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        
+        let result = MANAGERS.with(manager, |m| match m.session_mut(peer_id) {
+            Some(session) => session.decrypt(ciphertext_slice),
+            None => Err(crate::core::error::NoiseError::InvalidState("No session with peer".to_string())),
+        });
+        write_output(result, plaintext, plaintext_len)
+    })
+}
+
+/// Encrypt a message to a peer into a buffer allocated by the library
+///
+/// On success `*ciphertext` must be released with `noise_buffer_free`.
+#[cfg(feature = "manager")]
+#[no_mangle]
+pub extern "C" fn noise_manager_encrypt_alloc(
+    manager: u64,
+    peer_id: *const c_char,
+    plaintext: *const c_uchar,
+    plaintext_len: size_t,
+    ciphertext: *mut NoiseBuffer,
+) -> c_int {
+    alloc_call(ciphertext, |ciphertext| {
+        let Some(peer_id) = (unsafe { crate::ffi::helpers::c_to_str(peer_id) }) else {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        };
+        let Some(plaintext_slice) = (unsafe { crate::ffi::helpers::c_to_slice(plaintext, plaintext_len) }) else {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        };
+        
+        let result = MANAGERS.with(manager, |m| match m.session_mut(peer_id) {
+            Some(session) => session.encrypt(plaintext_slice),
+            None => Err(crate::core::error::NoiseError::InvalidState("No session with peer".to_string())),
+        });
+        unsafe { write_buffer(result, ciphertext) }
+    })
+}
+
+/// Decrypt a message from a peer into a buffer allocated by the library
+///
+/// On success `*plaintext` must be released with `noise_buffer_free`.
+#[cfg(feature = "manager")]
+#[no_mangle]
+pub extern "C" fn noise_manager_decrypt_alloc(
+    manager: u64,
+    peer_id: *const c_char,
+    ciphertext: *const c_uchar,
+    ciphertext_len: size_t,
+    plaintext: *mut NoiseBuffer,
+) -> c_int {
+    alloc_call(plaintext, |plaintext| {
+        let Some(peer_id) = (unsafe { crate::ffi::helpers::c_to_str(peer_id) }) else {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        };
+        let Some(ciphertext_slice) = (unsafe { crate::ffi::helpers::c_to_slice(ciphertext, ciphertext_len) }) else {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        };
+        
+        let result = MANAGERS.with(manager, |m| match m.session_mut(peer_id) {
+            Some(session) => session.decrypt(ciphertext_slice),
+            None => Err(crate::core::error::NoiseError::InvalidState("No session with peer".to_string())),
+        });
+        unsafe { write_buffer(result, plaintext) }
+    })
+}
+
+/// Check whether a session with a peer is live (1) or not (0)
+#[cfg(feature = "manager")]
+#[no_mangle]
+pub extern "C" fn noise_manager_is_established(manager: u64, peer_id: *const c_char) -> c_int {
+    crate::ffi::helpers::catch_panic(0, || {
+        let Some(peer_id) = (unsafe { crate::ffi::helpers::c_to_str(peer_id) }) else {
+            return 0;
+        };
+        MANAGERS.with(manager, |m| m.is_established(peer_id) as c_int).unwrap_or(0)
+    })
+}
+
+/// Drop every session with a peer
+#[cfg(feature = "manager")]
+#[no_mangle]
+pub extern "C" fn noise_manager_remove(manager: u64, peer_id: *const c_char) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        let Some(peer_id) = (unsafe { crate::ffi::helpers::c_to_str(peer_id) }) else {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        };
+        match MANAGERS.with(manager, |m| m.remove(peer_id)) {
+            Some(_) => NoiseErrorCode::Success as c_int,
+            None => NoiseErrorCode::InvalidParameter as c_int,
+        }
+    })
+}
+
+/// Number of peers with a live session or handshake in progress
+#[cfg(feature = "manager")]
+#[no_mangle]
+pub extern "C" fn noise_manager_peer_count(manager: u64) -> size_t {
+    crate::ffi::helpers::catch_panic(0, || {
+        MANAGERS.with(manager, |m| m.len()).unwrap_or(0)
+    })
+}
+
+/// Get traffic counters summed over all of a manager's live sessions
+///
+/// `handshake_duration_ms` is the longest handshake among them.
+#[cfg(feature = "manager")]
+#[no_mangle]
+pub extern "C" fn noise_manager_stats(manager: u64, stats: *mut NoiseSessionStats) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        if stats.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        match MANAGERS.with(manager, |m| m.stats()) {
+            Some(total) => {
+                unsafe { *stats = total.into() };
+                NoiseErrorCode::Success as c_int
+            }
+            None => NoiseErrorCode::InvalidParameter as c_int,
+        }
+    })
+}
+
+/// Get traffic counters summed over a peer's live sessions
+///
+/// Fails with `NOISE_ERROR_INVALID_PARAMETER` if the peer has none.
+#[cfg(feature = "manager")]
+#[no_mangle]
+pub extern "C" fn noise_manager_peer_stats(
+    manager: u64,
+    peer_id: *const c_char,
+    stats: *mut NoiseSessionStats,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        let Some(peer_id) = (unsafe { crate::ffi::helpers::c_to_str(peer_id) }) else {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        };
+        if stats.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        match MANAGERS.with(manager, |m| m.peer_stats(peer_id)).flatten() {
+            Some(total) => {
+                unsafe { *stats = total.into() };
+                NoiseErrorCode::Success as c_int
+            }
+            None => NoiseErrorCode::InvalidParameter as c_int,
+        }
+    })
+}
+
+/// Serialize every live session, including raw keys, into `output`
+///
+/// Wrap the output before persisting it, and free the manager afterwards:
+/// continuing to use it alongside a restored copy reuses nonces.
+#[cfg(feature = "manager")]
+#[no_mangle]
+pub extern "C" fn noise_manager_serialize(
+    manager: u64,
+    output: *mut c_uchar,
+    output_len: *mut size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        if output_len.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        write_output(MANAGERS.with(manager, |m| m.serialize()), output, output_len)
+    })
+}
+
+/// Serialize a manager's live sessions into a buffer allocated by the library
+///
+/// On success `*output` must be released with `noise_buffer_free`, which
+/// wipes the key material it holds.
+#[cfg(feature = "manager")]
+#[no_mangle]
+pub extern "C" fn noise_manager_serialize_alloc(manager: u64, output: *mut NoiseBuffer) -> c_int {
+    alloc_call(output, |output| unsafe { write_buffer(MANAGERS.with(manager, |m| m.serialize()), output) })
+}
+
+/// Split an established session into send and receive halves
+///
+/// On success the session handle is freed and each half gets its own handle,
+/// so a reader and a writer thread never wait on each other.
+#[no_mangle]
+pub extern "C" fn noise_session_split(
+    session: *mut NoiseSessionFFI,
+    send_half: *mut *mut NoiseSendHalfFFI,
+    recv_half: *mut *mut NoiseRecvHalfFFI,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        if send_half.is_null() || recv_half.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        
+        let result = with_session(session, |inner| match inner.split_off() {
+            Ok((send, recv)) => {
+                unsafe {
+                    *send_half = SEND_HALVES.insert_ptr(send);
+                    *recv_half = RECV_HALVES.insert_ptr(recv);
+                    if (*send_half).is_null() || (*recv_half).is_null() {
+                        NoiseErrorCode::OutOfMemory as c_int
+                    } else {
+                        NoiseErrorCode::Success as c_int
+                    }
+                }
+            }
+            Err(e) => crate::ffi::helpers::report_error(e),
+        });
+        if result == NoiseErrorCode::Success as c_int && SESSIONS.remove(session as u64) {
+            callbacks::closed(session as u64);
+        }
+        result
+    })
+}
+
+/// Encrypt a message with the sending half of a split session
+#[no_mangle]
+pub extern "C" fn noise_send_half_encrypt(
+    half: *mut NoiseSendHalfFFI,
+    plaintext: *const c_uchar,
+    plaintext_len: size_t,
+    ciphertext: *mut c_uchar,
+    ciphertext_len: *mut size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        let Some(plaintext_slice) = (unsafe { crate::ffi::helpers::c_to_slice(plaintext, plaintext_len) }) else {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        };
+        if ciphertext_len.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        
+        let result = SEND_HALVES.with(half as u64, |half| half.encrypt(plaintext_slice));
+        write_output(result, ciphertext, ciphertext_len)
+    })
+}
+
+/// Decrypt a message with the receiving half of a split session
+#[no_mangle]
+pub extern "C" fn noise_recv_half_decrypt(
+    half: *mut NoiseRecvHalfFFI,
+    ciphertext: *const c_uchar,
+    ciphertext_len: size_t,
+    plaintext: *mut c_uchar,
+    plaintext_len: *mut size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        let Some(ciphertext_slice) = (unsafe { crate::ffi::helpers::c_to_slice(ciphertext, ciphertext_len) }) else {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        };
+        if plaintext_len.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        
+        let result = RECV_HALVES.with(half as u64, |half| half.decrypt(ciphertext_slice));
+        write_output(result, plaintext, plaintext_len)
+    })
+}
+
+/// Rekey the sending half; the peer must rekey its receiving direction
+#[no_mangle]
+pub extern "C" fn noise_send_half_rekey(half: *mut NoiseSendHalfFFI) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        match SEND_HALVES.with(half as u64, |half| half.rekey()) {
+            Some(Ok(())) => NoiseErrorCode::Success as c_int,
+            Some(Err(e)) => crate::ffi::helpers::report_error(e),
+            None => NoiseErrorCode::InvalidParameter as c_int,
+        }
+    })
+}
+
+/// Rekey the receiving half to follow the peer's outgoing rekey
+#[no_mangle]
+pub extern "C" fn noise_recv_half_rekey(half: *mut NoiseRecvHalfFFI) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        match RECV_HALVES.with(half as u64, |half| half.rekey()) {
+            Some(Ok(())) => NoiseErrorCode::Success as c_int,
+            Some(Err(e)) => crate::ffi::helpers::report_error(e),
+            None => NoiseErrorCode::InvalidParameter as c_int,
+        }
+    })
+}
+
+/// Free the sending half of a split session
+#[no_mangle]
+pub extern "C" fn noise_send_half_free(half: *mut NoiseSendHalfFFI) {
+    crate::ffi::helpers::catch_panic((), || {
+        SEND_HALVES.remove(half as u64);
+    })
+}
+
+/// Free the receiving half of a split session
+#[no_mangle]
+pub extern "C" fn noise_recv_half_free(half: *mut NoiseRecvHalfFFI) {
+    crate::ffi::helpers::catch_panic((), || {
+        RECV_HALVES.remove(half as u64);
+    })
+}
+
+/// Start a Noise Pipes handshake
+///
+/// An initiator passing the responder's cached 32-byte static key tries IK
+/// and falls back to XX if the key has changed; with `remote_static` null
+/// it runs XX. Responders pass null.
+#[no_mangle]
+pub extern "C" fn noise_pipe_new(
+    private_key: *const c_uchar,
+    private_key_len: size_t,
+    mode: c_int,
+    remote_static: *const c_uchar,
+    remote_static_len: size_t,
+    error: *mut c_int,
+) -> *mut NoisePipeFFI {
+    crate::ffi::helpers::catch_panic_with_error(error, ptr::null_mut(), || {
+        if error.is_null() {
+            return ptr::null_mut();
+        }
+        let private_key = unsafe { crate::ffi::helpers::c_to_slice(private_key, private_key_len) }
+            .and_then(|key| SecretKey::from_bytes(key).ok());
+        let remote_static = (!remote_static.is_null())
+            .then(|| unsafe { slice::from_raw_parts(remote_static, remote_static_len) });
+        let pipe = match (private_key, NoiseMode::from_c_int(mode), remote_static) {
+            (Some(private_key), Some(NoiseMode::Initiator), remote_static) => {
+                PipeSession::new_initiator(&private_key, remote_static)
+            }
+            (Some(private_key), Some(NoiseMode::Responder), None) => PipeSession::new_responder(&private_key),
+            _ => Err(crate::core::error::NoiseError::InvalidParameter),
+        };
+        
+        match pipe {
+            Ok(pipe) => {
+                let handle = PIPES.insert_ptr(pipe);
+                unsafe { *error = handle_status(handle); }
+                handle
+            }
+            Err(e) => {
+                unsafe { *error = crate::ffi::helpers::report_error(e); }
+                ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Write the next Noise Pipes handshake message
+#[no_mangle]
+pub extern "C" fn noise_pipe_write_message(
+    pipe: *mut NoisePipeFFI,
+    payload: *const c_uchar,
+    payload_len: size_t,
+    output: *mut c_uchar,
+    output_len: *mut size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        if output_len.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        let payload = unsafe { crate::ffi::helpers::c_to_slice(payload, payload_len).unwrap_or(&[]) };
+        let result = PIPES.with(pipe as u64, |pipe| pipe.write_message(payload));
+        write_output(result, output, output_len)
+    })
+}
+
+/// Read the peer's next Noise Pipes handshake message
+#[no_mangle]
+pub extern "C" fn noise_pipe_read_message(
+    pipe: *mut NoisePipeFFI,
+    input: *const c_uchar,
+    input_len: size_t,
+    payload: *mut c_uchar,
+    payload_len: *mut size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        let Some(input) = (unsafe { crate::ffi::helpers::c_to_slice(input, input_len) }) else {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        };
+        if payload_len.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        let result = PIPES.with(pipe as u64, |pipe| pipe.read_message(input));
+        write_output(result, payload, payload_len)
+    })
+}
+
+/// Check if a Noise Pipes handshake is complete
+#[no_mangle]
+pub extern "C" fn noise_pipe_is_handshake_complete(pipe: *mut NoisePipeFFI) -> c_int {
+    crate::ffi::helpers::catch_panic(0, || {
+        PIPES
+            .with(pipe as u64, |pipe| if pipe.is_handshake_finished() { 1 } else { 0 })
+            .unwrap_or(0)
+    })
+}
+
+/// Check whether the responder rejected IK and the handshake fell back to XX
+#[no_mangle]
+pub extern "C" fn noise_pipe_fell_back(pipe: *mut NoisePipeFFI) -> c_int {
+    crate::ffi::helpers::catch_panic(0, || {
+        PIPES
+            .with(pipe as u64, |pipe| if pipe.fell_back() { 1 } else { 0 })
+            .unwrap_or(0)
+    })
+}
+
+/// Turn a completed Noise Pipes handshake into a session
+///
+/// On success the pipe handle is freed and `*session` can be used with the
+/// rest of the API.
+#[no_mangle]
+pub extern "C" fn noise_pipe_into_session(pipe: *mut NoisePipeFFI, session: *mut *mut NoiseSessionFFI) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        if session.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        let finished = PIPES.with(pipe as u64, |pipe| pipe.is_handshake_finished());
+        match finished {
+            None => return NoiseErrorCode::InvalidParameter as c_int,
+            Some(false) => {
+                return crate::ffi::helpers::report_error(crate::core::error::NoiseError::InvalidState(
+                    "Handshake not complete".to_string(),
+                ))
+            }
+            Some(true) => {}
+        }
+        
+        let Some(inner) = PIPES.take(pipe as u64) else {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        };
+        match inner.into_session() {
+            Ok(inner) => {
+                unsafe {
+                    *session = session_to_ffi(inner);
+                    handle_status(*session)
+                }
+            }
+            Err(e) => crate::ffi::helpers::report_error(e),
+        }
+    })
+}
+
+/// Free a Noise Pipes handshake
+#[no_mangle]
+pub extern "C" fn noise_pipe_free(pipe: *mut NoisePipeFFI) {
+    crate::ffi::helpers::catch_panic((), || {
+        PIPES.remove(pipe as u64);
+    })
+}
+
+/// The function inside a set `NoiseBatchFlushCallback`
+#[cfg(feature = "batching")]
+type BatchCallbackFn = unsafe extern "C" fn(*mut c_void, u64, c_int, c_int, *const c_uchar, size_t);
+
+/// A batch and the thread flushing it, if any
+#[cfg(feature = "batching")]
+struct BatchHandle {
+    batch: std::sync::Arc<std::sync::Mutex<BatchedCrypto>>,
+    flusher: Option<BatchFlusher>,
+    /// Delivers immediate results, which bypass the batch's flush handler
+    callback: Option<(BatchCallbackFn, CallbackContext)>,
+}
+
+#[cfg(feature = "batching")]
+impl BatchHandle {
+    fn lock(&self) -> std::sync::MutexGuard<'_, BatchedCrypto> {
+        self.batch.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Run `f` on the batch behind a handle
+#[cfg(feature = "batching")]
+fn with_batch(batch: *mut NoiseBatchFFI, f: impl FnOnce(&mut BatchHandle) -> c_int) -> c_int {
+    BATCHES.with(batch as u64, f).unwrap_or(NoiseErrorCode::InvalidParameter as c_int)
+}
+
+/// Hand each result of a flush to a C callback
+#[cfg(feature = "batching")]
+fn deliver_batch(
+    callback: BatchCallbackFn,
+    context: &CallbackContext,
+    operation: BatchOperation,
+    results: Vec<BatchResult>,
+) {
+    let operation = match operation {
+        BatchOperation::Encrypt => NOISE_BATCH_ENCRYPT,
+        BatchOperation::Decrypt => NOISE_BATCH_DECRYPT,
+    };
+    for item in results {
+        match item.result {
+            Ok(data) => unsafe {
+                callback(context.get(), item.id, operation, NoiseErrorCode::Success as c_int, data.as_ptr(), data.len())
+            },
+            Err(e) => unsafe {
+                callback(context.get(), item.id, operation, NoiseErrorCode::from(e) as c_int, ptr::null(), 0)
+            },
+        }
+    }
+}
+
+/// Move a session into a batch that encrypts and decrypts in bulk
+///
+/// On success the session handle is freed and `*batch` owns the session.
+/// Once a callback is set with `noise_batch_set_flush_callback`, messages
+/// are flushed to it when the batch's threshold is reached, when
+/// `noise_batch_poll` finds the interval has passed, or by a background
+/// flusher. Until then messages stay queued.
+#[cfg(feature = "batching")]
+#[no_mangle]
+pub extern "C" fn noise_batch_new(session: *mut NoiseSessionFFI, batch: *mut *mut NoiseBatchFFI) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        if batch.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        let Some(inner) = SESSIONS.take(session as u64) else {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        };
+        callbacks::closed(session as u64);
+        let mut inner = BatchedCrypto::new(inner);
+        inner.set_power_mode(*POWER_MODE.lock().unwrap_or_else(|poisoned| poisoned.into_inner()));
+        let handle = BatchHandle {
+            batch: std::sync::Arc::new(std::sync::Mutex::new(inner)),
+            flusher: None,
+            callback: None,
+        };
+        unsafe {
+            *batch = BATCHES.insert_ptr(handle);
+            handle_status(*batch)
+        }
+    })
+}
+
+/// Queue a plaintext for encryption, reported under `id`
+#[cfg(feature = "batching")]
+#[no_mangle]
+pub extern "C" fn noise_batch_queue_encrypt(
+    batch: *mut NoiseBatchFFI,
+    id: u64,
+    plaintext: *const c_uchar,
+    plaintext_len: size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        let Some(plaintext) = (unsafe { crate::ffi::helpers::c_to_slice(plaintext, plaintext_len) }) else {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        };
+        with_batch(batch, |batch| {
+            batch.lock().queue_encrypt_with_id(id, plaintext.to_vec());
+            NoiseErrorCode::Success as c_int
+        })
+    })
+}
+
+/// Queue a ciphertext for decryption, reported under `id`
+#[cfg(feature = "batching")]
+#[no_mangle]
+pub extern "C" fn noise_batch_queue_decrypt(
+    batch: *mut NoiseBatchFFI,
+    id: u64,
+    ciphertext: *const c_uchar,
+    ciphertext_len: size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        let Some(ciphertext) = (unsafe { crate::ffi::helpers::c_to_slice(ciphertext, ciphertext_len) }) else {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        };
+        with_batch(batch, |batch| {
+            batch.lock().queue_decrypt_with_id(id, ciphertext.to_vec());
+            NoiseErrorCode::Success as c_int
+        })
+    })
+}
+
+/// Queue a message at `priority`, reported under `id`
+///
+/// `operation` is `NOISE_BATCH_ENCRYPT` or `NOISE_BATCH_DECRYPT` and
+/// `priority` a `NOISE_BATCH_PRIORITY_*` value.
+/// `NOISE_BATCH_PRIORITY_IMMEDIATE` messages bypass the batch and reach the
+/// flush callback before this returns, so they need one set; background
+/// messages wait for the next normal flush or the background interval.
+/// Ciphertexts must be decrypted in order, so an immediate one waits behind
+/// queued ciphertexts and background ones are queued as normal.
+#[cfg(feature = "batching")]
+#[no_mangle]
+pub extern "C" fn noise_batch_queue_with_priority(
+    batch: *mut NoiseBatchFFI,
+    id: u64,
+    operation: c_int,
+    priority: c_int,
+    data: *const c_uchar,
+    data_len: size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        let Some(data) = (unsafe { crate::ffi::helpers::c_to_slice(data, data_len) }) else {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        };
+        let priority = match NoiseBatchPriority::from_c_int(priority) {
+            Some(NoiseBatchPriority::Immediate) => BatchPriority::Immediate,
+            Some(NoiseBatchPriority::Normal) => BatchPriority::Normal,
+            Some(NoiseBatchPriority::Background) => BatchPriority::Background,
+            None => return NoiseErrorCode::InvalidParameter as c_int,
+        };
+        with_batch(batch, |handle| {
+            if priority == BatchPriority::Immediate && handle.callback.is_none() {
+                return crate::ffi::helpers::report_error(crate::core::error::NoiseError::InvalidState(
+                    "No flush callback set".to_string(),
+                ));
+            }
+            let (operation, result) = match operation {
+                NOISE_BATCH_ENCRYPT => (
+                    BatchOperation::Encrypt,
+                    handle.lock().queue_encrypt_with_priority(id, data.to_vec(), priority),
+                ),
+                NOISE_BATCH_DECRYPT => (
+                    BatchOperation::Decrypt,
+                    handle.lock().queue_decrypt_with_priority(id, data.to_vec(), priority),
+                ),
+                _ => return NoiseErrorCode::InvalidParameter as c_int,
+            };
+            if let (Some(result), Some((callback, context))) = (result, &handle.callback) {
+                deliver_batch(*callback, context, operation, vec![result]);
+            }
+            NoiseErrorCode::Success as c_int
+        })
+    })
+}
+
+/// Send the batch's flushed results to `callback`, once per message
+///
+/// With `background` non-zero a library thread flushes the batch once its
+/// interval passes, so queued messages are never stranded; otherwise the
+/// app drives time-based flushes with `noise_batch_poll`. `callback` must
+/// not call back into the same batch. `context` is passed back unchanged
+/// and must stay valid until the batch is freed or the callback replaced.
+/// A null `callback` removes it and stops any background flusher.
+#[cfg(feature = "batching")]
+#[no_mangle]
+pub extern "C" fn noise_batch_set_flush_callback(
+    batch: *mut NoiseBatchFFI,
+    callback: NoiseBatchFlushCallback,
+    context: *mut c_void,
+    background: c_int,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        with_batch(batch, |handle| {
+            // Stop the old flusher before its callback goes away
+            handle.flusher = None;
+            let Some(callback) = callback else {
+                handle.callback = None;
+                handle.lock().clear_flush_handler();
+                return NoiseErrorCode::Success as c_int;
+            };
+            
+            handle.callback = Some((callback, CallbackContext(context)));
+            let context = CallbackContext(context);
+            handle.lock().set_flush_handler(move |operation, results| {
+                deliver_batch(callback, &context, operation, results);
+            });
+            if background != 0 {
+                handle.flusher = Some(BatchFlusher::spawn(handle.batch.clone()));
+            }
+            NoiseErrorCode::Success as c_int
+        })
+    })
+}
+
+/// Adapt every batch to the device's power state
+///
+/// Call it from battery and thermal notifications. Applies to live batches
+/// and those created later, replacing their flush threshold and intervals;
+/// background flushers pick up the new intervals at once. `mode` is a
+/// `NOISE_POWER_MODE_*` value.
+#[cfg(feature = "batching")]
+#[no_mangle]
+pub extern "C" fn noise_set_power_mode(mode: c_int) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        let mode = match NoisePowerMode::from_c_int(mode) {
+            Some(NoisePowerMode::Low) => PowerMode::Low,
+            Some(NoisePowerMode::Balanced) => PowerMode::Balanced,
+            Some(NoisePowerMode::Performance) => PowerMode::Performance,
+            None => return NoiseErrorCode::InvalidParameter as c_int,
+        };
+        *POWER_MODE.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = mode;
+        BATCHES.for_each(|handle| {
+            handle.lock().set_power_mode(mode);
+            if let Some(flusher) = &handle.flusher {
+                flusher.wake();
+            }
+        });
+        NoiseErrorCode::Success as c_int
+    })
+}
+
+/// Flush whatever is due to the flush callback
+///
+/// `*next_flush_ms` receives the milliseconds until the next flush falls
+/// due, for scheduling a timer, or -1 with nothing queued.
+#[cfg(feature = "batching")]
+#[no_mangle]
+pub extern "C" fn noise_batch_poll(batch: *mut NoiseBatchFFI, next_flush_ms: *mut i64) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        if next_flush_ms.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        with_batch(batch, |handle| {
+            let mut batch = handle.lock();
+            batch.flush_due();
+            let next = batch.next_flush_in().map_or(-1, |delay| delay.as_millis() as i64);
+            unsafe { *next_flush_ms = next; }
+            NoiseErrorCode::Success as c_int
+        })
+    })
+}
+
+/// Flush everything queued to the flush callback now
+///
+/// Fails with `NOISE_ERROR_INVALID_STATE` if no callback is set. Messages
+/// that failed to encrypt are reported and stay queued; automatic flushes
+/// report each failure once, and only this call retries them.
+#[cfg(feature = "batching")]
+#[no_mangle]
+pub extern "C" fn noise_batch_flush(batch: *mut NoiseBatchFFI) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        with_batch(batch, |handle| {
+            let mut batch = handle.lock();
+            if !batch.has_flush_handler() {
+                return crate::ffi::helpers::report_error(crate::core::error::NoiseError::InvalidState(
+                    "No flush callback set".to_string(),
+                ));
+            }
+            batch.flush_now();
+            NoiseErrorCode::Success as c_int
+        })
+    })
+}
+
+/// Free a batch, its session and anything still queued
+#[cfg(feature = "batching")]
+#[no_mangle]
+pub extern "C" fn noise_batch_free(batch: *mut NoiseBatchFFI) {
+    crate::ffi::helpers::catch_panic((), || {
+        // Join the flusher outside the table lock, as it may be mid-callback
+        BATCHES.with(batch as u64, |handle| handle.flusher = None);
+        BATCHES.remove(batch as u64);
+    })
+}
+
+/// Generate a static keypair without creating a session
+///
+/// Both buffers must hold 32 bytes. Persist the private key in secure
+/// storage and pass it to `noise_session_new_with_key`.
+#[no_mangle]
+pub extern "C" fn noise_generate_keypair(
+    private_key: *mut c_uchar,
+    private_key_len: *mut size_t,
+    public_key: *mut c_uchar,
+    public_key_len: *mut size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        if private_key_len.is_null() || public_key_len.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        
+        let keypair = match crate::core::keys::Keypair::generate() {
+            Ok(keypair) => keypair,
+            Err(e) => return crate::ffi::helpers::report_error(e),
+        };
+        // Check both buffers before writing either
+        let fits = unsafe { *private_key_len >= KEY_LEN && *public_key_len >= KEY_LEN };
+        if !fits || private_key.is_null() || public_key.is_null() {
+            unsafe {
+                *private_key_len = KEY_LEN;
+                *public_key_len = KEY_LEN;
+            }
+            return NoiseErrorCode::BufferTooSmall as c_int;
+        }
+        
+        unsafe {
+            crate::ffi::helpers::copy_to_c_buffer(keypair.private_key(), private_key, private_key_len);
+            crate::ffi::helpers::copy_to_c_buffer(keypair.public_key(), public_key, public_key_len);
+        }
+        NoiseErrorCode::Success as c_int
+    })
+}
+
+/// Derive the public key for a 32-byte private key
+#[no_mangle]
+pub extern "C" fn noise_public_key_from_private(
+    private_key: *const c_uchar,
+    private_key_len: size_t,
+    public_key: *mut c_uchar,
+    public_key_len: *mut size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        if private_key.is_null() || public_key_len.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        
+        let private_key = unsafe { slice::from_raw_parts(private_key, private_key_len) };
+        let result = crate::core::keys::public_from_private(private_key).map(|public| public.to_vec());
+        write_output(Some(result), public_key, public_key_len)
+    })
+}
+
+/// Render a public key fingerprint as a NUL-terminated string
+///
+/// `format` is one of the `NOISE_FINGERPRINT_*` constants; `output_len`
+/// includes the NUL.
+#[no_mangle]
+pub extern "C" fn noise_key_fingerprint(
+    public_key: *const c_uchar,
+    public_key_len: size_t,
+    format: c_int,
+    output: *mut c_char,
+    output_len: *mut size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        if public_key.is_null() || output_len.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        let format = match format {
+            NOISE_FINGERPRINT_HEX => FingerprintFormat::Hex,
+            NOISE_FINGERPRINT_BASE32 => FingerprintFormat::Base32,
+            NOISE_FINGERPRINT_SHA256 => FingerprintFormat::Sha256,
+            _ => return NoiseErrorCode::InvalidParameter as c_int,
+        };
+        
+        let public_key = unsafe { slice::from_raw_parts(public_key, public_key_len) };
+        let result = crate::core::keys::fingerprint(public_key, format).map(|text| nul_terminated(&text));
+        write_output(Some(result), output as *mut c_uchar, output_len)
+    })
+}
+
+/// Encode a pairing payload for a QR code
+///
+/// `psk` may be null; otherwise it must be 32 bytes. `endpoints` points to
+/// `endpoint_count` NUL-terminated hint strings and may be null when the
+/// count is 0.
+#[cfg(feature = "identity")]
+#[no_mangle]
+pub extern "C" fn noise_pairing_payload_encode(
+    static_key: *const c_uchar,
+    static_key_len: size_t,
+    psk: *const c_uchar,
+    psk_len: size_t,
+    endpoints: *const *const c_char,
+    endpoint_count: size_t,
+    output: *mut c_uchar,
+    output_len: *mut size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        if static_key.is_null() || output_len.is_null() || (endpoints.is_null() && endpoint_count > 0) {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        
+        let static_key = unsafe { slice::from_raw_parts(static_key, static_key_len) };
+        let Ok(static_key) = static_key.try_into() else {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        };
+        let mut payload = PairingPayload::new(static_key);
+        if !psk.is_null() {
+            let psk = unsafe { slice::from_raw_parts(psk, psk_len) };
+            let Ok(psk) = psk.try_into() else {
+                return NoiseErrorCode::InvalidParameter as c_int;
+            };
+            payload.psk = Some(psk);
+        }
+        for i in 0..endpoint_count {
+            let Some(hint) = (unsafe { crate::ffi::helpers::c_to_str(*endpoints.add(i)) }) else {
+                return NoiseErrorCode::InvalidParameter as c_int;
+            };
+            payload.endpoints.push(hint.to_string());
+        }
+        
+        write_output(Some(payload.encode()), output, output_len)
+    })
+}
+
+/// Decode a scanned pairing payload
+///
+/// Writes the 32-byte static key, the PSK (`*psk_len` is set to 0 if the
+/// payload has none) and the endpoint hints, each followed by a NUL. All
+/// buffers are checked before any is written; on `NOISE_ERROR_BUFFER_TOO_SMALL`
+/// every length holds the size needed.
+#[cfg(feature = "identity")]
+#[no_mangle]
+pub extern "C" fn noise_pairing_payload_decode(
+    payload: *const c_uchar,
+    payload_len: size_t,
+    static_key: *mut c_uchar,
+    static_key_len: *mut size_t,
+    psk: *mut c_uchar,
+    psk_len: *mut size_t,
+    endpoints: *mut c_char,
+    endpoints_len: *mut size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        if payload.is_null() || static_key_len.is_null() || psk_len.is_null() || endpoints_len.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        
+        let data = unsafe { slice::from_raw_parts(payload, payload_len) };
+        let payload = match PairingPayload::decode(data) {
+            Ok(payload) => payload,
+            Err(e) => return crate::ffi::helpers::report_error(e),
+        };
+        let psk_bytes = payload.psk.as_ref().map_or(&[][..], |psk| &psk[..]);
+        let mut hints = Vec::new();
+        for hint in &payload.endpoints {
+            hints.extend_from_slice(hint.as_bytes());
+            hints.push(0);
+        }
+        
+        let outputs = [
+            (static_key, static_key_len, &payload.static_key[..]),
+            (psk, psk_len, psk_bytes),
+            (endpoints as *mut c_uchar, endpoints_len, &hints[..]),
+        ];
+        let fits = outputs.iter().all(|&(buffer, len, data)| {
+            data.is_empty() || (!buffer.is_null() && unsafe { *len } >= data.len())
+        });
+        for &(buffer, len, data) in &outputs {
+            unsafe {
+                if fits && !data.is_empty() {
+                    crate::ffi::helpers::copy_to_c_buffer(data, buffer, len);
+                } else {
+                    *len = data.len();
+                }
+            }
+        }
+        if fits {
+            NoiseErrorCode::Success as c_int
+        } else {
+            NoiseErrorCode::BufferTooSmall as c_int
+        }
+    })
+}
+
+/// Seal a one-way message to a recipient's 32-byte static key
+///
+/// With `sender_private_key` null the message is anonymous; otherwise the
+/// recipient learns and can trust the sender's static key.
+#[cfg(feature = "sealed")]
+#[no_mangle]
+pub extern "C" fn noise_seal(
+    recipient_static: *const c_uchar,
+    recipient_static_len: size_t,
+    sender_private_key: *const c_uchar,
+    sender_private_key_len: size_t,
+    plaintext: *const c_uchar,
+    plaintext_len: size_t,
+    output: *mut c_uchar,
+    output_len: *mut size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        let recipient = unsafe { crate::ffi::helpers::c_to_slice(recipient_static, recipient_static_len) };
+        let plaintext = unsafe { crate::ffi::helpers::c_to_slice(plaintext, plaintext_len) };
+        let (Some(recipient), Some(plaintext)) = (recipient, plaintext) else {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        };
+        if output_len.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        
+        let result = match unsafe { crate::ffi::helpers::c_to_slice(sender_private_key, sender_private_key_len) } {
+            None => SealedMessage::seal(recipient, plaintext),
+            Some(sender) => Keypair::from_private(sender)
+                .and_then(|sender| SealedMessage::seal_from(&sender, recipient, plaintext)),
+        };
+        write_output(Some(result), output, output_len)
+    })
+}
+
+/// Seal a one-way message into a buffer allocated by the library
+///
+/// As `noise_seal`; on success `*output` must be released with
+/// `noise_buffer_free`.
+#[cfg(feature = "sealed")]
+#[no_mangle]
+pub extern "C" fn noise_seal_alloc(
+    recipient_static: *const c_uchar,
+    recipient_static_len: size_t,
+    sender_private_key: *const c_uchar,
+    sender_private_key_len: size_t,
+    plaintext: *const c_uchar,
+    plaintext_len: size_t,
+    output: *mut NoiseBuffer,
+) -> c_int {
+    alloc_call(output, |output| {
+        let recipient = unsafe { crate::ffi::helpers::c_to_slice(recipient_static, recipient_static_len) };
+        let plaintext = unsafe { crate::ffi::helpers::c_to_slice(plaintext, plaintext_len) };
+        let (Some(recipient), Some(plaintext)) = (recipient, plaintext) else {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        };
+        
+        let result = match unsafe { crate::ffi::helpers::c_to_slice(sender_private_key, sender_private_key_len) } {
+            None => SealedMessage::seal(recipient, plaintext),
+            Some(sender) => Keypair::from_private(sender)
+                .and_then(|sender| SealedMessage::seal_from(&sender, recipient, plaintext)),
+        };
+        unsafe { write_buffer(Some(result), output) }
+    })
+}
+
+/// Open a sealed message with the recipient's private key
+///
+/// If `sender` is not null it receives the sender's 32-byte static key,
+/// with `*sender_len` set to 0 for anonymous messages.
+#[cfg(feature = "sealed")]
+#[no_mangle]
+pub extern "C" fn noise_open(
+    private_key: *const c_uchar,
+    private_key_len: size_t,
+    blob: *const c_uchar,
+    blob_len: size_t,
+    plaintext: *mut c_uchar,
+    plaintext_len: *mut size_t,
+    sender: *mut c_uchar,
+    sender_len: *mut size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        let private_key = unsafe { crate::ffi::helpers::c_to_slice(private_key, private_key_len) };
+        let blob = unsafe { crate::ffi::helpers::c_to_slice(blob, blob_len) };
+        let (Some(private_key), Some(blob)) = (private_key, blob) else {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        };
+        if plaintext_len.is_null() || (!sender.is_null() && sender_len.is_null()) {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        
+        let opened = match Keypair::from_private(private_key).and_then(|keypair| SealedMessage::open(&keypair, blob)) {
+            Ok(opened) => opened,
+            Err(e) => return crate::ffi::helpers::report_error(e),
+        };
+        
+        // Check both buffers before writing either
+        let key = opened.sender.as_ref().map_or(&[][..], |key| &key[..]);
+        let plaintext_fits = !plaintext.is_null() && unsafe { *plaintext_len } >= opened.plaintext.len();
+        let sender_fits = sender.is_null() || unsafe { *sender_len } >= key.len();
+        unsafe {
+            *plaintext_len = opened.plaintext.len();
+            if !sender.is_null() {
+                *sender_len = key.len();
+            }
+        }
+        if !plaintext_fits || !sender_fits {
+            return NoiseErrorCode::BufferTooSmall as c_int;
+        }
+        unsafe {
+            ptr::copy_nonoverlapping(opened.plaintext.as_ptr(), plaintext, opened.plaintext.len());
+            if !sender.is_null() {
+                ptr::copy_nonoverlapping(key.as_ptr(), sender, key.len());
+            }
+        }
+        NoiseErrorCode::Success as c_int
+    })
+}
+
+/// Derive the session's file cipher, run `f` with it and report the outcome
+///
+/// The session is only locked while the cipher is derived, not for the IO.
+#[cfg(feature = "file-cipher")]
+fn with_file_cipher(
+    session: *mut NoiseSessionFFI,
+    plaintext_len: *mut u64,
+    f: impl FnOnce(&SessionFileCipher) -> std::io::Result<u64>,
+) -> c_int {
+    let cipher = match SESSIONS.with(session as u64, |session| SessionFileCipher::new(session)) {
+        None => return NoiseErrorCode::InvalidParameter as c_int,
+        Some(Err(e)) => return crate::ffi::helpers::report_error(e),
+        Some(Ok(cipher)) => cipher,
+    };
+    match f(&cipher) {
+        Ok(len) => {
+            if !plaintext_len.is_null() {
+                unsafe { *plaintext_len = len };
+            }
+            NoiseErrorCode::Success as c_int
+        }
+        Err(e) => crate::ffi::helpers::report_io_error(e),
+    }
+}
+
+/// Run a file cipher from one path to another
+///
+/// The output is written to a new temporary file beside `output_path` and
+/// renamed over it only on success, so a failure leaves any existing file
+/// at `output_path` untouched.
+#[cfg(feature = "file-cipher")]
+fn file_cipher_paths(
+    session: *mut NoiseSessionFFI,
+    input_path: *const c_char,
+    output_path: *const c_char,
+    plaintext_len: *mut u64,
+    decrypt: bool,
+) -> c_int {
+    let input_path = unsafe { crate::ffi::helpers::c_to_str(input_path) };
+    let output_path = unsafe { crate::ffi::helpers::c_to_str(output_path) };
+    let (Some(input_path), Some(output_path)) = (input_path, output_path) else {
+        return NoiseErrorCode::InvalidParameter as c_int;
+    };
+    let mut suffix = [0u8; 8];
+    crate::core::crypto::random_bytes(&mut suffix);
+    let temp_path = format!("{output_path}.{:016x}.tmp", u64::from_le_bytes(suffix));
+    let mut created = false;
+    let status = with_file_cipher(session, plaintext_len, |cipher| {
+        let input = std::io::BufReader::new(std::fs::File::open(input_path)?);
+        let output = std::fs::OpenOptions::new().write(true).create_new(true).open(&temp_path)?;
+        created = true;
+        let output = std::io::BufWriter::new(output);
+        let len = if decrypt {
+            cipher.decrypt(input, output)
+        } else {
+            cipher.encrypt(input, output)
+        }?;
+        std::fs::rename(&temp_path, output_path)?;
+        created = false;
+        Ok(len)
+    });
+    if created {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    status
+}
+
+/// Run a file cipher between borrowed file descriptors
+#[cfg(all(unix, feature = "file-cipher"))]
+fn file_cipher_fds(
+    session: *mut NoiseSessionFFI,
+    input_fd: c_int,
+    output_fd: c_int,
+    plaintext_len: *mut u64,
+    decrypt: bool,
+) -> c_int {
+    use std::os::unix::io::FromRawFd;
+    if input_fd < 0 || output_fd < 0 {
+        return NoiseErrorCode::InvalidParameter as c_int;
+    }
+    // The caller keeps ownership, so the files must not close on drop
+    let input = std::mem::ManuallyDrop::new(unsafe { std::fs::File::from_raw_fd(input_fd) });
+    let output = std::mem::ManuallyDrop::new(unsafe { std::fs::File::from_raw_fd(output_fd) });
+    with_file_cipher(session, plaintext_len, |cipher| {
+        let input = std::io::BufReader::new(&*input);
+        let output = std::io::BufWriter::new(&*output);
+        if decrypt {
+            cipher.decrypt(input, output)
+        } else {
+            cipher.encrypt(input, output)
+        }
+    })
+}
+
+/// Encrypt the file at `input_path` into `output_path` under the session's
+/// file key
+///
+/// Both ends of a session derive the same key, so the peer can decrypt the
+/// file with [`noise_file_decrypt`]; files never use transport nonces.
+/// If `plaintext_len` is not null it receives the bytes encrypted. The
+/// output only appears at `output_path` once complete; on failure any file
+/// already there is left as it was.
+#[cfg(feature = "file-cipher")]
+#[no_mangle]
+pub extern "C" fn noise_file_encrypt(
+    session: *mut NoiseSessionFFI,
+    input_path: *const c_char,
+    output_path: *const c_char,
+    plaintext_len: *mut u64,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| file_cipher_paths(session, input_path, output_path, plaintext_len, false))
+}
+
+/// Decrypt a file from [`noise_file_encrypt`]
+///
+/// Fails with `NOISE_ERROR_DECRYPTION_FAILED` or
+/// `NOISE_ERROR_PROTOCOL_ERROR` if the file was altered or cut short, in
+/// which case no output is written.
+#[cfg(feature = "file-cipher")]
+#[no_mangle]
+pub extern "C" fn noise_file_decrypt(
+    session: *mut NoiseSessionFFI,
+    input_path: *const c_char,
+    output_path: *const c_char,
+    plaintext_len: *mut u64,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| file_cipher_paths(session, input_path, output_path, plaintext_len, true))
+}
+
+/// Like [`noise_file_encrypt`] between open file descriptors
+///
+/// Reads `input_fd` from its current offset to the end and writes to
+/// `output_fd`. The descriptors stay open and owned by the caller.
+#[cfg(all(unix, feature = "file-cipher"))]
+#[no_mangle]
+pub extern "C" fn noise_file_encrypt_fd(
+    session: *mut NoiseSessionFFI,
+    input_fd: c_int,
+    output_fd: c_int,
+    plaintext_len: *mut u64,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| file_cipher_fds(session, input_fd, output_fd, plaintext_len, false))
+}
+
+/// Like [`noise_file_decrypt`] between open file descriptors
+///
+/// On failure `output_fd` may hold part of the file, which must be
+/// discarded. The descriptors stay open and owned by the caller.
+#[cfg(all(unix, feature = "file-cipher"))]
+#[no_mangle]
+pub extern "C" fn noise_file_decrypt_fd(
+    session: *mut NoiseSessionFFI,
+    input_fd: c_int,
+    output_fd: c_int,
+    plaintext_len: *mut u64,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| file_cipher_fds(session, input_fd, output_fd, plaintext_len, true))
+}
+
+/// Get the maximum message length
+#[no_mangle]
+pub extern "C" fn noise_max_message_len() -> size_t {
+    crate::ffi::helpers::catch_panic(0, || {
+        crate::core::crypto::NOISE_MAX_MESSAGE_LEN
+    })
+}
+
+/// Get the ciphertext length `noise_encrypt` will produce for a plaintext
+/// of `plaintext_len` bytes, accounting for the session's padding
+#[no_mangle]
+pub extern "C" fn noise_encrypt_size_hint(
+    session: *mut NoiseSessionFFI,
+    plaintext_len: size_t,
+    ciphertext_len: *mut size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        if ciphertext_len.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        
+        with_session(session, |session| {
+            unsafe { *ciphertext_len = session.encrypt_size(plaintext_len) };
+            NoiseErrorCode::Success as c_int
+        })
+    })
+}
+
+/// Get the plaintext buffer length `noise_decrypt` needs for a ciphertext
+/// of `ciphertext_len` bytes
+#[no_mangle]
+pub extern "C" fn noise_decrypt_size_hint(
+    session: *mut NoiseSessionFFI,
+    ciphertext_len: size_t,
+    plaintext_len: *mut size_t,
+) -> c_int {
+    crate::ffi::helpers::catch_status(|| {
+        if plaintext_len.is_null() {
+            return NoiseErrorCode::InvalidParameter as c_int;
+        }
+        
+        with_session(session, |session| {
+            unsafe { *plaintext_len = session.decrypt_size(ciphertext_len) };
+            NoiseErrorCode::Success as c_int
+        })
+    })
+}
+
+/// Get the maximum payload length
+#[no_mangle]
+pub extern "C" fn noise_max_payload_len() -> size_t {
+    crate::ffi::helpers::catch_panic(0, || {
+        crate::core::crypto::NOISE_MAX_PAYLOAD_LEN
+    })
+}
+
+/// Get the length of the authentication tag on every transport message
+#[no_mangle]
+pub extern "C" fn noise_tag_len() -> size_t {
+    crate::ffi::helpers::catch_panic(0, || crate::core::crypto::NOISE_TAG_LEN)
+}
+
+/// Get the number of handshake messages `pattern` takes
+///
+/// `pattern` is a handshake pattern such as `"XX"` or `"IKpsk2"`, run with
+/// this library's default suite, or a full protocol name such as
+/// `"Noise_XX_25519_AESGCM_SHA256"`. Returns 0 if it is not understood.
+#[no_mangle]
+pub extern "C" fn noise_handshake_message_count(pattern: *const c_char) -> size_t {
+    crate::ffi::helpers::catch_panic(0, || {
+        handshake_params(pattern).map_or(0, |params| crate::core::crypto::handshake_message_count(params.handshake.pattern))
+    })
+}
+
+/// Get the length of handshake message `index` (from 0) of `pattern`
+/// carrying an empty payload
+///
+/// A payload adds exactly its own length, so a buffer of this size plus the
+/// payload length always fits the message. `pattern` is read as by
+/// `noise_handshake_message_count`. Returns 0 if it is not understood or
+/// `index` is past the last message.
+#[no_mangle]
+pub extern "C" fn noise_handshake_message_max_len(pattern: *const c_char, index: size_t) -> size_t {
+    crate::ffi::helpers::catch_panic(0, || {
+        handshake_params(pattern)
+            .and_then(|params| crate::core::crypto::handshake_message_lens(&params).ok())
+            .and_then(|lens| lens.get(index).copied())
+            .unwrap_or(0)
+    })
+}
+
+/// Parse a bare handshake pattern, on the default suite, or a full protocol name
+fn handshake_params(pattern: *const c_char) -> Option<snow::params::NoiseParams> {
+    let pattern = unsafe { crate::ffi::helpers::c_to_str(pattern) }?;
+    if pattern.starts_with("Noise_") {
+        return pattern.parse().ok();
+    }
+    let mut descriptor = crate::core::protocols::ProtocolDescriptor::parse(NoiseSession::NOISE_PARAMS)?;
+    descriptor.pattern = pattern.to_string();
+    descriptor.name().parse().ok()
+}
+
+/// Get error string for an error code
+#[no_mangle]
+pub extern "C" fn noise_error_string(error: c_int) -> *const c_char {
+    crate::ffi::helpers::catch_panic(b"Internal error\0".as_ptr() as *const c_char, || {
+        crate::ffi::helpers::error_description(error).as_ptr()
+    })
+}
+
+/// Get the code of the last error on the calling thread
+///
+/// Every function that reports an error code resets this on entry, so it
+/// describes the most recent such call. `NOISE_ERROR_SUCCESS` if it succeeded.
+#[no_mangle]
+pub extern "C" fn noise_last_error_code() -> c_int {
+    crate::ffi::helpers::catch_panic(NoiseErrorCode::Internal as c_int, crate::ffi::helpers::last_error_code)
+}
+
+/// Get a description of the last error on the calling thread
+///
+/// Unlike `noise_error_string` this carries the underlying detail, e.g. which
+/// Noise check failed. Returns NULL if the last call succeeded. The string is
+/// owned by the library and valid until the next call on this thread.
+#[no_mangle]
+pub extern "C" fn noise_last_error_message() -> *const c_char {
+    crate::ffi::helpers::catch_panic(ptr::null(), crate::ffi::helpers::last_error_message)
+}
@@ -0,0 +1,109 @@
+//! Session event callbacks set with `noise_session_set_callbacks`
+//!
+//! Events are worked out by comparing a session before and after each call
+//! and raised once the session's lock is released, so a callback can call
+//! straight back into the library.
+
+use crate::core::session::NoiseSession;
+use crate::ffi::types::{NoiseErrorCode, NoiseSessionCallbacks};
+use libc::c_int;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// `direction` passed to `rekeyed` when sending keys changed
+pub const NOISE_REKEY_OUTGOING: c_int = 0;
+/// `direction` passed to `rekeyed` when receiving keys changed
+pub const NOISE_REKEY_INCOMING: c_int = 1;
+
+/// Callbacks by session handle
+static CALLBACKS: Mutex<BTreeMap<u64, RegisteredCallbacks>> = Mutex::new(BTreeMap::new());
+
+#[derive(Clone, Copy)]
+struct RegisteredCallbacks(NoiseSessionCallbacks);
+
+// The caller guarantees callbacks and context are usable from any thread
+// that calls in
+unsafe impl Send for RegisteredCallbacks {}
+
+/// What a call may change about a session that callbacks hear about
+#[derive(Clone, Copy)]
+pub(crate) struct SessionMarks {
+    transport: bool,
+    sending_generation: u32,
+    receiving_generation: u32,
+}
+
+impl SessionMarks {
+    pub(crate) fn of(session: &NoiseSession) -> Self {
+        Self {
+            transport: session.is_transport_state(),
+            sending_generation: session.sending_generation(),
+            receiving_generation: session.receiving_generation(),
+        }
+    }
+}
+
+/// Set or, with `None`, clear the callbacks for a session handle
+pub(crate) fn set_callbacks(handle: u64, callbacks: Option<NoiseSessionCallbacks>) {
+    let mut table = lock();
+    match callbacks {
+        Some(callbacks) => table.insert(handle, RegisteredCallbacks(callbacks)),
+        None => table.remove(&handle),
+    };
+}
+
+/// Raise the events a call on `handle` caused
+///
+/// `BufferTooSmall` is part of normal buffer sizing and is not an error.
+pub(crate) fn dispatch(handle: u64, before: SessionMarks, after: SessionMarks, status: c_int) {
+    let Some(RegisteredCallbacks(callbacks)) = get(handle) else {
+        return;
+    };
+    unsafe {
+        if !before.transport && after.transport {
+            if let Some(handshake_complete) = callbacks.handshake_complete {
+                handshake_complete(callbacks.context);
+            }
+        }
+        if let Some(rekeyed) = callbacks.rekeyed {
+            if after.sending_generation != before.sending_generation {
+                rekeyed(callbacks.context, NOISE_REKEY_OUTGOING);
+            }
+            if after.receiving_generation != before.receiving_generation {
+                rekeyed(callbacks.context, NOISE_REKEY_INCOMING);
+            }
+        }
+        if status != NoiseErrorCode::Success as c_int && status != NoiseErrorCode::BufferTooSmall as c_int {
+            if let Some(error) = callbacks.error {
+                error(callbacks.context, status);
+            }
+        }
+    }
+}
+
+/// Hand a decrypted message to the session's callback
+pub(crate) fn message_decrypted(handle: u64, plaintext: &[u8]) {
+    if let Some(RegisteredCallbacks(callbacks)) = get(handle) {
+        if let Some(message_decrypted) = callbacks.message_decrypted {
+            unsafe { message_decrypted(callbacks.context, plaintext.as_ptr(), plaintext.len()) };
+        }
+    }
+}
+
+/// Drop a freed handle's callbacks, raising `closed`
+pub(crate) fn closed(handle: u64) {
+    let removed = lock().remove(&handle);
+    if let Some(RegisteredCallbacks(callbacks)) = removed {
+        if let Some(closed) = callbacks.closed {
+            unsafe { closed(callbacks.context) };
+        }
+    }
+}
+
+fn get(handle: u64) -> Option<RegisteredCallbacks> {
+    lock().get(&handle).copied()
+}
+
+fn lock() -> std::sync::MutexGuard<'static, BTreeMap<u64, RegisteredCallbacks>> {
+    CALLBACKS.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
@@ -0,0 +1,161 @@
+//! Integer handles for objects owned on the Rust side of the FFI
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Handle value that never refers to an object
+pub const NOISE_INVALID_HANDLE: u64 = 0;
+
+/// Registry mapping opaque integer handles to owned objects
+///
+/// Handles are never reused, so a stale or freed handle fails lookup
+/// instead of reaching another object. Each object has its own lock: calls
+/// on one handle from several threads run one at a time, while calls on
+/// different handles do not wait for each other.
+pub(crate) struct HandleTable<T> {
+    next: AtomicU64,
+    entries: Mutex<BTreeMap<u64, Arc<Mutex<T>>>>,
+}
+
+impl<T> HandleTable<T> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            next: AtomicU64::new(1),
+            entries: Mutex::new(BTreeMap::new()),
+        }
+    }
+    
+    /// Take ownership of `value` and return its handle
+    #[cfg(any(test, feature = "manager"))]
+    pub(crate) fn insert(&self, value: T) -> u64 {
+        let handle = self.next.fetch_add(1, Ordering::Relaxed);
+        self.lock().insert(handle, Arc::new(Mutex::new(value)));
+        handle
+    }
+    
+    /// Take ownership of `value` and return its handle as an opaque pointer
+    ///
+    /// Returns null, dropping `value`, once every handle that fits in a
+    /// pointer has been issued, which only a 32-bit target can reach.
+    pub(crate) fn insert_ptr<P>(&self, value: T) -> *mut P {
+        let issued = self.next.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |next| {
+            usize::try_from(next).ok().and(next.checked_add(1))
+        });
+        let Ok(handle) = issued else {
+            return std::ptr::null_mut();
+        };
+        self.lock().insert(handle, Arc::new(Mutex::new(value)));
+        handle as usize as *mut P
+    }
+    
+    /// Run `f` on the object behind `handle`, if it exists
+    ///
+    /// Waits for any other call on the same handle to finish first. A handle
+    /// freed while `f` runs stays usable until `f` returns.
+    pub(crate) fn with<R>(&self, handle: u64, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        let entry = self.lock().get(&handle).cloned()?;
+        let mut value = entry.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        Some(f(&mut value))
+    }
+    
+    /// Remove the object behind `handle`, invalidating the handle
+    ///
+    /// Returns `false` if the handle was not live.
+    pub(crate) fn remove(&self, handle: u64) -> bool {
+        self.lock().remove(&handle).is_some()
+    }
+    
+    /// Remove the object behind `handle` and hand it back
+    ///
+    /// Returns `None`, leaving the handle live, if it is unknown or a call on
+    /// it is still running.
+    pub(crate) fn take(&self, handle: u64) -> Option<T> {
+        let mut entries = self.lock();
+        if Arc::strong_count(entries.get(&handle)?) > 1 {
+            return None;
+        }
+        let entry = Arc::try_unwrap(entries.remove(&handle)?).ok()?;
+        Some(entry.into_inner().unwrap_or_else(|poisoned| poisoned.into_inner()))
+    }
+    
+    /// Run `f` on every live object in turn
+    ///
+    /// Each object is locked only while `f` runs on it, so calls on other
+    /// handles are not held up.
+    #[cfg(any(test, feature = "batching"))]
+    pub(crate) fn for_each(&self, mut f: impl FnMut(&mut T)) {
+        let entries: Vec<_> = self.lock().values().cloned().collect();
+        for entry in entries {
+            f(&mut entry.lock().unwrap_or_else(|poisoned| poisoned.into_inner()));
+        }
+    }
+    
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<u64, Arc<Mutex<T>>>> {
+        // Keep serving other handles after a panic inside `with`
+        self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_handles_are_not_reused() {
+        let table = HandleTable::new();
+        let first = table.insert("a");
+        assert_ne!(first, NOISE_INVALID_HANDLE);
+        assert!(table.remove(first));
+        assert!(!table.remove(first));
+        
+        let second = table.insert("b");
+        assert_ne!(first, second);
+        assert!(table.with(first, |_| ()).is_none());
+        assert_eq!(table.with(second, |v| *v), Some("b"));
+        let mut seen = Vec::new();
+        table.for_each(|v| seen.push(*v));
+        assert_eq!(seen, ["b"]);
+        assert_eq!(table.take(second), Some("b"));
+        assert!(table.with(second, |_| ()).is_none());
+    }
+    
+    #[test]
+    fn test_pointer_handles_fit() {
+        let table = HandleTable::new();
+        let handle: *mut u8 = table.insert_ptr("a");
+        assert_eq!(table.with(handle as u64, |v| *v), Some("a"));
+        
+        // Handles past the pointer width are never issued
+        table.next.store(u64::MAX, Ordering::Relaxed);
+        assert!(table.insert_ptr::<u8>("b").is_null());
+        let mut count = 0;
+        table.for_each(|_| count += 1);
+        assert_eq!(count, 1);
+    }
+    
+    #[test]
+    fn test_concurrent_calls_on_one_handle() {
+        let table = Arc::new(HandleTable::new());
+        let handle = table.insert(0u64);
+        
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let table = Arc::clone(&table);
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        table.with(handle, |v| {
+                            let read = *v;
+                            std::thread::yield_now();
+                            *v = read + 1;
+                        });
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(table.with(handle, |v| *v), Some(8000));
+    }
+}
@@ -1,6 +1,12 @@
 //! Helper functions for safe FFI operations
 
-use libc::{c_uchar, size_t};
+use crate::core::error::NoiseError;
+use crate::ffi::types::NoiseErrorCode;
+use libc::{c_char, c_int, c_uchar, size_t};
+use std::any::Any;
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
 use std::ptr;
 use std::slice;
 
@@ -49,15 +55,187 @@ pub unsafe fn copy_to_c_buffer(
     true
 }
 
-/// Validate that a session pointer is not null and properly aligned
-pub fn validate_session_ptr(ptr: *mut crate::ffi::types::NoiseSessionFFI) -> bool {
-    !ptr.is_null() && (ptr as usize) % std::mem::align_of::<crate::core::session::NoiseSession>() == 0
+/// Borrow a NUL-terminated UTF-8 C string
+/// Returns None if the pointer is null or the string is not valid UTF-8
+pub unsafe fn c_to_str<'a>(ptr: *const c_char) -> Option<&'a str> {
+    if ptr.is_null() {
+        None
+    } else {
+        CStr::from_ptr(ptr).to_str().ok()
+    }
+}
+
+thread_local! {
+    /// Most recent failure on this thread, read by `noise_last_error_*`
+    static LAST_ERROR: RefCell<Option<(c_int, CString)>> = const { RefCell::new(None) };
+}
+
+/// NUL-terminated description of a `NOISE_ERROR_*` code
+pub(crate) fn error_description(code: c_int) -> &'static CStr {
+    let text: &'static [u8] = match code {
+        0 => b"Success\0",
+        1 => b"Invalid parameter\0",
+        2 => b"Out of memory\0",
+        3 => b"Handshake failed\0",
+        4 => b"Encryption failed\0",
+        5 => b"Decryption failed\0",
+        6 => b"Buffer too small\0",
+        7 => b"Invalid state\0",
+        8 => b"Protocol error\0",
+        9 => b"Nonce exhausted\0",
+        10 => b"Internal error\0",
+        11 => b"Peer key mismatch\0",
+        12 => b"Invalid key\0",
+        _ => b"Unknown error\0",
+    };
+    CStr::from_bytes_with_nul(text).unwrap_or_default()
+}
+
+/// Record a failure as this thread's last error
+pub(crate) fn set_last_error(code: c_int, message: &str) {
+    // Interior NULs would truncate the message on the C side
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some((code, message)));
+}
+
+/// Forget this thread's last error
+pub(crate) fn clear_last_error() {
+    LAST_ERROR.with(|last| *last.borrow_mut() = None);
+}
+
+/// Code of this thread's last error, `NOISE_ERROR_SUCCESS` if there is none
+pub(crate) fn last_error_code() -> c_int {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(NoiseErrorCode::Success as c_int, |(code, _)| *code))
+}
+
+/// Message of this thread's last error, or null if there is none
+///
+/// The string stays valid until the next call on this thread that resets
+/// or records an error.
+pub(crate) fn last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |(_, message)| message.as_ptr()))
+}
+
+/// Convert a library error to its code, keeping the full message as the
+/// thread's last error
+pub(crate) fn report_error(error: NoiseError) -> c_int {
+    let message = error.to_string();
+    let code = NoiseErrorCode::from(error) as c_int;
+    set_last_error(code, &message);
+    code
+}
+
+/// Like [`report_error`] for IO errors, which may wrap a library error
+#[cfg(feature = "file-cipher")]
+pub(crate) fn report_io_error(error: std::io::Error) -> c_int {
+    let message = error.to_string();
+    match error.into_inner().map(|inner| inner.downcast::<NoiseError>()) {
+        Some(Ok(inner)) => report_error(*inner),
+        _ => {
+            let code = NoiseErrorCode::Internal as c_int;
+            set_last_error(code, &message);
+            code
+        }
+    }
+}
+
+/// Record a caught panic as the thread's last error
+fn report_panic(payload: Box<dyn Any + Send>) {
+    let detail = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic");
+    set_last_error(NoiseErrorCode::Internal as c_int, &format!("Internal error: {}", detail));
+}
+
+/// Record `code` as the last error if it is a failure the body did not
+/// already describe
+fn report_status(code: c_int) {
+    if code != NoiseErrorCode::Success as c_int && last_error_code() == NoiseErrorCode::Success as c_int {
+        set_last_error(code, &error_description(code).to_string_lossy());
+    }
+}
+
+/// Run an FFI function body, returning `default` if it panics
+///
+/// Unwinding out of an `extern "C"` function into Swift or Kotlin is
+/// undefined behavior, so every exported function goes through this.
+pub fn catch_panic<R>(default: R, body: impl FnOnce() -> R) -> R {
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|payload| {
+        report_panic(payload);
+        default
+    })
+}
+
+/// Like [`catch_panic`] for functions returning a `NOISE_ERROR_*` code
+///
+/// Resets the thread's last error on entry and records the returned code
+/// if the call fails.
+pub(crate) fn catch_status(body: impl FnOnce() -> c_int) -> c_int {
+    clear_last_error();
+    let code = catch_panic(NoiseErrorCode::Internal as c_int, body);
+    report_status(code);
+    code
+}
+
+/// Like [`catch_panic`], also reporting `NOISE_ERROR_INTERNAL` through
+/// the function's `error` out-parameter
+///
+/// The thread's last error is reset and updated as for [`catch_status`].
+pub(crate) fn catch_panic_with_error<R>(error: *mut c_int, default: R, body: impl FnOnce() -> R) -> R {
+    clear_last_error();
+    let result = panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|payload| {
+        report_panic(payload);
+        if !error.is_null() {
+            unsafe { *error = NoiseErrorCode::Internal as c_int; }
+        }
+        default
+    });
+    if !error.is_null() {
+        report_status(unsafe { *error });
+    }
+    result
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     
+    #[test]
+    fn test_catch_panic() {
+        assert_eq!(catch_panic(-1, || 7), 7);
+        assert_eq!(catch_panic(-1, || panic!("boom")), -1);
+        
+        let mut error: c_int = 0;
+        let result = catch_panic_with_error(&mut error, ptr::null_mut::<u8>(), || panic!("boom"));
+        assert!(result.is_null());
+        assert_eq!(error, NoiseErrorCode::Internal as c_int);
+    }
+    
+    #[test]
+    fn test_last_error() {
+        assert_eq!(catch_status(|| report_error(NoiseError::InvalidState("Handshake not complete".to_string()))), 7);
+        assert_eq!(last_error_code(), 7);
+        let message = unsafe { CStr::from_ptr(last_error_message()) };
+        assert_eq!(message.to_str().unwrap(), "Invalid state: Handshake not complete");
+        
+        // Failures without a detailed message fall back to the code description
+        catch_status(|| NoiseErrorCode::BufferTooSmall as c_int);
+        let message = unsafe { CStr::from_ptr(last_error_message()) };
+        assert_eq!(message.to_str().unwrap(), "Buffer too small");
+        
+        catch_status(|| panic!("boom"));
+        let message = unsafe { CStr::from_ptr(last_error_message()) };
+        assert_eq!(message.to_str().unwrap(), "Internal error: boom");
+        
+        // Errors are per thread and reset by the next call
+        std::thread::spawn(|| assert!(last_error_message().is_null())).join().unwrap();
+        catch_status(|| NoiseErrorCode::Success as c_int);
+        assert_eq!(last_error_code(), NoiseErrorCode::Success as c_int);
+        assert!(last_error_message().is_null());
+    }
+    
     #[test]
     fn test_c_to_slice_null() {
         unsafe {
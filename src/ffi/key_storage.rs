@@ -0,0 +1,295 @@
+//! [`KeyStorage`] backed by callbacks supplied over the C API
+//!
+//! Lets apps keep keys in the iOS Keychain or Android Keystore using code
+//! written in Swift or Kotlin, installed with `noise_set_key_storage`.
+
+use crate::core::error::{NoiseError, Result};
+use crate::core::keys::SecretKey;
+use crate::ffi::helpers::error_description;
+use crate::ffi::types::{NoiseErrorCode, NoiseKeyStorageVTable};
+use crate::mobile::storage::KeyStorage;
+use libc::{c_int, size_t};
+use std::ffi::CString;
+use std::sync::{Arc, RwLock};
+use zeroize::Zeroizing;
+
+/// `kind` passed to callbacks for identity keys
+pub const NOISE_STORAGE_IDENTITY: c_int = 0;
+/// `kind` passed to callbacks for session data
+pub const NOISE_STORAGE_SESSION: c_int = 1;
+
+/// First buffer offered to `load`; keys fit, larger entries are retried
+const INITIAL_LOAD_LEN: usize = 256;
+
+/// Largest entry a callback may return; asking for more fails the load
+const MAX_LOAD_LEN: usize = 1 << 20;
+
+/// Storage installed by `noise_set_key_storage`
+static INSTALLED: RwLock<Option<Arc<CallbackKeyStorage>>> = RwLock::new(None);
+
+/// A [`KeyStorage`] that forwards to platform callbacks
+pub struct CallbackKeyStorage {
+    vtable: NoiseKeyStorageVTable,
+}
+
+// The vtable contract requires callbacks to accept calls from any thread
+unsafe impl Send for CallbackKeyStorage {}
+unsafe impl Sync for CallbackKeyStorage {}
+
+impl CallbackKeyStorage {
+    /// Wrap a vtable, taking over its `context`
+    ///
+    /// Fails if `store`, `load` or `delete` is missing.
+    ///
+    /// # Safety
+    ///
+    /// The callbacks must be safe to call with the vtable's `context` from
+    /// any thread until `release` is called.
+    pub unsafe fn new(vtable: NoiseKeyStorageVTable) -> Result<Self> {
+        if vtable.store.is_none() || vtable.load.is_none() || vtable.delete.is_none() {
+            return Err(NoiseError::InvalidParameter);
+        }
+        Ok(Self { vtable })
+    }
+    
+    fn store(&self, kind: c_int, id: &str, data: &[u8]) -> Result<()> {
+        let id = c_id(id)?;
+        let store = self.vtable.store.ok_or(NoiseError::InvalidParameter)?;
+        check(unsafe { store(self.vtable.context, kind, id.as_ptr(), data.as_ptr(), data.len()) })
+    }
+    
+    fn load(&self, kind: c_int, id: &str) -> Result<Zeroizing<Vec<u8>>> {
+        let id = c_id(id)?;
+        let load = self.vtable.load.ok_or(NoiseError::InvalidParameter)?;
+        self.read_sized(|output, output_len| unsafe {
+            load(self.vtable.context, kind, id.as_ptr(), output, output_len)
+        })
+    }
+    
+    fn delete(&self, kind: c_int, id: &str) -> Result<()> {
+        let id = c_id(id)?;
+        let delete = self.vtable.delete.ok_or(NoiseError::InvalidParameter)?;
+        check(unsafe { delete(self.vtable.context, kind, id.as_ptr()) })
+    }
+    
+    /// Call a buffer-filling callback, growing the buffer if it asks for more
+    ///
+    /// Buffers are wiped when dropped, including the ones outgrown on retry.
+    fn read_sized(&self, mut fill: impl FnMut(*mut u8, *mut size_t) -> c_int) -> Result<Zeroizing<Vec<u8>>> {
+        let mut buffer = Zeroizing::new(vec![0u8; INITIAL_LOAD_LEN]);
+        loop {
+            let mut len = buffer.len();
+            let code = fill(buffer.as_mut_ptr(), &mut len);
+            if code == NoiseErrorCode::BufferTooSmall as c_int && len > buffer.len() {
+                if len > MAX_LOAD_LEN {
+                    return Err(NoiseError::InvalidState("Key storage entry too large".to_string()));
+                }
+                buffer = Zeroizing::new(vec![0u8; len]);
+                continue;
+            }
+            check(code)?;
+            if len > buffer.len() {
+                return Err(NoiseError::InvalidState("Key storage callback overran its buffer".to_string()));
+            }
+            buffer.truncate(len);
+            return Ok(buffer);
+        }
+    }
+}
+
+impl Drop for CallbackKeyStorage {
+    fn drop(&mut self) {
+        if let Some(release) = self.vtable.release {
+            unsafe { release(self.vtable.context) }
+        }
+    }
+}
+
+impl KeyStorage for CallbackKeyStorage {
+    fn store_identity(&self, key: &SecretKey, id: &str) -> Result<()> {
+        self.store(NOISE_STORAGE_IDENTITY, id, key.as_bytes())
+    }
+    
+    fn load_identity(&self, id: &str) -> Result<SecretKey> {
+        SecretKey::from_bytes(&self.load(NOISE_STORAGE_IDENTITY, id)?)
+    }
+    
+    fn delete_identity(&self, id: &str) -> Result<()> {
+        self.delete(NOISE_STORAGE_IDENTITY, id)
+    }
+    
+    fn list_identities(&self) -> Result<Vec<String>> {
+        let list = self.vtable.list
+            .ok_or_else(|| NoiseError::InvalidState("Key storage cannot list identities".to_string()))?;
+        let names = self.read_sized(|output, output_len| unsafe {
+            list(self.vtable.context, NOISE_STORAGE_IDENTITY, output, output_len)
+        })?;
+        names
+            .split(|&b| b == 0)
+            .filter(|name| !name.is_empty())
+            .map(|name| String::from_utf8(name.to_vec()).map_err(|_| NoiseError::InvalidMessage))
+            .collect()
+    }
+    
+    fn has_identity(&self, id: &str) -> Result<bool> {
+        match self.load_identity(id) {
+            Ok(_) => Ok(true),
+            Err(NoiseError::InvalidParameter) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+    
+    fn store_session(&self, session_id: &str, session_data: &[u8]) -> Result<()> {
+        self.store(NOISE_STORAGE_SESSION, session_id, session_data)
+    }
+    
+    fn load_session(&self, session_id: &str) -> Result<Vec<u8>> {
+        self.load(NOISE_STORAGE_SESSION, session_id).map(|mut data| std::mem::take(&mut *data))
+    }
+    
+    fn delete_session(&self, session_id: &str) -> Result<()> {
+        self.delete(NOISE_STORAGE_SESSION, session_id)
+    }
+}
+
+/// The storage installed through the C API, if any
+pub fn installed_key_storage() -> Option<Arc<dyn KeyStorage>> {
+    let installed = INSTALLED.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+    installed.clone().map(|storage| storage as Arc<dyn KeyStorage>)
+}
+
+/// Replace the installed storage; `None` uninstalls it
+///
+/// The previous storage is released once no call is still using it.
+pub(crate) fn install_key_storage(storage: Option<CallbackKeyStorage>) {
+    let mut installed = INSTALLED.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+    *installed = storage.map(Arc::new);
+}
+
+fn c_id(id: &str) -> Result<CString> {
+    CString::new(id).map_err(|_| NoiseError::InvalidParameter)
+}
+
+/// Map a callback's return code to a library error
+fn check(code: c_int) -> Result<()> {
+    match code {
+        0 => Ok(()),
+        1 => Err(NoiseError::InvalidParameter),
+        2 => Err(NoiseError::OutOfMemory),
+        _ => Err(NoiseError::InvalidState(format!(
+            "Key storage callback failed: {}",
+            error_description(code).to_string_lossy()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libc::{c_char, c_uchar, c_void};
+    use std::collections::HashMap;
+    use std::ffi::CStr;
+    use std::sync::Mutex;
+    
+    type Entries = Mutex<HashMap<(c_int, String), Vec<u8>>>;
+    
+    unsafe fn entries<'a>(context: *mut c_void) -> &'a Entries {
+        &*(context as *const Entries)
+    }
+    
+    unsafe fn key(kind: c_int, id: *const c_char) -> (c_int, String) {
+        (kind, CStr::from_ptr(id).to_str().unwrap().to_string())
+    }
+    
+    unsafe fn copy_out(data: &[u8], output: *mut c_uchar, output_len: *mut size_t) -> c_int {
+        if crate::ffi::helpers::copy_to_c_buffer(data, output, output_len) {
+            0
+        } else {
+            NoiseErrorCode::BufferTooSmall as c_int
+        }
+    }
+    
+    unsafe extern "C" fn store(context: *mut c_void, kind: c_int, id: *const c_char, data: *const c_uchar, len: size_t) -> c_int {
+        let data = std::slice::from_raw_parts(data, len).to_vec();
+        entries(context).lock().unwrap().insert(key(kind, id), data);
+        0
+    }
+    
+    unsafe extern "C" fn load(context: *mut c_void, kind: c_int, id: *const c_char, output: *mut c_uchar, output_len: *mut size_t) -> c_int {
+        match entries(context).lock().unwrap().get(&key(kind, id)) {
+            Some(data) => copy_out(data, output, output_len),
+            None => NoiseErrorCode::InvalidParameter as c_int,
+        }
+    }
+    
+    unsafe extern "C" fn delete(context: *mut c_void, kind: c_int, id: *const c_char) -> c_int {
+        entries(context).lock().unwrap().remove(&key(kind, id));
+        0
+    }
+    
+    unsafe extern "C" fn list(context: *mut c_void, kind: c_int, output: *mut c_uchar, output_len: *mut size_t) -> c_int {
+        let mut names = Vec::new();
+        for (entry_kind, id) in entries(context).lock().unwrap().keys() {
+            if *entry_kind == kind {
+                names.extend_from_slice(id.as_bytes());
+                names.push(0);
+            }
+        }
+        copy_out(&names, output, output_len)
+    }
+    
+    unsafe extern "C" fn release(context: *mut c_void) {
+        drop(Box::from_raw(context as *mut Entries));
+    }
+    
+    fn callback_storage() -> CallbackKeyStorage {
+        let context = Box::into_raw(Box::new(Entries::default())) as *mut c_void;
+        unsafe {
+            CallbackKeyStorage::new(NoiseKeyStorageVTable {
+                context,
+                store: Some(store),
+                load: Some(load),
+                delete: Some(delete),
+                list: Some(list),
+                release: Some(release),
+            })
+            .unwrap()
+        }
+    }
+    
+    #[test]
+    fn test_callback_storage() {
+        let storage = callback_storage();
+        let key = SecretKey::from_bytes(&[7u8; 32]).unwrap();
+        
+        assert!(!storage.has_identity("device").unwrap());
+        storage.store_identity(&key, "device").unwrap();
+        assert!(storage.has_identity("device").unwrap());
+        assert_eq!(storage.load_identity("device").unwrap().as_bytes(), key.as_bytes());
+        assert_eq!(storage.list_identities().unwrap(), vec!["device".to_string()]);
+        
+        // Larger than the first load buffer
+        let session = vec![3u8; 1000];
+        storage.store_session("device", &session).unwrap();
+        assert_eq!(storage.load_session("device").unwrap(), session);
+        storage.store_session("huge", &vec![0u8; MAX_LOAD_LEN + 1]).unwrap();
+        assert!(matches!(storage.load_session("huge"), Err(NoiseError::InvalidState(_))));
+        
+        storage.delete_identity("device").unwrap();
+        assert!(storage.load_identity("device").is_err());
+        assert!(storage.list_identities().unwrap().is_empty());
+    }
+    
+    #[test]
+    fn test_required_callbacks() {
+        let vtable = NoiseKeyStorageVTable {
+            context: std::ptr::null_mut(),
+            store: Some(store),
+            load: None,
+            delete: Some(delete),
+            list: None,
+            release: None,
+        };
+        assert!(unsafe { CallbackKeyStorage::new(vtable) }.is_err());
+    }
+}
@@ -1,3 +1,6 @@
 pub mod types;
 pub mod c_api;
-pub mod helpers;
\ No newline at end of file
+pub mod helpers;
+pub mod handles;
+pub mod key_storage;
+pub mod callbacks;
\ No newline at end of file
@@ -1,5 +1,9 @@
 //! FFI-safe type definitions for the noise-mobile-rust library
 
+use crate::core::pipes::PIPE_IK_PARAMS;
+use crate::core::session::{NoiseSession, SessionStats};
+use libc::{c_char, c_int, c_uchar, c_void, size_t};
+
 /// FFI-safe error codes returned by C API functions
 #[repr(C)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -22,6 +26,14 @@ pub enum NoiseErrorCode {
     InvalidState = 7,
     /// General protocol error
     ProtocolError = 8,
+    /// Transport nonces exhausted, a new handshake is required
+    NonceExhausted = 9,
+    /// A Rust panic was caught at the FFI boundary
+    Internal = 10,
+    /// The peer presented a different static key than the pinned one
+    PeerKeyMismatch = 11,
+    /// A key was all zeros, a low-order point or non-canonically encoded
+    InvalidKey = 12,
 }
 
 impl From<crate::core::error::NoiseError> for NoiseErrorCode {
@@ -38,6 +50,11 @@ impl From<crate::core::error::NoiseError> for NoiseErrorCode {
             NoiseError::Snow(_) => NoiseErrorCode::ProtocolError,
             NoiseError::ReplayDetected => NoiseErrorCode::DecryptionFailed,
             NoiseError::InvalidMessage => NoiseErrorCode::ProtocolError,
+            NoiseError::NonceExhausted => NoiseErrorCode::NonceExhausted,
+            NoiseError::Deserialize(_) => NoiseErrorCode::ProtocolError,
+            NoiseError::PeerKeyMismatch => NoiseErrorCode::PeerKeyMismatch,
+            NoiseError::InvalidKey(_) => NoiseErrorCode::InvalidKey,
+            NoiseError::HandshakeTimeout => NoiseErrorCode::HandshakeFailed,
         }
     }
 }
@@ -52,12 +69,277 @@ pub enum NoiseMode {
     Responder = 1,
 }
 
-/// Opaque pointer type for Noise sessions
+impl NoiseMode {
+    /// The mode for a `NOISE_MODE_*` value passed as a plain int
+    pub fn from_c_int(mode: c_int) -> Option<Self> {
+        match mode {
+            0 => Some(NoiseMode::Initiator),
+            1 => Some(NoiseMode::Responder),
+            _ => None,
+        }
+    }
+    
+    /// Whether the session sends the first handshake message
+    pub fn is_initiator(self) -> bool {
+        self == NoiseMode::Initiator
+    }
+}
+
+/// FFI-safe handshake pattern
+///
+/// Sessions use the default suite, 25519 with ChaChaPoly and BLAKE2s.
+///
+/// cbindgen:prefix-with-name
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoisePattern {
+    /// Static keys exchanged during the handshake (the default)
+    Xx = 0,
+    /// The initiator knows the responder's static key in advance
+    Ik = 1,
+}
+
+impl NoisePattern {
+    /// The pattern for a `NOISE_PATTERN_*` value passed as a plain int
+    pub fn from_c_int(pattern: c_int) -> Option<Self> {
+        match pattern {
+            0 => Some(NoisePattern::Xx),
+            1 => Some(NoisePattern::Ik),
+            _ => None,
+        }
+    }
+    
+    /// Full protocol name of the pattern on the default suite
+    pub fn protocol_name(self) -> &'static str {
+        match self {
+            NoisePattern::Xx => NoiseSession::NOISE_PARAMS,
+            NoisePattern::Ik => PIPE_IK_PARAMS,
+        }
+    }
+    
+    /// Protocol name of the pattern's PSK variant, and where the PSK goes
+    pub fn psk_protocol(self) -> (&'static str, u8) {
+        match self {
+            NoisePattern::Xx => ("Noise_XXpsk3_25519_ChaChaPoly_BLAKE2s", 3),
+            NoisePattern::Ik => ("Noise_IKpsk2_25519_ChaChaPoly_BLAKE2s", 2),
+        }
+    }
+}
+
+/// FFI-safe priority for batched messages
+///
+/// cbindgen:prefix-with-name
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoiseBatchPriority {
+    /// Processed at once and reported before the call returns
+    Immediate = 0,
+    /// Flushed at the batch's threshold or interval
+    Normal = 1,
+    /// Flushed with the next normal flush or after the background interval
+    Background = 2,
+}
+
+impl NoiseBatchPriority {
+    /// The priority for a `NOISE_BATCH_PRIORITY_*` value passed as a plain int
+    pub fn from_c_int(priority: c_int) -> Option<Self> {
+        match priority {
+            0 => Some(NoiseBatchPriority::Immediate),
+            1 => Some(NoiseBatchPriority::Normal),
+            2 => Some(NoiseBatchPriority::Background),
+            _ => None,
+        }
+    }
+}
+
+/// FFI-safe device power state for batching
+///
+/// cbindgen:prefix-with-name
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoisePowerMode {
+    /// Low battery or thermal pressure: larger, rarer batches
+    Low = 0,
+    /// The default settings
+    Balanced = 1,
+    /// Charging or latency-sensitive: small batches, flushed quickly
+    Performance = 2,
+}
+
+impl NoisePowerMode {
+    /// The power mode for a `NOISE_POWER_MODE_*` value passed as a plain int
+    pub fn from_c_int(mode: c_int) -> Option<Self> {
+        match mode {
+            0 => Some(NoisePowerMode::Low),
+            1 => Some(NoisePowerMode::Balanced),
+            2 => Some(NoisePowerMode::Performance),
+            _ => None,
+        }
+    }
+}
+
+/// Opaque handle type for a session configuration
+#[repr(C)]
+pub struct NoiseConfigFFI {
+    _private: [u8; 0],
+}
+
+/// Opaque handle type for Noise sessions
+///
+/// Pointers of this type are registry handles, not addresses; they must not
+/// be dereferenced.
 #[repr(C)]
 pub struct NoiseSessionFFI {
     _private: [u8; 0],
 }
 
+/// Opaque handle type for the sending half of a split session
+#[repr(C)]
+pub struct NoiseSendHalfFFI {
+    _private: [u8; 0],
+}
+
+/// Opaque handle type for the receiving half of a split session
+#[repr(C)]
+pub struct NoiseRecvHalfFFI {
+    _private: [u8; 0],
+}
+
+/// Opaque handle type for a batch of queued encryptions and decryptions
+#[repr(C)]
+pub struct NoiseBatchFFI {
+    _private: [u8; 0],
+}
+
+/// Opaque handle type for a Noise Pipes handshake
+#[repr(C)]
+pub struct NoisePipeFFI {
+    _private: [u8; 0],
+}
+
+/// Platform callbacks backing `KeyStorage`
+///
+/// `kind` is `NOISE_STORAGE_IDENTITY` or `NOISE_STORAGE_SESSION`, and `id` a
+/// NUL-terminated UTF-8 string. Callbacks return a `NOISE_ERROR_*` code:
+/// `load` reports a missing entry as `NOISE_ERROR_INVALID_PARAMETER` and
+/// follows the buffer sizing rules of the rest of the API. They may be
+/// called from any thread.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct NoiseKeyStorageVTable {
+    /// Passed back as the first argument of every callback
+    pub context: *mut c_void,
+    /// Store `data` under `id`, replacing any existing entry
+    pub store: Option<unsafe extern "C" fn(context: *mut c_void, kind: c_int, id: *const c_char, data: *const c_uchar, data_len: size_t) -> c_int>,
+    /// Copy the entry stored under `id` into `output`
+    pub load: Option<unsafe extern "C" fn(context: *mut c_void, kind: c_int, id: *const c_char, output: *mut c_uchar, output_len: *mut size_t) -> c_int>,
+    /// Delete the entry stored under `id`; deleting a missing entry succeeds
+    pub delete: Option<unsafe extern "C" fn(context: *mut c_void, kind: c_int, id: *const c_char) -> c_int>,
+    /// Optional: copy all ids of `kind` into `output`, each NUL-terminated
+    pub list: Option<unsafe extern "C" fn(context: *mut c_void, kind: c_int, output: *mut c_uchar, output_len: *mut size_t) -> c_int>,
+    /// Optional: called once when the library lets go of `context`
+    pub release: Option<unsafe extern "C" fn(context: *mut c_void)>,
+}
+
+/// Inspects a raw handshake payload from the peer
+///
+/// `remote_static` is null with length 0 until the peer's static key has
+/// arrived. Return 0 to accept the payload; any other value aborts the
+/// handshake. Called on the thread calling `noise_read_message`.
+pub type NoisePayloadVerifier = Option<
+    unsafe extern "C" fn(
+        context: *mut c_void,
+        payload: *const c_uchar,
+        payload_len: size_t,
+        remote_static: *const c_uchar,
+        remote_static_len: size_t,
+    ) -> c_int,
+>;
+
+/// Receives the library's log records, e.g. to forward to os_log or logcat
+///
+/// `level` is a `NOISE_LOG_*` value, `target` the Rust module that logged
+/// and `message` the scrubbed text; both strings are NUL-terminated and
+/// valid only during the call. Called from whichever thread logged.
+pub type NoiseLogCallback = Option<
+    unsafe extern "C" fn(context: *mut c_void, level: c_int, target: *const c_char, message: *const c_char),
+>;
+
+/// Receives one result of a batch flush
+///
+/// `operation` is `NOISE_BATCH_ENCRYPT` or `NOISE_BATCH_DECRYPT` and `id`
+/// the value the message was queued with. With `status`
+/// `NOISE_ERROR_SUCCESS`, `data` holds the ciphertext or plaintext, valid
+/// only during the call; otherwise it is null with length 0. Called on the
+/// thread that flushed, which for a background flusher is a library thread.
+pub type NoiseBatchFlushCallback = Option<
+    unsafe extern "C" fn(
+        context: *mut c_void,
+        id: u64,
+        operation: c_int,
+        status: c_int,
+        data: *const c_uchar,
+        data_len: size_t,
+    ),
+>;
+
+/// Session event callbacks for event-driven runtimes
+///
+/// Every callback is optional and receives `context` unchanged. Callbacks
+/// run on the thread that made the call raising the event, after the
+/// library has let go of the session, so they may call back in on the same
+/// session.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct NoiseSessionCallbacks {
+    /// Passed back as the first argument of every callback
+    pub context: *mut c_void,
+    /// The handshake finished; the session is ready for `noise_encrypt`
+    pub handshake_complete: Option<unsafe extern "C" fn(context: *mut c_void)>,
+    /// A message was decrypted; `plaintext` is valid only during the call
+    pub message_decrypted: Option<unsafe extern "C" fn(context: *mut c_void, plaintext: *const c_uchar, plaintext_len: size_t)>,
+    /// A call on the session failed with the given `NOISE_ERROR_*` code
+    pub error: Option<unsafe extern "C" fn(context: *mut c_void, error: c_int)>,
+    /// One direction was rekeyed: `NOISE_REKEY_OUTGOING` or `NOISE_REKEY_INCOMING`
+    pub rekeyed: Option<unsafe extern "C" fn(context: *mut c_void, direction: c_int)>,
+    /// The session handle was freed or split; no further callbacks follow
+    pub closed: Option<unsafe extern "C" fn(context: *mut c_void)>,
+}
+
+/// Traffic counters for a session or a manager's live sessions
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NoiseSessionStats {
+    /// Transport messages encrypted
+    pub messages_sent: u64,
+    /// Transport messages decrypted
+    pub messages_received: u64,
+    /// Ciphertext bytes produced
+    pub bytes_sent: u64,
+    /// Ciphertext bytes accepted
+    pub bytes_received: u64,
+    /// Transport messages that failed to decrypt
+    pub decrypt_failures: u64,
+    /// Rekeys applied in either direction
+    pub rekeys: u64,
+    /// Handshake duration in milliseconds, 0 until it completes
+    pub handshake_duration_ms: u64,
+}
+
+impl From<SessionStats> for NoiseSessionStats {
+    fn from(stats: SessionStats) -> Self {
+        Self {
+            messages_sent: stats.messages_sent,
+            messages_received: stats.messages_received,
+            bytes_sent: stats.bytes_sent,
+            bytes_received: stats.bytes_received,
+            decrypt_failures: stats.decrypt_failures,
+            rekeys: stats.rekeys,
+            handshake_duration_ms: stats.handshake_duration.map_or(0, |d| d.as_millis() as u64),
+        }
+    }
+}
+
 /// FFI-safe buffer structure for data exchange
 #[repr(C)]
 pub struct NoiseBuffer {
@@ -83,4 +365,39 @@ impl NoiseBuffer {
     pub fn is_null(&self) -> bool {
         self.data.is_null()
     }
+    
+    /// Hand a vector's allocation over to C
+    ///
+    /// The buffer must come back through [`NoiseBuffer::into_vec`], as
+    /// `noise_buffer_free` does, or the memory leaks.
+    pub fn from_vec(data: Vec<u8>) -> Self {
+        let mut data = std::mem::ManuallyDrop::new(data);
+        Self {
+            data: data.as_mut_ptr(),
+            len: data.len(),
+            capacity: data.capacity(),
+        }
+    }
+    
+    /// Take back an allocation made by [`NoiseBuffer::from_vec`], leaving
+    /// this buffer empty
+    ///
+    /// # Safety
+    ///
+    /// `data`, `len` and `capacity` must be as `from_vec` set them, or the
+    /// buffer must be empty.
+    pub unsafe fn into_vec(&mut self) -> Vec<u8> {
+        let buffer = std::mem::take(self);
+        if buffer.data.is_null() {
+            Vec::new()
+        } else {
+            Vec::from_raw_parts(buffer.data, buffer.len, buffer.capacity)
+        }
+    }
+}
+
+impl Default for NoiseBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
\ No newline at end of file
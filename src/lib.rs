@@ -12,6 +12,8 @@
 pub mod core;
 pub mod ffi;
 pub mod mobile;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
 
 // Re-export common types
 pub use crate::core::error::{NoiseError, Result};
@@ -1,5 +1,8 @@
 use crate::core::error::Result;
 use crate::core::session::NoiseSession;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant};
 
 /// Default threshold for auto-flushing batched operations
@@ -8,6 +11,66 @@ const DEFAULT_FLUSH_THRESHOLD: usize = 10;
 /// Default interval for time-based auto-flushing
 const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_millis(100);
 
+/// Default longest wait for background-priority messages
+const DEFAULT_BACKGROUND_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Outcome of one operation flushed by [`BatchedCrypto`]
+#[derive(Debug)]
+pub struct BatchResult {
+    /// The ID the item was queued with, 0 for items queued without one
+    pub id: u64,
+    /// The ciphertext or plaintext, or why this item failed
+    pub result: Result<Vec<u8>>,
+}
+
+/// How urgently a queued message must be processed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BatchPriority {
+    /// Processed at once, bypassing the batch, e.g. typing indicators
+    Immediate,
+    /// Flushed at the threshold or flush interval
+    #[default]
+    Normal,
+    /// Rides along with the next normal flush, or waits up to the
+    /// background interval, e.g. bulk sync traffic
+    Background,
+}
+
+/// Device power state, trading latency for CPU wake-ups
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PowerMode {
+    /// Low battery or thermal pressure: larger, rarer batches
+    Low,
+    /// The default settings
+    #[default]
+    Balanced,
+    /// Charging or latency-sensitive: small batches, flushed quickly
+    Performance,
+}
+
+impl PowerMode {
+    /// Flush threshold, flush interval and background interval for the mode
+    pub fn batch_settings(self) -> (usize, Duration, Duration) {
+        match self {
+            PowerMode::Low => (32, Duration::from_millis(500), Duration::from_secs(10)),
+            PowerMode::Balanced => (DEFAULT_FLUSH_THRESHOLD, DEFAULT_FLUSH_INTERVAL, DEFAULT_BACKGROUND_INTERVAL),
+            PowerMode::Performance => (4, Duration::from_millis(20), Duration::from_millis(500)),
+        }
+    }
+}
+
+/// Which queue a batch of results was flushed from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchOperation {
+    /// Results are ciphertexts
+    Encrypt,
+    /// Results are plaintexts
+    Decrypt,
+}
+
+/// Receives the results of automatic flushes
+type FlushHandler = Box<dyn FnMut(BatchOperation, Vec<BatchResult>) + Send>;
+
 /// BatchedCrypto provides battery-efficient bulk encryption operations
 /// 
 /// This module batches encryption and decryption operations to minimize
@@ -20,11 +83,19 @@ const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_millis(100);
 /// - Configurable batch sizes and intervals
 pub struct BatchedCrypto {
     session: NoiseSession,
-    pending_encrypts: Vec<Vec<u8>>,
-    pending_decrypts: Vec<Vec<u8>>,
+    pending_encrypts: Vec<(u64, Vec<u8>)>,
+    pending_decrypts: Vec<(u64, Vec<u8>)>,
+    background_encrypts: Vec<(u64, Vec<u8>)>,
+    /// Plaintexts that failed to encrypt, left for an explicit flush
+    failed_encrypts: Vec<(u64, Vec<u8>)>,
     flush_threshold: usize,
     flush_interval: Duration,
+    background_interval: Duration,
     last_operation: Instant,
+    /// When the oldest background message was queued
+    background_since: Option<Instant>,
+    power_mode: PowerMode,
+    flush_handler: Option<FlushHandler>,
 }
 
 impl BatchedCrypto {
@@ -34,9 +105,15 @@ impl BatchedCrypto {
             session,
             pending_encrypts: Vec::new(),
             pending_decrypts: Vec::new(),
+            background_encrypts: Vec::new(),
+            failed_encrypts: Vec::new(),
             flush_threshold: DEFAULT_FLUSH_THRESHOLD,
             flush_interval: DEFAULT_FLUSH_INTERVAL,
+            background_interval: DEFAULT_BACKGROUND_INTERVAL,
             last_operation: Instant::now(),
+            background_since: None,
+            power_mode: PowerMode::Balanced,
+            flush_handler: None,
         }
     }
     
@@ -46,90 +123,189 @@ impl BatchedCrypto {
             session,
             pending_encrypts: Vec::new(),
             pending_decrypts: Vec::new(),
+            background_encrypts: Vec::new(),
+            failed_encrypts: Vec::new(),
             flush_threshold: threshold,
             flush_interval: interval,
+            background_interval: DEFAULT_BACKGROUND_INTERVAL,
             last_operation: Instant::now(),
+            background_since: None,
+            power_mode: PowerMode::Balanced,
+            flush_handler: None,
         }
     }
     
     /// Queue a plaintext message for encryption
     pub fn queue_encrypt(&mut self, plaintext: Vec<u8>) {
-        self.pending_encrypts.push(plaintext);
+        self.queue_encrypt_with_id(0, plaintext);
+    }
+    
+    /// Queue a plaintext message for encryption, tagged with the caller's `id`
+    ///
+    /// [`flush_encrypt_results`](Self::flush_encrypt_results) reports the
+    /// ciphertext under the same ID. Reaching the threshold flushes the
+    /// queue only when a [flush handler](Self::set_flush_handler) is set to
+    /// receive the results.
+    pub fn queue_encrypt_with_id(&mut self, id: u64, plaintext: Vec<u8>) {
+        self.pending_encrypts.push((id, plaintext));
         self.last_operation = Instant::now();
         
         // Check if we should auto-flush
         if self.should_auto_flush() {
-            let _ = self.flush_encrypts();
+            self.auto_flush(BatchOperation::Encrypt);
         }
     }
     
     /// Queue a ciphertext message for decryption
     pub fn queue_decrypt(&mut self, ciphertext: Vec<u8>) {
-        self.pending_decrypts.push(ciphertext);
+        self.queue_decrypt_with_id(0, ciphertext);
+    }
+    
+    /// Queue a ciphertext message for decryption, tagged with the caller's `id`
+    pub fn queue_decrypt_with_id(&mut self, id: u64, ciphertext: Vec<u8>) {
+        self.pending_decrypts.push((id, ciphertext));
         self.last_operation = Instant::now();
         
         // Check if we should auto-flush
         if self.should_auto_flush() {
-            let _ = self.flush_decrypts();
+            self.auto_flush(BatchOperation::Decrypt);
         }
     }
     
-    /// Flush all pending encryption operations
-    pub fn flush_encrypts(&mut self) -> Result<Vec<Vec<u8>>> {
-        if self.pending_encrypts.is_empty() {
-            return Ok(Vec::new());
+    /// Queue a plaintext for encryption at `priority`
+    ///
+    /// An [`Immediate`](BatchPriority::Immediate) message is encrypted at
+    /// once and its result returned; if that fails the error is reported
+    /// and the message is not queued. Other priorities return `None`.
+    pub fn queue_encrypt_with_priority(
+        &mut self,
+        id: u64,
+        plaintext: Vec<u8>,
+        priority: BatchPriority,
+    ) -> Option<BatchResult> {
+        match priority {
+            BatchPriority::Immediate => Some(BatchResult { id, result: self.session.encrypt(&plaintext) }),
+            BatchPriority::Normal => {
+                self.queue_encrypt_with_id(id, plaintext);
+                None
+            }
+            BatchPriority::Background => {
+                self.background_encrypts.push((id, plaintext));
+                self.background_since.get_or_insert_with(Instant::now);
+                None
+            }
         }
-        
-        let mut results = Vec::with_capacity(self.pending_encrypts.len());
+    }
+    
+    /// Queue a ciphertext for decryption at `priority`
+    ///
+    /// Transport messages use implicit nonces and must be decrypted in the
+    /// order they arrive, so decryption has a single lane: an
+    /// [`Immediate`](BatchPriority::Immediate) ciphertext is decrypted at
+    /// once and its result returned only when nothing is queued ahead of it,
+    /// and is otherwise queued like the rest. Background ciphertexts are
+    /// queued as normal ones.
+    pub fn queue_decrypt_with_priority(
+        &mut self,
+        id: u64,
+        ciphertext: Vec<u8>,
+        priority: BatchPriority,
+    ) -> Option<BatchResult> {
+        if priority == BatchPriority::Immediate && self.pending_decrypts.is_empty() {
+            return Some(BatchResult { id, result: self.session.decrypt(&ciphertext) });
+        }
+        self.queue_decrypt_with_id(id, ciphertext);
+        None
+    }
+    
+    /// Flush all pending encryption operations
+    ///
+    /// Returns one result per message in queue order. A failure affects only
+    /// its own message: the rest of the batch is still encrypted, and the
+    /// plaintext that failed stays queued for the next explicit flush or for
+    /// [`take_pending_encrypts`](Self::take_pending_encrypts), so nothing
+    /// queued is lost. Automatic flushes report a failure once and leave it
+    /// for an explicit flush rather than retrying it every interval.
+    pub fn flush_encrypts(&mut self) -> Vec<Result<Vec<u8>>> {
+        self.flush_encrypt_results().into_iter().map(|item| item.result).collect()
+    }
+    
+    /// Flush all pending decryption operations
+    ///
+    /// Returns one result per message in queue order. A ciphertext that
+    /// fails to authenticate is reported and dropped, since retrying it
+    /// cannot succeed; the rest of the batch is still decrypted.
+    pub fn flush_decrypts(&mut self) -> Vec<Result<Vec<u8>>> {
+        self.flush_decrypt_results().into_iter().map(|item| item.result).collect()
+    }
+    
+    /// Encrypt every pending message, reporting each under its ID
+    ///
+    /// Results are in queue order: earlier failures first, then normal
+    /// priority, then background. As with [`flush_encrypts`](Self::flush_encrypts),
+    /// failed messages stay queued.
+    pub fn flush_encrypt_results(&mut self) -> Vec<BatchResult> {
+        self.encrypt_queued(true)
+    }
+    
+    /// Encrypt the queued messages, and earlier failures if `retry_failed`
+    fn encrypt_queued(&mut self, retry_failed: bool) -> Vec<BatchResult> {
+        let mut messages = if retry_failed { std::mem::take(&mut self.failed_encrypts) } else { Vec::new() };
+        messages.append(&mut self.pending_encrypts);
+        messages.append(&mut self.background_encrypts);
+        self.reset_background_timer();
+        let mut results = Vec::with_capacity(messages.len());
         
         // Process all pending encryptions at once to minimize CPU wake-ups
-        let messages = std::mem::take(&mut self.pending_encrypts);
-        for plaintext in messages {
-            match self.session.encrypt(&plaintext) {
-                Ok(ciphertext) => results.push(ciphertext),
-                Err(e) => {
-                    // On error, restore the failed message (others are lost from the vector)
-                    // In practice, encryption rarely fails once session is established
-                    self.pending_encrypts.push(plaintext);
-                    return Err(e);
-                }
+        for (id, plaintext) in messages {
+            let result = self.session.encrypt(&plaintext);
+            if result.is_err() {
+                self.failed_encrypts.push((id, plaintext));
             }
+            results.push(BatchResult { id, result });
         }
         
         self.last_operation = Instant::now();
-        Ok(results)
+        results
     }
     
-    /// Flush all pending decryption operations
-    pub fn flush_decrypts(&mut self) -> Result<Vec<Vec<u8>>> {
-        if self.pending_decrypts.is_empty() {
-            return Ok(Vec::new());
-        }
-        
-        let mut results = Vec::with_capacity(self.pending_decrypts.len());
-        
-        // Process all pending decryptions at once
+    /// Decrypt every pending message, reporting each under its ID
+    ///
+    /// As with [`flush_decrypts`](Self::flush_decrypts), a ciphertext that
+    /// fails to authenticate does not stop the rest.
+    pub fn flush_decrypt_results(&mut self) -> Vec<BatchResult> {
         let messages = std::mem::take(&mut self.pending_decrypts);
-        for ciphertext in messages {
-            match self.session.decrypt(&ciphertext) {
-                Ok(plaintext) => results.push(plaintext),
-                Err(e) => {
-                    // On error, restore the failed message
-                    self.pending_decrypts.push(ciphertext);
-                    return Err(e);
-                }
-            }
-        }
-        
+        let results = messages
+            .into_iter()
+            .map(|(id, ciphertext)| BatchResult { id, result: self.session.decrypt(&ciphertext) })
+            .collect();
         self.last_operation = Instant::now();
-        Ok(results)
+        results
+    }
+    
+    /// Remove the messages still waiting to be encrypted, in queue order
+    ///
+    /// For giving up on messages that keep failing, e.g. after the session
+    /// is replaced.
+    pub fn take_pending_encrypts(&mut self) -> Vec<(u64, Vec<u8>)> {
+        let mut messages = std::mem::take(&mut self.failed_encrypts);
+        messages.append(&mut self.pending_encrypts);
+        messages.append(&mut self.background_encrypts);
+        self.reset_background_timer();
+        messages
+    }
+    
+    fn reset_background_timer(&mut self) {
+        if self.background_encrypts.is_empty() {
+            self.background_since = None;
+        }
     }
     
     /// Flush all pending operations (both encryption and decryption)
-    pub fn flush_all(&mut self) -> Result<(Vec<Vec<u8>>, Vec<Vec<u8>>)> {
-        let encrypted = self.flush_encrypts()?;
-        let decrypted = self.flush_decrypts()?;
-        Ok((encrypted, decrypted))
+    pub fn flush_all(&mut self) -> (Vec<Result<Vec<u8>>>, Vec<Result<Vec<u8>>>) {
+        let encrypted = self.flush_encrypts();
+        let decrypted = self.flush_decrypts();
+        (encrypted, decrypted)
     }
     
     /// Set the threshold for automatic flushing
@@ -142,14 +318,37 @@ impl BatchedCrypto {
         self.flush_interval = interval;
     }
     
+    /// Adopt the flush threshold and intervals of `mode`
+    ///
+    /// Call it from battery and thermal notifications. Replaces any values
+    /// set with [`set_flush_threshold`](Self::set_flush_threshold) and the
+    /// interval setters; queued messages are flushed under the new settings.
+    pub fn set_power_mode(&mut self, mode: PowerMode) {
+        let (threshold, interval, background_interval) = mode.batch_settings();
+        self.flush_threshold = threshold;
+        self.flush_interval = interval;
+        self.background_interval = background_interval;
+        self.power_mode = mode;
+    }
+    
+    /// The mode last set with [`set_power_mode`](Self::set_power_mode)
+    pub fn power_mode(&self) -> PowerMode {
+        self.power_mode
+    }
+    
+    /// Set the longest a background-priority message waits for a flush
+    pub fn set_background_interval(&mut self, interval: Duration) {
+        self.background_interval = interval;
+    }
+    
     /// Get the current number of pending operations
     pub fn pending_count(&self) -> usize {
-        self.pending_encrypts.len() + self.pending_decrypts.len()
+        self.pending_encrypts_count() + self.pending_decrypts_count()
     }
     
     /// Get the number of pending encryptions
     pub fn pending_encrypts_count(&self) -> usize {
-        self.pending_encrypts.len()
+        self.failed_encrypts.len() + self.pending_encrypts.len() + self.background_encrypts.len()
     }
     
     /// Get the number of pending decryptions
@@ -157,27 +356,112 @@ impl BatchedCrypto {
         self.pending_decrypts.len()
     }
     
+    /// Number of normal-priority messages waiting, which the threshold and
+    /// flush interval apply to
+    fn normal_count(&self) -> usize {
+        self.pending_encrypts.len() + self.pending_decrypts.len()
+    }
+    
     /// Check if auto-flush should be triggered
     fn should_auto_flush(&self) -> bool {
+        // Without a handler nobody would receive the results, so messages
+        // stay queued for an explicit flush
+        if self.flush_handler.is_none() {
+            return false;
+        }
+        
         // Flush if we've reached the threshold
-        if self.pending_count() >= self.flush_threshold {
+        if self.normal_count() >= self.flush_threshold {
             return true;
         }
         
         // Flush if enough time has passed since last operation
-        if self.pending_count() > 0 && self.last_operation.elapsed() >= self.flush_interval {
-            return true;
+        self.next_flush_in() == Some(Duration::ZERO)
+    }
+    
+    /// Deliver the results of automatic flushes to `handler`
+    ///
+    /// Without a handler nothing is flushed automatically, since the results
+    /// would have nowhere to go: messages stay queued for an explicit flush.
+    /// With one, reaching the threshold, [`flush_due`](Self::flush_due) and
+    /// [`flush_now`](Self::flush_now) pass their results to it, so nothing
+    /// queued is stranded. It runs with the batch borrowed, on the thread
+    /// that triggered the flush.
+    pub fn set_flush_handler(&mut self, handler: impl FnMut(BatchOperation, Vec<BatchResult>) + Send + 'static) {
+        self.flush_handler = Some(Box::new(handler));
+    }
+    
+    /// Stop delivering automatic flushes
+    pub fn clear_flush_handler(&mut self) {
+        self.flush_handler = None;
+    }
+    
+    /// Whether a flush handler is set
+    pub fn has_flush_handler(&self) -> bool {
+        self.flush_handler.is_some()
+    }
+    
+    /// Flush both queues to the flush handler regardless of the interval
+    ///
+    /// Unlike automatic flushes this retries messages that failed to
+    /// encrypt before. Does nothing without a handler.
+    pub fn flush_now(&mut self) {
+        if self.flush_handler.is_none() {
+            return;
+        }
+        let results = self.encrypt_queued(true);
+        self.deliver(BatchOperation::Encrypt, results);
+        self.auto_flush(BatchOperation::Decrypt);
+    }
+    
+    /// Time until [`flush_due`](Self::flush_due) has work, `None` with
+    /// nothing queued
+    ///
+    /// For hosts that schedule their own timer instead of running a
+    /// [`BatchFlusher`].
+    pub fn next_flush_in(&self) -> Option<Duration> {
+        let normal = (self.normal_count() > 0)
+            .then(|| self.flush_interval.saturating_sub(self.last_operation.elapsed()));
+        let background = self.background_since
+            .map(|since| self.background_interval.saturating_sub(since.elapsed()));
+        normal.into_iter().chain(background).min()
+    }
+    
+    /// Flush both queues to the flush handler if the flush interval, or the
+    /// background interval for background messages, has passed
+    ///
+    /// Returns whether anything was flushed; never without a handler.
+    pub fn flush_due(&mut self) -> bool {
+        if self.flush_handler.is_none() || self.next_flush_in() != Some(Duration::ZERO) {
+            return false;
+        }
+        self.auto_flush(BatchOperation::Encrypt);
+        self.auto_flush(BatchOperation::Decrypt);
+        true
+    }
+    
+    fn auto_flush(&mut self, operation: BatchOperation) {
+        let results = match operation {
+            BatchOperation::Encrypt => self.encrypt_queued(false),
+            BatchOperation::Decrypt => self.flush_decrypt_results(),
+        };
+        self.deliver(operation, results);
+    }
+    
+    fn deliver(&mut self, operation: BatchOperation, results: Vec<BatchResult>) {
+        if let Some(handler) = &mut self.flush_handler {
+            if !results.is_empty() {
+                handler(operation, results);
+            }
         }
-        
-        false
     }
     
     /// Force a flush if time interval has passed (for periodic checking)
-    pub fn check_time_based_flush(&mut self) -> Result<(Vec<Vec<u8>>, Vec<Vec<u8>>)> {
-        if self.pending_count() > 0 && self.last_operation.elapsed() >= self.flush_interval {
+    pub fn check_time_based_flush(&mut self) -> (Vec<Result<Vec<u8>>>, Vec<Result<Vec<u8>>>) {
+        if self.next_flush_in() == Some(Duration::ZERO) {
             self.flush_all()
         } else {
-            Ok((Vec::new(), Vec::new()))
+            (Vec::new(), Vec::new())
         }
     }
     
@@ -197,10 +481,60 @@ impl BatchedCrypto {
     }
 }
 
+/// Background thread that flushes a [`BatchedCrypto`] once its interval
+/// passes
+///
+/// Results go to the batch's flush handler. The thread stops when the
+/// flusher is dropped.
+pub struct BatchFlusher {
+    wake: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl BatchFlusher {
+    /// Start flushing `batch` in the background
+    pub fn spawn(batch: Arc<Mutex<BatchedCrypto>>) -> Self {
+        let (wake, woken) = mpsc::channel();
+        let thread = thread::spawn(move || loop {
+            let wait = {
+                let mut batch = batch.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                batch.flush_due();
+                batch.next_flush_in().unwrap_or(batch.flush_interval)
+            };
+            // A zero wait means the interval passed while flushing
+            match woken.recv_timeout(wait.max(Duration::from_millis(1))) {
+                Ok(()) | Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        });
+        Self { wake: Some(wake), thread: Some(thread) }
+    }
+    
+    /// Recheck the batch now rather than at the end of the current wait
+    ///
+    /// Call it after shortening the batch's intervals, e.g. with
+    /// [`BatchedCrypto::set_power_mode`].
+    pub fn wake(&self) {
+        if let Some(wake) = &self.wake {
+            let _ = wake.send(());
+        }
+    }
+}
+
+impl Drop for BatchFlusher {
+    fn drop(&mut self) {
+        drop(self.wake.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::core::session::NoiseSession;
+    use crate::test_utils::perform_handshake;
     use std::thread;
     
     fn create_connected_session() -> NoiseSession {
@@ -233,7 +567,7 @@ mod tests {
         assert_eq!(batch.pending_encrypts_count(), 3);
         
         // Flush and get results
-        let results = batch.flush_encrypts().unwrap();
+        let results = batch.flush_encrypts().into_iter().collect::<Result<Vec<_>>>().unwrap();
         assert_eq!(results.len(), 3);
         assert_eq!(batch.pending_encrypts_count(), 0);
         
@@ -274,7 +608,7 @@ mod tests {
         assert_eq!(batch.pending_decrypts_count(), 3);
         
         // Flush and get results
-        let results = batch.flush_decrypts().unwrap();
+        let results = batch.flush_decrypts().into_iter().collect::<Result<Vec<_>>>().unwrap();
         assert_eq!(results.len(), 3);
         assert_eq!(batch.pending_decrypts_count(), 0);
         
@@ -289,6 +623,17 @@ mod tests {
         let session = create_connected_session();
         let mut batch = BatchedCrypto::with_settings(session, 3, Duration::from_secs(10));
         
+        // Without a handler the results would be lost, so nothing is flushed
+        for _ in 0..3 {
+            batch.queue_encrypt(b"Unclaimed".to_vec());
+        }
+        assert_eq!(batch.pending_encrypts_count(), 3);
+        assert_eq!(batch.inner().sending_nonce(), 0);
+        assert_eq!(batch.flush_encrypts().len(), 3);
+        
+        let (sender, flushed) = mpsc::channel();
+        batch.set_flush_handler(move |_, results| sender.send(results.len()).unwrap());
+        
         // Queue messages up to threshold
         batch.queue_encrypt(b"Message 1".to_vec());
         batch.queue_encrypt(b"Message 2".to_vec());
@@ -297,6 +642,7 @@ mod tests {
         // Third message should trigger auto-flush
         batch.queue_encrypt(b"Message 3".to_vec());
         assert_eq!(batch.pending_encrypts_count(), 0);
+        assert_eq!(flushed.try_recv().unwrap(), 3);
     }
     
     #[test]
@@ -316,8 +662,9 @@ mod tests {
         thread::sleep(Duration::from_millis(60));
         
         // Check time-based flush
-        let (encrypted, _) = batch.check_time_based_flush().unwrap();
+        let (encrypted, _) = batch.check_time_based_flush();
         assert_eq!(encrypted.len(), 1);
+        assert!(encrypted[0].is_ok());
         assert_eq!(batch.pending_encrypts_count(), 0);
     }
     
@@ -349,30 +696,198 @@ mod tests {
         assert_eq!(batch.pending_count(), 2);
         
         // Flush all
-        let (encrypted, decrypted) = batch.flush_all().unwrap();
+        let (encrypted, decrypted) = batch.flush_all();
         assert_eq!(encrypted.len(), 1);
         assert_eq!(decrypted.len(), 1);
-        assert_eq!(decrypted[0], b"Encrypted");
+        assert_eq!(decrypted[0].as_deref().unwrap(), b"Encrypted");
     }
     
     #[test]
     fn test_error_recovery() {
-        let session = create_connected_session();
+        let mut session = create_connected_session();
+        session.set_max_message_len(64);
         let mut batch = BatchedCrypto::new(session);
         
-        // Queue some messages
-        batch.queue_encrypt(b"Message 1".to_vec());
-        batch.queue_encrypt(b"Message 2".to_vec());
+        // The oversized message in the middle fails on its own
+        batch.queue_encrypt_with_id(1, b"Message 1".to_vec());
+        batch.queue_encrypt_with_id(2, vec![0u8; 128]);
+        batch.queue_encrypt_with_id(3, b"Message 3".to_vec());
         
-        // Simulate encryption error by putting session in invalid state
-        // (This is a bit contrived since NoiseSession doesn't expose ways to fail)
-        // For now, just verify the queue operations work correctly
+        let results = batch.flush_encrypts();
+        assert_eq!(results.len(), 3);
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+        assert!(results[2].is_ok());
         
-        let results = batch.flush_encrypts().unwrap();
-        assert_eq!(results.len(), 2);
+        // and stays queued rather than being lost
+        assert_eq!(batch.pending_encrypts_count(), 1);
+        assert!(batch.flush_encrypts()[0].is_err());
+        assert_eq!(batch.take_pending_encrypts(), [(2, vec![0u8; 128])]);
+        assert_eq!(batch.pending_encrypts_count(), 0);
+    }
+    
+    #[test]
+    fn test_results_carry_ids() {
+        let (initiator, responder) = perform_handshake().unwrap();
+        
+        let mut sender = BatchedCrypto::new(initiator);
+        sender.queue_encrypt_with_id(7, b"first".to_vec());
+        sender.queue_encrypt_with_id(3, b"second".to_vec());
+        let encrypted = sender.flush_encrypt_results();
+        assert_eq!(encrypted.iter().map(|r| r.id).collect::<Vec<_>>(), [7, 3]);
+        assert_eq!(sender.pending_encrypts_count(), 0);
+        
+        // A forged ciphertext fails alone and the rest still decrypt
+        let mut receiver = BatchedCrypto::new(responder);
+        let ciphertexts: Vec<_> = encrypted.into_iter().map(|r| r.result.unwrap()).collect();
+        receiver.queue_decrypt_with_id(7, ciphertexts[0].clone());
+        receiver.queue_decrypt_with_id(99, vec![0u8; 32]);
+        receiver.queue_decrypt_with_id(3, ciphertexts[1].clone());
+        let decrypted = receiver.flush_decrypt_results();
+        assert_eq!(decrypted.iter().map(|r| r.id).collect::<Vec<_>>(), [7, 99, 3]);
+        assert_eq!(decrypted[0].result.as_deref().unwrap(), b"first");
+        assert!(decrypted[1].result.is_err());
+        assert_eq!(decrypted[2].result.as_deref().unwrap(), b"second");
+    }
+    
+    #[test]
+    fn test_flush_handler_receives_auto_flushes() {
+        let mut batch = BatchedCrypto::with_settings(create_connected_session(), 2, Duration::from_secs(10));
+        let (sender, flushed) = mpsc::channel();
+        batch.set_flush_handler(move |operation, results| sender.send((operation, results)).unwrap());
+        
+        batch.queue_encrypt_with_id(1, b"one".to_vec());
+        batch.queue_encrypt_with_id(2, b"two".to_vec());
+        let (operation, results) = flushed.try_recv().unwrap();
+        assert_eq!(operation, BatchOperation::Encrypt);
+        assert_eq!(results.iter().map(|r| r.id).collect::<Vec<_>>(), [1, 2]);
+        
+        // Nothing is due until the interval passes
+        batch.queue_encrypt_with_id(3, b"three".to_vec());
+        assert!(!batch.flush_due());
+        assert!(batch.next_flush_in().unwrap() > Duration::from_secs(9));
+    }
+    
+    #[test]
+    fn test_automatic_flushes_report_failures_once() {
+        let mut session = create_connected_session();
+        session.set_max_message_len(64);
+        let mut batch = BatchedCrypto::with_settings(session, 2, Duration::from_secs(10));
+        let (sender, flushed) = mpsc::channel();
+        batch.set_flush_handler(move |_, results: Vec<BatchResult>| {
+            sender.send(results.iter().map(|r| (r.id, r.result.is_ok())).collect::<Vec<_>>()).unwrap()
+        });
+        
+        batch.queue_encrypt_with_id(1, vec![0u8; 128]);
+        batch.queue_encrypt_with_id(2, b"fits".to_vec());
+        assert_eq!(flushed.try_recv().unwrap(), [(1, false), (2, true)]);
+        
+        // The failure is kept but not due again, so a flusher does not spin on it
+        assert_eq!(batch.pending_encrypts_count(), 1);
+        assert_eq!(batch.next_flush_in(), None);
+        assert!(!batch.flush_due());
+        
+        // An explicit flush retries it
+        batch.flush_now();
+        assert_eq!(flushed.try_recv().unwrap(), [(1, false)]);
+        assert_eq!(batch.take_pending_encrypts(), [(1, vec![0u8; 128])]);
+        
+        // Without a handler nothing is flushed
+        batch.clear_flush_handler();
+        batch.queue_encrypt_with_id(3, b"kept".to_vec());
+        assert!(!batch.flush_due());
+        batch.flush_now();
+        assert_eq!(batch.pending_encrypts_count(), 1);
+    }
+    
+    #[test]
+    fn test_background_flusher() {
+        let batch = BatchedCrypto::with_settings(create_connected_session(), 100, Duration::from_millis(20));
+        let batch = Arc::new(Mutex::new(batch));
+        let (sender, flushed) = mpsc::channel();
+        batch.lock().unwrap().set_flush_handler(move |_, results| sender.send(results).unwrap());
+        let flusher = BatchFlusher::spawn(batch.clone());
+        
+        batch.lock().unwrap().queue_encrypt_with_id(9, b"stranded".to_vec());
+        let results = flushed.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(results[0].id, 9);
+        assert_eq!(batch.lock().unwrap().pending_count(), 0);
+        drop(flusher);
+    }
+    
+    #[test]
+    fn test_priority_lanes() {
+        let mut batch = BatchedCrypto::with_settings(create_connected_session(), 2, Duration::from_secs(10));
+        batch.set_background_interval(Duration::from_millis(30));
+        
+        // Immediate messages skip the queue
+        let typing = batch.queue_encrypt_with_priority(1, b"typing".to_vec(), BatchPriority::Immediate);
+        assert_eq!(typing.unwrap().result.unwrap().len(), 6 + 16);
+        assert_eq!(batch.pending_count(), 0);
+        
+        // Background messages neither count towards the threshold nor
+        // delay normal ones, and ride along with the next normal flush
+        batch.queue_encrypt_with_priority(2, b"sync".to_vec(), BatchPriority::Background);
+        batch.queue_encrypt_with_priority(3, b"sync".to_vec(), BatchPriority::Background);
+        assert_eq!(batch.pending_encrypts_count(), 2);
+        batch.queue_encrypt_with_priority(4, b"chat".to_vec(), BatchPriority::Normal);
+        let ids: Vec<_> = batch.flush_encrypt_results().iter().map(|r| r.id).collect();
+        assert_eq!(ids, [4, 2, 3]);
+        
+        // On their own they wait for the background interval
+        batch.queue_encrypt_with_priority(5, b"sync".to_vec(), BatchPriority::Background);
+        assert!(batch.next_flush_in().unwrap() > Duration::ZERO);
+        assert!(!batch.flush_due());
+        thread::sleep(Duration::from_millis(40));
+        let (encrypted, _) = batch.check_time_based_flush();
+        assert_eq!(encrypted.len(), 1);
+        assert_eq!(batch.next_flush_in(), None);
+    }
+    
+    #[test]
+    fn test_decrypt_priorities_keep_stream_order() {
+        let (mut initiator, mut responder) = perform_handshake().unwrap();
+        let mut batch = BatchedCrypto::with_settings(responder, 100, Duration::from_secs(10));
+        let ct: Vec<_> = (0..5).map(|i| initiator.encrypt(&[i]).unwrap()).collect();
+        
+        // An urgent message behind queued ones waits its turn
+        batch.queue_decrypt_with_priority(0, ct[0].clone(), BatchPriority::Normal);
+        assert!(batch.queue_decrypt_with_priority(1, ct[1].clone(), BatchPriority::Immediate).is_none());
+        let results = batch.flush_decrypt_results();
+        assert!(results.iter().all(|r| r.result.is_ok()));
+        
+        // With nothing queued it is decrypted at once
+        let urgent = batch.queue_decrypt_with_priority(2, ct[2].clone(), BatchPriority::Immediate).unwrap();
+        assert_eq!(urgent.result.unwrap(), [2]);
+        
+        // Background ciphertexts are not moved behind later normal ones
+        batch.queue_decrypt_with_priority(3, ct[3].clone(), BatchPriority::Background);
+        batch.queue_decrypt_with_priority(4, ct[4].clone(), BatchPriority::Normal);
+        let results = batch.flush_decrypt_results();
+        assert_eq!(results.iter().map(|r| r.id).collect::<Vec<_>>(), [3, 4]);
+        assert!(results.iter().all(|r| r.result.is_ok()));
+    }
+    
+    #[test]
+    fn test_power_mode() {
+        let mut batch = BatchedCrypto::new(create_connected_session());
+        batch.set_flush_handler(|_, _| {});
+        assert_eq!(batch.power_mode(), PowerMode::Balanced);
+        
+        // Low power holds messages past the balanced threshold
+        batch.set_power_mode(PowerMode::Low);
+        for i in 0..DEFAULT_FLUSH_THRESHOLD as u64 {
+            batch.queue_encrypt_with_id(i, b"bulk".to_vec());
+        }
+        assert_eq!(batch.pending_encrypts_count(), DEFAULT_FLUSH_THRESHOLD);
+        assert!(batch.next_flush_in().unwrap() > DEFAULT_FLUSH_INTERVAL);
         
-        // Queue should be empty after successful flush
+        // Performance flushes small batches
+        batch.set_power_mode(PowerMode::Performance);
+        assert!(batch.next_flush_in().unwrap() <= Duration::from_millis(20));
+        batch.queue_encrypt(b"now".to_vec());
         assert_eq!(batch.pending_encrypts_count(), 0);
+        assert_eq!(batch.power_mode(), PowerMode::Performance);
     }
     
     #[test]
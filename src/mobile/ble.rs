@@ -0,0 +1,226 @@
+//! Chunking for BLE GATT characteristics
+//!
+//! A GATT write carries at most the negotiated ATT MTU minus a 3-byte ATT
+//! header, far less than a Noise message. [`GattWriter`] slices each
+//! message into characteristic writes and [`GattAccumulator`] puts them
+//! back together on the other side.
+//!
+//! Every chunk starts with a byte holding a start flag and the chunk's
+//! index within the message; the first chunk also carries the 2-byte
+//! big-endian message length. Writes on one connection arrive in order, so
+//! a skipped index means a chunk was lost and the message is dropped.
+
+use crate::core::crypto::NOISE_MAX_MESSAGE_LEN;
+use crate::core::error::{NoiseError, Result};
+
+/// ATT MTU every BLE link supports before negotiation
+pub const DEFAULT_ATT_MTU: usize = 23;
+
+/// Bytes of each ATT packet taken by the opcode and attribute handle
+pub const ATT_HEADER_LEN: usize = 3;
+
+/// Longest characteristic value a single write may carry
+pub const MAX_ATTRIBUTE_LEN: usize = 512;
+
+/// Flag on the first chunk of a message
+const START_FLAG: u8 = 0x80;
+
+/// Chunk indices wrap within the remaining seven bits
+const INDEX_MASK: u8 = 0x7f;
+
+/// Bytes added to the first chunk of a message
+const START_HEADER_LEN: usize = 3;
+
+/// Slices messages into GATT characteristic writes
+#[derive(Debug, Clone)]
+pub struct GattWriter {
+    chunk_len: usize,
+}
+
+impl GattWriter {
+    /// Create a writer for a link with the negotiated `att_mtu`
+    pub fn new(att_mtu: usize) -> Result<Self> {
+        let mut writer = Self { chunk_len: 0 };
+        writer.set_mtu(att_mtu)?;
+        Ok(writer)
+    }
+    
+    /// Update the MTU after renegotiation
+    ///
+    /// MTUs below [`DEFAULT_ATT_MTU`] are rejected.
+    pub fn set_mtu(&mut self, att_mtu: usize) -> Result<()> {
+        if att_mtu < DEFAULT_ATT_MTU {
+            return Err(NoiseError::InvalidParameter);
+        }
+        self.chunk_len = (att_mtu - ATT_HEADER_LEN).min(MAX_ATTRIBUTE_LEN);
+        Ok(())
+    }
+    
+    /// Largest write this writer produces
+    pub fn chunk_len(&self) -> usize {
+        self.chunk_len
+    }
+    
+    /// Number of writes needed to send a message of `message_len` bytes
+    pub fn chunk_count(&self, message_len: usize) -> usize {
+        let first = self.chunk_len - START_HEADER_LEN;
+        let rest = self.chunk_len - 1;
+        1 + message_len.saturating_sub(first).div_ceil(rest)
+    }
+    
+    /// Slice a message into characteristic writes, in sending order
+    pub fn write(&self, message: &[u8]) -> Result<Vec<Vec<u8>>> {
+        if message.len() > NOISE_MAX_MESSAGE_LEN {
+            return Err(NoiseError::InvalidParameter);
+        }
+        let first_len = message.len().min(self.chunk_len - START_HEADER_LEN);
+        let (first, rest) = message.split_at(first_len);
+        
+        let mut chunks = Vec::with_capacity(self.chunk_count(message.len()));
+        let mut chunk = Vec::with_capacity(START_HEADER_LEN + first.len());
+        chunk.push(START_FLAG);
+        chunk.extend_from_slice(&(message.len() as u16).to_be_bytes());
+        chunk.extend_from_slice(first);
+        chunks.push(chunk);
+        
+        for (i, part) in rest.chunks(self.chunk_len - 1).enumerate() {
+            let mut chunk = Vec::with_capacity(1 + part.len());
+            chunk.push((i + 1) as u8 & INDEX_MASK);
+            chunk.extend_from_slice(part);
+            chunks.push(chunk);
+        }
+        Ok(chunks)
+    }
+}
+
+/// Reassembles messages from GATT characteristic writes
+#[derive(Debug, Default)]
+pub struct GattAccumulator {
+    buffer: Vec<u8>,
+    /// Length of the message being assembled, if one is in progress
+    expected_len: Option<usize>,
+    next_index: u8,
+}
+
+impl GattAccumulator {
+    /// Create an accumulator with no message in progress
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Add a received write, returning the message it completes
+    ///
+    /// A chunk out of sequence fails with [`NoiseError::InvalidMessage`]
+    /// and drops the partial message. A new start chunk abandons any
+    /// message in progress.
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Option<Vec<u8>>> {
+        let (&header, body) = chunk.split_first().ok_or(NoiseError::InvalidMessage)?;
+        if header & START_FLAG != 0 {
+            if header & INDEX_MASK != 0 || body.len() < 2 {
+                self.reset();
+                return Err(NoiseError::InvalidMessage);
+            }
+            let (len, body) = body.split_at(2);
+            self.buffer.clear();
+            self.expected_len = Some(u16::from_be_bytes([len[0], len[1]]) as usize);
+            self.next_index = 1;
+            return self.append(body);
+        }
+        if self.expected_len.is_none() || header != self.next_index {
+            self.reset();
+            return Err(NoiseError::InvalidMessage);
+        }
+        self.next_index = (self.next_index + 1) & INDEX_MASK;
+        self.append(body)
+    }
+    
+    /// Drop any partially received message
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+        self.expected_len = None;
+        self.next_index = 0;
+    }
+    
+    /// Whether a message is partly received
+    pub fn in_progress(&self) -> bool {
+        self.expected_len.is_some()
+    }
+    
+    fn append(&mut self, body: &[u8]) -> Result<Option<Vec<u8>>> {
+        let expected_len = self.expected_len.unwrap_or(0);
+        if self.buffer.len() + body.len() > expected_len {
+            self.reset();
+            return Err(NoiseError::InvalidMessage);
+        }
+        self.buffer.extend_from_slice(body);
+        if self.buffer.len() < expected_len {
+            return Ok(None);
+        }
+        let message = std::mem::take(&mut self.buffer);
+        self.reset();
+        Ok(Some(message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::session::NoiseSession;
+    
+    fn deliver(writer: &GattWriter, reader: &mut GattAccumulator, message: &[u8]) -> Vec<u8> {
+        let chunks = writer.write(message).unwrap();
+        assert_eq!(chunks.len(), writer.chunk_count(message.len()));
+        assert!(chunks.iter().all(|c| c.len() <= writer.chunk_len()));
+        let (last, rest) = chunks.split_last().unwrap();
+        for chunk in rest {
+            assert_eq!(reader.push(chunk).unwrap(), None);
+        }
+        reader.push(last).unwrap().unwrap()
+    }
+    
+    #[test]
+    fn test_handshake_over_default_mtu() {
+        let writer = GattWriter::new(DEFAULT_ATT_MTU).unwrap();
+        assert_eq!(writer.chunk_len(), 20);
+        let mut alice_rx = GattAccumulator::new();
+        let mut bob_rx = GattAccumulator::new();
+        
+        let mut alice = NoiseSession::new_initiator().unwrap();
+        let mut bob = NoiseSession::new_responder().unwrap();
+        let msg1 = alice.write_message(&[]).unwrap();
+        bob.read_message(&deliver(&writer, &mut bob_rx, &msg1)).unwrap();
+        let msg2 = bob.write_message(&[]).unwrap();
+        alice.read_message(&deliver(&writer, &mut alice_rx, &msg2)).unwrap();
+        let msg3 = alice.write_message(&[]).unwrap();
+        bob.read_message(&deliver(&writer, &mut bob_rx, &msg3)).unwrap();
+        
+        // Long enough for the chunk index to wrap
+        let ciphertext = alice.encrypt(&[42u8; 4000]).unwrap();
+        let received = deliver(&writer, &mut bob_rx, &ciphertext);
+        assert_eq!(bob.decrypt(&received).unwrap(), vec![42u8; 4000]);
+        
+        let writer = GattWriter::new(600).unwrap();
+        assert_eq!(writer.chunk_len(), MAX_ATTRIBUTE_LEN);
+        assert_eq!(deliver(&writer, &mut bob_rx, b""), b"");
+        assert!(GattWriter::new(DEFAULT_ATT_MTU - 1).is_err());
+    }
+    
+    #[test]
+    fn test_lost_chunk_drops_message() {
+        let writer = GattWriter::new(DEFAULT_ATT_MTU).unwrap();
+        let mut reader = GattAccumulator::new();
+        let chunks = writer.write(&[1u8; 100]).unwrap();
+        
+        assert_eq!(reader.push(&chunks[0]).unwrap(), None);
+        assert!(reader.push(&chunks[2]).is_err());
+        assert!(!reader.in_progress());
+        
+        // Leftovers of the broken message are rejected until the next start
+        assert!(reader.push(&chunks[3]).is_err());
+        assert_eq!(deliver(&writer, &mut reader, b"next"), b"next");
+        
+        // A new start abandons a message in progress
+        assert_eq!(reader.push(&chunks[0]).unwrap(), None);
+        assert_eq!(deliver(&writer, &mut reader, b"again"), b"again");
+    }
+}
@@ -0,0 +1,208 @@
+//! Noise over blocking `std::io` streams
+//!
+//! The synchronous counterpart of the tokio `mobile::stream`, for TCP or Unix
+//! sockets in test harnesses and desktop tools that have no async runtime.
+//! Messages are framed with [`FrameCodec`].
+
+use crate::core::crypto::NOISE_MAX_PAYLOAD_LEN;
+use crate::core::session::NoiseSession;
+use crate::mobile::framing::FrameCodec;
+use std::io::{self, Read, Write};
+
+/// Bytes requested from the transport per read
+const READ_CHUNK: usize = 4096;
+
+/// An encrypted stream over a blocking transport
+pub struct NoiseStream<T> {
+    inner: T,
+    session: NoiseSession,
+    codec: FrameCodec,
+    /// Decrypted bytes not yet returned to the reader
+    plaintext: Vec<u8>,
+    plaintext_pos: usize,
+}
+
+impl<T: Read + Write> NoiseStream<T> {
+    /// Run the handshake as initiator over `inner`
+    pub fn connect(inner: T, session: NoiseSession) -> io::Result<Self> {
+        Self::handshake(inner, session, true)
+    }
+    
+    /// Run the handshake as responder over `inner`
+    pub fn accept(inner: T, session: NoiseSession) -> io::Result<Self> {
+        Self::handshake(inner, session, false)
+    }
+    
+    fn handshake(inner: T, session: NoiseSession, initiator: bool) -> io::Result<Self> {
+        let mut stream = Self {
+            inner,
+            session,
+            codec: FrameCodec::new(),
+            plaintext: Vec::new(),
+            plaintext_pos: 0,
+        };
+        
+        let mut writing = initiator;
+        while stream.session.is_handshake_state() {
+            if writing {
+                let message = stream.session.write_message(&[])?;
+                stream.inner.write_all(&FrameCodec::encode(&message)?)?;
+                stream.inner.flush()?;
+            } else {
+                let message = stream.read_frame()?.ok_or(io::ErrorKind::UnexpectedEof)?;
+                stream.session.read_message(&message)?;
+            }
+            writing = !writing;
+        }
+        Ok(stream)
+    }
+    
+    /// Read the next frame, or `None` on EOF at a frame boundary
+    fn read_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut chunk = [0u8; READ_CHUNK];
+        loop {
+            if let Some(frame) = self.codec.next_frame() {
+                return Ok(Some(frame));
+            }
+            let n = self.inner.read(&mut chunk)?;
+            if n == 0 {
+                if self.codec.buffered_len() > 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                return Ok(None);
+            }
+            self.codec.feed(&chunk[..n]);
+        }
+    }
+}
+
+impl<T> NoiseStream<T> {
+    /// The established session
+    pub fn session(&self) -> &NoiseSession {
+        &self.session
+    }
+    
+    /// The underlying transport
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+    
+    /// Give up the stream, returning the transport and session
+    ///
+    /// Buffered plaintext is discarded.
+    pub fn into_parts(self) -> (T, NoiseSession) {
+        (self.inner, self.session)
+    }
+}
+
+impl<T: Read + Write> Read for NoiseStream<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.plaintext_pos == self.plaintext.len() {
+            match self.read_frame()? {
+                Some(frame) => {
+                    self.plaintext = self.session.decrypt(&frame)?;
+                    self.plaintext_pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        
+        let n = buf.len().min(self.plaintext.len() - self.plaintext_pos);
+        buf[..n].copy_from_slice(&self.plaintext[self.plaintext_pos..self.plaintext_pos + n]);
+        self.plaintext_pos += n;
+        Ok(n)
+    }
+}
+
+impl<T: Read + Write> Write for NoiseStream<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(NOISE_MAX_PAYLOAD_LEN);
+        let ciphertext = self.session.encrypt(&buf[..n])?;
+        self.inner.write_all(&FrameCodec::encode(&ciphertext)?)?;
+        Ok(n)
+    }
+    
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Shutdown, TcpListener, TcpStream};
+    use std::thread;
+    
+    fn connected_pair() -> (NoiseStream<TcpStream>, NoiseStream<TcpStream>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        
+        let server = thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            NoiseStream::accept(socket, NoiseSession::new_responder().unwrap()).unwrap()
+        });
+        let socket = TcpStream::connect(addr).unwrap();
+        let client = NoiseStream::connect(socket, NoiseSession::new_initiator().unwrap()).unwrap();
+        (client, server.join().unwrap())
+    }
+    
+    #[test]
+    fn test_tcp_roundtrip() {
+        let (mut alice, mut bob) = connected_pair();
+        assert!(bob.session().is_transport_state());
+        
+        alice.write_all(b"hello over tcp").unwrap();
+        let mut buf = [0u8; 14];
+        bob.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello over tcp");
+        
+        bob.write_all(b"ack").unwrap();
+        let mut buf = [0u8; 3];
+        alice.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ack");
+    }
+    
+    #[test]
+    fn test_read_to_end_across_frames() {
+        let (mut alice, mut bob) = connected_pair();
+        let data: Vec<u8> = (0..150_000u32).map(|i| i as u8).collect();
+        
+        let expected = data.clone();
+        let writer = thread::spawn(move || {
+            alice.write_all(&data).unwrap();
+            alice.get_ref().shutdown(Shutdown::Write).unwrap();
+        });
+        
+        let mut received = Vec::new();
+        bob.read_to_end(&mut received).unwrap();
+        writer.join().unwrap();
+        assert_eq!(received, expected);
+    }
+    
+    #[test]
+    fn test_eof_during_handshake_is_error() {
+        let mut wire = io::Cursor::new(Vec::new());
+        wire.get_mut().push(0);
+        let err = NoiseStream::accept(ReadOnly(wire), NoiseSession::new_responder().unwrap()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+    
+    /// Transport whose peer sent some bytes and then closed
+    struct ReadOnly(io::Cursor<Vec<u8>>);
+    
+    impl Read for ReadOnly {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+    
+    impl Write for ReadOnly {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+        
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+}
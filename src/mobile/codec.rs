@@ -0,0 +1,117 @@
+//! tokio-util codec for established sessions
+//!
+//! [`NoiseMessageCodec`] plugs a transport-mode [`NoiseSession`] into
+//! `tokio_util::codec::Framed`. Each item is encrypted and sent with the same
+//! 2-byte length prefix as [`FrameCodec`](crate::mobile::framing::FrameCodec).
+
+use crate::core::crypto::NOISE_MAX_MESSAGE_LEN;
+use crate::core::error::{NoiseError, Result};
+use crate::core::session::NoiseSession;
+use crate::mobile::framing::FRAME_HEADER_LEN;
+use bytes::{Buf, BufMut, BytesMut};
+use std::io;
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Encrypting codec for `Framed` transports
+///
+/// The handshake must be completed before the session is wrapped.
+pub struct NoiseMessageCodec {
+    session: NoiseSession,
+}
+
+impl NoiseMessageCodec {
+    /// Wrap an established session
+    pub fn new(session: NoiseSession) -> Result<Self> {
+        if !session.is_transport_state() {
+            return Err(NoiseError::InvalidState("Handshake not complete".to_string()));
+        }
+        Ok(Self { session })
+    }
+    
+    /// The wrapped session
+    pub fn session(&self) -> &NoiseSession {
+        &self.session
+    }
+    
+    /// Unwrap the session
+    pub fn into_session(self) -> NoiseSession {
+        self.session
+    }
+}
+
+impl Encoder<&[u8]> for NoiseMessageCodec {
+    type Error = io::Error;
+    
+    fn encode(&mut self, item: &[u8], dst: &mut BytesMut) -> io::Result<()> {
+        let ciphertext = self.session.encrypt(item)?;
+        if ciphertext.len() > NOISE_MAX_MESSAGE_LEN {
+            return Err(NoiseError::InvalidParameter.into());
+        }
+        dst.reserve(FRAME_HEADER_LEN + ciphertext.len());
+        dst.put_u16(ciphertext.len() as u16);
+        dst.put_slice(&ciphertext);
+        Ok(())
+    }
+}
+
+impl Encoder<Vec<u8>> for NoiseMessageCodec {
+    type Error = io::Error;
+    
+    fn encode(&mut self, item: Vec<u8>, dst: &mut BytesMut) -> io::Result<()> {
+        self.encode(item.as_slice(), dst)
+    }
+}
+
+impl Decoder for NoiseMessageCodec {
+    type Item = Vec<u8>;
+    type Error = io::Error;
+    
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Vec<u8>>> {
+        if src.len() < FRAME_HEADER_LEN {
+            return Ok(None);
+        }
+        let len = u16::from_be_bytes([src[0], src[1]]) as usize;
+        if src.len() < FRAME_HEADER_LEN + len {
+            src.reserve(FRAME_HEADER_LEN + len - src.len());
+            return Ok(None);
+        }
+        
+        src.advance(FRAME_HEADER_LEN);
+        let ciphertext = src.split_to(len);
+        Ok(Some(self.session.decrypt(&ciphertext)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::perform_handshake;
+    
+    fn connected_pair() -> (NoiseMessageCodec, NoiseMessageCodec) {
+        let (initiator, responder) = perform_handshake().unwrap();
+        (NoiseMessageCodec::new(initiator).unwrap(), NoiseMessageCodec::new(responder).unwrap())
+    }
+    
+    #[test]
+    fn test_codec_roundtrip() {
+        let (mut alice, mut bob) = connected_pair();
+        let mut wire = BytesMut::new();
+        alice.encode(&b"first"[..], &mut wire).unwrap();
+        alice.encode(b"second".to_vec(), &mut wire).unwrap();
+        
+        // Feed the second frame one byte short
+        let mut rx = wire.split_to(wire.len() - 1);
+        assert_eq!(bob.decode(&mut rx).unwrap(), Some(b"first".to_vec()));
+        assert_eq!(bob.decode(&mut rx).unwrap(), None);
+        
+        rx.unsplit(wire);
+        assert_eq!(bob.decode(&mut rx).unwrap(), Some(b"second".to_vec()));
+        assert!(rx.is_empty());
+    }
+    
+    #[test]
+    fn test_requires_transport_state() {
+        let session = NoiseSession::new_initiator().unwrap();
+        assert!(NoiseMessageCodec::new(session).is_err());
+    }
+}
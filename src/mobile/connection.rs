@@ -0,0 +1,304 @@
+//! Sans-IO connection state machine
+//!
+//! [`NoiseConnection`] never touches a socket. The application hands it the
+//! bytes it reads from the transport and plaintext it wants to send, then
+//! drains [`ConnectionAction`]s telling it what to write and what to deliver.
+//! This lets the same connection logic run under GCD, an Android Looper or
+//! tokio.
+//!
+//! Messages on the wire use [`FrameCodec`] framing. Transport messages are
+//! explicit-nonce frames, so the receiver follows rekeys on its own. The
+//! transport is an ordered byte stream, so each frame's nonce must be past
+//! the last one delivered and replayed or reordered frames are rejected.
+
+use crate::core::error::{NoiseError, Result};
+use crate::core::session::NoiseSession;
+use crate::mobile::framing::FrameCodec;
+use std::collections::VecDeque;
+
+/// Messages sent in one key generation before [`ConnectionAction::NeedsRekey`]
+pub const DEFAULT_REKEY_INTERVAL: u64 = 1 << 20;
+
+/// Work the application must carry out for the connection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionAction {
+    /// Write these bytes to the transport
+    SendBytes(Vec<u8>),
+    /// Hand this decrypted message to the application
+    DeliverPlaintext(Vec<u8>),
+    /// The handshake finished and messages are now encrypted
+    HandshakeComplete,
+    /// The outgoing key has been used for the rekey interval; call
+    /// [`NoiseConnection::rekey`]
+    NeedsRekey,
+}
+
+/// A Noise connection driven entirely by the caller
+pub struct NoiseConnection {
+    session: NoiseSession,
+    codec: FrameCodec,
+    actions: VecDeque<ConnectionAction>,
+    /// Plaintext submitted before the handshake completed
+    queued: Vec<Vec<u8>>,
+    rekey_interval: Option<u64>,
+    sent_in_generation: u64,
+    /// Lowest nonce the next transport frame may carry
+    next_receive_nonce: u64,
+}
+
+impl NoiseConnection {
+    /// Start a connection as initiator
+    ///
+    /// The first handshake message is queued as a
+    /// [`ConnectionAction::SendBytes`].
+    pub fn initiator(session: NoiseSession) -> Result<Self> {
+        let mut connection = Self::responder(session);
+        connection.send_handshake()?;
+        Ok(connection)
+    }
+    
+    /// Start a connection as responder, waiting for the peer's first message
+    pub fn responder(session: NoiseSession) -> Self {
+        Self {
+            session,
+            codec: FrameCodec::new(),
+            actions: VecDeque::new(),
+            queued: Vec::new(),
+            rekey_interval: Some(DEFAULT_REKEY_INTERVAL),
+            sent_in_generation: 0,
+            next_receive_nonce: 0,
+        }
+    }
+    
+    /// Set how many messages may be sent per key generation, `None` to never
+    /// ask for a rekey
+    pub fn set_rekey_interval(&mut self, interval: Option<u64>) {
+        self.rekey_interval = interval;
+    }
+    
+    /// Process bytes read from the transport
+    ///
+    /// Partial frames are buffered until the rest arrives. A replayed frame
+    /// fails with [`NoiseError::ReplayDetected`]. An error leaves the
+    /// connection unusable; the caller should close the transport.
+    pub fn receive(&mut self, data: &[u8]) -> Result<()> {
+        self.codec.feed(data);
+        while let Some(frame) = self.codec.next_frame() {
+            if self.session.is_handshake_state() {
+                self.session.read_message(&frame)?;
+                if self.session.is_handshake_state() {
+                    self.send_handshake()?;
+                } else {
+                    self.complete()?;
+                }
+            } else {
+                // Only authenticated nonces may move the window
+                let (nonce, plaintext) = self.session.decrypt_with_explicit_nonce(&frame)?;
+                if nonce < self.next_receive_nonce {
+                    return Err(NoiseError::ReplayDetected);
+                }
+                self.next_receive_nonce = nonce + 1;
+                self.actions.push_back(ConnectionAction::DeliverPlaintext(plaintext));
+            }
+        }
+        Ok(())
+    }
+    
+    /// Encrypt and send a message
+    ///
+    /// Messages sent before the handshake completes are held and sent in
+    /// order once it does.
+    pub fn send(&mut self, plaintext: &[u8]) -> Result<()> {
+        if self.session.is_handshake_state() {
+            self.queued.push(plaintext.to_vec());
+            return Ok(());
+        }
+        self.send_transport(plaintext)
+    }
+    
+    /// Move the outgoing direction to a fresh key
+    pub fn rekey(&mut self) -> Result<()> {
+        self.session.rekey_outgoing()?;
+        self.sent_in_generation = 0;
+        Ok(())
+    }
+    
+    /// Take the next action to carry out
+    pub fn poll_action(&mut self) -> Option<ConnectionAction> {
+        self.actions.pop_front()
+    }
+    
+    /// Whether the handshake has completed
+    pub fn is_established(&self) -> bool {
+        self.session.is_transport_state()
+    }
+    
+    /// The underlying session
+    pub fn session(&self) -> &NoiseSession {
+        &self.session
+    }
+    
+    /// Give up the connection, returning the session
+    pub fn into_session(self) -> NoiseSession {
+        self.session
+    }
+    
+    fn send_handshake(&mut self) -> Result<()> {
+        let message = self.session.write_message(&[])?;
+        self.actions.push_back(ConnectionAction::SendBytes(FrameCodec::encode(&message)?));
+        if self.session.is_transport_state() {
+            self.complete()?;
+        }
+        Ok(())
+    }
+    
+    fn complete(&mut self) -> Result<()> {
+        self.actions.push_back(ConnectionAction::HandshakeComplete);
+        for plaintext in std::mem::take(&mut self.queued) {
+            self.send_transport(&plaintext)?;
+        }
+        Ok(())
+    }
+    
+    fn send_transport(&mut self, plaintext: &[u8]) -> Result<()> {
+        if !self.session.is_transport_state() {
+            return Err(NoiseError::InvalidState("Handshake not complete".to_string()));
+        }
+        let frame = self.session.encrypt_with_explicit_nonce(plaintext)?;
+        self.actions.push_back(ConnectionAction::SendBytes(FrameCodec::encode(&frame)?));
+        
+        self.sent_in_generation += 1;
+        if self.rekey_interval == Some(self.sent_in_generation) {
+            self.actions.push_back(ConnectionAction::NeedsRekey);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// Carry out `from`'s actions, feeding its bytes to `to`
+    fn pump(from: &mut NoiseConnection, to: &mut NoiseConnection) -> Vec<ConnectionAction> {
+        let mut other = Vec::new();
+        while let Some(action) = from.poll_action() {
+            match action {
+                ConnectionAction::SendBytes(bytes) => to.receive(&bytes).unwrap(),
+                action => other.push(action),
+            }
+        }
+        other
+    }
+    
+    fn connected_pair() -> (NoiseConnection, NoiseConnection) {
+        let mut alice = NoiseConnection::initiator(NoiseSession::new_initiator().unwrap()).unwrap();
+        let mut bob = NoiseConnection::responder(NoiseSession::new_responder().unwrap());
+        
+        pump(&mut alice, &mut bob);
+        pump(&mut bob, &mut alice);
+        assert_eq!(pump(&mut alice, &mut bob), vec![ConnectionAction::HandshakeComplete]);
+        assert_eq!(bob.poll_action(), Some(ConnectionAction::HandshakeComplete));
+        
+        (alice, bob)
+    }
+    
+    #[test]
+    fn test_handshake_and_messages() {
+        let (mut alice, mut bob) = connected_pair();
+        assert!(alice.is_established() && bob.is_established());
+        
+        alice.send(b"hello").unwrap();
+        pump(&mut alice, &mut bob);
+        assert_eq!(bob.poll_action(), Some(ConnectionAction::DeliverPlaintext(b"hello".to_vec())));
+        
+        bob.send(b"hi").unwrap();
+        pump(&mut bob, &mut alice);
+        assert_eq!(alice.poll_action(), Some(ConnectionAction::DeliverPlaintext(b"hi".to_vec())));
+    }
+    
+    #[test]
+    fn test_queued_until_established() {
+        let mut alice = NoiseConnection::initiator(NoiseSession::new_initiator().unwrap()).unwrap();
+        let mut bob = NoiseConnection::responder(NoiseSession::new_responder().unwrap());
+        alice.send(b"early").unwrap();
+        
+        pump(&mut alice, &mut bob);
+        pump(&mut bob, &mut alice);
+        pump(&mut alice, &mut bob);
+        
+        assert_eq!(bob.poll_action(), Some(ConnectionAction::HandshakeComplete));
+        assert_eq!(bob.poll_action(), Some(ConnectionAction::DeliverPlaintext(b"early".to_vec())));
+    }
+    
+    #[test]
+    fn test_byte_at_a_time() {
+        let (mut alice, mut bob) = connected_pair();
+        alice.send(b"one").unwrap();
+        alice.send(b"two").unwrap();
+        
+        let mut wire = Vec::new();
+        while let Some(ConnectionAction::SendBytes(bytes)) = alice.poll_action() {
+            wire.extend(bytes);
+        }
+        for byte in wire {
+            bob.receive(&[byte]).unwrap();
+        }
+        assert_eq!(bob.poll_action(), Some(ConnectionAction::DeliverPlaintext(b"one".to_vec())));
+        assert_eq!(bob.poll_action(), Some(ConnectionAction::DeliverPlaintext(b"two".to_vec())));
+    }
+    
+    #[test]
+    fn test_needs_rekey() {
+        let (mut alice, mut bob) = connected_pair();
+        alice.set_rekey_interval(Some(2));
+        
+        alice.send(b"a").unwrap();
+        assert!(pump(&mut alice, &mut bob).is_empty());
+        alice.send(b"b").unwrap();
+        assert_eq!(pump(&mut alice, &mut bob), vec![ConnectionAction::NeedsRekey]);
+        
+        alice.rekey().unwrap();
+        alice.send(b"c").unwrap();
+        pump(&mut alice, &mut bob);
+        
+        let delivered: Vec<_> = std::iter::from_fn(|| bob.poll_action()).collect();
+        assert_eq!(delivered.last(), Some(&ConnectionAction::DeliverPlaintext(b"c".to_vec())));
+        assert_eq!(bob.session().receiving_generation(), 1);
+    }
+    
+    #[test]
+    fn test_replayed_frame_is_rejected() {
+        let (mut alice, mut bob) = connected_pair();
+        alice.send(b"pay 10").unwrap();
+        alice.send(b"pay 20").unwrap();
+        let frames: Vec<_> = std::iter::from_fn(|| match alice.poll_action() {
+            Some(ConnectionAction::SendBytes(bytes)) => Some(bytes),
+            _ => None,
+        }).collect();
+        
+        bob.receive(&frames[0]).unwrap();
+        bob.receive(&frames[1]).unwrap();
+        assert!(matches!(bob.receive(&frames[0]), Err(NoiseError::ReplayDetected)));
+        assert!(matches!(bob.receive(&frames[1]), Err(NoiseError::ReplayDetected)));
+        
+        let delivered: Vec<_> = std::iter::from_fn(|| bob.poll_action()).collect();
+        assert_eq!(delivered, vec![
+            ConnectionAction::DeliverPlaintext(b"pay 10".to_vec()),
+            ConnectionAction::DeliverPlaintext(b"pay 20".to_vec()),
+        ]);
+    }
+    
+    #[test]
+    fn test_tampered_frame_is_error() {
+        let (mut alice, mut bob) = connected_pair();
+        alice.send(b"secret").unwrap();
+        
+        let Some(ConnectionAction::SendBytes(mut bytes)) = alice.poll_action() else {
+            panic!("expected bytes to send");
+        };
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        assert!(bob.receive(&bytes).is_err());
+    }
+}
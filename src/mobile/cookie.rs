@@ -0,0 +1,195 @@
+//! Stateless cookie challenges for responders under load
+//!
+//! Every handshake opening costs the responder Diffie-Hellman operations,
+//! so a flood of forged openings can pin the CPU. As in WireGuard, a
+//! responder under load answers an opening with a cookie reply instead of
+//! processing it. The cookie is a MAC of the sender's source identifier
+//! under a secret that rotates every [`COOKIE_SECRET_LIFETIME`], so the
+//! responder keeps no per-sender state. The initiator resends its opening
+//! with a MAC under the cookie appended, proving it can receive at the
+//! source it claims.
+//!
+//! [`SessionManager`](crate::mobile::manager::SessionManager) uses the
+//! peer ID as the source identifier; see
+//! [`set_under_load`](crate::mobile::manager::SessionManager::set_under_load).
+
+use crate::core::crypto::{constant_time_eq, hkdf, random_bytes};
+use crate::core::error::{NoiseError, Result};
+use crate::core::now_ms;
+use snow::params::HashChoice;
+use std::time::Duration;
+use zeroize::Zeroizing;
+
+/// Length of a cookie
+pub const COOKIE_LEN: usize = 16;
+
+/// Length of the MAC an initiator appends to its opening
+pub const COOKIE_MAC_LEN: usize = 16;
+
+/// Length of a cookie reply
+pub const COOKIE_REPLY_LEN: usize = 1 + COOKIE_LEN;
+
+/// How long a cookie secret is used before it is replaced
+pub const COOKIE_SECRET_LIFETIME: Duration = Duration::from_secs(120);
+
+/// First byte of a cookie reply
+///
+/// Cookie replies are shorter than any handshake message, so the byte only
+/// guards against stray input.
+const COOKIE_REPLY_TYPE: u8 = 0xc0;
+
+/// Domain separator for cookies
+const COOKIE_LABEL: &[u8] = b"noise-mobile/cookie/v1";
+
+/// Domain separator for the MAC over an opening
+const COOKIE_MAC_LABEL: &[u8] = b"noise-mobile/cookie-mac/v1";
+
+/// A cookie to attach to a resent opening
+pub type Cookie = [u8; COOKIE_LEN];
+
+/// Issues and checks cookies on the responder
+pub struct CookieChecker {
+    secret: Zeroizing<[u8; 32]>,
+    /// The secret before the last rotation, still accepted
+    previous: Option<Zeroizing<[u8; 32]>>,
+    rotated_ms: u64,
+    under_load: bool,
+}
+
+impl Default for CookieChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CookieChecker {
+    /// Create a checker with a fresh secret, not under load
+    pub fn new() -> Self {
+        Self::new_at(now_ms())
+    }
+    
+    fn new_at(now_ms: u64) -> Self {
+        let mut secret = Zeroizing::new([0u8; 32]);
+        random_bytes(secret.as_mut());
+        Self {
+            secret,
+            previous: None,
+            rotated_ms: now_ms,
+            under_load: false,
+        }
+    }
+    
+    /// Start or stop demanding cookies
+    pub fn set_under_load(&mut self, under_load: bool) {
+        self.under_load = under_load;
+    }
+    
+    /// Whether openings without a valid cookie MAC are challenged
+    pub fn is_under_load(&self) -> bool {
+        self.under_load
+    }
+    
+    /// The cookie for a source, to send back in a [`cookie_reply`]
+    pub fn cookie(&mut self, source: &[u8]) -> Result<Cookie> {
+        self.cookie_at(source, now_ms())
+    }
+    
+    fn cookie_at(&mut self, source: &[u8], now_ms: u64) -> Result<Cookie> {
+        self.rotate_at(now_ms);
+        make_cookie(&self.secret, source)
+    }
+    
+    /// Strip a valid cookie MAC from an opening
+    ///
+    /// Returns the opening without its MAC, or `None` if it does not end
+    /// in a MAC under the source's current or previous cookie.
+    pub fn verify<'a>(&mut self, source: &[u8], message: &'a [u8]) -> Result<Option<&'a [u8]>> {
+        self.verify_at(source, message, now_ms())
+    }
+    
+    fn verify_at<'a>(&mut self, source: &[u8], message: &'a [u8], now_ms: u64) -> Result<Option<&'a [u8]>> {
+        self.rotate_at(now_ms);
+        let Some(split) = message.len().checked_sub(COOKIE_MAC_LEN) else {
+            return Ok(None);
+        };
+        let (opening, mac) = message.split_at(split);
+        for secret in std::iter::once(&self.secret).chain(&self.previous) {
+            let cookie = make_cookie(secret, source)?;
+            if constant_time_eq(&opening_mac(&cookie, opening)?, mac) {
+                return Ok(Some(opening));
+            }
+        }
+        Ok(None)
+    }
+    
+    /// Replace the secret once it has been used for its lifetime
+    fn rotate_at(&mut self, now_ms: u64) {
+        let lifetime = COOKIE_SECRET_LIFETIME.as_millis() as u64;
+        if now_ms.saturating_sub(self.rotated_ms) < lifetime {
+            return;
+        }
+        // Cookies from more than one lifetime ago are no longer accepted
+        let fresh = Self::new_at(now_ms);
+        let stale = now_ms.saturating_sub(self.rotated_ms) >= 2 * lifetime;
+        let previous = std::mem::replace(&mut self.secret, fresh.secret);
+        self.previous = (!stale).then_some(previous);
+        self.rotated_ms = now_ms;
+    }
+}
+
+/// Encode a cookie reply
+pub fn cookie_reply(cookie: &Cookie) -> Vec<u8> {
+    let mut reply = Vec::with_capacity(COOKIE_REPLY_LEN);
+    reply.push(COOKIE_REPLY_TYPE);
+    reply.extend_from_slice(cookie);
+    reply
+}
+
+/// Take the cookie out of a message, if it is a cookie reply
+pub fn parse_cookie_reply(message: &[u8]) -> Option<Cookie> {
+    match message.split_first() {
+        Some((&COOKIE_REPLY_TYPE, cookie)) => cookie.try_into().ok(),
+        _ => None,
+    }
+}
+
+/// Append the MAC under `cookie` to an opening before resending it
+pub fn attach_cookie(opening: &[u8], cookie: &Cookie) -> Result<Vec<u8>> {
+    let mut message = Vec::with_capacity(opening.len() + COOKIE_MAC_LEN);
+    message.extend_from_slice(opening);
+    message.extend_from_slice(&opening_mac(cookie, opening)?);
+    Ok(message)
+}
+
+fn make_cookie(secret: &[u8; 32], source: &[u8]) -> Result<Cookie> {
+    let cookie = hkdf(HashChoice::SHA256, secret, source, COOKIE_LABEL, COOKIE_LEN)?;
+    cookie.try_into().map_err(|_| NoiseError::InvalidParameter)
+}
+
+fn opening_mac(cookie: &Cookie, opening: &[u8]) -> Result<Vec<u8>> {
+    hkdf(HashChoice::SHA256, cookie, opening, COOKIE_MAC_LABEL, COOKIE_MAC_LEN)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_cookie_is_bound_to_source_and_expires() {
+        let start = 1_000_000;
+        let lifetime = COOKIE_SECRET_LIFETIME.as_millis() as u64;
+        let mut checker = CookieChecker::new_at(start);
+        let opening = [7u8; 32];
+        
+        let cookie = parse_cookie_reply(&cookie_reply(&checker.cookie_at(b"10.0.0.1:4500", start).unwrap())).unwrap();
+        let resent = attach_cookie(&opening, &cookie).unwrap();
+        assert_eq!(checker.verify_at(b"10.0.0.1:4500", &resent, start).unwrap(), Some(&opening[..]));
+        assert_eq!(checker.verify_at(b"10.0.0.2:4500", &resent, start).unwrap(), None);
+        assert_eq!(checker.verify_at(b"10.0.0.1:4500", &opening, start).unwrap(), None);
+        
+        // Still accepted for one rotation, then rejected
+        assert!(checker.verify_at(b"10.0.0.1:4500", &resent, start + lifetime).unwrap().is_some());
+        assert!(checker.verify_at(b"10.0.0.1:4500", &resent, start + 2 * lifetime).unwrap().is_none());
+        assert_eq!(parse_cookie_reply(&[COOKIE_REPLY_TYPE; 3]), None);
+    }
+}
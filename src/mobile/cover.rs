@@ -0,0 +1,183 @@
+//! Cover traffic for hostile networks
+//!
+//! Even with padding, the timing of messages shows when a user is active.
+//! [`CoverTraffic`] schedules dummy [`FrameType::Cover`] frames at jittered
+//! intervals while a session is up. They are encrypted like any other
+//! frame, and with [padding](crate::core::padding) on they are as long as
+//! real ones; the receiver's
+//! [`receive_frame`](ResilientSession::receive_frame) returns them as
+//! [`Frame::Cover`] to be dropped.
+//!
+//! Like [`HandshakeDriver`](crate::mobile::driver::HandshakeDriver) the
+//! scheduler does no IO: call [`on_timeout`](CoverTraffic::on_timeout) at
+//! [`next_timeout`](CoverTraffic::next_timeout) and send the frame it
+//! returns. Cover traffic costs radio time and battery, so it is off unless
+//! started.
+//!
+//! [`FrameType::Cover`]: crate::mobile::network::FrameType::Cover
+//! [`Frame::Cover`]: crate::mobile::network::Frame::Cover
+
+use crate::core::crypto::random_bytes;
+use crate::core::error::Result;
+use crate::mobile::network::ResilientSession;
+use std::time::{Duration, Instant};
+
+/// Longest interval honoured; anything longer is as good as never
+const MAX_INTERVAL: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// How [`CoverTraffic`] paces dummy frames
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CoverTrafficConfig {
+    /// Average time between frames, capped at a year
+    pub interval: Duration,
+    /// Fraction of `interval` each wait varies by, from 0.0 to 1.0
+    pub jitter: f64,
+    /// Filler bytes in each dummy frame
+    pub filler_len: usize,
+    /// Whether real messages postpone the next dummy frame, keeping the
+    /// overall rate steady instead of adding to it
+    pub defer_on_send: bool,
+}
+
+impl Default for CoverTrafficConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(30),
+            jitter: 0.5,
+            filler_len: 64,
+            defer_on_send: true,
+        }
+    }
+}
+
+/// Schedules dummy frames on a session
+pub struct CoverTraffic {
+    config: CoverTrafficConfig,
+    deadline: Option<Instant>,
+    sent: u64,
+}
+
+impl CoverTraffic {
+    /// Create a stopped scheduler
+    pub fn new(config: CoverTrafficConfig) -> Self {
+        Self {
+            config,
+            deadline: None,
+            sent: 0,
+        }
+    }
+    
+    /// Start sending cover traffic, the first frame after one interval
+    pub fn start(&mut self, now: Instant) {
+        self.deadline = now.checked_add(self.next_wait());
+    }
+    
+    /// Stop sending cover traffic, e.g. when the session closes
+    pub fn stop(&mut self) {
+        self.deadline = None;
+    }
+    
+    /// Whether cover traffic is being sent
+    pub fn is_running(&self) -> bool {
+        self.deadline.is_some()
+    }
+    
+    /// Tell the scheduler a real message was sent
+    pub fn on_send(&mut self, now: Instant) {
+        if self.config.defer_on_send && self.deadline.is_some() {
+            self.deadline = now.checked_add(self.next_wait());
+        }
+    }
+    
+    /// When [`on_timeout`](Self::on_timeout) should next be called
+    pub fn next_timeout(&self) -> Option<Instant> {
+        self.deadline
+    }
+    
+    /// Encrypt a dummy frame on `session` if one is due
+    ///
+    /// Returns the frame to send. Does nothing before
+    /// [`next_timeout`](Self::next_timeout) or while stopped.
+    pub fn on_timeout(&mut self, session: &mut ResilientSession, now: Instant) -> Result<Option<Vec<u8>>> {
+        match self.deadline {
+            Some(deadline) if now >= deadline => {}
+            _ => return Ok(None),
+        }
+        let frame = session.send_cover(self.config.filler_len)?;
+        self.deadline = now.checked_add(self.next_wait());
+        self.sent += 1;
+        Ok(Some(frame))
+    }
+    
+    /// Dummy frames sent so far
+    pub fn frames_sent(&self) -> u64 {
+        self.sent
+    }
+    
+    /// The interval, moved by up to `jitter` either way
+    fn next_wait(&self) -> Duration {
+        let mut sample = [0u8; 8];
+        random_bytes(&mut sample);
+        // Uniform in [-1, 1)
+        let unit = (u64::from_be_bytes(sample) >> 11) as f64 / (1u64 << 52) as f64 - 1.0;
+        // A NaN jitter would otherwise survive the clamp and panic below
+        let jitter = if self.config.jitter.is_nan() { 0.0 } else { self.config.jitter.clamp(0.0, 1.0) };
+        self.config.interval.min(MAX_INTERVAL).mul_f64(1.0 + jitter * unit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::perform_handshake;
+    use crate::mobile::network::Frame;
+    
+    fn connected_pair() -> (ResilientSession, ResilientSession) {
+        let (initiator, responder) = perform_handshake().unwrap();
+        (ResilientSession::new(initiator), ResilientSession::new(responder))
+    }
+    
+    #[test]
+    fn test_dummy_frames_look_like_data() {
+        let (mut alice, mut bob) = connected_pair();
+        let config = CoverTrafficConfig { filler_len: 32, ..Default::default() };
+        let mut cover = CoverTraffic::new(config);
+        let start = Instant::now();
+        assert_eq!(cover.on_timeout(&mut alice, start + config.interval * 2).unwrap(), None);
+        
+        cover.start(start);
+        let deadline = cover.next_timeout().unwrap();
+        assert!(deadline >= start + config.interval / 2 && deadline <= start + config.interval * 3 / 2);
+        assert_eq!(cover.on_timeout(&mut alice, start).unwrap(), None);
+        
+        let dummy = cover.on_timeout(&mut alice, deadline).unwrap().unwrap();
+        let real = alice.send_data(&[1u8; 32]).unwrap();
+        assert_eq!(dummy.len(), real.len());
+        assert_eq!(bob.receive_frame(&dummy).unwrap(), Frame::Cover);
+        assert!(matches!(bob.receive_frame(&real).unwrap(), Frame::Data { .. }));
+        assert_eq!(cover.frames_sent(), 1);
+        
+        // Real traffic pushes the next dummy frame back
+        let later = deadline + config.interval * 2;
+        cover.on_send(later);
+        assert!(cover.next_timeout().unwrap() > later);
+        cover.stop();
+        assert!(!cover.is_running());
+    }
+    
+    #[test]
+    fn test_extreme_config_does_not_panic() {
+        let now = Instant::now();
+        for jitter in [f64::NAN, f64::INFINITY, -1.0] {
+            let mut cover = CoverTraffic::new(CoverTrafficConfig { interval: Duration::MAX, jitter, ..Default::default() });
+            cover.start(now);
+            cover.on_send(now);
+            assert!(cover.is_running());
+        }
+        
+        // Without jitter the wait is exactly the capped interval
+        let mut cover = CoverTraffic::new(CoverTrafficConfig { interval: Duration::MAX, jitter: f64::NAN, ..Default::default() });
+        cover.start(now);
+        assert_eq!(cover.next_timeout(), Some(now + MAX_INTERVAL));
+    }
+}
@@ -0,0 +1,213 @@
+//! Connectionless operation over UDP and mesh links
+//!
+//! Each datagram is a short session ID followed by an explicit-nonce frame,
+//! so it decrypts on its own whatever was lost or reordered before it. The
+//! ID lets a responder serving many peers on one socket find the right
+//! session without trusting the source address, which changes as phones
+//! roam. Both sides derive the same ID from the handshake with
+//! [`NoiseSession::export_key`].
+//!
+//! [`DatagramEndpoint`] holds the responder's sessions by ID, each with its
+//! own replay window.
+
+use crate::core::error::{NoiseError, Result};
+use crate::core::session::NoiseSession;
+use crate::mobile::network::ResilientSession;
+use std::collections::HashMap;
+
+/// Length of the session ID at the front of every datagram
+pub const SESSION_ID_LEN: usize = 8;
+
+/// Bytes each datagram adds ahead of the ciphertext
+pub const DATAGRAM_HEADER_LEN: usize = SESSION_ID_LEN + NoiseSession::EXPLICIT_HEADER_LEN;
+
+/// Exporter label the session ID is derived under
+const SESSION_ID_LABEL: &str = "noise-mobile datagram session id";
+
+/// Identifies a session on a shared socket
+pub type SessionId = [u8; SESSION_ID_LEN];
+
+/// Read the session ID of a datagram without decrypting it
+pub fn peek_session_id(datagram: &[u8]) -> Result<SessionId> {
+    datagram
+        .get(..SESSION_ID_LEN)
+        .and_then(|id| id.try_into().ok())
+        .ok_or(NoiseError::InvalidMessage)
+}
+
+/// A session sending and receiving self-contained datagrams
+pub struct DatagramSession {
+    id: SessionId,
+    inner: ResilientSession,
+}
+
+impl DatagramSession {
+    /// Wrap an established session, deriving its ID from the handshake
+    pub fn new(session: NoiseSession) -> Result<Self> {
+        let id = session.export_key(SESSION_ID_LABEL, &[], SESSION_ID_LEN)?;
+        let id = id.try_into().map_err(|_| NoiseError::InvalidState("Bad session ID length".to_string()))?;
+        Ok(Self::with_id(session, id))
+    }
+    
+    /// Wrap a session under an ID agreed some other way
+    pub fn with_id(session: NoiseSession, id: SessionId) -> Self {
+        Self {
+            id,
+            inner: ResilientSession::with_explicit_nonces(session),
+        }
+    }
+    
+    /// The ID carried by this session's datagrams
+    pub fn id(&self) -> SessionId {
+        self.id
+    }
+    
+    /// Encrypt a message into a datagram
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let frame = self.inner.encrypt_with_sequence(plaintext)?;
+        let mut datagram = Vec::with_capacity(SESSION_ID_LEN + frame.len());
+        datagram.extend_from_slice(&self.id);
+        datagram.extend_from_slice(&frame);
+        Ok(datagram)
+    }
+    
+    /// Decrypt a datagram, rejecting replays and other sessions' datagrams
+    pub fn decrypt(&mut self, datagram: &[u8]) -> Result<Vec<u8>> {
+        if peek_session_id(datagram)? != self.id {
+            return Err(NoiseError::InvalidMessage);
+        }
+        self.inner.decrypt_with_replay_check(&datagram[SESSION_ID_LEN..])
+    }
+    
+    /// The session with its replay window
+    pub fn resilient(&self) -> &ResilientSession {
+        &self.inner
+    }
+    
+    /// Mutable access to the session, e.g. to rekey
+    pub fn resilient_mut(&mut self) -> &mut ResilientSession {
+        &mut self.inner
+    }
+}
+
+/// A responder's datagram sessions, looked up by session ID
+#[derive(Default)]
+pub struct DatagramEndpoint {
+    sessions: HashMap<SessionId, DatagramSession>,
+}
+
+impl DatagramEndpoint {
+    /// Create an endpoint with no sessions
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Add a session, returning its ID
+    ///
+    /// Fails with [`NoiseError::InvalidState`] if the ID is already taken.
+    pub fn insert(&mut self, session: DatagramSession) -> Result<SessionId> {
+        let id = session.id();
+        if self.sessions.contains_key(&id) {
+            return Err(NoiseError::InvalidState("Session ID already in use".to_string()));
+        }
+        self.sessions.insert(id, session);
+        Ok(id)
+    }
+    
+    /// Remove a session
+    pub fn remove(&mut self, id: &SessionId) -> Option<DatagramSession> {
+        self.sessions.remove(id)
+    }
+    
+    /// Look up a session
+    pub fn get_mut(&mut self, id: &SessionId) -> Option<&mut DatagramSession> {
+        self.sessions.get_mut(id)
+    }
+    
+    /// Decrypt a datagram on whichever session it belongs to
+    ///
+    /// Datagrams for unknown sessions fail with
+    /// [`NoiseError::InvalidParameter`].
+    pub fn decrypt(&mut self, datagram: &[u8]) -> Result<(SessionId, Vec<u8>)> {
+        let id = peek_session_id(datagram)?;
+        let session = self.sessions.get_mut(&id).ok_or(NoiseError::InvalidParameter)?;
+        Ok((id, session.decrypt(datagram)?))
+    }
+    
+    /// Encrypt a datagram for a session
+    pub fn encrypt(&mut self, id: &SessionId, plaintext: &[u8]) -> Result<Vec<u8>> {
+        self.sessions.get_mut(id).ok_or(NoiseError::InvalidParameter)?.encrypt(plaintext)
+    }
+    
+    /// Number of sessions
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+    
+    /// Whether the endpoint has no sessions
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::perform_handshake;
+    
+    fn connected_pair() -> (DatagramSession, DatagramSession) {
+        let (initiator, responder) = perform_handshake().unwrap();
+        (DatagramSession::new(initiator).unwrap(), DatagramSession::new(responder).unwrap())
+    }
+    
+    #[test]
+    fn test_reordered_datagrams_and_replays() {
+        let (mut alice, mut bob) = connected_pair();
+        assert_eq!(alice.id(), bob.id());
+        
+        let datagrams: Vec<_> = (0..4u8).map(|i| alice.encrypt(&[i]).unwrap()).collect();
+        assert_eq!(datagrams[0].len(), DATAGRAM_HEADER_LEN + 1 + 16);
+        
+        // Lost and reordered datagrams still decrypt; replays do not
+        assert_eq!(bob.decrypt(&datagrams[3]).unwrap(), [3]);
+        assert_eq!(bob.decrypt(&datagrams[1]).unwrap(), [1]);
+        assert!(matches!(bob.decrypt(&datagrams[3]), Err(NoiseError::ReplayDetected)));
+        
+        let reply = bob.encrypt(b"ack").unwrap();
+        assert_eq!(alice.decrypt(&reply).unwrap(), b"ack");
+        assert!(DatagramSession::new(NoiseSession::new_initiator().unwrap()).is_err());
+    }
+    
+    #[test]
+    fn test_endpoint_demultiplexes_sessions() {
+        let mut endpoint = DatagramEndpoint::new();
+        let (mut alice, alice_at_server) = connected_pair();
+        let (mut carol, carol_at_server) = connected_pair();
+        let alice_id = endpoint.insert(alice_at_server).unwrap();
+        let carol_id = endpoint.insert(carol_at_server).unwrap();
+        assert_ne!(alice_id, carol_id);
+        assert_eq!(endpoint.len(), 2);
+        
+        let from_carol = carol.encrypt(b"carol").unwrap();
+        let from_alice = alice.encrypt(b"alice").unwrap();
+        assert_eq!(endpoint.decrypt(&from_carol).unwrap(), (carol_id, b"carol".to_vec()));
+        assert_eq!(endpoint.decrypt(&from_alice).unwrap(), (alice_id, b"alice".to_vec()));
+        
+        // Each session keeps its own replay window
+        assert!(matches!(endpoint.decrypt(&from_alice), Err(NoiseError::ReplayDetected)));
+        let second = carol.encrypt(b"again").unwrap();
+        assert_eq!(endpoint.decrypt(&second).unwrap().1, b"again");
+        
+        // A datagram relabelled with another session's ID fails to decrypt
+        let mut forged = alice.encrypt(b"x").unwrap();
+        forged[..SESSION_ID_LEN].copy_from_slice(&carol_id);
+        assert!(endpoint.decrypt(&forged).is_err());
+        
+        let reply = endpoint.encrypt(&alice_id, b"hi alice").unwrap();
+        assert_eq!(alice.decrypt(&reply).unwrap(), b"hi alice");
+        
+        endpoint.remove(&alice_id);
+        assert!(matches!(endpoint.decrypt(&from_alice), Err(NoiseError::InvalidParameter)));
+        assert!(peek_session_id(&[0; 3]).is_err());
+    }
+}
@@ -0,0 +1,271 @@
+//! Handshake retransmission for lossy datagram links
+//!
+//! Over BLE advertisements or UDP a lost handshake message stalls the
+//! session forever. [`HandshakeDriver`] keeps the last message it sent and
+//! sends it again with exponential backoff until the peer answers, failing
+//! with [`NoiseError::HandshakeTimeout`] after the configured attempts.
+//!
+//! Like [`NoiseConnection`](crate::mobile::connection::NoiseConnection) it
+//! does no IO. Feed it incoming datagrams with
+//! [`receive`](HandshakeDriver::receive), call
+//! [`on_timeout`](HandshakeDriver::on_timeout) at
+//! [`next_timeout`](HandshakeDriver::next_timeout), and send whatever
+//! [`poll_transmit`](HandshakeDriver::poll_transmit) returns.
+//!
+//! A duplicate of the peer's last message means our reply was lost, so it
+//! is answered with our last message again, even after the handshake has
+//! completed. The side that sends the final message only learns it arrived
+//! from the peer's first transport message; keep the driver until then.
+//! [`receive`](HandshakeDriver::receive) decrypts and returns transport
+//! messages that arrive once the handshake is complete.
+
+use crate::core::error::{NoiseError, Result};
+use crate::core::session::NoiseSession;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How a [`HandshakeDriver`] retransmits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetransmitPolicy {
+    /// Wait before the first retransmission
+    pub initial_timeout: Duration,
+    /// Upper bound on the doubled wait
+    pub max_timeout: Duration,
+    /// Times a message is sent, counting the first, before giving up
+    pub max_attempts: u32,
+}
+
+impl Default for RetransmitPolicy {
+    fn default() -> Self {
+        Self {
+            initial_timeout: Duration::from_millis(500),
+            max_timeout: Duration::from_secs(8),
+            max_attempts: 5,
+        }
+    }
+}
+
+/// Drives a handshake to completion over an unreliable transport
+pub struct HandshakeDriver {
+    session: NoiseSession,
+    policy: RetransmitPolicy,
+    transmit: VecDeque<Vec<u8>>,
+    last_sent: Option<Vec<u8>>,
+    last_received: Option<Vec<u8>>,
+    /// Transmissions of `last_sent` so far
+    attempts: u32,
+    /// When to retransmit, while waiting for the peer
+    deadline: Option<Instant>,
+}
+
+impl HandshakeDriver {
+    /// Start as initiator, queueing the first handshake message
+    pub fn initiator(session: NoiseSession, now: Instant) -> Result<Self> {
+        let mut driver = Self::responder(session);
+        driver.send_next(now)?;
+        Ok(driver)
+    }
+    
+    /// Start as responder, waiting for the peer's first message
+    pub fn responder(session: NoiseSession) -> Self {
+        Self {
+            session,
+            policy: RetransmitPolicy::default(),
+            transmit: VecDeque::new(),
+            last_sent: None,
+            last_received: None,
+            attempts: 0,
+            deadline: None,
+        }
+    }
+    
+    /// Change the retransmission policy
+    ///
+    /// Applies from the next message sent.
+    pub fn set_retransmit_policy(&mut self, policy: RetransmitPolicy) {
+        self.policy = policy;
+    }
+    
+    /// Process a datagram from the peer
+    ///
+    /// Returns the plaintext of a transport message received after the
+    /// handshake completed, and `None` for handshake messages. A handshake
+    /// message that fails to decrypt leaves the handshake unusable; start
+    /// over with a new session.
+    pub fn receive(&mut self, message: &[u8], now: Instant) -> Result<Option<Vec<u8>>> {
+        if self.last_received.as_deref() == Some(message) {
+            if let Some(last_sent) = &self.last_sent {
+                self.transmit.push_back(last_sent.clone());
+            }
+            return Ok(None);
+        }
+        if !self.session.is_handshake_state() {
+            return self.session.decrypt(message).map(Some);
+        }
+        
+        self.session.read_message(message)?;
+        self.last_received = Some(message.to_vec());
+        self.deadline = None;
+        if self.session.is_handshake_state() {
+            self.send_next(now)?;
+        }
+        Ok(None)
+    }
+    
+    /// Retransmit or give up if the peer has not answered in time
+    ///
+    /// Does nothing before [`next_timeout`](Self::next_timeout).
+    pub fn on_timeout(&mut self, now: Instant) -> Result<()> {
+        let (Some(deadline), Some(last_sent)) = (self.deadline, &self.last_sent) else {
+            return Ok(());
+        };
+        if now < deadline {
+            return Ok(());
+        }
+        if self.attempts >= self.policy.max_attempts {
+            self.deadline = None;
+            return Err(NoiseError::HandshakeTimeout);
+        }
+        
+        self.transmit.push_back(last_sent.clone());
+        self.attempts += 1;
+        self.deadline = Some(now + self.backoff());
+        Ok(())
+    }
+    
+    /// When [`on_timeout`](Self::on_timeout) should next be called, if the
+    /// driver is waiting for the peer
+    pub fn next_timeout(&self) -> Option<Instant> {
+        self.deadline
+    }
+    
+    /// Take the next datagram to send
+    pub fn poll_transmit(&mut self) -> Option<Vec<u8>> {
+        self.transmit.pop_front()
+    }
+    
+    /// Whether this side has finished the handshake
+    pub fn is_complete(&self) -> bool {
+        self.session.is_transport_state()
+    }
+    
+    /// The underlying session
+    pub fn session(&self) -> &NoiseSession {
+        &self.session
+    }
+    
+    /// The underlying session, e.g. to send the first transport message
+    pub fn session_mut(&mut self) -> &mut NoiseSession {
+        &mut self.session
+    }
+    
+    /// Give up the driver, returning the session
+    pub fn into_session(self) -> NoiseSession {
+        self.session
+    }
+    
+    fn send_next(&mut self, now: Instant) -> Result<()> {
+        let message = self.session.write_message(&[])?;
+        self.transmit.push_back(message.clone());
+        self.last_sent = Some(message);
+        self.attempts = 1;
+        // Nothing answers the final message, so there is nothing to wait for
+        self.deadline = self.session.is_handshake_state().then(|| now + self.backoff());
+        Ok(())
+    }
+    
+    /// Wait after the current attempt, doubling from the initial timeout
+    fn backoff(&self) -> Duration {
+        let doublings = self.attempts.saturating_sub(1).min(31);
+        self.policy
+            .initial_timeout
+            .saturating_mul(1 << doublings)
+            .min(self.policy.max_timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const TICK: Duration = Duration::from_millis(500);
+    
+    fn drivers(now: Instant) -> (HandshakeDriver, HandshakeDriver) {
+        let alice = HandshakeDriver::initiator(NoiseSession::new_initiator().unwrap(), now).unwrap();
+        let bob = HandshakeDriver::responder(NoiseSession::new_responder().unwrap());
+        (alice, bob)
+    }
+    
+    /// Deliver everything `from` has queued, optionally losing it
+    fn pump(from: &mut HandshakeDriver, to: &mut HandshakeDriver, now: Instant, lose: bool) {
+        while let Some(message) = from.poll_transmit() {
+            if !lose {
+                to.receive(&message, now).unwrap();
+            }
+        }
+    }
+    
+    #[test]
+    fn test_recovers_from_lost_messages() {
+        let start = Instant::now();
+        let (mut alice, mut bob) = drivers(start);
+        
+        // Message 1 is lost and resent after the initial timeout
+        pump(&mut alice, &mut bob, start, true);
+        assert_eq!(alice.next_timeout(), Some(start + TICK));
+        alice.on_timeout(start + TICK).unwrap();
+        assert_eq!(alice.next_timeout(), Some(start + 3 * TICK));
+        pump(&mut alice, &mut bob, start + TICK, false);
+        
+        // Message 3 is lost; Bob resends message 2 and Alice answers again
+        pump(&mut bob, &mut alice, start + TICK, false);
+        assert!(alice.is_complete());
+        assert_eq!(alice.next_timeout(), None);
+        pump(&mut alice, &mut bob, start + TICK, true);
+        bob.on_timeout(start + 2 * TICK).unwrap();
+        pump(&mut bob, &mut alice, start + 2 * TICK, false);
+        pump(&mut alice, &mut bob, start + 2 * TICK, false);
+        assert!(bob.is_complete());
+        assert_eq!(bob.next_timeout(), None);
+        
+        // Bob's first transport message reaches Alice's driver
+        let ciphertext = bob.session_mut().encrypt(b"first").unwrap();
+        assert_eq!(alice.receive(&ciphertext, start + 2 * TICK).unwrap(), Some(b"first".to_vec()));
+        
+        let mut alice = alice.into_session();
+        let mut bob = bob.into_session();
+        let ciphertext = alice.encrypt(b"made it").unwrap();
+        assert_eq!(bob.decrypt(&ciphertext).unwrap(), b"made it");
+    }
+    
+    #[test]
+    fn test_times_out_with_backoff() {
+        let start = Instant::now();
+        let (mut alice, _bob) = drivers(start);
+        alice.set_retransmit_policy(RetransmitPolicy {
+            initial_timeout: TICK,
+            max_timeout: 2 * TICK,
+            max_attempts: 3,
+        });
+        assert!(alice.poll_transmit().is_some());
+        
+        // Too early: nothing happens
+        alice.on_timeout(start).unwrap();
+        assert!(alice.poll_transmit().is_none());
+        
+        let mut sent = 1;
+        let err = loop {
+            let deadline = alice.next_timeout().unwrap();
+            match alice.on_timeout(deadline) {
+                Ok(()) => {
+                    assert!(alice.poll_transmit().is_some());
+                    sent += 1;
+                }
+                Err(err) => break err,
+            }
+        };
+        assert!(matches!(err, NoiseError::HandshakeTimeout));
+        assert_eq!(sent, 3);
+        assert_eq!(alice.next_timeout(), None);
+    }
+}
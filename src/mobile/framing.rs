@@ -0,0 +1,121 @@
+//! Length-prefixed framing for stream transports
+//!
+//! Noise messages carry no length of their own, so over byte streams (TCP,
+//! L2CAP) each message is sent as a 2-byte big-endian length followed by the
+//! message itself. [`FrameCodec`] handles frames split across reads and
+//! several frames arriving in a single read.
+
+use crate::core::crypto::NOISE_MAX_MESSAGE_LEN;
+use crate::core::error::{NoiseError, Result};
+
+/// Length of the frame header
+pub const FRAME_HEADER_LEN: usize = 2;
+
+/// Encoder and incremental decoder for 2-byte length-prefixed frames
+#[derive(Default)]
+pub struct FrameCodec {
+    buffer: Vec<u8>,
+}
+
+impl FrameCodec {
+    /// Create a codec with an empty receive buffer
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Frame a message for sending
+    pub fn encode(message: &[u8]) -> Result<Vec<u8>> {
+        let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + message.len());
+        Self::encode_into(message, &mut frame)?;
+        Ok(frame)
+    }
+    
+    /// Append a framed message to `out`
+    pub fn encode_into(message: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        if message.len() > NOISE_MAX_MESSAGE_LEN {
+            return Err(NoiseError::InvalidParameter);
+        }
+        out.extend_from_slice(&(message.len() as u16).to_be_bytes());
+        out.extend_from_slice(message);
+        Ok(())
+    }
+    
+    /// Buffer bytes read from the stream
+    pub fn feed(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+    
+    /// Take the next complete message, if one has been buffered
+    pub fn next_frame(&mut self) -> Option<Vec<u8>> {
+        let header: [u8; FRAME_HEADER_LEN] = self.buffer.get(..FRAME_HEADER_LEN)?.try_into().ok()?;
+        let end = FRAME_HEADER_LEN + u16::from_be_bytes(header) as usize;
+        if self.buffer.len() < end {
+            return None;
+        }
+        
+        let message = self.buffer[FRAME_HEADER_LEN..end].to_vec();
+        self.buffer.drain(..end);
+        Some(message)
+    }
+    
+    /// Buffer bytes read from the stream and return every completed message
+    pub fn decode(&mut self, data: &[u8]) -> Vec<Vec<u8>> {
+        self.feed(data);
+        std::iter::from_fn(|| self.next_frame()).collect()
+    }
+    
+    /// Number of bytes buffered towards an incomplete frame
+    pub fn buffered_len(&self) -> usize {
+        self.buffer.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_roundtrip() {
+        let mut codec = FrameCodec::new();
+        let frame = FrameCodec::encode(b"hello").unwrap();
+        assert_eq!(frame, [0, 5, b'h', b'e', b'l', b'l', b'o']);
+        
+        assert_eq!(codec.decode(&frame), vec![b"hello".to_vec()]);
+        assert_eq!(codec.buffered_len(), 0);
+    }
+    
+    #[test]
+    fn test_partial_reads() {
+        let mut codec = FrameCodec::new();
+        let frame = FrameCodec::encode(&[7u8; 300]).unwrap();
+        
+        // Split inside the header and inside the body
+        assert!(codec.decode(&frame[..1]).is_empty());
+        assert!(codec.decode(&frame[1..100]).is_empty());
+        assert_eq!(codec.buffered_len(), 100);
+        
+        let messages = codec.decode(&frame[100..]);
+        assert_eq!(messages, vec![vec![7u8; 300]]);
+    }
+    
+    #[test]
+    fn test_coalesced_frames() {
+        let mut codec = FrameCodec::new();
+        let mut stream = Vec::new();
+        FrameCodec::encode_into(b"one", &mut stream).unwrap();
+        FrameCodec::encode_into(b"", &mut stream).unwrap();
+        FrameCodec::encode_into(b"three", &mut stream).unwrap();
+        FrameCodec::encode_into(b"four", &mut stream).unwrap();
+        
+        // Last frame arrives incomplete
+        let messages = codec.decode(&stream[..stream.len() - 2]);
+        assert_eq!(messages, vec![b"one".to_vec(), Vec::new(), b"three".to_vec()]);
+        assert_eq!(codec.decode(&stream[stream.len() - 2..]), vec![b"four".to_vec()]);
+    }
+    
+    #[test]
+    fn test_oversized_message_rejected() {
+        assert!(FrameCodec::encode(&vec![0u8; NOISE_MAX_MESSAGE_LEN]).is_ok());
+        assert!(FrameCodec::encode(&vec![0u8; NOISE_MAX_MESSAGE_LEN + 1]).is_err());
+    }
+}
@@ -0,0 +1,446 @@
+//! Sender-keys group messaging
+//!
+//! Each member of a [`GroupSession`] encrypts to the whole group with its
+//! own symmetric chain key, so a message is encrypted and signed once no
+//! matter how many members there are. The chain key advances with every
+//! message; members learn each other's chains from distribution messages
+//! sent over the existing pairwise Noise sessions.
+//!
+//! Messages are signed with a per-chain signing key, so members can decrypt
+//! each other's messages but cannot forge them.
+//!
+//! Removing a member rotates the local chain, and every remaining member
+//! must do the same and redistribute: until they have, the removed member
+//! can still read their messages. New members receive the current chain
+//! position and cannot derive keys for earlier messages, so adding one
+//! needs no rotation.
+
+use crate::core::crypto::{aead_open, aead_seal, chain_step, random_bytes};
+use crate::core::error::{NoiseError, Result};
+use crate::core::keys::{verify_signature, Keypair, KEY_LEN, SIGNATURE_LEN};
+use crate::core::session::NoiseSession;
+use crate::mobile::snapshot::Reader;
+use std::collections::{BTreeMap, HashMap};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// Format version of group messages, distributions and saved state
+const GROUP_VERSION: u8 = 1;
+
+/// Domain separator for group message signatures
+const GROUP_LABEL: &[u8] = b"noise-mobile/group/v1";
+
+/// Header bytes before the ciphertext: version, key id and iteration
+const HEADER_LEN: usize = 9;
+
+/// Most message keys kept per member for out-of-order delivery
+pub const MAX_SKIPPED_MESSAGE_KEYS: usize = 1000;
+
+/// The local member's sending chain
+#[derive(Zeroize, ZeroizeOnDrop)]
+struct SenderChain {
+    key_id: u32,
+    iteration: u32,
+    chain_key: [u8; KEY_LEN],
+    signing: Keypair,
+}
+
+impl SenderChain {
+    fn generate(key_id: u32) -> Result<Self> {
+        let mut chain_key = [0u8; KEY_LEN];
+        random_bytes(&mut chain_key);
+        Ok(Self {
+            key_id,
+            iteration: 0,
+            chain_key,
+            signing: Keypair::generate()?,
+        })
+    }
+}
+
+/// Another member's chain, as learned from their distribution message
+struct ReceiverChain {
+    key_id: u32,
+    iteration: u32,
+    chain_key: [u8; KEY_LEN],
+    signing_key: [u8; KEY_LEN],
+    /// Keys for messages skipped over, by iteration
+    skipped: BTreeMap<u32, [u8; KEY_LEN]>,
+}
+
+impl Drop for ReceiverChain {
+    fn drop(&mut self) {
+        self.chain_key.zeroize();
+        self.skipped.values_mut().for_each(Zeroize::zeroize);
+    }
+}
+
+/// Group state for one local member
+pub struct GroupSession {
+    group_id: String,
+    own: SenderChain,
+    /// Member id -> their chain, once distributed
+    members: HashMap<String, Option<ReceiverChain>>,
+}
+
+impl GroupSession {
+    /// Create a group with a fresh sending chain and no other members
+    pub fn new(group_id: &str) -> Result<Self> {
+        let mut key_id = [0u8; 4];
+        random_bytes(&mut key_id);
+        Ok(Self {
+            group_id: group_id.to_string(),
+            own: SenderChain::generate(u32::from_be_bytes(key_id))?,
+            members: HashMap::new(),
+        })
+    }
+    
+    /// The group this session belongs to
+    pub fn group_id(&self) -> &str {
+        &self.group_id
+    }
+    
+    /// Ids of the other members
+    pub fn members(&self) -> impl Iterator<Item = &str> {
+        self.members.keys().map(String::as_str)
+    }
+    
+    /// Add a member; send them [`GroupSession::distribute`] and accept theirs
+    pub fn add_member(&mut self, member_id: &str) {
+        self.members.entry(member_id.to_string()).or_insert(None);
+    }
+    
+    /// Remove a member and rotate the local sending chain
+    ///
+    /// Send the new chain to every remaining member with
+    /// [`GroupSession::distribute`].
+    pub fn remove_member(&mut self, member_id: &str) -> Result<()> {
+        if self.members.remove(member_id).is_none() {
+            return Err(NoiseError::InvalidParameter);
+        }
+        self.rotate()
+    }
+    
+    /// Replace the local sending chain with a fresh one
+    pub fn rotate(&mut self) -> Result<()> {
+        self.own = SenderChain::generate(self.own.key_id.wrapping_add(1))?;
+        Ok(())
+    }
+    
+    /// Encrypt the local chain for one member over their pairwise session
+    pub fn distribute(&self, session: &mut NoiseSession) -> Result<Vec<u8>> {
+        let mut message = self.distribution_prefix();
+        message.extend_from_slice(&self.own.key_id.to_be_bytes());
+        message.extend_from_slice(&self.own.iteration.to_be_bytes());
+        message.extend_from_slice(&self.own.chain_key);
+        message.extend_from_slice(self.own.signing.public_key());
+        let ciphertext = session.encrypt(&message);
+        message.zeroize();
+        ciphertext
+    }
+    
+    /// Accept a member's chain from a [`GroupSession::distribute`] message
+    ///
+    /// The member must have been added first. A chain with a new key id
+    /// replaces the old one; the same key id again is ignored.
+    pub fn receive_distribution(&mut self, member_id: &str, session: &mut NoiseSession, ciphertext: &[u8]) -> Result<()> {
+        let mut message = session.decrypt(ciphertext)?;
+        let result = parse_distribution(&message, &self.distribution_prefix());
+        message.zeroize();
+        let chain = result?;
+        
+        let slot = self.members.get_mut(member_id).ok_or(NoiseError::InvalidState("not a group member".to_string()))?;
+        if slot.as_ref().is_none_or(|current| current.key_id != chain.key_id) {
+            *slot = Some(chain);
+        }
+        Ok(())
+    }
+    
+    fn distribution_prefix(&self) -> Vec<u8> {
+        let mut prefix = vec![GROUP_VERSION];
+        prefix.extend_from_slice(&(self.group_id.len() as u16).to_be_bytes());
+        prefix.extend_from_slice(self.group_id.as_bytes());
+        prefix
+    }
+    
+    /// Encrypt a message for every member, advancing the local chain
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let next_iteration = self.own.iteration.checked_add(1).ok_or(NoiseError::InvalidState("sender chain exhausted".to_string()))?;
+        let (next, mut message_key) = chain_step(&self.own.chain_key)?;
+        let mut message = vec![GROUP_VERSION];
+        message.extend_from_slice(&self.own.key_id.to_be_bytes());
+        message.extend_from_slice(&self.own.iteration.to_be_bytes());
+        let ciphertext = aead_seal(&message_key, &self.associated_data(&message), plaintext);
+        message_key.zeroize();
+        message.extend_from_slice(&ciphertext?);
+        
+        let signature = self.own.signing.sign(&self.associated_data(&message))?;
+        message.extend_from_slice(&signature);
+        self.own.chain_key = next;
+        self.own.iteration = next_iteration;
+        Ok(message)
+    }
+    
+    /// Decrypt a message from `member_id`
+    ///
+    /// Messages may arrive out of order, up to [`MAX_SKIPPED_MESSAGE_KEYS`]
+    /// ahead. Each is accepted once; repeats fail with
+    /// [`NoiseError::ReplayDetected`].
+    pub fn decrypt(&mut self, member_id: &str, message: &[u8]) -> Result<Vec<u8>> {
+        if message.len() < HEADER_LEN + SIGNATURE_LEN || message[0] != GROUP_VERSION {
+            return Err(NoiseError::InvalidMessage);
+        }
+        let (signed, signature) = message.split_at(message.len() - SIGNATURE_LEN);
+        let (header, ciphertext) = signed.split_at(HEADER_LEN);
+        let key_id = u32::from_be_bytes(header[1..5].try_into().map_err(|_| NoiseError::InvalidMessage)?);
+        let iteration = u32::from_be_bytes(header[5..9].try_into().map_err(|_| NoiseError::InvalidMessage)?);
+        let aad = self.associated_data(header);
+        let signed_data = self.associated_data(signed);
+        
+        let chain = self
+            .members
+            .get_mut(member_id)
+            .and_then(Option::as_mut)
+            .filter(|chain| chain.key_id == key_id)
+            .ok_or(NoiseError::InvalidState("no sender key for this message".to_string()))?;
+        if !verify_signature(&chain.signing_key, &signed_data, signature) {
+            return Err(NoiseError::DecryptionFailed);
+        }
+        
+        if iteration < chain.iteration {
+            let mut message_key = chain.skipped.remove(&iteration).ok_or(NoiseError::ReplayDetected)?;
+            let plaintext = aead_open(&message_key, &aad, ciphertext);
+            message_key.zeroize();
+            return plaintext;
+        }
+        if (iteration - chain.iteration) as usize > MAX_SKIPPED_MESSAGE_KEYS {
+            return Err(NoiseError::InvalidMessage);
+        }
+        
+        // Derive up to the message without touching the chain until it decrypts
+        let mut chain_key = chain.chain_key;
+        let mut skipped = Vec::new();
+        for i in chain.iteration..iteration {
+            let (next, message_key) = chain_step(&chain_key)?;
+            skipped.push((i, message_key));
+            chain_key = next;
+        }
+        let (next, mut message_key) = chain_step(&chain_key)?;
+        chain_key.zeroize();
+        let plaintext = aead_open(&message_key, &aad, ciphertext);
+        message_key.zeroize();
+        if plaintext.is_err() {
+            skipped.iter_mut().for_each(|(_, key)| key.zeroize());
+        } else {
+            chain.skipped.extend(skipped);
+            while chain.skipped.len() > MAX_SKIPPED_MESSAGE_KEYS {
+                if let Some((_, mut oldest)) = chain.skipped.pop_first() {
+                    oldest.zeroize();
+                }
+            }
+            chain.chain_key = next;
+            chain.iteration = iteration + 1;
+        }
+        plaintext
+    }
+    
+    fn associated_data(&self, data: &[u8]) -> Vec<u8> {
+        let mut aad = GROUP_LABEL.to_vec();
+        aad.extend_from_slice(&self.distribution_prefix());
+        aad.extend_from_slice(data);
+        aad
+    }
+    
+    /// Encode the group state, including every chain key, into bytes
+    ///
+    /// The output contains live key material and must be wrapped before it
+    /// reaches disk. Like [`crate::mobile::manager::SessionManager::serialize`]
+    /// it is single-use: encrypting from both a restored copy and this one
+    /// reuses message keys.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = self.distribution_prefix();
+        data.extend_from_slice(&self.own.key_id.to_be_bytes());
+        data.extend_from_slice(&self.own.iteration.to_be_bytes());
+        data.extend_from_slice(&self.own.chain_key);
+        data.extend_from_slice(self.own.signing.private_key());
+        
+        data.extend_from_slice(&(self.members.len() as u32).to_be_bytes());
+        for (member_id, chain) in &self.members {
+            data.extend_from_slice(&(member_id.len() as u16).to_be_bytes());
+            data.extend_from_slice(member_id.as_bytes());
+            let Some(chain) = chain else {
+                data.push(0);
+                continue;
+            };
+            data.push(1);
+            data.extend_from_slice(&chain.key_id.to_be_bytes());
+            data.extend_from_slice(&chain.iteration.to_be_bytes());
+            data.extend_from_slice(&chain.chain_key);
+            data.extend_from_slice(&chain.signing_key);
+            data.extend_from_slice(&(chain.skipped.len() as u16).to_be_bytes());
+            for (iteration, key) in &chain.skipped {
+                data.extend_from_slice(&iteration.to_be_bytes());
+                data.extend_from_slice(key);
+            }
+        }
+        data
+    }
+    
+    /// Rebuild a group from [`GroupSession::serialize`] output
+    pub fn restore(data: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(data);
+        if reader.take(1)?[0] != GROUP_VERSION {
+            return Err(NoiseError::InvalidMessage);
+        }
+        let group_id = read_string(&mut reader)?;
+        let own = SenderChain {
+            key_id: reader.u32()?,
+            iteration: reader.u32()?,
+            chain_key: read_key(&mut reader)?,
+            signing: Keypair::from_private(reader.take(KEY_LEN)?)?,
+        };
+        
+        let mut members = HashMap::new();
+        for _ in 0..reader.u32()? {
+            let member_id = read_string(&mut reader)?;
+            let chain = match reader.take(1)?[0] {
+                0 => None,
+                1 => {
+                    let mut chain = ReceiverChain {
+                        key_id: reader.u32()?,
+                        iteration: reader.u32()?,
+                        chain_key: read_key(&mut reader)?,
+                        signing_key: read_key(&mut reader)?,
+                        skipped: BTreeMap::new(),
+                    };
+                    for _ in 0..reader.u16()? {
+                        let iteration = reader.u32()?;
+                        chain.skipped.insert(iteration, read_key(&mut reader)?);
+                    }
+                    Some(chain)
+                }
+                _ => return Err(NoiseError::InvalidMessage),
+            };
+            members.insert(member_id, chain);
+        }
+        
+        if !reader.is_empty() {
+            return Err(NoiseError::InvalidMessage);
+        }
+        Ok(Self { group_id, own, members })
+    }
+}
+
+fn parse_distribution(message: &[u8], prefix: &[u8]) -> Result<ReceiverChain> {
+    let body = message.strip_prefix(prefix).ok_or(NoiseError::InvalidMessage)?;
+    let mut reader = Reader::new(body);
+    let chain = ReceiverChain {
+        key_id: reader.u32()?,
+        iteration: reader.u32()?,
+        chain_key: read_key(&mut reader)?,
+        signing_key: read_key(&mut reader)?,
+        skipped: BTreeMap::new(),
+    };
+    if !reader.is_empty() {
+        return Err(NoiseError::InvalidMessage);
+    }
+    Ok(chain)
+}
+
+fn read_key(reader: &mut Reader<'_>) -> Result<[u8; KEY_LEN]> {
+    reader.take(KEY_LEN)?.try_into().map_err(|_| NoiseError::InvalidMessage)
+}
+
+fn read_string(reader: &mut Reader<'_>) -> Result<String> {
+    let len = reader.u16()? as usize;
+    std::str::from_utf8(reader.take(len)?)
+        .map(str::to_string)
+        .map_err(|_| NoiseError::InvalidMessage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::perform_handshake;
+    
+    /// Alice and Bob in one group, each holding the other's chain
+    fn create_group() -> (GroupSession, GroupSession, NoiseSession, NoiseSession) {
+        let (mut alice_to_bob, mut bob_to_alice) = perform_handshake().unwrap();
+        let mut alice = GroupSession::new("friends").unwrap();
+        let mut bob = GroupSession::new("friends").unwrap();
+        alice.add_member("bob");
+        bob.add_member("alice");
+        
+        let dist = alice.distribute(&mut alice_to_bob).unwrap();
+        bob.receive_distribution("alice", &mut bob_to_alice, &dist).unwrap();
+        let dist = bob.distribute(&mut bob_to_alice).unwrap();
+        alice.receive_distribution("bob", &mut alice_to_bob, &dist).unwrap();
+        (alice, bob, alice_to_bob, bob_to_alice)
+    }
+    
+    #[test]
+    fn test_group_messages_out_of_order() {
+        let (mut alice, mut bob, _, _) = create_group();
+        let first = alice.encrypt(b"one").unwrap();
+        let second = alice.encrypt(b"two").unwrap();
+        let third = alice.encrypt(b"three").unwrap();
+        
+        assert_eq!(bob.decrypt("alice", &third).unwrap(), b"three");
+        assert_eq!(bob.decrypt("alice", &first).unwrap(), b"one");
+        assert!(matches!(bob.decrypt("alice", &first), Err(NoiseError::ReplayDetected)));
+        assert_eq!(bob.decrypt("alice", &second).unwrap(), b"two");
+        assert_eq!(alice.decrypt("bob", &bob.encrypt(b"hi").unwrap()).unwrap(), b"hi");
+        
+        // Bob cannot pass his own message off as Alice's
+        let forged = bob.encrypt(b"from alice").unwrap();
+        assert!(bob.decrypt("alice", &forged).is_err());
+        let mut tampered = alice.encrypt(b"four").unwrap();
+        tampered[HEADER_LEN] ^= 1;
+        assert!(matches!(bob.decrypt("alice", &tampered), Err(NoiseError::DecryptionFailed)));
+    }
+    
+    #[test]
+    fn test_remove_member_rotates() {
+        let (mut alice, mut bob, mut alice_to_bob, mut bob_to_alice) = create_group();
+        let mut carol = GroupSession::new("friends").unwrap();
+        let (mut alice_to_carol, mut carol_to_alice) = perform_handshake().unwrap();
+        alice.add_member("carol");
+        carol.add_member("alice");
+        let dist = alice.distribute(&mut alice_to_carol).unwrap();
+        carol.receive_distribution("alice", &mut carol_to_alice, &dist).unwrap();
+        assert!(carol.decrypt("alice", &alice.encrypt(b"welcome").unwrap()).is_ok());
+        
+        alice.remove_member("carol").unwrap();
+        assert!(alice.remove_member("carol").is_err());
+        let message = alice.encrypt(b"carol is gone").unwrap();
+        assert!(carol.decrypt("alice", &message).is_err());
+        assert!(bob.decrypt("alice", &message).is_err());
+        
+        let dist = alice.distribute(&mut alice_to_bob).unwrap();
+        bob.receive_distribution("alice", &mut bob_to_alice, &dist).unwrap();
+        assert_eq!(bob.decrypt("alice", &alice.encrypt(b"again").unwrap()).unwrap(), b"again");
+        
+        // Distributions from non-members or for another group are refused
+        let mut stranger = GroupSession::new("friends").unwrap();
+        let dist = stranger.distribute(&mut alice_to_bob).unwrap();
+        assert!(bob.receive_distribution("mallory", &mut bob_to_alice, &dist).is_err());
+        stranger = GroupSession::new("work").unwrap();
+        stranger.add_member("bob");
+        let dist = stranger.distribute(&mut alice_to_bob).unwrap();
+        assert!(bob.receive_distribution("alice", &mut bob_to_alice, &dist).is_err());
+    }
+    
+    #[test]
+    fn test_serialize_restore() {
+        let (mut alice, mut bob, _, _) = create_group();
+        let skipped = alice.encrypt(b"late").unwrap();
+        bob.decrypt("alice", &alice.encrypt(b"early").unwrap()).unwrap();
+        
+        let mut bob = GroupSession::restore(&bob.serialize()).unwrap();
+        let mut alice = GroupSession::restore(&alice.serialize()).unwrap();
+        assert_eq!(bob.group_id(), "friends");
+        assert_eq!(bob.decrypt("alice", &skipped).unwrap(), b"late");
+        assert_eq!(bob.decrypt("alice", &alice.encrypt(b"after").unwrap()).unwrap(), b"after");
+        assert_eq!(alice.decrypt("bob", &bob.encrypt(b"back").unwrap()).unwrap(), b"back");
+        assert!(GroupSession::restore(&[GROUP_VERSION, 0, 9]).is_err());
+    }
+}
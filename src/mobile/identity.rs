@@ -0,0 +1,280 @@
+//! Named static identities with key rotation
+//!
+//! [`IdentityManager`] keeps Curve25519 identities in a [`KeyStorage`]
+//! backend under the names apps choose. Rotating an identity replaces its
+//! key and records a [`RotationLink`] signed by the old key, which peers
+//! that pinned the old key can check before accepting the new one.
+
+use crate::core::error::{NoiseError, Result};
+use crate::core::keys::{keys_equal, verify_signature, Keypair, KEY_LEN, SIGNATURE_LEN};
+use crate::core::session::NoiseSessionBuilder;
+use crate::mobile::snapshot::Reader;
+use crate::mobile::storage::KeyStorage;
+use std::sync::{Arc, Mutex};
+
+/// Prefix of ids the library keeps for itself; identity names may not use it
+pub const RESERVED_ID_PREFIX: &str = "noise.";
+
+/// Prefix of the session slot ids holding rotation histories
+const ROTATION_ID_PREFIX: &str = "noise.rotation.";
+
+/// Rotation link format version
+const ROTATION_VERSION: u8 = 1;
+
+/// Domain separator for rotation signatures
+const ROTATION_LABEL: &[u8] = b"noise-mobile/rotation/v1";
+
+/// Encoded length of a [`RotationLink`]
+pub const ROTATION_LINK_LEN: usize = 1 + 2 * KEY_LEN + SIGNATURE_LEN;
+
+/// Proof that the holder of `old_key` moved to `new_key`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RotationLink {
+    /// The retired static public key
+    pub old_key: [u8; KEY_LEN],
+    /// The replacement static public key
+    pub new_key: [u8; KEY_LEN],
+    /// XEdDSA signature by `old_key` over both keys
+    pub signature: [u8; SIGNATURE_LEN],
+}
+
+impl RotationLink {
+    fn sign(old: &Keypair, new_key: &[u8; KEY_LEN]) -> Result<Self> {
+        let signature = old.sign(&Self::message(old.public_key(), new_key))?;
+        Ok(Self {
+            old_key: *old.public_key(),
+            new_key: *new_key,
+            signature,
+        })
+    }
+    
+    /// Check the signature by the old key
+    pub fn verify(&self) -> bool {
+        verify_signature(&self.old_key, &Self::message(&self.old_key, &self.new_key), &self.signature)
+    }
+    
+    /// Encode to send to peers
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(ROTATION_LINK_LEN);
+        data.push(ROTATION_VERSION);
+        data.extend_from_slice(&self.old_key);
+        data.extend_from_slice(&self.new_key);
+        data.extend_from_slice(&self.signature);
+        data
+    }
+    
+    /// Decode a link; the signature is not checked
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(data);
+        let link = Self::read(&mut reader)?;
+        if !reader.is_empty() {
+            return Err(NoiseError::InvalidMessage);
+        }
+        Ok(link)
+    }
+    
+    fn read(reader: &mut Reader<'_>) -> Result<Self> {
+        if reader.take(1)?[0] != ROTATION_VERSION {
+            return Err(NoiseError::InvalidMessage);
+        }
+        Ok(Self {
+            old_key: reader.take(KEY_LEN)?.try_into().map_err(|_| NoiseError::InvalidMessage)?,
+            new_key: reader.take(KEY_LEN)?.try_into().map_err(|_| NoiseError::InvalidMessage)?,
+            signature: reader.take(SIGNATURE_LEN)?.try_into().map_err(|_| NoiseError::InvalidMessage)?,
+        })
+    }
+    
+    fn message(old_key: &[u8; KEY_LEN], new_key: &[u8; KEY_LEN]) -> Vec<u8> {
+        [ROTATION_LABEL, old_key, new_key].concat()
+    }
+}
+
+/// Check that `links` lead from `pinned` to `current` without a break
+///
+/// Lets a peer that pinned an older key accept the current one. Every link
+/// must be correctly signed and start where the previous one ended.
+pub fn verify_rotation_chain(pinned: &[u8], current: &[u8], links: &[RotationLink]) -> bool {
+    let mut key = pinned;
+    for link in links.iter().skip_while(|link| !keys_equal(&link.old_key, pinned)) {
+        if !keys_equal(&link.old_key, key) || !link.verify() {
+            return false;
+        }
+        key = &link.new_key;
+    }
+    keys_equal(key, current)
+}
+
+/// Creates, rotates and hands out named static identities
+pub struct IdentityManager {
+    storage: Arc<dyn KeyStorage>,
+    /// Serializes rotations against other changes to the same storage
+    lock: Mutex<()>,
+}
+
+impl IdentityManager {
+    /// Manage the identities in `storage`
+    pub fn new(storage: Arc<dyn KeyStorage>) -> Self {
+        Self {
+            storage,
+            lock: Mutex::new(()),
+        }
+    }
+    
+    /// Generate and store a new identity, returning its public key
+    ///
+    /// Fails if the name is taken or reserved.
+    pub fn create(&self, name: &str) -> Result<[u8; KEY_LEN]> {
+        check_name(name)?;
+        let _guard = self.lock()?;
+        if self.storage.has_identity(name)? {
+            return Err(NoiseError::InvalidState("Identity already exists".to_string()));
+        }
+        let keypair = Keypair::generate()?;
+        self.storage.store_identity(keypair.secret_key(), name)?;
+        Ok(*keypair.public_key())
+    }
+    
+    /// The keypair of an identity
+    pub fn keypair(&self, name: &str) -> Result<Keypair> {
+        check_name(name)?;
+        Keypair::from_secret_key(self.storage.load_identity(name)?)
+    }
+    
+    /// The public key of an identity
+    pub fn public_key(&self, name: &str) -> Result<[u8; KEY_LEN]> {
+        Ok(*self.keypair(name)?.public_key())
+    }
+    
+    /// Names of all identities, excluding the library's own entries
+    pub fn list(&self) -> Result<Vec<String>> {
+        let mut names = self.storage.list_identities()?;
+        names.retain(|name| !name.starts_with(RESERVED_ID_PREFIX));
+        Ok(names)
+    }
+    
+    /// Delete an identity and its rotation history
+    pub fn delete(&self, name: &str) -> Result<()> {
+        check_name(name)?;
+        let _guard = self.lock()?;
+        self.storage.delete_identity(name)?;
+        self.storage.delete_session(&rotation_id(name))
+    }
+    
+    /// Replace an identity's key, returning the link signed by the old key
+    ///
+    /// The link is appended to the identity's history before the new key is
+    /// stored, so a failed rotation never leaves a key without its link.
+    pub fn rotate(&self, name: &str) -> Result<RotationLink> {
+        check_name(name)?;
+        let _guard = self.lock()?;
+        let old = self.keypair(name)?;
+        let new = Keypair::generate()?;
+        let link = RotationLink::sign(&old, new.public_key())?;
+        
+        let previous = self.load_history(name)?;
+        let mut history = previous.clone();
+        history.push(link.clone());
+        self.store_history(name, &history)?;
+        if let Err(e) = self.storage.store_identity(new.secret_key(), name) {
+            self.store_history(name, &previous)?;
+            return Err(e);
+        }
+        Ok(link)
+    }
+    
+    /// Every rotation of an identity, oldest first
+    pub fn rotation_history(&self, name: &str) -> Result<Vec<RotationLink>> {
+        check_name(name)?;
+        let _guard = self.lock()?;
+        self.load_history(name)
+    }
+    
+    /// A session builder using an identity's private key
+    pub fn session_builder(&self, name: &str) -> Result<NoiseSessionBuilder> {
+        Ok(NoiseSessionBuilder::new().private_key(self.keypair(name)?.secret_key()))
+    }
+    
+    fn load_history(&self, name: &str) -> Result<Vec<RotationLink>> {
+        let data = match self.storage.load_session(&rotation_id(name)) {
+            Ok(data) => data,
+            // Backends report a missing slot as an invalid parameter
+            Err(NoiseError::InvalidParameter) => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut reader = Reader::new(&data);
+        let mut links = Vec::new();
+        while !reader.is_empty() {
+            links.push(RotationLink::read(&mut reader)?);
+        }
+        Ok(links)
+    }
+    
+    fn store_history(&self, name: &str, links: &[RotationLink]) -> Result<()> {
+        let data: Vec<u8> = links.iter().flat_map(RotationLink::to_bytes).collect();
+        self.storage.store_session(&rotation_id(name), &data)
+    }
+    
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, ()>> {
+        self.lock.lock().map_err(|_| NoiseError::InvalidState("Lock poisoned".to_string()))
+    }
+}
+
+fn check_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.starts_with(RESERVED_ID_PREFIX) {
+        return Err(NoiseError::InvalidParameter);
+    }
+    Ok(())
+}
+
+fn rotation_id(name: &str) -> String {
+    format!("{}{}", ROTATION_ID_PREFIX, name)
+}
+
+#[cfg(all(test, feature = "storage-backends"))]
+mod tests {
+    use super::*;
+    use crate::mobile::storage::MemoryKeyStorage;
+    
+    #[test]
+    fn test_create_list_delete() {
+        let manager = IdentityManager::new(Arc::new(MemoryKeyStorage::new()));
+        let public = manager.create("phone").unwrap();
+        assert!(manager.create("phone").is_err());
+        assert!(manager.create("noise.snapshot-kek").is_err());
+        
+        assert_eq!(manager.public_key("phone").unwrap(), public);
+        assert_eq!(manager.list().unwrap(), vec!["phone".to_string()]);
+        
+        let session = manager.session_builder("phone").unwrap().build_initiator().unwrap();
+        assert_eq!(session.get_local_static(), Some(&public[..]));
+        
+        manager.delete("phone").unwrap();
+        assert!(manager.list().unwrap().is_empty());
+        assert!(manager.public_key("phone").is_err());
+    }
+    
+    #[test]
+    fn test_rotation_chain() {
+        let manager = IdentityManager::new(Arc::new(MemoryKeyStorage::new()));
+        let first = manager.create("phone").unwrap();
+        let link1 = manager.rotate("phone").unwrap();
+        let link2 = manager.rotate("phone").unwrap();
+        let current = manager.public_key("phone").unwrap();
+        
+        assert_eq!((link1.old_key, link1.new_key), (first, link2.old_key));
+        assert_eq!(link2.new_key, current);
+        assert!(link1.verify());
+        assert_eq!(RotationLink::from_bytes(&link1.to_bytes()).unwrap(), link1);
+        
+        let history = manager.rotation_history("phone").unwrap();
+        assert_eq!(history, vec![link1.clone(), link2.clone()]);
+        assert!(verify_rotation_chain(&first, &current, &history));
+        assert!(verify_rotation_chain(&link2.old_key, &current, &history));
+        assert!(!verify_rotation_chain(&first, &current, &history[..1]));
+        
+        let mut forged = link2;
+        forged.new_key = [9u8; KEY_LEN];
+        assert!(!forged.verify());
+        assert!(!verify_rotation_chain(&first, &forged.new_key, &[link1, forged.clone()]));
+    }
+}
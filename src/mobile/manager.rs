@@ -0,0 +1,1525 @@
+//! Session management across multiple peers
+//!
+//! [`SessionManager`] drives XX handshakes keyed by an application peer ID
+//! and resolves simultaneous opens, where both peers initiate toward each
+//! other before either has seen the other's first message. When a handshake
+//! completes with a peer that already has a live session, the configured
+//! [`SupersessionPolicy`] decides which sessions stay live and a
+//! [`SessionEvent`] reports the outcome.
+//!
+//! The whole set of live sessions can be serialized and restored, e.g. when
+//! the app is suspended. Handshakes in progress are not carried over.
+//!
+//! [`SessionManager::connect_0rtt`] reconnects to a peer whose static key is
+//! known with an IK handshake carrying early data. Every handshake message
+//! the manager writes starts with a byte saying whether it opens an XX or
+//! an IK handshake or continues one, so responders never guess the pattern.
+//! An IK opening from a peer with a live session must come from the static
+//! key that session authenticated.
+//!
+//! [`SessionLimits`] cap how long and how much each live session is used.
+//! Call [`SessionManager::enforce_limits`] periodically, e.g. from the app's
+//! maintenance timer; expired sessions are handed back as
+//! [`SessionEvent::SessionExpired`] so the app can start a fresh handshake.
+//!
+//! A responder under load can demand a [cookie](crate::mobile::cookie)
+//! before spending Diffie-Hellman operations on an opening. The peer ID
+//! stands in for the sender's address, so it must come from the transport
+//! (e.g. the UDP source address) for the challenge to mean anything.
+//!
+//! Openings can also be capped per peer ID with a [`RateLimiter`], and
+//! peers refused outright by ID or by static key.
+//!
+//! With a [`BufferPool`] set, live sessions share scratch space, so memory
+//! for padded and in-place messages stays bounded however many peers there
+//! are.
+//!
+//! [`SessionManager::prewarm`] builds the initiator state and ephemeral key
+//! for a known peer ahead of time, so [`SessionManager::connect`] can hand
+//! back the first handshake message at once when the peer comes into range,
+//! e.g. during a short BLE encounter.
+//!
+//! With the `parallel` feature, [`SessionManager::encrypt_many`] and
+//! [`SessionManager::decrypt_many`] work through queued messages for many
+//! peers at once on the rayon thread pool. Messages for one peer are still
+//! handled in order on a single thread, since each session's nonces are
+//! sequential.
+
+use crate::core::buffer_pool::{BufferPool, BufferPoolStats};
+use crate::core::early_data::{encode_early_data, ReplayCache};
+use crate::core::error::{NoiseError, Result};
+use crate::core::keys::{keys_equal, SecretKey};
+use crate::core::logging::{log_event, LogLevel};
+use crate::core::now_ms;
+use crate::core::pipes::PIPE_IK_PARAMS;
+use crate::core::session::{NoiseSession, SessionId, SessionStats};
+use crate::mobile::cookie::{attach_cookie, cookie_reply, parse_cookie_reply, CookieChecker};
+use crate::mobile::ratelimit::RateLimiter;
+use crate::mobile::snapshot::{Reader, SessionSnapshot};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Duration;
+
+/// Length of the ephemeral public key that opens an XX handshake
+const EPHEMERAL_LEN: usize = 32;
+
+/// Leading byte of a message opening an XX handshake
+const MESSAGE_XX_OPENING: u8 = 1;
+
+/// Leading byte of a message opening an IK handshake with early data
+const MESSAGE_IK_OPENING: u8 = 2;
+
+/// Leading byte of every later handshake message
+///
+/// An XX opening received while awaiting a reply is a simultaneous open.
+const MESSAGE_REPLY: u8 = 3;
+
+/// Default cap on live sessions kept per peer
+pub const DEFAULT_MAX_SESSIONS_PER_PEER: usize = 4;
+
+/// Format version of [`SessionManager::serialize`]
+///
+/// Version 2 adds each session's establishment and last-activity times.
+const MANAGER_STATE_VERSION: u8 = 2;
+
+/// What to keep when a handshake completes with a peer that already has a
+/// live session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SupersessionPolicy {
+    /// Replace the live session with the new one
+    ///
+    /// Only a session authenticated by the same static key replaces it; one
+    /// from a different key is retired instead, so a peer ID taken from the
+    /// transport cannot be used to displace the real peer. Remove the live
+    /// sessions first to accept a peer's new key.
+    #[default]
+    KeepNewest,
+    /// Keep the live session and retire the new one
+    KeepOldest,
+    /// Keep every session as a separate channel
+    KeepBoth,
+}
+
+/// Notifications produced by the manager, drained with [`SessionManager::poll_event`]
+pub enum SessionEvent {
+    /// A session with the peer became live
+    Established {
+        /// Peer the session belongs to
+        peer_id: String,
+        /// The new session's [`NoiseSession::session_id`]
+        session_id: Option<SessionId>,
+    },
+    /// A session was retired by the supersession policy or the per-peer limit
+    ///
+    /// The retired session is handed back so messages already in flight on
+    /// it can still be decrypted. Under [`SupersessionPolicy::KeepOldest`]
+    /// this is the newly completed session.
+    SessionSuperseded {
+        /// Peer the session belongs to
+        peer_id: String,
+        /// The session that is no longer live
+        retired: Box<NoiseSession>,
+    },
+    /// A live session passed one of the [`SessionLimits`] and was torn down
+    SessionExpired {
+        /// Peer the session belongs to
+        peer_id: String,
+        /// Which limit was reached
+        reason: ExpiryReason,
+        /// The expired session, for messages still in flight on it
+        retired: Box<NoiseSession>,
+    },
+    /// A peer sent 0-RTT early data with its IK opening
+    ///
+    /// The data passed the replay cache but may still be a replay from
+    /// before this manager started; see [`crate::core::early_data`].
+    EarlyData {
+        /// Peer that sent the data
+        peer_id: String,
+        /// The application data
+        data: Vec<u8>,
+    },
+}
+
+/// Crypto-hygiene limits on live sessions
+///
+/// Enforced by [`SessionManager::enforce_limits`]. `None` means unlimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SessionLimits {
+    /// Expire a session after this long without a message in either direction
+    pub max_idle: Option<Duration>,
+    /// Expire a session this long after it went live
+    pub max_age: Option<Duration>,
+    /// Messages a session may send, or receive, before it expires
+    ///
+    /// Applied as the session's nonce limit, so sending or receiving past it
+    /// fails with [`NoiseError::NonceExhausted`] even between enforcement
+    /// passes.
+    pub max_messages: Option<u64>,
+}
+
+/// Which of the [`SessionLimits`] expired a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpiryReason {
+    /// No messages for [`SessionLimits::max_idle`]
+    Idle,
+    /// Live for [`SessionLimits::max_age`]
+    MaxAge,
+    /// [`SessionLimits::max_messages`] used up
+    MaxMessages,
+}
+
+/// What a live session has left under the [`SessionLimits`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionBudget {
+    /// Time until the age or idle limit expires the session, if either is set
+    pub time_remaining: Option<Duration>,
+    /// Messages left in the busier direction
+    pub messages_remaining: u64,
+}
+
+/// When a live session was established and last used, in Unix milliseconds
+#[derive(Debug, Clone, Copy)]
+struct SessionClock {
+    established_ms: u64,
+    active_ms: u64,
+    /// Transport messages counted at the last activity check
+    messages: u64,
+}
+
+impl SessionClock {
+    fn new(session: &NoiseSession, now_ms: u64) -> Self {
+        Self { established_ms: now_ms, active_ms: now_ms, messages: message_count(session) }
+    }
+}
+
+/// Sessions and handshake state for a single peer
+#[derive(Default)]
+struct PeerSessions {
+    /// Established sessions, oldest first
+    live: Vec<NoiseSession>,
+    /// Timing of each live session, in the same order
+    clocks: Vec<SessionClock>,
+    /// Handshake in progress
+    pending: Option<NoiseSession>,
+    /// Ephemeral key we opened the pending handshake with, until the peer replies
+    opening: Option<[u8; EPHEMERAL_LEN]>,
+    /// Our opening message, kept to resend with a cookie
+    first_message: Option<Vec<u8>>,
+}
+
+/// Owns the Noise sessions for a set of peers
+pub struct SessionManager {
+    local_private_key: SecretKey,
+    peers: HashMap<String, PeerSessions>,
+    policy: SupersessionPolicy,
+    max_sessions_per_peer: usize,
+    events: VecDeque<SessionEvent>,
+    replay_cache: ReplayCache,
+    limits: SessionLimits,
+    cookies: CookieChecker,
+    rate_limiter: Option<RateLimiter>,
+    blocked_peers: HashSet<String>,
+    blocked_keys: HashSet<Vec<u8>>,
+    buffer_pool: Option<BufferPool>,
+    /// Initiator sessions built ahead of time, with their first message
+    prewarmed: HashMap<String, (NoiseSession, Vec<u8>)>,
+}
+
+impl SessionManager {
+    /// Create a manager that authenticates as the given static private key
+    pub fn new(local_private_key: &SecretKey) -> Self {
+        Self {
+            local_private_key: local_private_key.clone(),
+            peers: HashMap::new(),
+            policy: SupersessionPolicy::default(),
+            max_sessions_per_peer: DEFAULT_MAX_SESSIONS_PER_PEER,
+            events: VecDeque::new(),
+            replay_cache: ReplayCache::default(),
+            limits: SessionLimits::default(),
+            cookies: CookieChecker::new(),
+            rate_limiter: None,
+            blocked_peers: HashSet::new(),
+            blocked_keys: HashSet::new(),
+            buffer_pool: None,
+            prewarmed: HashMap::new(),
+        }
+    }
+    
+    /// Rebuild a manager from [`SessionManager::serialize`] output
+    ///
+    /// `local_private_key` and the [`SessionLimits`] are not part of the
+    /// serialized state and must be supplied again. Sessions saved by
+    /// version 1 count their age from the restore.
+    pub fn restore(local_private_key: &SecretKey, data: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(data);
+        let version = reader.take(1)?[0];
+        if version != 1 && version != MANAGER_STATE_VERSION {
+            return Err(NoiseError::InvalidMessage);
+        }
+        let now = now_ms();
+        
+        let mut manager = Self::new(local_private_key);
+        manager.policy = match reader.take(1)?[0] {
+            0 => SupersessionPolicy::KeepNewest,
+            1 => SupersessionPolicy::KeepOldest,
+            2 => SupersessionPolicy::KeepBoth,
+            _ => return Err(NoiseError::InvalidMessage),
+        };
+        manager.set_max_sessions_per_peer(reader.u32()? as usize);
+        
+        for _ in 0..reader.u32()? {
+            let id_len = reader.u16()? as usize;
+            let peer_id = std::str::from_utf8(reader.take(id_len)?)
+                .map_err(|_| NoiseError::InvalidMessage)?
+                .to_string();
+            
+            let mut live = Vec::new();
+            let mut clocks = Vec::new();
+            for _ in 0..reader.u16()? {
+                let len = reader.u32()? as usize;
+                let session = SessionSnapshot::from_bytes(reader.take(len)?)?.restore_session()?;
+                let mut clock = SessionClock::new(&session, now);
+                if version >= 2 {
+                    clock.established_ms = reader.u64()?;
+                    clock.active_ms = reader.u64()?;
+                }
+                live.push(session);
+                clocks.push(clock);
+            }
+            manager.peers.insert(peer_id, PeerSessions { live, clocks, ..Default::default() });
+        }
+        
+        if !reader.is_empty() {
+            return Err(NoiseError::InvalidMessage);
+        }
+        Ok(manager)
+    }
+    
+    /// Set how duplicate sessions with the same peer are resolved
+    pub fn set_supersession_policy(&mut self, policy: SupersessionPolicy) {
+        self.policy = policy;
+    }
+    
+    /// Set how many live sessions a peer may have under
+    /// [`SupersessionPolicy::KeepBoth`]
+    ///
+    /// The oldest sessions beyond the limit are retired. The limit is at
+    /// least one.
+    pub fn set_max_sessions_per_peer(&mut self, max: usize) {
+        self.max_sessions_per_peer = max.max(1);
+    }
+    
+    /// Demand a cookie before processing handshake openings
+    ///
+    /// While under load, an opening from a peer with no handshake in
+    /// progress is answered with a cookie reply; the peer's manager resends
+    /// the opening with the cookie attached, and only that is processed.
+    pub fn set_under_load(&mut self, under_load: bool) {
+        self.cookies.set_under_load(under_load);
+    }
+    
+    /// Limit how often each peer may open a handshake, `None` for no limit
+    ///
+    /// Openings over the limit fail with [`NoiseError::InvalidState`]
+    /// before any Diffie-Hellman work is done.
+    pub fn set_rate_limiter(&mut self, limiter: Option<RateLimiter>) {
+        self.rate_limiter = limiter;
+    }
+    
+    /// Have every session borrow scratch space from `pool`, `None` to give
+    /// each its own buffer again
+    ///
+    /// Takes effect on live sessions immediately.
+    pub fn set_buffer_pool(&mut self, pool: Option<BufferPool>) {
+        for (_, session) in self.iter_mut() {
+            session.set_buffer_pool(pool.clone());
+        }
+        self.buffer_pool = pool;
+    }
+    
+    /// Pressure on the shared buffer pool, if one is set
+    pub fn buffer_pool_stats(&self) -> Option<BufferPoolStats> {
+        self.buffer_pool.as_ref().map(BufferPool::stats)
+    }
+    
+    /// Traffic counters summed over every live session
+    ///
+    /// Sessions that were superseded, expired or removed no longer count.
+    pub fn stats(&self) -> SessionStats {
+        let mut total = SessionStats::default();
+        for session in self.peers.values().flat_map(|peer| &peer.live) {
+            total.merge(&session.stats());
+        }
+        total
+    }
+    
+    /// Traffic counters summed over a peer's live sessions
+    pub fn peer_stats(&self, peer_id: &str) -> Option<SessionStats> {
+        let peer = self.peers.get(peer_id).filter(|peer| !peer.live.is_empty())?;
+        let mut total = SessionStats::default();
+        for session in &peer.live {
+            total.merge(&session.stats());
+        }
+        Some(total)
+    }
+    
+    /// Refuse all handshake messages from a peer ID
+    ///
+    /// A handshake in progress is dropped; live sessions are kept, see
+    /// [`remove`](Self::remove).
+    pub fn block_peer(&mut self, peer_id: &str) {
+        self.blocked_peers.insert(peer_id.to_string());
+        if let Some(peer) = self.peers.get_mut(peer_id) {
+            peer.pending = None;
+            peer.opening = None;
+            peer.first_message = None;
+            if peer.live.is_empty() {
+                self.peers.remove(peer_id);
+            }
+        }
+    }
+    
+    /// Accept handshakes from a peer ID again
+    pub fn unblock_peer(&mut self, peer_id: &str) {
+        self.blocked_peers.remove(peer_id);
+    }
+    
+    /// Fail any handshake that authenticates this static public key
+    ///
+    /// The handshake fails as soon as the key is revealed, before a reply
+    /// is written.
+    pub fn block_static_key(&mut self, public_key: &[u8]) {
+        self.blocked_keys.insert(public_key.to_vec());
+    }
+    
+    /// Accept handshakes with a static public key again
+    pub fn unblock_static_key(&mut self, public_key: &[u8]) {
+        self.blocked_keys.remove(public_key);
+    }
+    
+    /// Whether handshakes from a peer ID are refused
+    pub fn is_peer_blocked(&self, peer_id: &str) -> bool {
+        self.blocked_peers.contains(peer_id)
+    }
+    
+    /// Set the limits [`enforce_limits`](Self::enforce_limits) applies
+    ///
+    /// A message limit takes effect on live sessions immediately.
+    pub fn set_session_limits(&mut self, limits: SessionLimits) {
+        self.limits = limits;
+        if let Some(max_messages) = limits.max_messages {
+            for (_, session) in self.iter_mut() {
+                session.set_nonce_limit(max_messages);
+            }
+        }
+    }
+    
+    /// The limits in force
+    pub fn session_limits(&self) -> SessionLimits {
+        self.limits
+    }
+    
+    /// Tear down every live session past one of the [`SessionLimits`]
+    ///
+    /// Each expired session is reported as [`SessionEvent::SessionExpired`].
+    /// Idleness is sampled: a session counts as active at a pass if it has
+    /// sent or received since the previous one, so call this more often
+    /// than `max_idle`. Returns the number of sessions expired.
+    pub fn enforce_limits(&mut self) -> usize {
+        self.enforce_limits_at(now_ms())
+    }
+    
+    fn enforce_limits_at(&mut self, now_ms: u64) -> usize {
+        let limits = self.limits;
+        let mut expired = Vec::new();
+        self.peers.retain(|peer_id, peer| {
+            let mut index = 0;
+            while index < peer.live.len() {
+                let clock = &mut peer.clocks[index];
+                let messages = message_count(&peer.live[index]);
+                if messages != clock.messages {
+                    clock.messages = messages;
+                    clock.active_ms = now_ms;
+                }
+                
+                match expiry(&limits, clock, &peer.live[index], now_ms) {
+                    Some(reason) => {
+                        peer.clocks.remove(index);
+                        let retired = Box::new(peer.live.remove(index));
+                        log_event!(LogLevel::Info, "Session with {peer_id} expired: {reason:?}");
+                        expired.push(SessionEvent::SessionExpired { peer_id: peer_id.clone(), reason, retired });
+                    }
+                    None => index += 1,
+                }
+            }
+            !peer.live.is_empty() || peer.pending.is_some()
+        });
+        
+        let count = expired.len();
+        self.events.extend(expired);
+        count
+    }
+    
+    /// What the newest live session with a peer has left before it expires
+    pub fn session_budget(&self, peer_id: &str) -> Option<SessionBudget> {
+        self.session_budget_at(peer_id, now_ms())
+    }
+    
+    fn session_budget_at(&self, peer_id: &str, now_ms: u64) -> Option<SessionBudget> {
+        let peer = self.peers.get(peer_id)?;
+        let (session, clock) = (peer.live.last()?, peer.clocks.last()?);
+        let remaining = |since: u64, limit: Option<Duration>| {
+            limit.map(|limit| limit.saturating_sub(Duration::from_millis(now_ms.saturating_sub(since))))
+        };
+        let age = remaining(clock.established_ms, self.limits.max_age);
+        // Activity since the last pass restarts the idle timer
+        let active_ms = if message_count(session) != clock.messages { now_ms } else { clock.active_ms };
+        let idle = remaining(active_ms, self.limits.max_idle);
+        Some(SessionBudget {
+            time_remaining: match (age, idle) {
+                (Some(age), Some(idle)) => Some(age.min(idle)),
+                (age, idle) => age.or(idle),
+            },
+            messages_remaining: session.remaining_nonce_budget(),
+        })
+    }
+    
+    /// Start a handshake with a peer, returning the first message to send
+    ///
+    /// Allowed while a session with the peer is live; the result is resolved
+    /// by the supersession policy once the handshake completes.
+    pub fn connect(&mut self, peer_id: &str) -> Result<Vec<u8>> {
+        self.check_no_pending(peer_id)?;
+        let (session, message) = match self.prewarmed.remove(peer_id) {
+            Some(prewarmed) => prewarmed,
+            None => open_xx(&self.local_private_key)?,
+        };
+        
+        // Only a handshake that opened gets an entry
+        let peer = self.peers.entry(peer_id.to_string()).or_default();
+        let mut ephemeral = [0u8; EPHEMERAL_LEN];
+        ephemeral.copy_from_slice(&message[1..=EPHEMERAL_LEN]);
+        peer.opening = Some(ephemeral);
+        peer.pending = Some(session);
+        peer.first_message = Some(message.clone());
+        Ok(message)
+    }
+    
+    /// Prepare a handshake with a peer before it is reachable
+    ///
+    /// The next [`connect`](Self::connect) to the peer returns the prepared
+    /// message without generating keys. The prepared ephemeral key is used
+    /// for one handshake only; preparing again for the same peer keeps the
+    /// existing one.
+    pub fn prewarm(&mut self, peer_id: &str) -> Result<()> {
+        if !self.prewarmed.contains_key(peer_id) {
+            let prewarmed = open_xx(&self.local_private_key)?;
+            self.prewarmed.insert(peer_id.to_string(), prewarmed);
+        }
+        Ok(())
+    }
+    
+    /// Check whether a handshake with a peer is prepared
+    pub fn is_prewarmed(&self, peer_id: &str) -> bool {
+        self.prewarmed.contains_key(peer_id)
+    }
+    
+    /// Drop a prepared handshake, e.g. once the peer is no longer expected
+    pub fn discard_prewarmed(&mut self, peer_id: &str) -> bool {
+        self.prewarmed.remove(peer_id).is_some()
+    }
+    
+    /// Reconnect to a peer with a known static key, sending early data
+    ///
+    /// Runs a one-round-trip IK handshake whose first message carries
+    /// `early_data`, which the peer receives as [`SessionEvent::EarlyData`].
+    /// Early data is not forward secret and may be replayed: see
+    /// [`crate::core::early_data`] for what is safe to send. If the peer's
+    /// key has changed the handshake fails; start over with
+    /// [`connect`](Self::connect). Simultaneous 0-RTT opens both fail the
+    /// same way.
+    pub fn connect_0rtt(&mut self, peer_id: &str, remote_static: &[u8], early_data: &[u8]) -> Result<Vec<u8>> {
+        self.check_no_pending(peer_id)?;
+        let mut session =
+            NoiseSession::with_protocol(PIPE_IK_PARAMS, self.local_private_key.as_bytes(), Some(remote_static), true, &[])?;
+        let message = tagged(MESSAGE_IK_OPENING, &session.write_message(&encode_early_data(early_data))?);
+        
+        let peer = self.peers.entry(peer_id.to_string()).or_default();
+        peer.pending = Some(session);
+        peer.first_message = Some(message.clone());
+        Ok(message)
+    }
+    
+    fn check_no_pending(&self, peer_id: &str) -> Result<()> {
+        if self.peers.get(peer_id).is_some_and(|peer| peer.pending.is_some()) {
+            return Err(NoiseError::InvalidState("Handshake with peer already in progress".to_string()));
+        }
+        Ok(())
+    }
+    
+    /// Replace the cache that rejects replayed 0-RTT early data
+    pub fn set_replay_cache(&mut self, cache: ReplayCache) {
+        self.replay_cache = cache;
+    }
+    
+    /// Process a handshake message from a peer
+    ///
+    /// Returns the reply to send back, if any. A failed handshake is dropped
+    /// so the peer can start over; live sessions with the peer are kept.
+    pub fn handle_handshake(&mut self, peer_id: &str, message: &[u8]) -> Result<Option<Vec<u8>>> {
+        if self.blocked_peers.contains(peer_id) {
+            return Err(NoiseError::InvalidState("Peer is blocked".to_string()));
+        }
+        let pending = self.peers.get(peer_id).is_some_and(|peer| peer.pending.is_some());
+        let result = if pending {
+            self.answer_cookie(peer_id, message)
+                .map_or_else(|| self.advance_handshake(peer_id, message), |resent| resent.map(Some))
+        } else {
+            // Checking the cookie is cheap; openings cost Diffie-Hellman
+            let opening = match self.cookies.verify(peer_id.as_bytes(), message)? {
+                Some(opening) => opening,
+                None if self.cookies.is_under_load() => {
+                    return Ok(Some(cookie_reply(&self.cookies.cookie(peer_id.as_bytes())?)));
+                }
+                None => message,
+            };
+            if self.rate_limiter.as_mut().is_some_and(|limiter| !limiter.check(peer_id)) {
+                return Err(NoiseError::InvalidState("Handshake rate limit exceeded".to_string()));
+            }
+            self.advance_handshake(peer_id, opening)
+        };
+        if let Err(e) = &result {
+            log_event!(LogLevel::Warn, "Handshake with {peer_id} failed: {e}");
+            if let Some(peer) = self.peers.get_mut(peer_id) {
+                peer.pending = None;
+                peer.opening = None;
+                peer.first_message = None;
+                if peer.live.is_empty() {
+                    self.peers.remove(peer_id);
+                }
+            }
+        }
+        result
+    }
+    
+    /// Resend our opening with the cookie from a cookie reply
+    fn answer_cookie(&self, peer_id: &str, message: &[u8]) -> Option<Result<Vec<u8>>> {
+        let cookie = parse_cookie_reply(message)?;
+        let opening = self.peers.get(peer_id)?.first_message.as_ref()?;
+        Some(attach_cookie(opening, &cookie))
+    }
+    
+    fn advance_handshake(&mut self, peer_id: &str, message: &[u8]) -> Result<Option<Vec<u8>>> {
+        let (&kind, message) = message.split_first().ok_or(NoiseError::InvalidMessage)?;
+        let peer = self.peers.entry(peer_id.to_string()).or_default();
+        
+        if let Some(local_ephemeral) = peer.opening {
+            if kind == MESSAGE_XX_OPENING {
+                // Both sides opened; the larger ephemeral key stays initiator
+                let remote_ephemeral = message.get(..EPHEMERAL_LEN).ok_or(NoiseError::InvalidMessage)?;
+                match local_ephemeral[..].cmp(remote_ephemeral) {
+                    Ordering::Greater => return Ok(None),
+                    Ordering::Less => peer.pending = None,
+                    Ordering::Equal => return Err(NoiseError::HandshakeFailed),
+                }
+            }
+            peer.opening = None;
+        }
+        peer.first_message = None;
+        
+        let session = match peer.pending {
+            Some(ref mut session) if kind == MESSAGE_REPLY => {
+                session.read_message(message)?;
+                session
+            }
+            None if kind == MESSAGE_IK_OPENING => {
+                let mut session = NoiseSession::with_protocol(PIPE_IK_PARAMS, self.local_private_key.as_bytes(), None, false, &[])?;
+                let payload = session.read_message(message)?;
+                let remote_static = session.revealed_remote_static().ok_or(NoiseError::HandshakeFailed)?;
+                if self.blocked_keys.contains(remote_static) {
+                    return Err(NoiseError::HandshakeFailed);
+                }
+                // Early data is only taken from the key the peer is known by
+                let known_key = peer.live.last().and_then(NoiseSession::get_remote_static);
+                if known_key.is_some_and(|known| !keys_equal(known, remote_static)) {
+                    log_event!(LogLevel::Warn, "0-RTT opening for {peer_id} from an unknown static key");
+                    return Err(NoiseError::PeerKeyMismatch);
+                }
+                let data = self.replay_cache.accept(message, &payload)?;
+                self.events.push_back(SessionEvent::EarlyData { peer_id: peer_id.to_string(), data });
+                peer.pending.insert(session)
+            }
+            None if kind == MESSAGE_XX_OPENING => {
+                let session = peer.pending.insert(NoiseSession::with_private_key(&self.local_private_key, false)?);
+                session.read_message(message)?;
+                session
+            }
+            _ => return Err(NoiseError::InvalidMessage),
+        };
+        
+        if session.revealed_remote_static().is_some_and(|key| self.blocked_keys.contains(key)) {
+            return Err(NoiseError::HandshakeFailed);
+        }
+        
+        let reply = if session.is_handshake_state() {
+            Some(tagged(MESSAGE_REPLY, &session.write_message(&[])?))
+        } else {
+            None
+        };
+        
+        if session.is_transport_state() {
+            if let Some(session) = peer.pending.take() {
+                self.promote(peer_id, session);
+            }
+        }
+        Ok(reply)
+    }
+    
+    /// Add a session established outside the manager
+    ///
+    /// The session goes live under the supersession policy exactly as if its
+    /// handshake had completed here.
+    pub fn insert(&mut self, peer_id: &str, session: NoiseSession) -> Result<()> {
+        if !session.is_transport_state() {
+            return Err(NoiseError::InvalidState("Handshake not complete".to_string()));
+        }
+        self.promote(peer_id, session);
+        Ok(())
+    }
+    
+    /// Make a completed handshake live, applying the supersession policy
+    fn promote(&mut self, peer_id: &str, mut session: NoiseSession) {
+        let peer = self.peers.entry(peer_id.to_string()).or_default();
+        // Only the key the peer is known by may replace its sessions
+        let changed_key = peer.live.last().is_some_and(|live| {
+            !matches!((live.get_remote_static(), session.get_remote_static()), (Some(a), Some(b)) if keys_equal(a, b))
+        });
+        
+        let retired = match self.policy {
+            SupersessionPolicy::KeepOldest if !peer.live.is_empty() => vec![session],
+            SupersessionPolicy::KeepNewest if changed_key => {
+                log_event!(LogLevel::Warn, "Handshake with {peer_id} used a different static key; keeping live sessions");
+                vec![session]
+            }
+            policy => {
+                let mut retired = if policy == SupersessionPolicy::KeepNewest {
+                    peer.clocks.clear();
+                    std::mem::take(&mut peer.live)
+                } else {
+                    Vec::new()
+                };
+                if let Some(max_messages) = self.limits.max_messages {
+                    session.set_nonce_limit(max_messages);
+                }
+                if let Some(pool) = &self.buffer_pool {
+                    session.set_buffer_pool(Some(pool.clone()));
+                }
+                let session_id = session.session_id();
+                peer.clocks.push(SessionClock::new(&session, now_ms()));
+                peer.live.push(session);
+                let excess = peer.live.len().saturating_sub(self.max_sessions_per_peer);
+                peer.clocks.drain(..excess);
+                retired.extend(peer.live.drain(..excess));
+                log_event!(
+                    LogLevel::Info,
+                    "Session {} with {peer_id} established, {} live",
+                    session_id.map_or_else(|| "-".to_string(), |id| id.to_string()),
+                    peer.live.len()
+                );
+                self.events.push_back(SessionEvent::Established { peer_id: peer_id.to_string(), session_id });
+                retired
+            }
+        };
+        
+        for retired in retired {
+            log_event!(LogLevel::Info, "Session with {peer_id} superseded under {:?}", self.policy);
+            self.events.push_back(SessionEvent::SessionSuperseded {
+                peer_id: peer_id.to_string(),
+                retired: Box::new(retired),
+            });
+        }
+    }
+    
+    /// Take the next pending event, oldest first
+    pub fn poll_event(&mut self) -> Option<SessionEvent> {
+        self.events.pop_front()
+    }
+    
+    /// Get the newest live session for a peer, or its handshake in progress
+    pub fn session(&self, peer_id: &str) -> Option<&NoiseSession> {
+        let peer = self.peers.get(peer_id)?;
+        peer.live.last().or(peer.pending.as_ref())
+    }
+    
+    /// Get the newest live session for a peer mutably, or its handshake in progress
+    pub fn session_mut(&mut self, peer_id: &str) -> Option<&mut NoiseSession> {
+        let peer = self.peers.get_mut(peer_id)?;
+        match peer.live.last_mut() {
+            Some(session) => Some(session),
+            None => peer.pending.as_mut(),
+        }
+    }
+    
+    /// Find a live session by its [`NoiseSession::session_id`], with its peer
+    pub fn find_session(&self, session_id: &SessionId) -> Option<(&str, &NoiseSession)> {
+        self.peers.iter().find_map(|(id, peer)| {
+            peer.live
+                .iter()
+                .find(|session| session.session_id().as_ref() == Some(session_id))
+                .map(|session| (id.as_str(), session))
+        })
+    }
+    
+    /// All live sessions for a peer, oldest first
+    pub fn sessions_mut(&mut self, peer_id: &str) -> &mut [NoiseSession] {
+        match self.peers.get_mut(peer_id) {
+            Some(peer) => &mut peer.live,
+            None => &mut [],
+        }
+    }
+    
+    /// Check whether a session with a peer is live
+    pub fn is_established(&self, peer_id: &str) -> bool {
+        self.peers.get(peer_id).is_some_and(|p| !p.live.is_empty())
+    }
+    
+    /// Visit every live session, e.g. to rekey on a schedule
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&str, &mut NoiseSession)> {
+        self.peers
+            .iter_mut()
+            .flat_map(|(id, peer)| peer.live.iter_mut().map(move |s| (id.as_str(), s)))
+    }
+    
+    /// Keep only the live sessions for which `keep` returns true
+    ///
+    /// Use this to expire sessions, e.g. those close to nonce exhaustion.
+    /// Peers left with neither a live session nor a handshake are removed.
+    pub fn retain(&mut self, mut keep: impl FnMut(&str, &mut NoiseSession) -> bool) {
+        self.peers.retain(|id, peer| {
+            let kept: Vec<bool> = peer.live.iter_mut().map(|session| keep(id, session)).collect();
+            let mut flags = kept.iter();
+            peer.live.retain(|_| flags.next() == Some(&true));
+            let mut flags = kept.iter();
+            peer.clocks.retain(|_| flags.next() == Some(&true));
+            !peer.live.is_empty() || peer.pending.is_some()
+        });
+    }
+    
+    /// IDs of all peers with a live session or handshake in progress
+    pub fn peer_ids(&self) -> impl Iterator<Item = &str> {
+        self.peers.keys().map(String::as_str)
+    }
+    
+    /// Encode every live session, with its raw keys, into bytes
+    ///
+    /// The output contains live key material and must be wrapped before it
+    /// reaches disk. Restoring it and continuing to use this manager reuses
+    /// nonces; treat the output as single-use.
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        let mut data = vec![MANAGER_STATE_VERSION, self.policy as u8];
+        data.extend_from_slice(&(self.max_sessions_per_peer as u32).to_be_bytes());
+        
+        let peers: Vec<_> = self.peers.iter().filter(|(_, p)| !p.live.is_empty()).collect();
+        data.extend_from_slice(&(peers.len() as u32).to_be_bytes());
+        for (peer_id, peer) in peers {
+            data.extend_from_slice(&(peer_id.len() as u16).to_be_bytes());
+            data.extend_from_slice(peer_id.as_bytes());
+            data.extend_from_slice(&(peer.live.len() as u16).to_be_bytes());
+            for (session, clock) in peer.live.iter().zip(&peer.clocks) {
+                let snapshot = SessionSnapshot::capture_session(session)?.to_bytes();
+                data.extend_from_slice(&(snapshot.len() as u32).to_be_bytes());
+                data.extend_from_slice(&snapshot);
+                data.extend_from_slice(&clock.established_ms.to_be_bytes());
+                data.extend_from_slice(&clock.active_ms.to_be_bytes());
+            }
+        }
+        Ok(data)
+    }
+    
+    /// Remove a peer, returning its live sessions
+    ///
+    /// A handshake prepared with [`prewarm`](Self::prewarm) is dropped too.
+    pub fn remove(&mut self, peer_id: &str) -> Vec<NoiseSession> {
+        self.prewarmed.remove(peer_id);
+        self.peers.remove(peer_id).map(|p| p.live).unwrap_or_default()
+    }
+    
+    /// Number of peers with a live session or handshake in progress
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+    
+    /// Check whether the manager holds no sessions
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+    
+    /// Encrypt queued messages for many peers in parallel
+    ///
+    /// Each peer's messages are encrypted in order with its newest live
+    /// session, and the results come back in the same order. Peers without
+    /// a live session get an error for every message.
+    #[cfg(feature = "parallel")]
+    pub fn encrypt_many(&mut self, messages: HashMap<String, Vec<Vec<u8>>>) -> HashMap<String, Vec<Result<Vec<u8>>>> {
+        self.process_many(messages, NoiseSession::encrypt)
+    }
+    
+    /// Decrypt received messages from many peers in parallel
+    ///
+    /// Each peer's messages must be in the order they were sent. A message
+    /// that fails to decrypt does not stop the ones after it.
+    #[cfg(feature = "parallel")]
+    pub fn decrypt_many(&mut self, messages: HashMap<String, Vec<Vec<u8>>>) -> HashMap<String, Vec<Result<Vec<u8>>>> {
+        self.process_many(messages, NoiseSession::decrypt)
+    }
+    
+    #[cfg(feature = "parallel")]
+    fn process_many(
+        &mut self,
+        mut messages: HashMap<String, Vec<Vec<u8>>>,
+        op: fn(&mut NoiseSession, &[u8]) -> Result<Vec<u8>>,
+    ) -> HashMap<String, Vec<Result<Vec<u8>>>> {
+        use rayon::prelude::*;
+        
+        // Sessions are disjoint, so each peer's work can borrow its own
+        let work: Vec<_> = self
+            .peers
+            .iter_mut()
+            .filter_map(|(id, peer)| {
+                let session = peer.live.last_mut()?;
+                let queued = messages.remove(id)?;
+                Some((id.clone(), session, queued))
+            })
+            .collect();
+        let mut results: HashMap<_, _> = work
+            .into_par_iter()
+            .map(|(id, session, queued)| {
+                let processed = queued.iter().map(|message| op(session, message)).collect();
+                (id, processed)
+            })
+            .collect();
+        for (id, queued) in messages {
+            let missing = queued
+                .iter()
+                .map(|_| Err(NoiseError::InvalidState("No session with peer".to_string())))
+                .collect();
+            results.insert(id, missing);
+        }
+        results
+    }
+}
+
+/// An XX initiator session and the opening message it wrote
+fn open_xx(local_private_key: &SecretKey) -> Result<(NoiseSession, Vec<u8>)> {
+    let mut session = NoiseSession::with_private_key(local_private_key, true)?;
+    let message = tagged(MESSAGE_XX_OPENING, &session.write_message(&[])?);
+    Ok((session, message))
+}
+
+/// A handshake message behind its leading `kind` byte
+fn tagged(kind: u8, message: &[u8]) -> Vec<u8> {
+    let mut tagged = Vec::with_capacity(1 + message.len());
+    tagged.push(kind);
+    tagged.extend_from_slice(message);
+    tagged
+}
+
+/// Transport messages a session has sent and received
+fn message_count(session: &NoiseSession) -> u64 {
+    session.sending_nonce().saturating_add(session.receiving_nonce())
+}
+
+/// The first limit `session` has reached, if any
+fn expiry(limits: &SessionLimits, clock: &SessionClock, session: &NoiseSession, now_ms: u64) -> Option<ExpiryReason> {
+    let elapsed = |since: u64| Duration::from_millis(now_ms.saturating_sub(since));
+    if limits.max_messages.is_some() && session.remaining_nonce_budget() == 0 {
+        Some(ExpiryReason::MaxMessages)
+    } else if limits.max_age.is_some_and(|max| elapsed(clock.established_ms) >= max) {
+        Some(ExpiryReason::MaxAge)
+    } else if limits.max_idle.is_some_and(|max| elapsed(clock.active_ms) >= max) {
+        Some(ExpiryReason::Idle)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    use crate::core::keys::public_from_private;
+    
+    /// A static key filled with `byte`
+    fn static_key(byte: u8) -> SecretKey {
+        SecretKey::from_bytes(&[byte; 32]).unwrap()
+    }
+    
+    /// Deliver a message and return the reply, if any
+    fn deliver(to: &mut SessionManager, from: &str, message: Option<Vec<u8>>) -> Option<Vec<u8>> {
+        message.and_then(|m| to.handle_handshake(from, &m).unwrap())
+    }
+    
+    /// Run a full handshake opened by `a` toward `b`
+    fn handshake(a: &mut SessionManager, a_id: &str, b: &mut SessionManager, b_id: &str) {
+        let msg1 = a.connect(b_id).unwrap();
+        let msg2 = deliver(b, a_id, Some(msg1));
+        let msg3 = deliver(a, b_id, msg2);
+        assert!(deliver(b, a_id, msg3).is_none());
+    }
+    
+    /// Drain events, returning how many sessions were established and retired
+    fn drain_events(manager: &mut SessionManager) -> (usize, Vec<Box<NoiseSession>>) {
+        let mut established = 0;
+        let mut retired = Vec::new();
+        while let Some(event) = manager.poll_event() {
+            match event {
+                SessionEvent::Established { .. } => established += 1,
+                SessionEvent::SessionSuperseded { retired: session, .. } => retired.push(session),
+                SessionEvent::EarlyData { .. } | SessionEvent::SessionExpired { .. } => {}
+            }
+        }
+        (established, retired)
+    }
+    
+    #[test]
+    fn test_single_sided_open() {
+        let mut alice = SessionManager::new(&static_key(1u8));
+        let mut bob = SessionManager::new(&static_key(2u8));
+        
+        let msg1 = alice.connect("bob").unwrap();
+        let msg2 = deliver(&mut bob, "alice", Some(msg1));
+        let msg3 = deliver(&mut alice, "bob", msg2);
+        assert!(deliver(&mut bob, "alice", msg3).is_none());
+        
+        assert!(alice.is_established("bob"));
+        assert!(bob.is_established("alice"));
+    }
+    
+    #[test]
+    fn test_simultaneous_open_converges() {
+        let mut alice = SessionManager::new(&static_key(1u8));
+        let mut bob = SessionManager::new(&static_key(2u8));
+        
+        let alice_open = alice.connect("bob").unwrap();
+        let bob_open = bob.connect("alice").unwrap();
+        
+        // Openings cross on the wire; exactly one side yields
+        let alice_reply = alice.handle_handshake("bob", &bob_open).unwrap();
+        let bob_reply = bob.handle_handshake("alice", &alice_open).unwrap();
+        assert!(alice_reply.is_some() != bob_reply.is_some());
+        
+        // The yielding side's reply is the responder message; the winner finishes
+        let (responder, initiator, msg2) = match (alice_reply, bob_reply) {
+            (Some(msg2), None) => ((&mut alice, "alice"), (&mut bob, "bob"), msg2),
+            (None, Some(msg2)) => ((&mut bob, "bob"), (&mut alice, "alice"), msg2),
+            _ => unreachable!(),
+        };
+        let msg3 = deliver(initiator.0, responder.1, Some(msg2));
+        assert!(deliver(responder.0, initiator.1, msg3).is_none());
+        
+        assert!(alice.is_established("bob"));
+        assert!(bob.is_established("alice"));
+        assert_eq!(alice.len(), 1);
+        assert_eq!(bob.len(), 1);
+        
+        let ct = alice.session_mut("bob").unwrap().encrypt(b"one session").unwrap();
+        let pt = bob.session_mut("alice").unwrap().decrypt(&ct).unwrap();
+        assert_eq!(pt, b"one session");
+    }
+    
+    #[test]
+    fn test_failed_handshake_is_removed() {
+        let mut alice = SessionManager::new(&static_key(1u8));
+        alice.connect("bob").unwrap();
+        
+        assert!(alice.handle_handshake("bob", &tagged(MESSAGE_REPLY, &[0u8; 96])).is_err());
+        assert!(alice.session("bob").is_none());
+        assert!(alice.connect("bob").is_ok());
+        
+        // A short reply is not mistaken for a crossing opening
+        assert!(alice.handle_handshake("bob", &tagged(MESSAGE_REPLY, &[0u8; 40])).is_err());
+        assert!(alice.session("bob").is_none());
+    }
+    
+    #[test]
+    fn test_keep_newest_supersedes_live_session() {
+        let mut alice = SessionManager::new(&static_key(1u8));
+        let mut bob = SessionManager::new(&static_key(2u8));
+        handshake(&mut alice, "alice", &mut bob, "bob");
+        assert_eq!(drain_events(&mut bob).0, 1);
+        
+        // Message still in flight on the old session when the peer reconnects
+        let in_flight = alice.session_mut("bob").unwrap().encrypt(b"old").unwrap();
+        handshake(&mut alice, "alice", &mut bob, "bob");
+        
+        let (established, mut retired) = drain_events(&mut bob);
+        assert_eq!(established, 1);
+        assert_eq!(retired.len(), 1);
+        assert_eq!(retired[0].decrypt(&in_flight).unwrap(), b"old");
+        
+        let ct = alice.session_mut("bob").unwrap().encrypt(b"new").unwrap();
+        assert_eq!(bob.session_mut("alice").unwrap().decrypt(&ct).unwrap(), b"new");
+        assert_eq!(bob.sessions_mut("alice").len(), 1);
+    }
+    
+    #[test]
+    fn test_keep_newest_keeps_session_from_other_key() {
+        let mut alice = SessionManager::new(&static_key(1u8));
+        let mut bob = SessionManager::new(&static_key(2u8));
+        let mut mallory = SessionManager::new(&static_key(3u8));
+        handshake(&mut alice, "alice", &mut bob, "bob");
+        drain_events(&mut bob);
+        
+        // Mallory completes a handshake under Alice's peer ID
+        handshake(&mut mallory, "alice", &mut bob, "bob");
+        let (established, retired) = drain_events(&mut bob);
+        assert_eq!(established, 0);
+        assert_eq!(retired[0].get_remote_static(), Some(&public_from_private(&[3u8; 32]).unwrap()[..]));
+        
+        let ct = alice.session_mut("bob").unwrap().encrypt(b"still alice").unwrap();
+        assert_eq!(bob.session_mut("alice").unwrap().decrypt(&ct).unwrap(), b"still alice");
+        
+        // Once removed, a new key is accepted
+        bob.remove("alice");
+        handshake(&mut mallory, "alice", &mut bob, "bob");
+        assert_eq!(drain_events(&mut bob).0, 1);
+    }
+    
+    #[test]
+    fn test_keep_oldest_retires_new_session() {
+        let mut alice = SessionManager::new(&static_key(1u8));
+        let mut bob = SessionManager::new(&static_key(2u8));
+        bob.set_supersession_policy(SupersessionPolicy::KeepOldest);
+        handshake(&mut alice, "alice", &mut bob, "bob");
+        let mut old = alice.remove("bob").pop().unwrap();
+        drain_events(&mut bob);
+        
+        handshake(&mut alice, "alice", &mut bob, "bob");
+        let (established, mut retired) = drain_events(&mut bob);
+        assert_eq!(established, 0);
+        assert_eq!(retired.len(), 1);
+        
+        // The original session stays live; the new one is handed back
+        let ct = old.encrypt(b"old channel").unwrap();
+        assert_eq!(bob.session_mut("alice").unwrap().decrypt(&ct).unwrap(), b"old channel");
+        let ct = alice.session_mut("bob").unwrap().encrypt(b"new channel").unwrap();
+        assert_eq!(retired[0].decrypt(&ct).unwrap(), b"new channel");
+    }
+    
+    #[test]
+    fn test_keep_both_channels() {
+        let mut alice = SessionManager::new(&static_key(1u8));
+        let mut bob = SessionManager::new(&static_key(2u8));
+        bob.set_supersession_policy(SupersessionPolicy::KeepBoth);
+        handshake(&mut alice, "alice", &mut bob, "bob");
+        handshake(&mut bob, "bob", &mut alice, "alice");
+        
+        let (established, retired) = drain_events(&mut bob);
+        assert_eq!(established, 2);
+        assert!(retired.is_empty());
+        assert_eq!(bob.sessions_mut("alice").len(), 2);
+        assert_eq!(bob.len(), 1);
+    }
+    
+    #[test]
+    fn test_per_peer_limit_retires_oldest() {
+        let mut alice = SessionManager::new(&static_key(1u8));
+        let mut bob = SessionManager::new(&static_key(2u8));
+        bob.set_supersession_policy(SupersessionPolicy::KeepBoth);
+        bob.set_max_sessions_per_peer(2);
+        
+        for _ in 0..3 {
+            handshake(&mut alice, "alice", &mut bob, "bob");
+        }
+        let (established, retired) = drain_events(&mut bob);
+        assert_eq!(established, 3);
+        assert_eq!(retired.len(), 1);
+        assert_eq!(bob.sessions_mut("alice").len(), 2);
+    }
+    
+    #[test]
+    fn test_maintenance_iteration() {
+        let mut alice = SessionManager::new(&static_key(1u8));
+        let mut bob = SessionManager::new(&static_key(2u8));
+        let mut carol = SessionManager::new(&static_key(3u8));
+        handshake(&mut bob, "bob", &mut alice, "alice");
+        handshake(&mut carol, "carol", &mut alice, "alice");
+        
+        for (_, session) in alice.iter_mut() {
+            session.rekey_outgoing().unwrap();
+        }
+        assert!(alice.iter_mut().all(|(_, s)| s.sending_generation() == 1));
+        
+        let mut ids: Vec<_> = alice.peer_ids().collect();
+        ids.sort();
+        assert_eq!(ids, ["bob", "carol"]);
+        
+        alice.retain(|peer_id, _| peer_id != "carol");
+        assert_eq!(alice.len(), 1);
+        assert!(!alice.is_established("carol"));
+    }
+    
+    #[test]
+    fn test_serialize_restore() {
+        let mut alice = SessionManager::new(&static_key(1u8));
+        let mut bob = SessionManager::new(&static_key(2u8));
+        bob.set_supersession_policy(SupersessionPolicy::KeepBoth);
+        handshake(&mut alice, "alice", &mut bob, "bob");
+        alice.connect("carol").unwrap();
+        
+        let ct = alice.session_mut("bob").unwrap().encrypt(b"before").unwrap();
+        bob.session_mut("alice").unwrap().decrypt(&ct).unwrap();
+        
+        let data = bob.serialize().unwrap();
+        let mut restored = SessionManager::restore(&static_key(2u8), &data).unwrap();
+        assert!(restored.is_established("alice"));
+        
+        let ct = alice.session_mut("bob").unwrap().encrypt(b"after").unwrap();
+        assert_eq!(restored.session_mut("alice").unwrap().decrypt(&ct).unwrap(), b"after");
+        
+        // Pending handshakes are not carried over
+        let restored_alice = SessionManager::restore(&static_key(1u8), &alice.serialize().unwrap()).unwrap();
+        assert_eq!(restored_alice.len(), 1);
+        
+        assert!(SessionManager::restore(&static_key(2u8), &data[..data.len() - 1]).is_err());
+        
+        // A zero session limit would retire every session as it goes live
+        let mut zero_limit = data.clone();
+        zero_limit[2..6].fill(0);
+        assert_eq!(SessionManager::restore(&static_key(2u8), &zero_limit).unwrap().max_sessions_per_peer, 1);
+    }
+    
+    #[test]
+    fn test_insert_external_session() {
+        let mut manager = SessionManager::new(&static_key(1u8));
+        assert!(manager.insert("bob", NoiseSession::new_initiator().unwrap()).is_err());
+        
+        let mut initiator = NoiseSession::new_initiator().unwrap();
+        let mut responder = NoiseSession::new_responder().unwrap();
+        let msg1 = initiator.write_message(&[]).unwrap();
+        responder.read_message(&msg1).unwrap();
+        let msg2 = responder.write_message(&[]).unwrap();
+        initiator.read_message(&msg2).unwrap();
+        initiator.write_message(&[]).unwrap();
+        
+        let id = initiator.session_id().unwrap();
+        manager.insert("bob", initiator).unwrap();
+        assert!(manager.is_established("bob"));
+        assert!(matches!(
+            manager.poll_event(),
+            Some(SessionEvent::Established { session_id: Some(established), .. }) if established == id
+        ));
+        assert_eq!(manager.find_session(&id).map(|(peer_id, _)| peer_id), Some("bob"));
+        manager.remove("bob");
+        assert!(manager.find_session(&id).is_none());
+    }
+    
+    #[test]
+    fn test_failed_rehandshake_keeps_live_session() {
+        let mut alice = SessionManager::new(&static_key(1u8));
+        let mut bob = SessionManager::new(&static_key(2u8));
+        handshake(&mut alice, "alice", &mut bob, "bob");
+        
+        bob.connect("alice").unwrap();
+        assert!(bob.handle_handshake("alice", &tagged(MESSAGE_REPLY, &[0u8; 96])).is_err());
+        assert!(bob.is_established("alice"));
+        
+        let ct = alice.session_mut("bob").unwrap().encrypt(b"still here").unwrap();
+        assert_eq!(bob.session_mut("alice").unwrap().decrypt(&ct).unwrap(), b"still here");
+    }
+    
+    #[test]
+    fn test_0rtt_reconnect() {
+        let mut alice = SessionManager::new(&static_key(1u8));
+        let mut bob = SessionManager::new(&static_key(2u8));
+        let bob_public = crate::core::keys::public_from_private(&[2u8; 32]).unwrap();
+        
+        let msg1 = alice.connect_0rtt("bob", &bob_public, b"GET /inbox").unwrap();
+        let msg2 = deliver(&mut bob, "alice", Some(msg1.clone()));
+        assert!(deliver(&mut alice, "bob", msg2).is_none());
+        assert!(alice.is_established("bob") && bob.is_established("alice"));
+        
+        match bob.poll_event() {
+            Some(SessionEvent::EarlyData { peer_id, data }) => {
+                assert_eq!(peer_id, "alice");
+                assert_eq!(data, b"GET /inbox");
+            }
+            _ => panic!("expected early data"),
+        }
+        let ct = alice.session_mut("bob").unwrap().encrypt(b"after 0-rtt").unwrap();
+        assert_eq!(bob.session_mut("alice").unwrap().decrypt(&ct).unwrap(), b"after 0-rtt");
+        
+        // A replayed opening is rejected and leaves the live session alone
+        assert!(matches!(bob.handle_handshake("alice", &msg1), Err(NoiseError::ReplayDetected)));
+        assert!(bob.is_established("alice"));
+    }
+    
+    #[test]
+    fn test_0rtt_from_unknown_key_is_rejected() {
+        let mut alice = SessionManager::new(&static_key(1u8));
+        let mut bob = SessionManager::new(&static_key(2u8));
+        let mut mallory = SessionManager::new(&static_key(3u8));
+        let bob_public = crate::core::keys::public_from_private(&[2u8; 32]).unwrap();
+        handshake(&mut alice, "alice", &mut bob, "bob");
+        drain_events(&mut bob);
+        
+        // Mallory claims Alice's peer ID with her own key
+        let forged = mallory.connect_0rtt("bob", &bob_public, b"transfer").unwrap();
+        assert!(matches!(bob.handle_handshake("alice", &forged), Err(NoiseError::PeerKeyMismatch)));
+        assert!(bob.poll_event().is_none());
+        assert_eq!(bob.peers["alice"].live.len(), 1);
+        
+        // Alice herself still reconnects with early data
+        let msg1 = alice.connect_0rtt("bob", &bob_public, b"hello again").unwrap();
+        assert!(deliver(&mut bob, "alice", Some(msg1)).is_some());
+        assert!(matches!(bob.poll_event(), Some(SessionEvent::EarlyData { data, .. }) if data == b"hello again"));
+    }
+    
+    #[test]
+    fn test_0rtt_to_changed_key_fails() {
+        let mut alice = SessionManager::new(&static_key(1u8));
+        let mut bob = SessionManager::new(&static_key(2u8));
+        let stale = crate::core::keys::public_from_private(&[3u8; 32]).unwrap();
+        
+        let msg1 = alice.connect_0rtt("bob", &stale, b"hello").unwrap();
+        assert!(bob.handle_handshake("alice", &msg1).is_err());
+        assert!(bob.poll_event().is_none());
+        assert!(alice.connect_0rtt("bob", &stale, b"again").is_err());
+    }
+    
+    #[test]
+    fn test_failed_open_adds_no_peer() {
+        let mut alice = SessionManager::new(&static_key(1u8));
+        assert!(alice.connect_0rtt("bob", &[0u8; 5], b"hello").is_err());
+        assert!(alice.is_empty());
+    }
+    
+    #[test]
+    fn test_session_limits_expire_sessions() {
+        let mut alice = SessionManager::new(&static_key(1u8));
+        let mut bob = SessionManager::new(&static_key(2u8));
+        bob.set_session_limits(SessionLimits {
+            max_idle: Some(Duration::from_secs(60)),
+            max_age: Some(Duration::from_secs(3600)),
+            max_messages: Some(2),
+        });
+        handshake(&mut alice, "alice", &mut bob, "bob");
+        drain_events(&mut bob);
+        let start = bob.peers["alice"].clocks[0].established_ms;
+        
+        let budget = bob.session_budget_at("alice", start + 10_000).unwrap();
+        assert_eq!(budget.time_remaining, Some(Duration::from_secs(50)));
+        assert_eq!(budget.messages_remaining, 2);
+        
+        // Activity seen at a pass restarts the idle timer
+        let ct = alice.session_mut("bob").unwrap().encrypt(b"one").unwrap();
+        bob.session_mut("alice").unwrap().decrypt(&ct).unwrap();
+        assert_eq!(bob.enforce_limits_at(start + 50_000), 0);
+        assert_eq!(bob.enforce_limits_at(start + 100_000), 0);
+        assert_eq!(bob.session_budget_at("alice", start + 100_000).unwrap().messages_remaining, 1);
+        
+        // The message limit is enforced by the session itself
+        let ct = alice.session_mut("bob").unwrap().encrypt(b"two").unwrap();
+        bob.session_mut("alice").unwrap().decrypt(&ct).unwrap();
+        let ct = alice.session_mut("bob").unwrap().encrypt(b"three").unwrap();
+        assert!(matches!(bob.session_mut("alice").unwrap().decrypt(&ct), Err(NoiseError::NonceExhausted)));
+        assert_eq!(bob.enforce_limits_at(start + 100_000), 1);
+        assert!(matches!(
+            bob.poll_event(),
+            Some(SessionEvent::SessionExpired { reason: ExpiryReason::MaxMessages, .. })
+        ));
+        assert!(!bob.is_established("alice"));
+        
+        // Idle and age limits
+        handshake(&mut alice, "alice", &mut bob, "bob");
+        drain_events(&mut bob);
+        let start = bob.peers["alice"].clocks[0].established_ms;
+        assert_eq!(bob.enforce_limits_at(start + 59_999), 0);
+        assert_eq!(bob.enforce_limits_at(start + 60_000), 1);
+        assert!(matches!(bob.poll_event(), Some(SessionEvent::SessionExpired { reason: ExpiryReason::Idle, .. })));
+        
+        bob.set_session_limits(SessionLimits { max_age: Some(Duration::from_secs(1)), ..Default::default() });
+        handshake(&mut alice, "alice", &mut bob, "bob");
+        drain_events(&mut bob);
+        let start = bob.peers["alice"].clocks[0].established_ms;
+        assert_eq!(bob.enforce_limits_at(start + 1_000), 1);
+        assert!(matches!(bob.poll_event(), Some(SessionEvent::SessionExpired { reason: ExpiryReason::MaxAge, .. })));
+        assert_eq!(bob.len(), 0);
+    }
+    
+    #[test]
+    fn test_sessions_share_buffer_pool() {
+        let mut hub = SessionManager::new(&static_key(1u8));
+        let pool = BufferPool::new(2);
+        hub.set_buffer_pool(Some(pool.clone()));
+        for (i, id) in ["a", "b", "c"].into_iter().enumerate() {
+            let mut peer = SessionManager::new(&static_key(i as u8 + 2));
+            handshake(&mut hub, "hub", &mut peer, id);
+            let session = hub.session_mut(id).unwrap();
+            assert!(session.buffer_pool().is_some());
+            session.encrypt_in_place(&mut b"hello".to_vec()).unwrap();
+        }
+        let stats = hub.buffer_pool_stats().unwrap();
+        assert_eq!((stats.acquired, stats.peak_in_use, stats.idle), (3, 1, 1));
+        
+        hub.set_buffer_pool(None);
+        assert!(hub.session("a").unwrap().buffer_pool().is_none());
+        assert!(hub.buffer_pool_stats().is_none());
+    }
+    
+    #[test]
+    fn test_stats_aggregate_live_sessions() {
+        let mut hub = SessionManager::new(&static_key(1u8));
+        assert_eq!(hub.stats(), SessionStats::default());
+        for (i, id) in ["a", "b"].into_iter().enumerate() {
+            let mut peer = SessionManager::new(&static_key(i as u8 + 2));
+            handshake(&mut hub, "hub", &mut peer, id);
+            let ciphertext = hub.session_mut(id).unwrap().encrypt(b"hello").unwrap();
+            peer.session_mut("hub").unwrap().decrypt(&ciphertext).unwrap();
+        }
+        
+        let total = hub.stats();
+        assert_eq!((total.messages_sent, total.bytes_sent), (2, 42));
+        assert!(total.handshake_duration.is_some());
+        assert_eq!(hub.peer_stats("a").unwrap().messages_sent, 1);
+        assert!(hub.peer_stats("nobody").is_none());
+        
+        hub.remove("a");
+        assert_eq!(hub.stats().messages_sent, 1);
+    }
+    
+    #[test]
+    fn test_restore_keeps_session_age() {
+        let mut alice = SessionManager::new(&static_key(1u8));
+        let mut bob = SessionManager::new(&static_key(2u8));
+        handshake(&mut alice, "alice", &mut bob, "bob");
+        bob.peers.get_mut("alice").unwrap().clocks[0].established_ms -= 5_000;
+        let established = bob.peers["alice"].clocks[0].established_ms;
+        
+        let mut restored = SessionManager::restore(&static_key(2u8), &bob.serialize().unwrap()).unwrap();
+        assert_eq!(restored.peers["alice"].clocks[0].established_ms, established);
+        restored.set_session_limits(SessionLimits { max_age: Some(Duration::from_secs(5)), ..Default::default() });
+        assert_eq!(restored.enforce_limits(), 1);
+    }
+    
+    #[test]
+    fn test_cookie_challenge_under_load() {
+        use crate::mobile::cookie::COOKIE_REPLY_LEN;
+        
+        let mut alice = SessionManager::new(&static_key(1u8));
+        let mut bob = SessionManager::new(&static_key(2u8));
+        bob.set_under_load(true);
+        
+        // The opening is answered with a cookie and leaves no state behind
+        let msg1 = alice.connect("bob").unwrap();
+        let challenge = bob.handle_handshake("alice", &msg1).unwrap().unwrap();
+        assert_eq!(challenge.len(), COOKIE_REPLY_LEN);
+        assert!(bob.is_empty());
+        
+        let resent = deliver(&mut alice, "bob", Some(challenge)).unwrap();
+        // A cookie is only good for the peer it was issued to
+        assert_eq!(bob.handle_handshake("mallory", &resent).unwrap().unwrap().len(), COOKIE_REPLY_LEN);
+        let msg2 = deliver(&mut bob, "alice", Some(resent));
+        let msg3 = deliver(&mut alice, "bob", msg2);
+        assert!(deliver(&mut bob, "alice", msg3).is_none());
+        assert!(alice.is_established("bob") && bob.is_established("alice"));
+        
+        // 0-RTT openings are challenged the same way
+        let mut carol = SessionManager::new(&static_key(3u8));
+        let bob_public = crate::core::keys::public_from_private(&[2u8; 32]).unwrap();
+        let msg1 = carol.connect_0rtt("bob", &bob_public, b"hi").unwrap();
+        let challenge = deliver(&mut bob, "carol", Some(msg1));
+        let resent = deliver(&mut carol, "bob", challenge);
+        let msg2 = deliver(&mut bob, "carol", resent);
+        assert!(deliver(&mut carol, "bob", msg2).is_none());
+        assert!(carol.is_established("bob") && bob.is_established("carol"));
+    }
+    
+    #[test]
+    fn test_rate_limit_and_blocklist() {
+        let mut alice = SessionManager::new(&static_key(1u8));
+        let mut bob = SessionManager::new(&static_key(2u8));
+        bob.set_rate_limiter(Some(RateLimiter::new(1, Duration::from_secs(60))));
+        
+        handshake(&mut alice, "alice", &mut bob, "bob");
+        let msg1 = alice.connect("bob").unwrap();
+        assert!(bob.handle_handshake("alice", &msg1).is_err());
+        assert!(bob.handle_handshake("carol", &msg1).unwrap().is_some());
+        
+        // Blocked peer IDs are refused outright
+        bob.set_rate_limiter(None);
+        bob.block_peer("alice");
+        assert!(bob.is_peer_blocked("alice"));
+        assert!(bob.handle_handshake("alice", &msg1).is_err());
+        bob.unblock_peer("alice");
+        
+        // A blocked static key fails the handshake once it is revealed
+        let mut mallory = SessionManager::new(&static_key(3u8));
+        bob.block_static_key(&crate::core::keys::public_from_private(&[3u8; 32]).unwrap());
+        let msg1 = mallory.connect("bob").unwrap();
+        let msg2 = deliver(&mut bob, "mallory", Some(msg1));
+        let msg3 = deliver(&mut mallory, "bob", msg2).unwrap();
+        assert!(matches!(bob.handle_handshake("mallory", &msg3), Err(NoiseError::HandshakeFailed)));
+        assert!(!bob.is_established("mallory"));
+        
+        // Also for 0-RTT, before any early data is delivered
+        let bob_public = crate::core::keys::public_from_private(&[2u8; 32]).unwrap();
+        let msg1 = mallory.connect_0rtt("bob2", &bob_public, b"drain").unwrap();
+        assert!(bob.handle_handshake("mallory2", &msg1).is_err());
+        while let Some(event) = bob.poll_event() {
+            assert!(!matches!(event, SessionEvent::EarlyData { .. }));
+        }
+    }
+    
+    #[test]
+    fn test_prewarmed_connect() {
+        let mut alice = SessionManager::new(&static_key(1u8));
+        let mut bob = SessionManager::new(&static_key(2u8));
+        
+        alice.prewarm("bob").unwrap();
+        assert!(alice.is_prewarmed("bob") && alice.is_empty());
+        let prepared = alice.prewarmed["bob"].1.clone();
+        alice.prewarm("bob").unwrap();
+        assert_eq!(alice.prewarmed["bob"].1, prepared);
+        
+        let msg1 = alice.connect("bob").unwrap();
+        assert_eq!(msg1, prepared);
+        assert!(!alice.is_prewarmed("bob"));
+        let msg2 = deliver(&mut bob, "alice", Some(msg1));
+        let msg3 = deliver(&mut alice, "bob", msg2);
+        assert!(deliver(&mut bob, "alice", msg3).is_none());
+        assert!(alice.is_established("bob") && bob.is_established("alice"));
+        
+        // The next handshake gets a fresh ephemeral key
+        alice.prewarm("bob").unwrap();
+        assert_ne!(alice.prewarmed["bob"].1, prepared);
+        assert!(alice.discard_prewarmed("bob"));
+        assert!(!alice.discard_prewarmed("bob"));
+    }
+    
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_parallel_encrypt_decrypt() {
+        let mut hub = SessionManager::new(&static_key(1u8));
+        let mut spokes: Vec<_> = (2u8..6).map(|i| (format!("peer-{i}"), SessionManager::new(&static_key(i)))).collect();
+        for (id, spoke) in &mut spokes {
+            handshake(&mut hub, "hub", spoke, id);
+        }
+        
+        let queued: HashMap<_, _> = spokes
+            .iter()
+            .map(|(id, _)| (id.clone(), (0..3).map(|n| format!("{id} #{n}").into_bytes()).collect()))
+            .chain([("nobody".to_string(), vec![b"lost".to_vec()])])
+            .collect();
+        let mut sent = hub.encrypt_many(queued);
+        assert!(sent.remove("nobody").unwrap()[0].is_err());
+        
+        for (id, spoke) in &mut spokes {
+            let ciphertexts: Vec<_> = sent.remove(id.as_str()).unwrap().into_iter().map(Result::unwrap).collect();
+            let mut received = spoke.decrypt_many(HashMap::from([("hub".to_string(), ciphertexts)]));
+            let plaintexts: Vec<_> = received.remove("hub").unwrap().into_iter().map(Result::unwrap).collect();
+            assert_eq!(plaintexts, (0..3).map(|n| format!("{id} #{n}").into_bytes()).collect::<Vec<_>>());
+        }
+    }
+}
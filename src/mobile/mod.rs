@@ -1,3 +1,39 @@
 pub mod storage;
 pub mod network;
+pub mod snapshot;
+#[cfg(feature = "manager")]
+pub mod manager;
+pub mod framing;
+pub mod wire;
+#[cfg(feature = "ble")]
+pub mod ble;
+pub mod connection;
+pub mod transport;
+#[cfg(feature = "datagram")]
+pub mod datagram;
+pub mod driver;
+pub mod cookie;
+pub mod ratelimit;
+#[cfg(feature = "cover")]
+pub mod cover;
+pub mod blocking;
+#[cfg(feature = "identity")]
+pub mod trust;
+#[cfg(feature = "identity")]
+pub mod pairing;
+#[cfg(feature = "identity")]
+pub mod identity;
+#[cfg(feature = "group")]
+pub mod group;
+#[cfg(feature = "ratchet")]
+pub mod ratchet;
+#[cfg(feature = "outbox")]
+pub mod outbox;
+#[cfg(feature = "tokio")]
+pub mod stream;
+#[cfg(feature = "tokio-util")]
+pub mod codec;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+#[cfg(feature = "batching")]
 pub mod battery;
\ No newline at end of file
@@ -1,54 +1,559 @@
-use crate::core::error::{NoiseError, Result};
+use crate::core::compression::{self, CompressionPolicy};
+use crate::core::crypto::random_bytes;
+use crate::core::error::{DeserializeError, NoiseError, Result};
 use crate::core::session::NoiseSession;
-use std::collections::VecDeque;
+#[cfg(feature = "serde")]
+use crate::mobile::snapshot::BytesVisitor;
+use crate::mobile::storage::KeyStorage;
+use std::io::IoSlice;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-/// Size of the replay protection window
+/// Default size of the replay protection window
 const REPLAY_WINDOW_SIZE: usize = 64;
 
+/// Largest replay window a session will allocate (8 KiB of bitmap)
+pub const MAX_REPLAY_WINDOW_SIZE: usize = 1 << 16;
+
+/// Bits per bitmap block in the replay window
+const REPLAY_BLOCK_BITS: u64 = 64;
+
+/// Format version written by [`ResilientSession::serialize`]
+///
+/// Version 2 replaced the fixed layout of version 1 with tagged records.
+const STATE_VERSION: u8 = 2;
+
+/// Set on record tags that a reader must understand to restore the session
+const RECORD_CRITICAL: u8 = 0x80;
+
+/// Send and receive sequence numbers
+const RECORD_SEQUENCES: u8 = RECORD_CRITICAL | 1;
+
+/// Replay window size and bits
+const RECORD_REPLAY_WINDOW: u8 = RECORD_CRITICAL | 2;
+
+/// Explicit-nonce mode and whether the session is closed
+const RECORD_FLAGS: u8 = RECORD_CRITICAL | 3;
+
+/// Compression policy; a reader that skips it only rejects compressed frames
+const RECORD_COMPRESSION: u8 = 4;
+
+/// Lowest transport nonce the session may still send with
+///
+/// Only written by checkpoints and only read when merging one, since
+/// [`ResilientSession::deserialize`] takes the transport state separately.
+const RECORD_SENDING_NONCE: u8 = 5;
+
+/// Sending and receiving rekey generations of the transport session
+const RECORD_GENERATIONS: u8 = RECORD_CRITICAL | 6;
+
+/// Static key the peer authenticated with
+const RECORD_REMOTE_STATIC: u8 = RECORD_CRITICAL | 7;
+
+/// Sending nonces reserved by each checkpoint write
+///
+/// A checkpoint records the sending nonce plus this reserve, and a new one is
+/// written before the reserve runs out, so a session restored from older
+/// state never reuses a nonce even if it crashed between checkpoints.
+const CHECKPOINT_NONCE_RESERVE: u64 = 1024;
+
+const FLAG_EXPLICIT_NONCE: u8 = 1;
+const FLAG_CLOSED: u8 = 2;
+
+/// Sliding replay window packed into a ring of `u64` bitmap blocks
+///
+/// Sequence `seq` lives at bit `seq % 64` of block `(seq / 64) % blocks.len()`.
+/// Advancing only clears the blocks the window moves into, so the cost per
+/// message does not depend on the window size.
+#[derive(Clone)]
+struct ReplayWindow {
+    blocks: Vec<u64>,
+    size: usize,
+}
+
+impl ReplayWindow {
+    fn new(size: usize) -> Self {
+        let size = size.min(MAX_REPLAY_WINDOW_SIZE);
+        // One spare block so the block being entered never holds live bits
+        Self {
+            blocks: vec![0; size.div_ceil(REPLAY_BLOCK_BITS as usize) + 1],
+            size,
+        }
+    }
+    
+    fn slot(&self, sequence: u64) -> (usize, u64) {
+        let block = (sequence / REPLAY_BLOCK_BITS) % self.blocks.len() as u64;
+        (block as usize, 1 << (sequence % REPLAY_BLOCK_BITS))
+    }
+    
+    fn contains(&self, sequence: u64) -> bool {
+        let (block, bit) = self.slot(sequence);
+        self.blocks[block] & bit != 0
+    }
+    
+    fn insert(&mut self, sequence: u64) {
+        let (block, bit) = self.slot(sequence);
+        self.blocks[block] |= bit;
+    }
+    
+    /// Mark what `other`, with newest sequence `other_last`, has seen and
+    /// this window, with newest sequence `last`, still covers
+    ///
+    /// The windows may differ in size; `last` must be at least `other_last`.
+    fn merge(&mut self, last: u64, other: &ReplayWindow, other_last: u64) {
+        for sequence in (1..=other_last).rev().take(other.size) {
+            if last - sequence >= self.size as u64 {
+                break;
+            }
+            if other.contains(sequence) {
+                self.insert(sequence);
+            }
+        }
+    }
+    
+    /// Slide the newest sequence from `last` to `next`, clearing entered blocks
+    fn advance(&mut self, last: u64, next: u64) {
+        let from = last / REPLAY_BLOCK_BITS;
+        let to = next / REPLAY_BLOCK_BITS;
+        
+        if to - from >= self.blocks.len() as u64 {
+            self.blocks.fill(0);
+        } else {
+            for block in from + 1..=to {
+                let index = (block % self.blocks.len() as u64) as usize;
+                self.blocks[index] = 0;
+            }
+        }
+    }
+}
+
+/// Called with the first missing sequence number and how many are missing
+pub type GapCallback = Arc<dyn Fn(u64, u64) + Send + Sync>;
+
+/// Bit set in the type byte of a data frame whose body is LZ4-compressed
+pub const FRAME_COMPRESSED: u8 = 0x80;
+
+/// Type byte at the front of every frame sent with [`ResilientSession::send_frame`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FrameType {
+    /// Application data
+    Data = 0,
+    /// Acknowledges the frame with the sequence number in the body
+    Ack = 1,
+    /// Keeps NAT bindings and idle timers alive; no body
+    Keepalive = 2,
+    /// The sender rekeyed its outgoing direction after this frame; no body
+    Rekey = 3,
+    /// The sender is closing the session; the body is an optional reason
+    Close = 4,
+    /// Cover traffic; the body is filler
+    Cover = 5,
+}
+
+impl FrameType {
+    pub(crate) fn from_u8(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(FrameType::Data),
+            1 => Ok(FrameType::Ack),
+            2 => Ok(FrameType::Keepalive),
+            3 => Ok(FrameType::Rekey),
+            4 => Ok(FrameType::Close),
+            5 => Ok(FrameType::Cover),
+            _ => Err(NoiseError::InvalidMessage),
+        }
+    }
+}
+
+/// A frame taken apart by [`ResilientSession::receive_frame`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    /// Application data, with the sequence number to acknowledge it by
+    Data {
+        /// Sequence number of the frame
+        sequence: u64,
+        /// The data
+        payload: Vec<u8>,
+    },
+    /// The peer received the frame with this sequence number
+    Ack(u64),
+    /// The peer is still there
+    Keepalive,
+    /// The peer rekeyed; the receiving direction has already followed
+    Rekey,
+    /// The peer closed the session
+    Close(Vec<u8>),
+    /// Cover traffic, to be discarded
+    Cover,
+}
+
 /// ResilientSession provides network resilience features on top of NoiseSession
 /// 
 /// Features:
 /// - Sequence number tracking for message ordering
 /// - Replay attack prevention with sliding window
 /// - Session state serialization for resumption
+/// - Debounced checkpoints of the replay state to a [`KeyStorage`]
 /// - Out-of-order message handling
+///
+/// By default messages must be decrypted in the order they were sent. In
+/// explicit-nonce mode each frame carries its transport nonce, which doubles
+/// as the sequence number, so frames can be decrypted in any order over
+/// lossy transports such as BLE or UDP.
+///
+/// With the `serde` feature the state serializes as the bytes of
+/// [`serialize`](Self::serialize); deserialize it with [`ResilientSessionSeed`].
 pub struct ResilientSession {
     inner: NoiseSession,
     last_sent: u64,
     last_received: u64,
-    replay_window: VecDeque<bool>,
+    replay_window: ReplayWindow,
+    explicit_nonce: bool,
+    gap_callback: Option<GapCallback>,
+    /// A close frame has been sent or received
+    closed: bool,
+    compression: Option<CompressionPolicy>,
+    checkpoint: Option<Checkpoint>,
+}
+
+/// Where and how often replay state is checkpointed
+struct Checkpoint {
+    storage: Arc<dyn KeyStorage>,
+    slot_id: String,
+    interval: Duration,
+    last_saved: Instant,
+    /// Sequence state has changed since the last write
+    dirty: bool,
+    /// Sending nonce the stored checkpoint reserves up to (exclusive)
+    reserved_nonce: u64,
 }
 
 impl ResilientSession {
     /// Create a new resilient session from a NoiseSession
     pub fn new(session: NoiseSession) -> Self {
-        let mut replay_window = VecDeque::with_capacity(REPLAY_WINDOW_SIZE);
-        replay_window.resize(REPLAY_WINDOW_SIZE, false);
-        
+        Self::with_replay_window(session, REPLAY_WINDOW_SIZE)
+    }
+    
+    /// Create a resilient session with a replay window of `size` messages
+    ///
+    /// Larger windows (1024+) tolerate more reordering on lossy links at a
+    /// cost of `size / 8` bytes. Sizes are capped at [`MAX_REPLAY_WINDOW_SIZE`].
+    pub fn with_replay_window(session: NoiseSession, size: usize) -> Self {
         Self {
             inner: session,
             last_sent: 0,
             last_received: 0,
-            replay_window,
+            replay_window: ReplayWindow::new(size),
+            explicit_nonce: false,
+            gap_callback: None,
+            closed: false,
+            compression: None,
+            checkpoint: None,
+        }
+    }
+    
+    /// Create a resilient session that uses explicit-nonce frames
+    pub fn with_explicit_nonces(session: NoiseSession) -> Self {
+        let mut resilient = Self::new(session);
+        resilient.explicit_nonce = true;
+        resilient
+    }
+    
+    /// Switch explicit-nonce framing on or off (both peers must agree)
+    pub fn set_explicit_nonces(&mut self, enabled: bool) {
+        self.explicit_nonce = enabled;
+    }
+    
+    /// Check whether explicit-nonce framing is enabled
+    pub fn uses_explicit_nonces(&self) -> bool {
+        self.explicit_nonce
+    }
+    
+    /// Report skipped sequence numbers to `on_gap(first_missing, count)`
+    ///
+    /// Called when an authenticated message jumps past the newest one seen,
+    /// so the app can ask for retransmission or show that messages are
+    /// missing. Missing messages that arrive later are still accepted while
+    /// they are inside the replay window. Without explicit nonces a gap can
+    /// only be seen if the transport drops whole messages in order.
+    pub fn set_gap_callback(&mut self, on_gap: impl Fn(u64, u64) + Send + Sync + 'static) {
+        self.gap_callback = Some(Arc::new(on_gap));
+    }
+    
+    /// Stop reporting gaps
+    pub fn clear_gap_callback(&mut self) {
+        self.gap_callback = None;
+    }
+    
+    /// Allow compressed data frames, or forbid them with `None`
+    ///
+    /// Off by default. Both peers must enable it: a compressed frame
+    /// arriving while it is off is rejected. Compression only happens in
+    /// [`send_compressed`](Self::send_compressed); read the
+    /// [side-channel warning](crate::core::compression) before using it.
+    pub fn set_compression(&mut self, policy: Option<CompressionPolicy>) {
+        self.compression = policy;
+    }
+    
+    /// The compression policy, if compression is enabled
+    pub fn compression(&self) -> Option<CompressionPolicy> {
+        self.compression
+    }
+    
+    /// Checkpoint sequence numbers and the replay window to `storage`
+    ///
+    /// The replay state is written to the session slot `slot_id` at most
+    /// once per `interval`, after a message is sent or received, so a crash
+    /// loses at most `interval` worth of progress instead of resetting replay
+    /// protection to whatever was last serialized. A checkpoint already in
+    /// the slot is merged in first, keeping whichever sequence numbers are
+    /// further ahead and the messages either replay window has seen; enable
+    /// checkpoints right after restoring a session to pick up progress made
+    /// since its snapshot.
+    ///
+    /// Each write also reserves a block of sending nonces, and merging skips
+    /// the inner session past the stored reservation so a restored session
+    /// cannot reuse a nonce. Only messages sent through this session count:
+    /// sending on [`inner_mut`](Self::inner_mut) directly bypasses the
+    /// reservation. Skipping nonces needs a stateless transport; sessions
+    /// adopted with `NoiseSession::from_snow_transport` fail to merge a
+    /// checkpoint that is ahead of them.
+    ///
+    /// The slot belongs to one set of transport keys. A new handshake starts
+    /// sequences again at 1, so give each session its own `slot_id` and
+    /// delete the slot once the session is gone. Writes made while sending
+    /// or receiving that fail are retried on the next message; call
+    /// [`checkpoint`](Self::checkpoint) before the app is suspended.
+    pub fn enable_checkpoints(&mut self, storage: Arc<dyn KeyStorage>, slot_id: &str, interval: Duration) -> Result<()> {
+        match storage.load_session(slot_id) {
+            Ok(data) => self.merge_checkpoint(&data)?,
+            Err(NoiseError::InvalidParameter) => {}
+            Err(e) => return Err(e),
+        }
+        self.checkpoint = Some(Checkpoint {
+            storage,
+            slot_id: slot_id.to_string(),
+            interval,
+            last_saved: Instant::now(),
+            dirty: false,
+            reserved_nonce: 0,
+        });
+        self.checkpoint().inspect_err(|_| self.checkpoint = None)
+    }
+    
+    /// Stop checkpointing; the last checkpoint stays in storage
+    pub fn disable_checkpoints(&mut self) {
+        self.checkpoint = None;
+    }
+    
+    /// Write a checkpoint now, regardless of the interval
+    pub fn checkpoint(&mut self) -> Result<()> {
+        let Some(checkpoint) = &self.checkpoint else {
+            return Err(NoiseError::InvalidState("Checkpoints not enabled".to_string()));
+        };
+        let reserved_nonce = self.inner.sending_nonce().saturating_add(CHECKPOINT_NONCE_RESERVE);
+        let mut data = self.checkpoint_bytes();
+        push_record(&mut data, RECORD_SENDING_NONCE, &reserved_nonce.to_be_bytes());
+        checkpoint.storage.store_session(&checkpoint.slot_id, &data)?;
+        if let Some(checkpoint) = &mut self.checkpoint {
+            checkpoint.last_saved = Instant::now();
+            checkpoint.dirty = false;
+            checkpoint.reserved_nonce = reserved_nonce;
+        }
+        Ok(())
+    }
+    
+    /// Whether sequence state has changed since the last checkpoint
+    pub fn has_pending_checkpoint(&self) -> bool {
+        self.checkpoint.as_ref().is_some_and(|checkpoint| checkpoint.dirty)
+    }
+    
+    /// Record a sequence change, writing a checkpoint if one is due
+    fn note_progress(&mut self) {
+        let Some(checkpoint) = &mut self.checkpoint else {
+            return;
+        };
+        checkpoint.dirty = true;
+        if checkpoint.last_saved.elapsed() >= checkpoint.interval {
+            // A failed write leaves the checkpoint pending for the next message
+            let _ = self.checkpoint();
         }
     }
     
     /// Encrypt a message with sequence number for ordering
     pub fn encrypt_with_sequence(&mut self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        if self.checkpoint.as_ref().is_some_and(|checkpoint| self.inner.sending_nonce() >= checkpoint.reserved_nonce) {
+            // Never send with a nonce no stored checkpoint accounts for
+            self.checkpoint()?;
+        }
+        let frame = self.encrypt_next(plaintext)?;
+        self.note_progress();
+        Ok(frame)
+    }
+    
+    fn encrypt_next(&mut self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        if self.explicit_nonce {
+            // The nonce is the sequence number, offset so sequences start at 1
+            let sequence = self.inner.sending_nonce() + 1;
+            let frame = self.inner.encrypt_with_explicit_nonce(plaintext)?;
+            self.last_sent = sequence;
+            return Ok(frame);
+        }
+        
         // Increment sequence number
         self.last_sent = self.last_sent.wrapping_add(1);
         
-        // Prepare message with sequence number prefix
-        let mut message = Vec::with_capacity(8 + plaintext.len());
-        message.extend_from_slice(&self.last_sent.to_be_bytes());
-        message.extend_from_slice(plaintext);
-        
-        // Encrypt the complete message
-        self.inner.encrypt(&message)
+        // Encrypt the message with its sequence number prefix
+        let sequence = self.last_sent.to_be_bytes();
+        self.inner.encrypt_vectored(&[IoSlice::new(&sequence), IoSlice::new(plaintext)])
     }
     
     /// Decrypt a message and check for replay attacks
     pub fn decrypt_with_replay_check(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        self.decrypt_sequenced(ciphertext).map(|(_, plaintext)| plaintext)
+    }
+    
+    /// Send a typed frame, multiplexing control traffic with data
+    ///
+    /// Frames are [`encrypt_with_sequence`](Self::encrypt_with_sequence)
+    /// messages whose first byte is the [`FrameType`]; read them with
+    /// [`receive_frame`](Self::receive_frame). Prefer the `send_*` helpers.
+    /// Fails once a close frame has been sent or received.
+    pub fn send_frame(&mut self, frame_type: FrameType, body: &[u8]) -> Result<Vec<u8>> {
+        self.send_typed(frame_type as u8, body)
+    }
+    
+    fn send_typed(&mut self, type_byte: u8, body: &[u8]) -> Result<Vec<u8>> {
+        if self.closed {
+            return Err(NoiseError::InvalidState("Session closed".to_string()));
+        }
+        let mut message = Vec::with_capacity(1 + body.len());
+        message.push(type_byte);
+        message.extend_from_slice(body);
+        self.encrypt_with_sequence(&message)
+    }
+    
+    /// Send application data as a [`FrameType::Data`] frame
+    pub fn send_data(&mut self, payload: &[u8]) -> Result<Vec<u8>> {
+        self.send_frame(FrameType::Data, payload)
+    }
+    
+    /// Send application data, compressed if that makes it shorter
+    ///
+    /// Payloads under the policy's `min_len`, or that LZ4 cannot shrink,
+    /// are sent as a plain data frame. Never use this for a payload that
+    /// mixes secrets with data an attacker can influence: the compressed
+    /// length would leak the secret. Fails if compression is not enabled.
+    pub fn send_compressed(&mut self, payload: &[u8]) -> Result<Vec<u8>> {
+        let policy = self.compression.ok_or_else(|| NoiseError::InvalidState("Compression not enabled".to_string()))?;
+        if payload.len() >= policy.min_len {
+            let compressed = compression::compress(payload);
+            if compressed.len() < payload.len() {
+                return self.send_typed(FrameType::Data as u8 | FRAME_COMPRESSED, &compressed);
+            }
+        }
+        self.send_data(payload)
+    }
+    
+    /// Acknowledge the frame with `sequence`
+    pub fn send_ack(&mut self, sequence: u64) -> Result<Vec<u8>> {
+        self.send_frame(FrameType::Ack, &sequence.to_be_bytes())
+    }
+    
+    /// Send a keepalive
+    pub fn send_keepalive(&mut self) -> Result<Vec<u8>> {
+        self.send_frame(FrameType::Keepalive, &[])
+    }
+    
+    /// Tell the peer to follow a rekey, then rekey the outgoing direction
+    ///
+    /// The returned frame is still under the old key. Without explicit
+    /// nonces the peer must receive it before any later frame; with them a
+    /// lost rekey frame is harmless.
+    pub fn send_rekey(&mut self) -> Result<Vec<u8>> {
+        let frame = self.send_frame(FrameType::Rekey, &[])?;
+        self.inner.rekey_outgoing()?;
+        Ok(frame)
+    }
+    
+    /// Close the session, with an optional reason for the peer
+    pub fn send_close(&mut self, reason: &[u8]) -> Result<Vec<u8>> {
+        let frame = self.send_frame(FrameType::Close, reason)?;
+        self.closed = true;
+        Ok(frame)
+    }
+    
+    /// Send a dummy frame with `len` bytes of random filler
+    ///
+    /// On the wire it looks like a data frame of the same length; with
+    /// [padding](crate::core::padding) on, like any data frame in its bucket.
+    pub fn send_cover(&mut self, len: usize) -> Result<Vec<u8>> {
+        let mut filler = vec![0u8; len];
+        random_bytes(&mut filler);
+        self.send_frame(FrameType::Cover, &filler)
+    }
+    
+    /// Decrypt a frame from [`send_frame`](Self::send_frame), applying
+    /// control frames to the session
+    ///
+    /// A rekey frame moves the receiving direction to the next key (with
+    /// explicit nonces the next frame does that itself, so frames reordered
+    /// around the rekey still decrypt), and a close frame closes the
+    /// session for sending. Frames are still accepted after a close so
+    /// traffic in flight can drain. Compressed data frames are
+    /// decompressed, and rejected if compression is off or they would
+    /// grow past the policy's `max_decompressed_len`.
+    pub fn receive_frame(&mut self, ciphertext: &[u8]) -> Result<Frame> {
+        let (sequence, plaintext) = self.decrypt_sequenced(ciphertext)?;
+        let (&frame_type, body) = plaintext.split_first().ok_or(NoiseError::InvalidMessage)?;
+        if frame_type == FrameType::Data as u8 | FRAME_COMPRESSED {
+            let policy = self.compression.ok_or(NoiseError::InvalidMessage)?;
+            let payload = compression::decompress(body, policy.max_decompressed_len)?;
+            return Ok(Frame::Data { sequence, payload });
+        }
+        match FrameType::from_u8(frame_type)? {
+            FrameType::Data => Ok(Frame::Data { sequence, payload: body.to_vec() }),
+            FrameType::Ack => {
+                let acked = body.try_into().map_err(|_| NoiseError::InvalidMessage)?;
+                Ok(Frame::Ack(u64::from_be_bytes(acked)))
+            }
+            FrameType::Keepalive => Ok(Frame::Keepalive),
+            FrameType::Rekey => {
+                if !self.explicit_nonce {
+                    self.inner.rekey_incoming()?;
+                }
+                Ok(Frame::Rekey)
+            }
+            FrameType::Close => {
+                self.closed = true;
+                Ok(Frame::Close(body.to_vec()))
+            }
+            FrameType::Cover => Ok(Frame::Cover),
+        }
+    }
+    
+    /// Whether a close frame has been sent or received
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+    
+    /// Decrypt and replay-check a message, returning its sequence number
+    fn decrypt_sequenced(&mut self, ciphertext: &[u8]) -> Result<(u64, Vec<u8>)> {
+        let message = self.decrypt_next(ciphertext)?;
+        self.note_progress();
+        Ok(message)
+    }
+    
+    fn decrypt_next(&mut self, ciphertext: &[u8]) -> Result<(u64, Vec<u8>)> {
+        if self.explicit_nonce {
+            // Only authenticated nonces may move the replay window
+            let (nonce, plaintext) = self.inner.decrypt_with_explicit_nonce(ciphertext)?;
+            if !self.check_and_update_replay_window(nonce + 1)? {
+                return Err(NoiseError::ReplayDetected);
+            }
+            return Ok((nonce + 1, plaintext));
+        }
+        
         // First decrypt the message
         let decrypted = self.inner.decrypt(ciphertext)?;
         
@@ -67,7 +572,7 @@ impl ResilientSession {
         }
         
         // Return the actual payload (without sequence number)
-        Ok(decrypted[8..].to_vec())
+        Ok((sequence, decrypted[8..].to_vec()))
     }
     
     /// Check if a sequence number is valid and update the replay window
@@ -80,41 +585,34 @@ impl ResilientSession {
         if sequence <= self.last_received {
             // Check if it's in the replay window
             let diff = self.last_received - sequence;
-            if diff >= REPLAY_WINDOW_SIZE as u64 {
+            if diff >= self.replay_window.size as u64 {
                 // Too old, definitely a replay
                 return Ok(false);
             }
             
             // Check if we've seen this sequence number before
-            let window_index = diff as usize;
-            if self.replay_window[window_index] {
+            if self.replay_window.contains(sequence) {
                 // Already seen, it's a replay
                 return Ok(false);
             }
             
             // Mark as seen
-            self.replay_window[window_index] = true;
+            self.replay_window.insert(sequence);
         } else {
-            // New sequence number, advance the window
-            let advance = sequence - self.last_received;
-            
-            if advance > REPLAY_WINDOW_SIZE as u64 {
-                // Big jump, reset the window
-                self.replay_window.clear();
-                self.replay_window.resize(REPLAY_WINDOW_SIZE, false);
-            } else {
-                // Shift the window
-                for _ in 0..advance {
-                    self.replay_window.pop_back();
-                    self.replay_window.push_front(false);
+            if sequence - self.last_received > 1 {
+                if let Some(on_gap) = &self.gap_callback {
+                    on_gap(self.last_received + 1, sequence - self.last_received - 1);
                 }
             }
             
+            // New sequence number, advance the window
+            self.replay_window.advance(self.last_received, sequence);
+            
             // Update last received
             self.last_received = sequence;
             
             // Mark the current sequence as seen
-            self.replay_window[0] = true;
+            self.replay_window.insert(sequence);
         }
         
         Ok(true)
@@ -122,121 +620,290 @@ impl ResilientSession {
     
     /// Set the replay window size (for testing or tuning)
     pub fn set_replay_window_size(&mut self, size: usize) {
-        self.replay_window.clear();
-        self.replay_window.resize(size, false);
+        self.replay_window = ReplayWindow::new(size);
+    }
+    
+    /// Get the replay window size
+    pub fn replay_window_size(&self) -> usize {
+        self.replay_window.size
     }
     
     /// Serialize the session state for resumption
+    ///
+    /// Written in the version 2 record format; see [`ResilientSession::deserialize`].
     pub fn serialize(&self) -> Vec<u8> {
-        // For now, we'll create a simple serialization format
-        // In production, consider using serde or similar
-        let mut data = Vec::new();
-        
-        // Version byte
-        data.push(1u8);
-        
-        // Sequence numbers
-        data.extend_from_slice(&self.last_sent.to_be_bytes());
-        data.extend_from_slice(&self.last_received.to_be_bytes());
-        
-        // Replay window size
-        data.extend_from_slice(&(self.replay_window.len() as u32).to_be_bytes());
-        
-        // Replay window bits (packed into bytes)
-        let mut window_bytes = Vec::new();
-        let mut current_byte = 0u8;
-        let mut bit_count = 0;
+        let mut data = self.checkpoint_bytes();
         
-        for &bit in &self.replay_window {
-            if bit {
-                current_byte |= 1 << (7 - bit_count);
-            }
-            bit_count += 1;
-            
-            if bit_count == 8 {
-                window_bytes.push(current_byte);
-                current_byte = 0;
-                bit_count = 0;
-            }
+        let mut flags = 0;
+        if self.explicit_nonce {
+            flags |= FLAG_EXPLICIT_NONCE;
         }
-        
-        // Push remaining bits if any
-        if bit_count > 0 {
-            window_bytes.push(current_byte);
+        if self.closed {
+            flags |= FLAG_CLOSED;
         }
+        push_record(&mut data, RECORD_FLAGS, &[flags]);
         
-        data.extend_from_slice(&window_bytes);
+        if let Some(policy) = self.compression {
+            let mut compression = Vec::with_capacity(16);
+            compression.extend_from_slice(&(policy.min_len as u64).to_be_bytes());
+            compression.extend_from_slice(&(policy.max_decompressed_len as u64).to_be_bytes());
+            push_record(&mut data, RECORD_COMPRESSION, &compression);
+        }
         
-        // Note: We don't serialize the inner NoiseSession as it contains
-        // cryptographic state that should be regenerated, not restored
+        let mut generations = self.inner.sending_generation().to_be_bytes().to_vec();
+        generations.extend_from_slice(&self.inner.receiving_generation().to_be_bytes());
+        push_record(&mut data, RECORD_GENERATIONS, &generations);
+        if let Some(remote_static) = self.inner.get_remote_static() {
+            push_record(&mut data, RECORD_REMOTE_STATIC, remote_static);
+        }
         
+        // The transport keys themselves are captured separately by SessionSnapshot
         data
     }
     
+    /// Sequence numbers and replay window, the state kept by checkpoints
+    ///
+    /// A valid serialized state on its own, with every other record left out.
+    fn checkpoint_bytes(&self) -> Vec<u8> {
+        let mut data = vec![STATE_VERSION];
+        
+        let mut sequences = Vec::with_capacity(16);
+        sequences.extend_from_slice(&self.last_sent.to_be_bytes());
+        sequences.extend_from_slice(&self.last_received.to_be_bytes());
+        push_record(&mut data, RECORD_SEQUENCES, &sequences);
+        
+        let mut window = (self.replay_window.size as u32).to_be_bytes().to_vec();
+        window.extend_from_slice(&self.encode_replay_window());
+        push_record(&mut data, RECORD_REPLAY_WINDOW, &window);
+        data
+    }
+    
+    /// Adopt a stored checkpoint wherever it is ahead of the current state,
+    /// keeping what either replay window has seen
+    fn merge_checkpoint(&mut self, data: &[u8]) -> Result<()> {
+        let (last_sent, last_received) = (self.last_sent, self.last_received);
+        let window = self.replay_window.clone();
+        let restored = match data.split_first() {
+            Some((&STATE_VERSION, body)) => self.restore_records(body, true).and_then(|sending_nonce| match sending_nonce {
+                Some(nonce) => self.inner.skip_sending_nonces_to(nonce),
+                None => Ok(()),
+            }),
+            Some((&version, _)) => Err(DeserializeError::UnsupportedVersion(version).into()),
+            None => Err(DeserializeError::Truncated { field: "version" }.into()),
+        };
+        
+        if restored.is_err() {
+            self.last_received = last_received;
+            self.replay_window = window;
+        } else if self.last_received < last_received {
+            // Keep the live window, adding what the checkpoint saw
+            let stored = std::mem::replace(&mut self.replay_window, window);
+            let stored_last = std::mem::replace(&mut self.last_received, last_received);
+            self.replay_window.merge(last_received, &stored, stored_last);
+        } else {
+            self.replay_window.merge(self.last_received, &window, last_received);
+        }
+        self.last_sent = if restored.is_ok() { self.last_sent.max(last_sent) } else { last_sent };
+        restored
+    }
+    
+    /// Replay window bits packed into bytes, most significant bit first
+    ///
+    /// Bit i records whether `last_received - i` has been seen.
+    fn encode_replay_window(&self) -> Vec<u8> {
+        let mut window_bytes = vec![0u8; self.replay_window.size.div_ceil(8)];
+        for i in 0..self.replay_window.size as u64 {
+            if i < self.last_received && self.replay_window.contains(self.last_received - i) {
+                window_bytes[i as usize / 8] |= 1 << (7 - i % 8);
+            }
+        }
+        window_bytes
+    }
+    
     /// Deserialize session state
     /// 
     /// Note: The NoiseSession must be provided separately as cryptographic
-    /// state should not be serialized
+    /// state should not be serialized. The rekey generations and peer
+    /// static key recorded in the state are not checked against it; use
+    /// [`deserialize_pinned`](Self::deserialize_pinned) when `session` must
+    /// be the one the state was saved from.
+    ///
+    /// Version 2 data is a sequence of records, each a tag byte, a 32-bit
+    /// length and a value. Records this build does not know are skipped
+    /// unless their tag has the high bit set, marking them as required to
+    /// understand, so later versions can add state without breaking older
+    /// readers. Version 1 data is still accepted; it carries no flags, so
+    /// explicit-nonce mode and compression must be re-enabled by the caller.
     pub fn deserialize(data: &[u8], session: NoiseSession) -> Result<Self> {
-        if data.is_empty() {
-            return Err(NoiseError::InvalidMessage);
+        Self::deserialize_with(data, session, false)
+    }
+    
+    /// Deserialize session state for the session it was saved from
+    ///
+    /// As [`deserialize`](Self::deserialize), but `session` must be at the
+    /// rekey generations recorded in the state, and is pinned to the
+    /// recorded peer static key: a session that already knows a different
+    /// key fails with [`NoiseError::PeerKeyMismatch`], and one still in its
+    /// handshake fails the handshake unless the same peer shows up. Version 1
+    /// data records neither, so it is accepted as is.
+    pub fn deserialize_pinned(data: &[u8], session: NoiseSession) -> Result<Self> {
+        Self::deserialize_with(data, session, true)
+    }
+    
+    fn deserialize_with(data: &[u8], session: NoiseSession, pinned: bool) -> Result<Self> {
+        let Some((&version, body)) = data.split_first() else {
+            return Err(DeserializeError::Truncated { field: "version" }.into());
+        };
+        let mut resilient = Self::new(session);
+        match version {
+            1 => resilient.restore_v1(body)?,
+            STATE_VERSION => {
+                resilient.restore_records(body, pinned)?;
+            }
+            _ => return Err(DeserializeError::UnsupportedVersion(version).into()),
+        }
+        Ok(resilient)
+    }
+    
+    /// Restore the version 1 layout: sequence numbers, window size and bits
+    fn restore_v1(&mut self, data: &[u8]) -> Result<()> {
+        if data.len() < 16 {
+            let field = if data.len() < 8 { "last_sent" } else { "last_received" };
+            return Err(DeserializeError::Truncated { field }.into());
+        }
+        self.restore_sequences(&data[..16])?;
+        self.restore_replay_window(&data[16..])
+    }
+    
+    /// Restore version 2 records, returning the checkpointed sending nonce
+    ///
+    /// The generation and peer key records are only enforced when `pinned`.
+    fn restore_records(&mut self, mut data: &[u8], pinned: bool) -> Result<Option<u64>> {
+        let mut seen = Vec::new();
+        let mut window = None;
+        let mut sending_nonce = None;
+        while !data.is_empty() {
+            if data.len() < 5 {
+                return Err(DeserializeError::Truncated { field: "record" }.into());
+            }
+            let tag = data[0];
+            let len = u32::from_be_bytes([data[1], data[2], data[3], data[4]]) as usize;
+            let Some(value) = data.get(5..).and_then(|rest| rest.get(..len)) else {
+                return Err(DeserializeError::Truncated { field: record_name(tag) }.into());
+            };
+            data = &data[5 + len..];
+            if seen.contains(&tag) {
+                return Err(DeserializeError::DuplicateRecord(tag).into());
+            }
+            seen.push(tag);
+            
+            match tag {
+                RECORD_SEQUENCES => self.restore_sequences(value)?,
+                // Window bits are relative to the last received sequence
+                RECORD_REPLAY_WINDOW => window = Some(value),
+                RECORD_FLAGS => {
+                    let &[flags] = value else {
+                        return Err(NoiseError::InvalidMessage);
+                    };
+                    self.explicit_nonce = flags & FLAG_EXPLICIT_NONCE != 0;
+                    self.closed = flags & FLAG_CLOSED != 0;
+                }
+                RECORD_COMPRESSION => {
+                    if value.len() != 16 {
+                        return Err(NoiseError::InvalidMessage);
+                    }
+                    let read = |at: usize| u64::from_be_bytes(value[at..at + 8].try_into().unwrap_or_default());
+                    self.compression = Some(CompressionPolicy {
+                        min_len: usize::try_from(read(0)).map_err(|_| NoiseError::InvalidMessage)?,
+                        max_decompressed_len: usize::try_from(read(8)).map_err(|_| NoiseError::InvalidMessage)?,
+                    });
+                }
+                RECORD_SENDING_NONCE => {
+                    let nonce = value.try_into().map_err(|_| NoiseError::InvalidMessage)?;
+                    sending_nonce = Some(u64::from_be_bytes(nonce));
+                }
+                RECORD_GENERATIONS if pinned => self.check_generations(value)?,
+                RECORD_REMOTE_STATIC if pinned => self.inner.pin_remote_static(value)?,
+                RECORD_GENERATIONS | RECORD_REMOTE_STATIC => {}
+                _ if tag & RECORD_CRITICAL != 0 => return Err(DeserializeError::UnknownRecord(tag).into()),
+                _ => {}
+            }
         }
         
-        // Check version
-        if data[0] != 1 {
+        if !seen.contains(&RECORD_SEQUENCES) {
+            return Err(DeserializeError::Truncated { field: record_name(RECORD_SEQUENCES) }.into());
+        }
+        match window {
+            Some(window) => self.restore_replay_window(window)?,
+            None => return Err(DeserializeError::Truncated { field: record_name(RECORD_REPLAY_WINDOW) }.into()),
+        }
+        Ok(sending_nonce)
+    }
+    
+    /// The state is only valid for a session at the same rekey generations
+    fn check_generations(&self, data: &[u8]) -> Result<()> {
+        if data.len() != 8 {
             return Err(NoiseError::InvalidMessage);
         }
-        
-        let mut offset = 1;
-        
-        // Read sequence numbers
-        if data.len() < offset + 16 {
+        let read = |at: usize| u32::from_be_bytes(data[at..at + 4].try_into().unwrap_or_default());
+        if (read(0), read(4)) != (self.inner.sending_generation(), self.inner.receiving_generation()) {
+            return Err(NoiseError::InvalidState("Session is at a different rekey generation than the saved state".to_string()));
+        }
+        Ok(())
+    }
+    
+    fn restore_sequences(&mut self, data: &[u8]) -> Result<()> {
+        if data.len() != 16 {
             return Err(NoiseError::InvalidMessage);
         }
-        
-        let last_sent_bytes: [u8; 8] = data[offset..offset+8].try_into()
+        let last_sent_bytes: [u8; 8] = data[..8].try_into()
             .map_err(|_| NoiseError::InvalidMessage)?;
-        let last_sent = u64::from_be_bytes(last_sent_bytes);
-        offset += 8;
-        
-        let last_received_bytes: [u8; 8] = data[offset..offset+8].try_into()
+        self.last_sent = u64::from_be_bytes(last_sent_bytes);
+        let last_received_bytes: [u8; 8] = data[8..].try_into()
             .map_err(|_| NoiseError::InvalidMessage)?;
-        let last_received = u64::from_be_bytes(last_received_bytes);
-        offset += 8;
-        
-        // Read replay window size
-        if data.len() < offset + 4 {
-            return Err(NoiseError::InvalidMessage);
+        self.last_received = u64::from_be_bytes(last_received_bytes);
+        Ok(())
+    }
+    
+    /// Restore the window size and bits; sequence numbers must be restored first
+    fn restore_replay_window(&mut self, data: &[u8]) -> Result<()> {
+        if data.len() < 4 {
+            return Err(DeserializeError::Truncated { field: "window_size" }.into());
         }
         
-        let window_size_bytes: [u8; 4] = data[offset..offset+4].try_into()
+        let window_size_bytes: [u8; 4] = data[..4].try_into()
             .map_err(|_| NoiseError::InvalidMessage)?;
         let window_size = u32::from_be_bytes(window_size_bytes) as usize;
-        offset += 4;
+        
+        // Bound the size before allocating so hostile blobs can't exhaust memory
+        if window_size > MAX_REPLAY_WINDOW_SIZE {
+            return Err(DeserializeError::WindowTooLarge {
+                size: window_size,
+                max: MAX_REPLAY_WINDOW_SIZE,
+            }.into());
+        }
         
         // Read replay window bits
-        let mut replay_window = VecDeque::with_capacity(window_size);
-        let bytes_needed = (window_size + 7) / 8;
+        let mut replay_window = ReplayWindow::new(window_size);
+        let bytes_needed = window_size.div_ceil(8);
         
-        if data.len() < offset + bytes_needed {
-            return Err(NoiseError::InvalidMessage);
+        if data.len() < 4 + bytes_needed {
+            return Err(DeserializeError::Truncated { field: "replay_window" }.into());
         }
         
-        let window_bytes = &data[offset..offset + bytes_needed];
+        let window_bytes = &data[4..4 + bytes_needed];
         
         for i in 0..window_size {
             let byte_index = i / 8;
             let bit_offset = 7 - (i % 8);
             let bit = (window_bytes[byte_index] >> bit_offset) & 1 != 0;
-            replay_window.push_back(bit);
+            if bit && (i as u64) < self.last_received {
+                replay_window.insert(self.last_received - i as u64);
+            }
         }
         
-        Ok(Self {
-            inner: session,
-            last_sent,
-            last_received,
-            replay_window,
-        })
+        self.replay_window = replay_window;
+        Ok(())
     }
     
     /// Get the current send sequence number
@@ -265,9 +932,59 @@ impl ResilientSession {
     }
 }
 
+/// Serializes as the bytes of [`ResilientSession::serialize`]
+#[cfg(feature = "serde")]
+impl serde::Serialize for ResilientSession {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&ResilientSession::serialize(self))
+    }
+}
+
+/// Deserializes a [`ResilientSession`] around the session it was saved from
+///
+/// The serialized state carries no cryptographic state, so as with
+/// [`ResilientSession::deserialize`] the caller supplies the session.
+#[cfg(feature = "serde")]
+pub struct ResilientSessionSeed(pub NoiseSession);
+
+#[cfg(feature = "serde")]
+impl<'de> serde::de::DeserializeSeed<'de> for ResilientSessionSeed {
+    type Value = ResilientSession;
+    
+    fn deserialize<D: serde::Deserializer<'de>>(self, deserializer: D) -> std::result::Result<ResilientSession, D::Error> {
+        deserializer.deserialize_bytes(BytesVisitor {
+            expecting: "resilient session state bytes",
+            parse: |data: &[u8]| ResilientSession::deserialize(data, self.0),
+        })
+    }
+}
+
+/// Append a state record: tag, 32-bit big-endian length, value
+fn push_record(data: &mut Vec<u8>, tag: u8, value: &[u8]) {
+    data.push(tag);
+    data.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    data.extend_from_slice(value);
+}
+
+/// Field name reported when a record is cut short or missing
+fn record_name(tag: u8) -> &'static str {
+    match tag {
+        RECORD_SEQUENCES => "sequences",
+        RECORD_REPLAY_WINDOW => "replay_window",
+        RECORD_FLAGS => "flags",
+        RECORD_COMPRESSION => "compression",
+        RECORD_SENDING_NONCE => "sending_nonce",
+        RECORD_GENERATIONS => "generations",
+        RECORD_REMOTE_STATIC => "remote_static",
+        _ => "record",
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(feature = "storage-backends")]
+    use crate::mobile::storage::MemoryKeyStorage;
     
     fn create_test_session() -> NoiseSession {
         NoiseSession::new_initiator().unwrap()
@@ -398,6 +1115,22 @@ mod tests {
         assert!(!bob.check_and_update_replay_window(101).unwrap());
     }
     
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_roundtrip() {
+        use serde::de::DeserializeSeed;
+        
+        let (_alice, mut bob) = create_connected_pair();
+        assert!(bob.check_and_update_replay_window(3).unwrap());
+        bob.last_sent = 42;
+        
+        let json = serde_json::to_string(&bob).unwrap();
+        let mut deserializer = serde_json::Deserializer::from_str(&json);
+        let mut restored = ResilientSessionSeed(create_test_session()).deserialize(&mut deserializer).unwrap();
+        assert_eq!(restored.send_sequence(), 42);
+        assert!(!restored.check_and_update_replay_window(3).unwrap());
+    }
+    
     #[test]
     fn test_serialization() {
         let (_alice, mut bob) = create_connected_pair();
@@ -458,4 +1191,415 @@ mod tests {
         alice.encrypt_with_sequence(b"test3").unwrap();
         assert_eq!(alice.send_sequence(), 0);
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn test_explicit_nonce_out_of_order() {
+        let (alice, bob) = create_connected_pair();
+        let mut alice = ResilientSession::with_explicit_nonces(alice.inner);
+        let mut bob = ResilientSession::with_explicit_nonces(bob.inner);
+        
+        let msg1 = alice.encrypt_with_sequence(b"First").unwrap();
+        let msg2 = alice.encrypt_with_sequence(b"Second").unwrap();
+        let msg3 = alice.encrypt_with_sequence(b"Third").unwrap();
+        assert_eq!(alice.send_sequence(), 3);
+        
+        // Frames arrive reordered over a lossy link
+        assert_eq!(bob.decrypt_with_replay_check(&msg3).unwrap(), b"Third");
+        assert_eq!(bob.decrypt_with_replay_check(&msg1).unwrap(), b"First");
+        assert_eq!(bob.receive_sequence(), 3);
+        
+        // Replaying an actual frame is caught by the window
+        assert!(matches!(bob.decrypt_with_replay_check(&msg1), Err(NoiseError::ReplayDetected)));
+        
+        assert_eq!(bob.decrypt_with_replay_check(&msg2).unwrap(), b"Second");
+        assert!(matches!(bob.decrypt_with_replay_check(&msg2), Err(NoiseError::ReplayDetected)));
+    }
+    
+    #[test]
+    fn test_gap_callback_reports_missing_sequences() {
+        let (alice, bob) = create_connected_pair();
+        let mut alice = ResilientSession::with_explicit_nonces(alice.inner);
+        let mut bob = ResilientSession::with_explicit_nonces(bob.inner);
+        let gaps = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = gaps.clone();
+        bob.set_gap_callback(move |first, count| recorded.lock().unwrap().push((first, count)));
+        
+        let frames: Vec<_> = (0..6).map(|i| alice.encrypt_with_sequence(&[i]).unwrap()).collect();
+        bob.decrypt_with_replay_check(&frames[0]).unwrap();
+        bob.decrypt_with_replay_check(&frames[3]).unwrap();
+        // A late arrival fills part of the gap without being reported again
+        bob.decrypt_with_replay_check(&frames[1]).unwrap();
+        bob.decrypt_with_replay_check(&frames[4]).unwrap();
+        
+        // Forged frames never reach the callback
+        let mut forged = frames[5].clone();
+        let last = forged.len() - 1;
+        forged[last] ^= 1;
+        assert!(bob.decrypt_with_replay_check(&forged).is_err());
+        assert_eq!(*gaps.lock().unwrap(), vec![(2, 2)]);
+        
+        bob.clear_gap_callback();
+        alice.encrypt_with_sequence(b"lost").unwrap();
+        bob.decrypt_with_replay_check(&alice.encrypt_with_sequence(b"after").unwrap()).unwrap();
+        assert_eq!(gaps.lock().unwrap().len(), 1);
+    }
+    
+    #[test]
+    fn test_control_frames() {
+        let (mut alice, mut bob) = create_connected_pair();
+        
+        let frame = alice.send_data(b"hello").unwrap();
+        let Frame::Data { sequence, payload } = bob.receive_frame(&frame).unwrap() else {
+            panic!("expected data");
+        };
+        assert_eq!(payload, b"hello");
+        let ack = bob.send_ack(sequence).unwrap();
+        assert_eq!(alice.receive_frame(&ack).unwrap(), Frame::Ack(1));
+        
+        let keepalive = alice.send_keepalive().unwrap();
+        assert_eq!(bob.receive_frame(&keepalive).unwrap(), Frame::Keepalive);
+        
+        // The receiver follows the rekey in place
+        let rekey = alice.send_rekey().unwrap();
+        assert_eq!(bob.receive_frame(&rekey).unwrap(), Frame::Rekey);
+        let frame = alice.send_data(b"new key").unwrap();
+        assert!(matches!(bob.receive_frame(&frame).unwrap(), Frame::Data { payload, .. } if payload == b"new key"));
+        
+        let close = alice.send_close(b"bye").unwrap();
+        assert!(alice.is_closed());
+        assert!(alice.send_keepalive().is_err());
+        assert_eq!(bob.receive_frame(&close).unwrap(), Frame::Close(b"bye".to_vec()));
+        assert!(bob.send_data(b"too late").is_err());
+        
+        // Plain messages are not frames
+        let (mut alice, mut bob) = create_connected_pair();
+        let plain = alice.encrypt_with_sequence(&[9]).unwrap();
+        assert!(bob.receive_frame(&plain).is_err());
+    }
+    
+    #[test]
+    fn test_compressed_data_frames() {
+        let (mut alice, mut bob) = create_connected_pair();
+        let json = br#"{"type":"reading","value":21.5,"unit":"C"}"#.repeat(50);
+        assert!(alice.send_compressed(&json).is_err());
+        
+        alice.set_compression(Some(CompressionPolicy::default()));
+        let plain_len = alice.send_data(&json).unwrap().len();
+        let frame = alice.send_compressed(&json).unwrap();
+        assert!(frame.len() < plain_len / 5);
+        
+        // The receiver must opt in too
+        assert!(bob.receive_frame(&frame).is_err());
+        let (mut alice, mut bob) = create_connected_pair();
+        alice.set_compression(Some(CompressionPolicy::default()));
+        bob.set_compression(Some(CompressionPolicy::default()));
+        let frame = alice.send_compressed(&json).unwrap();
+        assert!(matches!(bob.receive_frame(&frame).unwrap(), Frame::Data { payload, .. } if payload == json));
+        
+        // Short payloads go out as they are
+        let short = alice.send_compressed(b"hi").unwrap();
+        assert_eq!(short.len(), 8 + 1 + 2 + 16);
+        assert!(matches!(bob.receive_frame(&short).unwrap(), Frame::Data { payload, .. } if payload == b"hi"));
+        
+        // Decompression bombs are refused
+        bob.set_compression(Some(CompressionPolicy { max_decompressed_len: 1000, ..Default::default() }));
+        assert!(bob.receive_frame(&alice.send_compressed(&json).unwrap()).is_err());
+    }
+    
+    #[test]
+    fn test_control_frames_with_explicit_nonces() {
+        let (alice, bob) = create_connected_pair();
+        let mut alice = ResilientSession::with_explicit_nonces(alice.inner);
+        let mut bob = ResilientSession::with_explicit_nonces(bob.inner);
+        
+        // Frames carry their generation, so the receiver follows the first
+        // frame under the new key rather than the rekey frame itself
+        let rekey = alice.send_rekey().unwrap();
+        assert_eq!(bob.receive_frame(&rekey).unwrap(), Frame::Rekey);
+        assert_eq!(bob.inner().receiving_generation(), 0);
+        let _lost = alice.send_rekey().unwrap();
+        let after = alice.send_data(b"after").unwrap();
+        assert!(matches!(bob.receive_frame(&after).unwrap(), Frame::Data { payload, .. } if payload == b"after"));
+        assert_eq!(bob.inner().receiving_generation(), 2);
+    }
+    
+    #[cfg(feature = "storage-backends")]
+    #[test]
+    fn test_checkpoint_survives_restart() {
+        let (alice, bob) = create_connected_pair();
+        let mut alice = ResilientSession::with_explicit_nonces(alice.inner);
+        let mut bob = ResilientSession::with_explicit_nonces(bob.inner);
+        let storage = Arc::new(MemoryKeyStorage::new());
+        
+        // State from before the crash, e.g. the last snapshot taken
+        let keys = bob.inner().transport_snapshot().unwrap();
+        let stale = bob.serialize();
+        
+        bob.enable_checkpoints(storage.clone(), "bob-replay", Duration::ZERO).unwrap();
+        let msg1 = alice.encrypt_with_sequence(b"First").unwrap();
+        let msg2 = alice.encrypt_with_sequence(b"Second").unwrap();
+        bob.decrypt_with_replay_check(&msg2).unwrap();
+        bob.decrypt_with_replay_check(&msg1).unwrap();
+        assert!(!bob.has_pending_checkpoint());
+        drop(bob);
+        
+        let inner = NoiseSession::from_transport_snapshot(keys).unwrap();
+        let mut bob = ResilientSession::deserialize(&stale, inner).unwrap();
+        assert_eq!(bob.receive_sequence(), 0);
+        bob.enable_checkpoints(storage, "bob-replay", Duration::ZERO).unwrap();
+        assert_eq!(bob.receive_sequence(), 2);
+        assert!(matches!(bob.decrypt_with_replay_check(&msg1), Err(NoiseError::ReplayDetected)));
+        assert!(matches!(bob.decrypt_with_replay_check(&msg2), Err(NoiseError::ReplayDetected)));
+        
+        let msg3 = alice.encrypt_with_sequence(b"Third").unwrap();
+        assert_eq!(bob.decrypt_with_replay_check(&msg3).unwrap(), b"Third");
+    }
+    
+    #[cfg(feature = "storage-backends")]
+    #[test]
+    fn test_checkpoints_are_debounced() {
+        let (mut alice, mut bob) = create_connected_pair();
+        let storage = Arc::new(MemoryKeyStorage::new());
+        assert!(bob.checkpoint().is_err());
+        
+        bob.enable_checkpoints(storage.clone(), "bob-replay", Duration::from_secs(3600)).unwrap();
+        let written = storage.load_session("bob-replay").unwrap();
+        
+        let ct = alice.encrypt_with_sequence(b"Hello").unwrap();
+        bob.decrypt_with_replay_check(&ct).unwrap();
+        assert!(bob.has_pending_checkpoint());
+        assert_eq!(storage.load_session("bob-replay").unwrap(), written);
+        
+        bob.checkpoint().unwrap();
+        assert!(!bob.has_pending_checkpoint());
+        let restored = ResilientSession::deserialize(&storage.load_session("bob-replay").unwrap(), create_test_session()).unwrap();
+        assert_eq!(restored.receive_sequence(), 1);
+        
+        // A checkpoint that is behind never moves the session back
+        for _ in 0..2 {
+            let ct = alice.encrypt_with_sequence(b"more").unwrap();
+            bob.decrypt_with_replay_check(&ct).unwrap();
+        }
+        bob.disable_checkpoints();
+        bob.enable_checkpoints(storage.clone(), "bob-replay", Duration::ZERO).unwrap();
+        assert_eq!(bob.receive_sequence(), 3);
+        
+        storage.store_session("bob-replay", &[9]).unwrap();
+        assert!(bob.enable_checkpoints(storage, "bob-replay", Duration::ZERO).is_err());
+        assert_eq!(bob.receive_sequence(), 3);
+    }
+    
+    #[test]
+    fn test_merge_checkpoint_keeps_both_windows() {
+        let (_, mut stored) = create_connected_pair();
+        assert!(stored.check_and_update_replay_window(3).unwrap());
+        assert!(stored.check_and_update_replay_window(2).unwrap());
+        let data = stored.checkpoint_bytes();
+        
+        // Same newest sequence but a different window size
+        let (_, mut bob) = create_connected_pair();
+        bob.set_replay_window_size(stored.replay_window_size() * 2);
+        assert!(bob.check_and_update_replay_window(3).unwrap());
+        assert!(bob.check_and_update_replay_window(1).unwrap());
+        bob.merge_checkpoint(&data).unwrap();
+        for sequence in 1..=3 {
+            assert!(!bob.check_and_update_replay_window(sequence).unwrap());
+        }
+        
+        // A checkpoint that is behind still contributes what it saw
+        let (_, mut bob) = create_connected_pair();
+        assert!(bob.check_and_update_replay_window(5).unwrap());
+        bob.merge_checkpoint(&data).unwrap();
+        assert_eq!(bob.receive_sequence(), 5);
+        assert!(!bob.check_and_update_replay_window(2).unwrap());
+        assert!(!bob.check_and_update_replay_window(3).unwrap());
+        assert!(bob.check_and_update_replay_window(4).unwrap());
+    }
+    
+    #[cfg(feature = "storage-backends")]
+    #[test]
+    fn test_checkpoint_skips_spent_nonces() {
+        let (alice, bob) = create_connected_pair();
+        let mut alice = ResilientSession::with_explicit_nonces(alice.inner);
+        let mut bob = ResilientSession::with_explicit_nonces(bob.inner);
+        let storage = Arc::new(MemoryKeyStorage::new());
+        
+        // Crash after sending without a checkpoint write since the stale state
+        let stale = alice.inner().transport_snapshot().unwrap();
+        alice.enable_checkpoints(storage.clone(), "alice-replay", Duration::from_secs(3600)).unwrap();
+        let sent = alice.encrypt_with_sequence(b"before crash").unwrap();
+        bob.decrypt_with_replay_check(&sent).unwrap();
+        assert!(alice.has_pending_checkpoint());
+        drop(alice);
+        
+        let mut alice = ResilientSession::with_explicit_nonces(NoiseSession::from_transport_snapshot(stale).unwrap());
+        alice.enable_checkpoints(storage.clone(), "alice-replay", Duration::from_secs(3600)).unwrap();
+        assert_eq!(alice.inner().sending_nonce(), CHECKPOINT_NONCE_RESERVE);
+        let ct = alice.encrypt_with_sequence(b"after crash").unwrap();
+        assert_eq!(bob.decrypt_with_replay_check(&ct).unwrap(), b"after crash");
+        
+        // Running through the reservation writes a new checkpoint first
+        let written = storage.load_session("alice-replay").unwrap();
+        for _ in 1..CHECKPOINT_NONCE_RESERVE {
+            alice.encrypt_with_sequence(b"").unwrap();
+        }
+        assert_eq!(storage.load_session("alice-replay").unwrap(), written);
+        alice.encrypt_with_sequence(b"").unwrap();
+        assert_ne!(storage.load_session("alice-replay").unwrap(), written);
+        
+        // A stateful snow transport cannot skip ahead, so merging fails
+        let params: snow::params::NoiseParams = "Noise_NN_25519_ChaChaPoly_BLAKE2s".parse().unwrap();
+        let mut initiator = snow::Builder::new(params.clone()).build_initiator().unwrap();
+        let mut responder = snow::Builder::new(params.clone()).build_responder().unwrap();
+        let mut buf = [0u8; 128];
+        let len = initiator.write_message(&[], &mut buf).unwrap();
+        responder.read_message(&buf[..len], &mut []).unwrap();
+        let len = responder.write_message(&[], &mut buf).unwrap();
+        initiator.read_message(&buf[..len], &mut []).unwrap();
+        let transport = initiator.into_transport_mode().unwrap();
+        let mut stateful = ResilientSession::new(NoiseSession::from_snow_transport(transport, &params));
+        assert!(stateful.enable_checkpoints(storage, "alice-replay", Duration::ZERO).is_err());
+    }
+    
+    #[test]
+    fn test_large_replay_window() {
+        let session = NoiseSession::new_responder().unwrap();
+        let mut bob = ResilientSession::with_replay_window(session, 1024);
+        assert_eq!(bob.replay_window_size(), 1024);
+        
+        // Receive every other sequence, then fill the gaps far behind the head
+        for i in (2..=2000).step_by(2) {
+            assert!(bob.check_and_update_replay_window(i).unwrap());
+        }
+        assert!(bob.check_and_update_replay_window(977).unwrap());
+        assert!(!bob.check_and_update_replay_window(977).unwrap());
+        assert!(!bob.check_and_update_replay_window(1000).unwrap());
+        
+        // 976 is exactly one window behind the head
+        assert!(!bob.check_and_update_replay_window(976).unwrap());
+        
+        let mut restored = ResilientSession::deserialize(&bob.serialize(), NoiseSession::new_responder().unwrap()).unwrap();
+        assert_eq!(restored.replay_window_size(), 1024);
+        assert!(!restored.check_and_update_replay_window(977).unwrap());
+        assert!(restored.check_and_update_replay_window(1999).unwrap());
+    }
+    
+    #[test]
+    fn test_replay_window_large_jump_clears_old_bits() {
+        let (_alice, mut bob) = create_connected_pair();
+        
+        assert!(bob.check_and_update_replay_window(5).unwrap());
+        // Jump far enough that the ring wraps onto the block holding 5
+        assert!(bob.check_and_update_replay_window(5 + 128).unwrap());
+        assert!(bob.check_and_update_replay_window(5 + 128 - 63).unwrap());
+        assert!(!bob.check_and_update_replay_window(5 + 128 - 64).unwrap());
+        
+        assert!(bob.check_and_update_replay_window(1_000_000).unwrap());
+        assert!(bob.check_and_update_replay_window(999_999).unwrap());
+        assert!(!bob.check_and_update_replay_window(999_999).unwrap());
+    }
+    
+    #[test]
+    fn test_deserialize_errors_are_specific() {
+        let (_alice, mut bob) = create_connected_pair();
+        assert!(bob.check_and_update_replay_window(3).unwrap());
+        let session = || NoiseSession::new_responder().unwrap();
+        
+        let err = |data: &[u8]| match ResilientSession::deserialize(data, session()) {
+            Err(NoiseError::Deserialize(e)) => e,
+            _ => panic!("expected a deserialize error"),
+        };
+        
+        assert_eq!(err(&[]), DeserializeError::Truncated { field: "version" });
+        assert_eq!(err(&[9]), DeserializeError::UnsupportedVersion(9));
+        
+        // Version 1: sequences, window size and bits at fixed offsets
+        let mut data = vec![1u8];
+        data.extend_from_slice(&0u64.to_be_bytes());
+        data.extend_from_slice(&3u64.to_be_bytes());
+        data.extend_from_slice(&64u32.to_be_bytes());
+        data.extend_from_slice(&bob.encode_replay_window());
+        assert_eq!(err(&data[..5]), DeserializeError::Truncated { field: "last_sent" });
+        assert_eq!(err(&data[..12]), DeserializeError::Truncated { field: "last_received" });
+        assert_eq!(err(&data[..18]), DeserializeError::Truncated { field: "window_size" });
+        assert_eq!(err(&data[..data.len() - 1]), DeserializeError::Truncated { field: "replay_window" });
+        let mut migrated = ResilientSession::deserialize(&data, session()).unwrap();
+        assert_eq!(migrated.receive_sequence(), 3);
+        assert!(!migrated.check_and_update_replay_window(3).unwrap());
+        
+        // A hostile window size is rejected before anything is allocated
+        let mut hostile = data[..17].to_vec();
+        hostile.extend_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(
+            err(&hostile),
+            DeserializeError::WindowTooLarge { size: u32::MAX as usize, max: MAX_REPLAY_WINDOW_SIZE }
+        );
+        
+        // Version 2: records are length-checked and required ones must be present
+        let data = bob.serialize();
+        assert_eq!(data[0], STATE_VERSION);
+        assert_eq!(err(&data[..3]), DeserializeError::Truncated { field: "record" });
+        assert_eq!(err(&data[..10]), DeserializeError::Truncated { field: "sequences" });
+        assert_eq!(err(&data[..22]), DeserializeError::Truncated { field: "replay_window" });
+        let mut duplicated = data.clone();
+        duplicated.extend_from_slice(&data[1..22]);
+        assert_eq!(err(&duplicated), DeserializeError::DuplicateRecord(RECORD_SEQUENCES));
+    }
+    
+    #[test]
+    fn test_state_records() {
+        let (_alice, mut bob) = create_connected_pair();
+        bob.set_explicit_nonces(true);
+        bob.set_compression(Some(CompressionPolicy::default()));
+        assert!(bob.check_and_update_replay_window(2).unwrap());
+        bob.last_sent = 9;
+        
+        // Unknown optional records are skipped, unknown required ones refused
+        let mut data = bob.serialize();
+        push_record(&mut data, 0x7f, b"from a newer version");
+        let mut restored = ResilientSession::deserialize(&data, NoiseSession::new_responder().unwrap()).unwrap();
+        assert!(restored.uses_explicit_nonces() && !restored.is_closed());
+        assert_eq!(restored.compression(), Some(CompressionPolicy::default()));
+        assert_eq!((restored.send_sequence(), restored.receive_sequence()), (9, 2));
+        assert!(!restored.check_and_update_replay_window(2).unwrap());
+        
+        push_record(&mut data, RECORD_CRITICAL | 0x7f, &[]);
+        assert!(matches!(
+            ResilientSession::deserialize(&data, NoiseSession::new_responder().unwrap()),
+            Err(NoiseError::Deserialize(DeserializeError::UnknownRecord(0xff)))
+        ));
+    }
+    
+    #[test]
+    fn test_generation_and_peer_records() {
+        let (_alice, mut bob) = create_connected_pair();
+        let data = bob.serialize();
+        
+        // A session already talking to another peer is refused
+        let (_, other_bob) = create_connected_pair();
+        assert!(matches!(
+            ResilientSession::deserialize_pinned(&data, other_bob.inner),
+            Err(NoiseError::PeerKeyMismatch)
+        ));
+        let (_, other_bob) = create_connected_pair();
+        assert!(ResilientSession::deserialize(&data, other_bob.inner).is_ok());
+        
+        // A session still in its handshake is pinned to the recorded peer
+        let mut stranger = NoiseSession::new_initiator().unwrap();
+        let mut restored = ResilientSession::deserialize_pinned(&data, NoiseSession::new_responder().unwrap()).unwrap();
+        let msg1 = stranger.write_message(&[]).unwrap();
+        restored.inner_mut().read_message(&msg1).unwrap();
+        let msg2 = restored.inner_mut().write_message(&[]).unwrap();
+        stranger.read_message(&msg2).unwrap();
+        let msg3 = stranger.write_message(&[]).unwrap();
+        assert!(matches!(restored.inner_mut().read_message(&msg3), Err(NoiseError::PeerKeyMismatch)));
+        
+        // The state only fits a session at the same rekey generations
+        bob.inner_mut().rekey_outgoing().unwrap();
+        assert!(matches!(
+            ResilientSession::deserialize_pinned(&bob.serialize(), NoiseSession::new_responder().unwrap()),
+            Err(NoiseError::InvalidState(_))
+        ));
+        assert!(ResilientSession::deserialize(&bob.serialize(), NoiseSession::new_responder().unwrap()).is_ok());
+    }
+}
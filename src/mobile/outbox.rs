@@ -0,0 +1,276 @@
+//! Store-and-forward outbox for peers without a session
+//!
+//! [`Outbox`] queues plaintext messages per peer while no session is
+//! established and hands them back in order once one is. Queues are kept in
+//! the session slots of a [`KeyStorage`] backend under [`OUTBOX_ID_PREFIX`],
+//! encrypted under the snapshot key-encryption key, so they survive the app
+//! being killed.
+//!
+//! Messages leave the queue only once the transport has taken them, so a
+//! failed send never drops any. Delivery is at least once: a message handed
+//! to the transport just before a crash may be handed over again after the
+//! restart.
+
+use crate::core::crypto::{aead_open, aead_seal};
+use crate::core::error::{NoiseError, Result};
+use crate::core::session::NoiseSession;
+use crate::mobile::snapshot::{load_or_create_kek, Reader};
+use crate::mobile::storage::KeyStorage;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use zeroize::Zeroizing;
+
+/// Prefix of the session slot ids holding queued messages
+pub const OUTBOX_ID_PREFIX: &str = "noise.outbox.";
+
+/// Default cap on messages queued per peer
+pub const DEFAULT_MAX_QUEUED_MESSAGES: usize = 1000;
+
+/// Outbox record format version
+const OUTBOX_VERSION: u8 = 1;
+
+/// Domain separator for the associated data of stored queues
+const OUTBOX_LABEL: &[u8] = b"noise-mobile/outbox/v1";
+
+/// Per-peer queues of messages waiting for a session
+pub struct Outbox {
+    storage: Arc<dyn KeyStorage>,
+    max_queued: usize,
+    /// Serializes read-modify-write cycles on queues
+    lock: Mutex<()>,
+}
+
+impl Outbox {
+    /// Create an outbox over `storage`
+    ///
+    /// Messages queued by an earlier outbox on the same storage are picked
+    /// up again.
+    pub fn new(storage: Arc<dyn KeyStorage>) -> Self {
+        Self {
+            storage,
+            max_queued: DEFAULT_MAX_QUEUED_MESSAGES,
+            lock: Mutex::new(()),
+        }
+    }
+    
+    /// Set how many messages a peer's queue may hold
+    pub fn set_max_queued_messages(&mut self, max: usize) {
+        self.max_queued = max;
+    }
+    
+    /// Queue a message for a peer
+    ///
+    /// Fails with [`NoiseError::InvalidState`] when the peer's queue is full.
+    pub fn enqueue(&self, peer_id: &str, plaintext: &[u8]) -> Result<()> {
+        let _guard = self.lock()?;
+        let mut queue = self.load(peer_id)?;
+        if queue.len() >= self.max_queued {
+            return Err(NoiseError::InvalidState("Outbox full".to_string()));
+        }
+        queue.push_back(Zeroizing::new(plaintext.to_vec()));
+        self.store(peer_id, &queue)
+    }
+    
+    /// Number of messages queued for a peer
+    pub fn pending(&self, peer_id: &str) -> Result<usize> {
+        let _guard = self.lock()?;
+        Ok(self.load(peer_id)?.len())
+    }
+    
+    /// Hand a peer's queued messages to `send` in order
+    ///
+    /// Each message is removed once `send` accepts it. The first error stops
+    /// the flush and is returned, leaving that message and the rest queued.
+    /// `send` must not call back into the outbox. Returns the number of
+    /// messages sent.
+    pub fn flush_with(&self, peer_id: &str, mut send: impl FnMut(&[u8]) -> Result<()>) -> Result<usize> {
+        let _guard = self.lock()?;
+        let mut queue = self.load(peer_id)?;
+        let mut sent = 0;
+        let mut result = Ok(());
+        while let Some(message) = queue.front() {
+            result = send(message);
+            if result.is_err() {
+                break;
+            }
+            queue.pop_front();
+            sent += 1;
+        }
+        if sent > 0 {
+            self.store(peer_id, &queue)?;
+        }
+        result.map(|()| sent)
+    }
+    
+    /// Encrypt a peer's queued messages on an established session
+    ///
+    /// Returns the ciphertexts to send, oldest first, as many as the
+    /// session's nonce budget allows. The messages stay queued until
+    /// [`acknowledge`](Self::acknowledge) reports how many the transport
+    /// took, so a failed send loses nothing.
+    pub fn flush(&self, peer_id: &str, session: &mut NoiseSession) -> Result<Vec<Vec<u8>>> {
+        if !session.is_transport_state() {
+            return Err(NoiseError::InvalidState("Handshake not complete".to_string()));
+        }
+        let _guard = self.lock()?;
+        let budget = usize::try_from(session.remaining_nonce_budget()).unwrap_or(usize::MAX);
+        self.load(peer_id)?
+            .iter()
+            .take(budget)
+            .map(|message| session.encrypt(message))
+            .collect()
+    }
+    
+    /// Remove the oldest `count` messages queued for a peer
+    ///
+    /// Call it once the transport has taken that many ciphertexts from
+    /// [`flush`](Self::flush).
+    pub fn acknowledge(&self, peer_id: &str, count: usize) -> Result<()> {
+        let _guard = self.lock()?;
+        let mut queue = self.load(peer_id)?;
+        if count == 0 || queue.is_empty() {
+            return Ok(());
+        }
+        queue.drain(..count.min(queue.len()));
+        self.store(peer_id, &queue)
+    }
+    
+    /// Drop everything queued for a peer
+    pub fn clear(&self, peer_id: &str) -> Result<()> {
+        let _guard = self.lock()?;
+        self.storage.delete_session(&slot_id(peer_id))
+    }
+    
+    fn load(&self, peer_id: &str) -> Result<VecDeque<Zeroizing<Vec<u8>>>> {
+        let wrapped = match self.storage.load_session(&slot_id(peer_id)) {
+            Ok(data) => data,
+            // Backends report a missing slot as an invalid parameter
+            Err(NoiseError::InvalidParameter) => return Ok(VecDeque::new()),
+            Err(e) => return Err(e),
+        };
+        let kek = load_or_create_kek(self.storage.as_ref())?;
+        let plaintext = aead_open(kek.as_bytes(), &storage_aad(peer_id), &wrapped);
+        drop(kek);
+        
+        let plaintext = Zeroizing::new(plaintext?);
+        let mut reader = Reader::new(&plaintext);
+        if reader.take(1)?[0] != OUTBOX_VERSION {
+            return Err(NoiseError::InvalidMessage);
+        }
+        let mut queue = VecDeque::new();
+        for _ in 0..reader.u32()? {
+            let len = reader.u32()? as usize;
+            queue.push_back(Zeroizing::new(reader.take(len)?.to_vec()));
+        }
+        if !reader.is_empty() {
+            return Err(NoiseError::InvalidMessage);
+        }
+        Ok(queue)
+    }
+    
+    fn store(&self, peer_id: &str, queue: &VecDeque<Zeroizing<Vec<u8>>>) -> Result<()> {
+        if queue.is_empty() {
+            return self.storage.delete_session(&slot_id(peer_id));
+        }
+        let mut plaintext = Zeroizing::new(vec![OUTBOX_VERSION]);
+        plaintext.extend_from_slice(&(queue.len() as u32).to_be_bytes());
+        for message in queue {
+            plaintext.extend_from_slice(&(message.len() as u32).to_be_bytes());
+            plaintext.extend_from_slice(message);
+        }
+        
+        let kek = load_or_create_kek(self.storage.as_ref())?;
+        let wrapped = aead_seal(kek.as_bytes(), &storage_aad(peer_id), &plaintext);
+        drop(kek);
+        self.storage.store_session(&slot_id(peer_id), &wrapped?)
+    }
+    
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, ()>> {
+        self.lock.lock().map_err(|_| NoiseError::InvalidState("Lock poisoned".to_string()))
+    }
+}
+
+fn slot_id(peer_id: &str) -> String {
+    format!("{}{}", OUTBOX_ID_PREFIX, peer_id)
+}
+
+fn storage_aad(peer_id: &str) -> Vec<u8> {
+    let mut aad = OUTBOX_LABEL.to_vec();
+    aad.extend_from_slice(peer_id.as_bytes());
+    aad
+}
+
+#[cfg(all(test, feature = "storage-backends"))]
+mod tests {
+    use super::*;
+    use crate::test_utils::perform_handshake;
+    use crate::mobile::storage::MemoryKeyStorage;
+    
+    #[test]
+    fn test_queue_survives_restart_and_flushes_in_order() {
+        let storage = Arc::new(MemoryKeyStorage::new());
+        let outbox = Outbox::new(storage.clone());
+        outbox.enqueue("bob", b"first").unwrap();
+        outbox.enqueue("bob", b"second").unwrap();
+        outbox.enqueue("carol", b"other peer").unwrap();
+        
+        // Queued plaintext is not stored in the clear
+        let stored = storage.load_session(&slot_id("bob")).unwrap();
+        assert!(!stored.windows(5).any(|w| w == b"first"));
+        
+        let outbox = Outbox::new(storage.clone());
+        assert_eq!(outbox.pending("bob").unwrap(), 2);
+        let mut alice_session = NoiseSession::new_initiator().unwrap();
+        assert!(outbox.flush("bob", &mut alice_session).is_err());
+        
+        let (mut alice_session, mut bob_session) = perform_handshake().unwrap();
+        let ciphertexts = outbox.flush("bob", &mut alice_session).unwrap();
+        let received: Vec<_> = ciphertexts.iter().map(|c| bob_session.decrypt(c).unwrap()).collect();
+        assert_eq!(received, [b"first".to_vec(), b"second".to_vec()]);
+        assert_eq!(outbox.pending("bob").unwrap(), 2);
+        outbox.acknowledge("bob", ciphertexts.len()).unwrap();
+        assert_eq!(outbox.pending("bob").unwrap(), 0);
+        assert_eq!(outbox.pending("carol").unwrap(), 1);
+        assert!(storage.load_session(&slot_id("bob")).is_err());
+    }
+    
+    #[test]
+    fn test_failed_send_keeps_rest_queued() {
+        let mut outbox = Outbox::new(Arc::new(MemoryKeyStorage::new()));
+        outbox.set_max_queued_messages(3);
+        for message in [&b"a"[..], b"b", b"c"] {
+            outbox.enqueue("bob", message).unwrap();
+        }
+        assert!(outbox.enqueue("bob", b"d").is_err());
+        
+        let mut delivered = Vec::new();
+        let result = outbox.flush_with("bob", |message| {
+            if message == b"b" {
+                return Err(NoiseError::InvalidState("link down".to_string()));
+            }
+            delivered.push(message.to_vec());
+            Ok(())
+        });
+        assert!(result.is_err());
+        assert_eq!(delivered, [b"a".to_vec()]);
+        assert_eq!(outbox.pending("bob").unwrap(), 2);
+        
+        // Ciphertexts the transport never took are encrypted again next time
+        let (mut alice, _) = perform_handshake().unwrap();
+        assert_eq!(outbox.flush("bob", &mut alice).unwrap().len(), 2);
+        let (mut alice, mut bob) = perform_handshake().unwrap();
+        let resent = outbox.flush("bob", &mut alice).unwrap();
+        let received: Vec<_> = resent.iter().map(|c| bob.decrypt(c).unwrap()).collect();
+        assert_eq!(received, [b"b".to_vec(), b"c".to_vec()]);
+        
+        // A session short on nonces sends what it can
+        let (mut alice, _) = perform_handshake().unwrap();
+        alice.set_nonce_limit(1);
+        assert_eq!(outbox.flush("bob", &mut alice).unwrap().len(), 1);
+        outbox.acknowledge("bob", 1).unwrap();
+        assert_eq!(outbox.pending("bob").unwrap(), 1);
+        
+        outbox.clear("bob").unwrap();
+        assert_eq!(outbox.pending("bob").unwrap(), 0);
+    }
+}
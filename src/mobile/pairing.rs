@@ -0,0 +1,222 @@
+//! Pairing payloads for QR codes
+//!
+//! A compact binary encoding of what a peer needs to start a session: the
+//! static public key, an optional pre-shared key and optional endpoint
+//! hints. Apps on every platform read and write the same bytes, so a code
+//! shown on iOS scans on Android and vice versa.
+//!
+//! Layout (big-endian):
+//!
+//! ```text
+//! "NM" | version: u8 | flags: u8 | static key: 32
+//!      | [psk: 32 if flags & 1] | count: u8 | count * (len: u8 | utf-8)
+//! ```
+
+use crate::core::crypto::constant_time_eq;
+use crate::core::error::{NoiseError, Result};
+use crate::core::keys::KEY_LEN;
+#[cfg(feature = "serde")]
+use crate::mobile::snapshot::BytesVisitor;
+use crate::mobile::snapshot::Reader;
+#[cfg(feature = "serde")]
+use zeroize::Zeroizing;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// Leading bytes of every pairing payload
+const PAIRING_MAGIC: &[u8; 2] = b"NM";
+
+/// Pairing payload format version
+const PAIRING_VERSION: u8 = 1;
+
+/// Flag bit set when a PSK follows the static key
+const FLAG_PSK: u8 = 0x01;
+
+/// Length of a Noise pre-shared key
+pub const PSK_LEN: usize = 32;
+
+/// Most endpoint hints a payload can carry
+pub const MAX_ENDPOINT_HINTS: usize = u8::MAX as usize;
+
+/// Longest endpoint hint, in bytes
+pub const MAX_ENDPOINT_HINT_LEN: usize = u8::MAX as usize;
+
+/// The contents of a pairing QR code
+///
+/// The PSK is wiped when the payload is dropped. With the `serde` feature
+/// a payload serializes as the bytes of [`encode`](Self::encode), PSK
+/// included, and deserializing checks them like [`decode`](Self::decode).
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct PairingPayload {
+    /// Static public key of the peer showing the code
+    pub static_key: [u8; KEY_LEN],
+    /// Pre-shared key for PSK handshake patterns
+    pub psk: Option<[u8; PSK_LEN]>,
+    /// Where to reach the peer, e.g. `"ble:AA:BB:CC:DD:EE:FF"`
+    ///
+    /// The library does not interpret hints; apps agree on their meaning.
+    /// Hints may not contain NUL, which separates them over the C API.
+    pub endpoints: Vec<String>,
+}
+
+impl PairingPayload {
+    /// A payload carrying only a static key
+    pub fn new(static_key: [u8; KEY_LEN]) -> Self {
+        Self {
+            static_key,
+            psk: None,
+            endpoints: Vec::new(),
+        }
+    }
+    
+    /// Encode for a QR code (binary mode)
+    ///
+    /// Fails if there are too many endpoint hints or one is invalid.
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        if self.endpoints.len() > MAX_ENDPOINT_HINTS
+            || self.endpoints.iter().any(|hint| hint.len() > MAX_ENDPOINT_HINT_LEN || hint.contains('\0'))
+        {
+            return Err(NoiseError::InvalidParameter);
+        }
+        
+        let flags = if self.psk.is_some() { FLAG_PSK } else { 0 };
+        let mut data = PAIRING_MAGIC.to_vec();
+        data.extend_from_slice(&[PAIRING_VERSION, flags]);
+        data.extend_from_slice(&self.static_key);
+        if let Some(psk) = &self.psk {
+            data.extend_from_slice(psk);
+        }
+        data.push(self.endpoints.len() as u8);
+        for hint in &self.endpoints {
+            data.push(hint.len() as u8);
+            data.extend_from_slice(hint.as_bytes());
+        }
+        Ok(data)
+    }
+    
+    /// Decode a scanned payload
+    ///
+    /// Rejects other versions, unknown flags and trailing bytes.
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(data);
+        if reader.take(2)? != PAIRING_MAGIC || reader.take(1)?[0] != PAIRING_VERSION {
+            return Err(NoiseError::InvalidMessage);
+        }
+        let flags = reader.take(1)?[0];
+        if flags & !FLAG_PSK != 0 {
+            return Err(NoiseError::InvalidMessage);
+        }
+        
+        let mut payload = Self::new(reader.take(KEY_LEN)?.try_into().map_err(|_| NoiseError::InvalidMessage)?);
+        if flags & FLAG_PSK != 0 {
+            payload.psk = Some(reader.take(PSK_LEN)?.try_into().map_err(|_| NoiseError::InvalidMessage)?);
+        }
+        let count = reader.take(1)?[0];
+        for _ in 0..count {
+            let len = reader.take(1)?[0] as usize;
+            let hint = std::str::from_utf8(reader.take(len)?).map_err(|_| NoiseError::InvalidMessage)?;
+            if hint.contains('\0') {
+                return Err(NoiseError::InvalidMessage);
+            }
+            payload.endpoints.push(hint.to_string());
+        }
+        if !reader.is_empty() {
+            return Err(NoiseError::InvalidMessage);
+        }
+        Ok(payload)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for PairingPayload {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let data = Zeroizing::new(self.encode().map_err(serde::ser::Error::custom)?);
+        serializer.serialize_bytes(&data)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for PairingPayload {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        deserializer.deserialize_bytes(BytesVisitor {
+            expecting: "pairing payload bytes",
+            parse: PairingPayload::decode,
+        })
+    }
+}
+
+/// Compares the key and PSK in constant time
+impl PartialEq for PairingPayload {
+    fn eq(&self, other: &Self) -> bool {
+        let psk_equal = match (&self.psk, &other.psk) {
+            (Some(a), Some(b)) => constant_time_eq(a, b),
+            (a, b) => a.is_none() && b.is_none(),
+        };
+        constant_time_eq(&self.static_key, &other.static_key) & psk_equal && self.endpoints == other.endpoints
+    }
+}
+
+impl Eq for PairingPayload {}
+
+impl std::fmt::Debug for PairingPayload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PairingPayload")
+            .field("static_key", &self.static_key)
+            .field("has_psk", &self.psk.is_some())
+            .field("endpoints", &self.endpoints)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_roundtrip() {
+        let minimal = PairingPayload::new([7u8; KEY_LEN]);
+        let encoded = minimal.encode().unwrap();
+        assert_eq!(encoded.len(), 2 + 2 + KEY_LEN + 1);
+        assert_eq!(PairingPayload::decode(&encoded).unwrap(), minimal);
+        
+        let mut full = PairingPayload::new([7u8; KEY_LEN]);
+        full.psk = Some([9u8; PSK_LEN]);
+        full.endpoints = vec!["ble:AA:BB:CC:DD:EE:FF".to_string(), "tcp:192.0.2.1:7000".to_string()];
+        let decoded = PairingPayload::decode(&full.encode().unwrap()).unwrap();
+        assert_eq!(decoded, full);
+        assert!(!format!("{:?}", decoded).contains("9, 9"));
+    }
+    
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_roundtrip() {
+        let mut payload = PairingPayload::new([7u8; KEY_LEN]);
+        payload.endpoints = vec!["ble:AA:BB:CC:DD:EE:FF".to_string()];
+        let json = serde_json::to_string(&payload).unwrap();
+        assert_eq!(serde_json::from_str::<PairingPayload>(&json).unwrap(), payload);
+        
+        // Deserializing validates like decode
+        let mut encoded = payload.encode().unwrap();
+        encoded[3] = 0x80;
+        assert!(serde_json::from_str::<PairingPayload>(&serde_json::to_string(&encoded).unwrap()).is_err());
+    }
+    
+    #[test]
+    fn test_rejects_malformed() {
+        let mut payload = PairingPayload::new([7u8; KEY_LEN]);
+        payload.endpoints = vec!["x".repeat(MAX_ENDPOINT_HINT_LEN + 1)];
+        assert!(payload.encode().is_err());
+        payload.endpoints = vec!["ble\0tcp".to_string()];
+        assert!(payload.encode().is_err());
+        
+        let encoded = PairingPayload::new([7u8; KEY_LEN]).encode().unwrap();
+        assert!(PairingPayload::decode(&encoded[..encoded.len() - 1]).is_err());
+        assert!(PairingPayload::decode(&[&encoded[..], &[0]].concat()).is_err());
+        
+        let mut bad_version = encoded.clone();
+        bad_version[2] = 2;
+        assert!(PairingPayload::decode(&bad_version).is_err());
+        let mut bad_flags = encoded;
+        bad_flags[3] = 0x80;
+        assert!(PairingPayload::decode(&bad_flags).is_err());
+    }
+}
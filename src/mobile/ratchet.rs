@@ -0,0 +1,439 @@
+//! Double ratchet over a completed Noise session
+//!
+//! A [`RatchetSession`] gives long-lived chats per-message forward secrecy
+//! and post-compromise security. Every message advances a symmetric chain,
+//! and every reply carries a fresh Curve25519 ratchet key that is mixed into
+//! a root key, so a leaked state stops exposing traffic once the peer has
+//! replied.
+//!
+//! Both sides start from [`NoiseSession::export_key`] output, so no extra
+//! setup messages are needed. The initiator ratchets as soon as it sends;
+//! the responder may send first on a symmetric chain from that output, and
+//! ratchets when the initiator's first message arrives.
+//!
+//! Ratchet messages are self-contained and can travel over the Noise
+//! session or any other channel. State changes with every message; persist
+//! it with [`RatchetSession::save`] after each use.
+
+use crate::core::crypto::{aead_open, aead_seal, chain_step, hkdf};
+use crate::core::error::{NoiseError, Result};
+use crate::core::keys::{Keypair, KEY_LEN};
+use crate::core::session::NoiseSession;
+use crate::mobile::snapshot::{load_or_create_kek, Reader};
+use crate::mobile::storage::KeyStorage;
+use snow::params::HashChoice;
+use std::collections::VecDeque;
+use zeroize::{Zeroize, Zeroizing};
+
+/// Format version of ratchet messages and saved state
+const RATCHET_VERSION: u8 = 1;
+
+/// Export label for the initial ratchet secrets
+const RATCHET_EXPORT_LABEL: &str = "noise-mobile/ratchet";
+
+/// Domain separator for root key derivation and message associated data
+const RATCHET_LABEL: &[u8] = b"noise-mobile/ratchet/v1";
+
+/// Header bytes before the ciphertext: version, ratchet key, previous and
+/// current chain lengths
+pub const RATCHET_HEADER_LEN: usize = 1 + KEY_LEN + 4 + 4;
+
+/// Most message keys kept for messages that have not arrived yet
+pub const MAX_SKIPPED_MESSAGE_KEYS: usize = 1000;
+
+/// Ratchet state; cloned so a failed decryption leaves the original intact
+#[derive(Clone)]
+struct RatchetState {
+    root_key: [u8; KEY_LEN],
+    dh_self: Keypair,
+    dh_remote: Option<[u8; KEY_LEN]>,
+    send_chain: Option<[u8; KEY_LEN]>,
+    recv_chain: Option<[u8; KEY_LEN]>,
+    send_n: u32,
+    recv_n: u32,
+    prev_n: u32,
+    /// `(ratchet key, message number, message key)`, oldest first
+    skipped: VecDeque<([u8; KEY_LEN], u32, [u8; KEY_LEN])>,
+}
+
+impl Drop for RatchetState {
+    fn drop(&mut self) {
+        self.root_key.zeroize();
+        self.send_chain.zeroize();
+        self.recv_chain.zeroize();
+        self.skipped.iter_mut().for_each(|(_, _, key)| key.zeroize());
+    }
+}
+
+/// Double ratchet for one peer
+pub struct RatchetSession {
+    state: RatchetState,
+}
+
+impl RatchetSession {
+    /// Start a ratchet from a session that has completed its handshake
+    ///
+    /// Both peers must call this on their side of the same session.
+    pub fn new(session: &NoiseSession) -> Result<Self> {
+        let is_initiator = session.is_initiator_transport()?;
+        let secret = Zeroizing::new(session.export_key(RATCHET_EXPORT_LABEL, &[], 3 * KEY_LEN)?);
+        let root_key: [u8; KEY_LEN] = secret[..KEY_LEN].try_into().map_err(|_| NoiseError::InvalidState("Bad export".to_string()))?;
+        let responder_initial = Keypair::from_private(&secret[KEY_LEN..2 * KEY_LEN])?;
+        let first_chain: [u8; KEY_LEN] = secret[2 * KEY_LEN..].try_into().map_err(|_| NoiseError::InvalidState("Bad export".to_string()))?;
+        
+        let state = if is_initiator {
+            let dh_self = Keypair::generate()?;
+            let remote = *responder_initial.public_key();
+            let (root_key, send_chain) = kdf_root(&root_key, &dh_self.dh(&remote)?)?;
+            RatchetState {
+                root_key,
+                dh_self,
+                dh_remote: Some(remote),
+                send_chain: Some(send_chain),
+                recv_chain: Some(first_chain),
+                send_n: 0,
+                recv_n: 0,
+                prev_n: 0,
+                skipped: VecDeque::new(),
+            }
+        } else {
+            RatchetState {
+                root_key,
+                dh_self: responder_initial,
+                dh_remote: None,
+                send_chain: Some(first_chain),
+                recv_chain: None,
+                send_n: 0,
+                recv_n: 0,
+                prev_n: 0,
+                skipped: VecDeque::new(),
+            }
+        };
+        Ok(Self { state })
+    }
+    
+    /// Encrypt a message, advancing the sending chain
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let state = &mut self.state;
+        let chain = state.send_chain.as_ref().ok_or_else(|| NoiseError::InvalidState("No sending chain".to_string()))?;
+        let send_n = state.send_n.checked_add(1).ok_or_else(|| NoiseError::InvalidState("Sending chain exhausted".to_string()))?;
+        let (next, mut message_key) = chain_step(chain)?;
+        
+        let mut message = Vec::with_capacity(RATCHET_HEADER_LEN + plaintext.len() + 40);
+        message.push(RATCHET_VERSION);
+        message.extend_from_slice(state.dh_self.public_key());
+        message.extend_from_slice(&state.prev_n.to_be_bytes());
+        message.extend_from_slice(&state.send_n.to_be_bytes());
+        let ciphertext = aead_seal(&message_key, &associated_data(&message), plaintext);
+        message_key.zeroize();
+        message.extend_from_slice(&ciphertext?);
+        
+        state.send_chain = Some(next);
+        state.send_n = send_n;
+        Ok(message)
+    }
+    
+    /// Decrypt a message from the peer
+    ///
+    /// Messages may arrive out of order, up to [`MAX_SKIPPED_MESSAGE_KEYS`]
+    /// ahead. Each is accepted once; repeats fail with
+    /// [`NoiseError::ReplayDetected`]. State only changes if the message
+    /// authenticates.
+    pub fn decrypt(&mut self, message: &[u8]) -> Result<Vec<u8>> {
+        if message.len() < RATCHET_HEADER_LEN || message[0] != RATCHET_VERSION {
+            return Err(NoiseError::InvalidMessage);
+        }
+        let (header, ciphertext) = message.split_at(RATCHET_HEADER_LEN);
+        let dh: [u8; KEY_LEN] = header[1..1 + KEY_LEN].try_into().map_err(|_| NoiseError::InvalidMessage)?;
+        let prev_n = u32::from_be_bytes(header[1 + KEY_LEN..5 + KEY_LEN].try_into().map_err(|_| NoiseError::InvalidMessage)?);
+        let n = u32::from_be_bytes(header[5 + KEY_LEN..].try_into().map_err(|_| NoiseError::InvalidMessage)?);
+        let ad = associated_data(header);
+        
+        if let Some(index) = self.state.skipped.iter().position(|(key, number, _)| *key == dh && *number == n) {
+            let plaintext = aead_open(&self.state.skipped[index].2, &ad, ciphertext)?;
+            if let Some((_, _, mut key)) = self.state.skipped.remove(index) {
+                key.zeroize();
+            }
+            return Ok(plaintext);
+        }
+        
+        let mut next = self.state.clone();
+        if next.dh_remote == Some(dh) {
+            if n < next.recv_n {
+                return Err(NoiseError::ReplayDetected);
+            }
+        } else {
+            skip_message_keys(&mut next, prev_n)?;
+            dh_ratchet(&mut next, dh)?;
+        }
+        skip_message_keys(&mut next, n)?;
+        
+        let chain = next.recv_chain.as_ref().ok_or(NoiseError::DecryptionFailed)?;
+        let (chain, mut message_key) = chain_step(chain)?;
+        next.recv_chain = Some(chain);
+        next.recv_n = next.recv_n.checked_add(1).ok_or(NoiseError::InvalidMessage)?;
+        let plaintext = aead_open(&message_key, &ad, ciphertext);
+        message_key.zeroize();
+        
+        let plaintext = plaintext?;
+        self.state = next;
+        Ok(plaintext)
+    }
+    
+    /// This side's current ratchet public key
+    ///
+    /// Changes each time a reply from the peer is received.
+    pub fn ratchet_key(&self) -> &[u8; KEY_LEN] {
+        self.state.dh_self.public_key()
+    }
+    
+    /// Encode the ratchet state, including every key, into bytes
+    ///
+    /// The output contains live key material; prefer
+    /// [`RatchetSession::save`], which wraps it before it reaches storage.
+    pub fn serialize(&self) -> Vec<u8> {
+        let s = &self.state;
+        let mut data = vec![RATCHET_VERSION];
+        data.extend_from_slice(&s.root_key);
+        data.extend_from_slice(s.dh_self.private_key());
+        for key in [&s.dh_remote, &s.send_chain, &s.recv_chain] {
+            match key {
+                Some(key) => {
+                    data.push(1);
+                    data.extend_from_slice(key);
+                }
+                None => data.push(0),
+            }
+        }
+        data.extend_from_slice(&s.send_n.to_be_bytes());
+        data.extend_from_slice(&s.recv_n.to_be_bytes());
+        data.extend_from_slice(&s.prev_n.to_be_bytes());
+        data.extend_from_slice(&(s.skipped.len() as u16).to_be_bytes());
+        for (dh, n, key) in &s.skipped {
+            data.extend_from_slice(dh);
+            data.extend_from_slice(&n.to_be_bytes());
+            data.extend_from_slice(key);
+        }
+        data
+    }
+    
+    /// Rebuild a ratchet from [`RatchetSession::serialize`] output
+    ///
+    /// Restoring the same state twice and sending from both copies reuses
+    /// message keys. Treat serialized state as single-use.
+    pub fn restore(data: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(data);
+        if reader.take(1)?[0] != RATCHET_VERSION {
+            return Err(NoiseError::InvalidMessage);
+        }
+        let root_key = read_key(&mut reader)?;
+        let dh_self = Keypair::from_private(reader.take(KEY_LEN)?)?;
+        let dh_remote = read_optional_key(&mut reader)?;
+        let send_chain = read_optional_key(&mut reader)?;
+        let recv_chain = read_optional_key(&mut reader)?;
+        let mut state = RatchetState {
+            root_key,
+            dh_self,
+            dh_remote,
+            send_chain,
+            recv_chain,
+            send_n: reader.u32()?,
+            recv_n: reader.u32()?,
+            prev_n: reader.u32()?,
+            skipped: VecDeque::new(),
+        };
+        for _ in 0..reader.u16()? {
+            let dh = read_key(&mut reader)?;
+            let n = reader.u32()?;
+            state.skipped.push_back((dh, n, read_key(&mut reader)?));
+        }
+        
+        if !reader.is_empty() {
+            return Err(NoiseError::InvalidMessage);
+        }
+        Ok(Self { state })
+    }
+    
+    /// Store the state in `storage` under `session_id`, wrapped under the
+    /// same key-encryption key as session snapshots
+    ///
+    /// Save after every encrypt and decrypt: an older saved state would
+    /// reuse message keys if the ratchet is loaded from it.
+    pub fn save(&self, storage: &dyn KeyStorage, session_id: &str) -> Result<()> {
+        let mut kek = load_or_create_kek(storage)?;
+        let mut plaintext = self.serialize();
+        let wrapped = aead_seal(kek.as_bytes(), &storage_aad(session_id), &plaintext);
+        plaintext.zeroize();
+        kek.zeroize();
+        
+        storage.store_session(session_id, &wrapped?)
+    }
+    
+    /// Load a ratchet stored by [`RatchetSession::save`]
+    pub fn load(storage: &dyn KeyStorage, session_id: &str) -> Result<Self> {
+        let wrapped = storage.load_session(session_id)?;
+        let mut kek = load_or_create_kek(storage)?;
+        let plaintext = aead_open(kek.as_bytes(), &storage_aad(session_id), &wrapped);
+        kek.zeroize();
+        
+        let plaintext = Zeroizing::new(plaintext?);
+        Self::restore(&plaintext)
+    }
+}
+
+/// `KDF_RK`: mix a DH output into the root key, returning `(root key, chain key)`
+fn kdf_root(root_key: &[u8; KEY_LEN], dh_output: &[u8; KEY_LEN]) -> Result<([u8; KEY_LEN], [u8; KEY_LEN])> {
+    let output = Zeroizing::new(hkdf(HashChoice::SHA256, root_key, dh_output, RATCHET_LABEL, 2 * KEY_LEN)?);
+    let mut root = [0u8; KEY_LEN];
+    let mut chain = [0u8; KEY_LEN];
+    root.copy_from_slice(&output[..KEY_LEN]);
+    chain.copy_from_slice(&output[KEY_LEN..]);
+    Ok((root, chain))
+}
+
+/// Take a new ratchet key from the peer and start fresh chains
+fn dh_ratchet(state: &mut RatchetState, remote: [u8; KEY_LEN]) -> Result<()> {
+    state.prev_n = state.send_n;
+    state.send_n = 0;
+    state.recv_n = 0;
+    state.dh_remote = Some(remote);
+    
+    let (root_key, recv_chain) = kdf_root(&state.root_key, &state.dh_self.dh(&remote)?)?;
+    state.dh_self = Keypair::generate()?;
+    let (root_key, send_chain) = kdf_root(&root_key, &state.dh_self.dh(&remote)?)?;
+    state.root_key = root_key;
+    state.recv_chain = Some(recv_chain);
+    state.send_chain = Some(send_chain);
+    Ok(())
+}
+
+/// Keep keys for receiving-chain messages before `until` that have not arrived
+fn skip_message_keys(state: &mut RatchetState, until: u32) -> Result<()> {
+    let (Some(mut chain), Some(remote)) = (state.recv_chain, state.dh_remote) else {
+        return Ok(());
+    };
+    if until.saturating_sub(state.recv_n) as usize > MAX_SKIPPED_MESSAGE_KEYS {
+        return Err(NoiseError::InvalidMessage);
+    }
+    while state.recv_n < until {
+        let (next, message_key) = chain_step(&chain)?;
+        chain.zeroize();
+        chain = next;
+        state.skipped.push_back((remote, state.recv_n, message_key));
+        state.recv_n += 1;
+    }
+    state.recv_chain = Some(chain);
+    chain.zeroize();
+    
+    while state.skipped.len() > MAX_SKIPPED_MESSAGE_KEYS {
+        if let Some((_, _, mut oldest)) = state.skipped.pop_front() {
+            oldest.zeroize();
+        }
+    }
+    Ok(())
+}
+
+fn associated_data(header: &[u8]) -> Vec<u8> {
+    let mut ad = RATCHET_LABEL.to_vec();
+    ad.extend_from_slice(header);
+    ad
+}
+
+fn storage_aad(session_id: &str) -> Vec<u8> {
+    let mut aad = RATCHET_LABEL.to_vec();
+    aad.extend_from_slice(session_id.as_bytes());
+    aad
+}
+
+fn read_key(reader: &mut Reader<'_>) -> Result<[u8; KEY_LEN]> {
+    reader.take(KEY_LEN)?.try_into().map_err(|_| NoiseError::InvalidMessage)
+}
+
+fn read_optional_key(reader: &mut Reader<'_>) -> Result<Option<[u8; KEY_LEN]>> {
+    match reader.take(1)?[0] {
+        0 => Ok(None),
+        1 => read_key(reader).map(Some),
+        _ => Err(NoiseError::InvalidMessage),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::perform_handshake;
+    
+    fn create_ratchets() -> (RatchetSession, RatchetSession) {
+        let (initiator, responder) = perform_handshake().unwrap();
+        (RatchetSession::new(&initiator).unwrap(), RatchetSession::new(&responder).unwrap())
+    }
+    
+    #[test]
+    fn test_conversation_ratchets_keys() {
+        let (mut alice, mut bob) = create_ratchets();
+        
+        // Bob may speak first, before any DH ratchet
+        let early = bob.encrypt(b"hi alice").unwrap();
+        let first = alice.encrypt(b"hi bob").unwrap();
+        assert_eq!(bob.decrypt(&first).unwrap(), b"hi bob");
+        assert_eq!(alice.decrypt(&early).unwrap(), b"hi alice");
+        
+        let bob_key = *bob.ratchet_key();
+        let reply = bob.encrypt(b"how are you").unwrap();
+        assert_eq!(alice.decrypt(&reply).unwrap(), b"how are you");
+        let alice_key = *alice.ratchet_key();
+        assert_eq!(bob.decrypt(&alice.encrypt(b"good").unwrap()).unwrap(), b"good");
+        assert_ne!(*bob.ratchet_key(), bob_key);
+        assert_eq!(bob.decrypt(&alice.encrypt(b"you?").unwrap()).unwrap(), b"you?");
+        assert_eq!(*alice.ratchet_key(), alice_key);
+        
+        assert!(matches!(bob.decrypt(&first), Err(NoiseError::DecryptionFailed | NoiseError::ReplayDetected)));
+        let mut tampered = alice.encrypt(b"x").unwrap();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(bob.decrypt(&tampered).is_err());
+        assert!(bob.decrypt(&tampered[..10]).is_err());
+    }
+    
+    #[test]
+    fn test_out_of_order_across_ratchets() {
+        let (mut alice, mut bob) = create_ratchets();
+        let a1 = alice.encrypt(b"a1").unwrap();
+        let a2 = alice.encrypt(b"a2").unwrap();
+        assert_eq!(bob.decrypt(&a2).unwrap(), b"a2");
+        assert_eq!(alice.decrypt(&bob.encrypt(b"b1").unwrap()).unwrap(), b"b1");
+        let a3 = alice.encrypt(b"a3").unwrap();
+        let a4 = alice.encrypt(b"a4").unwrap();
+        
+        assert_eq!(bob.decrypt(&a4).unwrap(), b"a4");
+        assert_eq!(bob.decrypt(&a1).unwrap(), b"a1");
+        assert_eq!(bob.decrypt(&a3).unwrap(), b"a3");
+        assert!(matches!(bob.decrypt(&a3), Err(NoiseError::ReplayDetected)));
+        assert!(matches!(bob.decrypt(&a1), Err(NoiseError::DecryptionFailed)));
+    }
+    
+    #[test]
+    fn test_serialize_restore() {
+        let (mut alice, mut bob) = create_ratchets();
+        let late = alice.encrypt(b"late").unwrap();
+        bob.decrypt(&alice.encrypt(b"early").unwrap()).unwrap();
+        
+        let mut bob = RatchetSession::restore(&bob.serialize()).unwrap();
+        let mut alice = RatchetSession::restore(&alice.serialize()).unwrap();
+        assert_eq!(bob.decrypt(&late).unwrap(), b"late");
+        assert_eq!(alice.decrypt(&bob.encrypt(b"back").unwrap()).unwrap(), b"back");
+        assert!(RatchetSession::restore(&[RATCHET_VERSION, 0]).is_err());
+    }
+    
+    #[cfg(feature = "storage-backends")]
+    #[test]
+    fn test_save_load() {
+        use crate::mobile::storage::MemoryKeyStorage;
+        
+        let storage = MemoryKeyStorage::new();
+        let (mut alice, bob) = create_ratchets();
+        bob.save(&storage, "chat-alice").unwrap();
+        
+        let mut bob = RatchetSession::load(&storage, "chat-alice").unwrap();
+        assert_eq!(bob.decrypt(&alice.encrypt(b"saved").unwrap()).unwrap(), b"saved");
+        assert!(RatchetSession::load(&storage, "chat-carol").is_err());
+    }
+}
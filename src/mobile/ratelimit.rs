@@ -0,0 +1,133 @@
+//! Per-source rate limiting for handshake openings
+//!
+//! Every opening a responder processes costs Diffie-Hellman operations and
+//! keeps the radio busy, so a hostile device nearby can drain the battery by
+//! opening handshakes in a loop. [`RateLimiter`] lets each source open a
+//! short burst of handshakes and then one per interval.
+//!
+//! Used by [`SessionManager::set_rate_limiter`](crate::mobile::manager::SessionManager::set_rate_limiter).
+
+use crate::core::now_ms;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Default number of handshakes a source may open back to back
+pub const DEFAULT_HANDSHAKE_BURST: u32 = 5;
+
+/// Default wait for each further handshake once the burst is used
+pub const DEFAULT_HANDSHAKE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Default cap on sources tracked at once
+pub const DEFAULT_MAX_TRACKED_SOURCES: usize = 4096;
+
+/// Limits how often each source may open a handshake
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    burst: u32,
+    interval_ms: u64,
+    max_sources: usize,
+    /// When each source's allowance is next fully refilled, in Unix millis
+    refilled_at: HashMap<String, u64>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(DEFAULT_HANDSHAKE_BURST, DEFAULT_HANDSHAKE_INTERVAL)
+    }
+}
+
+impl RateLimiter {
+    /// Allow `burst` handshakes at once, then one per `interval`
+    ///
+    /// A burst of zero is treated as one.
+    pub fn new(burst: u32, interval: Duration) -> Self {
+        Self {
+            burst: burst.max(1),
+            interval_ms: interval.as_millis().try_into().unwrap_or(u64::MAX),
+            max_sources: DEFAULT_MAX_TRACKED_SOURCES,
+            refilled_at: HashMap::new(),
+        }
+    }
+    
+    /// Set how many sources are tracked at once
+    ///
+    /// When every tracked source is still within its limit, new sources are
+    /// refused until one recovers, so rotating identifiers cannot get
+    /// around the limiter.
+    pub fn set_max_tracked_sources(&mut self, max: usize) {
+        self.max_sources = max.max(1);
+    }
+    
+    /// Take one handshake from the source's allowance
+    ///
+    /// Returns `false` if the source has used its allowance.
+    pub fn check(&mut self, source: &str) -> bool {
+        self.check_at(source, now_ms())
+    }
+    
+    fn check_at(&mut self, source: &str, now_ms: u64) -> bool {
+        let window = self.interval_ms.saturating_mul(self.burst as u64);
+        if !self.refilled_at.contains_key(source) && self.refilled_at.len() >= self.max_sources {
+            self.refilled_at.retain(|_, refilled_at| *refilled_at > now_ms);
+            if self.refilled_at.len() >= self.max_sources {
+                return false;
+            }
+        }
+        
+        let refilled_at = self.refilled_at.entry(source.to_string()).or_insert(now_ms);
+        let next = (*refilled_at).max(now_ms).saturating_add(self.interval_ms);
+        if next.saturating_sub(now_ms) > window {
+            return false;
+        }
+        *refilled_at = next;
+        true
+    }
+    
+    /// Restore a source's full allowance
+    pub fn reset(&mut self, source: &str) {
+        self.refilled_at.remove(source);
+    }
+    
+    /// Number of sources being tracked
+    pub fn tracked_sources(&self) -> usize {
+        self.refilled_at.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_burst_then_steady_rate() {
+        let mut limiter = RateLimiter::new(3, Duration::from_secs(10));
+        let start = 1_000_000;
+        
+        assert!((0..3).all(|_| limiter.check_at("mallory", start)));
+        assert!(!limiter.check_at("mallory", start));
+        assert!(limiter.check_at("alice", start));
+        
+        // One more handshake per interval
+        assert!(!limiter.check_at("mallory", start + 9_999));
+        assert!(limiter.check_at("mallory", start + 10_000));
+        assert!(!limiter.check_at("mallory", start + 10_000));
+        
+        limiter.reset("mallory");
+        assert!(limiter.check_at("mallory", start + 10_000));
+    }
+    
+    #[test]
+    fn test_new_sources_refused_when_full() {
+        let mut limiter = RateLimiter::new(1, Duration::from_secs(10));
+        limiter.set_max_tracked_sources(2);
+        let start = 1_000_000;
+        
+        assert!(limiter.check_at("a", start));
+        assert!(limiter.check_at("b", start));
+        assert!(!limiter.check_at("c", start));
+        
+        // Recovered sources make room
+        assert!(limiter.check_at("c", start + 10_000));
+        assert_eq!(limiter.tracked_sources(), 1);
+    }
+}
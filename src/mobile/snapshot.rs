@@ -0,0 +1,558 @@
+//! Encrypted session snapshots for app backgrounding
+//!
+//! A [`SessionSnapshot`] captures everything needed to resume a
+//! [`ResilientSession`] after the process is suspended or killed: sequence
+//! numbers, the replay window and the live transport keys and nonces.
+//! Snapshots are wrapped under a key-encryption key kept in the identity
+//! slot of a [`KeyStorage`] backend before being written to its session slot.
+//!
+//! Each suspend also bumps a per-session epoch kept in its own session slot
+//! ([`EPOCH_ID_PREFIX`]) and binds it into the wrapped snapshot. Resuming
+//! refuses any snapshot older than the recorded epoch, so putting back an
+//! earlier copy of the blob (with its older replay window and nonces) fails
+//! with [`NoiseError::ReplayDetected`] instead of rolling the session back.
+//! This only holds if the epoch slot cannot be rolled back together with the
+//! snapshot; backends that sync or back up session slots should exclude it.
+//!
+//! With the `serde` feature a snapshot serializes as the bytes of
+//! [`SessionSnapshot::to_bytes`], so apps can embed it in their own
+//! persistence format. Those bytes hold live keys: wrap them before they
+//! reach disk.
+
+use crate::core::crypto::{aead_open, aead_seal};
+use crate::core::error::{NoiseError, Result};
+use crate::core::keys::SecretKey;
+use crate::core::session::{NoiseSession, TransportKeys, TransportSnapshot};
+use crate::mobile::network::ResilientSession;
+use crate::mobile::storage::KeyStorage;
+use zeroize::{Zeroize, Zeroizing};
+
+/// Snapshot format version
+///
+/// Version 2 added rekey generations; version 1 snapshots restore with both
+/// generations at zero. Version 3 added the local static key and version 4
+/// the key export secret.
+const SNAPSHOT_VERSION: u8 = 4;
+
+/// Identity slot holding the key-encryption key for all snapshots
+pub const SNAPSHOT_KEK_ID: &str = "noise.snapshot-kek";
+
+/// Prefix of the session slots holding each session's snapshot epoch
+pub const EPOCH_ID_PREFIX: &str = "noise.epoch.";
+
+/// Serialized state of a transport-mode [`ResilientSession`]
+///
+/// The snapshot contains live key material; prefer [`SessionSnapshot::suspend`]
+/// which wraps it before it reaches storage.
+pub struct SessionSnapshot {
+    transport: TransportSnapshot,
+    resilient_state: Vec<u8>,
+}
+
+impl SessionSnapshot {
+    /// Capture the state of a session that has completed its handshake
+    pub fn capture(session: &ResilientSession) -> Result<Self> {
+        Ok(Self {
+            transport: session.inner().transport_snapshot()?,
+            resilient_state: session.serialize(),
+        })
+    }
+    
+    /// Capture a bare transport session with no resilient layer
+    ///
+    /// Rebuild it with [`SessionSnapshot::restore_session`].
+    pub fn capture_session(session: &NoiseSession) -> Result<Self> {
+        Ok(Self {
+            transport: session.transport_snapshot()?,
+            resilient_state: Vec::new(),
+        })
+    }
+    
+    /// Rebuild the bare transport session captured by this snapshot
+    ///
+    /// Any resilient-layer state in the snapshot is ignored. The same
+    /// single-use rule as [`SessionSnapshot::restore`] applies.
+    pub fn restore_session(self) -> Result<NoiseSession> {
+        NoiseSession::from_transport_snapshot(self.transport)
+    }
+    
+    /// Rebuild the session captured by this snapshot
+    ///
+    /// Restoring the same snapshot twice and sending from both copies reuses
+    /// nonces, which breaks confidentiality. Treat snapshots as single-use.
+    pub fn restore(self) -> Result<ResilientSession> {
+        let session = NoiseSession::from_transport_snapshot(self.transport)?;
+        ResilientSession::deserialize_pinned(&self.resilient_state, session)
+    }
+    
+    /// Encode the snapshot (including raw keys) into bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let t = &self.transport;
+        let remote_static = t.remote_static.as_deref().unwrap_or(&[]);
+        let local_static = t.local_static.as_deref().unwrap_or(&[]);
+        let exporter_secret = t.exporter_secret.as_deref().map_or(&[][..], Vec::as_slice);
+        
+        let mut data = Vec::new();
+        data.push(SNAPSHOT_VERSION);
+        data.push(t.is_initiator as u8);
+        data.extend_from_slice(&(t.protocol_name.len() as u16).to_be_bytes());
+        data.extend_from_slice(t.protocol_name.as_bytes());
+        data.extend_from_slice(&t.keys.initiator);
+        data.extend_from_slice(&t.keys.responder);
+        data.extend_from_slice(&t.sending_nonce.to_be_bytes());
+        data.extend_from_slice(&t.receiving_nonce.to_be_bytes());
+        data.extend_from_slice(&t.sending_generation.to_be_bytes());
+        data.extend_from_slice(&t.receiving_generation.to_be_bytes());
+        data.extend_from_slice(&(remote_static.len() as u16).to_be_bytes());
+        data.extend_from_slice(remote_static);
+        data.extend_from_slice(&(local_static.len() as u16).to_be_bytes());
+        data.extend_from_slice(local_static);
+        data.extend_from_slice(&(exporter_secret.len() as u16).to_be_bytes());
+        data.extend_from_slice(exporter_secret);
+        data.extend_from_slice(&(self.resilient_state.len() as u32).to_be_bytes());
+        data.extend_from_slice(&self.resilient_state);
+        data
+    }
+    
+    /// Decode a snapshot produced by [`SessionSnapshot::to_bytes`]
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(data);
+        
+        let version = reader.take(1)?[0];
+        if version == 0 || version > SNAPSHOT_VERSION {
+            return Err(NoiseError::InvalidMessage);
+        }
+        let is_initiator = match reader.take(1)?[0] {
+            0 => false,
+            1 => true,
+            _ => return Err(NoiseError::InvalidMessage),
+        };
+        
+        let name_len = reader.u16()? as usize;
+        let protocol_name = std::str::from_utf8(reader.take(name_len)?)
+            .map_err(|_| NoiseError::InvalidMessage)?
+            .to_string();
+        
+        let mut keys = TransportKeys { initiator: [0u8; 32], responder: [0u8; 32] };
+        keys.initiator.copy_from_slice(reader.take(32)?);
+        keys.responder.copy_from_slice(reader.take(32)?);
+        
+        let sending_nonce = reader.u64()?;
+        let receiving_nonce = reader.u64()?;
+        let (sending_generation, receiving_generation) = if version >= 2 {
+            (reader.u32()?, reader.u32()?)
+        } else {
+            (0, 0)
+        };
+        
+        let remote_len = reader.u16()? as usize;
+        let remote_static = match reader.take(remote_len)? {
+            [] => None,
+            key => Some(key.to_vec()),
+        };
+        let local_static = if version >= 3 {
+            let local_len = reader.u16()? as usize;
+            match reader.take(local_len)? {
+                [] => None,
+                key => Some(key.to_vec()),
+            }
+        } else {
+            None
+        };
+        let exporter_secret = if version >= 4 {
+            let secret_len = reader.u16()? as usize;
+            match reader.take(secret_len)? {
+                [] => None,
+                secret => Some(Zeroizing::new(secret.to_vec())),
+            }
+        } else {
+            None
+        };
+        
+        let state_len = reader.u32()? as usize;
+        let resilient_state = reader.take(state_len)?.to_vec();
+        
+        Ok(Self {
+            transport: TransportSnapshot {
+                protocol_name,
+                is_initiator,
+                keys,
+                sending_nonce,
+                receiving_nonce,
+                sending_generation,
+                receiving_generation,
+                remote_static,
+                local_static,
+                exporter_secret,
+            },
+            resilient_state,
+        })
+    }
+    
+    /// Snapshot a session and store it wrapped in `storage` under `session_id`
+    ///
+    /// The session is consumed so it cannot keep sending with nonces that the
+    /// stored copy will reuse once resumed. The session's epoch is advanced
+    /// first, so any snapshot stored before this one can no longer be resumed.
+    pub fn suspend(session: ResilientSession, storage: &dyn KeyStorage, session_id: &str) -> Result<()> {
+        let snapshot = Self::capture(&session)?;
+        let kek = load_or_create_kek(storage)?;
+        let epoch = next_epoch(load_epoch(storage, session_id)?)?;
+        
+        let mut plaintext = snapshot.to_bytes();
+        let wrapped = aead_seal(kek.as_bytes(), &epoch_aad(session_id, epoch), &plaintext);
+        plaintext.zeroize();
+        
+        let mut data = epoch.to_be_bytes().to_vec();
+        data.extend_from_slice(&wrapped?);
+        storage.store_session(&epoch_slot(session_id), &epoch.to_be_bytes())?;
+        storage.store_session(session_id, &data)
+    }
+    
+    /// Load, unwrap and restore a session stored by [`SessionSnapshot::suspend`]
+    ///
+    /// The stored snapshot is deleted once restored so it cannot be resumed
+    /// twice; one that fails to decode is left in place.
+    /// A snapshot from an earlier epoch than the one recorded for `session_id`
+    /// fails with [`NoiseError::ReplayDetected`]. Resuming moves the epoch
+    /// past the snapshot, so neither it nor any older copy can be resumed again.
+    pub fn resume(storage: &dyn KeyStorage, session_id: &str) -> Result<ResilientSession> {
+        let stored = storage.load_session(session_id)?;
+        let kek = storage.load_identity(SNAPSHOT_KEK_ID)?;
+        let epoch = load_epoch(storage, session_id)?;
+        let plaintext = match epoch {
+            // Stored before epochs were recorded
+            None => aead_open(kek.as_bytes(), session_id.as_bytes(), &stored),
+            Some(epoch) => {
+                let mut reader = Reader::new(&stored);
+                let stored_epoch = reader.u64()?;
+                let wrapped = reader.take(stored.len() - 8)?;
+                if stored_epoch != epoch {
+                    return Err(NoiseError::ReplayDetected);
+                }
+                aead_open(kek.as_bytes(), &epoch_aad(session_id, epoch), wrapped)
+            }
+        };
+        drop(kek);
+        
+        let mut plaintext = plaintext?;
+        let snapshot = Self::from_bytes(&plaintext);
+        plaintext.zeroize();
+        // A snapshot that does not decode stays stored
+        let session = snapshot?.restore()?;
+        
+        // Mark this snapshot consumed before handing the session out
+        storage.store_session(&epoch_slot(session_id), &next_epoch(epoch)?.to_be_bytes())?;
+        storage.delete_session(session_id)?;
+        Ok(session)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for SessionSnapshot {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&Zeroizing::new(self.to_bytes()))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for SessionSnapshot {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        deserializer.deserialize_bytes(BytesVisitor {
+            expecting: "session snapshot bytes",
+            parse: SessionSnapshot::from_bytes,
+        })
+    }
+}
+
+/// Accepts a value as a byte string, or as a sequence of bytes from
+/// formats such as JSON that have no byte strings, and parses it
+#[cfg(feature = "serde")]
+pub(crate) struct BytesVisitor<F> {
+    pub(crate) expecting: &'static str,
+    pub(crate) parse: F,
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T, F: FnOnce(&[u8]) -> Result<T>> serde::de::Visitor<'de> for BytesVisitor<F> {
+    type Value = T;
+    
+    fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.expecting)
+    }
+    
+    fn visit_bytes<E: serde::de::Error>(self, data: &[u8]) -> std::result::Result<T, E> {
+        (self.parse)(data).map_err(E::custom)
+    }
+    
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> std::result::Result<T, A::Error> {
+        let mut data = Zeroizing::new(Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096)));
+        while let Some(byte) = seq.next_element()? {
+            data.push(byte);
+        }
+        self.visit_bytes(&data)
+    }
+}
+
+fn epoch_slot(session_id: &str) -> String {
+    format!("{EPOCH_ID_PREFIX}{session_id}")
+}
+
+/// The epoch recorded for `session_id`, or `None` if it was never suspended
+fn load_epoch(storage: &dyn KeyStorage, session_id: &str) -> Result<Option<u64>> {
+    match storage.load_session(&epoch_slot(session_id)) {
+        Ok(data) => {
+            let bytes = data.as_slice().try_into().map_err(|_| NoiseError::InvalidMessage)?;
+            Ok(Some(u64::from_be_bytes(bytes)))
+        }
+        Err(NoiseError::InvalidParameter) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// The epoch after `epoch`, starting from 1
+fn next_epoch(epoch: Option<u64>) -> Result<u64> {
+    epoch
+        .unwrap_or(0)
+        .checked_add(1)
+        .ok_or_else(|| NoiseError::InvalidState("snapshot epoch exhausted".into()))
+}
+
+/// Associated data binding a wrapped snapshot to its session and epoch
+fn epoch_aad(session_id: &str, epoch: u64) -> Vec<u8> {
+    let mut aad = session_id.as_bytes().to_vec();
+    aad.extend_from_slice(&epoch.to_be_bytes());
+    aad
+}
+
+/// Load the snapshot key-encryption key, generating it on first use
+pub(crate) fn load_or_create_kek(storage: &dyn KeyStorage) -> Result<SecretKey> {
+    if storage.has_identity(SNAPSHOT_KEK_ID)? {
+        return storage.load_identity(SNAPSHOT_KEK_ID);
+    }
+    let kek = SecretKey::generate();
+    storage.store_identity(&kek, SNAPSHOT_KEK_ID)?;
+    Ok(kek)
+}
+
+/// Bounds-checked cursor over snapshot bytes
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+    
+    /// Whether every byte has been consumed
+    #[cfg(any(feature = "manager", feature = "identity", feature = "group", feature = "ratchet", feature = "outbox"))]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.offset == self.data.len()
+    }
+    
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.offset.checked_add(len).ok_or(NoiseError::InvalidMessage)?;
+        let bytes = self.data.get(self.offset..end).ok_or(NoiseError::InvalidMessage)?;
+        self.offset = end;
+        Ok(bytes)
+    }
+    
+    pub(crate) fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().map_err(|_| NoiseError::InvalidMessage)?))
+    }
+    
+    pub(crate) fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().map_err(|_| NoiseError::InvalidMessage)?))
+    }
+    
+    pub(crate) fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().map_err(|_| NoiseError::InvalidMessage)?))
+    }
+}
+
+#[cfg(all(test, feature = "storage-backends"))]
+mod tests {
+    use super::*;
+    use crate::test_utils::perform_handshake;
+    use crate::mobile::storage::MemoryKeyStorage;
+    
+    fn create_connected_pair() -> (ResilientSession, ResilientSession) {
+        let (initiator, responder) = perform_handshake().unwrap();
+        (ResilientSession::new(initiator), ResilientSession::new(responder))
+    }
+    
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let (mut alice, mut bob) = create_connected_pair();
+        
+        let ct = alice.encrypt_with_sequence(b"before").unwrap();
+        bob.decrypt_with_replay_check(&ct).unwrap();
+        
+        let remote_static = alice.inner().get_remote_static().unwrap().to_vec();
+        let local_static = alice.inner().get_local_static().unwrap().to_vec();
+        let bytes = SessionSnapshot::capture(&alice).unwrap().to_bytes();
+        drop(alice);
+        let mut alice = SessionSnapshot::from_bytes(&bytes).unwrap().restore().unwrap();
+        
+        assert_eq!(alice.send_sequence(), 1);
+        assert!(alice.is_handshake_complete());
+        assert_eq!(alice.inner().get_remote_static(), Some(&remote_static[..]));
+        assert_eq!(alice.inner().get_local_static(), Some(&local_static[..]));
+        
+        // Both directions keep working with the restored keys and nonces
+        let ct = alice.encrypt_with_sequence(b"after").unwrap();
+        assert_eq!(bob.decrypt_with_replay_check(&ct).unwrap(), b"after");
+        
+        let ct = bob.encrypt_with_sequence(b"reply").unwrap();
+        assert_eq!(alice.decrypt_with_replay_check(&ct).unwrap(), b"reply");
+    }
+    
+    #[test]
+    fn test_snapshot_preserves_rekeyed_keys() {
+        let (mut alice, mut bob) = create_connected_pair();
+        alice.inner_mut().rekey_outgoing().unwrap();
+        bob.inner_mut().rekey_incoming().unwrap();
+        
+        let bytes = SessionSnapshot::capture(&alice).unwrap().to_bytes();
+        let mut alice = SessionSnapshot::from_bytes(&bytes).unwrap().restore().unwrap();
+        assert_eq!(alice.inner().sending_generation(), 1);
+        
+        let ct = alice.encrypt_with_sequence(b"rekeyed").unwrap();
+        assert_eq!(bob.decrypt_with_replay_check(&ct).unwrap(), b"rekeyed");
+    }
+    
+    #[test]
+    fn test_suspend_and_resume_through_storage() {
+        let storage = MemoryKeyStorage::new();
+        let (alice, mut bob) = create_connected_pair();
+        
+        SessionSnapshot::suspend(alice, &storage, "peer-bob").unwrap();
+        assert!(storage.has_identity(SNAPSHOT_KEK_ID).unwrap());
+        
+        // Stored blob is wrapped, not the raw snapshot
+        let stored = storage.load_session("peer-bob").unwrap();
+        assert!(SessionSnapshot::from_bytes(&stored).is_err());
+        
+        let mut alice = SessionSnapshot::resume(&storage, "peer-bob").unwrap();
+        let ct = alice.encrypt_with_sequence(b"resumed").unwrap();
+        assert_eq!(bob.decrypt_with_replay_check(&ct).unwrap(), b"resumed");
+        
+        // Snapshots are single-use
+        assert!(SessionSnapshot::resume(&storage, "peer-bob").is_err());
+    }
+    
+    #[test]
+    fn test_wrapped_snapshot_bound_to_session_id() {
+        let storage = MemoryKeyStorage::new();
+        let (alice, _bob) = create_connected_pair();
+        
+        SessionSnapshot::suspend(alice, &storage, "peer-a").unwrap();
+        let wrapped = storage.load_session("peer-a").unwrap();
+        storage.store_session("peer-b", &wrapped).unwrap();
+        
+        assert!(SessionSnapshot::resume(&storage, "peer-b").is_err());
+    }
+    
+    #[test]
+    fn test_rolled_back_snapshot_rejected() {
+        let storage = MemoryKeyStorage::new();
+        let (alice, mut bob) = create_connected_pair();
+        
+        SessionSnapshot::suspend(alice, &storage, "peer-bob").unwrap();
+        let old = storage.load_session("peer-bob").unwrap();
+        let alice = SessionSnapshot::resume(&storage, "peer-bob").unwrap();
+        SessionSnapshot::suspend(alice, &storage, "peer-bob").unwrap();
+        
+        // Putting back the first snapshot must not resume the older state
+        let current = storage.load_session("peer-bob").unwrap();
+        storage.store_session("peer-bob", &old).unwrap();
+        assert!(matches!(SessionSnapshot::resume(&storage, "peer-bob"), Err(NoiseError::ReplayDetected)));
+        
+        // Neither can the epoch prefix be bumped without the key
+        let mut forged = old.clone();
+        forged[..8].copy_from_slice(&current[..8]);
+        storage.store_session("peer-bob", &forged).unwrap();
+        assert!(SessionSnapshot::resume(&storage, "peer-bob").is_err());
+        
+        storage.store_session("peer-bob", &current).unwrap();
+        let mut alice = SessionSnapshot::resume(&storage, "peer-bob").unwrap();
+        let ct = alice.encrypt_with_sequence(b"latest").unwrap();
+        assert_eq!(bob.decrypt_with_replay_check(&ct).unwrap(), b"latest");
+    }
+    
+    #[test]
+    fn test_resumed_snapshot_cannot_be_resumed_again() {
+        let storage = MemoryKeyStorage::new();
+        let (alice, mut bob) = create_connected_pair();
+        
+        SessionSnapshot::suspend(alice, &storage, "peer-bob").unwrap();
+        let copy = storage.load_session("peer-bob").unwrap();
+        let mut alice = SessionSnapshot::resume(&storage, "peer-bob").unwrap();
+        let ct = alice.encrypt_with_sequence(b"after resume").unwrap();
+        assert_eq!(bob.decrypt_with_replay_check(&ct).unwrap(), b"after resume");
+        
+        // The copy would reuse the nonce just spent
+        storage.store_session("peer-bob", &copy).unwrap();
+        assert!(matches!(SessionSnapshot::resume(&storage, "peer-bob"), Err(NoiseError::ReplayDetected)));
+    }
+    
+    #[test]
+    fn test_resumes_snapshot_without_epoch() {
+        let storage = MemoryKeyStorage::new();
+        let (alice, _bob) = create_connected_pair();
+        
+        let kek = load_or_create_kek(&storage).unwrap();
+        let plaintext = SessionSnapshot::capture(&alice).unwrap().to_bytes();
+        let wrapped = aead_seal(kek.as_bytes(), b"peer-bob", &plaintext).unwrap();
+        storage.store_session("peer-bob", &wrapped).unwrap();
+        
+        assert!(SessionSnapshot::resume(&storage, "peer-bob").is_ok());
+        
+        // Once resumed, the legacy blob is rejected like any consumed snapshot
+        storage.store_session("peer-bob", &wrapped).unwrap();
+        assert!(SessionSnapshot::resume(&storage, "peer-bob").is_err());
+    }
+    
+    #[test]
+    fn test_undecodable_snapshot_is_kept() {
+        let storage = MemoryKeyStorage::new();
+        let kek = load_or_create_kek(&storage).unwrap();
+        let wrapped = aead_seal(kek.as_bytes(), b"peer-bob", b"not a snapshot").unwrap();
+        storage.store_session("peer-bob", &wrapped).unwrap();
+        
+        assert!(SessionSnapshot::resume(&storage, "peer-bob").is_err());
+        assert_eq!(storage.load_session("peer-bob").unwrap(), wrapped);
+        assert!(load_epoch(&storage, "peer-bob").unwrap().is_none());
+    }
+    
+    #[test]
+    fn test_handshake_session_cannot_be_snapshotted() {
+        let session = ResilientSession::new(NoiseSession::new_initiator().unwrap());
+        assert!(matches!(SessionSnapshot::capture(&session), Err(NoiseError::InvalidState(_))));
+    }
+    
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_roundtrip() {
+        let (alice, mut bob) = create_connected_pair();
+        
+        let json = serde_json::to_string(&SessionSnapshot::capture(&alice).unwrap()).unwrap();
+        let mut alice_restored = serde_json::from_str::<SessionSnapshot>(&json).unwrap().restore().unwrap();
+        let ct = alice_restored.encrypt_with_sequence(b"embedded").unwrap();
+        assert_eq!(bob.decrypt_with_replay_check(&ct).unwrap(), b"embedded");
+        
+        assert!(serde_json::from_str::<SessionSnapshot>("[1, 2, 3]").is_err());
+    }
+    
+    #[test]
+    fn test_truncated_snapshot_rejected() {
+        let (alice, _bob) = create_connected_pair();
+        let bytes = SessionSnapshot::capture(&alice).unwrap().to_bytes();
+        
+        for len in [0, 1, 10, bytes.len() - 1] {
+            assert!(SessionSnapshot::from_bytes(&bytes[..len]).is_err());
+        }
+    }
+}
@@ -0,0 +1,354 @@
+//! SQLite-backed key storage
+//!
+//! [`SqliteKeyStorage`] keeps identities, sessions and peer metadata in one
+//! database, for apps that already ship SQLite. Every value is sealed under a
+//! database key the app keeps in the Keychain or Keystore. With the
+//! `sqlcipher` feature the whole file, ids included, is encrypted as well,
+//! under a second key derived from the same database key.
+
+use crate::core::crypto::{aead_open, aead_seal, hkdf};
+use crate::core::error::{NoiseError, Result};
+use crate::core::keys::SecretKey;
+use crate::mobile::storage::KeyStorage;
+use rusqlite::{params, Connection, OptionalExtension};
+use snow::params::HashChoice;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use zeroize::Zeroizing;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS identities (id TEXT PRIMARY KEY, value BLOB NOT NULL);
+    CREATE TABLE IF NOT EXISTS sessions (id TEXT PRIMARY KEY, value BLOB NOT NULL);
+    CREATE TABLE IF NOT EXISTS peer_metadata (
+        peer_id TEXT NOT NULL,
+        name TEXT NOT NULL,
+        value BLOB NOT NULL,
+        PRIMARY KEY (peer_id, name)
+    );
+";
+
+/// HKDF info for the key that seals individual values
+const VALUE_KEY_LABEL: &[u8] = b"noise-mobile/sqlite/value/v1";
+/// HKDF info for the SQLCipher file key
+#[cfg(feature = "sqlcipher")]
+const FILE_KEY_LABEL: &[u8] = b"noise-mobile/sqlite/file/v1";
+
+const IDENTITIES: &str = "identities";
+const SESSIONS: &str = "sessions";
+
+/// Key storage in a single SQLite database
+pub struct SqliteKeyStorage {
+    connection: Mutex<Connection>,
+    key: Zeroizing<Vec<u8>>,
+}
+
+impl SqliteKeyStorage {
+    /// Open or create the database at `path`
+    ///
+    /// `key` is the 32-byte database key. Opening an existing database with
+    /// a different key succeeds, but reading any value from it fails.
+    pub fn open(path: impl AsRef<Path>, key: &[u8]) -> Result<Self> {
+        Self::with_connection(Connection::open(path).map_err(sql_error)?, key)
+    }
+    
+    /// Open a database that lives only as long as the storage
+    pub fn open_in_memory(key: &[u8]) -> Result<Self> {
+        Self::with_connection(Connection::open_in_memory().map_err(sql_error)?, key)
+    }
+    
+    fn with_connection(connection: Connection, key: &[u8]) -> Result<Self> {
+        if key.len() != 32 {
+            return Err(NoiseError::InvalidParameter);
+        }
+        
+        // The file key and the value key are derived separately, so neither
+        // layer's key is ever used by the other
+        #[cfg(feature = "sqlcipher")]
+        {
+            let file_key = Zeroizing::new(hkdf(HashChoice::SHA256, &[], key, FILE_KEY_LABEL, 32)?);
+            let hex: Zeroizing<String> = Zeroizing::new(file_key.iter().map(|b| format!("{:02x}", b)).collect());
+            let pragma = Zeroizing::new(format!("PRAGMA key = \"x'{}'\";", hex.as_str()));
+            connection.execute_batch(&pragma).map_err(sql_error)?;
+        }
+        connection.execute_batch(SCHEMA).map_err(sql_error)?;
+        
+        Ok(Self {
+            connection: Mutex::new(connection),
+            key: Zeroizing::new(hkdf(HashChoice::SHA256, &[], key, VALUE_KEY_LABEL, 32)?),
+        })
+    }
+    
+    /// Store a metadata value for a peer, replacing any previous value
+    pub fn store_peer_metadata(&self, peer_id: &str, name: &str, value: &[u8]) -> Result<()> {
+        put_metadata(&*self.lock()?, &self.key, peer_id, name, value)
+    }
+    
+    /// Load a metadata value for a peer, `None` if it was never stored
+    pub fn load_peer_metadata(&self, peer_id: &str, name: &str) -> Result<Option<Vec<u8>>> {
+        let connection = self.lock()?;
+        let sealed: Option<Vec<u8>> = connection
+            .query_row(
+                "SELECT value FROM peer_metadata WHERE peer_id = ?1 AND name = ?2",
+                params![peer_id, name],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(sql_error)?;
+        sealed.map(|sealed| aead_open(&self.key, &metadata_aad(peer_id, name), &sealed)).transpose()
+    }
+    
+    /// Delete every metadata value stored for a peer
+    pub fn delete_peer(&self, peer_id: &str) -> Result<()> {
+        delete_metadata(&*self.lock()?, peer_id)
+    }
+    
+    /// Apply several writes atomically
+    ///
+    /// The writes are committed if `f` returns `Ok` and rolled back otherwise.
+    pub fn transaction<R>(&self, f: impl FnOnce(&StorageTransaction<'_>) -> Result<R>) -> Result<R> {
+        let mut connection = self.lock()?;
+        let transaction = StorageTransaction {
+            transaction: connection.transaction().map_err(sql_error)?,
+            key: &self.key,
+        };
+        let result = f(&transaction)?;
+        transaction.transaction.commit().map_err(sql_error)?;
+        Ok(result)
+    }
+    
+    fn get(&self, table: &str, id: &str) -> Result<Vec<u8>> {
+        let connection = self.lock()?;
+        let sealed: Vec<u8> = connection
+            .query_row(&format!("SELECT value FROM {} WHERE id = ?1", table), params![id], |row| row.get(0))
+            .optional()
+            .map_err(sql_error)?
+            .ok_or(NoiseError::InvalidParameter)?;
+        aead_open(&self.key, &slot_aad(table, id), &sealed)
+    }
+    
+    fn lock(&self) -> Result<MutexGuard<'_, Connection>> {
+        self.connection.lock().map_err(|_| NoiseError::InvalidState("Lock poisoned".to_string()))
+    }
+}
+
+impl KeyStorage for SqliteKeyStorage {
+    fn store_identity(&self, key: &SecretKey, id: &str) -> Result<()> {
+        put(&*self.lock()?, &self.key, IDENTITIES, id, key.as_bytes())
+    }
+    
+    fn load_identity(&self, id: &str) -> Result<SecretKey> {
+        SecretKey::from_bytes(&Zeroizing::new(self.get(IDENTITIES, id)?))
+    }
+    
+    fn delete_identity(&self, id: &str) -> Result<()> {
+        delete(&*self.lock()?, IDENTITIES, id)
+    }
+    
+    fn list_identities(&self) -> Result<Vec<String>> {
+        let connection = self.lock()?;
+        let mut statement = connection.prepare("SELECT id FROM identities ORDER BY id").map_err(sql_error)?;
+        let ids = statement
+            .query_map([], |row| row.get(0))
+            .map_err(sql_error)?
+            .collect::<std::result::Result<Vec<String>, _>>()
+            .map_err(sql_error)?;
+        Ok(ids)
+    }
+    
+    fn has_identity(&self, id: &str) -> Result<bool> {
+        let connection = self.lock()?;
+        connection
+            .query_row("SELECT 1 FROM identities WHERE id = ?1", params![id], |_| Ok(()))
+            .optional()
+            .map(|row| row.is_some())
+            .map_err(sql_error)
+    }
+    
+    fn store_session(&self, session_id: &str, session_data: &[u8]) -> Result<()> {
+        put(&*self.lock()?, &self.key, SESSIONS, session_id, session_data)
+    }
+    
+    fn load_session(&self, session_id: &str) -> Result<Vec<u8>> {
+        self.get(SESSIONS, session_id)
+    }
+    
+    fn delete_session(&self, session_id: &str) -> Result<()> {
+        delete(&*self.lock()?, SESSIONS, session_id)
+    }
+}
+
+/// Writes grouped by [`SqliteKeyStorage::transaction`]
+pub struct StorageTransaction<'a> {
+    transaction: rusqlite::Transaction<'a>,
+    key: &'a [u8],
+}
+
+impl StorageTransaction<'_> {
+    /// Store an identity key with a given identifier
+    pub fn store_identity(&self, key: &SecretKey, id: &str) -> Result<()> {
+        put(&self.transaction, self.key, IDENTITIES, id, key.as_bytes())
+    }
+    
+    /// Delete an identity key by identifier
+    pub fn delete_identity(&self, id: &str) -> Result<()> {
+        delete(&self.transaction, IDENTITIES, id)
+    }
+    
+    /// Store session data
+    pub fn store_session(&self, session_id: &str, session_data: &[u8]) -> Result<()> {
+        put(&self.transaction, self.key, SESSIONS, session_id, session_data)
+    }
+    
+    /// Delete session data
+    pub fn delete_session(&self, session_id: &str) -> Result<()> {
+        delete(&self.transaction, SESSIONS, session_id)
+    }
+    
+    /// Store a metadata value for a peer
+    pub fn store_peer_metadata(&self, peer_id: &str, name: &str, value: &[u8]) -> Result<()> {
+        put_metadata(&self.transaction, self.key, peer_id, name, value)
+    }
+    
+    /// Delete every metadata value stored for a peer
+    pub fn delete_peer(&self, peer_id: &str) -> Result<()> {
+        delete_metadata(&self.transaction, peer_id)
+    }
+}
+
+fn put(connection: &Connection, key: &[u8], table: &str, id: &str, value: &[u8]) -> Result<()> {
+    let sealed = aead_seal(key, &slot_aad(table, id), value)?;
+    connection
+        .execute(&format!("INSERT OR REPLACE INTO {} (id, value) VALUES (?1, ?2)", table), params![id, sealed])
+        .map_err(sql_error)?;
+    Ok(())
+}
+
+fn delete(connection: &Connection, table: &str, id: &str) -> Result<()> {
+    connection
+        .execute(&format!("DELETE FROM {} WHERE id = ?1", table), params![id])
+        .map_err(sql_error)?;
+    Ok(())
+}
+
+fn put_metadata(connection: &Connection, key: &[u8], peer_id: &str, name: &str, value: &[u8]) -> Result<()> {
+    let sealed = aead_seal(key, &metadata_aad(peer_id, name), value)?;
+    connection
+        .execute(
+            "INSERT OR REPLACE INTO peer_metadata (peer_id, name, value) VALUES (?1, ?2, ?3)",
+            params![peer_id, name, sealed],
+        )
+        .map_err(sql_error)?;
+    Ok(())
+}
+
+fn delete_metadata(connection: &Connection, peer_id: &str) -> Result<()> {
+    connection
+        .execute("DELETE FROM peer_metadata WHERE peer_id = ?1", params![peer_id])
+        .map_err(sql_error)?;
+    Ok(())
+}
+
+/// Bind a sealed value to its row, so values can't be swapped between rows
+fn slot_aad(table: &str, id: &str) -> Vec<u8> {
+    [table.as_bytes(), b"\0", id.as_bytes()].concat()
+}
+
+fn metadata_aad(peer_id: &str, name: &str) -> Vec<u8> {
+    [b"peer_metadata\0".as_slice(), peer_id.as_bytes(), b"\0", name.as_bytes()].concat()
+}
+
+fn sql_error(e: rusqlite::Error) -> NoiseError {
+    NoiseError::InvalidState(format!("SQLite error: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const DB_KEY: [u8; 32] = [9u8; 32];
+    
+    #[test]
+    fn test_sqlite_storage_basic() {
+        let storage = SqliteKeyStorage::open_in_memory(&DB_KEY).unwrap();
+        let key = SecretKey::from_bytes(&[1u8; 32]).unwrap();
+        
+        storage.store_identity(&key, "device").unwrap();
+        assert!(storage.has_identity("device").unwrap());
+        assert_eq!(storage.load_identity("device").unwrap().as_bytes(), key.as_bytes());
+        assert_eq!(storage.list_identities().unwrap(), vec!["device".to_string()]);
+        
+        storage.store_session("bob", b"snapshot").unwrap();
+        assert_eq!(storage.load_session("bob").unwrap(), b"snapshot");
+        
+        storage.delete_identity("device").unwrap();
+        storage.delete_session("bob").unwrap();
+        assert!(!storage.has_identity("device").unwrap());
+        assert!(storage.load_session("bob").is_err());
+    }
+    
+    #[test]
+    fn test_peer_metadata() {
+        let storage = SqliteKeyStorage::open_in_memory(&DB_KEY).unwrap();
+        storage.store_peer_metadata("bob", "nickname", b"Bob").unwrap();
+        storage.store_peer_metadata("bob", "verified", &[1]).unwrap();
+        
+        assert_eq!(storage.load_peer_metadata("bob", "nickname").unwrap(), Some(b"Bob".to_vec()));
+        assert_eq!(storage.load_peer_metadata("bob", "avatar").unwrap(), None);
+        
+        storage.delete_peer("bob").unwrap();
+        assert_eq!(storage.load_peer_metadata("bob", "verified").unwrap(), None);
+    }
+    
+    #[test]
+    fn test_transaction_rolls_back_on_error() {
+        let storage = SqliteKeyStorage::open_in_memory(&DB_KEY).unwrap();
+        
+        let result: Result<()> = storage.transaction(|tx| {
+            tx.store_session("bob", b"snapshot")?;
+            Err(NoiseError::InvalidParameter)
+        });
+        assert!(result.is_err());
+        assert!(storage.load_session("bob").is_err());
+        
+        storage.transaction(|tx| {
+            tx.store_session("bob", b"snapshot")?;
+            tx.store_peer_metadata("bob", "nickname", b"Bob")
+        }).unwrap();
+        assert_eq!(storage.load_session("bob").unwrap(), b"snapshot");
+    }
+    
+    #[test]
+    fn test_values_sealed_under_derived_key() {
+        let storage = SqliteKeyStorage::open_in_memory(&DB_KEY).unwrap();
+        storage.store_session("bob", b"snapshot").unwrap();
+        
+        let sealed: Vec<u8> = storage
+            .lock()
+            .unwrap()
+            .query_row("SELECT value FROM sessions WHERE id = ?1", params!["bob"], |row| row.get(0))
+            .unwrap();
+        assert!(aead_open(&DB_KEY, &slot_aad(SESSIONS, "bob"), &sealed).is_err());
+        assert_eq!(aead_open(&storage.key, &slot_aad(SESSIONS, "bob"), &sealed).unwrap(), b"snapshot");
+    }
+    
+    #[test]
+    fn test_reopen_with_key() {
+        let path = std::env::temp_dir().join(format!("noise-sqlite-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        
+        {
+            let storage = SqliteKeyStorage::open(&path, &DB_KEY).unwrap();
+            storage.store_identity(&SecretKey::from_bytes(&[1u8; 32]).unwrap(), "device").unwrap();
+        }
+        
+        let storage = SqliteKeyStorage::open(&path, &DB_KEY).unwrap();
+        assert_eq!(storage.load_identity("device").unwrap().as_bytes(), &[1u8; 32]);
+        drop(storage);
+        
+        // The wrong key can't read values back
+        let wrong = SqliteKeyStorage::open(&path, &[8u8; 32]);
+        assert!(wrong.is_err() || wrong.unwrap().load_identity("device").is_err());
+        
+        std::fs::remove_file(&path).unwrap();
+    }
+}
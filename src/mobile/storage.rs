@@ -1,17 +1,26 @@
 //! Key storage abstraction for mobile platforms
+//!
+//! The concrete backends need the `storage-backends` feature; without it
+//! only the [`KeyStorage`] trait remains so companions can supply their own.
 
-use crate::core::error::{NoiseError, Result};
+use crate::core::error::Result;
+use crate::core::keys::SecretKey;
+#[cfg(feature = "storage-backends")]
+use crate::core::error::NoiseError;
+#[cfg(feature = "storage-backends")]
 use std::collections::HashMap;
+#[cfg(feature = "storage-backends")]
 use std::sync::{Arc, Mutex};
+#[cfg(feature = "storage-backends")]
 use zeroize::Zeroize;
 
 /// Trait for secure key storage on mobile platforms
 pub trait KeyStorage: Send + Sync {
     /// Store an identity key with a given identifier
-    fn store_identity(&self, key: &[u8], id: &str) -> Result<()>;
+    fn store_identity(&self, key: &SecretKey, id: &str) -> Result<()>;
     
     /// Load an identity key by identifier
-    fn load_identity(&self, id: &str) -> Result<Vec<u8>>;
+    fn load_identity(&self, id: &str) -> Result<SecretKey>;
     
     /// Delete an identity key by identifier
     fn delete_identity(&self, id: &str) -> Result<()>;
@@ -33,12 +42,14 @@ pub trait KeyStorage: Send + Sync {
 }
 
 /// Secure memory storage for keys (for testing and development)
+#[cfg(feature = "storage-backends")]
 #[derive(Clone)]
 pub struct MemoryKeyStorage {
-    keys: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+    keys: Arc<Mutex<HashMap<String, SecretKey>>>,
     sessions: Arc<Mutex<HashMap<String, Vec<u8>>>>,
 }
 
+#[cfg(feature = "storage-backends")]
 impl MemoryKeyStorage {
     /// Create a new memory key storage
     pub fn new() -> Self {
@@ -53,10 +64,8 @@ impl MemoryKeyStorage {
         let mut keys = self.keys.lock().map_err(|_| NoiseError::InvalidState("Lock poisoned".to_string()))?;
         let mut sessions = self.sessions.lock().map_err(|_| NoiseError::InvalidState("Lock poisoned".to_string()))?;
         
-        // Zeroize all keys before clearing
-        for (_, mut key) in keys.drain() {
-            key.zeroize();
-        }
+        // Keys wipe themselves when dropped
+        keys.clear();
         
         for (_, mut session) in sessions.drain() {
             session.zeroize();
@@ -66,12 +75,14 @@ impl MemoryKeyStorage {
     }
 }
 
+#[cfg(feature = "storage-backends")]
 impl Default for MemoryKeyStorage {
     fn default() -> Self {
         Self::new()
     }
 }
 
+#[cfg(feature = "storage-backends")]
 impl Drop for MemoryKeyStorage {
     fn drop(&mut self) {
         // Try to clear on drop, ignore errors
@@ -79,24 +90,16 @@ impl Drop for MemoryKeyStorage {
     }
 }
 
+#[cfg(feature = "storage-backends")]
 impl KeyStorage for MemoryKeyStorage {
-    fn store_identity(&self, key: &[u8], id: &str) -> Result<()> {
-        if key.len() != 32 {
-            return Err(NoiseError::InvalidParameter);
-        }
-        
+    fn store_identity(&self, key: &SecretKey, id: &str) -> Result<()> {
         let mut keys = self.keys.lock().map_err(|_| NoiseError::InvalidState("Lock poisoned".to_string()))?;
-        
-        // Zeroize old key if it exists
-        if let Some(mut old_key) = keys.remove(id) {
-            old_key.zeroize();
-        }
-        
-        keys.insert(id.to_string(), key.to_vec());
+        // Any old key is wiped as it is dropped
+        keys.insert(id.to_string(), key.clone());
         Ok(())
     }
     
-    fn load_identity(&self, id: &str) -> Result<Vec<u8>> {
+    fn load_identity(&self, id: &str) -> Result<SecretKey> {
         let keys = self.keys.lock().map_err(|_| NoiseError::InvalidState("Lock poisoned".to_string()))?;
         keys.get(id)
             .cloned()
@@ -105,9 +108,7 @@ impl KeyStorage for MemoryKeyStorage {
     
     fn delete_identity(&self, id: &str) -> Result<()> {
         let mut keys = self.keys.lock().map_err(|_| NoiseError::InvalidState("Lock poisoned".to_string()))?;
-        if let Some(mut key) = keys.remove(id) {
-            key.zeroize();
-        }
+        keys.remove(id);
         Ok(())
     }
     
@@ -150,10 +151,10 @@ impl KeyStorage for MemoryKeyStorage {
 }
 
 /// iOS Keychain storage (placeholder for actual implementation)
-#[cfg(target_os = "ios")]
+#[cfg(all(target_os = "ios", feature = "storage-backends"))]
 pub struct KeychainStorage;
 
-#[cfg(target_os = "ios")]
+#[cfg(all(target_os = "ios", feature = "storage-backends"))]
 impl KeychainStorage {
     /// Create a new Keychain storage instance
     pub fn new() -> Self {
@@ -161,14 +162,14 @@ impl KeychainStorage {
     }
 }
 
-#[cfg(target_os = "ios")]
+#[cfg(all(target_os = "ios", feature = "storage-backends"))]
 impl KeyStorage for KeychainStorage {
-    fn store_identity(&self, _key: &[u8], _id: &str) -> Result<()> {
+    fn store_identity(&self, _key: &SecretKey, _id: &str) -> Result<()> {
         // TODO: Implement using Security framework
         Err(NoiseError::InvalidState("Not implemented".to_string()))
     }
     
-    fn load_identity(&self, _id: &str) -> Result<Vec<u8>> {
+    fn load_identity(&self, _id: &str) -> Result<SecretKey> {
         // TODO: Implement using Security framework
         Err(NoiseError::InvalidState("Not implemented".to_string()))
     }
@@ -205,10 +206,10 @@ impl KeyStorage for KeychainStorage {
 }
 
 /// Android Keystore storage (placeholder for actual implementation)
-#[cfg(target_os = "android")]
+#[cfg(all(target_os = "android", feature = "storage-backends"))]
 pub struct KeystoreStorage;
 
-#[cfg(target_os = "android")]
+#[cfg(all(target_os = "android", feature = "storage-backends"))]
 impl KeystoreStorage {
     /// Create a new Keystore storage instance
     pub fn new() -> Self {
@@ -216,14 +217,14 @@ impl KeystoreStorage {
     }
 }
 
-#[cfg(target_os = "android")]
+#[cfg(all(target_os = "android", feature = "storage-backends"))]
 impl KeyStorage for KeystoreStorage {
-    fn store_identity(&self, _key: &[u8], _id: &str) -> Result<()> {
+    fn store_identity(&self, _key: &SecretKey, _id: &str) -> Result<()> {
         // TODO: Implement using Android Keystore
         Err(NoiseError::InvalidState("Not implemented".to_string()))
     }
     
-    fn load_identity(&self, _id: &str) -> Result<Vec<u8>> {
+    fn load_identity(&self, _id: &str) -> Result<SecretKey> {
         // TODO: Implement using Android Keystore
         Err(NoiseError::InvalidState("Not implemented".to_string()))
     }
@@ -259,14 +260,14 @@ impl KeyStorage for KeystoreStorage {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "storage-backends"))]
 mod tests {
     use super::*;
     
     #[test]
     fn test_memory_storage_basic() {
         let storage = MemoryKeyStorage::new();
-        let key = vec![0u8; 32];
+        let key = SecretKey::from_bytes(&[0u8; 32]).unwrap();
         let id = "test_key";
         
         // Store key
@@ -277,7 +278,7 @@ mod tests {
         
         // Load key
         let loaded = storage.load_identity(id).unwrap();
-        assert_eq!(key, loaded);
+        assert_eq!(key.as_bytes(), loaded.as_bytes());
         
         // List identities
         let identities = storage.list_identities().unwrap();
@@ -309,16 +310,14 @@ mod tests {
     
     #[test]
     fn test_invalid_key_size() {
-        let storage = MemoryKeyStorage::new();
-        let invalid_key = vec![0u8; 16]; // Wrong size
-        
-        assert!(storage.store_identity(&invalid_key, "test").is_err());
+        // Wrong size keys cannot reach storage at all
+        assert!(SecretKey::from_bytes(&[0u8; 16]).is_err());
     }
     
     #[test]
     fn test_zeroize_on_delete() {
         let storage = MemoryKeyStorage::new();
-        let key = vec![42u8; 32];
+        let key = SecretKey::from_bytes(&[42u8; 32]).unwrap();
         let id = "zeroize_test";
         
         storage.store_identity(&key, id).unwrap();
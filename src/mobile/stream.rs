@@ -0,0 +1,234 @@
+//! Noise over tokio byte streams
+//!
+//! [`NoiseStream`] runs the handshake over any `AsyncRead + AsyncWrite`
+//! transport and then behaves like the transport itself, encrypting writes
+//! and decrypting reads. Messages are framed with [`FrameCodec`].
+
+use crate::core::crypto::NOISE_MAX_PAYLOAD_LEN;
+use crate::core::session::NoiseSession;
+use crate::mobile::framing::FrameCodec;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+/// Bytes requested from the transport per read
+const READ_CHUNK: usize = 4096;
+
+/// An encrypted stream over a tokio transport
+pub struct NoiseStream<T> {
+    inner: T,
+    session: NoiseSession,
+    codec: FrameCodec,
+    /// Decrypted bytes not yet returned to the reader
+    plaintext: Vec<u8>,
+    plaintext_pos: usize,
+    /// Encrypted frames not yet written to the transport
+    outgoing: Vec<u8>,
+    outgoing_pos: usize,
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> NoiseStream<T> {
+    /// Run the handshake as initiator over `inner`
+    pub async fn connect(inner: T, session: NoiseSession) -> io::Result<Self> {
+        Self::handshake(inner, session, true).await
+    }
+    
+    /// Run the handshake as responder over `inner`
+    pub async fn accept(inner: T, session: NoiseSession) -> io::Result<Self> {
+        Self::handshake(inner, session, false).await
+    }
+    
+    async fn handshake(mut inner: T, mut session: NoiseSession, initiator: bool) -> io::Result<Self> {
+        let mut codec = FrameCodec::new();
+        let mut writing = initiator;
+        while session.is_handshake_state() {
+            if writing {
+                let message = session.write_message(&[])?;
+                inner.write_all(&FrameCodec::encode(&message)?).await?;
+                inner.flush().await?;
+            } else {
+                let message = Self::read_frame(&mut inner, &mut codec).await?;
+                session.read_message(&message)?;
+            }
+            writing = !writing;
+        }
+        
+        Ok(Self {
+            inner,
+            session,
+            codec,
+            plaintext: Vec::new(),
+            plaintext_pos: 0,
+            outgoing: Vec::new(),
+            outgoing_pos: 0,
+        })
+    }
+    
+    async fn read_frame(inner: &mut T, codec: &mut FrameCodec) -> io::Result<Vec<u8>> {
+        let mut chunk = [0u8; READ_CHUNK];
+        loop {
+            if let Some(frame) = codec.next_frame() {
+                return Ok(frame);
+            }
+            let n = inner.read(&mut chunk).await?;
+            if n == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            codec.feed(&chunk[..n]);
+        }
+    }
+    
+    /// Write buffered ciphertext to the transport
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.outgoing_pos < self.outgoing.len() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.outgoing[self.outgoing_pos..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.outgoing_pos += n;
+        }
+        self.outgoing.clear();
+        self.outgoing_pos = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T> NoiseStream<T> {
+    /// The established session
+    pub fn session(&self) -> &NoiseSession {
+        &self.session
+    }
+    
+    /// The underlying transport
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+    
+    /// Give up the stream, returning the transport and session
+    ///
+    /// Buffered plaintext and unsent ciphertext are discarded.
+    pub fn into_parts(self) -> (T, NoiseSession) {
+        (self.inner, self.session)
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> AsyncRead for NoiseStream<T> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        loop {
+            if this.plaintext_pos < this.plaintext.len() {
+                let n = buf.remaining().min(this.plaintext.len() - this.plaintext_pos);
+                buf.put_slice(&this.plaintext[this.plaintext_pos..this.plaintext_pos + n]);
+                this.plaintext_pos += n;
+                return Poll::Ready(Ok(()));
+            }
+            
+            if let Some(frame) = this.codec.next_frame() {
+                this.plaintext = this.session.decrypt(&frame)?;
+                this.plaintext_pos = 0;
+                continue;
+            }
+            
+            let mut chunk = [0u8; READ_CHUNK];
+            let mut chunk_buf = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk_buf))?;
+            if chunk_buf.filled().is_empty() {
+                // Clean EOF only on a frame boundary
+                if this.codec.buffered_len() > 0 {
+                    return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                }
+                return Poll::Ready(Ok(()));
+            }
+            this.codec.feed(chunk_buf.filled());
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> AsyncWrite for NoiseStream<T> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        ready!(this.poll_drain(cx))?;
+        
+        let n = buf.len().min(NOISE_MAX_PAYLOAD_LEN);
+        let ciphertext = this.session.encrypt(&buf[..n])?;
+        FrameCodec::encode_into(&ciphertext, &mut this.outgoing)?;
+        
+        // The frame is accepted even if the transport is not ready for it yet
+        if let Poll::Ready(Err(e)) = this.poll_drain(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(n))
+    }
+    
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_drain(cx))?;
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+    
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_drain(cx))?;
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::duplex;
+    
+    async fn connected_pair() -> (NoiseStream<tokio::io::DuplexStream>, NoiseStream<tokio::io::DuplexStream>) {
+        let (a, b) = duplex(1024);
+        let (alice, bob) = tokio::join!(
+            NoiseStream::connect(a, NoiseSession::new_initiator().unwrap()),
+            NoiseStream::accept(b, NoiseSession::new_responder().unwrap()),
+        );
+        (alice.unwrap(), bob.unwrap())
+    }
+    
+    #[tokio::test]
+    async fn test_stream_roundtrip() {
+        let (mut alice, mut bob) = connected_pair().await;
+        assert!(alice.session().is_transport_state());
+        
+        alice.write_all(b"hello over noise").await.unwrap();
+        alice.flush().await.unwrap();
+        let mut buf = [0u8; 16];
+        bob.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello over noise");
+        
+        bob.write_all(b"reply").await.unwrap();
+        bob.flush().await.unwrap();
+        let mut buf = [0u8; 5];
+        alice.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"reply");
+    }
+    
+    #[tokio::test]
+    async fn test_large_write_is_split_into_frames() {
+        let (mut alice, mut bob) = connected_pair().await;
+        let data: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        
+        let expected = data.clone();
+        let writer = tokio::spawn(async move {
+            alice.write_all(&data).await.unwrap();
+            alice.shutdown().await.unwrap();
+        });
+        
+        let mut received = Vec::new();
+        bob.read_to_end(&mut received).await.unwrap();
+        writer.await.unwrap();
+        assert_eq!(received, expected);
+    }
+    
+    #[tokio::test]
+    async fn test_truncated_frame_is_error() {
+        let (a, mut b) = duplex(1024);
+        let session = NoiseSession::new_responder().unwrap();
+        
+        // Half a frame header, then EOF
+        b.write_all(&[0]).await.unwrap();
+        drop(b);
+        assert!(NoiseStream::accept(a, session).await.is_err());
+    }
+}
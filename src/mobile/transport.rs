@@ -0,0 +1,246 @@
+//! Sessions over message-oriented mobile transports
+//!
+//! MultipeerConnectivity, Nearby Connections and similar frameworks move
+//! discrete packets rather than byte streams, each with its own size limit.
+//! [`PacketTransport`] describes such a transport and [`PacketLink`] runs a
+//! [`NoiseConnection`] over it.
+//!
+//! The connection's [`FrameCodec`](crate::mobile::framing::FrameCodec)
+//! framing already delimits messages, so a link simply cuts the framed
+//! bytes into packets of at most [`max_packet_len`] and feeds received
+//! packets back into the codec. This needs the transport to deliver packets
+//! reliably and in order, as Multipeer's `.reliable` mode and Nearby's
+//! `BYTES` payloads do; use
+//! [`HandshakeDriver`](crate::mobile::driver::HandshakeDriver) and explicit
+//! nonces for lossy datagram links.
+//!
+//! [`max_packet_len`]: PacketTransport::max_packet_len
+
+use crate::core::crypto::NOISE_MAX_MESSAGE_LEN;
+use crate::core::error::{NoiseError, Result};
+use crate::core::session::NoiseSession;
+use crate::mobile::connection::{ConnectionAction, NoiseConnection};
+use crate::mobile::framing::FRAME_HEADER_LEN;
+use std::sync::Arc;
+
+/// Largest `BYTES` payload Nearby Connections accepts
+pub const NEARBY_MAX_BYTES_LEN: usize = 32 * 1024;
+
+/// Packet size that fits any framed Noise message whole
+///
+/// Multipeer's reliable mode has no small limit, so every message goes out
+/// as a single packet.
+pub const MULTIPEER_MAX_PACKET_LEN: usize = FRAME_HEADER_LEN + NOISE_MAX_MESSAGE_LEN;
+
+/// A transport that moves whole packets in order
+pub trait PacketTransport {
+    /// Largest packet the transport delivers intact
+    fn max_packet_len(&self) -> usize;
+    
+    /// Hand a packet to the transport for delivery
+    fn send_packet(&mut self, packet: &[u8]) -> Result<()>;
+}
+
+/// Called with each packet to send
+pub type PacketSender = Arc<dyn Fn(&[u8]) -> Result<()> + Send + Sync>;
+
+/// A [`PacketTransport`] that hands packets to a callback
+///
+/// The callback is where the platform send goes, such as
+/// `MCSession.send(_:toPeers:with:)` or `ConnectionsClient.sendPayload`.
+pub struct CallbackTransport {
+    max_packet_len: usize,
+    send: PacketSender,
+}
+
+impl CallbackTransport {
+    /// Create a transport sending packets of at most `max_packet_len` bytes
+    pub fn new(max_packet_len: usize, send: impl Fn(&[u8]) -> Result<()> + Send + Sync + 'static) -> Result<Self> {
+        if max_packet_len == 0 {
+            return Err(NoiseError::InvalidParameter);
+        }
+        Ok(Self {
+            max_packet_len,
+            send: Arc::new(send),
+        })
+    }
+    
+    /// Create a transport sized for MultipeerConnectivity in reliable mode
+    pub fn multipeer(send: impl Fn(&[u8]) -> Result<()> + Send + Sync + 'static) -> Self {
+        Self {
+            max_packet_len: MULTIPEER_MAX_PACKET_LEN,
+            send: Arc::new(send),
+        }
+    }
+    
+    /// Create a transport sized for Nearby Connections `BYTES` payloads
+    pub fn nearby(send: impl Fn(&[u8]) -> Result<()> + Send + Sync + 'static) -> Self {
+        Self {
+            max_packet_len: NEARBY_MAX_BYTES_LEN,
+            send: Arc::new(send),
+        }
+    }
+}
+
+impl PacketTransport for CallbackTransport {
+    fn max_packet_len(&self) -> usize {
+        self.max_packet_len
+    }
+    
+    fn send_packet(&mut self, packet: &[u8]) -> Result<()> {
+        (self.send)(packet)
+    }
+}
+
+/// A Noise connection carried over a [`PacketTransport`]
+pub struct PacketLink<T: PacketTransport> {
+    connection: NoiseConnection,
+    transport: T,
+}
+
+impl<T: PacketTransport> PacketLink<T> {
+    /// Start a link as initiator, sending the first handshake message
+    pub fn initiator(session: NoiseSession, transport: T) -> Result<Self> {
+        let mut link = Self {
+            connection: NoiseConnection::initiator(session)?,
+            transport,
+        };
+        link.flush()?;
+        Ok(link)
+    }
+    
+    /// Start a link as responder, waiting for the peer's first packet
+    pub fn responder(session: NoiseSession, transport: T) -> Self {
+        Self {
+            connection: NoiseConnection::responder(session),
+            transport,
+        }
+    }
+    
+    /// Process a packet from the transport, returning the messages it
+    /// completes
+    ///
+    /// Handshake replies are sent before this returns. An error leaves the
+    /// link unusable; the caller should disconnect.
+    pub fn receive_packet(&mut self, packet: &[u8]) -> Result<Vec<Vec<u8>>> {
+        self.connection.receive(packet)?;
+        self.flush()
+    }
+    
+    /// Encrypt and send a message
+    ///
+    /// Messages sent before the handshake completes are held and sent in
+    /// order once it does.
+    pub fn send(&mut self, plaintext: &[u8]) -> Result<()> {
+        self.connection.send(plaintext)?;
+        let delivered = self.flush()?;
+        debug_assert!(delivered.is_empty());
+        Ok(())
+    }
+    
+    /// Whether the handshake has completed
+    pub fn is_established(&self) -> bool {
+        self.connection.is_established()
+    }
+    
+    /// The underlying connection
+    pub fn connection(&self) -> &NoiseConnection {
+        &self.connection
+    }
+    
+    /// The transport packets are sent on
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+    
+    /// Mutable access to the transport
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+    
+    /// Give up the link, returning the connection and the transport
+    pub fn into_parts(self) -> (NoiseConnection, T) {
+        (self.connection, self.transport)
+    }
+    
+    /// Carry out the connection's actions, returning delivered messages
+    fn flush(&mut self) -> Result<Vec<Vec<u8>>> {
+        let max_packet_len = self.transport.max_packet_len();
+        if max_packet_len == 0 {
+            return Err(NoiseError::InvalidState("Transport packet size is zero".to_string()));
+        }
+        let mut delivered = Vec::new();
+        while let Some(action) = self.connection.poll_action() {
+            match action {
+                ConnectionAction::SendBytes(bytes) => {
+                    for packet in bytes.chunks(max_packet_len) {
+                        self.transport.send_packet(packet)?;
+                    }
+                }
+                ConnectionAction::DeliverPlaintext(plaintext) => delivered.push(plaintext),
+                ConnectionAction::HandshakeComplete => {}
+                ConnectionAction::NeedsRekey => self.connection.rekey()?,
+            }
+        }
+        Ok(delivered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    
+    type Wire = Arc<Mutex<Vec<Vec<u8>>>>;
+    
+    fn recording(max_packet_len: usize) -> (CallbackTransport, Wire) {
+        let wire: Wire = Arc::default();
+        let sent = wire.clone();
+        let transport = CallbackTransport::new(max_packet_len, move |packet| {
+            sent.lock().unwrap().push(packet.to_vec());
+            Ok(())
+        })
+        .unwrap();
+        (transport, wire)
+    }
+    
+    /// Deliver every packet on `wire` to `to`, returning the messages
+    fn deliver(wire: &Wire, to: &mut PacketLink<CallbackTransport>) -> Vec<Vec<u8>> {
+        let packets = std::mem::take(&mut *wire.lock().unwrap());
+        packets.iter().flat_map(|p| to.receive_packet(p).unwrap()).collect()
+    }
+    
+    #[test]
+    fn test_small_packets_are_reassembled() {
+        let (alice_tx, to_bob) = recording(16);
+        let (bob_tx, to_alice) = recording(16);
+        let mut alice = PacketLink::initiator(NoiseSession::new_initiator().unwrap(), alice_tx).unwrap();
+        let mut bob = PacketLink::responder(NoiseSession::new_responder().unwrap(), bob_tx);
+        
+        // Queued until the handshake completes
+        alice.send(b"early").unwrap();
+        assert!(deliver(&to_bob, &mut bob).is_empty());
+        assert!(deliver(&to_alice, &mut alice).is_empty());
+        assert!(alice.is_established());
+        assert!(to_bob.lock().unwrap().iter().all(|p| p.len() <= 16));
+        assert_eq!(deliver(&to_bob, &mut bob), vec![b"early".to_vec()]);
+        assert!(bob.is_established());
+        
+        bob.send(&[5u8; 1000]).unwrap();
+        bob.send(b"second").unwrap();
+        assert_eq!(deliver(&to_alice, &mut alice), vec![vec![5u8; 1000], b"second".to_vec()]);
+    }
+    
+    #[test]
+    fn test_platform_presets() {
+        let multipeer = CallbackTransport::multipeer(|_| Ok(()));
+        let nearby = CallbackTransport::nearby(|_| Ok(()));
+        assert_eq!(multipeer.max_packet_len(), MULTIPEER_MAX_PACKET_LEN);
+        assert_eq!(nearby.max_packet_len(), NEARBY_MAX_BYTES_LEN);
+        assert!(CallbackTransport::new(0, |_| Ok(())).is_err());
+        
+        // Transport failures surface to the caller
+        let failing = CallbackTransport::nearby(|_| Err(NoiseError::InvalidState("disconnected".to_string())));
+        assert!(PacketLink::initiator(NoiseSession::new_initiator().unwrap(), failing).is_err());
+    }
+}
@@ -0,0 +1,261 @@
+//! Trust-on-first-use peer keys
+//!
+//! [`TrustStore`] pins the first static key seen for each peer ID and flags
+//! any later handshake that presents a different one. Records are kept in
+//! the session slots of a [`KeyStorage`] backend under [`TRUST_ID_PREFIX`].
+
+use crate::core::error::{NoiseError, Result};
+use crate::core::keys::keys_equal;
+use crate::mobile::snapshot::Reader;
+use crate::mobile::storage::KeyStorage;
+use std::sync::{Arc, Mutex};
+
+/// Prefix of the session slot ids holding trust records
+pub const TRUST_ID_PREFIX: &str = "noise.trust.";
+
+/// Trust record format version
+const TRUST_VERSION: u8 = 1;
+
+/// How much the user trusts a peer's pinned key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TrustLevel {
+    /// Pinned on first use but never checked out of band
+    Unverified,
+    /// Confirmed by the user, e.g. by comparing fingerprints
+    Verified,
+    /// The user refuses sessions with this peer
+    Blocked,
+}
+
+impl TrustLevel {
+    fn to_u8(self) -> u8 {
+        match self {
+            TrustLevel::Unverified => 0,
+            TrustLevel::Verified => 1,
+            TrustLevel::Blocked => 2,
+        }
+    }
+    
+    fn from_u8(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(TrustLevel::Unverified),
+            1 => Ok(TrustLevel::Verified),
+            2 => Ok(TrustLevel::Blocked),
+            _ => Err(NoiseError::InvalidMessage),
+        }
+    }
+}
+
+/// The pinned key and trust level of one peer
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TrustRecord {
+    /// Static public key pinned for the peer
+    pub remote_static: Vec<u8>,
+    /// Current trust level
+    pub level: TrustLevel,
+}
+
+impl TrustRecord {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut data = vec![TRUST_VERSION, self.level.to_u8()];
+        data.extend_from_slice(&(self.remote_static.len() as u16).to_be_bytes());
+        data.extend_from_slice(&self.remote_static);
+        data
+    }
+    
+    fn from_bytes(data: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(data);
+        if reader.take(1)?[0] != TRUST_VERSION {
+            return Err(NoiseError::InvalidMessage);
+        }
+        let level = TrustLevel::from_u8(reader.take(1)?[0])?;
+        let len = reader.u16()? as usize;
+        let remote_static = reader.take(len)?.to_vec();
+        if !reader.is_empty() {
+            return Err(NoiseError::InvalidMessage);
+        }
+        Ok(Self { remote_static, level })
+    }
+}
+
+/// Outcome of checking a peer's key against the store
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrustEvent {
+    /// No key was on record; the presented key is now pinned
+    FirstSeen,
+    /// The presented key matches the pinned one
+    Known(TrustLevel),
+    /// The peer presented a different key than the pinned one
+    ///
+    /// The pinned record is left unchanged until the user accepts the new
+    /// key with [`TrustStore::accept_new_key`].
+    KeyChanged {
+        /// The key on record
+        previous: Vec<u8>,
+        /// Trust level of the key on record
+        level: TrustLevel,
+    },
+}
+
+impl TrustEvent {
+    /// Whether a session with the peer may proceed without asking the user
+    pub fn is_trusted(&self) -> bool {
+        matches!(self, TrustEvent::FirstSeen | TrustEvent::Known(TrustLevel::Unverified | TrustLevel::Verified))
+    }
+}
+
+/// Per-peer pinned static keys, persisted through [`KeyStorage`]
+pub struct TrustStore {
+    storage: Arc<dyn KeyStorage>,
+    /// Serializes read-modify-write cycles on records
+    lock: Mutex<()>,
+}
+
+impl TrustStore {
+    /// Create a trust store over `storage`
+    pub fn new(storage: Arc<dyn KeyStorage>) -> Self {
+        Self {
+            storage,
+            lock: Mutex::new(()),
+        }
+    }
+    
+    /// Check the static key a peer presented in a handshake
+    ///
+    /// Pins the key if the peer has no record yet.
+    pub fn check(&self, peer_id: &str, remote_static: &[u8]) -> Result<TrustEvent> {
+        let _guard = self.lock()?;
+        match self.load(peer_id)? {
+            None => {
+                self.store(peer_id, &TrustRecord {
+                    remote_static: remote_static.to_vec(),
+                    level: TrustLevel::Unverified,
+                })?;
+                Ok(TrustEvent::FirstSeen)
+            }
+            Some(record) if keys_equal(&record.remote_static, remote_static) => Ok(TrustEvent::Known(record.level)),
+            Some(record) => Ok(TrustEvent::KeyChanged {
+                previous: record.remote_static,
+                level: record.level,
+            }),
+        }
+    }
+    
+    /// The record for a peer, if any
+    pub fn record(&self, peer_id: &str) -> Result<Option<TrustRecord>> {
+        let _guard = self.lock()?;
+        self.load(peer_id)
+    }
+    
+    /// Mark a peer's pinned key as verified out of band
+    pub fn mark_verified(&self, peer_id: &str) -> Result<()> {
+        self.set_level(peer_id, TrustLevel::Verified)
+    }
+    
+    /// Block a peer; its key stays pinned so it is recognized later
+    pub fn mark_blocked(&self, peer_id: &str) -> Result<()> {
+        self.set_level(peer_id, TrustLevel::Blocked)
+    }
+    
+    /// Replace a peer's pinned key after the user accepted a key change
+    ///
+    /// The new key starts out unverified.
+    pub fn accept_new_key(&self, peer_id: &str, remote_static: &[u8]) -> Result<()> {
+        let _guard = self.lock()?;
+        self.store(peer_id, &TrustRecord {
+            remote_static: remote_static.to_vec(),
+            level: TrustLevel::Unverified,
+        })
+    }
+    
+    /// Delete a peer's record, so its next key is pinned afresh
+    pub fn forget(&self, peer_id: &str) -> Result<()> {
+        let _guard = self.lock()?;
+        self.storage.delete_session(&slot_id(peer_id))
+    }
+    
+    fn set_level(&self, peer_id: &str, level: TrustLevel) -> Result<()> {
+        let _guard = self.lock()?;
+        let mut record = self.load(peer_id)?
+            .ok_or_else(|| NoiseError::InvalidState("No key pinned for peer".to_string()))?;
+        record.level = level;
+        self.store(peer_id, &record)
+    }
+    
+    fn load(&self, peer_id: &str) -> Result<Option<TrustRecord>> {
+        match self.storage.load_session(&slot_id(peer_id)) {
+            Ok(data) => TrustRecord::from_bytes(&data).map(Some),
+            // Backends report a missing slot as an invalid parameter
+            Err(NoiseError::InvalidParameter) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+    
+    fn store(&self, peer_id: &str, record: &TrustRecord) -> Result<()> {
+        self.storage.store_session(&slot_id(peer_id), &record.to_bytes())
+    }
+    
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, ()>> {
+        self.lock.lock().map_err(|_| NoiseError::InvalidState("Lock poisoned".to_string()))
+    }
+}
+
+fn slot_id(peer_id: &str) -> String {
+    format!("{}{}", TRUST_ID_PREFIX, peer_id)
+}
+
+#[cfg(all(test, feature = "storage-backends"))]
+mod tests {
+    use super::*;
+    use crate::mobile::storage::MemoryKeyStorage;
+    
+    #[test]
+    fn test_first_use_then_known() {
+        let store = TrustStore::new(Arc::new(MemoryKeyStorage::new()));
+        
+        assert_eq!(store.check("bob", &[1u8; 32]).unwrap(), TrustEvent::FirstSeen);
+        assert_eq!(store.check("bob", &[1u8; 32]).unwrap(), TrustEvent::Known(TrustLevel::Unverified));
+        
+        store.mark_verified("bob").unwrap();
+        let event = store.check("bob", &[1u8; 32]).unwrap();
+        assert_eq!(event, TrustEvent::Known(TrustLevel::Verified));
+        assert!(event.is_trusted());
+    }
+    
+    #[test]
+    fn test_key_change_is_reported() {
+        let store = TrustStore::new(Arc::new(MemoryKeyStorage::new()));
+        store.check("bob", &[1u8; 32]).unwrap();
+        store.mark_verified("bob").unwrap();
+        
+        let event = store.check("bob", &[2u8; 32]).unwrap();
+        assert_eq!(event, TrustEvent::KeyChanged { previous: vec![1u8; 32], level: TrustLevel::Verified });
+        assert!(!event.is_trusted());
+        // The pinned key is untouched until the change is accepted
+        assert_eq!(store.record("bob").unwrap().unwrap().remote_static, vec![1u8; 32]);
+        
+        store.accept_new_key("bob", &[2u8; 32]).unwrap();
+        assert_eq!(store.check("bob", &[2u8; 32]).unwrap(), TrustEvent::Known(TrustLevel::Unverified));
+    }
+    
+    #[test]
+    fn test_blocked_and_persisted() {
+        let storage = MemoryKeyStorage::new();
+        let store = TrustStore::new(Arc::new(storage.clone()));
+        assert!(store.mark_blocked("bob").is_err());
+        
+        store.check("bob", &[1u8; 32]).unwrap();
+        store.mark_blocked("bob").unwrap();
+        
+        // A second store over the same storage sees the record
+        let reopened = TrustStore::new(Arc::new(storage));
+        let event = reopened.check("bob", &[1u8; 32]).unwrap();
+        assert_eq!(event, TrustEvent::Known(TrustLevel::Blocked));
+        assert!(!event.is_trusted());
+        
+        reopened.forget("bob").unwrap();
+        assert_eq!(reopened.record("bob").unwrap(), None);
+    }
+}
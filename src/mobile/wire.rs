@@ -0,0 +1,233 @@
+//! CBOR wire envelope for frames
+//!
+//! A [`WireEnvelope`] wraps one transport message with what a receiver needs
+//! to route and decrypt it: the frame type, the session ID and the
+//! explicit nonce the payload was encrypted under. The encoding is plain
+//! CBOR (RFC 8949), so a desktop client or server in another language can
+//! read and write it with any CBOR library.
+//!
+//! Schema (CDDL):
+//!
+//! ```text
+//! envelope = [
+//!     type: uint,          ; FrameType: 0 data, 1 ack, 2 keepalive,
+//!                          ; 3 rekey, 4 close, 5 cover
+//!     session_id: bstr .size 8,
+//!     nonce: uint,         ; 0 .. 2^64 - 1
+//!     payload: bstr,       ; Noise transport message sealed under nonce
+//! ]
+//! ```
+//!
+//! Envelopes are written in deterministic encoding: definite lengths and
+//! the shortest form of every integer and length. The decoder accepts only
+//! that form, so each envelope has exactly one valid encoding. A data frame
+//! with session ID `01..08`, nonce 1 and payload `"hi"` encodes as
+//!
+//! ```text
+//! 84 00 48 0102030405060708 01 42 6869
+//! ```
+//!
+//! The envelope itself is not authenticated; the session ID and nonce are
+//! only trusted once the payload decrypts under them.
+
+use crate::core::error::{NoiseError, Result};
+use crate::core::session::SESSION_ID_LEN;
+use crate::mobile::network::FrameType;
+
+/// CBOR major type of unsigned integers
+const MAJOR_UINT: u8 = 0;
+
+/// CBOR major type of byte strings
+const MAJOR_BYTES: u8 = 2;
+
+/// CBOR major type of arrays
+const MAJOR_ARRAY: u8 = 4;
+
+/// Items in an envelope array
+const ENVELOPE_FIELDS: u64 = 4;
+
+/// One frame as sent on the wire
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WireEnvelope {
+    /// What the frame carries
+    pub frame_type: FrameType,
+    /// Session the frame belongs to, e.g. a [`SessionId`](crate::core::session::SessionId)
+    pub session_id: [u8; SESSION_ID_LEN],
+    /// Nonce the payload was encrypted under
+    pub nonce: u64,
+    /// The encrypted frame
+    pub payload: Vec<u8>,
+}
+
+impl WireEnvelope {
+    /// Encode the envelope as CBOR
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut out);
+        out
+    }
+    
+    /// Append the CBOR encoding of the envelope to `out`
+    pub fn encode_into(&self, out: &mut Vec<u8>) {
+        write_head(out, MAJOR_ARRAY, ENVELOPE_FIELDS);
+        write_head(out, MAJOR_UINT, self.frame_type as u64);
+        write_head(out, MAJOR_BYTES, SESSION_ID_LEN as u64);
+        out.extend_from_slice(&self.session_id);
+        write_head(out, MAJOR_UINT, self.nonce);
+        write_head(out, MAJOR_BYTES, self.payload.len() as u64);
+        out.extend_from_slice(&self.payload);
+    }
+    
+    /// Length of [`encode`](Self::encode) output
+    pub fn encoded_len(&self) -> usize {
+        head_len(ENVELOPE_FIELDS)
+            + head_len(self.frame_type as u64)
+            + head_len(SESSION_ID_LEN as u64)
+            + SESSION_ID_LEN
+            + head_len(self.nonce)
+            + head_len(self.payload.len() as u64)
+            + self.payload.len()
+    }
+    
+    /// Decode an envelope, rejecting anything but its deterministic encoding
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let mut cursor = data;
+        if read_head(&mut cursor, MAJOR_ARRAY)? != ENVELOPE_FIELDS {
+            return Err(NoiseError::InvalidMessage);
+        }
+        let frame_type = u8::try_from(read_head(&mut cursor, MAJOR_UINT)?)
+            .map_err(|_| NoiseError::InvalidMessage)
+            .and_then(FrameType::from_u8)?;
+        let session_id = read_bytes(&mut cursor)?.try_into().map_err(|_| NoiseError::InvalidMessage)?;
+        let nonce = read_head(&mut cursor, MAJOR_UINT)?;
+        let payload = read_bytes(&mut cursor)?.to_vec();
+        if !cursor.is_empty() {
+            return Err(NoiseError::InvalidMessage);
+        }
+        Ok(Self { frame_type, session_id, nonce, payload })
+    }
+}
+
+/// Write a CBOR head: major type and argument in its shortest form
+fn write_head(out: &mut Vec<u8>, major: u8, value: u64) {
+    let major = major << 5;
+    match value {
+        0..=23 => out.push(major | value as u8),
+        24..=0xff => out.extend_from_slice(&[major | 24, value as u8]),
+        0x100..=0xffff => {
+            out.push(major | 25);
+            out.extend_from_slice(&(value as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(major | 26);
+            out.extend_from_slice(&(value as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(major | 27);
+            out.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+}
+
+fn head_len(value: u64) -> usize {
+    match value {
+        0..=23 => 1,
+        24..=0xff => 2,
+        0x100..=0xffff => 3,
+        0x1_0000..=0xffff_ffff => 5,
+        _ => 9,
+    }
+}
+
+/// Read a CBOR head of the expected major type, returning its argument
+fn read_head(cursor: &mut &[u8], major: u8) -> Result<u64> {
+    let (&initial, rest) = cursor.split_first().ok_or(NoiseError::InvalidMessage)?;
+    if initial >> 5 != major {
+        return Err(NoiseError::InvalidMessage);
+    }
+    let (len, min) = match initial & 0x1f {
+        info @ 0..=23 => {
+            *cursor = rest;
+            return Ok(info as u64);
+        }
+        24 => (1, 24),
+        25 => (2, 0x100),
+        26 => (4, 0x1_0000),
+        27 => (8, 0x1_0000_0000),
+        // Reserved values and indefinite lengths
+        _ => return Err(NoiseError::InvalidMessage),
+    };
+    let bytes = rest.get(..len).ok_or(NoiseError::InvalidMessage)?;
+    let value = bytes.iter().fold(0u64, |value, &byte| (value << 8) | byte as u64);
+    // A longer form than needed would give the same envelope a second encoding
+    if value < min {
+        return Err(NoiseError::InvalidMessage);
+    }
+    *cursor = &rest[len..];
+    Ok(value)
+}
+
+fn read_bytes<'a>(cursor: &mut &'a [u8]) -> Result<&'a [u8]> {
+    let len = usize::try_from(read_head(cursor, MAJOR_BYTES)?).map_err(|_| NoiseError::InvalidMessage)?;
+    let bytes = cursor.get(..len).ok_or(NoiseError::InvalidMessage)?;
+    *cursor = &cursor[len..];
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn envelope(nonce: u64, payload: &[u8]) -> WireEnvelope {
+        WireEnvelope {
+            frame_type: FrameType::Data,
+            session_id: [1, 2, 3, 4, 5, 6, 7, 8],
+            nonce,
+            payload: payload.to_vec(),
+        }
+    }
+    
+    #[test]
+    fn test_encoding_matches_schema_example() {
+        let encoded = envelope(1, b"hi").encode();
+        assert_eq!(encoded, [0x84, 0x00, 0x48, 1, 2, 3, 4, 5, 6, 7, 8, 0x01, 0x42, b'h', b'i']);
+        assert_eq!(WireEnvelope::decode(&encoded).unwrap(), envelope(1, b"hi"));
+    }
+    
+    #[test]
+    fn test_roundtrip_integer_widths() {
+        for nonce in [0, 23, 24, 0xff, 0x100, 0xffff, 0x1_0000, u32::MAX as u64, u64::MAX] {
+            for len in [0, 23, 24, 300, 70_000] {
+                let original = WireEnvelope { frame_type: FrameType::Cover, ..envelope(nonce, &vec![0xa5; len]) };
+                let encoded = original.encode();
+                assert_eq!(encoded.len(), original.encoded_len());
+                assert_eq!(WireEnvelope::decode(&encoded).unwrap(), original);
+            }
+        }
+    }
+    
+    #[test]
+    fn test_rejects_non_deterministic_encodings() {
+        let encoded = envelope(1, b"hi").encode();
+        assert!(WireEnvelope::decode(&encoded[..encoded.len() - 1]).is_err());
+        assert!(WireEnvelope::decode(&[&encoded[..], &[0]].concat()).is_err());
+        
+        // Nonce 1 written in two bytes instead of one
+        let mut long_form = encoded[..11].to_vec();
+        long_form.extend_from_slice(&[0x18, 0x01, 0x42, b'h', b'i']);
+        assert!(WireEnvelope::decode(&long_form).is_err());
+        
+        // Indefinite-length array
+        let mut indefinite = encoded.clone();
+        indefinite[0] = 0x9f;
+        assert!(WireEnvelope::decode(&indefinite).is_err());
+        
+        // Unknown frame type and short session ID
+        let mut unknown_type = encoded.clone();
+        unknown_type[1] = 0x06;
+        assert!(WireEnvelope::decode(&unknown_type).is_err());
+        let mut short_id = encoded;
+        short_id[2] = 0x47;
+        assert!(WireEnvelope::decode(&short_id).is_err());
+    }
+}
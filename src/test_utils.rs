@@ -0,0 +1,432 @@
+//! Helpers for testing Noise integrations without real sockets
+//!
+//! [`pipe`] returns two connected in-memory endpoints. They carry whole
+//! messages through [`Endpoint::send`] and [`Endpoint::recv`], and also
+//! implement `Read`/`Write` so a blocking
+//! [`NoiseStream`](crate::mobile::blocking::NoiseStream) can run over them.
+//! Wrap an endpoint in a [`LossyChannel`] to drop, duplicate, reorder or
+//! delay what it sends, and use [`perform_handshake`] for a pair of
+//! sessions that are ready to encrypt. Only built with the `test-utils`
+//! feature.
+
+#[cfg(feature = "test-utils")]
+pub use crate::core::test_vectors::{load_vectors, parse_vectors, TestVector, VectorMessage, VectorParty};
+
+use crate::core::error::Result;
+use crate::core::session::NoiseSession;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Messages travelling in one direction of a [`pipe`]
+#[derive(Default)]
+struct Queue {
+    messages: VecDeque<Vec<u8>>,
+    /// The sending endpoint has been dropped
+    closed: bool,
+}
+
+#[derive(Default)]
+struct Direction {
+    queue: Mutex<Queue>,
+    ready: Condvar,
+}
+
+impl Direction {
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// One end of an in-memory [`pipe`]
+pub struct Endpoint {
+    incoming: Arc<Direction>,
+    outgoing: Arc<Direction>,
+    /// Unread bytes of the message `Read` is working through
+    partial: Vec<u8>,
+    partial_pos: usize,
+}
+
+/// Create two connected endpoints
+pub fn pipe() -> (Endpoint, Endpoint) {
+    let a_to_b = Arc::new(Direction::default());
+    let b_to_a = Arc::new(Direction::default());
+    let a = Endpoint {
+        incoming: b_to_a.clone(),
+        outgoing: a_to_b.clone(),
+        partial: Vec::new(),
+        partial_pos: 0,
+    };
+    let b = Endpoint {
+        incoming: a_to_b,
+        outgoing: b_to_a,
+        partial: Vec::new(),
+        partial_pos: 0,
+    };
+    (a, b)
+}
+
+impl Endpoint {
+    /// Queue a message for the peer
+    ///
+    /// Fails with `BrokenPipe` once the peer has been dropped.
+    pub fn send(&self, message: &[u8]) -> io::Result<()> {
+        if Arc::strong_count(&self.outgoing) < 2 {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        self.outgoing.lock().messages.push_back(message.to_vec());
+        self.outgoing.ready.notify_all();
+        Ok(())
+    }
+    
+    /// Take the next message from the peer without waiting
+    pub fn recv(&self) -> Option<Vec<u8>> {
+        self.incoming.lock().messages.pop_front()
+    }
+    
+    /// Wait up to `timeout` for the next message from the peer
+    ///
+    /// Returns `None` on timeout or once the peer is gone and nothing is left.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Vec<u8>> {
+        let deadline = Instant::now() + timeout;
+        let mut queue = self.incoming.lock();
+        loop {
+            if let Some(message) = queue.messages.pop_front() {
+                return Some(message);
+            }
+            let remaining = deadline.checked_duration_since(Instant::now())?;
+            if queue.closed || remaining.is_zero() {
+                return None;
+            }
+            queue = self
+                .incoming
+                .ready
+                .wait_timeout(queue, remaining)
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .0;
+        }
+    }
+    
+    /// Number of messages waiting to be received
+    pub fn pending(&self) -> usize {
+        self.incoming.lock().messages.len()
+    }
+}
+
+impl Read for Endpoint {
+    /// Block until the peer sends something, returning 0 once it is dropped
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.partial_pos == self.partial.len() {
+            let mut queue = self.incoming.lock();
+            loop {
+                if let Some(message) = queue.messages.pop_front() {
+                    self.partial = message;
+                    self.partial_pos = 0;
+                    break;
+                }
+                if queue.closed {
+                    return Ok(0);
+                }
+                queue = self.incoming.ready.wait(queue).unwrap_or_else(|poisoned| poisoned.into_inner());
+            }
+        }
+        
+        let n = buf.len().min(self.partial.len() - self.partial_pos);
+        buf[..n].copy_from_slice(&self.partial[self.partial_pos..self.partial_pos + n]);
+        self.partial_pos += n;
+        Ok(n)
+    }
+}
+
+impl Write for Endpoint {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.send(buf)?;
+        Ok(buf.len())
+    }
+    
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Endpoint {
+    fn drop(&mut self) {
+        self.outgoing.lock().closed = true;
+        self.outgoing.ready.notify_all();
+    }
+}
+
+/// Faults a [`LossyChannel`] applies to outgoing messages
+///
+/// Probabilities are per message, from 0.0 to 1.0. The same seed gives the
+/// same faults for the same sequence of sends.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LossyConfig {
+    /// Chance a message is silently discarded
+    pub drop_probability: f64,
+    /// Chance a message is delivered twice
+    pub duplicate_probability: f64,
+    /// Chance a message is held back and delivered after the next one
+    pub reorder_probability: f64,
+    /// Time every message waits before delivery
+    pub delay: Duration,
+    /// Seed for the fault generator
+    pub seed: u64,
+}
+
+impl Default for LossyConfig {
+    fn default() -> Self {
+        Self {
+            drop_probability: 0.0,
+            duplicate_probability: 0.0,
+            reorder_probability: 0.0,
+            delay: Duration::ZERO,
+            seed: 0x6e_6f69_7365,
+        }
+    }
+}
+
+/// Counts of the faults a [`LossyChannel`] has injected
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LossyStats {
+    /// Messages passed to [`LossyChannel::send`]
+    pub sent: u64,
+    /// Messages discarded
+    pub dropped: u64,
+    /// Extra copies delivered
+    pub duplicated: u64,
+    /// Messages delivered after a later one
+    pub reordered: u64,
+}
+
+/// An [`Endpoint`] whose outgoing messages pass through configurable faults
+///
+/// Delayed and held-back messages are released by later calls to
+/// [`send`](Self::send), [`recv`](Self::recv) or [`flush`](Self::flush).
+/// Not a source of secure randomness; for tests only.
+pub struct LossyChannel {
+    endpoint: Endpoint,
+    config: LossyConfig,
+    rng: u64,
+    /// Message waiting for the next one to overtake it
+    held: Option<Vec<u8>>,
+    delayed: VecDeque<(Instant, Vec<u8>)>,
+    stats: LossyStats,
+}
+
+impl LossyChannel {
+    /// Wrap `endpoint` with the faults in `config`
+    pub fn new(endpoint: Endpoint, config: LossyConfig) -> Self {
+        Self {
+            endpoint,
+            rng: config.seed | 1,
+            config,
+            held: None,
+            delayed: VecDeque::new(),
+            stats: LossyStats::default(),
+        }
+    }
+    
+    /// Send a message through the faults
+    pub fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        self.release_due()?;
+        self.stats.sent += 1;
+        if self.chance(self.config.drop_probability) {
+            self.stats.dropped += 1;
+            return Ok(());
+        }
+        
+        let copies = if self.chance(self.config.duplicate_probability) {
+            self.stats.duplicated += 1;
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            if self.held.is_none() && self.chance(self.config.reorder_probability) {
+                self.held = Some(message.to_vec());
+                continue;
+            }
+            self.deliver(message.to_vec())?;
+            if let Some(held) = self.held.take() {
+                self.stats.reordered += 1;
+                self.deliver(held)?;
+            }
+        }
+        Ok(())
+    }
+    
+    /// Take the next message from the peer without waiting
+    pub fn recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+        self.release_due()?;
+        Ok(self.endpoint.recv())
+    }
+    
+    /// Deliver everything held back or delayed immediately
+    pub fn flush(&mut self) -> io::Result<()> {
+        if let Some(held) = self.held.take() {
+            self.deliver(held)?;
+        }
+        while let Some((_, message)) = self.delayed.pop_front() {
+            self.endpoint.send(&message)?;
+        }
+        Ok(())
+    }
+    
+    /// Faults injected so far
+    pub fn stats(&self) -> LossyStats {
+        self.stats
+    }
+    
+    /// The wrapped endpoint
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+    
+    /// Give up the channel, discarding anything not yet delivered
+    pub fn into_inner(self) -> Endpoint {
+        self.endpoint
+    }
+    
+    fn deliver(&mut self, message: Vec<u8>) -> io::Result<()> {
+        if self.config.delay.is_zero() {
+            return self.endpoint.send(&message);
+        }
+        self.delayed.push_back((Instant::now() + self.config.delay, message));
+        Ok(())
+    }
+    
+    fn release_due(&mut self) -> io::Result<()> {
+        let now = Instant::now();
+        while self.delayed.front().is_some_and(|(due, _)| *due <= now) {
+            if let Some((_, message)) = self.delayed.pop_front() {
+                self.endpoint.send(&message)?;
+            }
+        }
+        Ok(())
+    }
+    
+    /// xorshift64*, enough to spread faults reproducibly
+    fn chance(&mut self, probability: f64) -> bool {
+        if probability <= 0.0 {
+            return false;
+        }
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        let sample = self.rng.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11;
+        (sample as f64 / (1u64 << 53) as f64) < probability
+    }
+}
+
+/// Run an XX handshake between two fresh sessions, returning (initiator, responder)
+pub fn perform_handshake() -> Result<(NoiseSession, NoiseSession)> {
+    let mut initiator = NoiseSession::new_initiator()?;
+    let mut responder = NoiseSession::new_responder()?;
+    
+    // Message 1: initiator -> responder (e)
+    let msg1 = initiator.write_message(&[])?;
+    responder.read_message(&msg1)?;
+    
+    // Message 2: responder -> initiator (e, ee, s, es)
+    let msg2 = responder.write_message(&[])?;
+    initiator.read_message(&msg2)?;
+    
+    // Message 3: initiator -> responder (s, se)
+    let msg3 = initiator.write_message(&[])?;
+    responder.read_message(&msg3)?;
+    
+    Ok((initiator, responder))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mobile::blocking::NoiseStream;
+    use crate::mobile::network::ResilientSession;
+    use std::thread;
+    
+    #[test]
+    fn test_pipe_carries_noise_stream() {
+        let (client, server) = pipe();
+        let responder = thread::spawn(move || {
+            let mut stream = NoiseStream::accept(server, NoiseSession::new_responder().unwrap()).unwrap();
+            let mut buf = [0u8; 5];
+            stream.read_exact(&mut buf).unwrap();
+            stream.write_all(&buf).unwrap();
+        });
+        
+        let mut stream = NoiseStream::connect(client, NoiseSession::new_initiator().unwrap()).unwrap();
+        stream.write_all(b"hello").unwrap();
+        let mut echo = [0u8; 5];
+        stream.read_exact(&mut echo).unwrap();
+        assert_eq!(&echo, b"hello");
+        responder.join().unwrap();
+        
+        // The peer is gone: reads see EOF and sends fail
+        let (mut transport, _) = stream.into_parts();
+        assert_eq!(transport.read(&mut echo).unwrap(), 0);
+        assert!(transport.send(b"late").is_err());
+    }
+    
+    #[test]
+    fn test_lossy_channel_with_resilient_session() {
+        let (mut alice, mut bob) = resilient_pair();
+        let (near, far) = pipe();
+        let config = LossyConfig {
+            drop_probability: 0.1,
+            duplicate_probability: 0.1,
+            reorder_probability: 0.2,
+            seed: 7,
+            ..LossyConfig::default()
+        };
+        let mut channel = LossyChannel::new(near, config);
+        
+        for i in 0..200u32 {
+            channel.send(&alice.encrypt_with_sequence(&i.to_be_bytes()).unwrap()).unwrap();
+        }
+        channel.flush().unwrap();
+        let stats = channel.stats();
+        assert!(stats.dropped > 0 && stats.duplicated > 0 && stats.reordered > 0);
+        
+        let mut delivered = 0u64;
+        let mut replays = 0u64;
+        while let Some(frame) = far.recv() {
+            match bob.decrypt_with_replay_check(&frame) {
+                Ok(_) => delivered += 1,
+                Err(crate::NoiseError::ReplayDetected) => replays += 1,
+                Err(e) => panic!("unexpected error: {e}"),
+            }
+        }
+        assert_eq!(delivered, stats.sent - stats.dropped);
+        assert_eq!(replays, stats.duplicated);
+    }
+    
+    #[test]
+    fn test_lossy_channel_delays_and_is_reproducible() {
+        let (near, far) = pipe();
+        let config = LossyConfig { delay: Duration::from_millis(20), ..LossyConfig::default() };
+        let mut channel = LossyChannel::new(near, config);
+        channel.send(b"later").unwrap();
+        assert_eq!(far.pending(), 0);
+        assert_eq!(far.recv_timeout(Duration::from_millis(5)), None);
+        thread::sleep(Duration::from_millis(25));
+        channel.recv().unwrap();
+        assert_eq!(far.recv().unwrap(), b"later");
+        
+        let faults = |seed| {
+            let (near, _far) = pipe();
+            let config = LossyConfig { drop_probability: 0.5, seed, ..LossyConfig::default() };
+            let mut channel = LossyChannel::new(near, config);
+            (0..64).for_each(|_| channel.send(b"x").unwrap());
+            channel.stats()
+        };
+        assert_eq!(faults(1), faults(1));
+    }
+    
+    fn resilient_pair() -> (ResilientSession, ResilientSession) {
+        let (initiator, responder) = perform_handshake().unwrap();
+        (ResilientSession::with_explicit_nonces(initiator), ResilientSession::with_explicit_nonces(responder))
+    }
+}
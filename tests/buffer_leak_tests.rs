@@ -0,0 +1,100 @@
+//! Leak checks for library-owned `NoiseBuffer`s
+//!
+//! A counting allocator tracks the bytes the test thread has outstanding.
+//! After a warm-up round, which fills per-thread state and handle tables,
+//! every further round of `_alloc` calls must give back all it allocated.
+
+#![cfg(feature = "manager")]
+
+use libc::size_t;
+use noise_mobile::ffi::c_api::*;
+use noise_mobile::ffi::types::NoiseBuffer;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::ptr;
+
+struct CountingAllocator;
+
+thread_local! {
+    static OUTSTANDING: Cell<isize> = const { Cell::new(0) };
+}
+
+fn track(delta: isize) {
+    let _ = OUTSTANDING.try_with(|outstanding| outstanding.set(outstanding.get() + delta));
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        track(layout.size() as isize);
+        System.alloc(layout)
+    }
+    
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        track(-(layout.size() as isize));
+        System.dealloc(ptr, layout)
+    }
+    
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        track(new_size as isize - layout.size() as isize);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn outstanding() -> isize {
+    OUTSTANDING.with(Cell::get)
+}
+
+/// One round of every buffer-returning call, freeing each result
+fn round(alice: u64, bob: u64) {
+    let mut ciphertext = NoiseBuffer::new();
+    let mut plaintext = NoiseBuffer::new();
+    assert_eq!(noise_manager_encrypt_alloc(alice, c"bob".as_ptr(), [7u8; 300].as_ptr(), 300, &mut ciphertext), 0);
+    assert_eq!(noise_manager_decrypt_alloc(bob, c"alice".as_ptr(), ciphertext.data, ciphertext.len, &mut plaintext), 0);
+    
+    // Outputs are write-only, so buffers are freed before reuse
+    noise_buffer_free(&mut ciphertext);
+    noise_buffer_free(&mut plaintext);
+    assert_eq!(noise_manager_serialize_alloc(bob, &mut ciphertext), 0);
+    assert_eq!(noise_buffer_resize(&mut ciphertext, 5000), 0);
+    assert_eq!(noise_buffer_resize(&mut ciphertext, 10), 0);
+    assert_eq!(noise_buffer_alloc(64, &mut plaintext), 0);
+    
+    noise_buffer_free(&mut ciphertext);
+    noise_buffer_free(&mut plaintext);
+}
+
+#[test]
+fn test_alloc_functions_do_not_leak() {
+    let mut error = 0;
+    let alice = noise_manager_new([1u8; 32].as_ptr(), 32, &mut error);
+    let bob = noise_manager_new([2u8; 32].as_ptr(), 32, &mut error);
+    let mut message = [0u8; 256];
+    let mut message_len: size_t = message.len();
+    assert_eq!(noise_manager_connect(alice, c"bob".as_ptr(), message.as_mut_ptr(), &mut message_len), 0);
+    for (to, from) in [(bob, c"alice"), (alice, c"bob"), (bob, c"alice")] {
+        let mut reply = [0u8; 256];
+        let mut reply_len: size_t = reply.len();
+        assert_eq!(noise_manager_handle_handshake(to, from.as_ptr(), message.as_ptr(), message_len, reply.as_mut_ptr(), &mut reply_len), 0);
+        (message, message_len) = (reply, reply_len);
+    }
+    assert_eq!(noise_manager_is_established(bob, c"alice".as_ptr()), 1);
+    
+    round(alice, bob);
+    let before = outstanding();
+    for _ in 0..100 {
+        round(alice, bob);
+    }
+    assert_eq!(outstanding(), before);
+    
+    // Freeing null or already freed buffers allocates and frees nothing
+    let mut empty = NoiseBuffer::new();
+    noise_buffer_free(&mut empty);
+    noise_buffer_free(ptr::null_mut());
+    assert_eq!(outstanding(), before);
+    
+    noise_manager_free(alice);
+    noise_manager_free(bob);
+}
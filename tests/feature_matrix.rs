@@ -0,0 +1,498 @@
+//! Feature matrix smoke tests for noise-mobile-rust
+//!
+//! The `smoke_*` tests run under whatever features this binary was compiled
+//! with, and each optional feature has at least one that calls into it. They
+//! need `test-utils` for their handshakes. The ignored `feature_matrix` test
+//! re-invokes cargo for every combination in the feature powerset (cargo
+//! hack-style) and runs the smoke tests under each one:
+//!
+//! ```bash
+//! cargo test --test feature_matrix --features test-utils -- --ignored
+//! ```
+
+#[cfg(feature = "test-utils")]
+use noise_mobile::core::session::NoiseSession;
+#[cfg(feature = "test-utils")]
+use noise_mobile::mobile::network::ResilientSession;
+#[cfg(feature = "test-utils")]
+use noise_mobile::test_utils::perform_handshake;
+use std::process::Command;
+
+/// Optional features covered by the matrix; `default` is always disabled
+const FEATURES: &[&str] = &[
+    "hardware-crypto",
+    "batching",
+    "file-cipher",
+    "outbox",
+    "storage-backends",
+    "manager",
+    "group",
+    "ratchet",
+    "tickets",
+    "sealed",
+    "identity",
+    "ble",
+    "datagram",
+    "cover",
+    "tokio",
+    "tokio-util",
+    "sqlite",
+    "tracing",
+    "mlock",
+    "parallel",
+    "serde",
+];
+
+/// Enabled in every combination, since the smoke tests build on it
+const BASE_FEATURES: &[&str] = &["test-utils"];
+
+/// Features the matrix leaves out, and why
+const EXCLUDED: &[(&str, &str)] = &[
+    ("generate-header", "its build script rewrites include/noise_mobile.h"),
+    ("sqlcipher", "links against a system libsqlcipher"),
+];
+
+/// Maximum number of features enabled at once (like `cargo hack --depth`)
+const MAX_DEPTH: usize = 2;
+
+/// Every subset of `FEATURES` with at most `MAX_DEPTH` members
+fn feature_powerset() -> Vec<Vec<&'static str>> {
+    let mut sets = Vec::new();
+    for mask in 0u32..(1 << FEATURES.len()) {
+        if mask.count_ones() as usize > MAX_DEPTH {
+            continue;
+        }
+        let set = FEATURES
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| mask & (1 << i) != 0)
+            .map(|(_, f)| *f)
+            .collect();
+        sets.push(set);
+    }
+    sets
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn smoke_session_roundtrip() {
+    let (mut alice, mut bob) = perform_handshake().unwrap();
+
+    let ct = alice.encrypt(b"ping").unwrap();
+    assert_eq!(bob.decrypt(&ct).unwrap(), b"ping");
+
+    let ct = bob.encrypt(b"pong").unwrap();
+    assert_eq!(alice.decrypt(&ct).unwrap(), b"pong");
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn smoke_resilient_roundtrip() {
+    let (alice, bob) = perform_handshake().unwrap();
+    let mut alice = ResilientSession::new(alice);
+    let mut bob = ResilientSession::new(bob);
+
+    let ct = alice.encrypt_with_sequence(b"sequenced").unwrap();
+    assert_eq!(bob.decrypt_with_replay_check(&ct).unwrap(), b"sequenced");
+
+    let restored = ResilientSession::deserialize(&bob.serialize(), NoiseSession::new_responder().unwrap()).unwrap();
+    assert_eq!(restored.receive_sequence(), 1);
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn smoke_test_utils_pipe() {
+    let (alice, bob) = noise_mobile::test_utils::pipe();
+    alice.send(b"over the pipe").unwrap();
+    assert_eq!(bob.recv().unwrap(), b"over the pipe");
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn smoke_has_feature_matches_build() {
+    use noise_mobile::ffi::c_api::noise_has_feature;
+
+    let expected = [
+        (c"hardware-crypto", cfg!(feature = "hardware-crypto")),
+        (c"batching", cfg!(feature = "batching")),
+        (c"file-cipher", cfg!(feature = "file-cipher")),
+        (c"manager", cfg!(feature = "manager")),
+        (c"sealed", cfg!(feature = "sealed")),
+        (c"identity", cfg!(feature = "identity")),
+        (c"sqlite", cfg!(feature = "sqlite")),
+        (c"tracing", cfg!(feature = "tracing")),
+    ];
+    for (name, enabled) in expected {
+        assert_eq!(noise_has_feature(name.as_ptr()), enabled as i32, "{:?}", name);
+    }
+}
+
+#[cfg(all(feature = "test-utils", feature = "batching"))]
+#[test]
+fn smoke_batched_roundtrip() {
+    use noise_mobile::mobile::battery::BatchedCrypto;
+
+    let (alice, mut bob) = perform_handshake().unwrap();
+    let mut batch = BatchedCrypto::new(alice);
+
+    batch.queue_encrypt(b"one".to_vec());
+    batch.queue_encrypt(b"two".to_vec());
+    let results = batch.flush_encrypts().into_iter().collect::<Result<Vec<_>, _>>().unwrap();
+
+    assert_eq!(bob.decrypt(&results[0]).unwrap(), b"one");
+    assert_eq!(bob.decrypt(&results[1]).unwrap(), b"two");
+}
+
+#[cfg(all(feature = "test-utils", feature = "file-cipher"))]
+#[test]
+fn smoke_file_cipher_roundtrip() {
+    use noise_mobile::core::file_cipher::SessionFileCipher;
+
+    let (alice, _) = perform_handshake().unwrap();
+    let cipher = SessionFileCipher::new(&alice).unwrap();
+
+    let mut sealed = Vec::new();
+    cipher.encrypt(&b"file contents"[..], &mut sealed).unwrap();
+    let mut opened = Vec::new();
+    cipher.decrypt(&sealed[..], &mut opened).unwrap();
+    assert_eq!(opened, b"file contents");
+}
+
+#[cfg(all(feature = "test-utils", feature = "storage-backends"))]
+#[test]
+fn smoke_memory_storage_roundtrip() {
+    use noise_mobile::mobile::storage::{KeyStorage, MemoryKeyStorage};
+
+    let storage = MemoryKeyStorage::new();
+    storage.store_session("bob", b"state").unwrap();
+    assert_eq!(storage.load_session("bob").unwrap(), b"state");
+}
+
+#[cfg(all(feature = "test-utils", feature = "outbox", feature = "storage-backends"))]
+#[test]
+fn smoke_outbox_flush() {
+    use noise_mobile::mobile::outbox::Outbox;
+    use noise_mobile::mobile::storage::MemoryKeyStorage;
+    use std::sync::Arc;
+
+    let (mut alice, mut bob) = perform_handshake().unwrap();
+    let outbox = Outbox::new(Arc::new(MemoryKeyStorage::new()));
+    outbox.enqueue("bob", b"while offline").unwrap();
+
+    let ciphertexts = outbox.flush("bob", &mut alice).unwrap();
+    assert_eq!(bob.decrypt(&ciphertexts[0]).unwrap(), b"while offline");
+}
+
+#[cfg(all(feature = "test-utils", feature = "manager"))]
+#[test]
+fn smoke_manager_handshake() {
+    use noise_mobile::core::keys::SecretKey;
+    use noise_mobile::mobile::manager::SessionManager;
+
+    let mut alice = SessionManager::new(&SecretKey::generate());
+    let mut bob = SessionManager::new(&SecretKey::generate());
+
+    let msg1 = alice.connect("bob").unwrap();
+    let msg2 = bob.handle_handshake("alice", &msg1).unwrap().unwrap();
+    let msg3 = alice.handle_handshake("bob", &msg2).unwrap().unwrap();
+    assert_eq!(bob.handle_handshake("alice", &msg3).unwrap(), None);
+
+    let ct = alice.session_mut("bob").unwrap().encrypt(b"managed").unwrap();
+    assert_eq!(bob.session_mut("alice").unwrap().decrypt(&ct).unwrap(), b"managed");
+}
+
+#[cfg(all(feature = "test-utils", feature = "parallel"))]
+#[test]
+fn smoke_parallel_encrypt_many() {
+    use noise_mobile::core::keys::SecretKey;
+    use noise_mobile::mobile::manager::SessionManager;
+    use std::collections::HashMap;
+
+    let mut alice = SessionManager::new(&SecretKey::generate());
+    let mut bob = SessionManager::new(&SecretKey::generate());
+    let msg1 = alice.connect("bob").unwrap();
+    let msg2 = bob.handle_handshake("alice", &msg1).unwrap().unwrap();
+    let msg3 = alice.handle_handshake("bob", &msg2).unwrap().unwrap();
+    bob.handle_handshake("alice", &msg3).unwrap();
+
+    let mut results = alice.encrypt_many(HashMap::from([("bob".to_string(), vec![b"fan".to_vec(), b"out".to_vec()])]));
+    let ciphertexts: Vec<_> = results.remove("bob").unwrap().into_iter().map(Result::unwrap).collect();
+    let mut results = bob.decrypt_many(HashMap::from([("alice".to_string(), ciphertexts)]));
+    let plaintexts: Vec<_> = results.remove("alice").unwrap().into_iter().map(Result::unwrap).collect();
+    assert_eq!(plaintexts, [b"fan".to_vec(), b"out".to_vec()]);
+}
+
+#[cfg(all(feature = "test-utils", feature = "group"))]
+#[test]
+fn smoke_group_roundtrip() {
+    use noise_mobile::mobile::group::GroupSession;
+
+    let (mut alice_to_bob, mut bob_to_alice) = perform_handshake().unwrap();
+    let mut alice = GroupSession::new("friends").unwrap();
+    let mut bob = GroupSession::new("friends").unwrap();
+    alice.add_member("bob");
+    bob.add_member("alice");
+
+    let dist = alice.distribute(&mut alice_to_bob).unwrap();
+    bob.receive_distribution("alice", &mut bob_to_alice, &dist).unwrap();
+    let ct = alice.encrypt(b"to the group").unwrap();
+    assert_eq!(bob.decrypt("alice", &ct).unwrap(), b"to the group");
+}
+
+#[cfg(all(feature = "test-utils", feature = "ratchet"))]
+#[test]
+fn smoke_ratchet_roundtrip() {
+    use noise_mobile::mobile::ratchet::RatchetSession;
+
+    let (alice, bob) = perform_handshake().unwrap();
+    let mut alice = RatchetSession::new(&alice).unwrap();
+    let mut bob = RatchetSession::new(&bob).unwrap();
+
+    let ct = alice.encrypt(b"ratcheted").unwrap();
+    assert_eq!(bob.decrypt(&ct).unwrap(), b"ratcheted");
+}
+
+#[cfg(all(feature = "test-utils", feature = "tickets"))]
+#[test]
+fn smoke_ticket_resumption() {
+    use noise_mobile::core::tickets::{SessionTicket, TicketIssuer};
+
+    let (_, responder) = perform_handshake().unwrap();
+    let mut issuer = TicketIssuer::new();
+    let ticket = SessionTicket::from_bytes(&issuer.issue(&responder).unwrap()).unwrap();
+
+    let (_, message) = ticket.resume(b"resume").unwrap();
+    let (_, payload) = issuer.accept(&message).unwrap();
+    assert_eq!(payload, b"resume");
+}
+
+#[cfg(all(feature = "test-utils", feature = "sealed"))]
+#[test]
+fn smoke_sealed_roundtrip() {
+    use noise_mobile::core::keys::Keypair;
+    use noise_mobile::core::sealed::SealedMessage;
+
+    let recipient = Keypair::generate().unwrap();
+    let blob = SealedMessage::seal(recipient.public_key(), b"sealed").unwrap();
+    assert_eq!(SealedMessage::open(&recipient, &blob).unwrap().plaintext, b"sealed");
+}
+
+#[cfg(all(feature = "test-utils", feature = "identity"))]
+#[test]
+fn smoke_pairing_payload_roundtrip() {
+    use noise_mobile::mobile::pairing::PairingPayload;
+
+    let payload = PairingPayload::new([7u8; 32]);
+    assert_eq!(PairingPayload::decode(&payload.encode().unwrap()).unwrap(), payload);
+}
+
+#[cfg(all(feature = "test-utils", feature = "ble"))]
+#[test]
+fn smoke_gatt_chunking() {
+    use noise_mobile::mobile::ble::{GattAccumulator, GattWriter};
+
+    let message = vec![0x5a; 100];
+    let chunks = GattWriter::new(23).unwrap().write(&message).unwrap();
+    assert!(chunks.len() > 1);
+
+    let mut accumulator = GattAccumulator::new();
+    let reassembled = chunks.iter().filter_map(|chunk| accumulator.push(chunk).unwrap()).next();
+    assert_eq!(reassembled.unwrap(), message);
+}
+
+#[cfg(all(feature = "test-utils", feature = "datagram"))]
+#[test]
+fn smoke_datagram_roundtrip() {
+    use noise_mobile::mobile::datagram::DatagramSession;
+
+    let (alice, bob) = perform_handshake().unwrap();
+    let mut alice = DatagramSession::new(alice).unwrap();
+    let mut bob = DatagramSession::new(bob).unwrap();
+
+    let datagram = alice.encrypt(b"unordered").unwrap();
+    assert_eq!(bob.decrypt(&datagram).unwrap(), b"unordered");
+}
+
+#[cfg(all(feature = "test-utils", feature = "cover"))]
+#[test]
+fn smoke_cover_frame() {
+    use noise_mobile::mobile::cover::{CoverTraffic, CoverTrafficConfig};
+    use noise_mobile::mobile::network::Frame;
+    use std::time::Instant;
+
+    let (alice, bob) = perform_handshake().unwrap();
+    let mut alice = ResilientSession::new(alice);
+    let mut bob = ResilientSession::new(bob);
+    let mut cover = CoverTraffic::new(CoverTrafficConfig::default());
+
+    cover.start(Instant::now());
+    let deadline = cover.next_timeout().unwrap();
+    let dummy = cover.on_timeout(&mut alice, deadline).unwrap().unwrap();
+    assert_eq!(bob.receive_frame(&dummy).unwrap(), Frame::Cover);
+}
+
+#[cfg(all(feature = "test-utils", feature = "tokio"))]
+#[tokio::test]
+async fn smoke_stream_roundtrip() {
+    use noise_mobile::mobile::stream::NoiseStream;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let (client, server) = tokio::io::duplex(4096);
+    let (mut alice, mut bob) = tokio::try_join!(
+        NoiseStream::connect(client, NoiseSession::new_initiator().unwrap()),
+        NoiseStream::accept(server, NoiseSession::new_responder().unwrap()),
+    )
+    .unwrap();
+
+    alice.write_all(b"streamed").await.unwrap();
+    alice.flush().await.unwrap();
+    let mut buf = [0u8; 8];
+    bob.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"streamed");
+}
+
+#[cfg(all(feature = "test-utils", feature = "tokio-util"))]
+#[test]
+fn smoke_codec_roundtrip() {
+    use bytes::BytesMut;
+    use noise_mobile::mobile::codec::NoiseMessageCodec;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    let (alice, bob) = perform_handshake().unwrap();
+    let mut alice = NoiseMessageCodec::new(alice).unwrap();
+    let mut bob = NoiseMessageCodec::new(bob).unwrap();
+
+    let mut wire = BytesMut::new();
+    alice.encode(&b"framed"[..], &mut wire).unwrap();
+    assert_eq!(bob.decode(&mut wire).unwrap(), Some(b"framed".to_vec()));
+}
+
+#[cfg(all(feature = "test-utils", feature = "sqlite"))]
+#[test]
+fn smoke_sqlite_storage_roundtrip() {
+    use noise_mobile::mobile::sqlite::SqliteKeyStorage;
+    use noise_mobile::mobile::storage::KeyStorage;
+
+    let storage = SqliteKeyStorage::open_in_memory(&[3u8; 32]).unwrap();
+    storage.store_session("bob", b"state").unwrap();
+    assert_eq!(storage.load_session("bob").unwrap(), b"state");
+}
+
+#[cfg(all(feature = "test-utils", feature = "tracing"))]
+#[test]
+fn smoke_tracing_events() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tracing::span;
+
+    /// Counts the events the library emits
+    struct EventCounter(Arc<AtomicUsize>);
+
+    impl tracing::Subscriber for EventCounter {
+        fn enabled(&self, _metadata: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, _span: &span::Attributes<'_>) -> span::Id {
+            span::Id::from_u64(1)
+        }
+
+        fn record(&self, _span: &span::Id, _values: &span::Record<'_>) {}
+
+        fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+
+        fn event(&self, event: &tracing::Event<'_>) {
+            if event.metadata().target() == "noise_mobile" {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        fn enter(&self, _span: &span::Id) {}
+
+        fn exit(&self, _span: &span::Id) {}
+    }
+
+    let events = Arc::new(AtomicUsize::new(0));
+    tracing::subscriber::with_default(EventCounter(events.clone()), || perform_handshake().unwrap());
+    assert!(events.load(Ordering::Relaxed) > 0);
+}
+
+#[cfg(all(feature = "test-utils", feature = "mlock"))]
+#[test]
+fn smoke_locked_secret() {
+    use noise_mobile::core::secret::SecretBytes;
+
+    // Locking is best effort, so only the contents are checked
+    let secret = SecretBytes::new(&[9u8; 32]);
+    let _ = secret.is_locked();
+    assert_eq!(&secret[..], &[9u8; 32]);
+}
+
+#[cfg(all(feature = "test-utils", feature = "serde"))]
+#[test]
+fn smoke_serde_roundtrip() {
+    use noise_mobile::mobile::network::ResilientSessionSeed;
+    use serde::de::DeserializeSeed;
+
+    let (alice, bob) = perform_handshake().unwrap();
+    let mut alice = ResilientSession::new(alice);
+    let mut bob = ResilientSession::new(bob);
+    bob.decrypt_with_replay_check(&alice.encrypt_with_sequence(b"counted").unwrap()).unwrap();
+
+    let json = serde_json::to_string(&bob).unwrap();
+    let mut deserializer = serde_json::Deserializer::from_str(&json);
+    let restored = ResilientSessionSeed(NoiseSession::new_responder().unwrap()).deserialize(&mut deserializer).unwrap();
+    assert_eq!(restored.receive_sequence(), 1);
+}
+
+#[test]
+fn powerset_includes_empty_and_respects_depth() {
+    let sets = feature_powerset();
+    assert!(sets.iter().any(|s| s.is_empty()));
+    assert!(sets.iter().all(|s| s.len() <= MAX_DEPTH));
+}
+
+#[test]
+fn features_cover_manifest() {
+    let manifest = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml")).unwrap();
+    let section = manifest.split("[features]").nth(1).unwrap();
+    let declared: Vec<&str> = section
+        .lines()
+        .take_while(|line| !line.starts_with('['))
+        .filter_map(|line| line.split_once(" = ").map(|(name, _)| name.trim()))
+        .filter(|name| !name.starts_with('#') && *name != "default")
+        .collect();
+    let missing: Vec<_> = declared
+        .iter()
+        .filter(|name| !FEATURES.contains(name) && !BASE_FEATURES.contains(name))
+        .filter(|name| !EXCLUDED.iter().any(|(excluded, _)| excluded == *name))
+        .collect();
+    assert!(missing.is_empty(), "features missing from the matrix: {:?}", missing);
+}
+
+#[test]
+#[ignore = "re-invokes cargo for every feature combination; run with --ignored"]
+fn feature_matrix() {
+    let cargo = env!("CARGO");
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    let target_dir = format!("{}/target/feature-matrix", manifest_dir);
+
+    let mut failures = Vec::new();
+    for set in feature_powerset() {
+        let features = BASE_FEATURES.iter().chain(&set).copied().collect::<Vec<_>>().join(",");
+        let status = Command::new(cargo)
+            .current_dir(manifest_dir)
+            .args(["test", "--test", "feature_matrix", "--no-default-features"])
+            .args(["--features", &features])
+            .args(["--target-dir", &target_dir])
+            .args(["--", "smoke_"])
+            .status()
+            .expect("failed to spawn cargo");
+
+        if !status.success() {
+            failures.push(if set.is_empty() { "<none>".to_string() } else { set.join(",") });
+        }
+    }
+
+    assert!(failures.is_empty(), "feature combinations failed: {:?}", failures);
+}
@@ -3,7 +3,7 @@
 //! These tests verify that the C API handles all edge cases safely without
 //! crashes, undefined behavior, or memory leaks.
 
-use noise_mobile::ffi::types::NoiseErrorCode;
+use noise_mobile::ffi::types::{NoiseErrorCode, NoiseKeyStorageVTable};
 use noise_mobile::ffi::c_api::*;
 use std::ptr;
 use libc::{c_int, size_t};
@@ -23,6 +23,10 @@ const NOISE_ERROR_DECRYPTION_FAILED: c_int = 5;
 const NOISE_ERROR_BUFFER_TOO_SMALL: c_int = 6;
 const NOISE_ERROR_INVALID_STATE: c_int = 7;
 const NOISE_ERROR_PROTOCOL_ERROR: c_int = 8;
+const NOISE_ERROR_NONCE_EXHAUSTED: c_int = 9;
+const NOISE_ERROR_INTERNAL: c_int = 10;
+const NOISE_ERROR_PEER_KEY_MISMATCH: c_int = 11;
+const NOISE_ERROR_INVALID_KEY: c_int = 12;
 
 const NOISE_MODE_INITIATOR: c_int = 0;
 const NOISE_MODE_RESPONDER: c_int = 1;
@@ -59,10 +63,21 @@ fn test_double_free_protection() {
         // Free it once
         noise_session_free(session);
         
-        // NOTE: We cannot test double-free without causing undefined behavior
-        // The implementation correctly checks for null, but after free,
-        // the pointer is dangling and using it is UB.
-        // This is a limitation of the C API design.
+        // Sessions are registry handles, so a second free is a no-op and
+        // use after free is rejected instead of being undefined behavior
+        noise_session_free(session);
+        let mut out = [0u8; 128];
+        let mut out_len = out.len() as size_t;
+        assert_eq!(
+            noise_write_message(session, ptr::null(), 0, out.as_mut_ptr(), &mut out_len),
+            NOISE_ERROR_INVALID_PARAMETER
+        );
+        assert_eq!(noise_is_handshake_complete(session), 0);
+        
+        // A new session never picks up the freed handle
+        let next = noise_session_new(NOISE_MODE_INITIATOR, &mut error);
+        assert_ne!(next, session);
+        noise_session_free(next);
     }
 }
 
@@ -328,7 +343,7 @@ fn test_very_large_buffer_sizes() {
 fn test_session_new_with_key_null_handling() {
     unsafe {
         let mut error = 0;
-        let valid_key = [0u8; 32];
+        let valid_key = [1u8; 32];
         
         // Test with null key pointer
         let session = noise_session_new_with_key(
@@ -360,6 +375,16 @@ fn test_session_new_with_key_null_handling() {
         assert_eq!(error, NOISE_ERROR_INVALID_PARAMETER);
         assert!(session.is_null());
         
+        // An all-zero key is refused with its own code
+        let session = noise_session_new_with_key(
+            [0u8; 32].as_ptr(),
+            32,
+            NOISE_MODE_INITIATOR,
+            &mut error
+        );
+        assert_eq!(error, NOISE_ERROR_INVALID_KEY);
+        assert!(session.is_null());
+        
         // Test with valid parameters
         let session = noise_session_new_with_key(
             valid_key.as_ptr(),
@@ -377,7 +402,7 @@ fn test_session_new_with_key_null_handling() {
 fn test_error_string_function() {
     unsafe {
         // Test all error codes return valid strings
-        for code in 0..=8 {
+        for code in 0..=NOISE_ERROR_INVALID_KEY {
             let str_ptr = noise_error_string(code);
             assert!(!str_ptr.is_null());
             let c_str = std::ffi::CStr::from_ptr(str_ptr);
@@ -394,6 +419,36 @@ fn test_error_string_function() {
     }
 }
 
+#[test]
+fn test_last_error_details() {
+    unsafe {
+        let mut error = 0;
+        let session = noise_session_new(NOISE_MODE_INITIATOR, &mut error);
+        
+        // Encrypting mid-handshake fails with a reason beyond the code
+        let plaintext = b"too early";
+        let mut out = [0u8; 128];
+        let mut out_len = out.len() as size_t;
+        let result = noise_encrypt(session, plaintext.as_ptr(), plaintext.len(), out.as_mut_ptr(), &mut out_len);
+        assert_eq!(result, NOISE_ERROR_INVALID_STATE);
+        assert_eq!(noise_last_error_code(), NOISE_ERROR_INVALID_STATE);
+        let message = std::ffi::CStr::from_ptr(noise_last_error_message()).to_str().unwrap();
+        assert!(message.starts_with("Invalid state: "));
+        
+        // A successful call clears it
+        let mut remaining: size_t = 0;
+        assert_eq!(noise_round_trips_remaining(session, &mut remaining), NOISE_ERROR_SUCCESS);
+        assert_eq!(noise_last_error_code(), NOISE_ERROR_SUCCESS);
+        assert!(noise_last_error_message().is_null());
+        
+        // Parameter checks are recorded too
+        noise_session_new(99, &mut error);
+        assert_eq!(noise_last_error_code(), NOISE_ERROR_INVALID_PARAMETER);
+        
+        noise_session_free(session);
+    }
+}
+
 #[test]
 fn test_operations_in_wrong_state() {
     unsafe {
@@ -473,6 +528,61 @@ fn test_concurrent_session_creation() {
     assert_eq!(session_count.load(Ordering::SeqCst), 1000);
 }
 
+#[test]
+fn test_concurrent_encrypt_on_one_session() {
+    use std::thread;
+    
+    unsafe {
+        let mut error = 0;
+        let initiator = noise_session_new(NOISE_MODE_INITIATOR, &mut error);
+        let responder = noise_session_new(NOISE_MODE_RESPONDER, &mut error);
+        
+        let mut buffer = vec![0u8; 1024];
+        let mut payload = vec![0u8; 1024];
+        for (from, to) in [(initiator, responder), (responder, initiator), (initiator, responder)] {
+            let mut len = buffer.len() as size_t;
+            assert_eq!(noise_write_message(from, ptr::null(), 0, buffer.as_mut_ptr(), &mut len), NOISE_ERROR_SUCCESS);
+            let mut payload_len = payload.len() as size_t;
+            assert_eq!(
+                noise_read_message(to, buffer.as_ptr(), len, payload.as_mut_ptr(), &mut payload_len),
+                NOISE_ERROR_SUCCESS
+            );
+        }
+        
+        let mut before = 0u64;
+        assert_eq!(noise_remaining_nonce_budget(initiator, &mut before), NOISE_ERROR_SUCCESS);
+        
+        // Handles are plain values, so they can be shared across threads
+        let handle = initiator as usize;
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                thread::spawn(move || {
+                    let session = handle as *mut noise_mobile::ffi::types::NoiseSessionFFI;
+                    let mut ct = vec![0u8; 64];
+                    for _ in 0..100 {
+                        let mut ct_len = ct.len() as size_t;
+                        assert_eq!(
+                            noise_encrypt(session, b"race".as_ptr(), 4, ct.as_mut_ptr(), &mut ct_len),
+                            NOISE_ERROR_SUCCESS
+                        );
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        
+        // Every encryption consumed its own nonce
+        let mut after = 0u64;
+        assert_eq!(noise_remaining_nonce_budget(initiator, &mut after), NOISE_ERROR_SUCCESS);
+        assert_eq!(before - after, 800);
+        
+        noise_session_free(initiator);
+        noise_session_free(responder);
+    }
+}
+
 #[test]
 fn test_malformed_encrypted_data() {
     unsafe {
@@ -550,4 +660,1620 @@ fn test_malformed_encrypted_data() {
         noise_session_free(initiator);
         noise_session_free(responder);
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_remaining_nonce_budget() {
+    unsafe {
+        let mut remaining: u64 = 0;
+        assert_eq!(
+            noise_remaining_nonce_budget(ptr::null_mut(), &mut remaining),
+            NOISE_ERROR_INVALID_PARAMETER
+        );
+        
+        let mut error = 0;
+        let session = noise_session_new(NOISE_MODE_INITIATOR, &mut error);
+        assert_eq!(error, NOISE_ERROR_SUCCESS);
+        
+        assert_eq!(
+            noise_remaining_nonce_budget(session, ptr::null_mut()),
+            NOISE_ERROR_INVALID_PARAMETER
+        );
+        assert_eq!(noise_remaining_nonce_budget(session, &mut remaining), NOISE_ERROR_SUCCESS);
+        assert_eq!(remaining, noise_mobile::core::crypto::NOISE_NONCE_LIMIT);
+        
+        noise_session_free(session);
+    }
+}
+
+#[test]
+fn test_round_trips_remaining() {
+    unsafe {
+        let mut remaining: size_t = 0;
+        assert_eq!(
+            noise_round_trips_remaining(ptr::null_mut(), &mut remaining),
+            NOISE_ERROR_INVALID_PARAMETER
+        );
+        
+        let mut error = 0;
+        let session = noise_session_new(NOISE_MODE_INITIATOR, &mut error);
+        assert_eq!(noise_round_trips_remaining(session, &mut remaining), NOISE_ERROR_SUCCESS);
+        assert_eq!(remaining, 2);
+        
+        noise_session_free(session);
+    }
+}
+
+#[test]
+fn test_session_new_with_binding() {
+    unsafe {
+        let key = [7u8; 32];
+        let binding = b"link-42";
+        let mut error = 0;
+        
+        let session = noise_session_new_with_binding(
+            key.as_ptr(), key.len(), NOISE_MODE_INITIATOR,
+            ptr::null(), 4, &mut error
+        );
+        assert!(session.is_null());
+        assert_eq!(error, NOISE_ERROR_INVALID_PARAMETER);
+        
+        let initiator = noise_session_new_with_binding(
+            key.as_ptr(), key.len(), NOISE_MODE_INITIATOR,
+            binding.as_ptr(), binding.len(), &mut error
+        );
+        assert_eq!(error, NOISE_ERROR_SUCCESS);
+        let responder = noise_session_new_with_binding(
+            key.as_ptr(), key.len(), NOISE_MODE_RESPONDER,
+            b"link-43".as_ptr(), 7, &mut error
+        );
+        assert_eq!(error, NOISE_ERROR_SUCCESS);
+        
+        let mut msg = vec![0u8; 1024];
+        let mut payload = vec![0u8; 1024];
+        let mut msg_len = msg.len() as size_t;
+        let mut payload_len = payload.len() as size_t;
+        
+        assert_eq!(noise_write_message(initiator, ptr::null(), 0, msg.as_mut_ptr(), &mut msg_len), NOISE_ERROR_SUCCESS);
+        assert_eq!(noise_read_message(responder, msg.as_ptr(), msg_len, payload.as_mut_ptr(), &mut payload_len), NOISE_ERROR_SUCCESS);
+        
+        msg_len = msg.len() as size_t;
+        payload_len = payload.len() as size_t;
+        assert_eq!(noise_write_message(responder, ptr::null(), 0, msg.as_mut_ptr(), &mut msg_len), NOISE_ERROR_SUCCESS);
+        // Mismatched bindings surface when the initiator reads the responder's reply
+        assert_ne!(noise_read_message(initiator, msg.as_ptr(), msg_len, payload.as_mut_ptr(), &mut payload_len), NOISE_ERROR_SUCCESS);
+        
+        noise_session_free(initiator);
+        noise_session_free(responder);
+    }
+}
+
+#[test]
+fn test_rekey_and_generations() {
+    unsafe {
+        let mut sending: u32 = 0;
+        let mut receiving: u32 = 0;
+        assert_eq!(noise_rekey_outgoing(ptr::null_mut()), NOISE_ERROR_INVALID_PARAMETER);
+        assert_eq!(
+            noise_key_generations(ptr::null_mut(), &mut sending, &mut receiving),
+            NOISE_ERROR_INVALID_PARAMETER
+        );
+        
+        let mut error = 0;
+        let session = noise_session_new(NOISE_MODE_INITIATOR, &mut error);
+        
+        // Rekeying needs transport keys
+        assert_eq!(noise_rekey_outgoing(session), NOISE_ERROR_INVALID_STATE);
+        assert_eq!(noise_rekey_incoming(session), NOISE_ERROR_INVALID_STATE);
+        assert_eq!(noise_key_generations(session, &mut sending, &mut receiving), NOISE_ERROR_SUCCESS);
+        assert_eq!((sending, receiving), (0, 0));
+        
+        noise_session_free(session);
+    }
+}
+
+#[test]
+fn test_supported_protocol_enumeration() {
+    unsafe {
+        let count = noise_supported_protocol_count();
+        assert!(count >= 1);
+        
+        let mut name = vec![0u8; 128];
+        let mut len = name.len() as size_t;
+        assert_eq!(
+            noise_supported_protocol_name(0, name.as_mut_ptr() as *mut libc::c_char, &mut len),
+            NOISE_ERROR_SUCCESS
+        );
+        let name = std::ffi::CStr::from_bytes_with_nul(&name[..len]).unwrap();
+        assert_eq!(name.to_str().unwrap(), "Noise_XX_25519_ChaChaPoly_BLAKE2s");
+        
+        // Size query with a short buffer
+        let mut len: size_t = 4;
+        let mut short = [0u8; 4];
+        assert_eq!(
+            noise_supported_protocol_name(0, short.as_mut_ptr() as *mut libc::c_char, &mut len),
+            NOISE_ERROR_BUFFER_TOO_SMALL
+        );
+        assert_eq!(len, name.to_bytes_with_nul().len());
+        
+        assert_eq!(
+            noise_supported_protocol_name(count, ptr::null_mut(), &mut len),
+            NOISE_ERROR_INVALID_PARAMETER
+        );
+    }
+}
+
+#[test]
+fn test_session_split_halves() {
+    unsafe {
+        let mut error = 0;
+        let initiator = noise_session_new(NOISE_MODE_INITIATOR, &mut error);
+        let responder = noise_session_new(NOISE_MODE_RESPONDER, &mut error);
+        
+        let mut send = ptr::null_mut();
+        let mut recv = ptr::null_mut();
+        assert_eq!(noise_session_split(initiator, &mut send, &mut recv), NOISE_ERROR_INVALID_STATE);
+        
+        let mut buffer = vec![0u8; 1024];
+        let mut payload = vec![0u8; 1024];
+        for (from, to) in [(initiator, responder), (responder, initiator), (initiator, responder)] {
+            let mut len = buffer.len() as size_t;
+            assert_eq!(noise_write_message(from, ptr::null(), 0, buffer.as_mut_ptr(), &mut len), NOISE_ERROR_SUCCESS);
+            let mut payload_len = payload.len() as size_t;
+            assert_eq!(
+                noise_read_message(to, buffer.as_ptr(), len, payload.as_mut_ptr(), &mut payload_len),
+                NOISE_ERROR_SUCCESS
+            );
+        }
+        
+        assert_eq!(noise_session_split(initiator, &mut send, &mut recv), NOISE_ERROR_SUCCESS);
+        assert_eq!(noise_is_handshake_complete(initiator), 0);
+        
+        let mut ct = vec![0u8; 64];
+        let mut ct_len = ct.len() as size_t;
+        assert_eq!(noise_send_half_encrypt(send, b"out".as_ptr(), 3, ct.as_mut_ptr(), &mut ct_len), NOISE_ERROR_SUCCESS);
+        let mut pt = vec![0u8; 64];
+        let mut pt_len = pt.len() as size_t;
+        assert_eq!(noise_decrypt(responder, ct.as_ptr(), ct_len, pt.as_mut_ptr(), &mut pt_len), NOISE_ERROR_SUCCESS);
+        assert_eq!(&pt[..pt_len], b"out");
+        
+        let mut ct_len = ct.len() as size_t;
+        assert_eq!(noise_encrypt(responder, b"in".as_ptr(), 2, ct.as_mut_ptr(), &mut ct_len), NOISE_ERROR_SUCCESS);
+        let mut pt_len = pt.len() as size_t;
+        assert_eq!(noise_recv_half_decrypt(recv, ct.as_ptr(), ct_len, pt.as_mut_ptr(), &mut pt_len), NOISE_ERROR_SUCCESS);
+        assert_eq!(&pt[..pt_len], b"in");
+        
+        noise_send_half_free(send);
+        noise_recv_half_free(recv);
+        let mut ct_len = ct.len() as size_t;
+        assert_eq!(
+            noise_send_half_encrypt(send, b"x".as_ptr(), 1, ct.as_mut_ptr(), &mut ct_len),
+            NOISE_ERROR_INVALID_PARAMETER
+        );
+        noise_session_free(responder);
+    }
+}
+
+/// Run a handshake between two managers through the C API
+#[cfg(feature = "manager")]
+fn manager_handshake(alice: u64, bob: u64) {
+    let mut msg = vec![0u8; 1024];
+    let mut reply = vec![0u8; 1024];
+    
+    let mut len = msg.len() as size_t;
+    assert_eq!(noise_manager_connect(alice, c"bob".as_ptr(), msg.as_mut_ptr(), &mut len), NOISE_ERROR_SUCCESS);
+    let mut reply_len = reply.len() as size_t;
+    assert_eq!(
+        noise_manager_handle_handshake(bob, c"alice".as_ptr(), msg.as_ptr(), len, reply.as_mut_ptr(), &mut reply_len),
+        NOISE_ERROR_SUCCESS
+    );
+    let mut len = msg.len() as size_t;
+    assert_eq!(
+        noise_manager_handle_handshake(alice, c"bob".as_ptr(), reply.as_ptr(), reply_len, msg.as_mut_ptr(), &mut len),
+        NOISE_ERROR_SUCCESS
+    );
+    let mut reply_len = reply.len() as size_t;
+    assert_eq!(
+        noise_manager_handle_handshake(bob, c"alice".as_ptr(), msg.as_ptr(), len, reply.as_mut_ptr(), &mut reply_len),
+        NOISE_ERROR_SUCCESS
+    );
+    assert_eq!(reply_len, 0);
+}
+
+#[cfg(feature = "manager")]
+#[test]
+fn test_manager_handles() {
+    let mut error: c_int = -1;
+    let alice = noise_manager_new([1u8; 32].as_ptr(), 32, &mut error);
+    assert_eq!(error, NOISE_ERROR_SUCCESS);
+    let bob = noise_manager_new([2u8; 32].as_ptr(), 32, &mut error);
+    assert_ne!(alice, bob);
+    
+    // A prepared handshake is not counted as a peer until it is used
+    assert_eq!(noise_manager_prewarm(alice, c"bob".as_ptr()), NOISE_ERROR_SUCCESS);
+    assert_eq!(noise_manager_peer_count(alice), 0);
+    manager_handshake(alice, bob);
+    assert_eq!(noise_manager_is_established(alice, c"bob".as_ptr()), 1);
+    assert_eq!(noise_manager_peer_count(bob), 1);
+    
+    let mut ct = vec![0u8; 128];
+    let mut ct_len = ct.len() as size_t;
+    assert_eq!(
+        noise_manager_encrypt(alice, c"bob".as_ptr(), b"hi".as_ptr(), 2, ct.as_mut_ptr(), &mut ct_len),
+        NOISE_ERROR_SUCCESS
+    );
+    
+    // Serialize, free and restore bob; the restored handle decrypts
+    let mut state_len: size_t = 0;
+    assert_eq!(noise_manager_serialize(bob, ptr::null_mut(), &mut state_len), NOISE_ERROR_BUFFER_TOO_SMALL);
+    let mut state = vec![0u8; state_len];
+    assert_eq!(noise_manager_serialize(bob, state.as_mut_ptr(), &mut state_len), NOISE_ERROR_SUCCESS);
+    noise_manager_free(bob);
+    assert_eq!(noise_manager_peer_count(bob), 0);
+    
+    let bob = noise_manager_restore([2u8; 32].as_ptr(), 32, state.as_ptr(), state_len, &mut error);
+    assert_eq!(error, NOISE_ERROR_SUCCESS);
+    let mut pt = vec![0u8; 128];
+    let mut pt_len = pt.len() as size_t;
+    assert_eq!(
+        noise_manager_decrypt(bob, c"alice".as_ptr(), ct.as_ptr(), ct_len, pt.as_mut_ptr(), &mut pt_len),
+        NOISE_ERROR_SUCCESS
+    );
+    assert_eq!(&pt[..pt_len], b"hi");
+    
+    assert_eq!(noise_manager_remove(bob, c"alice".as_ptr()), NOISE_ERROR_SUCCESS);
+    assert_eq!(noise_manager_is_established(bob, c"alice".as_ptr()), 0);
+    
+    noise_manager_free(alice);
+    noise_manager_free(bob);
+}
+
+#[cfg(feature = "manager")]
+#[test]
+fn test_manager_invalid_handles() {
+    let mut error: c_int = -1;
+    assert_eq!(noise_manager_new(ptr::null(), 32, &mut error), 0);
+    assert_eq!(error, NOISE_ERROR_INVALID_PARAMETER);
+    
+    let manager = noise_manager_new([1u8; 32].as_ptr(), 32, &mut error);
+    noise_manager_free(manager);
+    noise_manager_free(manager);
+    
+    let mut len: size_t = 64;
+    let mut out = [0u8; 64];
+    assert_eq!(
+        noise_manager_connect(manager, c"bob".as_ptr(), out.as_mut_ptr(), &mut len),
+        NOISE_ERROR_INVALID_PARAMETER
+    );
+    assert_eq!(noise_manager_connect(0, ptr::null(), out.as_mut_ptr(), &mut len), NOISE_ERROR_INVALID_PARAMETER);
+}
+
+#[cfg(feature = "manager")]
+#[test]
+fn test_session_and_manager_stats() {
+    use noise_mobile::ffi::types::NoiseSessionStats;
+    
+    let mut error: c_int = -1;
+    let alice = noise_manager_new([1u8; 32].as_ptr(), 32, &mut error);
+    let bob = noise_manager_new([2u8; 32].as_ptr(), 32, &mut error);
+    manager_handshake(alice, bob);
+    let mut ct = vec![0u8; 128];
+    let mut ct_len = ct.len() as size_t;
+    assert_eq!(
+        noise_manager_encrypt(alice, c"bob".as_ptr(), b"hi".as_ptr(), 2, ct.as_mut_ptr(), &mut ct_len),
+        NOISE_ERROR_SUCCESS
+    );
+    
+    let mut stats = NoiseSessionStats::default();
+    assert_eq!(noise_manager_stats(alice, &mut stats), NOISE_ERROR_SUCCESS);
+    assert_eq!((stats.messages_sent, stats.bytes_sent as usize), (1, ct_len));
+    assert_eq!(noise_manager_peer_stats(alice, c"bob".as_ptr(), &mut stats), NOISE_ERROR_SUCCESS);
+    assert_eq!(stats.messages_sent, 1);
+    assert_eq!(noise_manager_peer_stats(alice, c"carol".as_ptr(), &mut stats), NOISE_ERROR_INVALID_PARAMETER);
+    assert_eq!(noise_manager_stats(alice, ptr::null_mut()), NOISE_ERROR_INVALID_PARAMETER);
+    noise_manager_free(alice);
+    assert_eq!(noise_manager_stats(alice, &mut stats), NOISE_ERROR_INVALID_PARAMETER);
+    noise_manager_free(bob);
+    
+    let session = noise_session_new(NOISE_MODE_INITIATOR, &mut error);
+    assert_eq!(noise_session_stats(session, &mut stats), NOISE_ERROR_SUCCESS);
+    assert_eq!(stats, NoiseSessionStats::default());
+    assert_eq!(noise_session_stats(session, ptr::null_mut()), NOISE_ERROR_INVALID_PARAMETER);
+    noise_session_free(session);
+    assert_eq!(noise_session_stats(session, &mut stats), NOISE_ERROR_INVALID_PARAMETER);
+}
+
+#[test]
+fn test_session_id() {
+    let mut error: c_int = -1;
+    let initiator = noise_session_new(NOISE_MODE_INITIATOR, &mut error);
+    let responder = noise_session_new(NOISE_MODE_RESPONDER, &mut error);
+    
+    let mut id = [0u8; 16];
+    let mut id_len = id.len() as size_t;
+    assert_eq!(noise_session_id(initiator, id.as_mut_ptr(), &mut id_len), NOISE_ERROR_INVALID_STATE);
+    assert_eq!(id_len, 0);
+    
+    let mut buffer = vec![0u8; 1024];
+    let mut payload = vec![0u8; 1024];
+    for (from, to) in [(initiator, responder), (responder, initiator), (initiator, responder)] {
+        let mut len = buffer.len() as size_t;
+        assert_eq!(noise_write_message(from, ptr::null(), 0, buffer.as_mut_ptr(), &mut len), NOISE_ERROR_SUCCESS);
+        let mut payload_len = payload.len() as size_t;
+        assert_eq!(
+            noise_read_message(to, buffer.as_ptr(), len, payload.as_mut_ptr(), &mut payload_len),
+            NOISE_ERROR_SUCCESS
+        );
+    }
+    id_len = id.len() as size_t;
+    assert_eq!(noise_session_id(initiator, id.as_mut_ptr(), &mut id_len), NOISE_ERROR_SUCCESS);
+    assert_eq!(id_len, 8);
+    let mut peer_id = [0u8; 8];
+    let mut peer_id_len = peer_id.len() as size_t;
+    assert_eq!(noise_session_id(responder, peer_id.as_mut_ptr(), &mut peer_id_len), NOISE_ERROR_SUCCESS);
+    assert_eq!(id[..8], peer_id);
+    
+    let mut short_len: size_t = 4;
+    assert_eq!(noise_session_id(initiator, id.as_mut_ptr(), &mut short_len), NOISE_ERROR_BUFFER_TOO_SMALL);
+    assert_eq!(noise_session_id(initiator, id.as_mut_ptr(), ptr::null_mut()), NOISE_ERROR_INVALID_PARAMETER);
+    
+    noise_session_free(initiator);
+    noise_session_free(responder);
+}
+
+#[test]
+fn test_is_my_turn_to_send() {
+    let mut error: c_int = -1;
+    let initiator = noise_session_new(NOISE_MODE_INITIATOR, &mut error);
+    let responder = noise_session_new(NOISE_MODE_RESPONDER, &mut error);
+    
+    let mut buffer = vec![0u8; 1024];
+    let mut payload = vec![0u8; 1024];
+    while noise_is_handshake_complete(initiator) == 0 || noise_is_handshake_complete(responder) == 0 {
+        let (from, to) = if noise_is_my_turn_to_send(initiator) == 1 {
+            (initiator, responder)
+        } else {
+            (responder, initiator)
+        };
+        assert_eq!(noise_is_my_turn_to_send(to), 0);
+        let mut len = buffer.len() as size_t;
+        assert_eq!(noise_write_message(from, ptr::null(), 0, buffer.as_mut_ptr(), &mut len), NOISE_ERROR_SUCCESS);
+        let mut payload_len = payload.len() as size_t;
+        assert_eq!(
+            noise_read_message(to, buffer.as_ptr(), len, payload.as_mut_ptr(), &mut payload_len),
+            NOISE_ERROR_SUCCESS
+        );
+    }
+    assert_eq!((noise_is_my_turn_to_send(initiator), noise_is_my_turn_to_send(responder)), (1, 1));
+    
+    noise_session_free(initiator);
+    noise_session_free(responder);
+    assert_eq!(noise_is_my_turn_to_send(initiator), 0);
+}
+
+#[test]
+fn test_sizing_introspection() {
+    assert_eq!(noise_tag_len(), 16);
+    assert_eq!(noise_max_payload_len() + noise_tag_len(), noise_max_message_len());
+    
+    assert_eq!(noise_handshake_message_count(c"XX".as_ptr()), 3);
+    assert_eq!(noise_handshake_message_count(c"Noise_IK_25519_ChaChaPoly_BLAKE2s".as_ptr()), 2);
+    assert_eq!(noise_handshake_message_count(c"N".as_ptr()), 1);
+    assert_eq!(noise_handshake_message_count(c"nonsense".as_ptr()), 0);
+    assert_eq!(noise_handshake_message_count(ptr::null()), 0);
+    
+    let lens: Vec<size_t> = (0..4).map(|i| noise_handshake_message_max_len(c"XX".as_ptr(), i)).collect();
+    assert_eq!(lens, vec![32, 96, 64, 0]);
+    
+    // A real handshake with payloads matches the prediction
+    let mut error: c_int = -1;
+    let initiator = noise_session_new(NOISE_MODE_INITIATOR, &mut error);
+    let responder = noise_session_new(NOISE_MODE_RESPONDER, &mut error);
+    let mut buffer = vec![0u8; 1024];
+    let mut payload = vec![0u8; 1024];
+    for (index, (from, to)) in [(initiator, responder), (responder, initiator), (initiator, responder)].into_iter().enumerate() {
+        let mut len = buffer.len() as size_t;
+        assert_eq!(noise_write_message(from, b"hello".as_ptr(), 5, buffer.as_mut_ptr(), &mut len), NOISE_ERROR_SUCCESS);
+        assert_eq!(len, lens[index] + 5);
+        let mut payload_len = payload.len() as size_t;
+        assert_eq!(
+            noise_read_message(to, buffer.as_ptr(), len, payload.as_mut_ptr(), &mut payload_len),
+            NOISE_ERROR_SUCCESS
+        );
+    }
+    noise_session_free(initiator);
+    noise_session_free(responder);
+}
+
+#[test]
+fn test_session_new_with_pattern() {
+    use noise_mobile::ffi::types::{NoiseMode, NoisePattern};
+    
+    let mut responder_private = [0u8; 32];
+    let mut responder_public = [0u8; 32];
+    let (mut private_len, mut public_len) = (32 as size_t, 32 as size_t);
+    assert_eq!(
+        noise_generate_keypair(responder_private.as_mut_ptr(), &mut private_len, responder_public.as_mut_ptr(), &mut public_len),
+        NOISE_ERROR_SUCCESS
+    );
+    
+    // IK completes in one round trip with the responder's key known up front
+    let mut error: c_int = -1;
+    let initiator = noise_session_new_with_pattern(
+        NoiseMode::Initiator as c_int, NoisePattern::Ik as c_int, ptr::null(), 0, responder_public.as_ptr(), 32, &mut error,
+    );
+    assert_eq!(error, NOISE_ERROR_SUCCESS);
+    let responder = noise_session_new_with_pattern(
+        NoiseMode::Responder as c_int, NoisePattern::Ik as c_int, responder_private.as_ptr(), 32, ptr::null(), 0, &mut error,
+    );
+    assert_eq!(error, NOISE_ERROR_SUCCESS);
+    
+    let mut buffer = vec![0u8; 1024];
+    let mut payload = vec![0u8; 1024];
+    for (from, to) in [(initiator, responder), (responder, initiator)] {
+        let mut len = buffer.len() as size_t;
+        assert_eq!(noise_write_message(from, ptr::null(), 0, buffer.as_mut_ptr(), &mut len), NOISE_ERROR_SUCCESS);
+        let mut payload_len = payload.len() as size_t;
+        assert_eq!(
+            noise_read_message(to, buffer.as_ptr(), len, payload.as_mut_ptr(), &mut payload_len),
+            NOISE_ERROR_SUCCESS
+        );
+    }
+    assert_eq!(noise_is_handshake_complete(initiator), 1);
+    assert_eq!(noise_is_handshake_complete(responder), 1);
+    noise_session_free(initiator);
+    noise_session_free(responder);
+    
+    // IK initiators need the remote key and nothing else takes one
+    let session = noise_session_new_with_pattern(NoiseMode::Initiator as c_int, NoisePattern::Ik as c_int, ptr::null(), 0, ptr::null(), 0, &mut error);
+    assert!(session.is_null());
+    assert_eq!(error, NOISE_ERROR_INVALID_PARAMETER);
+    let session = noise_session_new_with_pattern(
+        NoiseMode::Initiator as c_int, NoisePattern::Xx as c_int, ptr::null(), 0, responder_public.as_ptr(), 32, &mut error,
+    );
+    assert!(session.is_null());
+    assert_eq!(error, NOISE_ERROR_INVALID_PARAMETER);
+    
+    let session = noise_session_new_with_pattern(NoiseMode::Responder as c_int, NoisePattern::Xx as c_int, ptr::null(), 0, ptr::null(), 0, &mut error);
+    assert_eq!(error, NOISE_ERROR_SUCCESS);
+    noise_session_free(session);
+    
+    // Out-of-range modes and patterns are rejected rather than trusted
+    let session = noise_session_new_with_pattern(2, NoisePattern::Xx as c_int, ptr::null(), 0, ptr::null(), 0, &mut error);
+    assert!(session.is_null());
+    assert_eq!(error, NOISE_ERROR_INVALID_PARAMETER);
+    let session = noise_session_new_with_pattern(NoiseMode::Responder as c_int, 7, ptr::null(), 0, ptr::null(), 0, &mut error);
+    assert!(session.is_null());
+    assert_eq!(error, NOISE_ERROR_INVALID_PARAMETER);
+}
+
+#[test]
+fn test_session_from_config() {
+    use noise_mobile::ffi::types::{NoiseMode, NoisePattern};
+    
+    let mut responder_private = [0u8; 32];
+    let mut responder_public = [0u8; 32];
+    let (mut private_len, mut public_len) = (32 as size_t, 32 as size_t);
+    assert_eq!(
+        noise_generate_keypair(responder_private.as_mut_ptr(), &mut private_len, responder_public.as_mut_ptr(), &mut public_len),
+        NOISE_ERROR_SUCCESS
+    );
+    let psk = [5u8; 32];
+    let prologue = b"transport binding";
+    
+    let initiator_config = noise_config_new();
+    assert_eq!(noise_config_set_pattern(initiator_config, NoisePattern::Ik as c_int), NOISE_ERROR_SUCCESS);
+    assert_eq!(noise_config_set_remote_key(initiator_config, responder_public.as_ptr(), 32), NOISE_ERROR_SUCCESS);
+    assert_eq!(noise_config_set_psk(initiator_config, psk.as_ptr(), 32), NOISE_ERROR_SUCCESS);
+    assert_eq!(noise_config_set_prologue(initiator_config, prologue.as_ptr(), prologue.len()), NOISE_ERROR_SUCCESS);
+    let responder_config = noise_config_new();
+    assert_eq!(noise_config_set_pattern(responder_config, NoisePattern::Ik as c_int), NOISE_ERROR_SUCCESS);
+    assert_eq!(noise_config_set_local_key(responder_config, responder_private.as_ptr(), 32), NOISE_ERROR_SUCCESS);
+    assert_eq!(noise_config_set_psk(responder_config, psk.as_ptr(), 32), NOISE_ERROR_SUCCESS);
+    assert_eq!(noise_config_set_prologue(responder_config, prologue.as_ptr(), prologue.len()), NOISE_ERROR_SUCCESS);
+    
+    // A config can be reused; the second handshake runs with a wrong PSK,
+    // which IKpsk2 mixes in at the end of message 2
+    let mut buffer = vec![0u8; 1024];
+    let mut payload = vec![0u8; 1024];
+    for attempt in 0..2 {
+        if attempt == 1 {
+            assert_eq!(noise_config_set_psk(responder_config, [6u8; 32].as_ptr(), 32), NOISE_ERROR_SUCCESS);
+        }
+        let mut error: c_int = -1;
+        let initiator = noise_session_from_config(initiator_config, NoiseMode::Initiator as c_int, &mut error);
+        assert_eq!(error, NOISE_ERROR_SUCCESS);
+        let responder = noise_session_from_config(responder_config, NoiseMode::Responder as c_int, &mut error);
+        assert_eq!(error, NOISE_ERROR_SUCCESS);
+        
+        let mut results = Vec::new();
+        for (from, to) in [(initiator, responder), (responder, initiator)] {
+            let mut len = buffer.len() as size_t;
+            assert_eq!(noise_write_message(from, ptr::null(), 0, buffer.as_mut_ptr(), &mut len), NOISE_ERROR_SUCCESS);
+            let mut payload_len = payload.len() as size_t;
+            results.push(noise_read_message(to, buffer.as_ptr(), len, payload.as_mut_ptr(), &mut payload_len));
+        }
+        if attempt == 0 {
+            assert_eq!(results, [NOISE_ERROR_SUCCESS, NOISE_ERROR_SUCCESS]);
+            assert_eq!(noise_is_handshake_complete(initiator), 1);
+        } else {
+            assert_ne!(results[1], NOISE_ERROR_SUCCESS);
+            assert_eq!(noise_is_handshake_complete(initiator), 0);
+        }
+        noise_session_free(initiator);
+        noise_session_free(responder);
+    }
+    
+    // Malformed settings are rejected as they are set
+    assert_eq!(noise_config_set_psk(initiator_config, psk.as_ptr(), 16), NOISE_ERROR_INVALID_PARAMETER);
+    assert_eq!(noise_config_set_remote_key(initiator_config, responder_public.as_ptr(), 31), NOISE_ERROR_INVALID_PARAMETER);
+    assert_eq!(noise_config_set_local_key(initiator_config, [1u8; 32].as_ptr(), 16), NOISE_ERROR_INVALID_PARAMETER);
+    assert_eq!(noise_config_set_pattern(initiator_config, 2), NOISE_ERROR_INVALID_PARAMETER);
+    let mut error: c_int = -1;
+    let session = noise_session_from_config(initiator_config, -1, &mut error);
+    assert!(session.is_null());
+    assert_eq!(error, NOISE_ERROR_INVALID_PARAMETER);
+    
+    // An IK initiator without the responder's key cannot start
+    assert_eq!(noise_config_set_remote_key(initiator_config, ptr::null(), 0), NOISE_ERROR_SUCCESS);
+    let session = noise_session_from_config(initiator_config, NoiseMode::Initiator as c_int, &mut error);
+    assert!(session.is_null());
+    assert_ne!(error, NOISE_ERROR_SUCCESS);
+    
+    noise_config_free(initiator_config);
+    noise_config_free(responder_config);
+    assert_eq!(noise_config_set_pattern(initiator_config, NoisePattern::Xx as c_int), NOISE_ERROR_INVALID_PARAMETER);
+    let session = noise_session_from_config(initiator_config, NoiseMode::Initiator as c_int, &mut error);
+    assert!(session.is_null());
+    assert_eq!(error, NOISE_ERROR_INVALID_PARAMETER);
+}
+
+static RELEASED: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+unsafe extern "C" fn storage_store(
+    _context: *mut libc::c_void,
+    _kind: c_int,
+    _id: *const libc::c_char,
+    _data: *const u8,
+    _data_len: size_t,
+) -> c_int {
+    NOISE_ERROR_INVALID_STATE
+}
+
+/// Holds a single identity, "device", with a fixed key
+unsafe extern "C" fn storage_load(
+    _context: *mut libc::c_void,
+    kind: c_int,
+    id: *const libc::c_char,
+    output: *mut u8,
+    output_len: *mut size_t,
+) -> c_int {
+    if kind != NOISE_STORAGE_IDENTITY || std::ffi::CStr::from_ptr(id) != c"device" {
+        return NOISE_ERROR_INVALID_PARAMETER;
+    }
+    if *output_len < 32 {
+        *output_len = 32;
+        return NOISE_ERROR_BUFFER_TOO_SMALL;
+    }
+    ptr::write_bytes(output, 5, 32);
+    *output_len = 32;
+    NOISE_ERROR_SUCCESS
+}
+
+unsafe extern "C" fn storage_delete(_context: *mut libc::c_void, _kind: c_int, _id: *const libc::c_char) -> c_int {
+    NOISE_ERROR_SUCCESS
+}
+
+unsafe extern "C" fn storage_release(_context: *mut libc::c_void) {
+    RELEASED.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+}
+
+#[test]
+fn test_key_storage_vtable() {
+    let mut vtable = NoiseKeyStorageVTable {
+        context: ptr::null_mut(),
+        store: Some(storage_store),
+        load: Some(storage_load),
+        delete: Some(storage_delete),
+        list: None,
+        release: Some(storage_release),
+    };
+    let mut error: c_int = -1;
+    
+    // Sessions can't be created from storage until some is installed
+    assert!(noise_session_new_with_identity(c"device".as_ptr(), NOISE_MODE_INITIATOR, &mut error).is_null());
+    assert_eq!(error, NOISE_ERROR_INVALID_STATE);
+    
+    assert_eq!(noise_set_key_storage(&vtable), NOISE_ERROR_SUCCESS);
+    let session = noise_session_new_with_identity(c"device".as_ptr(), NOISE_MODE_INITIATOR, &mut error);
+    assert_eq!(error, NOISE_ERROR_SUCCESS);
+    assert!(!session.is_null());
+    noise_session_free(session);
+    
+    assert!(noise_session_new_with_identity(c"other".as_ptr(), NOISE_MODE_INITIATOR, &mut error).is_null());
+    assert_eq!(error, NOISE_ERROR_INVALID_PARAMETER);
+    
+    // A vtable missing a required callback is rejected and not released
+    vtable.load = None;
+    assert_eq!(noise_set_key_storage(&vtable), NOISE_ERROR_INVALID_PARAMETER);
+    assert_eq!(RELEASED.load(std::sync::atomic::Ordering::SeqCst), 0);
+    
+    assert_eq!(noise_set_key_storage(ptr::null()), NOISE_ERROR_SUCCESS);
+    assert_eq!(RELEASED.load(std::sync::atomic::Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_generate_keypair() {
+    let mut private = [0u8; 32];
+    let mut public = [0u8; 32];
+    let mut private_len: size_t = 32;
+    let mut public_len: size_t = 16;
+    
+    // Too small for the public key: nothing is written
+    assert_eq!(
+        noise_generate_keypair(private.as_mut_ptr(), &mut private_len, public.as_mut_ptr(), &mut public_len),
+        NOISE_ERROR_BUFFER_TOO_SMALL
+    );
+    assert_eq!((private_len, public_len), (32, 32));
+    assert_eq!(private, [0u8; 32]);
+    
+    assert_eq!(
+        noise_generate_keypair(private.as_mut_ptr(), &mut private_len, public.as_mut_ptr(), &mut public_len),
+        NOISE_ERROR_SUCCESS
+    );
+    
+    let mut derived = [0u8; 32];
+    let mut derived_len: size_t = 32;
+    assert_eq!(
+        noise_public_key_from_private(private.as_ptr(), 32, derived.as_mut_ptr(), &mut derived_len),
+        NOISE_ERROR_SUCCESS
+    );
+    assert_eq!(derived, public);
+    assert_eq!(
+        noise_public_key_from_private(private.as_ptr(), 31, derived.as_mut_ptr(), &mut derived_len),
+        NOISE_ERROR_INVALID_PARAMETER
+    );
+}
+
+#[test]
+fn test_get_local_static() {
+    let mut private = [0u8; 32];
+    let mut public = [0u8; 32];
+    let (mut private_len, mut public_len): (size_t, size_t) = (32, 32);
+    noise_generate_keypair(private.as_mut_ptr(), &mut private_len, public.as_mut_ptr(), &mut public_len);
+    
+    let mut error = 0;
+    let session = noise_session_new_with_key(private.as_ptr(), 32, NOISE_MODE_RESPONDER, &mut error);
+    assert_eq!(error, NOISE_ERROR_SUCCESS);
+    
+    let mut local = [0u8; 32];
+    let mut local_len: size_t = 16;
+    assert_eq!(noise_get_local_static(session, local.as_mut_ptr(), &mut local_len), NOISE_ERROR_BUFFER_TOO_SMALL);
+    assert_eq!(local_len, 32);
+    assert_eq!(noise_get_local_static(session, local.as_mut_ptr(), &mut local_len), NOISE_ERROR_SUCCESS);
+    assert_eq!(local, public);
+    
+    noise_session_free(session);
+    assert_eq!(noise_get_local_static(session, local.as_mut_ptr(), &mut local_len), NOISE_ERROR_INVALID_PARAMETER);
+}
+
+#[test]
+fn test_expected_remote_static() {
+    let mut error = 0;
+    let alice_key = [1u8; 32];
+    let bob_key = [2u8; 32];
+    let mut bob_public = [0u8; 32];
+    let mut bob_public_len: size_t = 32;
+    noise_public_key_from_private(bob_key.as_ptr(), 32, bob_public.as_mut_ptr(), &mut bob_public_len);
+    
+    // Alice pinned a different key than the one Bob presents
+    let mut wrong = bob_public;
+    wrong[0] ^= 1;
+    let alice = noise_session_new_with_expected_remote(alice_key.as_ptr(), 32, NOISE_MODE_INITIATOR, wrong.as_ptr(), 32, &mut error);
+    assert_eq!(error, NOISE_ERROR_SUCCESS);
+    let bob = noise_session_new_with_key(bob_key.as_ptr(), 32, NOISE_MODE_RESPONDER, &mut error);
+    
+    let mut msg = [0u8; 256];
+    let mut msg_len: size_t = msg.len();
+    let mut payload = [0u8; 256];
+    let mut payload_len: size_t = payload.len();
+    assert_eq!(noise_write_message(alice, ptr::null(), 0, msg.as_mut_ptr(), &mut msg_len), NOISE_ERROR_SUCCESS);
+    assert_eq!(noise_read_message(bob, msg.as_ptr(), msg_len, payload.as_mut_ptr(), &mut payload_len), NOISE_ERROR_SUCCESS);
+    msg_len = msg.len();
+    assert_eq!(noise_write_message(bob, ptr::null(), 0, msg.as_mut_ptr(), &mut msg_len), NOISE_ERROR_SUCCESS);
+    payload_len = payload.len();
+    assert_eq!(
+        noise_read_message(alice, msg.as_ptr(), msg_len, payload.as_mut_ptr(), &mut payload_len),
+        NOISE_ERROR_PEER_KEY_MISMATCH
+    );
+    assert_eq!(noise_is_handshake_complete(alice), 0);
+    
+    assert!(noise_session_new_with_expected_remote(alice_key.as_ptr(), 32, NOISE_MODE_INITIATOR, wrong.as_ptr(), 31, &mut error).is_null());
+    assert_eq!(error, NOISE_ERROR_INVALID_PARAMETER);
+    
+    noise_session_free(alice);
+    noise_session_free(bob);
+}
+
+#[test]
+fn test_safety_number_and_sas() {
+    let mut error = 0;
+    let alice = noise_session_new(NOISE_MODE_INITIATOR, &mut error);
+    let bob = noise_session_new(NOISE_MODE_RESPONDER, &mut error);
+    
+    let mut output = [0 as libc::c_char; 128];
+    let mut output_len: size_t = output.len();
+    assert_eq!(noise_emoji_sas(alice, output.as_mut_ptr(), &mut output_len), NOISE_ERROR_INVALID_STATE);
+    
+    let mut buffer = [0u8; 256];
+    let mut payload = [0u8; 256];
+    for (from, to) in [(alice, bob), (bob, alice), (alice, bob)] {
+        let mut len: size_t = buffer.len();
+        assert_eq!(noise_write_message(from, ptr::null(), 0, buffer.as_mut_ptr(), &mut len), NOISE_ERROR_SUCCESS);
+        let mut payload_len: size_t = payload.len();
+        assert_eq!(noise_read_message(to, buffer.as_ptr(), len, payload.as_mut_ptr(), &mut payload_len), NOISE_ERROR_SUCCESS);
+    }
+    
+    let read = |session, local: &[u8], remote: &[u8]| {
+        let local = std::ffi::CString::new(local).unwrap();
+        let remote = std::ffi::CString::new(remote).unwrap();
+        let mut output = [0 as libc::c_char; 128];
+        let mut output_len: size_t = output.len();
+        assert_eq!(
+            noise_safety_number(session, local.as_ptr(), remote.as_ptr(), output.as_mut_ptr(), &mut output_len),
+            NOISE_ERROR_SUCCESS
+        );
+        // 12 groups of 5 digits, 11 spaces and the NUL
+        assert_eq!(output_len, 72);
+        unsafe { std::ffi::CStr::from_ptr(output.as_ptr()) }.to_str().unwrap().to_string()
+    };
+    assert_eq!(read(alice, b"alice", b"bob"), read(bob, b"bob", b"alice"));
+    
+    let mut small = [0 as libc::c_char; 8];
+    let mut small_len: size_t = small.len();
+    assert_eq!(
+        noise_safety_number(alice, ptr::null(), ptr::null(), small.as_mut_ptr(), &mut small_len),
+        NOISE_ERROR_BUFFER_TOO_SMALL
+    );
+    assert_eq!(small_len, 72);
+    
+    let sas = |session| {
+        let mut output = [0 as libc::c_char; 128];
+        let mut output_len: size_t = output.len();
+        assert_eq!(noise_emoji_sas(session, output.as_mut_ptr(), &mut output_len), NOISE_ERROR_SUCCESS);
+        unsafe { std::ffi::CStr::from_ptr(output.as_ptr()) }.to_str().unwrap().to_string()
+    };
+    assert_eq!(sas(alice), sas(bob));
+    assert_eq!(sas(alice).split(' ').count(), 7);
+    
+    noise_session_free(alice);
+    noise_session_free(bob);
+}
+
+#[test]
+fn test_export_key() {
+    let mut error = 0;
+    let alice = noise_session_new(NOISE_MODE_INITIATOR, &mut error);
+    let bob = noise_session_new(NOISE_MODE_RESPONDER, &mut error);
+    let label = std::ffi::CString::new("db").unwrap();
+    
+    let mut key = [0u8; 32];
+    assert_eq!(
+        noise_export_key(alice, label.as_ptr(), ptr::null(), 0, key.as_mut_ptr(), key.len()),
+        NOISE_ERROR_INVALID_STATE
+    );
+    
+    let mut buffer = [0u8; 256];
+    let mut payload = [0u8; 256];
+    for (from, to) in [(alice, bob), (bob, alice), (alice, bob)] {
+        let mut len: size_t = buffer.len();
+        assert_eq!(noise_write_message(from, ptr::null(), 0, buffer.as_mut_ptr(), &mut len), NOISE_ERROR_SUCCESS);
+        let mut payload_len: size_t = payload.len();
+        assert_eq!(noise_read_message(to, buffer.as_ptr(), len, payload.as_mut_ptr(), &mut payload_len), NOISE_ERROR_SUCCESS);
+    }
+    
+    let export = |session, context: &[u8]| {
+        let mut key = [0u8; 32];
+        assert_eq!(
+            noise_export_key(session, label.as_ptr(), context.as_ptr(), context.len(), key.as_mut_ptr(), key.len()),
+            NOISE_ERROR_SUCCESS
+        );
+        key
+    };
+    assert_eq!(export(alice, b"user-1"), export(bob, b"user-1"));
+    assert_ne!(export(alice, b"user-1"), export(alice, b""));
+    assert_eq!(
+        noise_export_key(alice, ptr::null(), ptr::null(), 0, key.as_mut_ptr(), key.len()),
+        NOISE_ERROR_INVALID_PARAMETER
+    );
+    
+    noise_session_free(alice);
+    noise_session_free(bob);
+}
+
+#[test]
+fn test_key_fingerprint() {
+    let key = [0xabu8; 32];
+    let mut output = [0 as libc::c_char; 128];
+    let mut output_len: size_t = output.len();
+    assert_eq!(
+        noise_key_fingerprint(key.as_ptr(), key.len(), NOISE_FINGERPRINT_HEX, output.as_mut_ptr(), &mut output_len),
+        NOISE_ERROR_SUCCESS
+    );
+    let text = unsafe { std::ffi::CStr::from_ptr(output.as_ptr()) }.to_str().unwrap();
+    assert!(text.starts_with("abab abab "));
+    assert_eq!(output_len, text.len() + 1);
+    
+    output_len = output.len();
+    assert_eq!(
+        noise_key_fingerprint(key.as_ptr(), key.len(), NOISE_FINGERPRINT_SHA256, output.as_mut_ptr(), &mut output_len),
+        NOISE_ERROR_SUCCESS
+    );
+    // 16 digest bytes as 8 groups of 4 hex characters
+    assert_eq!(output_len, 40);
+    
+    output_len = output.len();
+    assert_eq!(
+        noise_key_fingerprint(key.as_ptr(), key.len(), 7, output.as_mut_ptr(), &mut output_len),
+        NOISE_ERROR_INVALID_PARAMETER
+    );
+}
+
+#[cfg(feature = "identity")]
+#[test]
+fn test_pairing_payload() {
+    let static_key = [7u8; 32];
+    let psk = [9u8; 32];
+    let hints = [std::ffi::CString::new("ble:AA:BB").unwrap(), std::ffi::CString::new("tcp:192.0.2.1:7000").unwrap()];
+    let hint_ptrs: Vec<*const libc::c_char> = hints.iter().map(|h| h.as_ptr()).collect();
+    
+    let mut payload = [0u8; 256];
+    let mut payload_len: size_t = payload.len();
+    assert_eq!(
+        noise_pairing_payload_encode(
+            static_key.as_ptr(), 32, psk.as_ptr(), 32,
+            hint_ptrs.as_ptr(), hint_ptrs.len(),
+            payload.as_mut_ptr(), &mut payload_len
+        ),
+        NOISE_ERROR_SUCCESS
+    );
+    
+    let mut key_out = [0u8; 32];
+    let mut key_len: size_t = 32;
+    let mut psk_out = [0u8; 32];
+    let mut psk_len: size_t = 32;
+    let mut hints_out = [0 as libc::c_char; 8];
+    let mut hints_len: size_t = hints_out.len();
+    assert_eq!(
+        noise_pairing_payload_decode(
+            payload.as_ptr(), payload_len,
+            key_out.as_mut_ptr(), &mut key_len,
+            psk_out.as_mut_ptr(), &mut psk_len,
+            hints_out.as_mut_ptr(), &mut hints_len
+        ),
+        NOISE_ERROR_BUFFER_TOO_SMALL
+    );
+    // Nothing was written, and every length holds the size needed
+    assert_eq!(key_out, [0u8; 32]);
+    assert_eq!((key_len, psk_len, hints_len), (32, 32, 29));
+    
+    let mut hints_out = [0 as libc::c_char; 64];
+    hints_len = hints_out.len();
+    assert_eq!(
+        noise_pairing_payload_decode(
+            payload.as_ptr(), payload_len,
+            key_out.as_mut_ptr(), &mut key_len,
+            psk_out.as_mut_ptr(), &mut psk_len,
+            hints_out.as_mut_ptr(), &mut hints_len
+        ),
+        NOISE_ERROR_SUCCESS
+    );
+    assert_eq!((key_out, psk_out), (static_key, psk));
+    let hints_bytes: Vec<u8> = hints_out[..hints_len].iter().map(|&c| c as u8).collect();
+    assert_eq!(hints_bytes, b"ble:AA:BB\0tcp:192.0.2.1:7000\0");
+    
+    // Without a PSK or hints
+    payload_len = payload.len();
+    assert_eq!(
+        noise_pairing_payload_encode(
+            static_key.as_ptr(), 32, ptr::null(), 0, ptr::null(), 0,
+            payload.as_mut_ptr(), &mut payload_len
+        ),
+        NOISE_ERROR_SUCCESS
+    );
+    psk_len = 32;
+    hints_len = hints_out.len();
+    assert_eq!(
+        noise_pairing_payload_decode(
+            payload.as_ptr(), payload_len,
+            key_out.as_mut_ptr(), &mut key_len,
+            ptr::null_mut(), &mut psk_len,
+            ptr::null_mut(), &mut hints_len
+        ),
+        NOISE_ERROR_SUCCESS
+    );
+    assert_eq!((psk_len, hints_len), (0, 0));
+    
+    payload[0] = b'X';
+    assert_eq!(
+        noise_pairing_payload_decode(
+            payload.as_ptr(), payload_len,
+            key_out.as_mut_ptr(), &mut key_len,
+            psk_out.as_mut_ptr(), &mut psk_len,
+            hints_out.as_mut_ptr(), &mut hints_len
+        ),
+        NOISE_ERROR_PROTOCOL_ERROR
+    );
+}
+
+unsafe extern "C" fn reject_user(
+    context: *mut libc::c_void,
+    payload: *const u8,
+    payload_len: size_t,
+    remote_static: *const u8,
+    remote_static_len: size_t,
+) -> c_int {
+    *(context as *mut usize) += 1;
+    assert!(!remote_static.is_null() && remote_static_len == 32);
+    let payload = std::slice::from_raw_parts(payload, payload_len);
+    (payload == b"mallory") as c_int
+}
+
+#[test]
+fn test_payload_verifier() {
+    let run = |user: &[u8]| {
+        let mut error = 0;
+        let alice = noise_session_new(NOISE_MODE_INITIATOR, &mut error);
+        let bob = noise_session_new(NOISE_MODE_RESPONDER, &mut error);
+        let mut calls = 0usize;
+        assert_eq!(
+            noise_set_payload_verifier(alice, Some(reject_user), &mut calls as *mut usize as *mut libc::c_void),
+            NOISE_ERROR_SUCCESS
+        );
+        
+        let mut buffer = [0u8; 256];
+        let mut payload = [0u8; 256];
+        let mut len: size_t = buffer.len();
+        noise_write_message(alice, ptr::null(), 0, buffer.as_mut_ptr(), &mut len);
+        let mut payload_len: size_t = payload.len();
+        noise_read_message(bob, buffer.as_ptr(), len, payload.as_mut_ptr(), &mut payload_len);
+        len = buffer.len();
+        noise_write_message(bob, user.as_ptr(), user.len(), buffer.as_mut_ptr(), &mut len);
+        payload_len = payload.len();
+        let result = noise_read_message(alice, buffer.as_ptr(), len, payload.as_mut_ptr(), &mut payload_len);
+        
+        noise_session_free(alice);
+        noise_session_free(bob);
+        (result, calls)
+    };
+    
+    assert_eq!(run(b"bob"), (NOISE_ERROR_SUCCESS, 1));
+    assert_eq!(run(b"mallory"), (NOISE_ERROR_HANDSHAKE_FAILED, 1));
+    
+    // A null callback removes the verifier
+    let mut error = 0;
+    let session = noise_session_new(NOISE_MODE_INITIATOR, &mut error);
+    assert_eq!(noise_set_payload_verifier(session, None, ptr::null_mut()), NOISE_ERROR_SUCCESS);
+    noise_session_free(session);
+    assert_eq!(noise_set_payload_verifier(session, None, ptr::null_mut()), NOISE_ERROR_INVALID_PARAMETER);
+}
+
+#[test]
+fn test_pipe_fallback_to_session() {
+    let mut stale_private = [0u8; 32];
+    let mut stale_public = [0u8; 32];
+    let mut server_private = [0u8; 32];
+    let mut server_public = [0u8; 32];
+    let (mut private_len, mut public_len) = (32, 32);
+    noise_generate_keypair(stale_private.as_mut_ptr(), &mut private_len, stale_public.as_mut_ptr(), &mut public_len);
+    noise_generate_keypair(server_private.as_mut_ptr(), &mut private_len, server_public.as_mut_ptr(), &mut public_len);
+    
+    let mut error = 0;
+    let client_private = [3u8; 32];
+    let alice = noise_pipe_new(
+        client_private.as_ptr(), 32, NOISE_MODE_INITIATOR, stale_public.as_ptr(), 32, &mut error,
+    );
+    assert_eq!(error, NOISE_ERROR_SUCCESS);
+    let bob = noise_pipe_new(server_private.as_ptr(), 32, NOISE_MODE_RESPONDER, ptr::null(), 0, &mut error);
+    assert_eq!(error, NOISE_ERROR_SUCCESS);
+    
+    // IK against the stale key, fallback, then the rest of XX
+    let mut buffer = [0u8; 512];
+    let mut payload = [0u8; 512];
+    let mut sender = alice;
+    let mut receiver = bob;
+    while noise_pipe_is_handshake_complete(alice) == 0 || noise_pipe_is_handshake_complete(bob) == 0 {
+        let mut len: size_t = buffer.len();
+        assert_eq!(noise_pipe_write_message(sender, ptr::null(), 0, buffer.as_mut_ptr(), &mut len), NOISE_ERROR_SUCCESS);
+        let mut payload_len: size_t = payload.len();
+        assert_eq!(
+            noise_pipe_read_message(receiver, buffer.as_ptr(), len, payload.as_mut_ptr(), &mut payload_len),
+            NOISE_ERROR_SUCCESS
+        );
+        std::mem::swap(&mut sender, &mut receiver);
+    }
+    assert_eq!(noise_pipe_fell_back(alice), 1);
+    
+    let mut session = ptr::null_mut();
+    assert_eq!(noise_pipe_into_session(alice, &mut session), NOISE_ERROR_SUCCESS);
+    assert_eq!(noise_pipe_is_handshake_complete(alice), 0);
+    let mut remote = [0u8; 32];
+    let mut remote_len: size_t = remote.len();
+    assert_eq!(noise_get_remote_static(session, remote.as_mut_ptr(), &mut remote_len), NOISE_ERROR_SUCCESS);
+    assert_eq!(remote, server_public);
+    
+    noise_session_free(session);
+    noise_pipe_free(bob);
+    
+    // Responders take no cached key, and unfinished pipes stay pipes
+    let pipe = noise_pipe_new(server_private.as_ptr(), 32, NOISE_MODE_RESPONDER, server_public.as_ptr(), 32, &mut error);
+    assert!(pipe.is_null());
+    assert_eq!(error, NOISE_ERROR_INVALID_PARAMETER);
+    let pipe = noise_pipe_new(client_private.as_ptr(), 32, NOISE_MODE_INITIATOR, ptr::null(), 0, &mut error);
+    assert_eq!(noise_pipe_into_session(pipe, &mut session), NOISE_ERROR_INVALID_STATE);
+    noise_pipe_free(pipe);
+    assert_eq!(noise_pipe_into_session(pipe, &mut session), NOISE_ERROR_INVALID_PARAMETER);
+}
+
+#[cfg(feature = "sealed")]
+#[test]
+fn test_sealed_messages() {
+    let mut recipient_private = [0u8; 32];
+    let mut recipient_public = [0u8; 32];
+    let (mut private_len, mut public_len) = (32, 32);
+    noise_generate_keypair(recipient_private.as_mut_ptr(), &mut private_len, recipient_public.as_mut_ptr(), &mut public_len);
+    let sender_private = [5u8; 32];
+    let mut sender_public = [0u8; 32];
+    let mut sender_public_len: size_t = 32;
+    noise_public_key_from_private(sender_private.as_ptr(), 32, sender_public.as_mut_ptr(), &mut sender_public_len);
+    
+    let message = b"offline hello";
+    let mut blob = [0u8; 256];
+    let mut plaintext = [0u8; 256];
+    let mut sender = [0u8; 32];
+    for sender_key in [ptr::null(), sender_private.as_ptr()] {
+        let mut blob_len: size_t = blob.len();
+        assert_eq!(
+            noise_seal(recipient_public.as_ptr(), 32, sender_key, 32, message.as_ptr(), message.len(), blob.as_mut_ptr(), &mut blob_len),
+            NOISE_ERROR_SUCCESS
+        );
+        
+        // Both buffers are checked before either is written
+        let mut plaintext_len: size_t = 4;
+        let mut sender_len: size_t = 32;
+        assert_eq!(
+            noise_open(recipient_private.as_ptr(), 32, blob.as_ptr(), blob_len, plaintext.as_mut_ptr(), &mut plaintext_len, sender.as_mut_ptr(), &mut sender_len),
+            NOISE_ERROR_BUFFER_TOO_SMALL
+        );
+        assert_eq!(plaintext_len, message.len());
+        
+        plaintext_len = plaintext.len();
+        assert_eq!(
+            noise_open(recipient_private.as_ptr(), 32, blob.as_ptr(), blob_len, plaintext.as_mut_ptr(), &mut plaintext_len, sender.as_mut_ptr(), &mut sender_len),
+            NOISE_ERROR_SUCCESS
+        );
+        assert_eq!(&plaintext[..plaintext_len], message);
+        if sender_key.is_null() {
+            assert_eq!(sender_len, 0);
+        } else {
+            assert_eq!(sender, sender_public);
+        }
+        
+        // Anyone else's key fails to open it
+        plaintext_len = plaintext.len();
+        assert_eq!(
+            noise_open(sender_private.as_ptr(), 32, blob.as_ptr(), blob_len, plaintext.as_mut_ptr(), &mut plaintext_len, ptr::null_mut(), ptr::null_mut()),
+            NOISE_ERROR_DECRYPTION_FAILED
+        );
+    }
+    
+    let mut blob_len: size_t = blob.len();
+    assert_eq!(
+        noise_seal(recipient_public.as_ptr(), 16, ptr::null(), 0, message.as_ptr(), message.len(), blob.as_mut_ptr(), &mut blob_len),
+        NOISE_ERROR_INVALID_PARAMETER
+    );
+}
+
+#[cfg(feature = "file-cipher")]
+#[test]
+fn test_file_encryption() {
+    let mut error = 0;
+    let alice = noise_session_new(NOISE_MODE_INITIATOR, &mut error);
+    let bob = noise_session_new(NOISE_MODE_RESPONDER, &mut error);
+    let dir = std::env::temp_dir().join(format!("noise-file-cipher-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = |name: &str| std::ffi::CString::new(dir.join(name).to_str().unwrap()).unwrap();
+    let (plain, sealed, opened) = (path("plain"), path("sealed"), path("opened"));
+    let attachment: Vec<u8> = (0..300_000u32).map(|i| (i % 253) as u8).collect();
+    std::fs::write(dir.join("plain"), &attachment).unwrap();
+    
+    // The file key needs a finished handshake
+    let mut len = 0u64;
+    assert_eq!(noise_file_encrypt(alice, plain.as_ptr(), sealed.as_ptr(), &mut len), NOISE_ERROR_INVALID_STATE);
+    
+    let mut buffer = [0u8; 256];
+    let mut payload = [0u8; 256];
+    for (from, to) in [(alice, bob), (bob, alice), (alice, bob)] {
+        let mut len: size_t = buffer.len();
+        assert_eq!(noise_write_message(from, ptr::null(), 0, buffer.as_mut_ptr(), &mut len), NOISE_ERROR_SUCCESS);
+        let mut payload_len: size_t = payload.len();
+        assert_eq!(noise_read_message(to, buffer.as_ptr(), len, payload.as_mut_ptr(), &mut payload_len), NOISE_ERROR_SUCCESS);
+    }
+    
+    assert_eq!(noise_file_encrypt(alice, plain.as_ptr(), sealed.as_ptr(), &mut len), NOISE_ERROR_SUCCESS);
+    assert_eq!(len, attachment.len() as u64);
+    assert_eq!(noise_file_decrypt(bob, sealed.as_ptr(), opened.as_ptr(), ptr::null_mut()), NOISE_ERROR_SUCCESS);
+    assert_eq!(std::fs::read(dir.join("opened")).unwrap(), attachment);
+    
+    // A cut-short file fails without touching the existing output or
+    // leaving partial output behind
+    let encrypted = std::fs::read(dir.join("sealed")).unwrap();
+    std::fs::write(dir.join("sealed"), &encrypted[..encrypted.len() - 40]).unwrap();
+    assert_eq!(noise_file_decrypt(bob, sealed.as_ptr(), opened.as_ptr(), ptr::null_mut()), NOISE_ERROR_PROTOCOL_ERROR);
+    assert_eq!(std::fs::read(dir.join("opened")).unwrap(), attachment);
+    assert_ne!(noise_file_decrypt(bob, path("missing").as_ptr(), opened.as_ptr(), ptr::null_mut()), NOISE_ERROR_SUCCESS);
+    assert_eq!(std::fs::read(dir.join("opened")).unwrap(), attachment);
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 3);
+    assert_eq!(noise_file_decrypt(bob, ptr::null(), opened.as_ptr(), ptr::null_mut()), NOISE_ERROR_INVALID_PARAMETER);
+    
+    #[cfg(unix)]
+    {
+        use std::os::unix::io::AsRawFd;
+        let input = std::fs::File::open(dir.join("plain")).unwrap();
+        let output = std::fs::File::create(dir.join("sealed")).unwrap();
+        assert_eq!(noise_file_encrypt_fd(bob, input.as_raw_fd(), output.as_raw_fd(), &mut len), NOISE_ERROR_SUCCESS);
+        let input = std::fs::File::open(dir.join("sealed")).unwrap();
+        let output = std::fs::File::create(dir.join("opened")).unwrap();
+        assert_eq!(noise_file_decrypt_fd(alice, input.as_raw_fd(), output.as_raw_fd(), &mut len), NOISE_ERROR_SUCCESS);
+        assert_eq!(std::fs::read(dir.join("opened")).unwrap(), attachment);
+        assert_eq!(noise_file_encrypt_fd(bob, -1, output.as_raw_fd(), &mut len), NOISE_ERROR_INVALID_PARAMETER);
+    }
+    
+    std::fs::remove_dir_all(&dir).unwrap();
+    noise_session_free(alice);
+    noise_session_free(bob);
+}
+
+#[test]
+fn test_size_hints() {
+    let mut error = 0;
+    let alice = noise_session_new(NOISE_MODE_INITIATOR, &mut error);
+    let bob = noise_session_new(NOISE_MODE_RESPONDER, &mut error);
+    let mut size: size_t = 0;
+    assert_eq!(noise_encrypt_size_hint(ptr::null_mut(), 10, &mut size), NOISE_ERROR_INVALID_PARAMETER);
+    assert_eq!(noise_encrypt_size_hint(alice, 10, ptr::null_mut()), NOISE_ERROR_INVALID_PARAMETER);
+    
+    let mut buffer = [0u8; 256];
+    let mut payload = [0u8; 256];
+    for (from, to) in [(alice, bob), (bob, alice), (alice, bob)] {
+        let mut len: size_t = buffer.len();
+        assert_eq!(noise_write_message(from, ptr::null(), 0, buffer.as_mut_ptr(), &mut len), NOISE_ERROR_SUCCESS);
+        let mut payload_len: size_t = payload.len();
+        assert_eq!(noise_read_message(to, buffer.as_ptr(), len, payload.as_mut_ptr(), &mut payload_len), NOISE_ERROR_SUCCESS);
+    }
+    
+    // A short buffer reports the size without using a nonce
+    let message = b"sized exactly";
+    let mut short: size_t = 4;
+    assert_eq!(noise_encrypt(alice, message.as_ptr(), message.len(), buffer.as_mut_ptr(), &mut short), NOISE_ERROR_BUFFER_TOO_SMALL);
+    assert_eq!(noise_encrypt_size_hint(alice, message.len(), &mut size), NOISE_ERROR_SUCCESS);
+    assert_eq!(short, size);
+    
+    let mut ciphertext = vec![0u8; size];
+    let mut ciphertext_len = size;
+    assert_eq!(noise_encrypt(alice, message.as_ptr(), message.len(), ciphertext.as_mut_ptr(), &mut ciphertext_len), NOISE_ERROR_SUCCESS);
+    assert_eq!(ciphertext_len, size);
+    
+    assert_eq!(noise_decrypt_size_hint(bob, ciphertext_len, &mut size), NOISE_ERROR_SUCCESS);
+    let mut plaintext = vec![0u8; size];
+    let mut plaintext_len = size;
+    assert_eq!(noise_decrypt(bob, ciphertext.as_ptr(), ciphertext_len, plaintext.as_mut_ptr(), &mut plaintext_len), NOISE_ERROR_SUCCESS);
+    assert_eq!(&plaintext[..plaintext_len], message);
+    
+    noise_session_free(alice);
+    noise_session_free(bob);
+}
+
+#[test]
+fn test_alloc_variants() {
+    use noise_mobile::ffi::types::NoiseBuffer;
+    
+    let mut error = 0;
+    let alice = noise_session_new(NOISE_MODE_INITIATOR, &mut error);
+    let bob = noise_session_new(NOISE_MODE_RESPONDER, &mut error);
+    let mut message = NoiseBuffer::new();
+    let mut payload = NoiseBuffer::new();
+    assert_eq!(noise_encrypt_alloc(alice, ptr::null(), 0, ptr::null_mut()), NOISE_ERROR_INVALID_PARAMETER);
+    assert_eq!(noise_write_message_alloc(ptr::null_mut(), ptr::null(), 0, &mut message), NOISE_ERROR_INVALID_PARAMETER);
+    assert!(message.is_null());
+    
+    for (from, to) in [(alice, bob), (bob, alice), (alice, bob)] {
+        assert_eq!(noise_write_message_alloc(from, ptr::null(), 0, &mut message), NOISE_ERROR_SUCCESS);
+        assert_eq!(noise_read_message_alloc(to, message.data, message.len, &mut payload), NOISE_ERROR_SUCCESS);
+        assert_eq!(payload.len, 0);
+        noise_buffer_free(&mut message);
+        noise_buffer_free(&mut payload);
+    }
+    
+    let text = b"no size probing";
+    let mut ciphertext = NoiseBuffer::new();
+    assert_eq!(noise_encrypt_alloc(alice, text.as_ptr(), text.len(), &mut ciphertext), NOISE_ERROR_SUCCESS);
+    assert_eq!(ciphertext.len, text.len() + 16);
+    let mut plaintext = NoiseBuffer::new();
+    assert_eq!(noise_decrypt_alloc(bob, ciphertext.data, ciphertext.len, &mut plaintext), NOISE_ERROR_SUCCESS);
+    assert_eq!(unsafe { std::slice::from_raw_parts(plaintext.data, plaintext.len) }, text);
+    
+    noise_buffer_free(&mut plaintext);
+    
+    // Failures leave the output empty
+    assert_ne!(noise_decrypt_alloc(bob, ciphertext.data, ciphertext.len, &mut plaintext), NOISE_ERROR_SUCCESS);
+    assert!(plaintext.is_null());
+    
+    // Freeing twice or freeing null is harmless
+    noise_buffer_free(&mut ciphertext);
+    noise_buffer_free(&mut ciphertext);
+    noise_buffer_free(ptr::null_mut());
+    assert!(ciphertext.is_null() && ciphertext.len == 0 && ciphertext.capacity == 0);
+    
+    noise_session_free(alice);
+    noise_session_free(bob);
+}
+
+#[cfg(all(feature = "manager", feature = "sealed"))]
+#[test]
+fn test_buffer_functions() {
+    use noise_mobile::ffi::types::NoiseBuffer;
+    
+    let mut buffer = NoiseBuffer::new();
+    assert_eq!(noise_buffer_alloc(4, ptr::null_mut()), NOISE_ERROR_INVALID_PARAMETER);
+    assert_eq!(noise_buffer_resize(ptr::null_mut(), 4), NOISE_ERROR_INVALID_PARAMETER);
+    assert_eq!(noise_buffer_alloc(4, &mut buffer), NOISE_ERROR_SUCCESS);
+    assert_eq!(buffer.len, 4);
+    unsafe { std::slice::from_raw_parts_mut(buffer.data, 4) }.copy_from_slice(b"keep");
+    
+    // Growing keeps the contents and zero-fills; shrinking truncates
+    assert_eq!(noise_buffer_resize(&mut buffer, 1000), NOISE_ERROR_SUCCESS);
+    let grown = unsafe { std::slice::from_raw_parts(buffer.data, buffer.len) };
+    assert_eq!(&grown[..4], b"keep");
+    assert!(grown[4..].iter().all(|&b| b == 0));
+    assert_eq!(noise_buffer_resize(&mut buffer, 2), NOISE_ERROR_SUCCESS);
+    assert_eq!(unsafe { std::slice::from_raw_parts(buffer.data, buffer.len) }, b"ke");
+    
+    // Output functions never read what the buffer holds
+    noise_buffer_free(&mut buffer);
+    let mut garbage = std::mem::MaybeUninit::<NoiseBuffer>::uninit();
+    unsafe { ptr::write_bytes(garbage.as_mut_ptr(), 0xa5, 1) };
+    let mut garbage = unsafe { garbage.assume_init() };
+    assert_eq!(noise_buffer_alloc(3, &mut garbage), NOISE_ERROR_SUCCESS);
+    assert_eq!(garbage.len, 3);
+    noise_buffer_free(&mut garbage);
+    unsafe { ptr::write_bytes(&mut garbage, 0xa5, 1) };
+    assert_ne!(noise_decrypt_alloc(ptr::null_mut(), b"x".as_ptr(), 1, &mut garbage), NOISE_ERROR_SUCCESS);
+    assert!(garbage.is_null());
+    
+    let mut error: c_int = -1;
+    let alice = noise_manager_new([1u8; 32].as_ptr(), 32, &mut error);
+    let bob = noise_manager_new([2u8; 32].as_ptr(), 32, &mut error);
+    manager_handshake(alice, bob);
+    assert_eq!(noise_manager_encrypt_alloc(alice, c"bob".as_ptr(), b"hi".as_ptr(), 2, &mut buffer), NOISE_ERROR_SUCCESS);
+    let mut plaintext = NoiseBuffer::new();
+    assert_eq!(noise_manager_decrypt_alloc(bob, c"alice".as_ptr(), buffer.data, buffer.len, &mut plaintext), NOISE_ERROR_SUCCESS);
+    assert_eq!(unsafe { std::slice::from_raw_parts(plaintext.data, plaintext.len) }, b"hi");
+    noise_buffer_free(&mut buffer);
+    assert_eq!(noise_manager_encrypt_alloc(alice, c"carol".as_ptr(), b"hi".as_ptr(), 2, &mut buffer), NOISE_ERROR_INVALID_STATE);
+    assert!(buffer.is_null());
+    
+    assert_eq!(noise_manager_serialize_alloc(bob, &mut buffer), NOISE_ERROR_SUCCESS);
+    let restored = noise_manager_restore([2u8; 32].as_ptr(), 32, buffer.data, buffer.len, &mut error);
+    assert_eq!(error, NOISE_ERROR_SUCCESS);
+    noise_buffer_free(&mut buffer);
+    
+    let recipient = [9u8; 32];
+    let mut recipient_public = [0u8; 32];
+    let mut public_len: size_t = 32;
+    assert_eq!(noise_public_key_from_private(recipient.as_ptr(), 32, recipient_public.as_mut_ptr(), &mut public_len), NOISE_ERROR_SUCCESS);
+    assert_eq!(noise_seal_alloc(recipient_public.as_ptr(), 32, ptr::null(), 0, b"sealed".as_ptr(), 6, &mut buffer), NOISE_ERROR_SUCCESS);
+    let mut opened = [0u8; 16];
+    let mut opened_len: size_t = opened.len();
+    assert_eq!(
+        noise_open(recipient.as_ptr(), 32, buffer.data, buffer.len, opened.as_mut_ptr(), &mut opened_len, ptr::null_mut(), ptr::null_mut()),
+        NOISE_ERROR_SUCCESS
+    );
+    assert_eq!(&opened[..opened_len], b"sealed");
+    
+    noise_buffer_free(&mut buffer);
+    noise_buffer_free(&mut plaintext);
+    noise_manager_free(alice);
+    noise_manager_free(bob);
+    noise_manager_free(restored);
+}
+
+#[test]
+fn test_version_and_features() {
+    let version = noise_ffi_version();
+    assert_eq!(version >> 16, NOISE_FFI_VERSION_MAJOR);
+    assert_eq!(version & 0xffff, NOISE_FFI_VERSION_MINOR);
+    // Pinned so that adding functions without bumping the minor fails here
+    assert_eq!(version, 0x0001_0001);
+    let library = unsafe { std::ffi::CStr::from_ptr(noise_library_version()) };
+    assert_eq!(library.to_str().unwrap(), env!("CARGO_PKG_VERSION"));
+    
+    for feature in [c"xx", c"ik"] {
+        assert_eq!(noise_has_feature(feature.as_ptr()), 1);
+    }
+    // Only ticket resumption uses a PSK pattern
+    assert_eq!(noise_has_feature(c"psk".as_ptr()), cfg!(feature = "tickets") as c_int);
+    assert_eq!(noise_has_feature(c"pq".as_ptr()), 0);
+    assert_eq!(noise_has_feature(c"batching".as_ptr()), cfg!(feature = "batching") as c_int);
+    assert_eq!(noise_has_feature(c"teleport".as_ptr()), 0);
+    assert_eq!(noise_has_feature(ptr::null()), 0);
+}
+
+/// Names of the functions declared in `text`, taken from lines that look
+/// like `<prefix>fn_name(` outside comments
+fn declared_functions(text: &str, prefix: &str) -> std::collections::BTreeSet<String> {
+    text.lines()
+        .map(str::trim_start)
+        .filter(|line| !line.starts_with('*') && !line.starts_with("/*") && !line.starts_with("//"))
+        .filter_map(|line| {
+            let start = line.find(prefix)? + prefix.len();
+            let name = &line[start..];
+            let end = name.find('(')?;
+            let name = name[..end].rsplit(|c: char| c.is_whitespace() || c == '*').next()?;
+            name.starts_with("noise_").then(|| name.to_string())
+        })
+        .collect()
+}
+
+#[test]
+fn test_header_matches_exported_functions() {
+    // Regenerate with `cargo build --features generate-header`
+    let root = env!("CARGO_MANIFEST_DIR");
+    let header = std::fs::read_to_string(format!("{root}/include/noise_mobile.h")).unwrap();
+    let c_api = std::fs::read_to_string(format!("{root}/src/ffi/c_api.rs")).unwrap();
+    
+    let exported = declared_functions(&c_api, "pub extern \"C\" fn ");
+    let declared = declared_functions(&header, "");
+    assert!(exported.contains("noise_session_new") && exported.len() > 50);
+    assert_eq!(declared, exported, "include/noise_mobile.h is out of date");
+}
+
+#[derive(Default)]
+struct SessionEventLog {
+    handshakes: usize,
+    messages: Vec<Vec<u8>>,
+    errors: Vec<c_int>,
+    rekeys: Vec<c_int>,
+    closed: usize,
+}
+
+unsafe extern "C" fn on_handshake_complete(context: *mut libc::c_void) {
+    (*(context as *mut SessionEventLog)).handshakes += 1;
+}
+
+unsafe extern "C" fn on_message_decrypted(context: *mut libc::c_void, plaintext: *const u8, plaintext_len: size_t) {
+    let message = std::slice::from_raw_parts(plaintext, plaintext_len).to_vec();
+    (*(context as *mut SessionEventLog)).messages.push(message);
+}
+
+unsafe extern "C" fn on_error(context: *mut libc::c_void, error: c_int) {
+    (*(context as *mut SessionEventLog)).errors.push(error);
+}
+
+unsafe extern "C" fn on_rekeyed(context: *mut libc::c_void, direction: c_int) {
+    (*(context as *mut SessionEventLog)).rekeys.push(direction);
+}
+
+unsafe extern "C" fn on_closed(context: *mut libc::c_void) {
+    (*(context as *mut SessionEventLog)).closed += 1;
+}
+
+#[test]
+fn test_session_callbacks() {
+    use noise_mobile::ffi::types::NoiseSessionCallbacks;
+    
+    let mut error = 0;
+    let alice = noise_session_new(NOISE_MODE_INITIATOR, &mut error);
+    let bob = noise_session_new(NOISE_MODE_RESPONDER, &mut error);
+    let mut log = Box::new(SessionEventLog::default());
+    let callbacks = NoiseSessionCallbacks {
+        context: &mut *log as *mut SessionEventLog as *mut libc::c_void,
+        handshake_complete: Some(on_handshake_complete),
+        message_decrypted: Some(on_message_decrypted),
+        error: Some(on_error),
+        rekeyed: Some(on_rekeyed),
+        closed: Some(on_closed),
+    };
+    assert_eq!(noise_session_set_callbacks(ptr::null_mut(), &callbacks), NOISE_ERROR_INVALID_PARAMETER);
+    assert_eq!(noise_session_set_callbacks(bob, &callbacks), NOISE_ERROR_SUCCESS);
+    
+    let mut buffer = [0u8; 256];
+    let mut payload = [0u8; 256];
+    for (from, to) in [(alice, bob), (bob, alice), (alice, bob)] {
+        assert_eq!(log.handshakes, 0);
+        let mut len: size_t = buffer.len();
+        assert_eq!(noise_write_message(from, ptr::null(), 0, buffer.as_mut_ptr(), &mut len), NOISE_ERROR_SUCCESS);
+        let mut payload_len: size_t = payload.len();
+        assert_eq!(noise_read_message(to, buffer.as_ptr(), len, payload.as_mut_ptr(), &mut payload_len), NOISE_ERROR_SUCCESS);
+    }
+    assert_eq!(log.handshakes, 1);
+    
+    let mut ciphertext = [0u8; 64];
+    let mut ciphertext_len: size_t = ciphertext.len();
+    assert_eq!(noise_encrypt(alice, b"event".as_ptr(), 5, ciphertext.as_mut_ptr(), &mut ciphertext_len), NOISE_ERROR_SUCCESS);
+    let mut plaintext_len: size_t = payload.len();
+    assert_eq!(noise_decrypt(bob, ciphertext.as_ptr(), ciphertext_len, payload.as_mut_ptr(), &mut plaintext_len), NOISE_ERROR_SUCCESS);
+    assert_eq!(log.messages, vec![b"event".to_vec()]);
+    
+    // Replaying the message fails and reports the error
+    plaintext_len = payload.len();
+    let status = noise_decrypt(bob, ciphertext.as_ptr(), ciphertext_len, payload.as_mut_ptr(), &mut plaintext_len);
+    assert_ne!(status, NOISE_ERROR_SUCCESS);
+    assert_eq!(log.errors, vec![status]);
+    assert_eq!(log.messages.len(), 1);
+    
+    assert_eq!(noise_rekey_incoming(bob), NOISE_ERROR_SUCCESS);
+    assert_eq!(noise_rekey_outgoing(bob), NOISE_ERROR_SUCCESS);
+    assert_eq!(log.rekeys, vec![NOISE_REKEY_INCOMING, NOISE_REKEY_OUTGOING]);
+    
+    noise_session_free(bob);
+    noise_session_free(bob);
+    assert_eq!(log.closed, 1);
+    noise_session_free(alice);
+}
+
+static LOG_RECORDS: std::sync::Mutex<Vec<(c_int, String)>> = std::sync::Mutex::new(Vec::new());
+
+unsafe extern "C" fn on_log(_context: *mut libc::c_void, level: c_int, _target: *const libc::c_char, message: *const libc::c_char) {
+    let message = std::ffi::CStr::from_ptr(message).to_string_lossy().into_owned();
+    LOG_RECORDS.lock().unwrap().push((level, message));
+}
+
+#[test]
+fn test_log_callback() {
+    assert_eq!(noise_set_log_callback(9, Some(on_log), ptr::null_mut()), NOISE_ERROR_INVALID_PARAMETER);
+    assert_eq!(noise_set_log_callback(NOISE_LOG_INFO, Some(on_log), ptr::null_mut()), NOISE_ERROR_SUCCESS);
+    
+    let mut error = 0;
+    let alice = noise_session_new(NOISE_MODE_INITIATOR, &mut error);
+    let bob = noise_session_new(NOISE_MODE_RESPONDER, &mut error);
+    let mut buffer = [0u8; 256];
+    let mut payload = [0u8; 256];
+    for (from, to) in [(alice, bob), (bob, alice), (alice, bob)] {
+        let mut len: size_t = buffer.len();
+        assert_eq!(noise_write_message(from, ptr::null(), 0, buffer.as_mut_ptr(), &mut len), NOISE_ERROR_SUCCESS);
+        let mut payload_len: size_t = payload.len();
+        assert_eq!(noise_read_message(to, buffer.as_ptr(), len, payload.as_mut_ptr(), &mut payload_len), NOISE_ERROR_SUCCESS);
+    }
+    
+    // Rejected messages are debug records, below the level asked for
+    let garbage = [0u8; 48];
+    let mut payload_len: size_t = payload.len();
+    assert_ne!(noise_decrypt(bob, garbage.as_ptr(), garbage.len(), payload.as_mut_ptr(), &mut payload_len), NOISE_ERROR_SUCCESS);
+    assert_eq!(noise_set_log_callback(NOISE_LOG_OFF, None, ptr::null_mut()), NOISE_ERROR_SUCCESS);
+    
+    // Other tests may log while the callback is set
+    let records = LOG_RECORDS.lock().unwrap();
+    assert!(records.iter().any(|(level, message)| *level == NOISE_LOG_INFO && message.starts_with("Handshake complete")));
+    assert!(records.iter().all(|(level, _)| *level <= NOISE_LOG_INFO));
+    drop(records);
+    
+    noise_session_free(alice);
+    noise_session_free(bob);
+}
+
+#[cfg(feature = "batching")]
+type BatchLog = std::sync::Mutex<Vec<(u64, c_int, c_int, Vec<u8>)>>;
+
+#[cfg(feature = "batching")]
+unsafe extern "C" fn on_batch_result(
+    context: *mut libc::c_void,
+    id: u64,
+    operation: c_int,
+    status: c_int,
+    data: *const u8,
+    data_len: size_t,
+) {
+    let data = if data.is_null() { Vec::new() } else { std::slice::from_raw_parts(data, data_len).to_vec() };
+    (*(context as *const BatchLog)).lock().unwrap().push((id, operation, status, data));
+}
+
+#[cfg(feature = "batching")]
+#[test]
+fn test_batch_flush_callback() {
+    let mut error = 0;
+    let alice = noise_session_new(NOISE_MODE_INITIATOR, &mut error);
+    let bob = noise_session_new(NOISE_MODE_RESPONDER, &mut error);
+    let mut buffer = [0u8; 256];
+    let mut payload = [0u8; 256];
+    for (from, to) in [(alice, bob), (bob, alice), (alice, bob)] {
+        let mut len: size_t = buffer.len();
+        assert_eq!(noise_write_message(from, ptr::null(), 0, buffer.as_mut_ptr(), &mut len), NOISE_ERROR_SUCCESS);
+        let mut payload_len: size_t = payload.len();
+        assert_eq!(noise_read_message(to, buffer.as_ptr(), len, payload.as_mut_ptr(), &mut payload_len), NOISE_ERROR_SUCCESS);
+    }
+    
+    // The batch takes over the session handle
+    let mut batch = ptr::null_mut();
+    assert_eq!(noise_batch_new(alice, &mut batch), NOISE_ERROR_SUCCESS);
+    let mut len: size_t = buffer.len();
+    assert_eq!(noise_encrypt(alice, b"gone".as_ptr(), 4, buffer.as_mut_ptr(), &mut len), NOISE_ERROR_INVALID_PARAMETER);
+    assert_eq!(noise_batch_flush(batch), NOISE_ERROR_INVALID_STATE);
+    
+    let log = Box::new(BatchLog::default());
+    let context = &*log as *const BatchLog as *mut libc::c_void;
+    assert_eq!(noise_batch_set_flush_callback(batch, Some(on_batch_result), context, 0), NOISE_ERROR_SUCCESS);
+    assert_eq!(noise_batch_queue_encrypt(batch, 10, b"first".as_ptr(), 5), NOISE_ERROR_SUCCESS);
+    assert_eq!(noise_batch_queue_encrypt(batch, 11, b"second".as_ptr(), 6), NOISE_ERROR_SUCCESS);
+    
+    // Nothing is due yet, so polling reports when it will be
+    let mut next_flush_ms = 0i64;
+    assert_eq!(noise_batch_poll(batch, &mut next_flush_ms), NOISE_ERROR_SUCCESS);
+    assert!((0..=100).contains(&next_flush_ms));
+    assert!(log.lock().unwrap().is_empty());
+    
+    assert_eq!(noise_batch_flush(batch), NOISE_ERROR_SUCCESS);
+    let results = std::mem::take(&mut *log.lock().unwrap());
+    assert_eq!(results.iter().map(|r| (r.0, r.1, r.2)).collect::<Vec<_>>(), [
+        (10, NOISE_BATCH_ENCRYPT, NOISE_ERROR_SUCCESS),
+        (11, NOISE_BATCH_ENCRYPT, NOISE_ERROR_SUCCESS),
+    ]);
+    for ((_, _, _, ciphertext), expected) in results.iter().zip([&b"first"[..], b"second"]) {
+        let mut plaintext_len: size_t = payload.len();
+        assert_eq!(
+            noise_decrypt(bob, ciphertext.as_ptr(), ciphertext.len(), payload.as_mut_ptr(), &mut plaintext_len),
+            NOISE_ERROR_SUCCESS
+        );
+        assert_eq!(&payload[..plaintext_len], expected);
+    }
+    assert_eq!(noise_batch_poll(batch, &mut next_flush_ms), NOISE_ERROR_SUCCESS);
+    assert_eq!(next_flush_ms, -1);
+    
+    // Power modes stretch or shrink the flush interval of live batches
+    use noise_mobile::ffi::types::NoisePowerMode;
+    assert_eq!(noise_set_power_mode(NoisePowerMode::Low as c_int), NOISE_ERROR_SUCCESS);
+    assert_eq!(noise_batch_queue_encrypt(batch, 15, b"bulk".as_ptr(), 4), NOISE_ERROR_SUCCESS);
+    assert_eq!(noise_batch_poll(batch, &mut next_flush_ms), NOISE_ERROR_SUCCESS);
+    assert!(next_flush_ms > 100);
+    assert_eq!(noise_set_power_mode(3), NOISE_ERROR_INVALID_PARAMETER);
+    assert_eq!(noise_set_power_mode(NoisePowerMode::Balanced as c_int), NOISE_ERROR_SUCCESS);
+    assert_eq!(noise_batch_poll(batch, &mut next_flush_ms), NOISE_ERROR_SUCCESS);
+    assert!((0..=100).contains(&next_flush_ms));
+    assert_eq!(noise_batch_flush(batch), NOISE_ERROR_SUCCESS);
+    log.lock().unwrap().clear();
+    
+    // Immediate messages are reported before the call returns
+    use noise_mobile::ffi::types::NoiseBatchPriority;
+    assert_eq!(
+        noise_batch_queue_with_priority(batch, 20, NOISE_BATCH_ENCRYPT, NoiseBatchPriority::Immediate as c_int, b"typing".as_ptr(), 6),
+        NOISE_ERROR_SUCCESS
+    );
+    assert_eq!(
+        noise_batch_queue_with_priority(batch, 21, NOISE_BATCH_ENCRYPT, NoiseBatchPriority::Background as c_int, b"sync".as_ptr(), 4),
+        NOISE_ERROR_SUCCESS
+    );
+    assert_eq!(noise_batch_queue_with_priority(batch, 22, 7, NoiseBatchPriority::Normal as c_int, b"x".as_ptr(), 1), NOISE_ERROR_INVALID_PARAMETER);
+    assert_eq!(noise_batch_queue_with_priority(batch, 23, NOISE_BATCH_ENCRYPT, 3, b"x".as_ptr(), 1), NOISE_ERROR_INVALID_PARAMETER);
+    let results = std::mem::take(&mut *log.lock().unwrap());
+    assert_eq!(results.iter().map(|r| (r.0, r.2)).collect::<Vec<_>>(), [(20, NOISE_ERROR_SUCCESS)]);
+    assert_eq!(noise_batch_flush(batch), NOISE_ERROR_SUCCESS);
+    let results = std::mem::take(&mut *log.lock().unwrap());
+    assert_eq!(results.iter().map(|r| r.0).collect::<Vec<_>>(), [21]);
+    
+    // A background flusher delivers without any further calls
+    assert_eq!(noise_batch_set_flush_callback(batch, Some(on_batch_result), context, 1), NOISE_ERROR_SUCCESS);
+    assert_eq!(noise_batch_queue_decrypt(batch, 12, [0u8; 32].as_ptr(), 32), NOISE_ERROR_SUCCESS);
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    while log.lock().unwrap().is_empty() && std::time::Instant::now() < deadline {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    let results = log.lock().unwrap().clone();
+    assert_eq!(results.len(), 1);
+    assert_eq!((results[0].0, results[0].1), (12, NOISE_BATCH_DECRYPT));
+    assert_ne!(results[0].2, NOISE_ERROR_SUCCESS);
+    assert!(results[0].3.is_empty());
+    
+    noise_batch_free(batch);
+    assert_eq!(noise_batch_queue_encrypt(batch, 13, b"late".as_ptr(), 4), NOISE_ERROR_INVALID_PARAMETER);
+    noise_session_free(bob);
+}
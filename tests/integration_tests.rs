@@ -5,6 +5,7 @@
 
 use noise_mobile::ffi::c_api::*;
 use noise_mobile::mobile::network::ResilientSession;
+#[cfg(feature = "batching")]
 use noise_mobile::mobile::battery::BatchedCrypto;
 use noise_mobile::core::session::NoiseSession;
 use noise_mobile::core::error::NoiseError;
@@ -329,6 +330,7 @@ fn test_resilient_session_out_of_order() {
     }
 }
 
+#[cfg(feature = "batching")]
 #[test]
 fn test_batched_crypto_integration() {
     // Create and complete handshake
@@ -360,7 +362,7 @@ fn test_batched_crypto_integration() {
     assert_eq!(batched_initiator.pending_encrypts_count(), 4);
     
     // Manually flush to get encrypted messages
-    let encrypted = batched_initiator.flush_encrypts().unwrap();
+    let encrypted = batched_initiator.flush_encrypts().into_iter().collect::<Result<Vec<_>, _>>().unwrap();
     assert_eq!(encrypted.len(), 4);
     
     // Queue one more and check threshold behavior
@@ -368,7 +370,7 @@ fn test_batched_crypto_integration() {
     assert_eq!(batched_initiator.pending_encrypts_count(), 1);
     
     // Flush remaining
-    let encrypted2 = batched_initiator.flush_encrypts().unwrap();
+    let encrypted2 = batched_initiator.flush_encrypts().into_iter().collect::<Result<Vec<_>, _>>().unwrap();
     assert_eq!(encrypted2.len(), 1);
     
     // Decrypt all messages